@@ -0,0 +1,122 @@
+use std::io::{Cursor, Seek, SeekFrom::Start};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use dex_tool::dex_file::{DexFile, Mode, ParseOptions};
+use dex_tool::raw_dex::{self, ClassData, CodeItem, DexHeader};
+
+/*
+Benchmarks of the parsing stages on the bundled fixtures: sample.dex is the small hand-written file of
+the other fixtures, many_classes.dex has 1000 generated classes with 7 methods each. Each stage reads
+from an in-memory Cursor, so only the parser is measured.
+ */
+
+const FIXTURES: &[(&str, &[u8])] = &[
+    ("sample", include_bytes!("fixtures/sample.dex")),
+    ("many_classes", include_bytes!("fixtures/many_classes.dex")),
+];
+
+fn header_and_ids(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header_and_ids");
+    for (name, data) in FIXTURES {
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| {
+            let reader = &mut Cursor::new(data);
+            let header = DexHeader::from_reader(reader).unwrap();
+            (
+                raw_dex::parse_string_ids(&header, reader).unwrap(),
+                raw_dex::parse_type_ids(&header, reader).unwrap(),
+                raw_dex::parse_proto_ids(&header, reader).unwrap(),
+                raw_dex::parse_field_ids(&header, reader).unwrap(),
+                raw_dex::parse_method_ids(&header, reader).unwrap(),
+                raw_dex::parse_class_defs(&header, reader).unwrap(),
+            )
+        }));
+    }
+    group.finish();
+}
+
+fn strings(c: &mut Criterion) {
+    let mut group = c.benchmark_group("string_data");
+    for (name, data) in FIXTURES {
+        let reader = &mut Cursor::new(data);
+        let header = DexHeader::from_reader(reader).unwrap();
+        let string_ids = raw_dex::parse_string_ids(&header, reader).unwrap();
+        group.throughput(Throughput::Elements(string_ids.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| raw_dex::parse_string_data_items(&string_ids, reader).unwrap()));
+    }
+    group.finish();
+}
+
+fn class_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("class_data");
+    for (name, data) in FIXTURES {
+        let reader = &mut Cursor::new(data);
+        let header = DexHeader::from_reader(reader).unwrap();
+        let offsets: Vec<u32> = raw_dex::parse_class_defs(&header, reader).unwrap().iter()
+            .map(|it| it.class_data_off)
+            .filter(|it| *it != 0)
+            .collect();
+        group.throughput(Throughput::Elements(offsets.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| {
+            offsets.iter().map(|off| {
+                reader.seek(Start((*off).into())).unwrap();
+                ClassData::from_reader(reader).unwrap()
+            }).collect::<Vec<_>>()
+        }));
+    }
+    group.finish();
+}
+
+fn code_items(c: &mut Criterion) {
+    let mut group = c.benchmark_group("code_items");
+    for (name, data) in FIXTURES {
+        let dex = DexFile::from_bytes(data).unwrap();
+        let offsets: Vec<u64> = dex.class_data.iter().flatten()
+            .flat_map(|it| it.direct_methods.iter().chain(&it.virtual_methods))
+            .map(|it| it.code_off)
+            .filter(|it| *it != 0)
+            .collect();
+        let reader = &mut Cursor::new(data);
+        group.throughput(Throughput::Elements(offsets.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| {
+            offsets.iter().map(|off| {
+                reader.seek(Start(*off)).unwrap();
+                CodeItem::from_reader(reader).unwrap()
+            }).collect::<Vec<_>>()
+        }));
+    }
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("end_to_end");
+    for (name, data) in FIXTURES {
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(format!("{}/full", name), |b| b.iter(|| DexFile::from_bytes(data).unwrap()));
+        group.bench_function(format!("{}/no_code", name), |b| b.iter(|| ParseOptions::new().code(false).parse_bytes(data).unwrap()));
+        group.bench_function(format!("{}/lazy", name), |b| b.iter(|| ParseOptions::new().mode(Mode::Lazy).parse_bytes(data).unwrap()));
+        group.bench_function(format!("{}/parallel", name), |b| b.iter(|| ParseOptions::new().mode(Mode::Parallel).parse_bytes(data).unwrap()));
+        group.bench_function(format!("{}/compact", name), |b| b.iter(|| ParseOptions::new().parse_compact(&mut Cursor::new(data)).unwrap()));
+        // Reparsing an unchanged file reuses all sections
+        group.bench_function(format!("{}/reparse", name), |b| b.iter_batched(
+            || DexFile::from_bytes(data).unwrap(),
+            |dex| ParseOptions::new().reparse(dex, data, data).unwrap(),
+            BatchSize::SmallInput,
+        ));
+    }
+    group.finish();
+}
+
+// The references of all methods, behind the xref listings and exports
+fn references(c: &mut Criterion) {
+    let mut group = c.benchmark_group("references");
+    for (name, data) in FIXTURES {
+        let dex = DexFile::from_bytes(data).unwrap();
+        group.bench_function(*name, |b| b.iter(|| dex_tool::export::references(&dex)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, header_and_ids, strings, class_data, code_items, end_to_end, references);
+criterion_main!(benches);
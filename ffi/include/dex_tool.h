@@ -0,0 +1,107 @@
+#ifndef DEX_TOOL_H
+#define DEX_TOOL_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A parsed dex file, opaque to C
+typedef struct DexHandle DexHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failure on the calling thread, NULL if there was none.
+// Valid until the next failing call on the same thread.
+const char *dex_last_error(void);
+
+// Parses the dex file at `path`. Returns NULL if the file could not be read or parsed.
+//
+// # Safety
+// `path` must be a NUL-terminated string.
+struct DexHandle *dex_open(const char *path);
+
+// Parses a dex file from memory, the data is not referenced after the call. Returns NULL if the data
+// could not be parsed.
+//
+// # Safety
+// `data` must point to `len` readable bytes.
+struct DexHandle *dex_open_bytes(const uint8_t *data,
+                                 size_t len);
+
+// Releases a dex file returned by dex_open or dex_open_bytes, NULL is ignored.
+//
+// # Safety
+// `handle` must be returned by dex_open or dex_open_bytes and not be used afterwards.
+void dex_close(struct DexHandle *handle);
+
+// Releases a string returned by this library, NULL is ignored.
+//
+// # Safety
+// `value` must be returned by this library and not be used afterwards.
+void dex_string_free(char *value);
+
+// Dex format version, 0 for NULL
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+uint16_t dex_version(const struct DexHandle *dex);
+
+// Number of string ids
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+uint32_t dex_string_count(const struct DexHandle *dex);
+
+// Value of a string id, NULL if `idx` is out of range
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+char *dex_string(const struct DexHandle *dex, uint32_t idx);
+
+// Number of classes defined in the dex file
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+uint32_t dex_class_count(const struct DexHandle *dex);
+
+// Descriptor of a class definition (e.g. "Lcom/example/Foo;"), NULL if `idx` is out of range
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+char *dex_class_descriptor(const struct DexHandle *dex, uint32_t idx);
+
+// Descriptor of the superclass of a class definition, NULL if it has none or `idx` is out of range
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+char *dex_class_superclass(const struct DexHandle *dex, uint32_t idx);
+
+// Access flags of a class definition, 0 if `idx` is out of range
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+uint32_t dex_class_access_flags(const struct DexHandle *dex, uint32_t idx);
+
+// Number of method ids, including methods defined in other dex files
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+uint32_t dex_method_count(const struct DexHandle *dex);
+
+// Method id in the form "Lcom/example/Foo;->name(I)V", NULL if `idx` is out of range
+//
+// # Safety
+// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+char *dex_method(const struct DexHandle *dex, uint32_t idx);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DEX_TOOL_H */
@@ -0,0 +1,216 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic;
+use std::ptr;
+
+use dex_tool::dex_file::{DexFile, NO_INDEX};
+
+/*
+C API of the parser. The header include/dex_tool.h is generated from this file by the build script.
+
+Strings returned by the query functions are owned by the caller and must be released with
+dex_string_free. Functions returning a pointer return NULL on failure, dex_last_error then describes
+the failure.
+ */
+
+/// A parsed dex file, opaque to C
+pub struct DexHandle {
+    dex: DexFile,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|it| *it.borrow_mut() = Some(message));
+}
+
+/// Copies a string to the C heap, a string containing NUL can not be represented
+fn to_c_string(value: &str) -> *mut c_char {
+    match CString::new(value) {
+        Ok(value) => value.into_raw(),
+        Err(_) => {
+            set_last_error(format!("String {:?} contains NUL", value));
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn handle<'a>(handle: *const DexHandle) -> Option<&'a DexFile> {
+    handle.as_ref().map(|it| &it.dex)
+}
+
+/// Message of the last failure on the calling thread, NULL if there was none.
+/// Valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn dex_last_error() -> *const c_char {
+    LAST_ERROR.with(|it| it.borrow().as_ref().map_or(ptr::null(), |it| it.as_ptr()))
+}
+
+/// Parses the dex file at `path`. Returns NULL if the file could not be read or parsed.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn dex_open(path: *const c_char) -> *mut DexHandle {
+    if path.is_null() {
+        set_last_error("path is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let result = panic::catch_unwind(|| DexFile::open(&path));
+    match result {
+        Ok(Ok(dex)) => Box::into_raw(Box::new(DexHandle { dex })),
+        Ok(Err(err)) => {
+            set_last_error(format!("Could not read {}: {}", path, err));
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_last_error(format!("Could not parse {}", path));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Parses a dex file from memory, the data is not referenced after the call. Returns NULL if the data
+/// could not be parsed.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_open_bytes(data: *const u8, len: usize) -> *mut DexHandle {
+    if data.is_null() {
+        set_last_error("data is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    match panic::catch_unwind(|| DexFile::from_bytes(data)) {
+        Ok(Ok(dex)) => Box::into_raw(Box::new(DexHandle { dex })),
+        Ok(Err(err)) => {
+            set_last_error(format!("Could not read dex file: {}", err));
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_last_error("Could not parse dex file".to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Releases a dex file returned by dex_open or dex_open_bytes, NULL is ignored.
+///
+/// # Safety
+/// `handle` must be returned by dex_open or dex_open_bytes and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn dex_close(handle: *mut DexHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Releases a string returned by this library, NULL is ignored.
+///
+/// # Safety
+/// `value` must be returned by this library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn dex_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Dex format version, 0 for NULL
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_version(dex: *const DexHandle) -> u16 {
+    handle(dex).map_or(0, |it| it.version())
+}
+
+/// Number of string ids
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_string_count(dex: *const DexHandle) -> u32 {
+    handle(dex).map_or(0, |it| it.strings.len() as u32)
+}
+
+/// Value of a string id, NULL if `idx` is out of range
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_string(dex: *const DexHandle, idx: u32) -> *mut c_char {
+    match handle(dex).and_then(|it| it.strings.get(idx as usize)) {
+        Some(value) => to_c_string(value),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Number of classes defined in the dex file
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_count(dex: *const DexHandle) -> u32 {
+    handle(dex).map_or(0, |it| it.class_defs.len() as u32)
+}
+
+/// Descriptor of a class definition (e.g. "Lcom/example/Foo;"), NULL if `idx` is out of range
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_descriptor(dex: *const DexHandle, idx: u32) -> *mut c_char {
+    match handle(dex).and_then(|dex| Some((dex, dex.class_defs.get(idx as usize)?))) {
+        Some((dex, class_def)) => to_c_string(dex.type_descriptor(class_def.class_idx)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Descriptor of the superclass of a class definition, NULL if it has none or `idx` is out of range
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_superclass(dex: *const DexHandle, idx: u32) -> *mut c_char {
+    match handle(dex).and_then(|dex| Some((dex, dex.class_defs.get(idx as usize)?))) {
+        Some((dex, class_def)) if class_def.superclass_idx != NO_INDEX => to_c_string(dex.type_descriptor(class_def.superclass_idx)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Access flags of a class definition, 0 if `idx` is out of range
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_access_flags(dex: *const DexHandle, idx: u32) -> u32 {
+    handle(dex).and_then(|it| it.class_defs.get(idx as usize)).map_or(0, |it| it.access_flags)
+}
+
+/// Number of method ids, including methods defined in other dex files
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_method_count(dex: *const DexHandle) -> u32 {
+    handle(dex).map_or(0, |it| it.method_ids.len() as u32)
+}
+
+/// Method id in the form "Lcom/example/Foo;->name(I)V", NULL if `idx` is out of range
+///
+/// # Safety
+/// `dex` must be NULL or returned by dex_open or dex_open_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_method(dex: *const DexHandle, idx: u32) -> *mut c_char {
+    match handle(dex).filter(|it| (idx as usize) < it.method_ids.len()) {
+        Some(dex) => to_c_string(&format!("{}->{}{}", dex.method_class(idx), dex.method_name(idx), dex.method_signature(idx))),
+        None => ptr::null_mut(),
+    }
+}
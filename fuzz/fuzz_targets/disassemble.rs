@@ -0,0 +1,24 @@
+#![no_main]
+
+use std::io::sink;
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::smali::Comments;
+use dex_tool::{decompiler, dexdump, smali, verifier};
+
+// The listings of the commands reading code (dump, disasm, decompile, verify), which must not panic
+// for any file that parsed
+fuzz_target!(|data: &[u8]| {
+    let dex = match DexFile::from_bytes(data) {
+        Ok(dex) => dex,
+        Err(_) => return,
+    };
+    let _ = dexdump::dump(&dex, "fuzz.dex", &mut sink());
+    for idx in 0..dex.class_defs.len() {
+        let _ = smali::write_class(&dex, idx, &Comments::new(), &mut sink());
+        let _ = decompiler::write_class(&dex, idx, &mut sink());
+    }
+    let _ = verifier::verify(&dex);
+});
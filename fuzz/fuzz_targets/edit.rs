@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::editor::{Dangling, DexEditor};
+
+// Edits of a parsed file either fail with an EditError or write a file that parses again
+fuzz_target!(|data: &[u8]| {
+    let editor = match DexEditor::from_bytes(data) {
+        Ok(editor) => editor,
+        Err(_) => return,
+    };
+    let check = |editor: &DexEditor| {
+        if let Ok(written) = editor.to_bytes() {
+            DexFile::from_bytes(&written).expect("Written dex file does not parse");
+        }
+    };
+    check(&editor);
+    let mut compacted = editor.clone();
+    if compacted.compact().is_ok() {
+        check(&compacted);
+    }
+    let first = editor.classes.first().map(|it| editor.type_descriptor(it.class_idx).to_owned());
+    for dangling in [Dangling::Fail, Dangling::Stub, Dangling::Cascade] {
+        let mut stripped = editor.clone();
+        if stripped.strip(&first.iter().map(String::as_str).collect::<Vec<_>>(), dangling).is_ok() {
+            check(&stripped);
+        }
+    }
+});
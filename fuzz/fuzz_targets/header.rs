@@ -0,0 +1,25 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::raw_dex::{self, DexHeader, MapItem};
+
+// The header, the map list and the id tables, without the items of the data section
+fuzz_target!(|data: &[u8]| {
+    let reader = &mut Cursor::new(data);
+    let header = match DexHeader::from_reader(reader) {
+        Ok(header) => header,
+        Err(_) => return,
+    };
+    // A header that parsed has valid magic bytes
+    assert!(DexHeader::verify_magic(&header.magic).is_ok());
+    let _ = MapItem::parse_map_list(&header, reader);
+    let _ = raw_dex::parse_string_ids(&header, reader).and_then(|it| raw_dex::parse_string_data_items(&it, reader));
+    let _ = raw_dex::parse_type_ids(&header, reader);
+    let _ = raw_dex::parse_proto_ids(&header, reader);
+    let _ = raw_dex::parse_field_ids(&header, reader);
+    let _ = raw_dex::parse_method_ids(&header, reader);
+    let _ = raw_dex::parse_class_defs(&header, reader);
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::instructions::{Instruction, Instructions, Payload};
+
+// An instruction array of little endian code units, an odd last byte is dropped
+fuzz_target!(|data: &[u8]| {
+    let insns: Vec<u16> = data.chunks_exact(2).map(|it| u16::from_le_bytes([it[0], it[1]])).collect();
+    for result in Instructions::new(&insns) {
+        let (pc, insn) = match result {
+            Ok(it) => it,
+            Err(_) => break,
+        };
+        let _ = (insn.name(), insn.format(), insn.index(), insn.registers());
+        let _ = (insn.defined_registers(), insn.used_registers());
+        // Encoding is the inverse of decoding, up to the unused bits of format 10x
+        if let Ok(units) = insn.encode() {
+            let decoded = Instruction::decode(&units, 0).expect("Encoded instruction does not decode");
+            assert_eq!(decoded.encode().ok(), Some(units));
+        }
+        if let Ok(payload) = Payload::decode(&insns, pc) {
+            if let Ok(units) = payload.encode() {
+                let decoded = Payload::decode(&units, 0).expect("Encoded payload does not decode");
+                assert_eq!(decoded.encode().ok(), Some(units));
+            }
+            let _ = payload.switch_cases();
+            if let Some(array) = payload.array_data() {
+                let _ = (array.values(), array.to_string());
+            }
+        }
+    }
+});
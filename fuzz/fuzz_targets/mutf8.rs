@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::m_utf8;
+
+// The string data of a string_data_item with its declared utf16_size
+fuzz_target!(|input: (u64, &[u8])| {
+    let (utf16_size, data) = input;
+    let lossy = m_utf8::to_string_lossy(data, utf16_size);
+    match m_utf8::to_string(data, utf16_size) {
+        Ok(string) => {
+            assert!(lossy.errors.is_empty());
+            assert_eq!(string, lossy.string.value);
+            assert!(m_utf8::eq_str(data, &string));
+            assert_eq!(m_utf8::cmp_str(data, &string), std::cmp::Ordering::Equal);
+        }
+        Err(_) => assert!(!lossy.errors.is_empty()),
+    }
+});
@@ -0,0 +1,51 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use dex_tool::dex_file::{DexFile, ParseOptions, Strictness};
+use dex_tool::instructions::Instructions;
+
+// Resolves everything the model of a parsed file references, which must not panic for any file that
+// parsed
+fn walk(dex: &DexFile) {
+    let _ = dex.version();
+    for class in dex.classes() {
+        let _ = (class.descriptor(), class.annotations());
+        for field in class.fields() {
+            let _ = (field.name(), field.static_value(), field.annotations());
+        }
+        for method in class.methods() {
+            let idx = method.method_idx;
+            let _ = (dex.method_class(idx), dex.method_name(idx), dex.method_signature(idx));
+            let _ = (method.annotations(), method.parameter_annotations());
+            if let Some(code) = dex.code_item(method.encoded.code_off) {
+                Instructions::new(&code.insns).take_while(|it| it.is_ok()).for_each(drop);
+                let _ = dex.catches(code);
+                let _ = dex.locals(idx, method.encoded.access_flags, code);
+                if let Some(debug_info) = dex.debug_info.get(&code.debug_info_off) {
+                    let _ = dex.positions(debug_info);
+                }
+            }
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    for strictness in [Strictness::Lenient, Strictness::Normal, Strictness::Strict] {
+        if let Ok(dex) = ParseOptions::new().strictness(strictness).parse_bytes(data) {
+            walk(&dex);
+        }
+    }
+    if let Ok(dex) = ParseOptions::new().parse_compact(&mut Cursor::new(data)) {
+        for class_def_idx in 0..dex.class_defs.len() {
+            for field in dex.fields(class_def_idx) {
+                let _ = (dex.field_class(field.field_idx), dex.field_name(field.field_idx), dex.field_type(field.field_idx));
+            }
+            for method in dex.methods(class_def_idx) {
+                let _ = (dex.method_class(method.method_idx), dex.method_name(method.method_idx), dex.method_signature(method.method_idx));
+            }
+        }
+    }
+});
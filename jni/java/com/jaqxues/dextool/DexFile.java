@@ -0,0 +1,82 @@
+package com.jaqxues.dextool;
+
+import java.io.IOException;
+
+/**
+ * A dex file parsed by the native dex_tool library (libdex_tool_jni).
+ * The native memory is released by {@link #close()}.
+ */
+public final class DexFile implements AutoCloseable {
+    static {
+        System.loadLibrary("dex_tool_jni");
+    }
+
+    private long handle;
+
+    private DexFile(long handle) {
+        this.handle = handle;
+    }
+
+    public static DexFile open(String path) throws IOException {
+        return new DexFile(nativeOpen(path));
+    }
+
+    public static DexFile fromBytes(byte[] data) throws IOException {
+        return new DexFile(nativeFromBytes(data));
+    }
+
+    /** Dex format version */
+    public int version() {
+        return nativeVersion(handle());
+    }
+
+    public String[] strings() {
+        return nativeStrings(handle());
+    }
+
+    /** Descriptors of the classes defined in the dex file */
+    public String[] classes() {
+        return nativeClasses(handle());
+    }
+
+    /** All method ids in the form Lcom/example/Foo;->name(I)V */
+    public String[] methods() {
+        return nativeMethods(handle());
+    }
+
+    /** Smali of the class with the given descriptor */
+    public String disassemble(String descriptor) {
+        return nativeDisassemble(handle(), descriptor);
+    }
+
+    @Override
+    public synchronized void close() {
+        if (handle != 0) {
+            nativeClose(handle);
+            handle = 0;
+        }
+    }
+
+    private synchronized long handle() {
+        if (handle == 0) {
+            throw new IllegalStateException("DexFile is closed");
+        }
+        return handle;
+    }
+
+    private static native long nativeOpen(String path) throws IOException;
+
+    private static native long nativeFromBytes(byte[] data) throws IOException;
+
+    private static native void nativeClose(long handle);
+
+    private static native int nativeVersion(long handle);
+
+    private static native String[] nativeStrings(long handle);
+
+    private static native String[] nativeClasses(long handle);
+
+    private static native String[] nativeMethods(long handle);
+
+    private static native String nativeDisassemble(long handle, String descriptor);
+}
@@ -0,0 +1,111 @@
+use std::panic;
+
+use jni::objects::{JByteArray, JClass, JObject, JObjectArray, JString};
+use jni::sys::{jint, jlong, jobjectArray, jstring};
+use jni::JNIEnv;
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::smali;
+
+/*
+JNI implementation of the native methods of com.jaqxues.dextool.DexFile (see java/). A parsed dex file
+is passed to Java as a pointer in a long and released by nativeClose.
+
+Failures are thrown as Java exceptions, the returned value is ignored by the JVM in that case.
+ */
+
+unsafe fn dex<'a>(handle: jlong) -> &'a DexFile {
+    &*(handle as *const DexFile)
+}
+
+/// Boxes the parsed dex file, failures and panics of the parser (which must not unwind into the JVM) are thrown as IOException
+fn parse(env: &mut JNIEnv, parse: impl FnOnce() -> std::io::Result<DexFile> + panic::UnwindSafe) -> jlong {
+    let message = match panic::catch_unwind(parse) {
+        Ok(Ok(dex)) => return Box::into_raw(Box::new(dex)) as jlong,
+        Ok(Err(err)) => format!("Could not parse dex file: {}", err),
+        Err(_) => "Could not parse dex file".to_string(),
+    };
+    let _ = env.throw_new("java/io/IOException", message);
+    0
+}
+
+fn string_array(env: &mut JNIEnv, values: impl ExactSizeIterator<Item = String>) -> jobjectArray {
+    let array = (|| -> jni::errors::Result<JObjectArray> {
+        let array = env.new_object_array(values.len() as jint, "java/lang/String", JObject::null())?;
+        for (i, value) in values.enumerate() {
+            let value = env.new_string(value)?;
+            env.set_object_array_element(&array, i as jint, &value)?;
+            env.delete_local_ref(value)?;
+        }
+        Ok(array)
+    })();
+    // On failure an exception (e.g. OutOfMemoryError) is pending already
+    array.map_or(std::ptr::null_mut(), |it| it.into_raw())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeOpen(mut env: JNIEnv, _class: JClass, path: JString) -> jlong {
+    let path: String = match env.get_string(&path) {
+        Ok(path) => path.into(),
+        Err(_) => return 0,
+    };
+    parse(&mut env, || DexFile::open(path))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeFromBytes(mut env: JNIEnv, _class: JClass, data: JByteArray) -> jlong {
+    let data = match env.convert_byte_array(&data) {
+        Ok(data) => data,
+        Err(_) => return 0,
+    };
+    parse(&mut env, || DexFile::from_bytes(&data))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeClose(_env: JNIEnv, _class: JClass, handle: jlong) {
+    drop(unsafe { Box::from_raw(handle as *mut DexFile) });
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeVersion(_env: JNIEnv, _class: JClass, handle: jlong) -> jint {
+    unsafe { dex(handle) }.version() as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeStrings(mut env: JNIEnv, _class: JClass, handle: jlong) -> jobjectArray {
+    let dex = unsafe { dex(handle) };
+    string_array(&mut env, dex.strings.iter().cloned())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeClasses(mut env: JNIEnv, _class: JClass, handle: jlong) -> jobjectArray {
+    let dex = unsafe { dex(handle) };
+    string_array(&mut env, dex.class_defs.iter().map(|it| dex.type_descriptor(it.class_idx).to_string()))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeMethods(mut env: JNIEnv, _class: JClass, handle: jlong) -> jobjectArray {
+    let dex = unsafe { dex(handle) };
+    let methods = (0..dex.method_ids.len() as u32)
+        .map(|it| format!("{}->{}{}", dex.method_class(it), dex.method_name(it), dex.method_signature(it)));
+    string_array(&mut env, methods)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_jaqxues_dextool_DexFile_nativeDisassemble(mut env: JNIEnv, _class: JClass, handle: jlong, descriptor: JString) -> jstring {
+    let dex = unsafe { dex(handle) };
+    let descriptor: String = match env.get_string(&descriptor) {
+        Ok(descriptor) => descriptor.into(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let idx = match dex.class_defs.iter().position(|it| dex.type_descriptor(it.class_idx) == descriptor) {
+        Some(idx) => idx,
+        None => {
+            let _ = env.throw_new("java/lang/IllegalArgumentException", format!("Class {} is not defined", descriptor));
+            return std::ptr::null_mut();
+        }
+    };
+    let mut out = Vec::new();
+    smali::write_class(dex, idx, &smali::Comments::new(), &mut out).expect("Writing to a Vec does not fail");
+    env.new_string(String::from_utf8_lossy(&out)).map_or(std::ptr::null_mut(), |it| it.into_raw())
+}
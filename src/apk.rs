@@ -0,0 +1,158 @@
+use std::io::{Read, Seek};
+
+use zip::result::ZipResult;
+use zip::ZipArchive;
+
+/*
+Dex files of APKs and other zip archives. Only the dex files at the root of the archive are loaded by
+the runtime, in multidex order: classes.dex, classes2.dex, classes3.dex, ...
+Apps split into several APKs are distributed as bundles, zip archives of the APKs: .apks of bundletool
+(splits/base-master.apk, splits/base-arm64_v8a.apk, ...) and .xapk (com.example.apk,
+config.arm64_v8a.apk, ...). The runtime loads the base APK first, then the feature splits, config
+splits (resources and native libraries) rarely contain code.
+Android App Bundles (.aab) are the format apps are uploaded in, the split APKs are generated from them.
+They contain one directory per module, base and the dynamic features, with the dex files under dex/:
+base/dex/classes.dex, base/dex/classes2.dex, feature/dex/classes.dex, ...
+ */
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Start of the magic of a dex file, followed by the version
+const DEX_MAGIC: &[u8] = b"dex\n";
+
+/// Whether `data` starts with the magic of a zip archive
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(ZIP_MAGIC)
+}
+
+/// Position of an entry in multidex order, None if it is not a dex file loaded by the runtime
+fn multidex_index(name: &str) -> Option<u32> {
+    let number = name.strip_prefix("classes")?.strip_suffix(".dex")?;
+    match number {
+        "" => Some(1),
+        _ if number.starts_with('0') => None,
+        _ => number.parse().ok().filter(|it| *it >= 2),
+    }
+}
+
+/// Loading order of an APK of a split app by its name: the base APK, feature splits, then config splits
+fn split_rank(name: &str) -> u8 {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let stem = file_name.strip_suffix(".apk").unwrap_or(file_name);
+    if stem == "base" || stem == "base-master" {
+        0
+    } else if stem.starts_with("config.") || stem.starts_with("split_config.") || (stem.contains('-') && !stem.ends_with("-master")) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Sorts the APKs of a split app by name into loading order, see split_rank
+pub fn sort_splits<T>(apks: &mut [(String, T)]) {
+    apks.sort_by(|(a, _), (b, _)| (split_rank(a), a).cmp(&(split_rank(b), b)));
+}
+
+/// Names and contents of the dex files of the archive, in multidex order
+pub fn dex_entries<R: Read + Seek>(reader: R) -> ZipResult<Vec<(String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut names: Vec<_> = archive.file_names()
+        .filter_map(|name| Some((multidex_index(name)?, name.to_string())))
+        .collect();
+    names.sort();
+
+    let mut entries = Vec::with_capacity(names.len());
+    for (_, name) in names {
+        let mut file = archive.by_name(&name)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        entries.push((name, data));
+    }
+    Ok(entries)
+}
+
+/// Modules, entry names and contents of the dex files of an Android App Bundle, the base module first,
+/// then the dynamic features by name, each in multidex order. Empty if the archive is not a bundle.
+pub fn module_dex_entries<R: Read + Seek>(reader: R) -> ZipResult<Vec<(String, String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut names: Vec<_> = archive.file_names()
+        .filter_map(|name| {
+            let (module, dex) = name.split_once("/dex/")?;
+            if module.is_empty() || module.contains('/') {
+                return None;
+            }
+            Some((module != "base", module.to_string(), multidex_index(dex)?, name.to_string()))
+        })
+        .collect();
+    names.sort();
+
+    let mut entries = Vec::with_capacity(names.len());
+    for (_, module, _, name) in names {
+        let mut file = archive.by_name(&name)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        entries.push((module, name, data));
+    }
+    Ok(entries)
+}
+
+/// Names and contents of the class files of the archive (e.g. of android.jar), in the order of the
+/// archive. module-info.class and the class files of other Java versions under `META-INF/versions/`
+/// are skipped.
+pub fn class_entries<R: Read + Seek>(reader: R) -> ZipResult<Vec<(String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut entries = Vec::new();
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let name = file.name().to_string();
+        if !name.ends_with(".class") || name.ends_with("module-info.class") || name.starts_with("META-INF/") || !file.is_file() {
+            continue;
+        }
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        entries.push((name, data));
+    }
+    Ok(entries)
+}
+
+/// Names and contents of the entries of the archive that are dex files or zip archives by their magic,
+/// wherever they are in the archive (e.g. under `assets/`), in the order of the archive
+pub fn payload_entries<R: Read + Seek>(reader: R) -> ZipResult<Vec<(String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut entries = Vec::new();
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        if !file.is_file() {
+            continue;
+        }
+        let name = file.name().to_string();
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        if data.starts_with(DEX_MAGIC) || is_zip(&data) {
+            entries.push((name, data));
+        }
+    }
+    Ok(entries)
+}
+
+/// Names and contents of the APKs of a bundle (.apks, .xapk), in loading order. The standalone APKs of
+/// an .apks archive for devices without split support are skipped if it has splits. Empty if the
+/// archive is not a bundle, e.g. an APK.
+pub fn split_entries<R: Read + Seek>(reader: R) -> ZipResult<Vec<(String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let names: Vec<String> = archive.file_names().map(String::from).collect();
+    if names.iter().any(|it| multidex_index(it).is_some()) {
+        return Ok(Vec::new());
+    }
+    let has_splits = names.iter().any(|it| it.starts_with("splits/"));
+    let mut entries = Vec::new();
+    for name in names.into_iter().filter(|it| it.ends_with(".apk") && !(has_splits && it.starts_with("standalones/"))) {
+        let mut file = archive.by_name(&name)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        if is_zip(&data) {
+            entries.push((name, data));
+        }
+    }
+    sort_splits(&mut entries);
+    Ok(entries)
+}
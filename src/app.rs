@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use crate::apk;
+use crate::input;
+use crate::string_pool::StringPool;
+
+/*
+The dex files of an app in class loading order, whether it comes as dex file, APK, bundle of split APKs
+(.apks, .xapk), directory of split APKs (e.g. pulled from /data/app/<package>/) or Android App Bundle
+(.aab). Together they form one class path (see ClassPath): the dex files of the base APK or module in
+multidex order come first, then those of the splits or dynamic features, see apk::sort_splits.
+ */
+
+/// A dex file of an app
+pub struct AppDex {
+    /// Name of the APK in the bundle or directory, None for a single APK or dex file
+    pub apk: Option<String>,
+    /// Module of an Android App Bundle, e.g. base
+    pub module: Option<String>,
+    /// Entry name in the APK (classes.dex, classes2.dex, ...) or App Bundle (base/dex/classes.dex, ...),
+    /// or file name of a dex file
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl AppDex {
+    /// Name of the dex file including the APK, e.g. `splits/base-master.apk!classes2.dex`
+    pub fn full_name(&self) -> String {
+        match &self.apk {
+            Some(apk) => format!("{}!{}", apk, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn apk_dex_files(apk: Option<String>, data: &[u8]) -> io::Result<Vec<AppDex>> {
+    Ok(apk::dex_entries(Cursor::new(data))?.into_iter()
+        .map(|(name, data)| AppDex { apk: apk.clone(), module: None, name, data })
+        .collect())
+}
+
+/// Dex files of the app in the file `data` named `name`: a dex file, an APK, a bundle of split APKs or
+/// an Android App Bundle
+pub fn dex_files_of(name: &str, data: &[u8]) -> io::Result<Vec<AppDex>> {
+    if !apk::is_zip(data) {
+        return Ok(vec![AppDex { apk: None, module: None, name: name.to_string(), data: data.to_vec() }]);
+    }
+    let modules = apk::module_dex_entries(Cursor::new(data))?;
+    if !modules.is_empty() {
+        return Ok(modules.into_iter()
+            .map(|(module, name, data)| AppDex { apk: None, module: Some(module), name, data })
+            .collect());
+    }
+    let splits = apk::split_entries(Cursor::new(data))?;
+    if splits.is_empty() {
+        return apk_dex_files(None, data);
+    }
+    let mut dex_files = Vec::new();
+    for (apk, data) in splits {
+        dex_files.extend(apk_dex_files(Some(apk), &data)?);
+    }
+    Ok(dex_files)
+}
+
+/// APKs directly in the directory `dir`, by file name
+fn split_apks(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut apks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|it| it == "apk") {
+            apks.push((path.file_name().unwrap_or_default().to_string_lossy().into_owned(), path));
+        }
+    }
+    Ok(apks)
+}
+
+/// Whether `path` is a directory of split APKs
+pub fn is_split_dir(path: &Path) -> bool {
+    path.is_dir() && split_apks(path).is_ok_and(|it| !it.is_empty())
+}
+
+/// Dex files of the app at `path`, a directory of split APKs or a file, see dex_files_of
+pub fn dex_files(path: &Path) -> io::Result<Vec<AppDex>> {
+    if !path.is_dir() {
+        let name = path.file_name().map_or_else(String::new, |it| it.to_string_lossy().into_owned());
+        return dex_files_of(&name, &input::read_file(path)?);
+    }
+    let mut apks = split_apks(path)?;
+    if apks.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No APKs in {}", path.display())));
+    }
+    apk::sort_splits(&mut apks);
+    let mut dex_files = Vec::new();
+    for (apk, path) in apks {
+        dex_files.extend(apk_dex_files(Some(apk), &input::read_file(&path)?)?);
+    }
+    Ok(dex_files)
+}
+
+/// The first of the dex files defining the class with the descriptor and the index of its class_def, as the
+/// class loader would find it. Only the id tables of the dex files are read, the string pools are searched
+/// without decoding them.
+pub fn find_class<'a>(dex_files: &'a [AppDex], descriptor: &str) -> io::Result<Option<(&'a AppDex, u32)>> {
+    for dex in dex_files {
+        if let Some(class_def_idx) = StringPool::new(&dex.data)?.class_def_idx(descriptor)? {
+            return Ok(Some((dex, class_def_idx)));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,149 @@
+use std::io::{Cursor, Error, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::checked;
+use crate::dex_file::DexFile;
+use crate::raw_dex::{read_u32, DexHeader, MapItem};
+
+/*
+Async front-end for parsing while the dex file is still being received (e.g. downloaded). The header
+and the map list are parsed as soon as their bytes arrived, the sections are parsed once the whole
+file is buffered, on a blocking thread of the tokio runtime.
+ */
+
+const HEADER_SIZE: usize = 0x70;
+/// Upper bound of the buffer reserved upfront, the declared file size is not trusted before it arrived
+const MAX_RESERVE: usize = 1 << 24;
+
+/// A dex file of which a prefix has been received
+pub struct PartialDex {
+    data: Vec<u8>,
+    header: DexHeader,
+    map_list: Option<Vec<MapItem>>,
+}
+
+/// Reads from `reader` until `data` has at least `len` bytes
+async fn fill_to<R: AsyncRead + Unpin + ?Sized>(reader: &mut R, data: &mut Vec<u8>, len: usize) -> Result<(), Error> {
+    let mut buf = [0u8; 8192];
+    while data.len() < len {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("Expected {} bytes, received {}", len, data.len())));
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    Ok(())
+}
+
+impl PartialDex {
+    /// Reads until the header is complete
+    pub async fn read_header<R: AsyncRead + Unpin + ?Sized>(reader: &mut R) -> Result<PartialDex, Error> {
+        let mut data = Vec::new();
+        fill_to(reader, &mut data, HEADER_SIZE).await?;
+        let header = DexHeader::from_reader(&mut Cursor::new(&data))?;
+        let file_size = header.file_size as usize;
+        if file_size < HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("File size {} is smaller than the header", file_size)));
+        }
+        data.reserve(file_size.min(MAX_RESERVE).saturating_sub(data.len()));
+        Ok(PartialDex { data, header, map_list: None })
+    }
+
+    pub fn header(&self) -> &DexHeader {
+        &self.header
+    }
+
+    /// Number of bytes received so far
+    pub fn received(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Reads until the map list is complete. The map list is usually at the end of the data section,
+    /// so this mostly waits for the whole file.
+    pub async fn read_map_list<R: AsyncRead + Unpin + ?Sized>(&mut self, reader: &mut R) -> Result<&[MapItem], Error> {
+        if self.map_list.is_none() {
+            let map_off = self.header.map_off;
+            let items_off = checked::end(map_off, 4)?;
+            fill_to(reader, &mut self.data, items_off as usize).await?;
+            let size = read_u32(&mut &self.data[map_off as usize..items_off as usize])?;
+            fill_to(reader, &mut self.data, checked::table_end(items_off, size, 12)? as usize).await?;
+            self.map_list = Some(MapItem::parse_map_list(&self.header, &mut Cursor::new(&self.data))?);
+        }
+        Ok(self.map_list.as_deref().unwrap())
+    }
+
+    /// Reads the rest of the file (as declared by the header) and parses it on a blocking thread.
+    /// Must be called within a tokio runtime.
+    pub async fn finish<R: AsyncRead + Unpin + ?Sized>(mut self, reader: &mut R) -> Result<DexFile, Error> {
+        let file_size = self.header.file_size as usize;
+        fill_to(reader, &mut self.data, file_size).await?;
+        let data = self.data;
+        tokio::task::spawn_blocking(move || DexFile::from_bytes(&data)).await.map_err(Error::other)?
+    }
+}
+
+/// Parses a dex file from an async reader, see PartialDex to inspect the header before the file is complete.
+/// Must be called within a tokio runtime.
+pub async fn parse<R: AsyncRead + Unpin + ?Sized>(reader: &mut R) -> Result<DexFile, Error> {
+    PartialDex::read_header(reader).await?.finish(reader).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// Hands out the data in chunks of at most 7 bytes, like a slow connection
+    struct Chunked(Vec<u8>, usize);
+
+    impl AsyncRead for Chunked {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            let end = (self.1 + 7).min(self.0.len()).min(self.1 + buf.remaining());
+            buf.put_slice(&self.0[self.1..end]);
+            self.1 = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn chunked() {
+        let fixture = Fixture::new().class("La/B;").string("hello");
+        let data = fixture.build();
+        let expected = fixture.parse();
+        block_on(async {
+            let mut reader = Chunked(data.clone(), 0);
+            let mut partial = PartialDex::read_header(&mut reader).await.unwrap();
+            assert_eq!(partial.header().file_size as usize, data.len());
+            assert!(partial.received() < data.len());
+            let map_list = partial.read_map_list(&mut reader).await.unwrap().len();
+            assert_eq!(map_list, expected.map_list.len());
+            let dex = partial.finish(&mut reader).await.unwrap();
+            assert_eq!(dex.strings, expected.strings);
+            assert_eq!(dex.class_defs.len(), expected.class_defs.len());
+        });
+    }
+
+    #[test]
+    fn invalid_file_size() {
+        let mut data = Fixture::new().build();
+        data[32..36].copy_from_slice(&0x10u32.to_le_bytes());
+        let err = block_on(PartialDex::read_header(&mut Chunked(data, 0))).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A huge declared size is not reserved upfront, the file simply ends early
+        let mut data = Fixture::new().build();
+        data[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = block_on(parse(&mut Chunked(data, 0))).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
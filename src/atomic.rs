@@ -0,0 +1,66 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/*
+Output files are written under a temporary name next to them and renamed once complete, so an
+interrupted run (a failure, Ctrl-C, a full disk) never leaves a truncated file behind, and readers of
+an existing file see either the old or the new version.
+ */
+
+/// Temporary name of the file at `path` while it is written, in the same directory so the rename
+/// does not cross file systems
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map_or_else(|| "output".into(), |it| it.to_string_lossy());
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// File that replaces `path` once committed, it is removed if dropped before
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<AtomicFile> {
+        let temp = temp_path(path);
+        let file = BufWriter::new(File::create(&temp)?);
+        Ok(AtomicFile { path: path.to_path_buf(), temp, file: Some(file) })
+    }
+
+    /// Renames the written file to its path, replacing an existing file
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("Committed twice");
+        let result = file.into_inner().map_err(|err| err.into_error()).and_then(|_| fs::rename(&self.temp, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&self.temp);
+        }
+        result
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("Written after commit").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("Written after commit").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Writes `contents` to the file at `path` like fs::write, but atomically
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents)?;
+    file.commit()
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+
+use crate::instructions::{DecodeError, Format, Instruction, Instructions, Payload};
+use crate::raw_dex::{CodeItem, TryItem};
+
+/// A maximal sequence of instructions that is only entered at its first and left after its last instruction
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// pc of the first instruction
+    pub start: usize,
+    /// pc after the last instruction
+    pub end: usize,
+    /// Indices of the instructions in ControlFlowGraph::instructions
+    pub instructions: std::ops::Range<usize>,
+    /// Indices of the blocks reached by branches or falling through
+    pub successors: Vec<usize>,
+    /// Indices of the blocks of the exception handlers covering this block
+    pub exception_successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+/// Control flow graph of a code item. Payload pseudo-instructions are not part of any block.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub instructions: Vec<(usize, Instruction)>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+/// Targets of an instruction ending a block and whether execution may continue with the next instruction
+fn branch_targets(insns: &[u16], pc: usize, insn: &Instruction) -> Option<(Vec<usize>, bool)> {
+    let target = |offset: u32| (pc as i64 + offset as i32 as i64) as usize;
+    match insn.format() {
+        Format::F10t | Format::F20t | Format::F30t => Some((vec![target(insn.a)], false)),
+        Format::F21t => Some((vec![target(insn.b)], true)),
+        Format::F22t => Some((vec![target(insn.c)], true)),
+        Format::F31t if insn.opcode == 0x2b || insn.opcode == 0x2c => {
+            let targets = match Payload::decode(insns, target(insn.b)) {
+                Ok(Payload::PackedSwitch { targets, .. }) | Ok(Payload::SparseSwitch { targets, .. }) => targets,
+                _ => {
+                    tracing::warn!(pc, payload = target(insn.b), "Switch without payload, assuming no cases");
+                    Vec::new()
+                }
+            };
+            Some((targets.into_iter().map(|it| target(it as u32)).collect(), true))
+        }
+        // return-void, return, return-wide, return-object and throw
+        _ if (0x0e..=0x11).contains(&insn.opcode) || insn.opcode == 0x27 => Some((Vec::new(), false)),
+        _ => None,
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn build(code: &CodeItem) -> Result<ControlFlowGraph, DecodeError> {
+        let instructions = Instructions::new(&code.insns)
+            .filter(|it| !matches!(it, Ok((_, insn)) if insn.payload.is_some()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let handler_addresses = |try_item: &TryItem| -> Vec<usize> {
+            try_item.resolve_handler(code).into_iter()
+                .flat_map(|it| it.handlers.iter().map(|pair| pair.addr).chain(it.catch_all_addr))
+                .map(|addr| addr as usize)
+                .collect()
+        };
+
+        // Block leaders: entry, branch targets, instructions following branches, try boundaries and handlers
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (i, (pc, insn)) in instructions.iter().enumerate() {
+            if let Some((targets, _)) = branch_targets(&code.insns, *pc, insn) {
+                leaders.extend(targets);
+                if let Some((next, _)) = instructions.get(i + 1) {
+                    leaders.insert(*next);
+                }
+            }
+        }
+        for try_item in &code.tries {
+            leaders.insert(try_item.start_addr as usize);
+            leaders.insert(try_item.start_addr as usize + try_item.insn_count as usize);
+            leaders.extend(handler_addresses(try_item));
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        for (i, (pc, insn)) in instructions.iter().enumerate() {
+            match blocks.last_mut() {
+                Some(block) if !leaders.contains(pc) && block.end == *pc => {
+                    block.end = pc + insn.size;
+                    block.instructions.end = i + 1;
+                }
+                _ => blocks.push(BasicBlock {
+                    start: *pc,
+                    end: pc + insn.size,
+                    instructions: i..i + 1,
+                    successors: Vec::new(),
+                    exception_successors: Vec::new(),
+                    predecessors: Vec::new(),
+                }),
+            }
+        }
+
+        let block_at = |pc: usize| blocks.binary_search_by_key(&pc, |it| it.start).ok();
+        let mut edges = Vec::new();
+        for (idx, block) in blocks.iter().enumerate() {
+            let (pc, insn) = &instructions[block.instructions.end - 1];
+            let (targets, falls_through) = branch_targets(&code.insns, *pc, insn).unwrap_or((Vec::new(), true));
+            let mut successors: Vec<usize> = targets.into_iter().filter_map(block_at).collect();
+            if falls_through {
+                if let Some(next) = block_at(block.end) {
+                    successors.push(next);
+                }
+            }
+            successors.dedup();
+
+            let mut exception_successors = Vec::new();
+            for try_item in &code.tries {
+                let start = try_item.start_addr as usize;
+                if block.start >= start && block.start < start + try_item.insn_count as usize {
+                    exception_successors.extend(handler_addresses(try_item).into_iter().filter_map(block_at));
+                }
+            }
+            edges.push((idx, successors, exception_successors));
+        }
+        for (idx, successors, exception_successors) in edges {
+            for successor in successors.iter().chain(&exception_successors) {
+                if !blocks[*successor].predecessors.contains(&idx) {
+                    blocks[*successor].predecessors.push(idx);
+                }
+            }
+            blocks[idx].successors = successors;
+            blocks[idx].exception_successors = exception_successors;
+        }
+
+        Ok(ControlFlowGraph { instructions, blocks })
+    }
+
+    /// Index of the block starting at `pc`
+    pub fn block_at(&self, pc: usize) -> Option<usize> {
+        self.blocks.binary_search_by_key(&pc, |it| it.start).ok()
+    }
+
+    /// Instructions of a block with their pc
+    pub fn block_instructions(&self, block: usize) -> &[(usize, Instruction)] {
+        &self.instructions[self.blocks[block].instructions.clone()]
+    }
+
+    /// Blocks that are not reachable from the entry, following branches and exception edges. Blocks of
+    /// nops only are left out, they pad payloads to their alignment.
+    pub fn unreachable_blocks(&self) -> Vec<usize> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = Vec::new();
+        if !self.blocks.is_empty() {
+            reachable[0] = true;
+            stack.push(0);
+        }
+        while let Some(block) = stack.pop() {
+            for successor in self.blocks[block].successors.iter().chain(&self.blocks[block].exception_successors) {
+                if !reachable[*successor] {
+                    reachable[*successor] = true;
+                    stack.push(*successor);
+                }
+            }
+        }
+        (0..self.blocks.len())
+            .filter(|it| !reachable[*it])
+            .filter(|it| self.block_instructions(*it).iter().any(|(_, insn)| insn.opcode != 0x00))
+            .collect()
+    }
+
+    /// Whether the graph (including exception edges) has a cycle reachable from the entry
+    pub fn has_loops(&self) -> bool {
+        // 0: unvisited, 1: on the stack, 2: done
+        let mut state = vec![0u8; self.blocks.len()];
+        let mut stack = vec![(0usize, 0usize)];
+        if self.blocks.is_empty() {
+            return false;
+        }
+        state[0] = 1;
+        while let Some((block, next)) = stack.pop() {
+            let successors: Vec<usize> = self.blocks[block].successors.iter()
+                .chain(&self.blocks[block].exception_successors).copied().collect();
+            match successors.get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+                    match state[successor] {
+                        0 => {
+                            state[successor] = 1;
+                            stack.push((successor, 0));
+                        }
+                        1 => return true,
+                        _ => {}
+                    }
+                }
+                None => state[block] = 2,
+            }
+        }
+        false
+    }
+
+    /// Immediate post-dominator of every block (ignoring exception edges), None if it is the method exit
+    pub fn immediate_post_dominators(&self) -> Vec<Option<usize>> {
+        let n = self.blocks.len();
+        let all: BTreeSet<usize> = (0..n).collect();
+        let mut post_dominators: Vec<BTreeSet<usize>> = self.blocks.iter().enumerate()
+            .map(|(idx, block)| if block.successors.is_empty() { BTreeSet::from([idx]) } else { all.clone() })
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in (0..n).rev() {
+                let block = &self.blocks[idx];
+                if block.successors.is_empty() {
+                    continue;
+                }
+                let mut set = block.successors.iter()
+                    .map(|it| post_dominators[*it].clone())
+                    .reduce(|a, b| a.intersection(&b).copied().collect())
+                    .unwrap_or_default();
+                set.insert(idx);
+                if set != post_dominators[idx] {
+                    post_dominators[idx] = set;
+                    changed = true;
+                }
+            }
+        }
+        // Post-dominators form a chain, the closest one has the most post-dominators itself
+        (0..n).map(|idx| {
+            post_dominators[idx].iter()
+                .filter(|it| **it != idx)
+                .max_by_key(|it| post_dominators[**it].len())
+                .copied()
+        }).collect()
+    }
+}
@@ -0,0 +1,64 @@
+use core::convert::TryFrom;
+
+use crate::prelude::*;
+
+use crate::error::DexError;
+use crate::io::{self, Read, Seek, SeekFrom::{End, Start}};
+
+/*
+Checked conversions and arithmetic on the counts, sizes and offsets read from a dex file. A crafted
+file can declare any u32, so counts are not trusted for allocations, and offsets computed from them are
+checked instead of wrapping (or panicking in debug builds). Failures are DexErrors with the offset of
+the value, the callers add the item being parsed with DexError::context.
+ */
+
+/// Upper bound of the elements preallocated for a count read from the file. Larger counts still parse
+/// by growing the vector, a crafted count just can not allocate gigabytes before the reads fail.
+pub const MAX_PREALLOCATION: u32 = 4096;
+
+/// Capacity to preallocate for `count` elements read from the file
+pub fn capacity(count: u32) -> usize {
+    count.min(MAX_PREALLOCATION) as usize
+}
+
+/// Converts a size or offset read from the file at `offset` to usize
+pub fn to_usize(value: u32, offset: u64) -> Result<usize, DexError> {
+    usize::try_from(value).map_err(|_| DexError::Overflow { offset })
+}
+
+/// `off + len`, the end of `len` bytes at `off`
+pub fn end(off: u32, len: u32) -> Result<u32, DexError> {
+    off.checked_add(len).ok_or(DexError::Overflow { offset: off.into() })
+}
+
+/// Offset of the entry `idx` of a table at `off` with entries of `entry_size` bytes, None if it is out
+/// of the 32 bit range of dex offsets
+pub fn entry_offset(off: u32, idx: u32, entry_size: u32) -> Option<u32> {
+    idx.checked_mul(entry_size)?.checked_add(off)
+}
+
+/// End of a table at `off` with `count` entries of `entry_size` bytes
+pub fn table_end(off: u32, count: u32, entry_size: u32) -> Result<u32, DexError> {
+    entry_offset(off, count, entry_size).ok_or(DexError::Overflow { offset: off.into() })
+}
+
+/// Checks that `index`, read at `offset`, is within the `size` entries of the `kind` table
+pub fn index(kind: &'static str, index: u64, size: usize, offset: u32) -> Result<(), DexError> {
+    if index >= size as u64 {
+        return Err(DexError::InvalidIndex { offset: offset.into(), kind, index, size: size as u64 });
+    }
+    Ok(())
+}
+
+/// Reads `len` bytes at `off`, checking that they are within the reader before allocating them
+pub fn read_bytes<R: Read + Seek + ?Sized>(reader: &mut R, off: u32, len: u32) -> Result<Vec<u8>, io::Error> {
+    let end = end(off, len)?;
+    let available = reader.seek(End(0))?;
+    if u64::from(end) > available {
+        return Err(DexError::OutOfBounds { offset: off.into(), len: len.into(), available }.into());
+    }
+    let mut data = vec![0; to_usize(len, off.into())?];
+    reader.seek(Start(off.into()))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,394 @@
+use core::fmt;
+
+use crate::dex_file::{self, DexFile};
+use crate::prelude::*;
+use crate::raw_dex::{AnnotationItem, AnnotationsDirectory, ClassData, ClassDef, EncodedField, EncodedMethod, EncodedValue, Visibility, CODE_ITEM_HEADER_SIZE};
+
+/*
+Views of the classes defined in a dex file and their members, resolving the indices and offsets of
+the raw items against the DexFile they borrow from.
+ */
+
+/// A class defined in the dex file
+#[derive(Copy, Clone)]
+pub struct Class<'a> {
+    dex: &'a DexFile,
+    class_def_idx: usize,
+}
+
+/// A method defined by a class
+#[derive(Debug, Copy, Clone)]
+pub struct Method<'a> {
+    pub class: Class<'a>,
+    pub method_idx: u32,
+    pub encoded: &'a EncodedMethod,
+}
+
+/// A field defined by a class
+#[derive(Debug, Copy, Clone)]
+pub struct Field<'a> {
+    pub class: Class<'a>,
+    pub field_idx: u32,
+    pub encoded: &'a EncodedField,
+}
+
+/// An annotation with its type and element names resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation<'a> {
+    /// None for annotations nested in values
+    pub visibility: Option<Visibility>,
+    pub type_descriptor: &'a str,
+    /// Names and values of the elements, in the order of the annotation item (sorted by name index)
+    pub elements: Vec<(&'a str, Value<'a>)>,
+}
+
+/// A class, field or method with an annotation of the type looked up by DexFile::find_annotated
+#[derive(Debug, Clone)]
+pub enum Annotated<'a> {
+    Class(Class<'a>, Annotation<'a>),
+    Field(Field<'a>, Annotation<'a>),
+    Method(Method<'a>, Annotation<'a>),
+}
+
+/// Field or method referenced by a value, descriptor being the field type or method signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberRef<'a> {
+    pub class: &'a str,
+    pub name: &'a str,
+    pub descriptor: String,
+}
+
+/// An EncodedValue with its indices resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Byte(i8),
+    Short(i16),
+    Char(u16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    /// Signature of the prototype
+    MethodType(String),
+    /// Index of the method handle, method handles are not parsed yet
+    MethodHandle(u32),
+    String(&'a str),
+    Type(&'a str),
+    Field(MemberRef<'a>),
+    Method(MemberRef<'a>),
+    /// Field of the enum constant
+    Enum(MemberRef<'a>),
+    Array(Vec<Value<'a>>),
+    Annotation(Annotation<'a>),
+    Null,
+    Boolean(bool),
+}
+
+impl<'a> Value<'a> {
+    pub fn resolve(dex: &'a DexFile, value: &'a EncodedValue) -> Value<'a> {
+        let field = |idx: u32| MemberRef {
+            class: dex.field_class(idx),
+            name: dex.field_name(idx),
+            descriptor: dex.field_type(idx).to_owned(),
+        };
+        match value {
+            EncodedValue::Byte(value) => Value::Byte(*value),
+            EncodedValue::Short(value) => Value::Short(*value),
+            EncodedValue::Char(value) => Value::Char(*value),
+            EncodedValue::Int(value) => Value::Int(*value),
+            EncodedValue::Long(value) => Value::Long(*value),
+            EncodedValue::Float(value) => Value::Float(*value),
+            EncodedValue::Double(value) => Value::Double(*value),
+            EncodedValue::MethodType(proto_idx) => Value::MethodType(dex.proto_signature(*proto_idx)),
+            EncodedValue::MethodHandle(idx) => Value::MethodHandle(*idx),
+            EncodedValue::String(string_idx) => Value::String(dex.string(*string_idx)),
+            EncodedValue::Type(type_idx) => Value::Type(dex.type_descriptor(*type_idx)),
+            EncodedValue::Field(field_idx) => Value::Field(field(*field_idx)),
+            EncodedValue::Method(method_idx) => Value::Method(MemberRef {
+                class: dex.method_class(*method_idx),
+                name: dex.method_name(*method_idx),
+                descriptor: dex.method_signature(*method_idx),
+            }),
+            EncodedValue::Enum(field_idx) => Value::Enum(field(*field_idx)),
+            EncodedValue::Array(values) => Value::Array(values.iter().map(|it| Value::resolve(dex, it)).collect()),
+            EncodedValue::Annotation(annotation) => Value::Annotation(Annotation {
+                visibility: None,
+                type_descriptor: dex.type_descriptor(annotation.type_idx as u32),
+                elements: annotation.elements.iter()
+                    .map(|it| (dex.string(it.name_idx as u32), Value::resolve(dex, &it.value)))
+                    .collect(),
+            }),
+            EncodedValue::Null => Value::Null,
+            EncodedValue::Boolean(value) => Value::Boolean(*value),
+        }
+    }
+}
+
+impl EncodedValue {
+    /// The string of a string value
+    pub fn as_string<'a>(&self, dex: &'a DexFile) -> Option<&'a str> {
+        match *self {
+            EncodedValue::String(string_idx) => Some(dex.string(string_idx)),
+            _ => None,
+        }
+    }
+
+    /// The type descriptor of a type value
+    pub fn as_type<'a>(&self, dex: &'a DexFile) -> Option<&'a str> {
+        match *self {
+            EncodedValue::Type(type_idx) => Some(dex.type_descriptor(type_idx)),
+            _ => None,
+        }
+    }
+}
+
+/// Values in Java-like syntax with smali references, e.g. `Lcom/example/Foo;->name:I`
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Byte(value) => write!(f, "{}", value),
+            Value::Short(value) => write!(f, "{}", value),
+            Value::Char(value) => fmt::Display::fmt(&EncodedValue::Char(*value), f),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Long(value) => write!(f, "{}L", value),
+            Value::Float(value) => write!(f, "{:?}f", value),
+            Value::Double(value) => write!(f, "{:?}", value),
+            Value::MethodType(signature) => f.write_str(signature),
+            Value::MethodHandle(idx) => write!(f, "method_handle@{}", idx),
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::Type(descriptor) => f.write_str(descriptor),
+            Value::Field(field) | Value::Enum(field) => write!(f, "{}->{}:{}", field.class, field.name, field.descriptor),
+            Value::Method(method) => write!(f, "{}->{}{}", method.class, method.name, method.descriptor),
+            Value::Array(values) => {
+                f.write_str("{")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("}")
+            }
+            Value::Annotation(annotation) => write!(f, "{}", annotation),
+            Value::Null => f.write_str("null"),
+            Value::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Annotations like in Java source, with the type descriptor, e.g. `@Lcom/example/Marker;(level=3)`
+impl fmt::Display for Annotation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}", self.type_descriptor)?;
+        if self.elements.is_empty() {
+            return Ok(());
+        }
+        f.write_str("(")?;
+        for (i, (name, value)) in self.elements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl<'a> Annotation<'a> {
+    pub fn resolve(dex: &'a DexFile, item: &'a AnnotationItem) -> Annotation<'a> {
+        Annotation {
+            visibility: Some(item.visibility),
+            type_descriptor: dex.type_descriptor(item.annotation.type_idx as u32),
+            elements: item.annotation.elements.iter()
+                .map(|it| (dex.string(it.name_idx as u32), Value::resolve(dex, &it.value)))
+                .collect(),
+        }
+    }
+}
+
+/// Resolved annotations of the annotation set at `off`, an offset of 0 (or of a set that was not
+/// parsed, see ParseOptions) denoting an empty set
+fn annotation_set(dex: &DexFile, off: u32) -> Vec<Annotation<'_>> {
+    dex.annotation_sets.get(&off).map_or_else(Vec::new, |set| {
+        set.iter()
+            .filter_map(|it| dex.annotation_items.get(it))
+            .map(|it| Annotation::resolve(dex, it))
+            .collect()
+    })
+}
+
+fn of_type<'a>(annotations: Vec<Annotation<'a>>, type_descriptor: &str) -> Option<Annotation<'a>> {
+    annotations.into_iter().find(|it| it.type_descriptor == type_descriptor)
+}
+
+impl DexFile {
+    /// Classes defined in the dex file, in the order of the class definitions
+    pub fn classes(&self) -> impl Iterator<Item=Class<'_>> {
+        (0..self.class_defs.len()).map(move |class_def_idx| Class { dex: self, class_def_idx })
+    }
+
+    /// Class of the class definition at `class_def_idx`
+    pub fn class(&self, class_def_idx: usize) -> Option<Class<'_>> {
+        Some(Class { dex: self, class_def_idx }).filter(|_| class_def_idx < self.class_defs.len())
+    }
+
+    /// Class with the descriptor (e.g. `Lcom/foo/Bar;`), None if this file does not define it
+    pub fn find_class(&self, descriptor: &str) -> Option<Class<'_>> {
+        self.type_class_def_idx(self.type_idx(descriptor)?).map(|class_def_idx| Class { dex: self, class_def_idx })
+    }
+
+    /// Classes, fields and methods with an annotation of the type (e.g. `Landroidx/annotation/Keep;`), in
+    /// the order of the classes, each followed by its annotated fields and methods. Annotations of
+    /// parameters do not annotate their method.
+    pub fn find_annotated(&self, type_descriptor: &str) -> Vec<Annotated<'_>> {
+        let mut annotated = Vec::new();
+        if self.type_idx(type_descriptor).is_none() {
+            return annotated;
+        }
+        for class in self.classes().filter(|it| it.annotations_directory().is_some()) {
+            if let Some(annotation) = of_type(class.annotations(), type_descriptor) {
+                annotated.push(Annotated::Class(class, annotation));
+            }
+            for field in class.fields() {
+                if let Some(annotation) = of_type(field.annotations(), type_descriptor) {
+                    annotated.push(Annotated::Field(field, annotation));
+                }
+            }
+            for method in class.methods() {
+                if let Some(annotation) = of_type(method.annotations(), type_descriptor) {
+                    annotated.push(Annotated::Method(method, annotation));
+                }
+            }
+        }
+        annotated
+    }
+
+    /// Method `class->name(signature)` as defined by this file, e.g. `find_method("Lcom/foo/Bar;", "run",
+    /// "()V")`. Methods that are only referenced have a method_idx but no definition, see method_idx.
+    pub fn find_method(&self, class: &str, name: &str, signature: &str) -> Option<Method<'_>> {
+        let method_idx = self.method_idx(class, name, signature)?;
+        self.find_class(class)?.methods().into_iter().find(|it| it.method_idx == method_idx)
+    }
+
+    /// Field `class->name:type_descriptor` as defined by this file, see find_method
+    pub fn find_field(&self, class: &str, name: &str, type_descriptor: &str) -> Option<Field<'_>> {
+        let field_idx = self.field_idx(class, name, type_descriptor)?;
+        self.find_class(class)?.fields().into_iter().find(|it| it.field_idx == field_idx)
+    }
+}
+
+impl fmt::Debug for Class<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Class").field("class_def_idx", &self.class_def_idx).field("descriptor", &self.descriptor()).finish()
+    }
+}
+
+impl<'a> Class<'a> {
+    pub fn dex(&self) -> &'a DexFile {
+        self.dex
+    }
+
+    pub fn class_def_idx(&self) -> usize {
+        self.class_def_idx
+    }
+
+    pub fn def(&self) -> &'a ClassDef {
+        &self.dex.class_defs[self.class_def_idx]
+    }
+
+    pub fn descriptor(&self) -> &'a str {
+        self.dex.type_descriptor(self.def().class_idx)
+    }
+
+    pub fn class_data(&self) -> Option<&'a ClassData> {
+        self.dex.class_data[self.class_def_idx].as_ref()
+    }
+
+    /// Static fields followed by instance fields
+    pub fn fields(&self) -> Vec<Field<'a>> {
+        let class = *self;
+        self.class_data().map_or_else(Vec::new, |data| {
+            [&data.static_fields, &data.instance_fields].iter()
+                .flat_map(|fields| dex_file::field_indices(fields).into_iter().zip(fields.iter()))
+                .map(|(field_idx, encoded)| Field { class, field_idx, encoded })
+                .collect()
+        })
+    }
+
+    /// Direct methods followed by virtual methods
+    pub fn methods(&self) -> Vec<Method<'a>> {
+        let class = *self;
+        self.class_data().map_or_else(Vec::new, |data| {
+            [&data.direct_methods, &data.virtual_methods].iter()
+                .flat_map(|methods| dex_file::method_indices(methods).into_iter().zip(methods.iter()))
+                .map(|(method_idx, encoded)| Method { class, method_idx, encoded })
+                .collect()
+        })
+    }
+
+    fn annotations_directory(&self) -> Option<&'a AnnotationsDirectory> {
+        let off = self.def().annotations_off;
+        self.dex.annotations_directories.get(&off)
+    }
+
+    /// Annotations of the class itself
+    pub fn annotations(&self) -> Vec<Annotation<'a>> {
+        self.annotations_directory().map_or_else(Vec::new, |it| annotation_set(self.dex, it.class_annotations_off))
+    }
+}
+
+impl<'a> Method<'a> {
+    pub fn name(&self) -> &'a str {
+        self.class.dex.method_name(self.method_idx)
+    }
+
+    /// Offset in the file of the code unit at `addr` of the code of the method, e.g. of an instruction
+    /// at a pc of the smali code, the xrefs or the verifier. None for methods without code and
+    /// addresses after the instructions.
+    pub fn file_offset_of(&self, addr: u32) -> Option<u64> {
+        let code = self.class.dex.code_item(self.encoded.code_off)?;
+        if addr as usize >= code.insns.len() {
+            return None;
+        }
+        let span = self.class.dex.item_span(self.encoded.code_off as u32)?;
+        let offset = span.offset as u64 + CODE_ITEM_HEADER_SIZE as u64 + 2 * addr as u64;
+        Some(offset).filter(|it| *it < span.offset as u64 + span.len as u64)
+    }
+
+    pub fn annotations(&self) -> Vec<Annotation<'a>> {
+        self.class.annotations_directory()
+            .and_then(|it| it.method_annotations_off(self.method_idx))
+            .map_or_else(Vec::new, |off| annotation_set(self.class.dex, off))
+    }
+
+    /// Annotations of each parameter, empty if none of the parameters has annotations. The list can
+    /// be shorter than the parameters, e.g. for the implicit parameters of constructors of inner classes.
+    pub fn parameter_annotations(&self) -> Vec<Vec<Annotation<'a>>> {
+        let dex = self.class.dex;
+        self.class.annotations_directory()
+            .and_then(|it| it.parameter_annotations_off(self.method_idx))
+            .and_then(|off| dex.annotation_set_ref_lists.get(&off))
+            .map_or_else(Vec::new, |list| list.iter().map(|set_off| annotation_set(dex, *set_off)).collect())
+    }
+}
+
+impl<'a> Field<'a> {
+    pub fn name(&self) -> &'a str {
+        self.class.dex.field_name(self.field_idx)
+    }
+
+    /// Initial value of a static field from the static values of its class. None for instance fields
+    /// and for static fields after the last value, which are initialized to 0 or null.
+    pub fn static_value(&self) -> Option<Value<'a>> {
+        let dex = self.class.dex;
+        let position = self.class.class_data()?.static_fields.iter().position(|it| core::ptr::eq(it, self.encoded))?;
+        let values = dex.static_values.get(&self.class.def().static_values_off)?;
+        values.get(position).map(|it| Value::resolve(dex, it))
+    }
+
+    pub fn annotations(&self) -> Vec<Annotation<'a>> {
+        self.class.annotations_directory()
+            .and_then(|it| it.field_annotations_off(self.field_idx))
+            .map_or_else(Vec::new, |off| annotation_set(self.class.dex, off))
+    }
+}
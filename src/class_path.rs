@@ -0,0 +1,194 @@
+use alloc::collections::{BTreeSet, VecDeque};
+
+use crate::class::{Class, Field, Method};
+use crate::dex_file::{DexFile, NO_INDEX};
+use crate::prelude::*;
+use crate::stubs::{StubClass, StubMember, Stubs};
+
+/*
+Resolution of classes across several dex files, e.g. the dex files of a multidex app together with the
+framework. Like the class loaders of the runtime, the dex files are searched in order and the first
+definition of a class wins, so the framework comes first and the dex files of an app follow in multidex
+order (classes.dex, classes2.dex, ...).
+Instead of framework dex files, the declarations of the framework can come from stubs (android.jar of the
+SDK), they are searched after the dex files.
+ */
+
+/// Dex files searched in order for the definitions of classes, then the stubs
+pub struct ClassPath<'a> {
+    dex_files: Vec<&'a DexFile>,
+    stubs: Option<&'a Stubs>,
+}
+
+/// A superclass or interface of a class, see Class::ancestors and Class::all_interfaces
+#[derive(Debug, Copy, Clone)]
+pub enum Ancestor<'a> {
+    /// A class defined in one of the dex files of the class path
+    Defined(Class<'a>),
+    /// A class declared by the stubs of the class path
+    Stub(&'a StubClass),
+    /// The descriptor of a class that is neither defined nor declared in the class path (e.g. of the
+    /// framework, if it is not part of the class path), its own supertypes are unknown
+    External(&'a str),
+}
+
+/// A field or method resolved in the class path, see ClassPath::resolve_method
+#[derive(Debug, Copy, Clone)]
+pub enum Resolved<'a, T> {
+    Defined(T),
+    /// The declaring stub class and the declaration
+    Stub(&'a StubClass, &'a StubMember),
+}
+
+impl<'a> Ancestor<'a> {
+    pub fn descriptor(&self) -> &'a str {
+        match *self {
+            Ancestor::Defined(class) => class.descriptor(),
+            Ancestor::Stub(class) => &class.descriptor,
+            Ancestor::External(descriptor) => descriptor,
+        }
+    }
+
+    fn superclass(&self) -> Option<&'a str> {
+        match *self {
+            Ancestor::Defined(class) => Some(class.def().superclass_idx)
+                .filter(|it| *it != NO_INDEX)
+                .map(|it| class.dex().type_descriptor(it)),
+            Ancestor::Stub(class) => class.superclass.as_deref(),
+            Ancestor::External(_) => None,
+        }
+    }
+
+    fn interfaces(&self) -> Vec<&'a str> {
+        match *self {
+            Ancestor::Defined(class) => {
+                let dex = class.dex();
+                dex.type_list(class.def().interfaces_off).iter().map(|it| dex.type_descriptor(u32::from(*it))).collect()
+            }
+            Ancestor::Stub(class) => class.interfaces.iter().map(|it| it.as_str()).collect(),
+            Ancestor::External(_) => Vec::new(),
+        }
+    }
+}
+
+impl<'a> ClassPath<'a> {
+    pub fn new(dex_files: Vec<&'a DexFile>) -> ClassPath<'a> {
+        ClassPath { dex_files, stubs: None }
+    }
+
+    /// Adds the stubs, searched for the classes that none of the dex files defines
+    pub fn with_stubs(mut self, stubs: &'a Stubs) -> ClassPath<'a> {
+        self.stubs = Some(stubs);
+        self
+    }
+
+    pub fn dex_files(&self) -> &[&'a DexFile] {
+        &self.dex_files
+    }
+
+    pub fn stubs(&self) -> Option<&'a Stubs> {
+        self.stubs
+    }
+
+    /// First definition of the class with the descriptor in the dex files of the class path
+    pub fn find_class(&self, descriptor: &str) -> Option<Class<'a>> {
+        self.dex_files.iter().find_map(|it| it.find_class(descriptor))
+    }
+
+    /// Stub of the class with the descriptor, whether or not a dex file defines it
+    pub fn find_stub(&self, descriptor: &str) -> Option<&'a StubClass> {
+        self.stubs?.get(descriptor)
+    }
+
+    /// The definition of the class or else its stub, None if it is external
+    fn find(&self, descriptor: &str) -> Option<Ancestor<'a>> {
+        self.find_class(descriptor).map(Ancestor::Defined)
+            .or_else(|| self.find_stub(descriptor).map(Ancestor::Stub))
+    }
+
+    fn resolve(&self, descriptor: &'a str) -> Ancestor<'a> {
+        self.find(descriptor).unwrap_or(Ancestor::External(descriptor))
+    }
+
+    /// Superclasses of the type, nearest first
+    fn superclasses(&self, start: Ancestor<'a>) -> Vec<Ancestor<'a>> {
+        let mut superclasses = Vec::new();
+        let mut visited = BTreeSet::from([start.descriptor()]);
+        let mut class = start;
+        while let Some(superclass) = class.superclass() {
+            if !visited.insert(superclass) {
+                break;
+            }
+            class = self.resolve(superclass);
+            superclasses.push(class);
+        }
+        superclasses
+    }
+
+    /// Interfaces of the type and its superclasses and superinterfaces, breadth first
+    fn interfaces(&self, start: Ancestor<'a>) -> Vec<Ancestor<'a>> {
+        let mut pending: VecDeque<&'a str> = Some(start).into_iter()
+            .chain(self.superclasses(start))
+            .flat_map(|it| it.interfaces())
+            .collect();
+        let mut visited = BTreeSet::new();
+        let mut interfaces = Vec::new();
+        while let Some(descriptor) = pending.pop_front() {
+            if !visited.insert(descriptor) {
+                continue;
+            }
+            let interface = self.resolve(descriptor);
+            pending.extend(interface.interfaces());
+            interfaces.push(interface);
+        }
+        interfaces
+    }
+
+    /// The declaration of a member of the class found by `find` in the class, its superclasses and then
+    /// its interfaces, like the resolution of member references by the runtime
+    fn resolve_member<T>(&self, class: &str,
+                         find: impl Fn(Class<'a>) -> Option<T>,
+                         find_stub: impl Fn(&'a StubClass) -> Option<&'a StubMember>) -> Option<Resolved<'a, T>> {
+        let start = self.find(class)?;
+        Some(start).into_iter()
+            .chain(self.superclasses(start))
+            .chain(self.interfaces(start))
+            .find_map(|it| match it {
+                Ancestor::Defined(class) => find(class).map(Resolved::Defined),
+                Ancestor::Stub(class) => find_stub(class).map(|it| Resolved::Stub(class, it)),
+                Ancestor::External(_) => None,
+            })
+    }
+
+    /// The method declared by the class or inherited from a supertype, e.g. the declaration in
+    /// android.app.Activity of `Lcom/example/MainActivity;->findViewById(I)Landroid/view/View;`. None
+    /// if the method is not found before an external supertype.
+    pub fn resolve_method(&self, class: &str, name: &str, signature: &str) -> Option<Resolved<'a, Method<'a>>> {
+        self.resolve_member(class,
+                            |it| it.dex().find_method(it.descriptor(), name, signature),
+                            |it| it.find_method(name, signature))
+    }
+
+    /// The field declared by the class or inherited from a supertype, see resolve_method
+    pub fn resolve_field(&self, class: &str, name: &str, type_descriptor: &str) -> Option<Resolved<'a, Field<'a>>> {
+        self.resolve_member(class,
+                            |it| it.dex().find_field(it.descriptor(), name, type_descriptor),
+                            |it| it.find_field(name, type_descriptor))
+    }
+}
+
+impl<'a> Class<'a> {
+    /// Superclasses of the class resolved in the class path, nearest first. The walk ends with
+    /// java.lang.Object (or another class without superclass), or with the first External ancestor. A
+    /// cycle of superclasses (only in malformed files) ends it as well.
+    pub fn ancestors(&self, class_path: &ClassPath<'a>) -> Vec<Ancestor<'a>> {
+        class_path.superclasses(Ancestor::Defined(*self))
+    }
+
+    /// Interfaces implemented by the class resolved in the class path, each once: the interfaces it and
+    /// its superclasses list, nearest first, followed by their superinterfaces. The superinterfaces of
+    /// External interfaces are unknown.
+    pub fn all_interfaces(&self, class_path: &ClassPath<'a>) -> Vec<Ancestor<'a>> {
+        class_path.interfaces(Ancestor::Defined(*self))
+    }
+}
@@ -0,0 +1,385 @@
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::instructions::{EncodeError, Format, Instruction, Payload, OPCODES};
+use crate::prelude::*;
+
+/*
+Builder of instruction arrays for the fixtures, the dex editing and the patching, e.g.
+
+    let mut code = CodeBuilder::new();
+    let done = code.label();
+    code.emit("const-string", &[Reg(0), Idx(hello)])?
+        .emit("if-eqz", &[Reg(1), Target(done)])?
+        .emit("invoke-static", &[Regs(vec![0]), Idx(log)])?;
+    code.bind(done)?;
+    code.emit("return-void", &[])?;
+    let insns = code.build()?;
+
+Instructions are named like in smali and their operands given in smali order. Each instruction is
+checked when emitted, registers, indices and literals have to fit their fields in the format of the
+opcode (there is no automatic selection of a wider variant like move/from16). Branch offsets are
+resolved and checked by build, which appends the switch and array payloads after the instructions.
+ */
+
+/// Position in the instructions, created by label and bound to the next instruction by bind
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Operand of an instruction
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Reg(u32),
+    /// Literal of const*, the /lit8 and /lit16 operations. The value of const/high16 and
+    /// const-wide/high16 (e.g. 0x7f010000), not its high bits.
+    Lit(i64),
+    /// String, type, field, method, proto, call site or method handle index
+    Idx(u32),
+    /// Branch target
+    Target(Label),
+    /// Argument registers of format 35c and 45cc, e.g. `{v0, v1}`
+    Regs(Vec<u32>),
+    /// First and last argument register of format 3rc and 4rcc, e.g. `{v0 .. v3}`
+    Range(u32, u32),
+}
+
+use Operand::*;
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// No instruction has the name
+    UnknownInstruction(String),
+    /// The operands do not match the format of the instruction, with the expected ones
+    Operands { name: &'static str, expected: &'static str },
+    /// The literal does not fit the instruction, e.g. const/high16 with low bits set
+    Literal { name: &'static str, value: i64 },
+    /// A register, index or branch offset does not fit its field
+    Encode(EncodeError),
+    LabelBoundTwice(Label),
+    /// A branch or switch case targets a label that was never bound
+    UnboundLabel(Label),
+}
+
+impl core::error::Error for BuildError {}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::UnknownInstruction(name) => write!(f, "Unknown instruction {}", name),
+            BuildError::Operands { name, expected } => write!(f, "Operands of {} have to be {}", name, expected),
+            BuildError::Literal { name, value } => write!(f, "Literal of {} out of range: {}", name, value),
+            BuildError::Encode(err) => err.fmt(f),
+            BuildError::LabelBoundTwice(label) => write!(f, "Label {} bound twice", label.0),
+            BuildError::UnboundLabel(label) => write!(f, "Label {} is not bound", label.0),
+        }
+    }
+}
+
+impl From<EncodeError> for BuildError {
+    fn from(err: EncodeError) -> Self {
+        BuildError::Encode(err)
+    }
+}
+
+/// Branch of an emitted instruction, resolved by build
+#[derive(Debug)]
+enum Branch {
+    Label(Label),
+    /// Index of the payload of a switch or fill-array-data
+    Payload(usize),
+}
+
+/// Payload with the switch cases targeting labels
+#[derive(Debug)]
+enum PendingPayload {
+    PackedSwitch { first_key: i32, targets: Vec<Label> },
+    SparseSwitch { keys: Vec<i32>, targets: Vec<Label> },
+    FillArrayData { element_width: u16, data: Vec<u8> },
+}
+
+#[derive(Debug, Default)]
+pub struct CodeBuilder {
+    /// Instructions with their pc and branch
+    instructions: Vec<(usize, Instruction, Option<Branch>)>,
+    /// pc of the instruction each label is bound to
+    labels: Vec<Option<usize>>,
+    /// Payloads with the pc of the instruction referencing them
+    payloads: Vec<(usize, PendingPayload)>,
+    pc: usize,
+}
+
+/// Operands expected by the instructions of a format, for the errors
+fn expected(format: Format) -> &'static str {
+    use Format::*;
+    match format {
+        F10x => "none",
+        F12x | F22x | F32x => "vA, vB",
+        F11n | F21s | F21h | F31i | F51l => "vA, literal",
+        F11x => "vA",
+        F10t | F20t | F30t => "label",
+        F21t => "vA, label",
+        F31t => "emitted by packed_switch, sparse_switch or fill_array_data",
+        F21c | F31c => "vA, index",
+        F23x => "vA, vB, vC",
+        F22b | F22s => "vA, vB, literal",
+        F22t => "vA, vB, label",
+        F22c => "vA, vB, index",
+        F35c => "{registers}, index",
+        F3rc => "{range}, index",
+        F45cc => "{registers}, method index, proto index",
+        F4rcc => "{range}, method index, proto index",
+    }
+}
+
+impl CodeBuilder {
+    pub fn new() -> CodeBuilder {
+        CodeBuilder::default()
+    }
+
+    /// New label, to be bound with bind
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds the label to the next emitted instruction
+    pub fn bind(&mut self, label: Label) -> Result<&mut Self, BuildError> {
+        if self.labels[label.0].is_some() {
+            return Err(BuildError::LabelBoundTwice(label));
+        }
+        self.labels[label.0] = Some(self.pc);
+        Ok(self)
+    }
+
+    /// Emits the instruction `name` (e.g. invoke-static) with its operands in smali order
+    pub fn emit(&mut self, name: &str, operands: &[Operand]) -> Result<&mut Self, BuildError> {
+        let opcode = OPCODES.iter().position(|it| it.name == name && !name.starts_with("unused-"))
+            .ok_or_else(|| BuildError::UnknownInstruction(name.to_owned()))?;
+        let mut insn = Instruction::new(opcode as u8);
+        let (name, format) = (insn.name(), insn.format());
+        let literal_error = |value: i64| BuildError::Literal { name, value };
+        // Signed literal, the encoder checks the fields narrower than 32 bits
+        let literal = |value: i64| i32::try_from(value).map(|it| it as u32).map_err(|_| literal_error(value));
+        let mut branch = None;
+        use Format::*;
+        match (format, operands) {
+            (F10x, []) => {}
+            (F12x | F22x | F32x, [Reg(a), Reg(b)]) => {
+                insn.a = *a;
+                insn.b = *b;
+            }
+            (F23x, [Reg(a), Reg(b), Reg(c)]) => {
+                insn.a = *a;
+                insn.b = *b;
+                insn.c = *c;
+            }
+            (F11n | F21s, [Reg(a), Lit(value)]) => {
+                insn.a = *a;
+                insn.b = literal(*value)?;
+            }
+            (F31i, [Reg(a), Lit(value)]) => {
+                insn.a = *a;
+                // Also the bits of an unsigned int, e.g. 0xffffffff
+                insn.b = literal(*value).or_else(|_| u32::try_from(*value).map_err(|_| literal_error(*value)))?;
+            }
+            (F21h, [Reg(a), Lit(value)]) => {
+                insn.a = *a;
+                // const/high16 sets the high 16 bits of an int, const-wide/high16 of a long
+                let shift = if insn.opcode == 0x15 { 16 } else { 48 };
+                let bits = if insn.opcode == 0x15 { literal(*value)? as u64 } else { *value as u64 };
+                if bits & ((1 << shift) - 1) != 0 {
+                    return Err(literal_error(*value));
+                }
+                insn.b = (bits >> shift) as u32;
+            }
+            (F51l, [Reg(a), Lit(value)]) => {
+                insn.a = *a;
+                insn.wide_b = *value as u64;
+            }
+            (F11x, [Reg(a)]) => insn.a = *a,
+            (F10t | F20t | F30t, [Target(label)]) => branch = Some(Branch::Label(*label)),
+            (F21t, [Reg(a), Target(label)]) => {
+                insn.a = *a;
+                branch = Some(Branch::Label(*label));
+            }
+            (F21c | F31c, [Reg(a), Idx(index)]) => {
+                insn.a = *a;
+                insn.b = *index;
+            }
+            (F22b | F22s, [Reg(a), Reg(b), Lit(value)]) => {
+                insn.a = *a;
+                insn.b = *b;
+                insn.c = literal(*value)?;
+            }
+            (F22t, [Reg(a), Reg(b), Target(label)]) => {
+                insn.a = *a;
+                insn.b = *b;
+                branch = Some(Branch::Label(*label));
+            }
+            (F22c, [Reg(a), Reg(b), Idx(index)]) => {
+                insn.a = *a;
+                insn.b = *b;
+                insn.c = *index;
+            }
+            (F35c, [Regs(registers), Idx(index)]) | (F45cc, [Regs(registers), Idx(index), Idx(_)]) => {
+                insn.a = registers.len() as u32;
+                for (i, register) in registers.iter().enumerate().take(5) {
+                    let operand = ["vC", "vD", "vE", "vF", "vG"][i];
+                    insn.args[i] = u8::try_from(*register).ok().filter(|it| *it < 16)
+                        .ok_or(EncodeError::OutOfRange { opcode: insn.opcode, operand, value: *register as i64 })?;
+                }
+                insn.b = *index;
+                if let [_, _, Idx(proto)] = operands {
+                    insn.h = *proto;
+                }
+            }
+            (F3rc, [Range(first, last), Idx(index)]) | (F4rcc, [Range(first, last), Idx(index), Idx(_)]) if first <= last => {
+                insn.a = last - first + 1;
+                insn.b = *index;
+                insn.c = *first;
+                if let [_, _, Idx(proto)] = operands {
+                    insn.h = *proto;
+                }
+            }
+            _ => return Err(BuildError::Operands { name, expected: expected(format) }),
+        }
+        self.push(insn, branch)
+    }
+
+    fn push(&mut self, insn: Instruction, branch: Option<Branch>) -> Result<&mut Self, BuildError> {
+        // Checks the operands but the branch offset, which is only known once the labels are bound
+        insn.encode()?;
+        let size = insn.size;
+        self.instructions.push((self.pc, insn, branch));
+        self.pc += size;
+        Ok(self)
+    }
+
+    /// Emits the 31t instruction `opcode` on `register` referencing the payload
+    fn emit_with_payload(&mut self, opcode: u8, register: u32, payload: PendingPayload) -> Result<&mut Self, BuildError> {
+        let mut insn = Instruction::new(opcode);
+        insn.a = register;
+        self.payloads.push((self.pc, payload));
+        self.push(insn, Some(Branch::Payload(self.payloads.len() - 1)))
+    }
+
+    /// Emits packed-switch on `register`, the cases first_key, first_key + 1, ... branch to `targets`
+    pub fn packed_switch(&mut self, register: u32, first_key: i32, targets: &[Label]) -> Result<&mut Self, BuildError> {
+        self.emit_with_payload(0x2b, register, PendingPayload::PackedSwitch { first_key, targets: targets.to_vec() })
+    }
+
+    /// Emits sparse-switch on `register` with the cases sorted by key
+    pub fn sparse_switch(&mut self, register: u32, cases: &[(i32, Label)]) -> Result<&mut Self, BuildError> {
+        let mut cases = cases.to_vec();
+        cases.sort_by_key(|it| it.0);
+        let payload = PendingPayload::SparseSwitch {
+            keys: cases.iter().map(|it| it.0).collect(),
+            targets: cases.iter().map(|it| it.1).collect(),
+        };
+        self.emit_with_payload(0x2c, register, payload)
+    }
+
+    /// Emits fill-array-data of the array in `register` with the little endian elements of element_width
+    /// bytes each
+    pub fn fill_array_data(&mut self, register: u32, element_width: u16, data: &[u8]) -> Result<&mut Self, BuildError> {
+        self.emit_with_payload(0x26, register, PendingPayload::FillArrayData { element_width, data: data.to_vec() })
+    }
+
+    /// Number of registers used by the instructions, for registers_size of the code item
+    pub fn registers_size(&self) -> u32 {
+        self.instructions.iter()
+            .flat_map(|(_, insn, _)| insn.registers())
+            .map(|(register, wide)| register + 1 + wide as u32)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Most argument registers of the invokes, for outs_size of the code item
+    pub fn outs_size(&self) -> u32 {
+        self.instructions.iter()
+            .filter(|(_, insn, _)| insn.name().starts_with("invoke-"))
+            .map(|(_, insn, _)| insn.a)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Resolves the labels and encodes the instructions followed by the payloads (4 byte aligned)
+    pub fn build(self) -> Result<Vec<u16>, BuildError> {
+        let label_pc = |label: Label| self.labels[label.0].ok_or(BuildError::UnboundLabel(label));
+        let mut insns = Vec::with_capacity(self.pc);
+        let mut payload_pcs = Vec::with_capacity(self.payloads.len());
+        let mut payload_pc = self.pc;
+        for (_, payload) in &self.payloads {
+            payload_pc += payload_pc % 2;
+            payload_pcs.push(payload_pc);
+            payload_pc += match payload {
+                PendingPayload::PackedSwitch { targets, .. } => 4 + targets.len() * 2,
+                PendingPayload::SparseSwitch { keys, .. } => 2 + keys.len() * 4,
+                PendingPayload::FillArrayData { data, .. } => 4 + data.len().div_ceil(2),
+            };
+        }
+
+        for (pc, insn, branch) in &self.instructions {
+            let mut insn = insn.clone();
+            let offset = match branch {
+                Some(Branch::Label(label)) => label_pc(*label)? as i64 - *pc as i64,
+                Some(Branch::Payload(i)) => payload_pcs[*i] as i64 - *pc as i64,
+                None => 0,
+            } as i32 as u32;
+            match insn.format() {
+                Format::F10t | Format::F20t | Format::F30t => insn.a = offset,
+                Format::F21t | Format::F31t => insn.b = offset,
+                Format::F22t => insn.c = offset,
+                _ => {}
+            }
+            insns.extend(insn.encode()?);
+        }
+        for ((switch_pc, payload), pc) in self.payloads.iter().zip(payload_pcs) {
+            if insns.len() < pc {
+                insns.push(0x0000);
+            }
+            let relative = |targets: &[Label]| -> Result<Vec<i32>, BuildError> {
+                targets.iter().map(|it| Ok((label_pc(*it)? as i64 - *switch_pc as i64) as i32)).collect()
+            };
+            let payload = match payload {
+                PendingPayload::PackedSwitch { first_key, targets } => Payload::PackedSwitch { first_key: *first_key, targets: relative(targets)? },
+                PendingPayload::SparseSwitch { keys, targets } => Payload::SparseSwitch { keys: keys.clone(), targets: relative(targets)? },
+                PendingPayload::FillArrayData { element_width, data } => Payload::FillArrayData { element_width: *element_width, data: data.clone() },
+            };
+            insns.extend(payload.encode()?);
+        }
+        Ok(insns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Fixture, FixtureMethod};
+    use crate::instructions::Instructions;
+
+    #[test]
+    fn built_code() {
+        let fixture = Fixture::empty().string("hello");
+        let hello = fixture.string_idx("hello").unwrap();
+        let mut code = CodeBuilder::new();
+        let (zero, done) = (code.label(), code.label());
+        code.emit("const-string", &[Reg(0), Idx(hello)]).unwrap()
+            .emit("if-eqz", &[Reg(1), Target(zero)]).unwrap()
+            .packed_switch(1, 1, &[done]).unwrap();
+        code.bind(zero).unwrap().emit("const/high16", &[Reg(0), Lit(0x7f010000)]).unwrap();
+        code.bind(done).unwrap().emit("return-void", &[]).unwrap();
+        assert!(code.emit("const/4", &[Reg(0), Lit(8)]).is_err());
+        assert!(code.emit("move", &[Reg(16), Reg(0)]).is_err());
+        assert!(code.emit("const/high16", &[Reg(0), Lit(1)]).is_err());
+        assert_eq!(code.registers_size(), 2);
+
+        let method = FixtureMethod::new("run", "V", &["I"], code.build().unwrap()).registers_size(2);
+        let insns = fixture::insns(&fixture.method(method).parse(), "run");
+        let decoded: Vec<(usize, &str)> = Instructions::new(&insns).map(|it| it.unwrap()).map(|(pc, it)| (pc, it.name())).collect();
+        assert_eq!(decoded, [(0, "const-string"), (2, "if-eqz"), (4, "packed-switch"), (7, "const/high16"), (9, "return-void"), (10, "nop")]);
+        assert_eq!(Instruction::decode(&insns, 2).unwrap().b, 5);
+        assert_eq!(Instruction::decode(&insns, 7).unwrap().b, 0x7f01);
+        assert_eq!(Payload::decode(&insns, 10).unwrap().switch_cases(), Some(vec![(1, 5)]));
+    }
+}
@@ -0,0 +1,494 @@
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+use crate::dex_file::{self, context, Diagnostics, Strictness};
+use crate::io::{self, Read, Seek, SeekFrom::Start};
+use crate::m_utf8;
+use crate::prelude::*;
+use crate::raw_dex::{self, ClassData, ClassDef, CodeItem, DexHeader, EncodedField};
+
+/*
+Compact representation of a dex file for very large (multi-)dex apps, see ParseOptions::parse_compact.
+Instead of a struct (with its own allocations) per item, each table is stored as columns, and variable
+sized items (strings, parameter lists, instructions) are concatenated into one buffer per kind, indexed
+by the end offsets of the items. It keeps the id tables, the class definitions with their members and
+the instructions of the code items; debug info, annotations, static values and try blocks are not
+kept.
+ */
+
+/// Marks methods without code in CompactDexFile::method_code
+const NO_CODE: u32 = u32::MAX;
+
+/// Items concatenated into one buffer, item `i` is `data[ends[i - 1]..ends[i]]`
+#[derive(Debug, Default)]
+struct Concatenated<T> {
+    data: Vec<T>,
+    ends: Vec<u32>,
+}
+
+impl<T> Concatenated<T> {
+    fn range(&self, idx: usize) -> Range<usize> {
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] as usize };
+        start..self.ends[idx] as usize
+    }
+
+    fn get(&self, idx: usize) -> &[T] {
+        &self.data[self.range(idx)]
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn close_item(&mut self) {
+        self.ends.push(self.data.len() as u32);
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.ends.shrink_to_fit();
+    }
+}
+
+/// A dex file in the compact representation, see the module comment
+#[derive(Debug)]
+pub struct CompactDexFile {
+    pub header: DexHeader,
+    strings: String,
+    string_ends: Vec<u32>,
+    type_ids: Vec<u32>,
+    proto_shorty: Vec<u32>,
+    proto_return_type: Vec<u32>,
+    proto_parameters: Concatenated<u16>,
+    field_class: Vec<u16>,
+    field_type: Vec<u16>,
+    field_name: Vec<u32>,
+    method_class: Vec<u16>,
+    method_proto: Vec<u16>,
+    method_name: Vec<u32>,
+    pub class_defs: Vec<ClassDef>,
+    // Members of the classes, static fields before instance fields and direct methods before virtual
+    // ones. Class `i` has the fields class_fields[i]..class_fields[i + 1], its instance fields start at
+    // class_instance_fields[i], the same for methods.
+    class_fields: Vec<u32>,
+    class_instance_fields: Vec<u32>,
+    class_methods: Vec<u32>,
+    class_virtual_methods: Vec<u32>,
+    member_field_idx: Vec<u32>,
+    member_field_access: Vec<u32>,
+    member_method_idx: Vec<u32>,
+    member_method_access: Vec<u32>,
+    /// Index into the code columns, NO_CODE for abstract and native methods
+    member_method_code: Vec<u32>,
+    code_off: Vec<u32>,
+    code_registers_size: Vec<u16>,
+    code_ins_size: Vec<u16>,
+    code_outs_size: Vec<u16>,
+    code_insns: Concatenated<u16>,
+}
+
+/// A field defined by a class
+#[derive(Debug, Copy, Clone)]
+pub struct CompactField {
+    pub field_idx: u32,
+    pub access_flags: u32,
+    pub is_static: bool,
+}
+
+/// A method defined by a class
+#[derive(Debug, Copy, Clone)]
+pub struct CompactMethod<'a> {
+    pub method_idx: u32,
+    pub access_flags: u32,
+    pub is_direct: bool,
+    pub code: Option<CompactCode<'a>>,
+}
+
+/// The code item of a method, without its try blocks and debug info
+#[derive(Debug, Copy, Clone)]
+pub struct CompactCode<'a> {
+    pub code_off: u32,
+    pub registers_size: u16,
+    pub ins_size: u16,
+    pub outs_size: u16,
+    pub insns: &'a [u16],
+}
+
+impl CompactDexFile {
+    pub(crate) fn parse<R: Read + Seek + ?Sized>(reader: &mut R, parse_code: bool, diagnostics: &mut Diagnostics) -> Result<CompactDexFile, io::Error> {
+        let header = DexHeader::from_reader(reader).map_err(context(|| "header".to_owned(), 0))?;
+        if diagnostics.strictness == Strictness::Strict {
+            reader.seek(Start(0))?;
+            let checksum = DexHeader::compute_checksum(&io::read_to_end(reader)?);
+            if checksum != header.checksum {
+                diagnostics.check(8, format!("Checksum 0x{:08x} does not match the data (0x{:08x})", header.checksum, checksum))?;
+            }
+        }
+
+        // Decoded one at a time, the strings are never held as separate allocations
+        let string_ids = raw_dex::parse_string_ids(&header, reader).map_err(context(|| "string_ids".to_owned(), header.string_ids_off))?;
+        let mut strings = String::new();
+        let mut string_ends = Vec::with_capacity(string_ids.len());
+        for (idx, &off) in string_ids.iter().enumerate() {
+            let mut read = || -> Result<m_utf8::LossyString, io::Error> {
+                reader.seek(Start(off.into()))?;
+                let size = raw_dex::read_uleb128(reader)?;
+                Ok(m_utf8::to_string_lossy(&m_utf8::read_data(reader)?, size.into()))
+            };
+            let decoded = read().map_err(context(|| format!("string_data_item #{}", idx), off))?;
+            if !decoded.errors.is_empty() {
+                diagnostics.check(off.into(), format!("Invalid MUTF-8 in string #{}: {:?}", idx, decoded.errors))?;
+            }
+            strings.push_str(&decoded.string.value);
+            string_ends.push(strings.len() as u32);
+        }
+        strings.shrink_to_fit();
+        drop(string_ids);
+
+        let type_ids = raw_dex::parse_type_ids(&header, reader).map_err(context(|| "type_ids".to_owned(), header.type_ids_off))?;
+        let proto_ids = raw_dex::parse_proto_ids(&header, reader).map_err(context(|| "proto_ids".to_owned(), header.proto_ids_off))?;
+        let field_ids = raw_dex::parse_field_ids(&header, reader).map_err(context(|| "field_ids".to_owned(), header.field_ids_off))?;
+        let method_ids = raw_dex::parse_method_ids(&header, reader).map_err(context(|| "method_ids".to_owned(), header.method_ids_off))?;
+        let class_defs = raw_dex::parse_class_defs(&header, reader).map_err(context(|| "class_defs".to_owned(), header.class_defs_off))?;
+        let counts = dex_file::check_ids(&header, string_ends.len(), &type_ids, &proto_ids, &field_ids, &method_ids, &class_defs)?;
+        let mut proto_parameters = Concatenated::default();
+        for proto in &proto_ids {
+            if proto.parameters_off != 0 {
+                reader.seek(Start(proto.parameters_off.into()))?;
+                let parameters = raw_dex::parse_type_list(reader)
+                    .and_then(|it| counts.type_list(&it, proto.parameters_off).map(|_| it))
+                    .map_err(context(|| "type_list".to_owned(), proto.parameters_off))?;
+                proto_parameters.data.extend(parameters);
+            }
+            proto_parameters.close_item();
+        }
+        proto_parameters.shrink_to_fit();
+
+        let mut dex = CompactDexFile {
+            header,
+            strings,
+            string_ends,
+            type_ids,
+            proto_shorty: proto_ids.iter().map(|it| it.shorty_idx).collect(),
+            proto_return_type: proto_ids.iter().map(|it| it.return_type_idx).collect(),
+            proto_parameters,
+            field_class: field_ids.iter().map(|it| it.class_idx).collect(),
+            field_type: field_ids.iter().map(|it| it.type_idx).collect(),
+            field_name: field_ids.iter().map(|it| it.name_idx).collect(),
+            method_class: method_ids.iter().map(|it| it.class_idx).collect(),
+            method_proto: method_ids.iter().map(|it| it.proto_idx).collect(),
+            method_name: method_ids.iter().map(|it| it.name_idx).collect(),
+            class_defs: Vec::new(),
+            class_fields: vec![0],
+            class_instance_fields: Vec::new(),
+            class_methods: vec![0],
+            class_virtual_methods: Vec::new(),
+            member_field_idx: Vec::new(),
+            member_field_access: Vec::new(),
+            member_method_idx: Vec::new(),
+            member_method_access: Vec::new(),
+            member_method_code: Vec::new(),
+            code_off: Vec::new(),
+            code_registers_size: Vec::new(),
+            code_ins_size: Vec::new(),
+            code_outs_size: Vec::new(),
+            code_insns: Concatenated::default(),
+        };
+        drop((proto_ids, field_ids, method_ids));
+
+        // Index into the code columns of the code items parsed so far, code items can be shared
+        let mut code_indices = BTreeMap::new();
+        for class_def in &class_defs {
+            let off = class_def.class_data_off;
+            let class_data = if off == 0 { None } else {
+                reader.seek(Start(off.into()))?;
+                let item = ClassData::from_reader(reader)
+                    .and_then(|it| counts.class_data(&it, off).map(|_| it))
+                    .map_err(context(|| format!("class_data_item of class {}", dex.type_descriptor(class_def.class_idx)), off));
+                diagnostics.recover(item, off)?
+            };
+            let class_data = class_data.unwrap_or_else(|| ClassData {
+                static_fields: Vec::new(),
+                instance_fields: Vec::new(),
+                direct_methods: Vec::new(),
+                virtual_methods: Vec::new(),
+            });
+
+            dex.push_fields(&class_data.static_fields);
+            dex.class_instance_fields.push(dex.member_field_idx.len() as u32);
+            dex.push_fields(&class_data.instance_fields);
+            dex.class_fields.push(dex.member_field_idx.len() as u32);
+
+            let mut code = |dex: &mut CompactDexFile, method_idx: u32, code_off: u32| -> Result<u32, io::Error> {
+                if !parse_code || code_off == 0 {
+                    return Ok(NO_CODE);
+                }
+                if let Some(code) = code_indices.get(&code_off) {
+                    return Ok(*code);
+                }
+                reader.seek(Start(code_off.into()))?;
+                let item = CodeItem::from_reader(reader)
+                    .map_err(context(|| format!("code_item for method {}->{}", dex.method_class(method_idx), dex.method_name(method_idx)), code_off));
+                Ok(match diagnostics.recover(item, code_off)? {
+                    Some(item) => {
+                        let code = dex.push_code(code_off, item);
+                        code_indices.insert(code_off, code);
+                        code
+                    }
+                    None => NO_CODE,
+                })
+            };
+            for (i, methods) in [&class_data.direct_methods, &class_data.virtual_methods].iter().enumerate() {
+                if i == 1 {
+                    dex.class_virtual_methods.push(dex.member_method_idx.len() as u32);
+                }
+                for (method_idx, method) in dex_file::method_indices(methods).into_iter().zip(methods.iter()) {
+                    let code = code(&mut dex, method_idx, method.code_off as u32)?;
+                    dex.member_method_idx.push(method_idx);
+                    dex.member_method_access.push(method.access_flags as u32);
+                    dex.member_method_code.push(code);
+                }
+            }
+            dex.class_methods.push(dex.member_method_idx.len() as u32);
+        }
+        dex.class_defs = class_defs;
+        dex.shrink_to_fit();
+        Ok(dex)
+    }
+
+    fn push_fields(&mut self, fields: &[EncodedField]) {
+        self.member_field_idx.extend(dex_file::field_indices(fields));
+        self.member_field_access.extend(fields.iter().map(|it| it.access_flags as u32));
+    }
+
+    fn push_code(&mut self, code_off: u32, item: CodeItem) -> u32 {
+        self.code_off.push(code_off);
+        self.code_registers_size.push(item.registers_size);
+        self.code_ins_size.push(item.ins_size);
+        self.code_outs_size.push(item.outs_size);
+        self.code_insns.data.extend(item.insns);
+        self.code_insns.close_item();
+        self.code_off.len() as u32 - 1
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for column in [&mut self.class_fields, &mut self.class_instance_fields, &mut self.class_methods, &mut self.class_virtual_methods,
+                       &mut self.member_field_idx, &mut self.member_field_access, &mut self.member_method_idx,
+                       &mut self.member_method_access, &mut self.member_method_code, &mut self.code_off] {
+            column.shrink_to_fit();
+        }
+        self.code_registers_size.shrink_to_fit();
+        self.code_ins_size.shrink_to_fit();
+        self.code_outs_size.shrink_to_fit();
+        self.code_insns.shrink_to_fit();
+    }
+
+    pub fn strings_len(&self) -> usize {
+        self.string_ends.len()
+    }
+
+    pub fn string(&self, string_idx: u32) -> &str {
+        let idx = string_idx as usize;
+        let start = if idx == 0 { 0 } else { self.string_ends[idx - 1] as usize };
+        &self.strings[start..self.string_ends[idx] as usize]
+    }
+
+    pub fn type_descriptor(&self, type_idx: u32) -> &str {
+        self.string(self.type_ids[type_idx as usize])
+    }
+
+    pub fn proto_shorty(&self, proto_idx: u32) -> &str {
+        self.string(self.proto_shorty[proto_idx as usize])
+    }
+
+    /// Type indices of the parameters of a prototype
+    pub fn proto_parameters(&self, proto_idx: u32) -> &[u16] {
+        self.proto_parameters.get(proto_idx as usize)
+    }
+
+    /// Signature of a prototype in descriptor form, e.g. `(ILjava/lang/String;)V`
+    pub fn proto_signature(&self, proto_idx: u32) -> String {
+        let parameters: String = self.proto_parameters(proto_idx).iter().map(|it| self.type_descriptor(*it as u32)).collect();
+        format!("({}){}", parameters, self.type_descriptor(self.proto_return_type[proto_idx as usize]))
+    }
+
+    pub fn field_class(&self, field_idx: u32) -> &str {
+        self.type_descriptor(self.field_class[field_idx as usize] as u32)
+    }
+
+    pub fn field_name(&self, field_idx: u32) -> &str {
+        self.string(self.field_name[field_idx as usize])
+    }
+
+    pub fn field_type(&self, field_idx: u32) -> &str {
+        self.type_descriptor(self.field_type[field_idx as usize] as u32)
+    }
+
+    pub fn method_class(&self, method_idx: u32) -> &str {
+        self.type_descriptor(self.method_class[method_idx as usize] as u32)
+    }
+
+    pub fn method_name(&self, method_idx: u32) -> &str {
+        self.string(self.method_name[method_idx as usize])
+    }
+
+    pub fn method_signature(&self, method_idx: u32) -> String {
+        self.proto_signature(self.method_proto[method_idx as usize] as u32)
+    }
+
+    /// Fields defined by a class definition, static fields first
+    pub fn fields(&self, class_def_idx: usize) -> impl Iterator<Item = CompactField> + '_ {
+        let instance = self.class_instance_fields[class_def_idx] as usize;
+        (self.class_fields[class_def_idx] as usize..self.class_fields[class_def_idx + 1] as usize).map(move |i| CompactField {
+            field_idx: self.member_field_idx[i],
+            access_flags: self.member_field_access[i],
+            is_static: i < instance,
+        })
+    }
+
+    /// Methods defined by a class definition, direct methods first
+    pub fn methods(&self, class_def_idx: usize) -> impl Iterator<Item = CompactMethod<'_>> + '_ {
+        let virtual_start = self.class_virtual_methods[class_def_idx] as usize;
+        (self.class_methods[class_def_idx] as usize..self.class_methods[class_def_idx + 1] as usize).map(move |i| CompactMethod {
+            method_idx: self.member_method_idx[i],
+            access_flags: self.member_method_access[i],
+            is_direct: i < virtual_start,
+            code: self.code(self.member_method_code[i]),
+        })
+    }
+
+    fn code(&self, code: u32) -> Option<CompactCode<'_>> {
+        if code == NO_CODE {
+            return None;
+        }
+        let idx = code as usize;
+        Some(CompactCode {
+            code_off: self.code_off[idx],
+            registers_size: self.code_registers_size[idx],
+            ins_size: self.code_ins_size[idx],
+            outs_size: self.code_outs_size[idx],
+            insns: self.code_insns.get(idx),
+        })
+    }
+
+    /// Number of distinct code items
+    pub fn code_items_len(&self) -> usize {
+        self.code_insns.len()
+    }
+
+    /// Bytes allocated for the tables and buffers (by their capacity), to compare with other
+    /// representations
+    pub fn heap_size(&self) -> usize {
+        fn size<T>(v: &Vec<T>) -> usize {
+            v.capacity() * core::mem::size_of::<T>()
+        }
+        self.strings.capacity() + size(&self.string_ends) + size(&self.type_ids)
+            + size(&self.proto_shorty) + size(&self.proto_return_type) + size(&self.proto_parameters.data) + size(&self.proto_parameters.ends)
+            + size(&self.field_class) + size(&self.field_type) + size(&self.field_name)
+            + size(&self.method_class) + size(&self.method_proto) + size(&self.method_name)
+            + size(&self.class_defs) + size(&self.class_fields) + size(&self.class_instance_fields)
+            + size(&self.class_methods) + size(&self.class_virtual_methods)
+            + size(&self.member_field_idx) + size(&self.member_field_access)
+            + size(&self.member_method_idx) + size(&self.member_method_access) + size(&self.member_method_code)
+            + size(&self.code_off) + size(&self.code_registers_size) + size(&self.code_ins_size) + size(&self.code_outs_size)
+            + size(&self.code_insns.data) + size(&self.code_insns.ends)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex_file::{DexFile, ParseOptions};
+    use crate::fixture::{Fixture, FixtureMethod};
+    use crate::io::Cursor;
+
+    fn parse(data: &[u8]) -> CompactDexFile {
+        ParseOptions::new().parse_compact(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn same_as_dex_file() {
+        let mut abstract_method = FixtureMethod::new("run", "V", &["I", "Ljava/lang/String;"], Vec::new()).access_flags(0x401);
+        abstract_method.insns = None;
+        let fixture = Fixture::new().string("caf\u{e9} \u{1F600}")
+            .method(FixtureMethod::new("sum", "J", &["J", "J"], vec![0x04bb, 0x0010]).access_flags(0x9))
+            .method(abstract_method);
+        let data = fixture.build();
+        let (dex, compact) = (fixture.parse(), parse(&data));
+
+        assert_eq!(compact.strings_len(), dex.strings.len());
+        for idx in 0..dex.strings.len() as u32 {
+            assert_eq!(compact.string(idx), dex.string(idx));
+        }
+        for idx in 0..dex.method_ids.len() as u32 {
+            assert_eq!(compact.method_class(idx), dex.method_class(idx));
+            assert_eq!(compact.method_name(idx), dex.method_name(idx));
+            assert_eq!(compact.method_signature(idx), dex.method_signature(idx));
+        }
+        for (class_def_idx, class) in dex.classes().enumerate() {
+            let methods: Vec<_> = class.methods().iter().map(|it| {
+                let code = dex.code_item(it.encoded.code_off).map(|code| (code.registers_size, code.ins_size, code.outs_size, &code.insns[..]));
+                (it.method_idx, it.encoded.access_flags as u32, code)
+            }).collect();
+            let compact_methods: Vec<_> = compact.methods(class_def_idx).map(|it| {
+                (it.method_idx, it.access_flags, it.code.map(|code| (code.registers_size, code.ins_size, code.outs_size, code.insns)))
+            }).collect();
+            assert_eq!(compact_methods, methods);
+        }
+        assert_eq!(compact.code_items_len(), dex.code_items.len());
+    }
+
+    // Bytes allocated and not freed by this thread, to measure what a parsed file keeps
+    #[cfg(feature = "std")]
+    mod allocations {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        struct Counting;
+
+        std::thread_local! {
+            static LIVE: Cell<isize> = const { Cell::new(0) };
+        }
+
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                LIVE.with(|it| it.set(it.get() + layout.size() as isize));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                LIVE.with(|it| it.set(it.get() - layout.size() as isize));
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOCATOR: Counting = Counting;
+
+        /// Result of `f` with the bytes it allocated and did not free
+        pub fn retained<T>(f: impl FnOnce() -> T) -> (T, usize) {
+            let before = LIVE.with(Cell::get);
+            let value = f();
+            (value, (LIVE.with(Cell::get) - before) as usize)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn half_the_memory() {
+        let mut fixture = Fixture::new();
+        for i in 0..300 {
+            let insns = [vec![0x0000; 20], vec![0x000e]].concat();
+            fixture = fixture.method(FixtureMethod::new(&format!("method{}", i), "V", &["I", "Ljava/lang/String;"], insns));
+        }
+        let data = fixture.build();
+        // Once before measuring, for the allocations of the first parse only (e.g. of tracing)
+        DexFile::from_bytes(&data).unwrap();
+        parse(&data);
+        let (dex, dex_size) = allocations::retained(|| DexFile::from_bytes(&data).unwrap());
+        let (compact, compact_size) = allocations::retained(|| parse(&data));
+        assert_eq!(compact.code_items_len(), dex.code_items.len());
+        assert_eq!(compact.heap_size(), compact_size);
+        assert!(dex_size >= 2 * compact_size, "DexFile {} bytes, CompactDexFile {} bytes", dex_size, compact_size);
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Write};
+
+use clap::{Arg, Command, ValueEnum};
+
+/*
+Shell completion scripts generated from the clap definition of the commands, so they stay in sync with
+them: the names of the subcommands, their options and the values of options with a fixed set of values
+(e.g. the formats) are completed, the values of other options and the positional arguments as files.
+ */
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// An option of a command
+struct Opt {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    takes_value: bool,
+    /// Possible values, empty for options taking any value (completed as files)
+    values: Vec<String>,
+}
+
+impl Opt {
+    fn names(&self) -> Vec<String> {
+        self.long.iter().map(|it| format!("--{}", it)).chain(self.short.map(|it| format!("-{}", it))).collect()
+    }
+}
+
+/// A command or subcommand, `path` is empty for the binary and the names of the subcommands leading
+/// to it joined by spaces otherwise (e.g. `export sqlite`)
+struct Node {
+    path: String,
+    options: Vec<Opt>,
+    /// Names and first lines of the help of the subcommands
+    subcommands: Vec<(String, String)>,
+    /// Possible values of the first positional argument, empty for files, None without positional arguments
+    positional: Option<Vec<String>>,
+}
+
+fn first_line(text: Option<String>) -> String {
+    text.unwrap_or_default().lines().next().unwrap_or_default().to_string()
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values().iter().filter(|it| !it.is_hide_set()).map(|it| it.get_name().to_string()).collect()
+}
+
+fn collect(command: &Command, path: &str, nodes: &mut Vec<Node>) {
+    let options = command.get_arguments()
+        .filter(|it| !it.is_positional() && !it.is_hide_set())
+        .map(|arg| Opt {
+            long: arg.get_long().map(str::to_string),
+            short: arg.get_short(),
+            help: first_line(arg.get_help().map(ToString::to_string)),
+            takes_value: arg.get_action().takes_values(),
+            values: possible_values(arg),
+        })
+        .collect();
+    let subcommands: Vec<&Command> = command.get_subcommands().filter(|it| !it.is_hide_set()).collect();
+    nodes.push(Node {
+        path: path.to_string(),
+        options,
+        subcommands: subcommands.iter().map(|it| (it.get_name().to_string(), first_line(it.get_about().map(ToString::to_string)))).collect(),
+        positional: command.get_arguments().find(|it| it.is_positional()).map(possible_values),
+    });
+    // The help subcommands repeat the command tree, only their names are completed
+    for subcommand in subcommands.into_iter().filter(|it| it.get_name() != "help") {
+        let path = if path.is_empty() { subcommand.get_name().to_string() } else { format!("{} {}", path, subcommand.get_name()) };
+        collect(subcommand, &path, nodes);
+    }
+}
+
+/// Quotes for the single quoted strings of sh
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// `case` patterns of the words following the subcommands of a node, by the node before the word:
+/// `":dump"` for the dump command, `"export:sqlite"` for its subcommand
+fn subcommand_patterns(nodes: &[Node]) -> String {
+    nodes.iter()
+        .flat_map(|node| node.subcommands.iter().map(move |(name, _)| format!("\"{}:{}\"", node.path, name)))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Writes the loop of bash and zsh determining the subcommand path in `cmd` from the words before the
+/// current one, `first` is the index of the first word after the name of the binary
+fn write_sh_subcommand_loop(nodes: &[Node], words: &str, first: u32, current: &str, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "    for ((i = {}; i < {}; i++)); do", first, current)?;
+    writeln!(out, "        word=\"${{{}[i]}}\"", words)?;
+    writeln!(out, "        case \"$cmd:$word\" in")?;
+    writeln!(out, "            {}) cmd=\"${{cmd:+$cmd }}$word\" ;;", subcommand_patterns(nodes))?;
+    writeln!(out, "        esac")?;
+    writeln!(out, "    done")
+}
+
+fn write_bash(name: &str, nodes: &[Node], out: &mut dyn Write) -> io::Result<()> {
+    let function = format!("_{}", name.replace('-', "_"));
+    writeln!(out, "{}() {{", function)?;
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(out, "    local cmd=\"\" word i opts=\"\" subcommands=\"\" positional=\"\" values=\"\"")?;
+    write_sh_subcommand_loop(nodes, "COMP_WORDS", 1, "COMP_CWORD", out)?;
+    writeln!(out, "    case \"$cmd:$prev\" in")?;
+    for node in nodes {
+        for opt in node.options.iter().filter(|it| it.takes_value) {
+            let patterns: Vec<String> = opt.names().iter().map(|it| format!("\"{}:{}\"", node.path, it)).collect();
+            let reply = if opt.values.is_empty() {
+                "$(compgen -f -- \"$cur\")".to_string()
+            } else {
+                format!("$(compgen -W {} -- \"$cur\")", sh_quote(&opt.values.join(" ")))
+            };
+            writeln!(out, "        {}) COMPREPLY=({}); return ;;", patterns.join("|"), reply)?;
+        }
+    }
+    writeln!(out, "    esac")?;
+    writeln!(out, "    case \"$cmd\" in")?;
+    for node in nodes {
+        let opts: Vec<String> = node.options.iter().flat_map(Opt::names).collect();
+        let subcommands: Vec<&str> = node.subcommands.iter().map(|(name, _)| name.as_str()).collect();
+        let values = node.positional.as_ref().map_or(String::new(), |it| it.join(" "));
+        writeln!(out, "        \"{}\") opts={} subcommands={} positional={} values={} ;;", node.path, sh_quote(&opts.join(" ")),
+                 sh_quote(&subcommands.join(" ")), if node.positional.is_some() { "1" } else { "\"\"" }, sh_quote(&values))?;
+    }
+    writeln!(out, "    esac")?;
+    writeln!(out, "    if [[ $cur == -* ]]; then")?;
+    writeln!(out, "        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))")?;
+    writeln!(out, "    else")?;
+    writeln!(out, "        COMPREPLY=($(compgen -W \"$subcommands $values\" -- \"$cur\"))")?;
+    writeln!(out, "        [[ -n $positional && -z $values ]] && COMPREPLY+=($(compgen -f -- \"$cur\"))")?;
+    writeln!(out, "    fi")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "complete -F {} -o filenames {}", function, name)
+}
+
+/// Entry of _describe, colons in the name are escaped
+fn zsh_describe(name: &str, help: &str) -> String {
+    sh_quote(&format!("{}:{}", name.replace(':', "\\:"), help))
+}
+
+fn write_zsh(name: &str, nodes: &[Node], out: &mut dyn Write) -> io::Result<()> {
+    let function = format!("_{}", name.replace('-', "_"));
+    writeln!(out, "#compdef {}", name)?;
+    writeln!(out)?;
+    writeln!(out, "{}() {{", function)?;
+    writeln!(out, "    local cmd=\"\" word i prev=\"${{words[CURRENT-1]}}\" positional=\"\"")?;
+    writeln!(out, "    local -a options subcommands values")?;
+    write_sh_subcommand_loop(nodes, "words", 2, "CURRENT", out)?;
+    writeln!(out, "    case \"$cmd:$prev\" in")?;
+    for node in nodes {
+        for opt in node.options.iter().filter(|it| it.takes_value) {
+            let patterns: Vec<String> = opt.names().iter().map(|it| format!("\"{}:{}\"", node.path, it)).collect();
+            let completion = if opt.values.is_empty() {
+                "_files".to_string()
+            } else {
+                format!("compadd -- {}", opt.values.iter().map(|it| sh_quote(it)).collect::<Vec<_>>().join(" "))
+            };
+            writeln!(out, "        {}) {}; return ;;", patterns.join("|"), completion)?;
+        }
+    }
+    writeln!(out, "    esac")?;
+    writeln!(out, "    case \"$cmd\" in")?;
+    for node in nodes {
+        let options: Vec<String> = node.options.iter()
+            .flat_map(|opt| opt.names().into_iter().map(move |it| zsh_describe(&it, &opt.help)))
+            .collect();
+        let subcommands: Vec<String> = node.subcommands.iter().map(|(name, help)| zsh_describe(name, help)).collect();
+        writeln!(out, "        \"{}\")", node.path)?;
+        writeln!(out, "            options=({})", options.join(" "))?;
+        writeln!(out, "            subcommands=({})", subcommands.join(" "))?;
+        let values: Vec<String> = node.positional.iter().flatten().map(|it| sh_quote(it)).collect();
+        writeln!(out, "            values=({})", values.join(" "))?;
+        writeln!(out, "            positional={} ;;", if node.positional.is_some() { "1" } else { "\"\"" })?;
+    }
+    writeln!(out, "    esac")?;
+    writeln!(out, "    if [[ ${{words[CURRENT]}} == -* ]]; then")?;
+    writeln!(out, "        _describe 'option' options")?;
+    writeln!(out, "    else")?;
+    writeln!(out, "        (( ${{#subcommands}} )) && _describe 'command' subcommands")?;
+    writeln!(out, "        (( ${{#values}} )) && compadd -- $values")?;
+    writeln!(out, "        [[ -n $positional ]] && (( ! ${{#values}} )) && _files")?;
+    writeln!(out, "    fi")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "if [ \"$funcstack[1]\" = \"{}\" ]; then", function)?;
+    writeln!(out, "    {} \"$@\"", function)?;
+    writeln!(out, "else")?;
+    writeln!(out, "    compdef {} {}", function, name)?;
+    writeln!(out, "fi")
+}
+
+/// Quotes for the single quoted strings of fish
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn write_fish(name: &str, nodes: &[Node], out: &mut dyn Write) -> io::Result<()> {
+    for node in nodes {
+        // Subcommand names are distinct, the last one identifies the node
+        let (condition, nested) = match node.path.rsplit(' ').next().filter(|it| !it.is_empty()) {
+            Some(last) => (format!("__fish_seen_subcommand_from {}", last), true),
+            None => ("__fish_use_subcommand".to_string(), false),
+        };
+        let subcommand_condition = if nested && !node.subcommands.is_empty() {
+            let names: Vec<&str> = node.subcommands.iter().map(|(name, _)| name.as_str()).collect();
+            format!("{}; and not __fish_seen_subcommand_from {}", condition, names.join(" "))
+        } else {
+            condition.clone()
+        };
+        for (subcommand, help) in &node.subcommands {
+            writeln!(out, "complete -c {} -n {} -f -a {} -d {}", name, fish_quote(&subcommand_condition), fish_quote(subcommand), fish_quote(help))?;
+        }
+        for opt in &node.options {
+            let mut line = format!("complete -c {} -n {}", name, fish_quote(&condition));
+            if let Some(long) = &opt.long {
+                line.push_str(&format!(" -l {}", long));
+            }
+            if let Some(short) = opt.short {
+                line.push_str(&format!(" -s {}", short));
+            }
+            if !opt.values.is_empty() {
+                line.push_str(&format!(" -x -a {}", fish_quote(&opt.values.join(" "))));
+            } else if opt.takes_value {
+                line.push_str(" -r -F");
+            }
+            if !opt.help.is_empty() {
+                line.push_str(&format!(" -d {}", fish_quote(&opt.help)));
+            }
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the completion script of `shell` for the commands of `command`
+pub fn write(shell: Shell, mut command: Command, out: &mut dyn Write) -> io::Result<()> {
+    // Adds the help subcommand and options and propagates the global options to the subcommands
+    command.build();
+    let name = command.get_name().to_string();
+    let mut nodes = Vec::new();
+    collect(&command, "", &mut nodes);
+    match shell {
+        Shell::Bash => write_bash(&name, &nodes, out),
+        Shell::Zsh => write_zsh(&name, &nodes, out),
+        Shell::Fish => write_fish(&name, &nodes, out),
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Command;
+use toml::{Table, Value};
+
+/*
+Defaults of the command line options from dex_tool.toml files, read from the user configuration
+directory ($XDG_CONFIG_HOME or ~/.config) and from the current directory or the nearest parent having
+one, which overrides the user configuration. Options given on the command line override both.
+
+    # Default --format of the commands supporting it, e.g. csv for the listings or json for stats
+    format = "csv"
+    # false for --no-color and --no-pager
+    color = false
+    pager = false
+    # Default --strictness of the parser: lenient, normal or strict
+    strictness = "lenient"
+ */
+
+pub const FILE_NAME: &str = "dex_tool.toml";
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub format: Option<String>,
+    pub color: Option<bool>,
+    pub pager: Option<bool>,
+    pub strictness: Option<String>,
+}
+
+fn string(path: &Path, key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value),
+        value => Err(format!("{}: {} must be a string, not {}", path.display(), key, value.type_str())),
+    }
+}
+
+fn boolean(path: &Path, key: &str, value: Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(value) => Ok(value),
+        value => Err(format!("{}: {} must be a boolean, not {}", path.display(), key, value.type_str())),
+    }
+}
+
+/// The user configuration file and the project configuration file, in the order they are applied
+fn paths() -> Vec<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|it| PathBuf::from(it).join(".config")));
+    let project = std::env::current_dir().ok()
+        .and_then(|dir| dir.ancestors().map(|it| it.join(FILE_NAME)).find(|it| it.is_file()));
+    config_dir.map(|it| it.join(FILE_NAME)).filter(|it| it.is_file()).into_iter().chain(project).collect()
+}
+
+impl Config {
+    /// Reads the configuration files, values of later files override earlier ones
+    pub fn load() -> Result<Config, String> {
+        let mut config = Config::default();
+        for path in paths() {
+            config.read(&path)?;
+        }
+        Ok(config)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let table: Table = text.parse().map_err(|err| format!("{}: {}", path.display(), err))?;
+        for (key, value) in table {
+            match key.as_str() {
+                "format" => self.format = Some(string(path, &key, value)?),
+                "color" => self.color = Some(boolean(path, &key, value)?),
+                "pager" => self.pager = Some(boolean(path, &key, value)?),
+                "strictness" => self.strictness = Some(string(path, &key, value)?),
+                _ => return Err(format!("{}: unknown key {}", path.display(), key)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the configured values as defaults of the options of `command`: the format of each
+    /// subcommand supporting it and the strictness
+    pub fn apply_defaults(&self, mut command: Command) -> Result<Command, String> {
+        if let Some(strictness) = &self.strictness {
+            let valid = command.get_arguments()
+                .any(|arg| arg.get_id() == "strictness" && arg.get_possible_values().iter().any(|it| it.matches(strictness, false)));
+            if !valid {
+                return Err(format!("strictness {} is not one of lenient, normal or strict", strictness));
+            }
+            let strictness: &'static str = Box::leak(strictness.clone().into_boxed_str());
+            command = command.mut_arg("strictness", |arg| arg.default_value(strictness));
+        }
+        let format: &'static str = match &self.format {
+            Some(format) => Box::leak(format.clone().into_boxed_str()),
+            None => return Ok(command),
+        };
+        let supported: Vec<String> = command.get_subcommands()
+            .filter(|subcommand| subcommand.get_arguments().any(|arg| {
+                arg.get_id() == "format" && arg.get_possible_values().iter().any(|it| it.matches(format, false))
+            }))
+            .map(|it| it.get_name().to_string())
+            .collect();
+        if supported.is_empty() {
+            return Err(format!("format {} is not supported by any command", format));
+        }
+        for name in supported {
+            command = command.mut_subcommand(name, |subcommand| subcommand.mut_arg("format", |arg| arg.default_value(format)));
+        }
+        Ok(command)
+    }
+}
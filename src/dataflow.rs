@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cfg::ControlFlowGraph;
+use crate::instructions::Instruction;
+use crate::raw_dex::CodeItem;
+
+/*
+Dataflow analyses over the control flow graph of a method. An analysis defines its facts (elements
+of a join semilattice), the direction and the transfer function of the instructions; solve iterates
+the facts to a fixed point. Exception handlers are entered from every instruction of the blocks they
+cover, as any of them may throw.
+Constant propagation, reaching definitions and liveness of registers are provided on top of it.
+ */
+
+/// Elements of a join semilattice
+pub trait Lattice: Clone + PartialEq {
+    /// Least upper bound of two elements
+    fn join(&self, other: &Self) -> Self;
+}
+
+/// Sets ordered by inclusion, joined by union
+impl<T: Ord + Clone> Lattice for BTreeSet<T> {
+    fn join(&self, other: &Self) -> Self {
+        self.union(other).cloned().collect()
+    }
+}
+
+/// Per-register facts, joined register by register
+impl<L: Lattice> Lattice for Vec<L> {
+    fn join(&self, other: &Self) -> Self {
+        self.iter().zip(other).map(|(a, b)| a.join(b)).collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// Facts flow from the entry along the edges, e.g. reaching definitions
+    Forward,
+    /// Facts flow from the exits against the edges, e.g. liveness
+    Backward,
+}
+
+pub trait Analysis {
+    type Fact: Lattice;
+
+    fn direction(&self) -> Direction;
+
+    /// Fact at the entry (forward) or at the exits (backward) of the method
+    fn boundary(&self) -> Self::Fact;
+
+    /// Initial fact of all other points, the bottom of the lattice
+    fn bottom(&self) -> Self::Fact;
+
+    /// Applies an instruction to `fact`, the fact before it (forward) or after it (backward)
+    fn transfer(&self, fact: &mut Self::Fact, pc: usize, insn: &Instruction);
+}
+
+/// Facts before and after each instruction (in execution order, regardless of the direction), by pc
+#[derive(Debug, Clone)]
+pub struct Results<F> {
+    pub before: BTreeMap<usize, F>,
+    pub after: BTreeMap<usize, F>,
+}
+
+/// Iterates the facts of an analysis to a fixed point
+pub fn solve<A: Analysis>(analysis: &A, cfg: &ControlFlowGraph) -> Results<A::Fact> {
+    match analysis.direction() {
+        Direction::Forward => solve_forward(analysis, cfg),
+        Direction::Backward => solve_backward(analysis, cfg),
+    }
+}
+
+fn solve_forward<A: Analysis>(analysis: &A, cfg: &ControlFlowGraph) -> Results<A::Fact> {
+    let n = cfg.blocks.len();
+    let mut entries = vec![analysis.bottom(); n];
+    if let Some(entry) = entries.first_mut() {
+        *entry = analysis.boundary();
+    }
+    // Facts after the last instruction and the join of the facts before each instruction (for handlers)
+    let run_block = |block: usize, entry: &A::Fact, results: Option<&mut Results<A::Fact>>| {
+        let mut fact = entry.clone();
+        let mut throwing = analysis.bottom();
+        let mut results = results;
+        for (pc, insn) in cfg.block_instructions(block) {
+            throwing = throwing.join(&fact);
+            if let Some(results) = results.as_mut() {
+                results.before.insert(*pc, fact.clone());
+            }
+            analysis.transfer(&mut fact, *pc, insn);
+            if let Some(results) = results.as_mut() {
+                results.after.insert(*pc, fact.clone());
+            }
+        }
+        (fact, throwing)
+    };
+
+    let mut worklist: BTreeSet<usize> = (0..n).collect();
+    while let Some(block) = worklist.pop_first() {
+        let (exit, throwing) = run_block(block, &entries[block], None);
+        let edges = cfg.blocks[block].successors.iter().map(|it| (*it, &exit))
+            .chain(cfg.blocks[block].exception_successors.iter().map(|it| (*it, &throwing)));
+        for (successor, fact) in edges {
+            let joined = entries[successor].join(fact);
+            if joined != entries[successor] {
+                entries[successor] = joined;
+                worklist.insert(successor);
+            }
+        }
+    }
+
+    let mut results = Results { before: BTreeMap::new(), after: BTreeMap::new() };
+    for (block, entry) in entries.iter().enumerate() {
+        run_block(block, entry, Some(&mut results));
+    }
+    results
+}
+
+fn solve_backward<A: Analysis>(analysis: &A, cfg: &ControlFlowGraph) -> Results<A::Fact> {
+    let n = cfg.blocks.len();
+    let mut entries = vec![analysis.bottom(); n];
+    // Facts before the first instruction of a block, from the facts at the entries of its successors
+    let run_block = |block: usize, entries: &[A::Fact], results: Option<&mut Results<A::Fact>>| {
+        let blocks = &cfg.blocks[block];
+        let mut fact = if blocks.successors.is_empty() && blocks.exception_successors.is_empty() {
+            analysis.boundary()
+        } else {
+            blocks.successors.iter().fold(analysis.bottom(), |fact, it| fact.join(&entries[*it]))
+        };
+        let handlers = blocks.exception_successors.iter().fold(analysis.bottom(), |fact, it| fact.join(&entries[*it]));
+        let mut results = results;
+        for (pc, insn) in cfg.block_instructions(block).iter().rev() {
+            fact = fact.join(&handlers);
+            if let Some(results) = results.as_mut() {
+                results.after.insert(*pc, fact.clone());
+            }
+            analysis.transfer(&mut fact, *pc, insn);
+            if let Some(results) = results.as_mut() {
+                results.before.insert(*pc, fact.clone());
+            }
+        }
+        fact
+    };
+
+    let mut worklist: BTreeSet<usize> = (0..n).collect();
+    while let Some(block) = worklist.pop_last() {
+        let entry = run_block(block, &entries, None);
+        if entry != entries[block] {
+            entries[block] = entry;
+            worklist.extend(&cfg.blocks[block].predecessors);
+        }
+    }
+
+    let mut results = Results { before: BTreeMap::new(), after: BTreeMap::new() };
+    for block in 0..n {
+        run_block(block, &entries, Some(&mut results));
+    }
+    results
+}
+
+/// Value of a register in constant propagation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Constant {
+    /// Not written on any path yet (bottom)
+    Undefined,
+    /// The same value on all paths, the raw 32 or 64 bits of the constant (sign extended)
+    Value(i64),
+    /// Different or unknown values (top)
+    Varying,
+}
+
+impl Lattice for Constant {
+    fn join(&self, other: &Self) -> Self {
+        match (*self, *other) {
+            (Constant::Undefined, it) | (it, Constant::Undefined) => it,
+            (a, b) if a == b => a,
+            _ => Constant::Varying,
+        }
+    }
+}
+
+/// Forward analysis of the constant values of the registers. Constants are folded through moves and
+/// integer arithmetic of ints, everything else is Varying.
+#[derive(Debug)]
+pub struct ConstantPropagation {
+    pub registers_size: u16,
+    pub ins_size: u16,
+}
+
+impl ConstantPropagation {
+    pub fn new(code: &CodeItem) -> ConstantPropagation {
+        ConstantPropagation { registers_size: code.registers_size, ins_size: code.ins_size }
+    }
+}
+
+/// Folds an int operation named like add-int or add-int/lit8
+fn fold_int(name: &str, a: i32, b: i32) -> Option<i32> {
+    match name.split('-').next().unwrap_or_default() {
+        "add" => Some(a.wrapping_add(b)),
+        "sub" => Some(a.wrapping_sub(b)),
+        "rsub" => Some(b.wrapping_sub(a)),
+        "mul" => Some(a.wrapping_mul(b)),
+        "div" => a.checked_div(b),
+        "rem" => a.checked_rem(b),
+        "and" => Some(a & b),
+        "or" => Some(a | b),
+        "xor" => Some(a ^ b),
+        "shl" => Some(a.wrapping_shl(b as u32)),
+        "shr" => Some(a.wrapping_shr(b as u32)),
+        "ushr" => Some((a as u32).wrapping_shr(b as u32) as i32),
+        _ => None,
+    }
+}
+
+impl Analysis for ConstantPropagation {
+    type Fact = Vec<Constant>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    /// Parameters are unknown
+    fn boundary(&self) -> Vec<Constant> {
+        let first_param = self.registers_size.saturating_sub(self.ins_size) as usize;
+        (0..self.registers_size as usize)
+            .map(|it| if it < first_param { Constant::Undefined } else { Constant::Varying })
+            .collect()
+    }
+
+    fn bottom(&self) -> Vec<Constant> {
+        vec![Constant::Undefined; self.registers_size as usize]
+    }
+
+    fn transfer(&self, fact: &mut Vec<Constant>, _pc: usize, insn: &Instruction) {
+        let get = |fact: &Vec<Constant>, register: u32| fact.get(register as usize).copied().unwrap_or(Constant::Varying);
+        let int = |constant: Constant| match constant {
+            Constant::Value(value) => Some(value as i32),
+            _ => None,
+        };
+        let folded = match insn.opcode {
+            0x01..=0x09 => Some(get(fact, insn.b)),
+            0x12..=0x19 => Some(Constant::Value(match insn.opcode {
+                0x15 => (insn.b << 16) as i32 as i64,
+                0x18 => insn.wide_b as i64,
+                0x19 => ((insn.b as u64) << 48) as i64,
+                _ => insn.b as i32 as i64,
+            })),
+            0x90..=0x9a | 0xb0..=0xba | 0xd0..=0xe2 => {
+                let (left, right) = match insn.opcode {
+                    0x90..=0x9a => (get(fact, insn.b), get(fact, insn.c)),
+                    0xb0..=0xba => (get(fact, insn.a), get(fact, insn.b)),
+                    _ => (get(fact, insn.b), Constant::Value(insn.c as i32 as i64)),
+                };
+                let folded = int(left).zip(int(right)).and_then(|(a, b)| fold_int(insn.name(), a, b));
+                Some(folded.map_or(Constant::Varying, |it| Constant::Value(it as i64)))
+            }
+            _ => None,
+        };
+        for register in insn.defined_registers() {
+            if let Some(slot) = fact.get_mut(register as usize) {
+                *slot = Constant::Varying;
+            }
+        }
+        if let (Some(value), Some(slot)) = (folded, fact.get_mut(insn.a as usize)) {
+            if insn.writes_a() {
+                *slot = value;
+            }
+        }
+    }
+}
+
+/// Definition of a register reaching an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Definition {
+    pub register: u32,
+    /// pc of the defining instruction, None for the parameters defined at the entry
+    pub pc: Option<usize>,
+}
+
+/// Forward analysis of the definitions of registers that reach each instruction
+#[derive(Debug)]
+pub struct ReachingDefinitions {
+    pub registers_size: u16,
+    pub ins_size: u16,
+}
+
+impl ReachingDefinitions {
+    pub fn new(code: &CodeItem) -> ReachingDefinitions {
+        ReachingDefinitions { registers_size: code.registers_size, ins_size: code.ins_size }
+    }
+}
+
+impl Analysis for ReachingDefinitions {
+    type Fact = BTreeSet<Definition>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self) -> BTreeSet<Definition> {
+        let first_param = self.registers_size.saturating_sub(self.ins_size) as u32;
+        (first_param..self.registers_size as u32).map(|register| Definition { register, pc: None }).collect()
+    }
+
+    fn bottom(&self) -> BTreeSet<Definition> {
+        BTreeSet::new()
+    }
+
+    fn transfer(&self, fact: &mut BTreeSet<Definition>, pc: usize, insn: &Instruction) {
+        for register in insn.defined_registers() {
+            fact.retain(|it| it.register != register);
+            fact.insert(Definition { register, pc: Some(pc) });
+        }
+    }
+}
+
+/// Backward analysis of the registers whose current value is read later
+#[derive(Debug, Default)]
+pub struct Liveness;
+
+impl Analysis for Liveness {
+    type Fact = BTreeSet<u32>;
+
+    fn direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn boundary(&self) -> BTreeSet<u32> {
+        BTreeSet::new()
+    }
+
+    fn bottom(&self) -> BTreeSet<u32> {
+        BTreeSet::new()
+    }
+
+    fn transfer(&self, fact: &mut BTreeSet<u32>, _pc: usize, insn: &Instruction) {
+        for register in insn.defined_registers() {
+            fact.remove(&register);
+        }
+        fact.extend(insn.used_registers());
+    }
+}
@@ -0,0 +1,556 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::cfg::ControlFlowGraph;
+use crate::dex_file::{self, DexFile, ACC_STATIC, NO_INDEX};
+use crate::dexdump::descriptor_to_dot;
+use crate::instructions::{Format, IndexType, Instruction, Payload};
+use crate::kotlin::Owned;
+use crate::raw_dex::{CodeItem, EncodedMethod};
+use crate::types::{self, RegType, RegisterTypes};
+use crate::verifier;
+
+/*
+Experimental decompiler lifting simple methods to pseudo-Java.
+Only acyclic methods without try blocks and switches are supported, everything else is left as a comment.
+Register types are only inferred to compare references with null, so the output is meant for triage rather
+than recompilation.
+ */
+
+/// Upper bound of emitted statements per method, as unstructured branches duplicate shared code
+const MAX_STATEMENTS: usize = 2000;
+
+const JAVA_MODIFIERS: [(u32, &str); 9] = [
+    (0x1, "public"), (0x2, "private"), (0x4, "protected"), (0x8, "static"), (0x10, "final"),
+    (0x20, "synchronized"), (0x100, "native"), (0x400, "abstract"), (0x800, "strictfp"),
+];
+
+/// Reason why a method could not be decompiled
+#[derive(Debug)]
+pub enum Unsupported {
+    InvalidCode(String),
+    Loops,
+    TryBlocks,
+    Switches,
+    Instruction(&'static str),
+    TooComplex,
+}
+
+impl std::error::Error for Unsupported {}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unsupported::InvalidCode(err) => write!(f, "Invalid code: {}", err),
+            Unsupported::Loops => write!(f, "Loops are not supported"),
+            Unsupported::TryBlocks => write!(f, "Try blocks are not supported"),
+            Unsupported::Switches => write!(f, "Switches are not supported"),
+            Unsupported::Instruction(name) => write!(f, "Unsupported instruction {}", name),
+            Unsupported::TooComplex => write!(f, "Control flow is too complex"),
+        }
+    }
+}
+
+fn modifiers(flags: u32) -> String {
+    JAVA_MODIFIERS.iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| format!("{} ", name))
+        .collect()
+}
+
+/// Java source name of a descriptor, without the package for classes in java.lang
+fn java_type(descriptor: &str) -> String {
+    let dotted = descriptor_to_dot(descriptor);
+    match dotted.strip_prefix("java.lang.") {
+        Some(name) if !name.contains('.') => name.to_string(),
+        _ => dotted,
+    }
+}
+
+fn escape_string(s: &str) -> String {
+    s.chars().flat_map(char::escape_default).collect()
+}
+
+/// Writes a pseudo-Java rendition of a class, with all decompilable method bodies
+pub fn write_class(dex: &DexFile, idx: usize, out: &mut dyn Write) -> std::io::Result<()> {
+    let class_def = &dex.class_defs[idx];
+    let class = dex.class(idx).expect("Invalid class_def index");
+    if let Some(owned) = class.kotlin_owner() {
+        writeln!(out, "// Kotlin {} of {}", owned.construct, java_type(&owned.owner))?;
+    }
+    let kind = if class_def.access_flags & 0x200 != 0 { "interface" } else { "class" };
+    // Interfaces are implicitly abstract
+    let flags = if kind == "interface" { class_def.access_flags & !0x400 } else { class_def.access_flags };
+    write!(out, "{}{} {}", modifiers(flags & !0x20), kind, java_type(dex.type_descriptor(class_def.class_idx)))?;
+    if class_def.superclass_idx != NO_INDEX && dex.type_descriptor(class_def.superclass_idx) != "Ljava/lang/Object;" {
+        write!(out, " extends {}", java_type(dex.type_descriptor(class_def.superclass_idx)))?;
+    }
+    let interfaces: Vec<String> = dex.type_list(class_def.interfaces_off).iter()
+        .map(|it| java_type(dex.type_descriptor(*it as u32)))
+        .collect();
+    if !interfaces.is_empty() {
+        write!(out, " implements {}", interfaces.join(", "))?;
+    }
+    writeln!(out, " {{")?;
+
+    if let Some(class_data) = &dex.class_data[idx] {
+        for fields in [&class_data.static_fields, &class_data.instance_fields] {
+            for (field, field_idx) in fields.iter().zip(dex_file::field_indices(fields)) {
+                writeln!(out, "    {}{} {};", modifiers(field.access_flags as u32),
+                         java_type(dex.field_type(field_idx)), dex.field_name(field_idx))?;
+            }
+        }
+        let kotlin: HashMap<u32, Owned> = class.methods().iter()
+            .filter_map(|it| Some((it.method_idx, it.kotlin_owner()?)))
+            .collect();
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            for (method, method_idx) in methods.iter().zip(dex_file::method_indices(methods)) {
+                writeln!(out)?;
+                if let Some(owned) = kotlin.get(&method_idx) {
+                    writeln!(out, "    // Kotlin {} of {}", owned.construct, owned.owner)?;
+                }
+                write_method(dex, method_idx, method, out)?;
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
+
+fn write_method(dex: &DexFile, method_idx: u32, method: &EncodedMethod, out: &mut dyn Write) -> std::io::Result<()> {
+    let code = dex.code_item(method.code_off);
+    let names = code.map(|code| register_names(dex, method_idx, method, code)).unwrap_or_default();
+
+    let proto_idx = dex.method_ids[method_idx as usize].proto_idx as u32;
+    let mut reg = code.map_or(0, |code| code.registers_size.saturating_sub(code.ins_size) as u32);
+    if method.access_flags & ACC_STATIC == 0 {
+        reg += 1;
+    }
+    let mut parameters = Vec::new();
+    for (i, descriptor) in dex.proto_parameters(proto_idx).into_iter().enumerate() {
+        let name = names.get(&reg).cloned().unwrap_or_else(|| format!("arg{}", i));
+        parameters.push(format!("{} {}", java_type(descriptor), name));
+        reg += if descriptor == "J" || descriptor == "D" { 2 } else { 1 };
+    }
+
+    let name = dex.method_name(method_idx);
+    let class_name = java_type(dex.method_class(method_idx));
+    let flags = modifiers(method.access_flags as u32);
+    if name == "<clinit>" {
+        write!(out, "    static")?;
+    } else if name == "<init>" {
+        write!(out, "    {}{}({})", flags, class_name.rsplit('.').next().unwrap_or(&class_name), parameters.join(", "))?;
+    } else {
+        let return_type = dex.type_descriptor(dex.proto_ids[proto_idx as usize].return_type_idx);
+        write!(out, "    {}{} {}({})", flags, java_type(return_type), name, parameters.join(", "))?;
+    }
+
+    let code = match code {
+        Some(code) => code,
+        None => return writeln!(out, ";"),
+    };
+    writeln!(out, " {{")?;
+    match decompile_method(dex, method_idx, method, code) {
+        Ok(lines) => for line in lines {
+            writeln!(out, "        {}", line)?;
+        },
+        Err(err) => writeln!(out, "        // Could not decompile: {}", err)?,
+    }
+    writeln!(out, "    }}")
+}
+
+/// Names of the parameter registers: `this`, the parameter names of the debug info or `pN`
+fn register_names(dex: &DexFile, method_idx: u32, method: &EncodedMethod, code: &CodeItem) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    let first_param = code.registers_size.saturating_sub(code.ins_size) as u32;
+    for reg in first_param..code.registers_size as u32 {
+        names.insert(reg, format!("p{}", reg - first_param));
+    }
+    let mut reg = first_param;
+    if method.access_flags & ACC_STATIC == 0 {
+        names.insert(reg, String::from("this"));
+        reg += 1;
+    }
+    let debug_info = dex.debug_info.get(&code.debug_info_off).filter(|_| code.debug_info_off != 0);
+    let proto_idx = dex.method_ids[method_idx as usize].proto_idx as u32;
+    for (i, descriptor) in dex.proto_parameters(proto_idx).into_iter().enumerate() {
+        if let Some(name_idx) = debug_info.and_then(|it| it.parameter_names.get(i)).and_then(|it| it.get()) {
+            names.insert(reg, dex.string(name_idx).to_string());
+        }
+        reg += if descriptor == "J" || descriptor == "D" { 2 } else { 1 };
+    }
+    names
+}
+
+/// Decompiles the body of a method into lines of pseudo-Java (indented by 4 spaces per level)
+pub fn decompile_method(dex: &DexFile, method_idx: u32, method: &EncodedMethod, code: &CodeItem) -> Result<Vec<String>, Unsupported> {
+    if !code.tries.is_empty() {
+        return Err(Unsupported::TryBlocks);
+    }
+    // Lifting indexes the registers and id tables by the operands
+    if let Some((pc, problem)) = verifier::structural_problems(dex, code).first() {
+        return Err(Unsupported::InvalidCode(format!("0x{:04x}: {}", pc, problem)));
+    }
+    let cfg = ControlFlowGraph::build(code).map_err(|err| Unsupported::InvalidCode(err.to_string()))?;
+    if cfg.instructions.iter().any(|(_, insn)| insn.opcode == 0x2b || insn.opcode == 0x2c) {
+        return Err(Unsupported::Switches);
+    }
+    if cfg.has_loops() {
+        return Err(Unsupported::Loops);
+    }
+    let mut decompiler = Decompiler {
+        dex,
+        code,
+        cfg: &cfg,
+        class_idx: dex.method_ids[method_idx as usize].class_idx as u32,
+        names: register_names(dex, method_idx, method, code),
+        post_dominators: cfg.immediate_post_dominators(),
+        types: types::infer(dex, method_idx, method.access_flags, code, &cfg),
+        uninitialized: HashMap::new(),
+        lines: Vec::new(),
+    };
+    if !cfg.blocks.is_empty() {
+        decompiler.region(0, None, 0)?;
+    }
+    // The implicit return at the end of void methods
+    if decompiler.lines.last().is_some_and(|it| it == "return;") {
+        decompiler.lines.pop();
+    }
+    Ok(decompiler.lines)
+}
+
+struct Decompiler<'a> {
+    dex: &'a DexFile,
+    code: &'a CodeItem,
+    cfg: &'a ControlFlowGraph,
+    class_idx: u32,
+    names: HashMap<u32, String>,
+    post_dominators: Vec<Option<usize>>,
+    types: RegisterTypes<'a>,
+    /// Registers holding the result of new-instance whose constructor was not invoked yet
+    uninitialized: HashMap<u32, u32>,
+    lines: Vec<String>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn reg(&self, reg: u32) -> String {
+        self.names.get(&reg).cloned().unwrap_or_else(|| format!("v{}", reg))
+    }
+
+    fn emit(&mut self, depth: usize, line: String) -> Result<(), Unsupported> {
+        if self.lines.len() >= MAX_STATEMENTS {
+            return Err(Unsupported::TooComplex);
+        }
+        self.lines.push(format!("{}{}", "    ".repeat(depth), line));
+        Ok(())
+    }
+
+    /// Emits the blocks from `block` up to (excluding) `stop`, nesting the branches in between
+    fn region(&mut self, block: usize, stop: Option<usize>, depth: usize) -> Result<(), Unsupported> {
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if Some(block) == stop {
+                break;
+            }
+            let (statements, condition) = self.lift_block(block)?;
+            for statement in statements {
+                self.emit(depth, statement)?;
+            }
+            let successors = self.cfg.blocks[block].successors.clone();
+            current = match (condition, successors.as_slice()) {
+                (Some(condition), [target, fall_through]) => {
+                    let join = self.post_dominators[block];
+                    if Some(*target) == join {
+                        self.emit(depth, format!("if ({}) {{", condition.negate()))?;
+                        self.region(*fall_through, join, depth + 1)?;
+                    } else if Some(*fall_through) == join {
+                        self.emit(depth, format!("if ({}) {{", condition))?;
+                        self.region(*target, join, depth + 1)?;
+                    } else {
+                        self.emit(depth, format!("if ({}) {{", condition))?;
+                        self.region(*target, join, depth + 1)?;
+                        self.emit(depth, String::from("} else {"))?;
+                        self.region(*fall_through, join, depth + 1)?;
+                    }
+                    self.emit(depth, String::from("}"))?;
+                    join
+                }
+                // Both branch targets are the same block
+                (_, [next]) => Some(*next),
+                _ => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// Statements of a block and the condition of its final branch (if any)
+    fn lift_block(&mut self, block: usize) -> Result<(Vec<String>, Option<Condition>), Unsupported> {
+        let mut statements = Vec::new();
+        let mut pending_call: Option<String> = None;
+        let mut condition = None;
+        for (pc, insn) in self.cfg.block_instructions(block) {
+            // move-result consumes the call of the previous instruction
+            if let Some(call) = pending_call.take() {
+                if (0x0a..=0x0c).contains(&insn.opcode) {
+                    statements.push(format!("{} = {};", self.reg(insn.a), call));
+                    continue;
+                }
+                statements.push(format!("{};", call));
+            }
+            match insn.format() {
+                Format::F21t | Format::F22t => {
+                    condition = Some(self.condition(*pc, insn));
+                    continue;
+                }
+                Format::F10t | Format::F20t | Format::F30t => continue,
+                _ => {}
+            }
+            match self.lift(*pc, insn)? {
+                Lifted::Statement(statement) => statements.push(statement),
+                Lifted::Call(call) => pending_call = Some(call),
+                Lifted::Nothing => {}
+            }
+        }
+        if let Some(call) = pending_call {
+            statements.push(format!("{};", call));
+        }
+        Ok((statements, condition))
+    }
+
+    fn condition(&self, pc: usize, insn: &Instruction) -> Condition {
+        // if-eq .. if-le and if-eqz .. if-lez share the order of their comparisons
+        let operator = ["==", "!=", "<", ">=", ">", "<="][(insn.opcode as usize - 0x32) % 6];
+        let right = if insn.format() == Format::F22t {
+            self.reg(insn.b)
+        } else if matches!(self.types.at(pc, insn.a), Some(RegType::Reference(_))) {
+            String::from("null")
+        } else {
+            String::from("0")
+        };
+        Condition { left: self.reg(insn.a), operator, right }
+    }
+
+    /// Arguments of an invoke instruction, skipping the second register of wide parameters
+    fn invoke_args(&self, insn: &Instruction, method_idx: u32, is_static: bool) -> Vec<String> {
+        let registers: Vec<u32> = match insn.format() {
+            Format::F35c | Format::F45cc => insn.args[..insn.a as usize].iter().map(|it| *it as u32).collect(),
+            _ => (insn.c..insn.c + insn.a).collect(),
+        };
+        let mut registers = registers.into_iter();
+        let mut args = Vec::new();
+        if !is_static {
+            args.extend(registers.next().map(|it| self.reg(it)));
+        }
+        let proto_idx = self.dex.method_ids[method_idx as usize].proto_idx as u32;
+        for descriptor in self.dex.proto_parameters(proto_idx) {
+            args.extend(registers.next().map(|it| self.reg(it)));
+            if descriptor == "J" || descriptor == "D" {
+                registers.next();
+            }
+        }
+        args
+    }
+
+    fn lift(&mut self, pc: usize, insn: &Instruction) -> Result<Lifted, Unsupported> {
+        let dex = self.dex;
+        let a = self.reg(insn.a);
+        let name = insn.name();
+        let statement = match insn.opcode {
+            0x00 => return Ok(Lifted::Nothing),
+            0x01..=0x09 => format!("{} = {};", a, self.reg(insn.b)),
+            0x0d => format!("{} = <exception>;", a),
+            0x0e => String::from("return;"),
+            0x0f..=0x11 => format!("return {};", a),
+            0x12..=0x14 | 0x16 | 0x17 => format!("{} = {}{};", a, insn.b as i32, if name.starts_with("const-wide") { "L" } else { "" }),
+            0x15 => format!("{} = {:#x};", a, insn.b << 16),
+            0x18 => format!("{} = {:#x}L;", a, insn.wide_b),
+            0x19 => format!("{} = {:#x}L;", a, (insn.b as u64) << 48),
+            0x1a | 0x1b => format!("{} = \"{}\";", a, escape_string(dex.string(insn.b))),
+            0x1c => format!("{} = {}.class;", a, java_type(dex.type_descriptor(insn.b))),
+            0x1d => format!("synchronized_enter({});", a),
+            0x1e => format!("synchronized_exit({});", a),
+            0x1f => format!("{} = ({}) {};", a, java_type(dex.type_descriptor(insn.b)), a),
+            0x20 => format!("{} = {} instanceof {};", a, self.reg(insn.b), java_type(dex.type_descriptor(insn.c))),
+            0x21 => format!("{} = {}.length;", a, self.reg(insn.b)),
+            0x22 => {
+                self.uninitialized.insert(insn.a, insn.b);
+                return Ok(Lifted::Nothing);
+            }
+            0x23 => {
+                let element = dex.type_descriptor(insn.c).strip_prefix('[').unwrap_or("?");
+                format!("{} = new {}[{}];", a, java_type(element), self.reg(insn.b))
+            }
+            0x24 | 0x25 => {
+                let registers: Vec<String> = match insn.format() {
+                    Format::F35c => insn.args[..insn.a as usize].iter().map(|it| self.reg(*it as u32)).collect(),
+                    _ => (insn.c..insn.c + insn.a).map(|it| self.reg(it)).collect(),
+                };
+                return Ok(Lifted::Call(format!("new {} {{{}}}", java_type(dex.type_descriptor(insn.b)), registers.join(", "))));
+            }
+            0x26 => {
+                let target = (pc as i64 + insn.b as i32 as i64) as usize;
+                let values = match Payload::decode(&self.code.insns, target).ok().and_then(|it| it.array_data()) {
+                    Some(values) => values,
+                    None => return Err(Unsupported::InvalidCode(format!("No array data at 0x{:04x}", target))),
+                };
+                format!("{} = {};", a, values)
+            }
+            0x27 => format!("throw {};", a),
+            0x2d..=0x31 => {
+                let class = match insn.opcode {
+                    0x31 => "Long",
+                    0x2d | 0x2e => "Float",
+                    _ => "Double",
+                };
+                format!("{} = {}.compare({}, {});", a, class, self.reg(insn.b), self.reg(insn.c))
+            }
+            0x44..=0x4a => format!("{} = {}[{}];", a, self.reg(insn.b), self.reg(insn.c)),
+            0x4b..=0x51 => format!("{}[{}] = {};", self.reg(insn.b), self.reg(insn.c), a),
+            0x52..=0x58 => format!("{} = {}.{};", a, self.reg(insn.b), dex.field_name(insn.c)),
+            0x59..=0x5f => format!("{}.{} = {};", self.reg(insn.b), dex.field_name(insn.c), a),
+            0x60..=0x66 => format!("{} = {}.{};", a, self.static_owner(dex.field_class(insn.b)), dex.field_name(insn.b)),
+            0x67..=0x6d => format!("{}.{} = {};", self.static_owner(dex.field_class(insn.b)), dex.field_name(insn.b), a),
+            0x6e..=0x72 | 0x74..=0x78 => return self.lift_invoke(insn),
+            0x7b..=0x8f => {
+                let b = self.reg(insn.b);
+                let expression = match name {
+                    "neg-int" | "neg-long" | "neg-float" | "neg-double" => format!("-{}", b),
+                    "not-int" | "not-long" => format!("~{}", b),
+                    _ => {
+                        // Conversions are named <from>-to-<to>
+                        let to = name.rsplit('-').next().unwrap_or(name);
+                        format!("({}) {}", to, b)
+                    }
+                };
+                format!("{} = {};", a, expression)
+            }
+            0x90..=0xaf => format!("{} = {} {} {};", a, self.reg(insn.b), binary_operator(name), self.reg(insn.c)),
+            0xb0..=0xcf => format!("{} {}= {};", a, binary_operator(name), self.reg(insn.b)),
+            0xd1 | 0xd9 => format!("{} = {} - {};", a, insn.c as i32, self.reg(insn.b)),
+            0xd0..=0xe2 => format!("{} = {} {} {};", a, self.reg(insn.b), binary_operator(name), insn.c as i32),
+            _ => return Err(Unsupported::Instruction(name)),
+        };
+        Ok(Lifted::Statement(statement))
+    }
+
+    fn lift_invoke(&mut self, insn: &Instruction) -> Result<Lifted, Unsupported> {
+        let dex = self.dex;
+        let method_idx = insn.b;
+        if insn.info().index_type != IndexType::MethodRef {
+            return Err(Unsupported::Instruction(insn.name()));
+        }
+        let is_static = matches!(insn.opcode, 0x71 | 0x77);
+        let mut args = self.invoke_args(insn, method_idx, is_static);
+        let name = dex.method_name(method_idx);
+        if is_static {
+            let owner = self.static_owner(dex.method_class(method_idx));
+            return Ok(Lifted::Call(format!("{}.{}({})", owner, name, args.join(", "))));
+        }
+        let receiver_reg = match insn.format() {
+            Format::F35c => insn.args[0] as u32,
+            _ => insn.c,
+        };
+        let receiver = if args.is_empty() { String::from("?") } else { args.remove(0) };
+        if name == "<init>" {
+            if let Some(type_idx) = self.uninitialized.remove(&receiver_reg) {
+                return Ok(Lifted::Statement(format!("{} = new {}({});", receiver,
+                                                    java_type(dex.type_descriptor(type_idx)), args.join(", "))));
+            }
+            if receiver == "this" {
+                let delegate = if dex.method_ids[method_idx as usize].class_idx as u32 == self.class_idx { "this" } else { "super" };
+                return Ok(Lifted::Statement(format!("{}({});", delegate, args.join(", "))));
+            }
+        }
+        // invoke-super
+        let receiver = if matches!(insn.opcode, 0x6f | 0x75) { String::from("super") } else { receiver };
+        Ok(Lifted::Call(format!("{}.{}({})", receiver, name, args.join(", "))))
+    }
+
+    /// Qualifier of static members, omitted for members of the class itself
+    fn static_owner(&self, descriptor: &str) -> String {
+        if descriptor == self.dex.type_descriptor(self.class_idx) {
+            java_type(descriptor).rsplit('.').next().unwrap_or_default().to_string()
+        } else {
+            java_type(descriptor)
+        }
+    }
+}
+
+enum Lifted {
+    Statement(String),
+    /// An invocation whose result may be consumed by a following move-result
+    Call(String),
+    Nothing,
+}
+
+struct Condition {
+    left: String,
+    operator: &'static str,
+    right: String,
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        let operator = match self.operator {
+            "==" => "!=",
+            "!=" => "==",
+            "<" => ">=",
+            ">=" => "<",
+            ">" => "<=",
+            _ => ">",
+        };
+        Condition { operator, ..self }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.left, self.operator, self.right)
+    }
+}
+
+/// Java operator of a binary operation, named <operation>-<type>[/2addr|/lit16|/lit8]
+fn binary_operator(name: &str) -> &'static str {
+    match name.split('-').next().unwrap_or_default() {
+        "add" => "+",
+        "sub" | "rsub" => "-",
+        "mul" => "*",
+        "div" => "/",
+        "rem" => "%",
+        "and" => "&",
+        "or" => "|",
+        "xor" => "^",
+        "shl" => "<<",
+        "shr" => ">>",
+        "ushr" => ">>>",
+        _ => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureMethod};
+
+    #[test]
+    fn fewer_registers_than_ins() {
+        let method = FixtureMethod::new("run", "V", &["I"], vec![0x000e]).registers_size(0);
+        let dex = DexFile::from_bytes(&Fixture::empty().method(method).build()).unwrap();
+        let mut out = Vec::new();
+        write_class(&dex, 0, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("public void run(int arg0) {"));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        // invoke-static {}, method@99 and code without instructions
+        let invoke = FixtureMethod::new("run", "V", &[], vec![0x0071, 99, 0x0000, 0x000e]);
+        let empty = FixtureMethod::new("empty", "V", &[], vec![]);
+        let dex = DexFile::from_bytes(&Fixture::empty().method(invoke).method(empty).build()).unwrap();
+        let mut out = Vec::new();
+        write_class(&dex, 0, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("// Could not decompile: Invalid code: 0x0000: "), "{}", out);
+        assert!(out.contains("public void empty() {\n    }"), "{}", out);
+    }
+}
@@ -0,0 +1,1510 @@
+use alloc::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::Range;
+
+use crate::checked;
+use crate::compact::CompactDexFile;
+use crate::error::DexError;
+use crate::io::{Read, Seek};
+use crate::io::SeekFrom::Start;
+use crate::prelude::*;
+
+use crate::raw_dex::{self, AnnotationItem, AnnotationsDirectory, ClassData, ClassDef, CodeItem, DebugInfoItem, DebugInstruction, DexHeader, EncodedAnnotation, EncodedField, EncodedMethod, EncodedValue, FieldId, MapItem, MethodId, OptionalIdx, ProtoIdItem};
+
+/// Value of 32-bit indices that do not reference anything (e.g. the superclass_idx of java.lang.Object)
+pub const NO_INDEX: u32 = 0xffffffff;
+
+pub(crate) const ACC_STATIC: u64 = 0x8;
+
+// Item types of the map list of sections that can be reused by ParseOptions::reparse
+const TYPE_STRING_ID_ITEM: u16 = 0x0001;
+const TYPE_TYPE_ID_ITEM: u16 = 0x0002;
+const TYPE_PROTO_ID_ITEM: u16 = 0x0003;
+const TYPE_FIELD_ID_ITEM: u16 = 0x0004;
+const TYPE_METHOD_ID_ITEM: u16 = 0x0005;
+const TYPE_CLASS_DEF_ITEM: u16 = 0x0006;
+const TYPE_TYPE_LIST: u16 = 0x1001;
+const TYPE_ANNOTATION_SET_REF_LIST: u16 = 0x1002;
+const TYPE_ANNOTATION_SET_ITEM: u16 = 0x1003;
+const TYPE_CLASS_DATA_ITEM: u16 = 0x2000;
+const TYPE_CODE_ITEM: u16 = 0x2001;
+const TYPE_STRING_DATA_ITEM: u16 = 0x2002;
+const TYPE_DEBUG_INFO_ITEM: u16 = 0x2003;
+const TYPE_ANNOTATION_ITEM: u16 = 0x2004;
+const TYPE_ENCODED_ARRAY_ITEM: u16 = 0x2005;
+const TYPE_ANNOTATIONS_DIRECTORY_ITEM: u16 = 0x2006;
+
+// Constants of the debug info state machine
+pub(crate) const DBG_FIRST_SPECIAL: i64 = 0x0a;
+pub(crate) const DBG_LINE_BASE: i64 = -4;
+pub(crate) const DBG_LINE_RANGE: i64 = 15;
+
+/// A Dex File with all id tables loaded, plus the class data, code items, debug info, type lists and
+/// annotations referenced by its class definitions and prototypes (keyed by their offset). The maps
+/// are ordered, iterating them visits the items in file order, the same on every run. DexFile is Send
+/// and Sync, one parse can serve concurrent queries.
+pub struct DexFile {
+    pub header: DexHeader,
+    pub map_list: Vec<MapItem>,
+    pub strings: Vec<String>,
+    /// Offset of the string data item of each string
+    pub string_data_offs: Vec<u32>,
+    pub type_ids: Vec<u32>,
+    pub proto_ids: Vec<ProtoIdItem>,
+    pub field_ids: Vec<FieldId>,
+    pub method_ids: Vec<MethodId>,
+    pub class_defs: Vec<ClassDef>,
+    /// Index into `class_defs` of the definition of each type, NO_INDEX for types not defined by this
+    /// file (the first definition of a type defined twice)
+    pub class_def_indices: Vec<u32>,
+    /// Class Data for each entry of `class_defs` (None if the class has no class data)
+    pub class_data: Vec<Option<ClassData>>,
+    pub type_lists: BTreeMap<u32, Vec<u16>>,
+    /// Empty for files parsed with Mode::Lazy, see DexFile::code_item
+    pub code_items: BTreeMap<u32, CodeItem>,
+    pub debug_info: BTreeMap<u32, DebugInfoItem>,
+    pub annotations_directories: BTreeMap<u32, AnnotationsDirectory>,
+    /// Offsets of the annotation items of each annotation set
+    pub annotation_sets: BTreeMap<u32, Vec<u32>>,
+    /// Offsets of the annotation sets of the parameters of each annotation set ref list (0 for none)
+    pub annotation_set_ref_lists: BTreeMap<u32, Vec<u32>>,
+    pub annotation_items: BTreeMap<u32, AnnotationItem>,
+    /// Initial values of the static fields of the class definitions, by static_values_off
+    pub static_values: BTreeMap<u32, Vec<EncodedValue>>,
+    /// Bytes of the link section (data of statically linked files), unused by the runtime and empty in
+    /// almost all files, but a place to hide data
+    pub link_data: Vec<u8>,
+    /// Sections of the map list with item types unknown to the parser (e.g. of vendor toolchains)
+    pub unknown_sections: Vec<UnknownSection>,
+    /// Size in bytes of each parsed item of the data section (string data, class data, code items,
+    /// debug info, type lists, annotations and static values) by offset, see DexFile::item_span
+    pub item_sizes: BTreeMap<u32, u32>,
+    #[cfg(feature = "std")]
+    lazy_code: Option<LazyCode>,
+}
+
+// A parsed DexFile is immutable plain data, it can be shared between threads (e.g. in an Arc) and
+// queried without locks (the code items parsed on access with Mode::Lazy are set once, in a OnceLock)
+const _: fn() = || {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<DexFile>();
+};
+
+/// A section of an item type the parser does not know, with its bytes up to the next section
+#[derive(Debug, Clone)]
+pub struct UnknownSection {
+    pub item_type: u16,
+    /// Number of items as declared by the map list
+    pub size: u32,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+/// Bytes of an item in the file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ByteSpan {
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl ByteSpan {
+    pub fn range(&self) -> Range<usize> {
+        self.offset as usize..self.offset as usize + self.len as usize
+    }
+}
+
+/// Entry of the position (line number) table of a method
+#[derive(Debug)]
+pub struct PositionInfo {
+    pub address: u32,
+    pub line: u32,
+}
+
+/// Entry of the local variable table of a method
+#[derive(Debug, Clone)]
+pub struct LocalInfo<'a> {
+    pub name: Option<&'a str>,
+    pub descriptor: Option<&'a str>,
+    pub signature: Option<&'a str>,
+    pub start_address: u32,
+    pub end_address: u32,
+    pub reg: u16,
+}
+
+/// Range of instructions covered by a try item, with its resolved exception handlers
+#[derive(Debug, Clone)]
+pub struct CatchInfo<'a> {
+    pub start_address: u32,
+    /// Exclusive
+    pub end_address: u32,
+    /// Handlers in the order they are checked, the catch-all handler (if any) last
+    pub handlers: Vec<CatchHandlerInfo<'a>>,
+}
+
+/// Exception handler of a try item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatchHandlerInfo<'a> {
+    /// Descriptor of the caught exception class, None for the catch-all handler
+    pub exception: Option<&'a str>,
+    pub address: u32,
+}
+
+/// Progress of DexFile::from_reader_with_progress, reported after each section and after each item
+/// of the class data and code items
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// Name of the section that is being parsed
+    pub section: &'static str,
+    /// Items of the section parsed so far
+    pub items: usize,
+    pub total_items: usize,
+    /// Position of the reader
+    pub bytes: u64,
+    /// Size of the file as declared by the header
+    pub total_bytes: u64,
+}
+
+/// How malformed input is handled, see ParseOptions::strictness
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Items of the data section that fail to parse are skipped with a warning, as if they were absent
+    Lenient,
+    /// Malformed items fail the parse, invalid MUTF-8 in strings is replaced with a warning
+    #[default]
+    Normal,
+    /// Like Normal, but invalid MUTF-8 in strings and a wrong checksum fail the parse as well
+    Strict,
+}
+
+/// When the code items are parsed, see ParseOptions::mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Everything is parsed before the parse returns
+    #[default]
+    Eager,
+    /// The code items are parsed on first access with DexFile::code_item, from a copy of the file kept
+    /// by the DexFile (their debug info is still parsed up front). A code item failing to parse is
+    /// logged and None then, regardless of the strictness.
+    #[cfg(feature = "std")]
+    Lazy,
+    /// The code items, most of the data of a typical file, are parsed on the rayon thread pool
+    #[cfg(feature = "parallel")]
+    Parallel,
+}
+
+impl Mode {
+    /// Whether the code items are parsed on access
+    fn lazy(self) -> bool {
+        match self {
+            #[cfg(feature = "std")]
+            Mode::Lazy => true,
+            _ => false,
+        }
+    }
+}
+
+/// Code items of a file parsed with Mode::Lazy, parsed from the data of the file on first access
+#[cfg(feature = "std")]
+struct LazyCode {
+    data: Vec<u8>,
+    counts: IdCounts,
+    code_items: BTreeMap<u32, std::sync::OnceLock<Option<CodeItem>>>,
+}
+
+#[cfg(feature = "std")]
+impl LazyCode {
+    fn get(&self, off: u32) -> Option<&CodeItem> {
+        self.code_items.get(&off)?.get_or_init(|| {
+            let mut reader = crate::io::Cursor::new(&self.data[..]);
+            let code_item = reader.seek(Start(off.into()))
+                .and_then(|_| CodeItem::from_reader(&mut reader))
+                .and_then(|it| self.counts.code_item(&it, off).map(|_| it))
+                .map_err(context(|| "code_item".to_owned(), off));
+            code_item.map_err(|err| tracing::warn!("{}", err)).ok()
+        }).as_ref()
+    }
+}
+
+/// A recoverable problem of the parsed dex file, see ParseOptions::warnings
+#[derive(Debug, Clone)]
+pub struct Warning {
+    /// Offset of the affected item
+    pub offset: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}: {}", self.offset, self.message)
+    }
+}
+
+/// Options of parsing a dex file, e.g. `ParseOptions::new().debug_info(false).parse(&mut reader)`.
+/// By default everything is parsed with Strictness::Normal.
+pub struct ParseOptions<'a> {
+    code: bool,
+    debug_info: bool,
+    annotations: bool,
+    static_values: bool,
+    strictness: Strictness,
+    mode: Mode,
+    progress: Option<&'a mut dyn FnMut(Progress)>,
+    warnings: Option<&'a mut dyn FnMut(Warning)>,
+}
+
+impl Default for ParseOptions<'_> {
+    fn default() -> Self {
+        ParseOptions {
+            code: true,
+            debug_info: true,
+            annotations: true,
+            static_values: true,
+            strictness: Strictness::Normal,
+            mode: Mode::Eager,
+            progress: None,
+            warnings: None,
+        }
+    }
+}
+
+impl<'a> ParseOptions<'a> {
+    pub fn new() -> ParseOptions<'a> {
+        ParseOptions::default()
+    }
+
+    /// Whether to parse the code items and their debug info, DexFile::code_item returns None otherwise
+    pub fn code(mut self, parse: bool) -> Self {
+        self.code = parse;
+        self
+    }
+
+    /// Whether to parse the debug info of the code items (positions, locals and parameter names)
+    pub fn debug_info(mut self, parse: bool) -> Self {
+        self.debug_info = parse;
+        self
+    }
+
+    /// Whether to parse annotations, classes and their members have none otherwise
+    pub fn annotations(mut self, parse: bool) -> Self {
+        self.annotations = parse;
+        self
+    }
+
+    /// Whether to parse the initial values of static fields, see Field::static_value
+    pub fn static_values(mut self, parse: bool) -> Self {
+        self.static_values = parse;
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// When the code items are parsed, see Mode
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Called after each section and after each item of the class data and code items
+    pub fn progress(mut self, progress: &'a mut dyn FnMut(Progress)) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Called for each recoverable problem, which is logged with tracing as well
+    pub fn warnings(mut self, warnings: &'a mut dyn FnMut(Warning)) -> Self {
+        self.warnings = Some(warnings);
+        self
+    }
+
+    /// Parses a dex file from a seekable reader, see DexFile::from_reader
+    pub fn parse<R: Read + Seek + ?Sized>(self, reader: &mut R) -> Result<DexFile, crate::io::Error> {
+        DexFile::parse(reader, self, Reusable::default())
+    }
+
+    pub fn parse_bytes(self, data: &[u8]) -> Result<DexFile, crate::io::Error> {
+        self.parse(&mut crate::io::Cursor::new(data))
+    }
+
+    /// Parses `data`, a modified version of `old_data` from which `old` was parsed with the same options.
+    /// Sections at the same offset with the same bytes are not parsed again (e.g. after changing a few
+    /// methods, only the id tables and items that moved are), nor are their warnings reported again.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn reparse(self, old: DexFile, old_data: &[u8], data: &[u8]) -> Result<DexFile, crate::io::Error> {
+        let mut reader = crate::io::Cursor::new(data);
+        let header = DexHeader::from_reader(&mut reader)?;
+        let map_list = MapItem::parse_map_list(&header, &mut reader)?;
+        let old_ranges = section_ranges(&old.map_list, old.header.file_size);
+        let unchanged: Vec<u16> = section_ranges(&map_list, header.file_size).into_iter()
+            .filter(|(item, range)| {
+                old_ranges.iter().any(|(old, old_range)| {
+                    old.item_type == item.item_type && old.size == item.size && old_range == range
+                }) && old_data.get(range.clone()).is_some_and(|old| data.get(range.clone()) == Some(old))
+            })
+            .map(|(item, _)| item.item_type)
+            .collect();
+        tracing::debug!(?unchanged, "Reusing unchanged sections");
+        DexFile::parse(&mut crate::io::Cursor::new(data), self, Reusable::new(old, &unchanged))
+    }
+
+    /// Parses a dex file into the compact representation, for very large files. Of the section toggles
+    /// only `code` applies and the mode is ignored, see CompactDexFile for what is kept.
+    pub fn parse_compact<R: Read + Seek + ?Sized>(self, reader: &mut R) -> Result<CompactDexFile, crate::io::Error> {
+        let mut diagnostics = Diagnostics { strictness: self.strictness, warnings: self.warnings };
+        CompactDexFile::parse(reader, self.code, &mut diagnostics)
+    }
+}
+
+/// Handling of malformed input according to the Strictness
+pub(crate) struct Diagnostics<'a> {
+    pub(crate) strictness: Strictness,
+    warnings: Option<&'a mut dyn FnMut(Warning)>,
+}
+
+impl Diagnostics<'_> {
+    pub(crate) fn warn(&mut self, offset: u64, message: String) {
+        tracing::warn!(offset, %message);
+        if let Some(warnings) = &mut self.warnings {
+            warnings(Warning { offset, message });
+        }
+    }
+
+    /// Warns about a problem that only fails a strict parse
+    pub(crate) fn check(&mut self, offset: u64, message: String) -> Result<(), crate::io::Error> {
+        if self.strictness == Strictness::Strict {
+            return Err(crate::io::Error::new(crate::io::ErrorKind::InvalidData, format!("{} at offset 0x{:x}", message, offset)));
+        }
+        self.warn(offset, message);
+        Ok(())
+    }
+
+    /// The item parsed at `offset`, or None after a warning if it is malformed and the parse is lenient
+    pub(crate) fn recover<T>(&mut self, result: Result<T, crate::io::Error>, offset: u32) -> Result<Option<T>, crate::io::Error> {
+        match result {
+            Ok(item) => Ok(Some(item)),
+            Err(err) if self.strictness == Strictness::Lenient => {
+                self.warn(offset.into(), format!("Skipped {}", err));
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Sections of a previous parse whose bytes are unchanged, see ParseOptions::reparse
+#[derive(Default)]
+struct Reusable {
+    strings: Option<(Vec<u32>, Vec<String>)>,
+    type_ids: Option<Vec<u32>>,
+    proto_ids: Option<Vec<ProtoIdItem>>,
+    field_ids: Option<Vec<FieldId>>,
+    method_ids: Option<Vec<MethodId>>,
+    class_defs: Option<Vec<ClassDef>>,
+    // Items by offset, empty if their section changed
+    type_lists: BTreeMap<u32, Vec<u16>>,
+    class_data: BTreeMap<u32, ClassData>,
+    code_items: BTreeMap<u32, CodeItem>,
+    debug_info: BTreeMap<u32, DebugInfoItem>,
+    annotations_directories: BTreeMap<u32, AnnotationsDirectory>,
+    annotation_sets: BTreeMap<u32, Vec<u32>>,
+    annotation_set_ref_lists: BTreeMap<u32, Vec<u32>>,
+    annotation_items: BTreeMap<u32, AnnotationItem>,
+    static_values: BTreeMap<u32, Vec<EncodedValue>>,
+    // Sizes of the items of the previous parse, valid for the reused items
+    item_sizes: BTreeMap<u32, u32>,
+}
+
+impl Reusable {
+    fn new(previous: DexFile, unchanged: &[u16]) -> Reusable {
+        let reuse = |item_types: &[u16]| item_types.iter().all(|it| unchanged.contains(it));
+        let class_data = if reuse(&[TYPE_CLASS_DATA_ITEM]) {
+            previous.class_defs.iter().map(|it| it.class_data_off).zip(previous.class_data)
+                .filter_map(|(off, class_data)| Some((off, class_data?)))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        Reusable {
+            strings: Some((previous.string_data_offs, previous.strings)).filter(|_| reuse(&[TYPE_STRING_ID_ITEM, TYPE_STRING_DATA_ITEM])),
+            type_ids: Some(previous.type_ids).filter(|_| reuse(&[TYPE_TYPE_ID_ITEM])),
+            proto_ids: Some(previous.proto_ids).filter(|_| reuse(&[TYPE_PROTO_ID_ITEM])),
+            field_ids: Some(previous.field_ids).filter(|_| reuse(&[TYPE_FIELD_ID_ITEM])),
+            method_ids: Some(previous.method_ids).filter(|_| reuse(&[TYPE_METHOD_ID_ITEM])),
+            class_defs: Some(previous.class_defs).filter(|_| reuse(&[TYPE_CLASS_DEF_ITEM])),
+            type_lists: if reuse(&[TYPE_TYPE_LIST]) { previous.type_lists } else { BTreeMap::new() },
+            class_data,
+            code_items: if reuse(&[TYPE_CODE_ITEM]) { previous.code_items } else { BTreeMap::new() },
+            debug_info: if reuse(&[TYPE_DEBUG_INFO_ITEM]) { previous.debug_info } else { BTreeMap::new() },
+            annotations_directories: if reuse(&[TYPE_ANNOTATIONS_DIRECTORY_ITEM]) { previous.annotations_directories } else { BTreeMap::new() },
+            annotation_sets: if reuse(&[TYPE_ANNOTATION_SET_ITEM]) { previous.annotation_sets } else { BTreeMap::new() },
+            annotation_set_ref_lists: if reuse(&[TYPE_ANNOTATION_SET_REF_LIST]) { previous.annotation_set_ref_lists } else { BTreeMap::new() },
+            annotation_items: if reuse(&[TYPE_ANNOTATION_ITEM]) { previous.annotation_items } else { BTreeMap::new() },
+            static_values: if reuse(&[TYPE_ENCODED_ARRAY_ITEM]) { previous.static_values } else { BTreeMap::new() },
+            item_sizes: previous.item_sizes,
+        }
+    }
+}
+
+/// Item at `off` from `reusable` if its section is unchanged, otherwise parsed from the reader. The size
+/// of the item is recorded in `sizes`.
+fn reuse_or_parse<R, T, F>(reusable: &mut BTreeMap<u32, T>, sizes: &mut ItemSizes, off: u32, reader: &mut R, parse: F) -> Result<T, crate::io::Error>
+    where R: Read + Seek + ?Sized, F: FnOnce(&mut R) -> Result<T, crate::io::Error> {
+    match reusable.remove(&off) {
+        Some(item) => {
+            sizes.reused(off);
+            Ok(item)
+        }
+        None => {
+            reader.seek(Start(off.into()))?;
+            let item = parse(reader)?;
+            sizes.parsed(off, reader)?;
+            Ok(item)
+        }
+    }
+}
+
+/// Sizes of the items of the data section, see DexFile::item_sizes
+struct ItemSizes {
+    previous: BTreeMap<u32, u32>,
+    sizes: BTreeMap<u32, u32>,
+}
+
+impl ItemSizes {
+    /// Takes over the size of an item from the previous parse
+    fn reused(&mut self, off: u32) {
+        if let Some(size) = self.previous.get(&off) {
+            self.sizes.insert(off, *size);
+        }
+    }
+
+    /// Records the size of an item parsed from `off` up to the position of the reader
+    fn parsed<R: Seek + ?Sized>(&mut self, off: u32, reader: &mut R) -> Result<(), crate::io::Error> {
+        let end = reader.stream_position()?;
+        self.sizes.insert(off, end.saturating_sub(off.into()) as u32);
+        Ok(())
+    }
+}
+
+/// Adds the item being parsed at `offset` to an error, see DexError::context
+pub(crate) fn context<'a>(item: impl FnOnce() -> String + 'a, offset: u32) -> impl FnOnce(crate::io::Error) -> crate::io::Error + 'a {
+    move |err| DexError::context(err, item(), offset.into())
+}
+
+/// Id tables parsed so far, to name the items parsed after them in errors. Indices out of range are
+/// shown as `?` instead of panicking.
+struct Ids<'a> {
+    strings: &'a [String],
+    type_ids: &'a [u32],
+    proto_ids: &'a [ProtoIdItem],
+    method_ids: &'a [MethodId],
+    type_lists: &'a BTreeMap<u32, Vec<u16>>,
+}
+
+impl Ids<'_> {
+    fn string(&self, string_idx: u32) -> &str {
+        self.strings.get(string_idx as usize).map_or("?", |it| it)
+    }
+
+    fn type_descriptor(&self, type_idx: u32) -> &str {
+        self.type_ids.get(type_idx as usize).map_or("?", |it| self.string(*it))
+    }
+
+    /// `Lcls;->name(sig)` of a method
+    fn method(&self, method_idx: u32) -> String {
+        let method = match self.method_ids.get(method_idx as usize) {
+            Some(method) => method,
+            None => return format!("method@{}", method_idx),
+        };
+        let signature = match self.proto_ids.get(method.proto_idx as usize) {
+            Some(proto) => {
+                let parameters: String = self.type_lists.get(&proto.parameters_off).map_or(&[][..], |it| it).iter()
+                    .map(|it| self.type_descriptor(*it as u32))
+                    .collect();
+                format!("({}){}", parameters, self.type_descriptor(proto.return_type_idx))
+            }
+            None => "?".to_owned(),
+        };
+        format!("{}->{}{}", self.type_descriptor(method.class_idx as u32), self.string(method.name_idx), signature)
+    }
+}
+
+/// Bytes of the entry `idx` of an id table at `off` with `count` entries of `size` bytes, None for
+/// indices out of the table
+fn id_span(off: u32, count: u32, size: u32, idx: u32) -> Option<ByteSpan> {
+    if idx >= count {
+        return None;
+    }
+    checked::entry_offset(off, idx, size).map(|offset| ByteSpan { offset, len: size })
+}
+
+/// Checks that the indices of the id tables are within the tables they refer to, so the accessors of
+/// DexFile and CompactDexFile can index them. The returned counts check the items of the data section,
+/// only the operands of instructions are left to the consumers of the code.
+pub(crate) fn check_ids(header: &DexHeader, strings: usize, type_ids: &[u32], proto_ids: &[ProtoIdItem], field_ids: &[FieldId],
+                        method_ids: &[MethodId], class_defs: &[ClassDef]) -> Result<IdCounts, crate::io::Error> {
+    let entry = |name: &'static str, off: u32, idx: usize, size: u32| {
+        let offset = checked::entry_offset(off, idx as u32, size).unwrap_or(off);
+        move |err: DexError| DexError::context(err.into(), format!("{} #{}", name, idx), offset.into())
+    };
+    let (types, protos) = (type_ids.len(), proto_ids.len());
+    for (idx, descriptor_idx) in type_ids.iter().enumerate() {
+        let off = header.type_ids_off;
+        checked::index("string", (*descriptor_idx).into(), strings, off).map_err(entry("type_id", off, idx, 4))?;
+    }
+    for (idx, proto) in proto_ids.iter().enumerate() {
+        let off = header.proto_ids_off;
+        checked::index("string", proto.shorty_idx.into(), strings, off)
+            .and_then(|_| checked::index("type", proto.return_type_idx.into(), types, off))
+            .map_err(entry("proto_id", off, idx, 12))?;
+    }
+    for (idx, field) in field_ids.iter().enumerate() {
+        let off = header.field_ids_off;
+        checked::index("type", field.class_idx.into(), types, off)
+            .and_then(|_| checked::index("type", field.type_idx.into(), types, off))
+            .and_then(|_| checked::index("string", field.name_idx.into(), strings, off))
+            .map_err(entry("field_id", off, idx, 8))?;
+    }
+    for (idx, method) in method_ids.iter().enumerate() {
+        let off = header.method_ids_off;
+        checked::index("type", method.class_idx.into(), types, off)
+            .and_then(|_| checked::index("proto", method.proto_idx.into(), protos, off))
+            .and_then(|_| checked::index("string", method.name_idx.into(), strings, off))
+            .map_err(entry("method_id", off, idx, 8))?;
+    }
+    for (idx, class_def) in class_defs.iter().enumerate() {
+        let off = header.class_defs_off;
+        checked::index("type", class_def.class_idx.into(), types, off)
+            .and_then(|_| match class_def.superclass_idx {
+                NO_INDEX => Ok(()),
+                superclass_idx => checked::index("type", superclass_idx.into(), types, off),
+            })
+            .and_then(|_| match class_def.source_file_idx {
+                NO_INDEX => Ok(()),
+                source_file_idx => checked::index("string", source_file_idx.into(), strings, off),
+            })
+            .map_err(entry("class_def", off, idx, 32))?;
+    }
+    Ok(IdCounts { strings, types, protos, fields: field_ids.len(), methods: method_ids.len() })
+}
+
+/// Sizes of the id tables, to check the indices of the items of the data section against
+#[derive(Clone, Copy)]
+pub(crate) struct IdCounts {
+    pub strings: usize,
+    pub types: usize,
+    pub protos: usize,
+    pub fields: usize,
+    pub methods: usize,
+}
+
+impl IdCounts {
+    /// Checks the type indices of the type_list at `off`
+    pub fn type_list(self, type_list: &[u16], off: u32) -> Result<(), crate::io::Error> {
+        type_list.iter().try_for_each(|it| checked::index("type", (*it).into(), self.types, off))?;
+        Ok(())
+    }
+
+    /// Checks the absolute member indices of the class_data_item at `off`, the last index of each list
+    /// is its largest
+    pub fn class_data(self, class_data: &ClassData, off: u32) -> Result<(), crate::io::Error> {
+        for fields in [&class_data.static_fields, &class_data.instance_fields] {
+            if !fields.is_empty() {
+                let last = fields.iter().map(|it| it.field_idx_diff).fold(0, u64::saturating_add);
+                checked::index("field", last, self.fields, off)?;
+            }
+        }
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            if !methods.is_empty() {
+                let last = methods.iter().map(|it| it.method_idx_diff).fold(0, u64::saturating_add);
+                checked::index("method", last, self.methods, off)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the exception types of the catch handlers of the code_item at `off`
+    pub fn code_item(self, code_item: &CodeItem, off: u32) -> Result<(), crate::io::Error> {
+        code_item.handlers.iter().flat_map(|it| &it.handlers)
+            .try_for_each(|it| checked::index("type", it.type_idx, self.types, off))?;
+        Ok(())
+    }
+
+    /// Checks the names and types of the debug_info_item at `off`
+    pub fn debug_info(self, debug_info: &DebugInfoItem, off: u32) -> Result<(), crate::io::Error> {
+        let check = |kind, idx: OptionalIdx, size| idx.get().map_or(Ok(()), |it| checked::index(kind, it.into(), size, off));
+        for name_idx in &debug_info.parameter_names {
+            check("string", *name_idx, self.strings)?;
+        }
+        for instruction in &debug_info.bytecode {
+            match *instruction {
+                DebugInstruction::StartLocal { name_idx, type_idx, .. } => {
+                    check("string", name_idx, self.strings)?;
+                    check("type", type_idx, self.types)?;
+                }
+                DebugInstruction::StartLocalExtended { name_idx, type_idx, sig_idx, .. } => {
+                    check("string", name_idx, self.strings)?;
+                    check("type", type_idx, self.types)?;
+                    check("string", sig_idx, self.strings)?;
+                }
+                DebugInstruction::SetFile(name_idx) => check("string", name_idx, self.strings)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the members of the annotations_directory_item at `off`
+    pub fn annotations_directory(self, directory: &AnnotationsDirectory, off: u32) -> Result<(), crate::io::Error> {
+        directory.field_annotations.iter().try_for_each(|it| checked::index("field", it.field_idx.into(), self.fields, off))?;
+        directory.method_annotations.iter().map(|it| it.method_idx)
+            .chain(directory.parameter_annotations.iter().map(|it| it.method_idx))
+            .try_for_each(|it| checked::index("method", it.into(), self.methods, off))?;
+        Ok(())
+    }
+
+    /// Checks the indices of an encoded value, or of the annotation it is part of, at `off`
+    pub fn encoded_value(self, value: &EncodedValue, off: u32) -> Result<(), crate::io::Error> {
+        match value {
+            EncodedValue::MethodType(idx) => checked::index("proto", (*idx).into(), self.protos, off)?,
+            EncodedValue::String(idx) => checked::index("string", (*idx).into(), self.strings, off)?,
+            EncodedValue::Type(idx) => checked::index("type", (*idx).into(), self.types, off)?,
+            EncodedValue::Field(idx) | EncodedValue::Enum(idx) => checked::index("field", (*idx).into(), self.fields, off)?,
+            EncodedValue::Method(idx) => checked::index("method", (*idx).into(), self.methods, off)?,
+            EncodedValue::Array(values) => values.iter().try_for_each(|it| self.encoded_value(it, off))?,
+            EncodedValue::Annotation(annotation) => self.annotation(annotation, off)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Checks the type and element names of an encoded annotation at `off`
+    pub fn annotation(self, annotation: &EncodedAnnotation, off: u32) -> Result<(), crate::io::Error> {
+        checked::index("type", annotation.type_idx, self.types, off)?;
+        for element in &annotation.elements {
+            checked::index("string", element.name_idx, self.strings, off)?;
+            self.encoded_value(&element.value, off)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits concatenated type descriptors, e.g. the parameters `I[JLjava/lang/String;` of a signature.
+/// None if the last descriptor is incomplete.
+pub(crate) fn split_descriptors(mut descriptors: &str) -> Option<Vec<&str>> {
+    let mut split = Vec::new();
+    while !descriptors.is_empty() {
+        let dimensions = descriptors.len() - descriptors.trim_start_matches('[').len();
+        let element = &descriptors[dimensions..];
+        let len = match element.chars().next()? {
+            'L' => element.find(';')? + 1,
+            c => c.len_utf8(),
+        };
+        let (descriptor, rest) = descriptors.split_at(dimensions + len);
+        split.push(descriptor);
+        descriptors = rest;
+    }
+    Some(split)
+}
+
+/// Byte range of each section of the map list, a section extends to the start of the next one
+fn section_ranges(map_list: &[MapItem], file_size: u32) -> Vec<(&MapItem, Range<usize>)> {
+    let mut offsets: Vec<u32> = map_list.iter().map(|it| it.offset).collect();
+    offsets.sort_unstable();
+    map_list.iter().map(|item| {
+        let end = offsets.iter().copied().find(|it| *it > item.offset).unwrap_or(file_size);
+        (item, item.offset as usize..end as usize)
+    }).collect()
+}
+
+impl DexFile {
+    /// Parses a dex file from a seekable reader. Sections are read with many small reads, so files and
+    /// other unbuffered readers should be wrapped in a BufReader.
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<DexFile, crate::io::Error> {
+        DexFile::from_reader_with_progress(reader, &mut |_| {})
+    }
+
+    /// Parses a dex file from memory, e.g. a mapped file or a payload extracted from another format
+    pub fn from_bytes(data: &[u8]) -> Result<DexFile, crate::io::Error> {
+        DexFile::from_reader(&mut crate::io::Cursor::new(data))
+    }
+
+    /// Parses the dex file at `path`, see input::read_file
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<DexFile, crate::io::Error> {
+        DexFile::from_bytes(&crate::input::read_file(path.as_ref())?)
+    }
+
+    /// Parses a dex file from a reader that can not seek (e.g. a zip entry or a network stream), the
+    /// data is read into memory first since the sections are not read in file order.
+    pub fn from_stream<R: Read + ?Sized>(reader: &mut R) -> Result<DexFile, crate::io::Error> {
+        DexFile::from_bytes(&crate::io::read_to_end(reader)?)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn from_reader_with_progress<R: Read + Seek + ?Sized>(reader: &mut R, progress: &mut dyn FnMut(Progress)) -> Result<DexFile, crate::io::Error> {
+        ParseOptions::new().progress(progress).parse(reader)
+    }
+
+    fn parse<R: Read + Seek + ?Sized>(reader: &mut R, options: ParseOptions, mut reusable: Reusable) -> Result<DexFile, crate::io::Error> {
+        let ParseOptions { code: parse_code, debug_info: parse_debug_info, annotations: parse_annotations,
+            static_values: parse_static_values, strictness, mode, mut progress, warnings } = options;
+        let mut diagnostics = Diagnostics { strictness, warnings };
+        let header = DexHeader::from_reader(reader).map_err(context(|| "header".to_owned(), 0))?;
+        let total_bytes = header.file_size as u64;
+        tracing::debug!(file_size = header.file_size, version = DexHeader::parse_version(&header.magic), "Parsed header");
+        if strictness == Strictness::Strict {
+            reader.seek(Start(0))?;
+            let checksum = DexHeader::compute_checksum(&crate::io::read_to_end(reader)?);
+            if checksum != header.checksum {
+                diagnostics.check(8, format!("Checksum 0x{:08x} does not match the data (0x{:08x})", header.checksum, checksum))?;
+            }
+        }
+        let mut report = |section: &'static str, items: usize, total_items: usize, reader: &mut R| -> Result<(), crate::io::Error> {
+            if let Some(progress) = &mut progress {
+                let bytes = reader.stream_position()?;
+                progress(Progress { section, items, total_items, bytes, total_bytes });
+            }
+            Ok(())
+        };
+        report("header", 1, 1, reader)?;
+        let map_list = MapItem::parse_map_list(&header, reader).map_err(context(|| "map_list".to_owned(), header.map_off))?;
+        report("map_list", map_list.len(), map_list.len(), reader)?;
+        let link_data = if header.link_off == 0 { Vec::new() } else {
+            checked::read_bytes(reader, header.link_off, header.link_size).map_err(context(|| "link data".to_owned(), header.link_off))?
+        };
+        if !link_data.is_empty() {
+            diagnostics.warn(header.link_off.into(), format!("Link section of {} bytes", link_data.len()));
+        }
+        let mut unknown_sections = Vec::new();
+        for (item, range) in section_ranges(&map_list, header.file_size) {
+            if item.type_name().is_none() {
+                let data = checked::read_bytes(reader, item.offset, range.len() as u32)
+                    .map_err(context(|| format!("unknown section 0x{:04x}", item.item_type), item.offset))?;
+                diagnostics.warn(item.offset.into(), format!("Unknown map item type 0x{:04x}", item.item_type));
+                unknown_sections.push(UnknownSection { item_type: item.item_type, size: item.size, offset: item.offset, data });
+            }
+        }
+        let mut sizes = ItemSizes { previous: core::mem::take(&mut reusable.item_sizes), sizes: BTreeMap::new() };
+        let (string_data_offs, strings) = match reusable.strings.take() {
+            Some(strings) => {
+                strings.0.iter().for_each(|off| sizes.reused(*off));
+                strings
+            }
+            None => {
+                let string_ids = raw_dex::parse_string_ids(&header, reader).map_err(context(|| "string_ids".to_owned(), header.string_ids_off))?;
+                report("string_ids", string_ids.len(), string_ids.len(), reader)?;
+                let mut strings = Vec::with_capacity(string_ids.len());
+                for (idx, (item, &off)) in raw_dex::parse_string_data_items(&string_ids, reader)?.into_iter().zip(&string_ids).enumerate() {
+                    if !item.errors.is_empty() {
+                        diagnostics.check(off.into(), format!("Invalid MUTF-8 in string #{}: {:?}", idx, item.errors))?;
+                    }
+                    sizes.sizes.insert(off, item.size);
+                    strings.push(item.value);
+                }
+                (string_ids, strings)
+            }
+        };
+        report("string_data", strings.len(), strings.len(), reader)?;
+        let type_ids = match reusable.type_ids.take() {
+            Some(type_ids) => type_ids,
+            None => raw_dex::parse_type_ids(&header, reader).map_err(context(|| "type_ids".to_owned(), header.type_ids_off))?,
+        };
+        report("type_ids", type_ids.len(), type_ids.len(), reader)?;
+        let proto_ids = match reusable.proto_ids.take() {
+            Some(proto_ids) => proto_ids,
+            None => raw_dex::parse_proto_ids(&header, reader).map_err(context(|| "proto_ids".to_owned(), header.proto_ids_off))?,
+        };
+        report("proto_ids", proto_ids.len(), proto_ids.len(), reader)?;
+        let field_ids = match reusable.field_ids.take() {
+            Some(field_ids) => field_ids,
+            None => raw_dex::parse_field_ids(&header, reader).map_err(context(|| "field_ids".to_owned(), header.field_ids_off))?,
+        };
+        report("field_ids", field_ids.len(), field_ids.len(), reader)?;
+        let method_ids = match reusable.method_ids.take() {
+            Some(method_ids) => method_ids,
+            None => raw_dex::parse_method_ids(&header, reader).map_err(context(|| "method_ids".to_owned(), header.method_ids_off))?,
+        };
+        report("method_ids", method_ids.len(), method_ids.len(), reader)?;
+        let class_defs = match reusable.class_defs.take() {
+            Some(class_defs) => class_defs,
+            None => raw_dex::parse_class_defs(&header, reader).map_err(context(|| "class_defs".to_owned(), header.class_defs_off))?,
+        };
+        report("class_defs", class_defs.len(), class_defs.len(), reader)?;
+        let counts = check_ids(&header, strings.len(), &type_ids, &proto_ids, &field_ids, &method_ids, &class_defs)?;
+        let mut class_def_indices = vec![NO_INDEX; type_ids.len()];
+        for (idx, class_def) in class_defs.iter().enumerate().rev() {
+            class_def_indices[class_def.class_idx as usize] = idx as u32;
+        }
+
+        let span = tracing::debug_span!("type_lists").entered();
+        let mut type_lists = BTreeMap::new();
+        let type_list_offs = proto_ids.iter().map(|it| it.parameters_off)
+            .chain(class_defs.iter().map(|it| it.interfaces_off));
+        for off in type_list_offs {
+            if off != 0 && !type_lists.contains_key(&off) {
+                let type_list = reuse_or_parse(&mut reusable.type_lists, &mut sizes, off, reader, raw_dex::parse_type_list)
+                    .and_then(|it| counts.type_list(&it, off).map(|_| it))
+                    .map_err(context(|| "type_list".to_owned(), off));
+                if let Some(type_list) = diagnostics.recover(type_list, off)? {
+                    type_lists.insert(off, type_list);
+                }
+            }
+        }
+        report("type_lists", type_lists.len(), type_lists.len(), reader)?;
+        tracing::debug!(size = type_lists.len());
+        span.exit();
+        let ids = Ids { strings: &strings, type_ids: &type_ids, proto_ids: &proto_ids, method_ids: &method_ids, type_lists: &type_lists };
+        let class = |class_def: &ClassDef| ids.type_descriptor(class_def.class_idx);
+
+        let span = tracing::debug_span!("class_data", size = class_defs.len()).entered();
+        let mut class_data = Vec::with_capacity(class_defs.len());
+        for class_def in &class_defs {
+            let off = class_def.class_data_off;
+            class_data.push(if off == 0 { None } else {
+                let item = reuse_or_parse(&mut reusable.class_data, &mut sizes, off, reader, ClassData::from_reader)
+                    .and_then(|it| counts.class_data(&it, off).map(|_| it))
+                    .map_err(context(|| format!("class_data_item of class {}", class(class_def)), off));
+                diagnostics.recover(item, off)?
+            });
+            report("class_data", class_data.len(), class_defs.len(), reader)?;
+        }
+        span.exit();
+
+        let span = tracing::debug_span!("code_items").entered();
+        let mut code_items = BTreeMap::new();
+        let mut lazy_code_offs = BTreeSet::new();
+        let mut debug_info = BTreeMap::new();
+        let methods: Vec<(u32, &EncodedMethod)> = class_data.iter().flatten()
+            .flat_map(|it| [&it.direct_methods, &it.virtual_methods])
+            .flat_map(|methods| method_indices(methods).into_iter().zip(methods.iter()))
+            .collect();
+        // With Mode::Lazy and Mode::Parallel, the code items are parsed from the data of the file
+        #[cfg(feature = "std")]
+        let data = if mode != Mode::Eager && parse_code {
+            reader.seek(Start(0))?;
+            crate::io::read_to_end(reader)?
+        } else {
+            Vec::new()
+        };
+        // Code items parsed on the thread pool, with the position after each
+        #[cfg(feature = "parallel")]
+        let mut parsed_in_parallel: BTreeMap<u32, (Result<CodeItem, crate::io::Error>, u64)> = if mode == Mode::Parallel {
+            use rayon::prelude::*;
+            let mut offs: Vec<u32> = methods.iter().map(|(_, method)| method.code_off as u32)
+                .filter(|off| *off != 0 && !reusable.code_items.contains_key(off))
+                .collect();
+            offs.sort_unstable();
+            offs.dedup();
+            offs.into_par_iter().map(|off| {
+                let mut reader = crate::io::Cursor::new(&data[..]);
+                let code_item = reader.seek(Start(off.into())).and_then(|_| CodeItem::from_reader(&mut reader));
+                (off, (code_item, reader.position()))
+            }).collect()
+        } else {
+            BTreeMap::new()
+        };
+        let total_methods = methods.len();
+        for (i, (method_idx, method)) in methods.into_iter().enumerate() {
+            let code_off = method.code_off as u32;
+            if !parse_code || code_off == 0 || code_items.contains_key(&code_off) || lazy_code_offs.contains(&code_off) {
+                continue;
+            }
+            let code_item_idx = code_items.len() + lazy_code_offs.len();
+            let code_item_context = context(|| format!("code_item #{} for method {}", code_item_idx, ids.method(method_idx)), code_off);
+            if mode.lazy() {
+                // Only the offset of the debug info is read, the code item is parsed on access
+                let debug_info_off = reader.seek(Start(u64::from(code_off) + 8))
+                    .and_then(|_| raw_dex::read_u32(reader))
+                    .map_err(code_item_context);
+                if let Some(debug_info_off) = diagnostics.recover(debug_info_off, code_off)? {
+                    lazy_code_offs.insert(code_off);
+                    if parse_debug_info && debug_info_off != 0 && !debug_info.contains_key(&debug_info_off) {
+                        let item = reuse_or_parse(&mut reusable.debug_info, &mut sizes, debug_info_off, reader, DebugInfoItem::from_reader)
+                            .and_then(|it| counts.debug_info(&it, debug_info_off).map(|_| it))
+                            .map_err(context(|| format!("debug_info_item for method {}", ids.method(method_idx)), debug_info_off));
+                        if let Some(item) = diagnostics.recover(item, debug_info_off)? {
+                            debug_info.insert(debug_info_off, item);
+                        }
+                    }
+                }
+                report("code_items", i + 1, total_methods, reader)?;
+                continue;
+            }
+            let parse = |reader: &mut R| {
+                #[cfg(feature = "parallel")]
+                if let Some((code_item, end)) = parsed_in_parallel.remove(&code_off) {
+                    reader.seek(Start(end))?;
+                    return code_item;
+                }
+                CodeItem::from_reader(reader)
+            };
+            let code_item = reuse_or_parse(&mut reusable.code_items, &mut sizes, code_off, reader, parse)
+                .and_then(|it| counts.code_item(&it, code_off).map(|_| it))
+                .map_err(code_item_context);
+            let code_item = match diagnostics.recover(code_item, code_off)? {
+                Some(code_item) => code_item,
+                None => continue,
+            };
+            let debug_info_off = code_item.debug_info_off;
+            if parse_debug_info && debug_info_off != 0 && !debug_info.contains_key(&debug_info_off) {
+                let item = reuse_or_parse(&mut reusable.debug_info, &mut sizes, debug_info_off, reader, DebugInfoItem::from_reader)
+                    .and_then(|it| counts.debug_info(&it, debug_info_off).map(|_| it))
+                    .map_err(context(|| format!("debug_info_item for method {}", ids.method(method_idx)), debug_info_off));
+                if let Some(item) = diagnostics.recover(item, debug_info_off)? {
+                    debug_info.insert(debug_info_off, item);
+                }
+            }
+            code_items.insert(code_off, code_item);
+            report("code_items", i + 1, total_methods, reader)?;
+        }
+        #[cfg(feature = "std")]
+        let lazy_code = mode.lazy().then(|| LazyCode {
+            data,
+            counts,
+            code_items: lazy_code_offs.iter().map(|off| (*off, std::sync::OnceLock::new())).collect(),
+        });
+        tracing::debug!(code_items = code_items.len(), debug_info = debug_info.len());
+        span.exit();
+
+        let span = tracing::debug_span!("annotations").entered();
+        let mut annotations_directories = BTreeMap::new();
+        let mut annotation_sets = BTreeMap::new();
+        let mut annotation_set_ref_lists = BTreeMap::new();
+        let mut annotation_items = BTreeMap::new();
+        for (i, class_def) in class_defs.iter().enumerate() {
+            let off = class_def.annotations_off;
+            if !parse_annotations || off == 0 || annotations_directories.contains_key(&off) {
+                continue;
+            }
+            let directory = reuse_or_parse(&mut reusable.annotations_directories, &mut sizes, off, reader, AnnotationsDirectory::from_reader)
+                .and_then(|it| counts.annotations_directory(&it, off).map(|_| it))
+                .map_err(context(|| format!("annotations_directory_item of class {}", class(class_def)), off));
+            let directory = match diagnostics.recover(directory, off)? {
+                Some(directory) => directory,
+                None => continue,
+            };
+            let mut set_offs: Vec<u32> = directory.field_annotations.iter().map(|it| it.annotations_off)
+                .chain(directory.method_annotations.iter().map(|it| it.annotations_off))
+                .chain(Some(directory.class_annotations_off))
+                .collect();
+            for parameter_annotations in &directory.parameter_annotations {
+                let ref_list_off = parameter_annotations.annotations_off;
+                if let Entry::Vacant(entry) = annotation_set_ref_lists.entry(ref_list_off) {
+                    let list = reuse_or_parse(&mut reusable.annotation_set_ref_lists, &mut sizes, ref_list_off, reader, raw_dex::read_offset_list)
+                        .map_err(context(|| format!("annotation_set_ref_list of class {}", class(class_def)), ref_list_off));
+                    if let Some(list) = diagnostics.recover(list, ref_list_off)? {
+                        entry.insert(list);
+                    }
+                }
+                if let Some(list) = annotation_set_ref_lists.get(&ref_list_off) {
+                    set_offs.extend(list);
+                }
+            }
+            for set_off in set_offs {
+                if set_off == 0 || annotation_sets.contains_key(&set_off) {
+                    continue;
+                }
+                let set = reuse_or_parse(&mut reusable.annotation_sets, &mut sizes, set_off, reader, raw_dex::read_offset_list)
+                    .map_err(context(|| format!("annotation_set_item of class {}", class(class_def)), set_off));
+                let set = match diagnostics.recover(set, set_off)? {
+                    Some(set) => set,
+                    None => continue,
+                };
+                for &item_off in &set {
+                    if let Entry::Vacant(entry) = annotation_items.entry(item_off) {
+                        let item = reuse_or_parse(&mut reusable.annotation_items, &mut sizes, item_off, reader, AnnotationItem::from_reader)
+                            .and_then(|it| counts.annotation(&it.annotation, item_off).map(|_| it))
+                            .map_err(context(|| format!("annotation_item of class {}", class(class_def)), item_off));
+                        if let Some(item) = diagnostics.recover(item, item_off)? {
+                            entry.insert(item);
+                        }
+                    }
+                }
+                annotation_sets.insert(set_off, set);
+            }
+            annotations_directories.insert(off, directory);
+            report("annotations", i + 1, class_defs.len(), reader)?;
+        }
+        tracing::debug!(directories = annotations_directories.len(), sets = annotation_sets.len(), items = annotation_items.len());
+        span.exit();
+
+        let mut static_values = BTreeMap::new();
+        for class_def in &class_defs {
+            let off = class_def.static_values_off;
+            if !parse_static_values || off == 0 || static_values.contains_key(&off) {
+                continue;
+            }
+            let values = reuse_or_parse(&mut reusable.static_values, &mut sizes, off, reader, raw_dex::parse_encoded_array)
+                .and_then(|it| it.iter().try_for_each(|value| counts.encoded_value(value, off)).map(|_| it))
+                .map_err(context(|| format!("static values of class {}", class(class_def)), off));
+            if let Some(values) = diagnostics.recover(values, off)? {
+                static_values.insert(off, values);
+            }
+        }
+        report("static_values", static_values.len(), static_values.len(), reader)?;
+
+        Ok(DexFile {
+            header,
+            map_list,
+            strings,
+            string_data_offs,
+            type_ids,
+            proto_ids,
+            field_ids,
+            method_ids,
+            class_defs,
+            class_def_indices,
+            class_data,
+            type_lists,
+            code_items,
+            debug_info,
+            annotations_directories,
+            annotation_sets,
+            annotation_set_ref_lists,
+            annotation_items,
+            static_values,
+            link_data,
+            unknown_sections,
+            item_sizes: sizes.sizes,
+            #[cfg(feature = "std")]
+            lazy_code,
+        })
+    }
+
+    /// Dex Format Version as declared in the magic bytes, 0 if they were replaced by invalid ones after parsing
+    pub fn version(&self) -> u16 {
+        DexHeader::parse_version(&self.header.magic).unwrap_or(0)
+    }
+
+    pub fn string(&self, string_idx: u32) -> &str {
+        &self.strings[string_idx as usize]
+    }
+
+    pub fn type_descriptor(&self, type_idx: u32) -> &str {
+        self.string(self.type_ids[type_idx as usize])
+    }
+
+    /// Returns the type list at the given offset, an offset of 0 denoting an empty list
+    pub fn type_list(&self, off: u32) -> &[u16] {
+        self.type_lists.get(&off).map_or(&[], |it| it)
+    }
+
+    /// Returns the descriptors of the parameters of a prototype
+    pub fn proto_parameters(&self, proto_idx: u32) -> Vec<&str> {
+        let proto = &self.proto_ids[proto_idx as usize];
+        self.type_list(proto.parameters_off).iter()
+            .map(|it| self.type_descriptor(*it as u32))
+            .collect()
+    }
+
+    /// Returns the signature of a prototype in descriptor format, e.g. "(ILjava/lang/String;)V"
+    pub fn proto_signature(&self, proto_idx: u32) -> String {
+        let proto = &self.proto_ids[proto_idx as usize];
+        format!("({}){}", self.proto_parameters(proto_idx).concat(), self.type_descriptor(proto.return_type_idx))
+    }
+
+    pub fn method_class(&self, method_idx: u32) -> &str {
+        self.type_descriptor(self.method_ids[method_idx as usize].class_idx as u32)
+    }
+
+    pub fn method_name(&self, method_idx: u32) -> &str {
+        self.string(self.method_ids[method_idx as usize].name_idx)
+    }
+
+    pub fn method_signature(&self, method_idx: u32) -> String {
+        self.proto_signature(self.method_ids[method_idx as usize].proto_idx as u32)
+    }
+
+    pub fn field_class(&self, field_idx: u32) -> &str {
+        self.type_descriptor(self.field_ids[field_idx as usize].class_idx as u32)
+    }
+
+    pub fn field_name(&self, field_idx: u32) -> &str {
+        self.string(self.field_ids[field_idx as usize].name_idx)
+    }
+
+    pub fn field_type(&self, field_idx: u32) -> &str {
+        self.type_descriptor(self.field_ids[field_idx as usize].type_idx as u32)
+    }
+
+    /// Index of the string equal to `value`, by binary search over the string ids (sorted by their
+    /// UTF-16 code units)
+    pub fn string_idx(&self, value: &str) -> Option<u32> {
+        self.strings.binary_search_by(|it| it.encode_utf16().cmp(value.encode_utf16())).ok().map(|it| it as u32)
+    }
+
+    /// Index of the type with the descriptor, by binary search over the type ids (sorted by string index)
+    pub fn type_idx(&self, descriptor: &str) -> Option<u32> {
+        let string_idx = self.string_idx(descriptor)?;
+        self.type_ids.binary_search(&string_idx).ok().map(|it| it as u32)
+    }
+
+    /// Index into `class_defs` of the definition of a type, None if this file does not define it
+    pub fn type_class_def_idx(&self, type_idx: u32) -> Option<usize> {
+        self.class_def_indices.get(type_idx as usize).copied().filter(|it| *it != NO_INDEX).map(|it| it as usize)
+    }
+
+    /// Class definition of the class with the descriptor (e.g. `Lcom/foo/Bar;`), in O(log n)
+    pub fn class_def(&self, descriptor: &str) -> Option<&ClassDef> {
+        self.type_class_def_idx(self.type_idx(descriptor)?).map(|it| &self.class_defs[it])
+    }
+
+    /// Index of the prototype with the signature in descriptor form (e.g. `(ILjava/lang/String;)V`), by
+    /// binary search over the proto ids (sorted by return type and parameters)
+    pub fn proto_idx(&self, signature: &str) -> Option<u32> {
+        let (parameters, return_type) = signature.strip_prefix('(')?.split_once(')')?;
+        let return_type_idx = self.type_idx(return_type)?;
+        let parameters: Vec<u16> = split_descriptors(parameters)?.into_iter()
+            .map(|it| self.type_idx(it).map(|idx| idx as u16))
+            .collect::<Option<_>>()?;
+        self.proto_ids.binary_search_by(|proto| {
+            proto.return_type_idx.cmp(&return_type_idx).then_with(|| self.type_list(proto.parameters_off).cmp(&parameters))
+        }).ok().map(|it| it as u32)
+    }
+
+    /// Index of the field `class->name:type_descriptor`, by binary search over the field ids (sorted by
+    /// class, name and type)
+    pub fn field_idx(&self, class: &str, name: &str, type_descriptor: &str) -> Option<u32> {
+        let key = (self.type_idx(class)?, self.string_idx(name)?, self.type_idx(type_descriptor)?);
+        self.field_ids.binary_search_by_key(&key, |it| (it.class_idx.into(), it.name_idx, it.type_idx.into())).ok().map(|it| it as u32)
+    }
+
+    /// Index of the method `class->name(signature)`, by binary search over the method ids (sorted by
+    /// class, name and prototype)
+    pub fn method_idx(&self, class: &str, name: &str, signature: &str) -> Option<u32> {
+        let key = (self.type_idx(class)?, self.string_idx(name)?, self.proto_idx(signature)?);
+        self.method_ids.binary_search_by_key(&key, |it| (it.class_idx.into(), it.name_idx, it.proto_idx.into())).ok().map(|it| it as u32)
+    }
+
+    fn is_defined(&self, type_idx: u32) -> bool {
+        self.class_def_indices[type_idx as usize] != NO_INDEX
+    }
+
+    /// Indices of the types defined by the class definitions of this file, in the order of the type_ids
+    pub fn defined_types(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.type_ids.len() as u32).filter(move |it| self.is_defined(*it))
+    }
+
+    /// Indices of the class types referenced but not defined by this file, e.g. of the platform or of
+    /// libraries. Array and primitive types are neither defined nor external.
+    pub fn external_types(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.type_ids.len() as u32).filter(move |it| !self.is_defined(*it) && self.type_descriptor(*it).starts_with('L'))
+    }
+
+    /// Indices of the fields of classes defined by this file. Members are partitioned by the class of
+    /// their id, so a reference to an inherited field through a class of this file is defined as well.
+    pub fn defined_fields(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.field_ids.len() as u32).filter(move |it| self.is_defined(self.field_ids[*it as usize].class_idx.into()))
+    }
+
+    /// Indices of the fields of classes not defined by this file, see defined_fields
+    pub fn external_fields(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.field_ids.len() as u32).filter(move |it| !self.is_defined(self.field_ids[*it as usize].class_idx.into()))
+    }
+
+    /// Indices of the methods of classes defined by this file, see defined_fields
+    pub fn defined_methods(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.method_ids.len() as u32).filter(move |it| self.is_defined(self.method_ids[*it as usize].class_idx.into()))
+    }
+
+    /// Indices of the methods of classes not defined by this file (including array classes, e.g.
+    /// `[I->clone()`), see defined_fields
+    pub fn external_methods(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.method_ids.len() as u32).filter(move |it| !self.is_defined(self.method_ids[*it as usize].class_idx.into()))
+    }
+
+    /// Code item at `code_off` (of an EncodedMethod), parsed on first access with Mode::Lazy
+    pub fn code_item(&self, code_off: u64) -> Option<&CodeItem> {
+        if code_off == 0 {
+            return None;
+        }
+        #[cfg(feature = "std")]
+        if let Some(lazy_code) = &self.lazy_code {
+            return lazy_code.get(code_off as u32);
+        }
+        self.code_items.get(&(code_off as u32))
+    }
+
+    /// Bytes of the header
+    pub fn header_span(&self) -> ByteSpan {
+        ByteSpan { offset: 0, len: self.header.header_size }
+    }
+
+    /// Bytes of the header field `name`, e.g. `checksum`, see DexHeader::FIELDS
+    pub fn header_field_span(&self, name: &str) -> Option<ByteSpan> {
+        DexHeader::FIELDS.iter().find(|it| it.0 == name).map(|&(_, offset, len)| ByteSpan { offset: offset as u32, len: len as u32 })
+    }
+
+    /// Bytes of the map list, including its size
+    pub fn map_list_span(&self) -> ByteSpan {
+        ByteSpan { offset: self.header.map_off, len: 4 + 12 * self.map_list.len() as u32 }
+    }
+
+    pub fn string_id_span(&self, string_idx: u32) -> Option<ByteSpan> {
+        id_span(self.header.string_ids_off, self.header.string_ids_size, 4, string_idx)
+    }
+
+    pub fn type_id_span(&self, type_idx: u32) -> Option<ByteSpan> {
+        id_span(self.header.type_ids_off, self.header.type_ids_size, 4, type_idx)
+    }
+
+    pub fn proto_id_span(&self, proto_idx: u32) -> Option<ByteSpan> {
+        id_span(self.header.proto_ids_off, self.header.proto_ids_size, 12, proto_idx)
+    }
+
+    pub fn field_id_span(&self, field_idx: u32) -> Option<ByteSpan> {
+        id_span(self.header.field_ids_off, self.header.field_ids_size, 8, field_idx)
+    }
+
+    pub fn method_id_span(&self, method_idx: u32) -> Option<ByteSpan> {
+        id_span(self.header.method_ids_off, self.header.method_ids_size, 8, method_idx)
+    }
+
+    pub fn class_def_span(&self, class_def_idx: usize) -> Option<ByteSpan> {
+        id_span(self.header.class_defs_off, self.header.class_defs_size, 32, u32::try_from(class_def_idx).ok()?)
+    }
+
+    /// Bytes of the string_data_item of a string
+    pub fn string_data_span(&self, string_idx: u32) -> Option<ByteSpan> {
+        self.item_span(*self.string_data_offs.get(string_idx as usize)?)
+    }
+
+    /// Bytes of the parsed item of the data section at `off`, e.g. a class_data_off, code_off or
+    /// debug_info_off. None for offsets of items that were not parsed.
+    pub fn item_span(&self, off: u32) -> Option<ByteSpan> {
+        self.item_sizes.get(&off).map(|len| ByteSpan { offset: off, len: *len })
+    }
+
+    /// Decodes the line number table of the debug info item
+    pub fn positions(&self, debug_info: &DebugInfoItem) -> Vec<PositionInfo> {
+        let mut positions = Vec::new();
+        let mut address = 0u32;
+        let mut line = debug_info.line_start as i64;
+        // A malformed item advancing the address past u32 ends the table
+        for insn in &debug_info.bytecode {
+            match insn {
+                DebugInstruction::AdvancePc(diff) => match address.checked_add(*diff as u32) {
+                    Some(next) => address = next,
+                    None => break,
+                },
+                DebugInstruction::AdvanceLine(diff) => line += diff,
+                DebugInstruction::Special(opcode) => {
+                    let adjusted = *opcode as i64 - DBG_FIRST_SPECIAL;
+                    address = match address.checked_add((adjusted / DBG_LINE_RANGE) as u32) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    line += DBG_LINE_BASE + adjusted % DBG_LINE_RANGE;
+                    positions.push(PositionInfo { address, line: line as u32 });
+                }
+                _ => {}
+            }
+        }
+        positions
+    }
+
+    /// Try items of a code item with the handlers they reference by handler_off, in the order of the
+    /// try items
+    pub fn catches(&self, code: &CodeItem) -> Vec<CatchInfo<'_>> {
+        code.tries.iter().map(|try_item| {
+            let handlers = try_item.resolve_handler(code).map_or_else(Vec::new, |handler| {
+                    handler.handlers.iter()
+                        .map(|pair| CatchHandlerInfo {
+                            exception: Some(self.type_descriptor(pair.type_idx as u32)),
+                            address: pair.addr as u32,
+                        })
+                        .chain(handler.catch_all_addr.map(|addr| CatchHandlerInfo { exception: None, address: addr as u32 }))
+                        .collect()
+                });
+            CatchInfo {
+                start_address: try_item.start_addr,
+                end_address: try_item.start_addr.saturating_add(try_item.insn_count.into()),
+                handlers,
+            }
+        }).collect()
+    }
+
+    /// Decodes the local variable table of a method from the debug info of its code item, in the
+    /// order in which the variables go out of scope (matching the order of the reference implementation).
+    pub fn locals(&self, method_idx: u32, access_flags: u64, code: &CodeItem) -> Vec<LocalInfo<'_>> {
+        let debug_info = match self.debug_info.get(&code.debug_info_off) {
+            Some(debug_info) if code.debug_info_off != 0 => debug_info,
+            _ => return Vec::new(),
+        };
+        let optional_string = |idx: OptionalIdx| idx.get().map(|it| self.string(it));
+        let optional_type = |idx: OptionalIdx| idx.get().map(|it| self.type_descriptor(it));
+
+        let registers_size = code.registers_size as usize;
+        let mut locals: Vec<Option<LocalInfo>> = vec![None; registers_size];
+        let mut is_live = vec![false; registers_size];
+        let mut closed = Vec::new();
+
+        let mut arg_reg = code.registers_size.wrapping_sub(code.ins_size) as usize;
+        if access_flags & ACC_STATIC == 0 && arg_reg < registers_size {
+            locals[arg_reg] = Some(LocalInfo {
+                name: Some("this"),
+                descriptor: Some(self.method_class(method_idx)),
+                signature: None,
+                start_address: 0,
+                end_address: 0,
+                reg: arg_reg as u16,
+            });
+            is_live[arg_reg] = true;
+            arg_reg += 1;
+        }
+        let parameters = self.proto_parameters(self.method_ids[method_idx as usize].proto_idx as u32);
+        for (name_idx, descriptor) in debug_info.parameter_names.iter().zip(parameters) {
+            if arg_reg >= registers_size {
+                break;
+            }
+            locals[arg_reg] = Some(LocalInfo {
+                name: optional_string(*name_idx),
+                descriptor: Some(descriptor),
+                signature: None,
+                start_address: 0,
+                end_address: 0,
+                reg: arg_reg as u16,
+            });
+            is_live[arg_reg] = true;
+            arg_reg += if descriptor == "J" || descriptor == "D" { 2 } else { 1 };
+        }
+
+        let mut address = 0u32;
+        // Like in positions, a malformed item advancing the address past u32 ends the ranges
+        for insn in &debug_info.bytecode {
+            match *insn {
+                DebugInstruction::AdvancePc(diff) => match address.checked_add(diff as u32) {
+                    Some(next) => address = next,
+                    None => break,
+                },
+                DebugInstruction::StartLocal { register_num, name_idx, type_idx } |
+                DebugInstruction::StartLocalExtended { register_num, name_idx, type_idx, .. } => {
+                    let reg = register_num as usize;
+                    if reg >= registers_size {
+                        break;
+                    }
+                    if is_live[reg] {
+                        // Parameters with generic types are declared with empty ranges, skip them
+                        if let Some(local) = locals[reg].as_ref().filter(|_| address != 0) {
+                            closed.push(LocalInfo { end_address: address, ..local.clone() });
+                        }
+                    }
+                    let sig_idx = match *insn {
+                        DebugInstruction::StartLocalExtended { sig_idx, .. } => sig_idx,
+                        _ => OptionalIdx::NONE,
+                    };
+                    locals[reg] = Some(LocalInfo {
+                        name: optional_string(name_idx),
+                        descriptor: optional_type(type_idx),
+                        signature: optional_string(sig_idx),
+                        start_address: address,
+                        end_address: 0,
+                        reg: reg as u16,
+                    });
+                    is_live[reg] = true;
+                }
+                DebugInstruction::EndLocal(register_num) => {
+                    let reg = register_num as usize;
+                    if reg < registers_size && is_live[reg] {
+                        if let Some(local) = &locals[reg] {
+                            closed.push(LocalInfo { end_address: address, ..local.clone() });
+                        }
+                        is_live[reg] = false;
+                    }
+                }
+                DebugInstruction::RestartLocal(register_num) => {
+                    let reg = register_num as usize;
+                    if reg < registers_size && !is_live[reg] {
+                        if let Some(local) = &mut locals[reg] {
+                            local.start_address = address;
+                            is_live[reg] = true;
+                        }
+                    }
+                }
+                DebugInstruction::Special(opcode) => match address.checked_add(((opcode as i64 - DBG_FIRST_SPECIAL) / DBG_LINE_RANGE) as u32) {
+                    Some(next) => address = next,
+                    None => break,
+                },
+                _ => {}
+            }
+        }
+
+        let insns_size = code.insns.len() as u32;
+        for (local, _) in locals.into_iter().zip(is_live).filter(|(_, live)| *live) {
+            if let Some(local) = local {
+                closed.push(LocalInfo { end_address: insns_size, ..local });
+            }
+        }
+        closed
+    }
+}
+
+/// Converts the index differences of encoded fields into absolute field indices
+pub fn field_indices(fields: &[EncodedField]) -> Vec<u32> {
+    let mut idx = 0u64;
+    fields.iter().map(|it| {
+        idx += it.field_idx_diff;
+        idx as u32
+    }).collect()
+}
+
+/// Converts the index differences of encoded methods into absolute method indices
+pub fn method_indices(methods: &[EncodedMethod]) -> Vec<u32> {
+    let mut idx = 0u64;
+    methods.iter().map(|it| {
+        idx += it.method_idx_diff;
+        idx as u32
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureMethod};
+
+    #[test]
+    fn debug_info_address_overflow() {
+        let mut dex = Fixture::new().parse();
+        let bytecode = vec![DebugInstruction::Special(0x1f), DebugInstruction::AdvancePc(u32::MAX.into()), DebugInstruction::Special(0x1f),
+                            DebugInstruction::EndLocal(0)];
+        dex.debug_info.insert(1, DebugInfoItem { line_start: 1, parameter_names: Vec::new(), bytecode });
+        let code = CodeItem { registers_size: 1, ins_size: 1, outs_size: 0, debug_info_off: 1, insns: vec![0x000e], tries: Vec::new(), handlers: Vec::new() };
+
+        let positions: Vec<(u32, u32)> = dex.positions(&dex.debug_info[&1]).iter().map(|it| (it.address, it.line)).collect();
+        assert_eq!(positions, [(1, 3)]);
+        // The end of the local is not reached, it spans the whole code
+        let locals: Vec<(Option<&str>, u32, u32)> = dex.locals(0, 0, &code).iter().map(|it| (it.name, it.start_address, it.end_address)).collect();
+        assert_eq!(locals, [(Some("this"), 0, 1)]);
+    }
+
+    #[test]
+    fn reparse_options() {
+        let data = Fixture::new().build();
+        let mut changed = data.clone();
+        changed[8] ^= 1;
+        let old = || Fixture::new().parse();
+        assert!(ParseOptions::new().strictness(Strictness::Strict).reparse(old(), &data, &changed).is_err());
+        let dex = ParseOptions::new().reparse(old(), &data, &changed).unwrap();
+        assert_eq!(dex.strings, old().strings);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn modes() {
+        let data = Fixture::new().method(FixtureMethod::new("other", "V", &[], vec![0x0000, 0x000e])).build();
+        let code = |dex: &DexFile| -> Vec<Vec<u16>> {
+            dex.classes().flat_map(|it| it.methods()).map(|it| dex.code_item(it.encoded.code_off).unwrap().insns.clone()).collect()
+        };
+        let eager = ParseOptions::new().parse_bytes(&data).unwrap();
+        assert_eq!(code(&eager).len(), 2);
+        for mode in [Mode::Lazy, Mode::Parallel] {
+            let dex = ParseOptions::new().mode(mode).parse_bytes(&data).unwrap();
+            assert_eq!(code(&dex), code(&eager), "{:?}", mode);
+            assert_eq!(dex.debug_info.len(), eager.debug_info.len());
+        }
+        assert!(ParseOptions::new().mode(Mode::Lazy).parse_bytes(&data).unwrap().code_items.is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::dex_file::DexFile;
+use crate::editor::DexEditor;
+use crate::instructions::Instructions;
+use crate::prelude::*;
+use crate::raw_dex::{AnnotationItem, EncodedValue};
+
+/*
+Versions of the dex format needed by the features a dex file uses. 035 is read by all runtimes, 037
+added methods with code in interfaces (default and static interface methods, Android 7.0), 038
+invoke-polymorphic, invoke-custom and the call site and method handle sections (Android 8.0) and 039
+const-method-handle and const-method-type (Android 9). The runtime rejects a file using features newer
+than its declared version. hiddenapi_class_data_item is only written to the dex files of the boot
+class path from Android 10 on, which declare 039, so an older version with the section points to a
+modified file. Method handles and method types are also used by the encoded values of annotations and
+static fields, which need 038 like the sections they index.
+ */
+
+/// Version read by all runtimes
+pub const OLDEST_VERSION: u16 = 35;
+const INTERFACE_METHODS_VERSION: u16 = 37;
+const METHOD_HANDLES_VERSION: u16 = 38;
+const CONST_METHOD_HANDLE_VERSION: u16 = 39;
+const HIDDENAPI_VERSION: u16 = 39;
+
+const ACC_INTERFACE: u32 = 0x200;
+
+const TYPE_CALL_SITE_ID_ITEM: u16 = 0x0007;
+const TYPE_METHOD_HANDLE_ITEM: u16 = 0x0008;
+const TYPE_HIDDENAPI_CLASS_DATA_ITEM: u16 = 0xf000;
+
+/// A feature of the dex format used by a dex file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureUse {
+    /// e.g. invoke-custom or call_site_ids
+    pub feature: &'static str,
+    /// Oldest version of the format supporting the feature
+    pub version: u16,
+    /// Number of uses, instructions, methods or items of the section
+    pub count: usize,
+    /// Where the feature is used first, e.g. `Lcom/example/Foo;->run()V at 0x0004`
+    pub first: String,
+}
+
+/// Which version DexEditor::to_bytes writes, see update_version
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VersionUpdate {
+    /// The version of the parsed file, also if the features used need a newer one
+    Keep,
+    /// The oldest version supporting all features used if the version of the parsed file is older
+    Raise,
+    /// The oldest version supporting all features used, also if the version of the parsed file is newer.
+    /// Like Raise if an instruction fails to decode, the features of the instructions after it are unknown.
+    Lowest,
+}
+
+/// Uses of the features by first use
+#[derive(Default)]
+struct Uses {
+    uses: Vec<FeatureUse>,
+    /// Whether an instruction failed to decode, the instructions after it are not checked
+    incomplete: bool,
+}
+
+impl Uses {
+    fn add(&mut self, feature: &'static str, version: u16, count: usize, first: impl FnOnce() -> String) {
+        match self.uses.iter_mut().find(|it| it.feature == feature) {
+            Some(existing) => existing.count += count,
+            None if count > 0 => self.uses.push(FeatureUse { feature, version, count, first: first() }),
+            None => {}
+        }
+    }
+
+    /// Adds the method handles and method types of an encoded value and the values it contains
+    fn value(&mut self, value: &EncodedValue, location: &dyn Fn() -> String) {
+        match value {
+            EncodedValue::MethodHandle(_) => self.add("method handle value", METHOD_HANDLES_VERSION, 1, location),
+            EncodedValue::MethodType(_) => self.add("method type value", METHOD_HANDLES_VERSION, 1, location),
+            EncodedValue::Array(values) => values.iter().for_each(|it| self.value(it, location)),
+            EncodedValue::Annotation(annotation) => annotation.elements.iter().for_each(|it| self.value(&it.value, location)),
+            _ => {}
+        }
+    }
+
+    fn annotations(&mut self, annotations: &[AnnotationItem], location: &dyn Fn() -> String) {
+        for item in annotations {
+            item.annotation.elements.iter().for_each(|it| self.value(&it.value, location));
+        }
+    }
+
+    /// Adds the features used by a method. Instructions after one failing to decode are not checked, the
+    /// verifier reports them.
+    fn method(&mut self, interface: bool, name: &str, insns: Option<&[u16]>, method: impl Fn() -> String) {
+        let insns = match insns {
+            Some(insns) => insns,
+            None => return,
+        };
+        // Static initializers are the only methods of interfaces with code before 037
+        if interface && name != "<clinit>" {
+            self.add("interface method with code", INTERFACE_METHODS_VERSION, 1, &method);
+        }
+        for decoded in Instructions::new(insns) {
+            let (pc, insn) = match decoded {
+                Ok(decoded) => decoded,
+                Err(_) => {
+                    self.incomplete = true;
+                    return;
+                }
+            };
+            let version = match insn.opcode {
+                0xfa..=0xfd => METHOD_HANDLES_VERSION,
+                0xfe | 0xff => CONST_METHOD_HANDLE_VERSION,
+                _ => continue,
+            };
+            self.add(insn.name(), version, 1, || format!("{} at 0x{:04x}", method(), pc));
+        }
+    }
+}
+
+/// Oldest version supporting all of the features
+pub fn required_version(uses: &[FeatureUse]) -> u16 {
+    uses.iter().map(|it| it.version).fold(OLDEST_VERSION, u16::max)
+}
+
+/// The features of the dex format newer than 035 used by the dex file, in the order of their first use.
+/// The ones with a version newer than DexFile::version are not supported by the declared version.
+pub fn feature_uses(dex: &DexFile) -> Vec<FeatureUse> {
+    let mut uses = Uses::default();
+    for item in &dex.map_list {
+        let (feature, version) = match item.item_type {
+            TYPE_CALL_SITE_ID_ITEM => ("call_site_ids", METHOD_HANDLES_VERSION),
+            TYPE_METHOD_HANDLE_ITEM => ("method_handles", METHOD_HANDLES_VERSION),
+            TYPE_HIDDENAPI_CLASS_DATA_ITEM => ("hiddenapi_class_data", HIDDENAPI_VERSION),
+            _ => continue,
+        };
+        uses.add(feature, version, item.size as usize, || format!("section at 0x{:08x}", item.offset));
+    }
+    for (off, values) in &dex.static_values {
+        values.iter().for_each(|it| uses.value(it, &|| format!("static values at 0x{:08x}", off)));
+    }
+    for (off, item) in &dex.annotation_items {
+        uses.annotations(core::slice::from_ref(item), &|| format!("annotation at 0x{:08x}", off));
+    }
+    for class in dex.classes() {
+        let interface = class.def().access_flags & ACC_INTERFACE != 0;
+        for method in class.methods() {
+            let method_idx = method.method_idx;
+            let insns = dex.code_item(method.encoded.code_off).map(|it| it.insns.as_slice());
+            uses.method(interface, dex.method_name(method_idx), insns,
+                        || format!("{}->{}{}", dex.method_class(method_idx), dex.method_name(method_idx), dex.method_signature(method_idx)));
+        }
+    }
+    uses.uses
+}
+
+/// The features of the dex format newer than 035 used by the edited dex file, see feature_uses. The
+/// editor does not write the hiddenapi section.
+pub fn edited_feature_uses(editor: &DexEditor) -> Vec<FeatureUse> {
+    edited_uses(editor).uses
+}
+
+fn edited_uses(editor: &DexEditor) -> Uses {
+    let mut uses = Uses::default();
+    uses.add("call_site_ids", METHOD_HANDLES_VERSION, editor.call_sites.len(), || "call site 0".to_owned());
+    uses.add("method_handles", METHOD_HANDLES_VERSION, editor.method_handles.len(), || "method handle 0".to_owned());
+    for class in &editor.classes {
+        let interface = class.access_flags & ACC_INTERFACE != 0;
+        let class_name = || editor.types.get(class.class_idx as usize)
+            .and_then(|it| editor.strings.get(*it as usize))
+            .map_or_else(|| format!("class #{}", class.class_idx), |it| it.clone());
+        uses.annotations(&class.annotations, &class_name);
+        for field in class.fields() {
+            field.static_value.iter().for_each(|it| uses.value(it, &class_name));
+            uses.annotations(&field.annotations, &class_name);
+        }
+        for method in class.methods() {
+            let method_idx = method.method_idx;
+            let method_ref = editor.methods.get(method_idx as usize);
+            let name = method_ref.and_then(|it| editor.strings.get(it.name_idx as usize)).map_or("", |it| it.as_str());
+            uses.method(interface, name, method.code.as_ref().map(|it| it.insns.as_slice()),
+                        || match method_ref {
+                            Some(_) => editor.qualified_method_name(method_idx),
+                            None => format!("method #{}", method_idx),
+                        });
+            uses.annotations(&method.annotations, &class_name);
+            method.parameter_annotations.iter().for_each(|it| uses.annotations(it, &class_name));
+        }
+    }
+    uses
+}
+
+/// Sets the version written by DexEditor::to_bytes according to `update`, returns the features used that
+/// it does not support (only with VersionUpdate::Keep)
+pub fn update_version(editor: &mut DexEditor, update: VersionUpdate) -> Vec<FeatureUse> {
+    let Uses { mut uses, incomplete } = edited_uses(editor);
+    let required = required_version(&uses);
+    editor.version = match update {
+        VersionUpdate::Keep => editor.version,
+        VersionUpdate::Raise => editor.version.max(required),
+        VersionUpdate::Lowest if incomplete => editor.version.max(required),
+        VersionUpdate::Lowest => required,
+    };
+    uses.retain(|it| it.version > editor.version);
+    uses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::fixture::{self, Fixture, FixtureMethod};
+    use crate::raw_dex::{AnnotationElement, EncodedAnnotation, Visibility};
+
+    #[test]
+    fn version_of_features() {
+        let mut code = CodeBuilder::new();
+        code.emit("const-method-type", &[Reg(0), Idx(0)]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        let method = FixtureMethod::new("type", "Ljava/lang/Object;", &[], code.build().unwrap()).registers_size(1);
+        let fixture = Fixture::empty().method(method);
+        let uses = feature_uses(&fixture.parse());
+        assert_eq!(uses.iter().map(|it| (it.feature, it.version, it.count)).collect::<Vec<_>>(), [("const-method-type", 39, 1)]);
+        assert!(uses[0].first.ends_with("->type()Ljava/lang/Object; at 0x0000"));
+
+        let mut editor = fixture.editor();
+        assert_eq!((update_version(&mut editor, VersionUpdate::Keep).len(), editor.version), (1, 35));
+        assert_eq!((update_version(&mut editor, VersionUpdate::Raise).len(), editor.version), (0, 39));
+        assert_eq!(fixture::rewritten(&editor).version(), 39);
+
+        let mut editor = Fixture::new().editor();
+        editor.version = 39;
+        update_version(&mut editor, VersionUpdate::Lowest);
+        assert_eq!(editor.version, 35);
+    }
+
+    #[test]
+    fn lowest_version_of_undecoded_code() {
+        // A const-string without its index, a const-method-type could follow
+        let mut editor = Fixture::empty().method(FixtureMethod::new("run", "V", &[], vec![0x000e, 0x001a])).editor();
+        editor.version = 39;
+        update_version(&mut editor, VersionUpdate::Lowest);
+        assert_eq!(editor.version, 39);
+    }
+
+    #[test]
+    fn version_of_values() {
+        let mut editor = Fixture::new().editor();
+        let element = AnnotationElement { name_idx: 0, value: EncodedValue::Array(vec![EncodedValue::MethodType(0)]) };
+        let annotation = EncodedAnnotation { type_idx: 0, elements: vec![element] };
+        editor.classes[0].annotations.push(AnnotationItem { visibility: Visibility::VisibilityRuntime, annotation });
+        let uses = edited_feature_uses(&editor);
+        assert_eq!(uses.iter().map(|it| (it.feature, it.version, it.count, it.first.as_str())).collect::<Vec<_>>(),
+                   [("method type value", 38, 1, "Lcom/example/Fixture;")]);
+        update_version(&mut editor, VersionUpdate::Lowest);
+        assert_eq!(editor.version, 38);
+        let uses = feature_uses(&fixture::rewritten(&editor));
+        assert_eq!(uses.iter().map(|it| (it.feature, it.count)).collect::<Vec<_>>(), [("method type value", 1)]);
+
+        // A definition of a method without id
+        let mut code = CodeBuilder::new();
+        code.emit("const-method-type", &[Reg(0), Idx(0)]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        let method = &mut editor.classes[0].virtual_methods[0];
+        method.method_idx = 999;
+        method.code.as_mut().unwrap().insns = code.build().unwrap();
+        assert_eq!(edited_feature_uses(&editor)[1].first, "method #999 at 0x0000");
+    }
+}
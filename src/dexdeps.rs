@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::dex_file::DexFile;
+use crate::dexdump::descriptor_to_dot;
+
+/*
+Output formats of the AOSP dexdeps tool, listing the classes, fields and methods that a dex file
+references but does not define, see
+* https://cs.android.com/android/platform/superproject/+/master:dalvik/tools/dexdeps/src/com/android/dexdeps/Output.java
+Like dexdeps, array and primitive types are never external and members are only external if their
+class is. Unlike dexdeps, classes are ordered by package first, so each package is listed once.
+ */
+
+/// A class referenced but not defined by a dex file, with its referenced members
+pub struct ExternalClass<'a> {
+    pub descriptor: &'a str,
+    /// Indices of the referenced fields, in the order of the field_ids
+    pub fields: Vec<u32>,
+    /// Indices of the referenced methods, in the order of the method_ids
+    pub methods: Vec<u32>,
+}
+
+impl ExternalClass<'_> {
+    /// Package in dotted form, empty for the default package
+    pub fn package(&self) -> String {
+        let dotted = descriptor_to_dot(self.descriptor);
+        dotted.rfind('.').map_or_else(String::new, |idx| dotted[..idx].to_owned())
+    }
+
+    /// Name of the class without its package, inner classes keep the `$` separator
+    pub fn simple_name(&self) -> String {
+        let dotted = descriptor_to_dot(self.descriptor);
+        match dotted.rfind('.') {
+            Some(idx) => dotted[idx + 1..].to_owned(),
+            None => dotted,
+        }
+    }
+}
+
+/// The classes referenced but not defined by the dex file, ordered by package and descriptor
+pub fn external_classes(dex: &DexFile) -> Vec<ExternalClass<'_>> {
+    let mut classes: BTreeMap<u32, ExternalClass> = dex.external_types()
+        .map(|idx| (idx, ExternalClass { descriptor: dex.type_descriptor(idx), fields: Vec::new(), methods: Vec::new() }))
+        .collect();
+    for idx in dex.external_fields() {
+        if let Some(class) = classes.get_mut(&(dex.field_ids[idx as usize].class_idx as u32)) {
+            class.fields.push(idx);
+        }
+    }
+    for idx in dex.external_methods() {
+        if let Some(class) = classes.get_mut(&(dex.method_ids[idx as usize].class_idx as u32)) {
+            class.methods.push(idx);
+        }
+    }
+    let mut classes: Vec<ExternalClass> = classes.into_values().collect();
+    classes.sort_by_cached_key(|it| (it.package(), it.descriptor));
+    classes
+}
+
+/// Writes the external references like `dexdeps --format=brief`, only the classes with `just_classes`
+pub fn write_brief(dex: &DexFile, just_classes: bool, out: &mut dyn Write) -> std::io::Result<()> {
+    let classes = external_classes(dex);
+    if !just_classes {
+        writeln!(out, "Classes:")?;
+    }
+    for class in &classes {
+        writeln!(out, "{}", descriptor_to_dot(class.descriptor))?;
+    }
+    if just_classes {
+        return Ok(());
+    }
+    writeln!(out, "\nFields:")?;
+    for (class, &field_idx) in classes.iter().flat_map(|class| class.fields.iter().map(move |it| (class, it))) {
+        writeln!(out, "{}.{} : {}", descriptor_to_dot(class.descriptor), dex.field_name(field_idx), dex.field_type(field_idx))?;
+    }
+    writeln!(out, "\nMethods:")?;
+    for (class, &method_idx) in classes.iter().flat_map(|class| class.methods.iter().map(move |it| (class, it))) {
+        writeln!(out, "{}.{} : {}", descriptor_to_dot(class.descriptor), dex.method_name(method_idx), dex.method_signature(method_idx))?;
+    }
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Writes the external references like `dexdeps --format=xml`, one package element per package
+pub fn write_xml(dex: &DexFile, file_name: &str, just_classes: bool, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "<external>")?;
+    writeln!(out, "<dex file=\"{}\">", escape_xml(file_name))?;
+    let mut package = None;
+    for class in external_classes(dex) {
+        let class_package = class.package();
+        if package.as_ref() != Some(&class_package) {
+            if package.is_some() {
+                writeln!(out, "</package>")?;
+            }
+            writeln!(out, "<package name=\"{}\">", escape_xml(&class_package))?;
+            package = Some(class_package);
+        }
+        writeln!(out, "  <class name=\"{}\">", escape_xml(&class.simple_name()))?;
+        if !just_classes {
+            for &field_idx in &class.fields {
+                let type_name = descriptor_to_dot(dex.field_type(field_idx));
+                writeln!(out, "    <field name=\"{}\" type=\"{}\">", escape_xml(dex.field_name(field_idx)), escape_xml(&type_name))?;
+                writeln!(out, "    </field>")?;
+            }
+            for &method_idx in &class.methods {
+                let method_id = &dex.method_ids[method_idx as usize];
+                let constructor = dex.method_name(method_idx) == "<init>";
+                if constructor {
+                    writeln!(out, "    <constructor name=\"{}\">", escape_xml(&class.simple_name()))?;
+                } else {
+                    let return_type = dex.type_descriptor(dex.proto_ids[method_id.proto_idx as usize].return_type_idx);
+                    writeln!(out, "    <method name=\"{}\" return=\"{}\">", escape_xml(dex.method_name(method_idx)), escape_xml(&descriptor_to_dot(return_type)))?;
+                }
+                for parameter in dex.proto_parameters(method_id.proto_idx as u32) {
+                    writeln!(out, "      <parameter type=\"{}\">", escape_xml(&descriptor_to_dot(parameter)))?;
+                    writeln!(out, "      </parameter>")?;
+                }
+                writeln!(out, "    {}", if constructor { "</constructor>" } else { "</method>" })?;
+            }
+        }
+        writeln!(out, "  </class>")?;
+    }
+    if package.is_some() {
+        writeln!(out, "</package>")?;
+    }
+    writeln!(out, "</dex>")?;
+    writeln!(out, "</external>")
+}
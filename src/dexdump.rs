@@ -0,0 +1,372 @@
+use std::io::Write;
+
+use crate::dex_file::{self, DexFile, NO_INDEX};
+use crate::instructions::{Format, IndexType, Instruction, Instructions, PayloadKind};
+use crate::raw_dex::{CodeItem, EncodedField, EncodedMethod};
+
+/*
+Output format of the AOSP dexdump tool (`dexdump -d`), see
+* https://cs.android.com/android/platform/superproject/+/master:art/dexdump/dexdump.cc
+ */
+
+#[derive(Copy, Clone)]
+enum AccessFor {
+    Class,
+    Method,
+    Field,
+}
+
+const ACCESS_STRINGS: [[&str; 18]; 3] = [
+    [
+        "PUBLIC", "PRIVATE", "PROTECTED", "STATIC", "FINAL", "?", "?", "?", "?",
+        "INTERFACE", "ABSTRACT", "?", "SYNTHETIC", "ANNOTATION", "ENUM", "?", "VERIFIED", "OPTIMIZED",
+    ],
+    [
+        "PUBLIC", "PRIVATE", "PROTECTED", "STATIC", "FINAL", "SYNCHRONIZED", "BRIDGE", "VARARGS", "NATIVE",
+        "?", "ABSTRACT", "STRICT", "SYNTHETIC", "?", "?", "MIRANDA", "CONSTRUCTOR", "DECLARED_SYNCHRONIZED",
+    ],
+    [
+        "PUBLIC", "PRIVATE", "PROTECTED", "STATIC", "FINAL", "?", "VOLATILE", "TRANSIENT", "?",
+        "?", "?", "?", "SYNTHETIC", "?", "ENUM", "?", "?", "?",
+    ],
+];
+
+fn access_flags_str(flags: u32, access_for: AccessFor) -> String {
+    let strings = &ACCESS_STRINGS[access_for as usize];
+    (0..strings.len())
+        .filter(|bit| flags & (1 << bit) != 0)
+        .map(|bit| strings[bit])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Converts a type descriptor to its dotted form, e.g. "Ljava/lang/String;" to "java.lang.String"
+/// and "[I" to "int[]"
+pub fn descriptor_to_dot(descriptor: &str) -> String {
+    let array_depth = descriptor.len() - descriptor.trim_start_matches('[').len();
+    let element = &descriptor[array_depth..];
+    let mut dotted = if element.len() == 1 {
+        primitive_type_label(element.as_bytes()[0]).to_string()
+    } else if element.len() >= 2 && element.starts_with('L') && element.ends_with(';') {
+        element[1..element.len() - 1].replace('/', ".")
+    } else {
+        element.replace('/', ".")
+    };
+    for _ in 0..array_depth {
+        dotted.push_str("[]");
+    }
+    dotted
+}
+
+fn primitive_type_label(c: u8) -> &'static str {
+    match c {
+        b'B' => "byte",
+        b'C' => "char",
+        b'D' => "double",
+        b'F' => "float",
+        b'I' => "int",
+        b'J' => "long",
+        b'S' => "short",
+        b'V' => "void",
+        b'Z' => "boolean",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Escapes quotes, backslashes and non-printable characters of string constants
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Dumps all classes of the dex file in the format of `dexdump -d`
+pub fn dump(dex: &DexFile, file_name: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "Processing '{}'...", file_name)?;
+    writeln!(out, "Opened '{}', DEX version '{:03}'", file_name, dex.version())?;
+
+    for idx in 0..dex.class_defs.len() {
+        dump_class(dex, idx, out)?;
+    }
+    Ok(())
+}
+
+fn dump_class(dex: &DexFile, idx: usize, out: &mut dyn Write) -> std::io::Result<()> {
+    let class_def = &dex.class_defs[idx];
+    let descriptor = dex.type_descriptor(class_def.class_idx);
+
+    writeln!(out, "Class #{}            -", idx)?;
+    writeln!(out, "  Class descriptor  : '{}'", descriptor)?;
+    writeln!(out, "  Access flags      : 0x{:04x} ({})", class_def.access_flags,
+             access_flags_str(class_def.access_flags, AccessFor::Class))?;
+    if class_def.superclass_idx != NO_INDEX {
+        writeln!(out, "  Superclass        : '{}'", dex.type_descriptor(class_def.superclass_idx))?;
+    }
+    writeln!(out, "  Interfaces        -")?;
+    for (i, type_idx) in dex.type_list(class_def.interfaces_off).iter().enumerate() {
+        writeln!(out, "    #{}              : '{}'", i, dex.type_descriptor(*type_idx as u32))?;
+    }
+
+    let class_data = dex.class_data[idx].as_ref();
+    writeln!(out, "  Static fields     -")?;
+    if let Some(class_data) = class_data {
+        dump_fields(dex, descriptor, &class_data.static_fields, out)?;
+    }
+    writeln!(out, "  Instance fields   -")?;
+    if let Some(class_data) = class_data {
+        dump_fields(dex, descriptor, &class_data.instance_fields, out)?;
+    }
+    writeln!(out, "  Direct methods    -")?;
+    if let Some(class_data) = class_data {
+        dump_methods(dex, descriptor, &class_data.direct_methods, out)?;
+    }
+    writeln!(out, "  Virtual methods   -")?;
+    if let Some(class_data) = class_data {
+        dump_methods(dex, descriptor, &class_data.virtual_methods, out)?;
+    }
+
+    let source_file = if class_def.source_file_idx == NO_INDEX { "unknown" } else {
+        dex.string(class_def.source_file_idx)
+    };
+    writeln!(out, "  source_file_idx   : {} ({})", class_def.source_file_idx as i32, source_file)?;
+    writeln!(out)
+}
+
+fn dump_fields(dex: &DexFile, class_descriptor: &str, fields: &[EncodedField], out: &mut dyn Write) -> std::io::Result<()> {
+    for (i, (field, field_idx)) in fields.iter().zip(dex_file::field_indices(fields)).enumerate() {
+        let flags = field.access_flags as u32;
+        writeln!(out, "    #{}              : (in {})", i, class_descriptor)?;
+        writeln!(out, "      name          : '{}'", dex.field_name(field_idx))?;
+        writeln!(out, "      type          : '{}'", dex.field_type(field_idx))?;
+        writeln!(out, "      access        : 0x{:04x} ({})", flags, access_flags_str(flags, AccessFor::Field))?;
+    }
+    Ok(())
+}
+
+fn dump_methods(dex: &DexFile, class_descriptor: &str, methods: &[EncodedMethod], out: &mut dyn Write) -> std::io::Result<()> {
+    for (i, (method, method_idx)) in methods.iter().zip(dex_file::method_indices(methods)).enumerate() {
+        let flags = method.access_flags as u32;
+        writeln!(out, "    #{}              : (in {})", i, class_descriptor)?;
+        writeln!(out, "      name          : '{}'", dex.method_name(method_idx))?;
+        writeln!(out, "      type          : '{}'", dex.method_signature(method_idx))?;
+        writeln!(out, "      access        : 0x{:04x} ({})", flags, access_flags_str(flags, AccessFor::Method))?;
+        match dex.code_item(method.code_off) {
+            None => writeln!(out, "      code          : (none)")?,
+            Some(code) => {
+                writeln!(out, "      code          -")?;
+                dump_code(dex, method_idx, method, code, out)?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn dump_code(dex: &DexFile, method_idx: u32, method: &EncodedMethod, code: &CodeItem, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "      registers     : {}", code.registers_size)?;
+    writeln!(out, "      ins           : {}", code.ins_size)?;
+    writeln!(out, "      outs          : {}", code.outs_size)?;
+    writeln!(out, "      insns size    : {} 16-bit code units", code.insns.len())?;
+
+    dump_bytecodes(dex, method_idx, method.code_off as u32, code, out)?;
+
+    if code.tries.is_empty() {
+        writeln!(out, "      catches       : (none)")?;
+    } else {
+        writeln!(out, "      catches       : {}", code.tries.len())?;
+        for catch in dex.catches(code) {
+            writeln!(out, "        0x{:04x} - 0x{:04x}", catch.start_address, catch.end_address)?;
+            for handler in &catch.handlers {
+                writeln!(out, "          {} -> 0x{:04x}", handler.exception.unwrap_or("<any>"), handler.address)?;
+            }
+        }
+    }
+
+    writeln!(out, "      positions     : ")?;
+    if let Some(debug_info) = dex.debug_info.get(&code.debug_info_off) {
+        for position in dex.positions(debug_info) {
+            writeln!(out, "        0x{:04x} line={}", position.address, position.line)?;
+        }
+    }
+    writeln!(out, "      locals        : ")?;
+    for local in dex.locals(method_idx, method.access_flags, code) {
+        writeln!(out, "        0x{:04x} - 0x{:04x} reg={} {} {} {}", local.start_address, local.end_address, local.reg,
+                 local.name.unwrap_or("(null)"), local.descriptor.unwrap_or("(null)"), local.signature.unwrap_or(""))?;
+    }
+    Ok(())
+}
+
+fn dump_bytecodes(dex: &DexFile, method_idx: u32, code_off: u32, code: &CodeItem, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "{:06x}:                                        |[{:06x}] {}.{}:{}", code_off, code_off,
+             descriptor_to_dot(dex.method_class(method_idx)), dex.method_name(method_idx), dex.method_signature(method_idx))?;
+
+    for insn in Instructions::new(&code.insns) {
+        match insn {
+            Ok((pc, insn)) => dump_instruction(dex, code_off, code, pc, &insn, out)?,
+            Err(err) => {
+                tracing::warn!(method_idx, "Could not decode the instructions: {}", err);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_instruction(dex: &DexFile, code_off: u32, code: &CodeItem, pc: usize, insn: &Instruction, out: &mut dyn Write) -> std::io::Result<()> {
+    // Code units start after the 16 byte header of the code item
+    write!(out, "{:06x}:", code_off as usize + 0x10 + pc * 2)?;
+    for i in 0..8 {
+        if i < insn.size {
+            if i == 7 {
+                write!(out, " ... ")?;
+            } else {
+                let bytes = code.insns[pc + i].to_le_bytes();
+                write!(out, " {:02x}{:02x}", bytes[0], bytes[1])?;
+            }
+        } else {
+            write!(out, "     ")?;
+        }
+    }
+
+    match insn.payload {
+        Some(PayloadKind::PackedSwitch) => write!(out, "|{:04x}: packed-switch-data ({} units)", pc, insn.size)?,
+        Some(PayloadKind::SparseSwitch) => write!(out, "|{:04x}: sparse-switch-data ({} units)", pc, insn.size)?,
+        Some(PayloadKind::FillArrayData) => write!(out, "|{:04x}: array-data ({} units)", pc, insn.size)?,
+        None if insn.opcode == 0 => write!(out, "|{:04x}: nop // spacer", pc)?,
+        None => write!(out, "|{:04x}: {}", pc, insn.name())?,
+    }
+
+    let index = index_string(dex, insn);
+    let branch = |offset: u32| {
+        let offset = offset as i32;
+        format!("{:04x} // {}{:04x}", (pc as i32).wrapping_add(offset), if offset < 0 { '-' } else { '+' }, offset.unsigned_abs())
+    };
+    match insn.format() {
+        Format::F10x => {}
+        Format::F12x | Format::F22x | Format::F32x => write!(out, " v{}, v{}", insn.a, insn.b)?,
+        Format::F11n => write!(out, " v{}, #int {} // #{:x}", insn.a, insn.b as i32, insn.b as u8)?,
+        Format::F11x => write!(out, " v{}", insn.a)?,
+        Format::F10t | Format::F20t => write!(out, " {}", branch(insn.a))?,
+        Format::F21t => write!(out, " v{}, {}", insn.a, branch(insn.b))?,
+        Format::F21s => write!(out, " v{}, #int {} // #{:x}", insn.a, insn.b as i32, insn.b as u16)?,
+        Format::F21h => {
+            // const/high16 and const-wide/high16
+            if insn.opcode == 0x15 {
+                write!(out, " v{}, #int {} // #{:x}", insn.a, (insn.b << 16) as i32, insn.b as u16)?;
+            } else {
+                write!(out, " v{}, #long {} // #{:x}", insn.a, ((insn.b as u64) << 48) as i64, insn.b as u16)?;
+            }
+        }
+        Format::F21c | Format::F31c => write!(out, " v{}, {}", insn.a, index)?,
+        Format::F23x => write!(out, " v{}, v{}, v{}", insn.a, insn.b, insn.c)?,
+        Format::F22b => write!(out, " v{}, v{}, #int {} // #{:02x}", insn.a, insn.b, insn.c as i32, insn.c as u8)?,
+        Format::F22t => write!(out, " v{}, v{}, {}", insn.a, insn.b, branch(insn.c))?,
+        Format::F22s => write!(out, " v{}, v{}, #int {} // #{:04x}", insn.a, insn.b, insn.c as i32, insn.c as u16)?,
+        Format::F22c => write!(out, " v{}, v{}, {}", insn.a, insn.b, index)?,
+        Format::F30t => write!(out, " #{:08x}", insn.a)?,
+        Format::F31i => write!(out, " v{}, #float {} // #{:08x}", insn.a, format_g(f32::from_bits(insn.b) as f64), insn.b)?,
+        Format::F31t => write!(out, " v{}, {:08x} // +{:08x}", insn.a, (pc as u32).wrapping_add(insn.b), insn.b)?,
+        Format::F35c | Format::F45cc => {
+            let args: Vec<String> = insn.args.iter().take(insn.a as usize).map(|it| format!("v{}", it)).collect();
+            write!(out, " {{{}}}, {}", args.join(", "), index)?;
+        }
+        Format::F3rc | Format::F4rcc => {
+            let args: Vec<String> = (0..insn.a).map(|i| format!("v{}", insn.c + i)).collect();
+            write!(out, " {{{}}}, {}", args.join(", "), index)?;
+        }
+        Format::F51l => write!(out, " v{}, #double {} // #{:016x}", insn.a, format_g(f64::from_bits(insn.wide_b)), insn.wide_b)?,
+    }
+    writeln!(out)
+}
+
+/// Describes the index operand of an instruction, e.g. `Ljava/lang/Object; // type@0003`
+fn index_string(dex: &DexFile, insn: &Instruction) -> String {
+    let (index, width) = match insn.format() {
+        Format::F21c | Format::F35c | Format::F3rc | Format::F45cc | Format::F4rcc => (insn.b, 4),
+        Format::F31c => (insn.b, 8),
+        Format::F22c => (insn.c, 4),
+        _ => (0, 4),
+    };
+    let header = &dex.header;
+    match insn.info().index_type {
+        IndexType::None => String::from("<no-index>"),
+        IndexType::TypeRef => if index < header.type_ids_size {
+            format!("{} // type@{:0w$x}", dex.type_descriptor(index), index, w = width)
+        } else {
+            format!("<type?> // type@{:0w$x}", index, w = width)
+        },
+        IndexType::StringRef => if index < header.string_ids_size {
+            format!("\"{}\" // string@{:0w$x}", escape_string(dex.string(index)), index, w = width)
+        } else {
+            format!("<string?> // string@{:0w$x}", index, w = width)
+        },
+        IndexType::MethodRef => if index < header.method_ids_size {
+            format!("{}.{}:{} // method@{:0w$x}", dex.method_class(index), dex.method_name(index),
+                    dex.method_signature(index), index, w = width)
+        } else {
+            format!("<method?> // method@{:0w$x}", index, w = width)
+        },
+        IndexType::FieldRef => if index < header.field_ids_size {
+            format!("{}.{}:{} // field@{:0w$x}", dex.field_class(index), dex.field_name(index),
+                    dex.field_type(index), index, w = width)
+        } else {
+            format!("<field?> // field@{:0w$x}", index, w = width)
+        },
+        IndexType::MethodAndProtoRef => {
+            let method = if index < header.method_ids_size {
+                format!("{}.{}:{}", dex.method_class(index), dex.method_name(index), dex.method_signature(index))
+            } else {
+                String::from("<method?>")
+            };
+            let proto = if insn.h < header.proto_ids_size {
+                dex.proto_signature(insn.h)
+            } else {
+                String::from("<proto?>")
+            };
+            format!("{}, {} // method@{:0w$x}, proto@{:0w$x}", method, proto, index, insn.h, w = width)
+        }
+        IndexType::CallSiteRef => format!("call_site@{:0w$x}", index, w = width),
+        IndexType::MethodHandleRef => format!("method_handle@{:0w$x}", index, w = width),
+        IndexType::ProtoRef => if index < header.proto_ids_size {
+            format!("{} // proto@{:0w$x}", dex.proto_signature(index), index, w = width)
+        } else {
+            format!("<?> // proto@{:0w$x}", index, w = width)
+        },
+    }
+}
+
+/// Formats a floating point value like the `%g` conversion of printf (6 significant digits)
+fn format_g(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return match value {
+            v if v.is_nan() => String::from("nan"),
+            v if v.is_infinite() => String::from(if v > 0.0 { "inf" } else { "-inf" }),
+            v => String::from(if v.is_sign_negative() { "-0" } else { "0" }),
+        };
+    }
+    const PRECISION: i32 = 6;
+    let exponent = format!("{:.*e}", (PRECISION - 1) as usize, value);
+    let (mantissa, exp) = exponent.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let trim = |s: &str| if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        s.to_string()
+    };
+    if !(-4..PRECISION).contains(&exp) {
+        format!("{}e{}{:02}", trim(mantissa), if exp < 0 { '-' } else { '+' }, exp.abs())
+    } else {
+        trim(&format!("{:.*}", (PRECISION - 1 - exp) as usize, value))
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::dex_file::DexFile;
+use crate::highlight::{self, Syntax};
+use crate::smali::{self, Comments};
+
+/*
+Differences between two versions of a dex file, e.g. of an app before and after an update. Classes are
+matched by their descriptor and methods by their name and signature, the methods are compared by their
+smali code. Changed methods are diffed line by line, labels are named by the address of their target,
+so an inserted instruction also renames the labels after it.
+ */
+
+/// Largest product of the line counts of two versions of a method that is diffed line by line, larger
+/// methods are shown as completely replaced
+const MAX_DIFF_CELLS: usize = 1 << 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        })
+    }
+}
+
+/// A line of the smali code of a method in the old version, the new version or both
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MethodDiff {
+    /// Name and signature, e.g. `add(II)I`
+    pub method: String,
+    pub change: Change,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDiff {
+    pub descriptor: String,
+    /// Changed for classes defined by both versions
+    pub change: Change,
+    /// Added, removed and changed methods, unchanged methods are left out
+    pub methods: Vec<MethodDiff>,
+}
+
+/// Smali code of the methods of the classes, by descriptor and name and signature
+fn methods(dex: &DexFile) -> BTreeMap<&str, BTreeMap<String, String>> {
+    let comments = Comments::new();
+    dex.classes().map(|class| {
+        let methods = class.methods().into_iter().map(|method| {
+            let mut code = Vec::new();
+            smali::write_method(dex, method.encoded, method.method_idx, &comments, &mut code).expect("Writing to a Vec does not fail");
+            (format!("{}{}", method.name(), dex.method_signature(method.method_idx)), String::from_utf8_lossy(&code).into_owned())
+        }).collect();
+        (class.descriptor(), methods)
+    }).collect()
+}
+
+/// Line diff of two texts, the lines of the longest common subsequence are kept
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut lines: Vec<DiffLine> = old[..prefix].iter().map(|it| DiffLine::Same(it.to_string())).collect();
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS {
+        lines.extend(old_middle.iter().map(|it| DiffLine::Removed(it.to_string())));
+        lines.extend(new_middle.iter().map(|it| DiffLine::Added(it.to_string())));
+    } else {
+        // lengths[i][j]: length of the longest common subsequence of old_middle[i..] and new_middle[j..]
+        let width = new_middle.len() + 1;
+        let mut lengths = vec![0u32; (old_middle.len() + 1) * width];
+        for i in (0..old_middle.len()).rev() {
+            for j in (0..new_middle.len()).rev() {
+                lengths[i * width + j] = if old_middle[i] == new_middle[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old_middle.len() || j < new_middle.len() {
+            if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+                lines.push(DiffLine::Same(old_middle[i].to_string()));
+                i += 1;
+                j += 1;
+            } else if j == new_middle.len() || (i < old_middle.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1]) {
+                lines.push(DiffLine::Removed(old_middle[i].to_string()));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(new_middle[j].to_string()));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|it| DiffLine::Same(it.to_string())));
+    lines
+}
+
+fn method_diff(method: &str, old: Option<&String>, new: Option<&String>) -> Option<MethodDiff> {
+    let (change, lines) = match (old, new) {
+        (Some(old), Some(new)) if old == new => return None,
+        (Some(old), Some(new)) => (Change::Changed, diff_lines(old, new)),
+        (Some(old), None) => (Change::Removed, old.lines().map(|it| DiffLine::Removed(it.to_string())).collect()),
+        (None, Some(new)) => (Change::Added, new.lines().map(|it| DiffLine::Added(it.to_string())).collect()),
+        (None, None) => return None,
+    };
+    Some(MethodDiff { method: method.to_string(), change, lines })
+}
+
+/// Classes added, removed or with added, removed or changed methods from `old` to `new`, by descriptor
+pub fn diff(old: &DexFile, new: &DexFile) -> Vec<ClassDiff> {
+    let old_methods = methods(old);
+    let new_methods = methods(new);
+    let empty = BTreeMap::new();
+    let mut descriptors: Vec<&str> = old_methods.keys().chain(new_methods.keys()).copied().collect();
+    descriptors.sort_unstable();
+    descriptors.dedup();
+
+    let mut classes = Vec::new();
+    for descriptor in descriptors {
+        let (old_class, new_class) = (old_methods.get(descriptor), new_methods.get(descriptor));
+        let change = match (old_class, new_class) {
+            (Some(_), None) => Change::Removed,
+            (None, Some(_)) => Change::Added,
+            _ => Change::Changed,
+        };
+        let (old_class, new_class) = (old_class.unwrap_or(&empty), new_class.unwrap_or(&empty));
+        let mut names: Vec<&String> = old_class.keys().chain(new_class.keys()).collect();
+        names.sort_unstable();
+        names.dedup();
+        let methods: Vec<MethodDiff> = names.into_iter()
+            .filter_map(|name| method_diff(name, old_class.get(name), new_class.get(name)))
+            .collect();
+        if change != Change::Changed || !methods.is_empty() {
+            classes.push(ClassDiff { descriptor: descriptor.to_string(), change, methods });
+        }
+    }
+    classes
+}
+
+/// Writes the differences as unified diff of the smali code, one section per method
+pub fn write_text(classes: &[ClassDiff], out: &mut dyn Write) -> std::io::Result<()> {
+    for class in classes {
+        writeln!(out, "# {} {}", class.change, class.descriptor)?;
+        for method in &class.methods {
+            writeln!(out, "@@ {} {}->{}", method.change, class.descriptor, method.method)?;
+            for line in &method.lines {
+                match line {
+                    DiffLine::Same(line) => writeln!(out, " {}", line)?,
+                    DiffLine::Removed(line) => writeln!(out, "-{}", line)?,
+                    DiffLine::Added(line) => writeln!(out, "+{}", line)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+summary { cursor: pointer; padding: 0.2em 0; }
+details details { margin-left: 1.5em; }
+.code { font-family: monospace; margin: 0.5em 0; padding: 0.5em; background: #fafafa; border: 1px solid #ddd; overflow-x: auto; }
+.line { white-space: pre; }
+.added { background: #e6ffec; }
+.removed { background: #ffebe9; }
+.change { font-size: 0.8em; padding: 0 0.4em; border-radius: 0.3em; background: #eee; }
+.opcode { color: #0550ae; font-weight: bold; }
+.directive { color: #8250df; }
+.register { color: #0a7480; }
+.string { color: #116329; }
+.type { color: #953800; }
+.label { color: #8250df; font-weight: bold; }
+.comment { color: #6e7781; }
+";
+
+/// Writes the differences as a standalone HTML page, each class and method in a collapsible section
+/// and the smali code highlighted. `old_name` and `new_name` are shown in the title.
+pub fn write_html(classes: &[ClassDiff], old_name: &str, new_name: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    let title = format!("{} → {}", highlight::escape_html(old_name), highlight::escape_html(new_name));
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>", title, HTML_STYLE)?;
+    writeln!(out, "<h1>{}</h1>", title)?;
+    let count = |change| classes.iter().filter(|it| it.change == change).count();
+    writeln!(out, "<p>{} classes added, {} removed, {} changed</p>", count(Change::Added), count(Change::Removed), count(Change::Changed))?;
+    for class in classes {
+        writeln!(out, "<details>\n<summary><span class=\"change\">{}</span> <code>{}</code> ({} methods)</summary>",
+                 class.change, highlight::escape_html(&class.descriptor), class.methods.len())?;
+        for method in &class.methods {
+            writeln!(out, "<details open>\n<summary><span class=\"change\">{}</span> <code>{}</code></summary>\n<div class=\"code\">",
+                     method.change, highlight::escape_html(&method.method))?;
+            for line in &method.lines {
+                let (class, marker, line) = match line {
+                    DiffLine::Same(line) => ("line", ' ', line),
+                    DiffLine::Removed(line) => ("line removed", '-', line),
+                    DiffLine::Added(line) => ("line added", '+', line),
+                };
+                writeln!(out, "<div class=\"{}\">{}{}</div>", class, marker, highlight::highlight_html(Syntax::Smali, line))?;
+            }
+            writeln!(out, "</div>\n</details>")?;
+        }
+        writeln!(out, "</details>")?;
+    }
+    writeln!(out, "</body>\n</html>")
+}
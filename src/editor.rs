@@ -0,0 +1,2290 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::cell::Cell;
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::dex_file::{split_descriptors, DexFile, ParseOptions, UnknownSection, Warning};
+use crate::instructions::{DecodeError, EncodeError, Format, IndexType, Instructions};
+use crate::io::{Cursor, Seek, SeekFrom};
+use crate::m_utf8;
+use crate::prelude::*;
+use crate::raw_dex::{self, write_uleb128, AnnotationItem, DebugInfoItem, DebugInstruction, DexHeader, EncodedAnnotation, EncodedValue, OptionalIdx, Visibility};
+
+pub use crate::dex_file::NO_INDEX;
+
+/*
+Editing of dex files: the id tables and class definitions of a dex file as owned model that is written
+back as a new file. Like in the file, items reference each other by index. Edits adding ids keep the
+id tables in the order the format requires (strings by UTF-16 code units, types by string index, ...)
+and update the indices of all references with a Remap, which only ever moves ids in the order they had.
+On write, the data section is laid out anew (shared type lists, annotations and static values are
+written once, like d8 does) and the checksum and, with the index feature, the signature are computed.
+Sections unknown to the parser (e.g. of vendor toolchains) are kept as bytes and written back unchanged
+after the known sections, offsets within them are not updated. The link data and the hiddenapi flags of
+platform dex files are not part of the model and dropped.
+ */
+
+const ACC_PRIVATE: u32 = 0x2;
+const ACC_STATIC: u32 = 0x8;
+const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+const HEADER_SIZE: u32 = 0x70;
+const ENDIAN_CONSTANT: u32 = 0x12345678;
+
+// Item types of the map list
+const TYPE_HEADER_ITEM: u16 = 0x0000;
+const TYPE_STRING_ID_ITEM: u16 = 0x0001;
+const TYPE_TYPE_ID_ITEM: u16 = 0x0002;
+const TYPE_PROTO_ID_ITEM: u16 = 0x0003;
+const TYPE_FIELD_ID_ITEM: u16 = 0x0004;
+const TYPE_METHOD_ID_ITEM: u16 = 0x0005;
+const TYPE_CLASS_DEF_ITEM: u16 = 0x0006;
+const TYPE_CALL_SITE_ID_ITEM: u16 = 0x0007;
+const TYPE_METHOD_HANDLE_ITEM: u16 = 0x0008;
+const TYPE_MAP_LIST: u16 = 0x1000;
+const TYPE_TYPE_LIST: u16 = 0x1001;
+const TYPE_ANNOTATION_SET_REF_LIST: u16 = 0x1002;
+const TYPE_ANNOTATION_SET_ITEM: u16 = 0x1003;
+const TYPE_CLASS_DATA_ITEM: u16 = 0x2000;
+const TYPE_CODE_ITEM: u16 = 0x2001;
+const TYPE_STRING_DATA_ITEM: u16 = 0x2002;
+const TYPE_DEBUG_INFO_ITEM: u16 = 0x2003;
+const TYPE_ANNOTATION_ITEM: u16 = 0x2004;
+const TYPE_ENCODED_ARRAY_ITEM: u16 = 0x2005;
+const TYPE_ANNOTATIONS_DIRECTORY_ITEM: u16 = 0x2006;
+
+/// Kind of an id table (and of the indices into it)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IdKind {
+    String,
+    Type,
+    Proto,
+    Field,
+    Method,
+    CallSite,
+    MethodHandle,
+}
+
+impl fmt::Display for IdKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IdKind::String => "string",
+            IdKind::Type => "type",
+            IdKind::Proto => "proto",
+            IdKind::Field => "field",
+            IdKind::Method => "method",
+            IdKind::CallSite => "call site",
+            IdKind::MethodHandle => "method handle",
+        })
+    }
+}
+
+/// A prototype, with the type indices of its parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proto {
+    pub shorty_idx: u32,
+    pub return_type_idx: u32,
+    pub parameters: Vec<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FieldRef {
+    pub class_idx: u32,
+    pub type_idx: u32,
+    pub name_idx: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MethodRef {
+    pub class_idx: u32,
+    pub proto_idx: u32,
+    pub name_idx: u32,
+}
+
+/// A method handle, `target` is a field index for the kinds 0 to 3 (static-put to instance-get) and a
+/// method index for the others
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MethodHandleRef {
+    pub kind: u16,
+    pub target: u32,
+}
+
+impl MethodHandleRef {
+    pub fn target_kind(&self) -> IdKind {
+        if self.kind <= 0x03 { IdKind::Field } else { IdKind::Method }
+    }
+}
+
+/// A try block with its catch clauses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Try {
+    pub start_addr: u32,
+    pub insn_count: u16,
+    /// Type index and address of each catch clause, in order
+    pub handlers: Vec<(u32, u32)>,
+    pub catch_all_addr: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Code {
+    pub registers_size: u16,
+    pub ins_size: u16,
+    pub outs_size: u16,
+    pub insns: Vec<u16>,
+    pub tries: Vec<Try>,
+    pub debug_info: Option<DebugInfoItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDefinition {
+    pub field_idx: u32,
+    pub access_flags: u32,
+    /// Initial value of a static field, None for the default value of its type
+    pub static_value: Option<EncodedValue>,
+    pub annotations: Vec<AnnotationItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MethodDefinition {
+    pub method_idx: u32,
+    pub access_flags: u32,
+    /// None for abstract and native methods
+    pub code: Option<Code>,
+    pub annotations: Vec<AnnotationItem>,
+    /// Annotations of each parameter, empty if no parameter has any
+    pub parameter_annotations: Vec<Vec<AnnotationItem>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDefinition {
+    pub class_idx: u32,
+    pub access_flags: u32,
+    /// None for java.lang.Object
+    pub superclass_idx: Option<u32>,
+    pub interfaces: Vec<u32>,
+    pub source_file_idx: Option<u32>,
+    pub annotations: Vec<AnnotationItem>,
+    pub static_fields: Vec<FieldDefinition>,
+    pub instance_fields: Vec<FieldDefinition>,
+    pub direct_methods: Vec<MethodDefinition>,
+    pub virtual_methods: Vec<MethodDefinition>,
+}
+
+impl ClassDefinition {
+    pub fn fields(&self) -> impl Iterator<Item = &FieldDefinition> {
+        self.static_fields.iter().chain(&self.instance_fields)
+    }
+
+    pub fn methods(&self) -> impl Iterator<Item = &MethodDefinition> {
+        self.direct_methods.iter().chain(&self.virtual_methods)
+    }
+}
+
+/// New index of each index of an id table after an edit, NO_INDEX for ids that are gone. Empty if the
+/// table did not change.
+#[derive(Debug, Clone, Default)]
+pub struct IndexMap(Vec<u32>);
+
+impl IndexMap {
+    pub fn get(&self, idx: u32) -> u32 {
+        if self.0.is_empty() {
+            return idx;
+        }
+        self.0.get(idx as usize).copied().unwrap_or(NO_INDEX)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// New indices of the ids of all tables after an edit
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    pub strings: IndexMap,
+    pub types: IndexMap,
+    pub protos: IndexMap,
+    pub fields: IndexMap,
+    pub methods: IndexMap,
+    pub call_sites: IndexMap,
+    pub method_handles: IndexMap,
+}
+
+impl Remap {
+    fn map(&self, kind: IdKind) -> &IndexMap {
+        match kind {
+            IdKind::String => &self.strings,
+            IdKind::Type => &self.types,
+            IdKind::Proto => &self.protos,
+            IdKind::Field => &self.fields,
+            IdKind::Method => &self.methods,
+            IdKind::CallSite => &self.call_sites,
+            IdKind::MethodHandle => &self.method_handles,
+        }
+    }
+
+    fn map_mut(&mut self, kind: IdKind) -> &mut IndexMap {
+        match kind {
+            IdKind::String => &mut self.strings,
+            IdKind::Type => &mut self.types,
+            IdKind::Proto => &mut self.protos,
+            IdKind::Field => &mut self.fields,
+            IdKind::Method => &mut self.methods,
+            IdKind::CallSite => &mut self.call_sites,
+            IdKind::MethodHandle => &mut self.method_handles,
+        }
+    }
+
+    /// New index of the id `idx` of the table `kind`
+    pub fn get(&self, kind: IdKind, idx: u32) -> u32 {
+        self.map(kind).get(idx)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        [IdKind::String, IdKind::Type, IdKind::Proto, IdKind::Field, IdKind::Method, IdKind::CallSite, IdKind::MethodHandle]
+            .iter().all(|it| self.map(*it).is_identity())
+    }
+}
+
+/// What strip does with the references to the removed classes and members
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dangling {
+    /// Fail with EditError::Dangling
+    Fail,
+    /// Replace the code of the methods referencing them with a stub throwing a NullPointerException,
+    /// other references (e.g. by a superclass or annotation) fail
+    Stub,
+    /// Remove the classes and members referencing them as well
+    Cascade,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StripAction {
+    Removed,
+    Stubbed,
+}
+
+impl fmt::Display for StripAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StripAction::Removed => "removed",
+            StripAction::Stubbed => "stubbed",
+        })
+    }
+}
+
+/// Outcome of strip
+#[derive(Debug, Clone)]
+pub struct Stripped {
+    /// The classes and members removed (a removed class stands for its members) and the methods
+    /// stubbed, e.g. `Lcom/example/Foo;->run()V`
+    pub items: Vec<(String, StripAction)>,
+    /// Moves of the remaining ids
+    pub remap: Remap,
+}
+
+/// Classes (by type index) and members (by field and method index) to remove
+#[derive(Default)]
+struct Removal {
+    classes: BTreeSet<u32>,
+    fields: BTreeSet<u32>,
+    methods: BTreeSet<u32>,
+}
+
+/// Ids of the removed classes and members: their types, fields and methods and the method handles
+/// and call sites referencing them
+struct Gone {
+    types: BTreeSet<u32>,
+    fields: Vec<bool>,
+    methods: Vec<bool>,
+    method_handles: Vec<bool>,
+    call_sites: Vec<bool>,
+}
+
+impl Gone {
+    fn contains(&self, kind: IdKind, idx: u32) -> bool {
+        let idx = idx as usize;
+        match kind {
+            IdKind::String | IdKind::Proto => false,
+            IdKind::Type => self.types.contains(&(idx as u32)),
+            IdKind::Field => self.fields[idx],
+            IdKind::Method => self.methods[idx],
+            IdKind::MethodHandle => self.method_handles[idx],
+            IdKind::CallSite => self.call_sites[idx],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    Parse(crate::io::Error),
+    /// The file holds data the model cannot represent exactly, e.g. invalid MUTF-8
+    Unsupported(String),
+    /// The instructions of a method could not be decoded
+    Decode { method_idx: u32, error: DecodeError },
+    /// An index does not fit its instruction after the edit, e.g. a string index above 65535 of
+    /// const-string
+    Encode { method_idx: u32, error: EncodeError },
+    /// More ids than their indices can address, e.g. more than 65536 types
+    TooManyIds(IdKind),
+    /// A reference (e.g. an instruction operand, which the parser does not check) past the end of its table
+    IndexOutOfBounds(IdKind, u32),
+    ClassExists(String),
+    ClassNotFound(String),
+    MethodNotFound(String),
+    FieldNotFound(String),
+    /// A class or member to strip that is not of the form `Lcom/example/Foo;`,
+    /// `Lcom/example/Foo;->run()V` or `Lcom/example/Foo;->name:I`
+    InvalidTarget(String),
+    /// A trampoline of redirect::redirect_invokes that cannot replace the target, with the reason
+    IncompatibleTrampoline(String),
+    /// A removed class or member (the first) is referenced by a remaining one (the second)
+    Dangling(String, String),
+    InvalidDescriptor(String),
+    /// A method signature that is not of the form `(I[Ljava/lang/String;)V`
+    InvalidSignature(String),
+}
+
+impl core::error::Error for EditError {}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::Parse(err) => write!(f, "{}", err),
+            EditError::Unsupported(reason) => write!(f, "Unsupported dex file: {}", reason),
+            EditError::Decode { method_idx, error } => write!(f, "Method #{}: {}", method_idx, error),
+            EditError::Encode { method_idx, error } => write!(f, "Method #{}: {}", method_idx, error),
+            EditError::TooManyIds(kind) => write!(f, "Too many {} ids", kind),
+            EditError::IndexOutOfBounds(kind, idx) => write!(f, "{} index {} out of bounds", kind, idx),
+            EditError::ClassExists(descriptor) => write!(f, "Class {} is already defined", descriptor),
+            EditError::ClassNotFound(descriptor) => write!(f, "Class {} is not defined", descriptor),
+            EditError::MethodNotFound(method) => write!(f, "Method {} is not defined", method),
+            EditError::FieldNotFound(field) => write!(f, "Field {} is not defined", field),
+            EditError::InvalidTarget(target) => write!(f, "Invalid class or member {}", target),
+            EditError::IncompatibleTrampoline(reason) => write!(f, "Incompatible trampoline: {}", reason),
+            EditError::Dangling(removed, referrer) => write!(f, "{} is referenced by {}", removed, referrer),
+            EditError::InvalidDescriptor(descriptor) => write!(f, "Invalid type descriptor {}", descriptor),
+            EditError::InvalidSignature(signature) => write!(f, "Invalid method signature {}", signature),
+        }
+    }
+}
+
+/// An id by its contents, independent of the indices of a file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Symbol {
+    String(String),
+    Type(String),
+    /// Return type and parameters
+    Proto(String, Vec<String>),
+    /// Class, name and type
+    Field(String, String, String),
+    /// Class, name, return type and parameters
+    Method(String, String, String, Vec<String>),
+}
+
+/// Shorty descriptor of a prototype, e.g. `VLI` for `(Ljava/lang/String;I)V`
+fn shorty(return_type: &str, parameters: &[String]) -> String {
+    let shorty = |descriptor: &str| if descriptor.starts_with('L') || descriptor.starts_with('[') { 'L' } else { descriptor.chars().next().unwrap_or('V') };
+    core::iter::once(shorty(return_type)).chain(parameters.iter().map(|it| shorty(it))).collect()
+}
+
+/// The descriptor if it is one of a single type, e.g. `[Ljava/lang/String;`
+fn checked_descriptor(descriptor: &str) -> Result<String, EditError> {
+    let element = descriptor.trim_start_matches('[');
+    let valid = match element.strip_prefix('L') {
+        Some(class) => class.len() > 1 && class.find(';') == Some(class.len() - 1),
+        None => (element.len() == 1 && "ZBSCIJFD".contains(element)) || descriptor == "V",
+    };
+    if valid { Ok(descriptor.to_owned()) } else { Err(EditError::InvalidDescriptor(descriptor.to_owned())) }
+}
+
+/// The method `class->name(signature)` as symbol
+fn method_symbol(class: &str, name: &str, signature: &str) -> Result<Symbol, EditError> {
+    let invalid = || EditError::InvalidSignature(signature.to_owned());
+    let (parameters, return_type) = signature.strip_prefix('(').and_then(|it| it.split_once(')')).ok_or_else(invalid)?;
+    let parameters = split_descriptors(parameters).ok_or_else(invalid)?;
+    if split_descriptors(return_type).ok_or_else(invalid)?.len() != 1 {
+        return Err(invalid());
+    }
+    Ok(Symbol::Method(class.to_owned(), name.to_owned(), return_type.to_owned(), parameters.iter().map(|it| (*it).to_owned()).collect()))
+}
+
+fn cmp_utf16(a: &str, b: &str) -> Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+/// Adds the `added` entries to the `table` sorted by `cmp`, returns the new index of each entry of the
+/// table. Entries already in the table are not added again.
+fn merge_sorted<T>(table: &mut Vec<T>, mut added: Vec<T>, cmp: impl Fn(&T, &T) -> Ordering) -> IndexMap {
+    added.sort_by(&cmp);
+    added.dedup_by(|a, b| cmp(a, b) == Ordering::Equal);
+    added.retain(|it| table.binary_search_by(|entry| cmp(entry, it)).is_err());
+    if added.is_empty() {
+        return IndexMap::default();
+    }
+    let old = core::mem::take(table);
+    let mut map = Vec::with_capacity(old.len());
+    let mut added = added.into_iter().peekable();
+    for entry in old {
+        while let Some(new) = added.next_if(|it| cmp(it, &entry) == Ordering::Less) {
+            table.push(new);
+        }
+        map.push(table.len() as u32);
+        table.push(entry);
+    }
+    table.extend(added);
+    IndexMap(map)
+}
+
+fn is_sorted<T>(table: &[T], cmp: impl Fn(&T, &T) -> Ordering) -> bool {
+    table.windows(2).all(|it| cmp(&it[0], &it[1]) == Ordering::Less)
+}
+
+fn cmp_proto(a: &Proto, b: &Proto) -> Ordering {
+    (a.return_type_idx, &a.parameters).cmp(&(b.return_type_idx, &b.parameters))
+}
+
+fn cmp_field(a: &FieldRef, b: &FieldRef) -> Ordering {
+    (a.class_idx, a.name_idx, a.type_idx).cmp(&(b.class_idx, b.name_idx, b.type_idx))
+}
+
+fn cmp_method(a: &MethodRef, b: &MethodRef) -> Ordering {
+    (a.class_idx, a.name_idx, a.proto_idx).cmp(&(b.class_idx, b.name_idx, b.proto_idx))
+}
+
+/// Called with each index of the model and the table it indexes, which it may change
+type Visitor<'a> = dyn FnMut(IdKind, &mut u32) + 'a;
+
+fn visit_u64(kind: IdKind, idx: &mut u64, f: &mut Visitor) {
+    let mut value = *idx as u32;
+    f(kind, &mut value);
+    *idx = value.into();
+}
+
+fn visit_optional(kind: IdKind, idx: &mut OptionalIdx, f: &mut Visitor) {
+    if let Some(mut value) = idx.get() {
+        f(kind, &mut value);
+        *idx = OptionalIdx::new(value);
+    }
+}
+
+fn visit_annotation(annotation: &mut EncodedAnnotation, f: &mut Visitor) {
+    visit_u64(IdKind::Type, &mut annotation.type_idx, f);
+    for element in &mut annotation.elements {
+        visit_u64(IdKind::String, &mut element.name_idx, f);
+        visit_value(&mut element.value, f);
+    }
+}
+
+fn visit_annotations(annotations: &mut [AnnotationItem], f: &mut Visitor) {
+    for item in annotations {
+        visit_annotation(&mut item.annotation, f);
+    }
+}
+
+fn visit_value(value: &mut EncodedValue, f: &mut Visitor) {
+    match value {
+        EncodedValue::MethodType(idx) => f(IdKind::Proto, idx),
+        EncodedValue::MethodHandle(idx) => f(IdKind::MethodHandle, idx),
+        EncodedValue::String(idx) => f(IdKind::String, idx),
+        EncodedValue::Type(idx) => f(IdKind::Type, idx),
+        EncodedValue::Field(idx) | EncodedValue::Enum(idx) => f(IdKind::Field, idx),
+        EncodedValue::Method(idx) => f(IdKind::Method, idx),
+        EncodedValue::Array(values) => values.iter_mut().for_each(|it| visit_value(it, f)),
+        EncodedValue::Annotation(annotation) => visit_annotation(annotation, f),
+        _ => {}
+    }
+}
+
+fn visit_debug_info(debug_info: &mut DebugInfoItem, f: &mut Visitor) {
+    for name in &mut debug_info.parameter_names {
+        visit_optional(IdKind::String, name, f);
+    }
+    for insn in &mut debug_info.bytecode {
+        match insn {
+            DebugInstruction::StartLocal { name_idx, type_idx, .. } => {
+                visit_optional(IdKind::String, name_idx, f);
+                visit_optional(IdKind::Type, type_idx, f);
+            }
+            DebugInstruction::StartLocalExtended { name_idx, type_idx, sig_idx, .. } => {
+                visit_optional(IdKind::String, name_idx, f);
+                visit_optional(IdKind::Type, type_idx, f);
+                visit_optional(IdKind::String, sig_idx, f);
+            }
+            DebugInstruction::SetFile(name_idx) => visit_optional(IdKind::String, name_idx, f),
+            _ => {}
+        }
+    }
+}
+
+/// Visits the index operands of the instructions, re-encoding the instructions whose indices changed
+fn visit_insns(insns: &mut [u16], method_idx: u32, f: &mut Visitor) -> Result<(), EditError> {
+    let decoded = Instructions::new(insns).collect::<Result<Vec<_>, _>>()
+        .map_err(|error| EditError::Decode { method_idx, error })?;
+    for (pc, mut insn) in decoded {
+        let kind = match insn.info().index_type {
+            _ if insn.payload.is_some() => continue,
+            IndexType::None => continue,
+            IndexType::StringRef => IdKind::String,
+            IndexType::TypeRef => IdKind::Type,
+            IndexType::FieldRef => IdKind::Field,
+            IndexType::MethodRef | IndexType::MethodAndProtoRef => IdKind::Method,
+            IndexType::CallSiteRef => IdKind::CallSite,
+            IndexType::MethodHandleRef => IdKind::MethodHandle,
+            IndexType::ProtoRef => IdKind::Proto,
+        };
+        let before = (insn.b, insn.c, insn.h);
+        if insn.format() == Format::F22c {
+            f(kind, &mut insn.c);
+        } else {
+            f(kind, &mut insn.b);
+        }
+        if insn.info().index_type == IndexType::MethodAndProtoRef {
+            f(IdKind::Proto, &mut insn.h);
+        }
+        if (insn.b, insn.c, insn.h) != before {
+            let units = insn.encode().map_err(|error| EditError::Encode { method_idx, error })?;
+            insns[pc..pc + units.len()].copy_from_slice(&units);
+        }
+    }
+    Ok(())
+}
+
+fn visit_method(method: &mut MethodDefinition, f: &mut Visitor) -> Result<(), EditError> {
+    let method_idx = method.method_idx;
+    f(IdKind::Method, &mut method.method_idx);
+    visit_annotations(&mut method.annotations, f);
+    for annotations in &mut method.parameter_annotations {
+        visit_annotations(annotations, f);
+    }
+    if let Some(code) = &mut method.code {
+        if let Some(debug_info) = &mut code.debug_info {
+            visit_debug_info(debug_info, f);
+        }
+        visit_code(code, method_idx, f)?;
+    }
+    Ok(())
+}
+
+/// Visits the indices of the instructions and catch clauses, not of the debug info
+fn visit_code(code: &mut Code, method_idx: u32, f: &mut Visitor) -> Result<(), EditError> {
+    for try_ in &mut code.tries {
+        for (type_idx, _) in &mut try_.handlers {
+            f(IdKind::Type, type_idx);
+        }
+    }
+    visit_insns(&mut code.insns, method_idx, f)
+}
+
+/// Keeps the entries of the table the map does not remove
+fn retain_mapped<T>(table: &mut Vec<T>, map: &IndexMap) {
+    let mut idx = 0;
+    table.retain(|_| {
+        idx += 1;
+        map.get(idx - 1) != NO_INDEX
+    });
+}
+
+/// Map of a table of `len` entries that removes the ones not `used`
+fn used_map(len: usize, used: &BTreeSet<(IdKind, u32)>, kind: IdKind) -> IndexMap {
+    if (0..len as u32).all(|it| used.contains(&(kind, it))) {
+        return IndexMap::default();
+    }
+    let mut next = 0;
+    IndexMap((0..len as u32).map(|idx| {
+        if !used.contains(&(kind, idx)) {
+            return NO_INDEX;
+        }
+        next += 1;
+        next - 1
+    }).collect())
+}
+
+/// Visits all indices of the class definition, its members, their code and annotations
+fn visit_class(class: &mut ClassDefinition, f: &mut Visitor) -> Result<(), EditError> {
+    f(IdKind::Type, &mut class.class_idx);
+    if let Some(superclass_idx) = &mut class.superclass_idx {
+        f(IdKind::Type, superclass_idx);
+    }
+    for interface in &mut class.interfaces {
+        f(IdKind::Type, interface);
+    }
+    if let Some(source_file_idx) = &mut class.source_file_idx {
+        f(IdKind::String, source_file_idx);
+    }
+    visit_annotations(&mut class.annotations, f);
+    for field in class.static_fields.iter_mut().chain(&mut class.instance_fields) {
+        f(IdKind::Field, &mut field.field_idx);
+        if let Some(value) = &mut field.static_value {
+            visit_value(value, f);
+        }
+        visit_annotations(&mut field.annotations, f);
+    }
+    for method in class.direct_methods.iter_mut().chain(&mut class.virtual_methods) {
+        visit_method(method, f)?;
+    }
+    Ok(())
+}
+
+/// The ids of the dex file with its class definitions, see the module documentation
+#[derive(Debug, Clone)]
+pub struct DexEditor {
+    /// Version of the format, e.g. 35 for `dex\n035`
+    pub version: u16,
+    pub strings: Vec<String>,
+    /// String index of the descriptor of each type
+    pub types: Vec<u32>,
+    pub protos: Vec<Proto>,
+    pub fields: Vec<FieldRef>,
+    pub methods: Vec<MethodRef>,
+    pub classes: Vec<ClassDefinition>,
+    /// Encoded arrays of the call sites (method handle, method name, method type and extra arguments)
+    pub call_sites: Vec<Vec<EncodedValue>>,
+    pub method_handles: Vec<MethodHandleRef>,
+    /// Sections of item types unknown to the parser, written back unchanged
+    pub unknown_sections: Vec<UnknownSection>,
+}
+
+impl DexEditor {
+    /// Parses the dex file `data` for editing. Fails for files whose strings are invalid MUTF-8, as
+    /// they would be changed by writing them back.
+    pub fn from_bytes(data: &[u8]) -> Result<DexEditor, EditError> {
+        let mut warnings = Vec::new();
+        let mut collect = |warning: Warning| warnings.push(warning.offset);
+        let dex = ParseOptions::new().warnings(&mut collect).parse_bytes(data).map_err(EditError::Parse)?;
+        let string_data_offs: BTreeSet<u64> = dex.string_data_offs.iter().map(|it| *it as u64).collect();
+        if let Some(offset) = warnings.iter().find(|it| string_data_offs.contains(it)) {
+            return Err(EditError::Unsupported(format!("Invalid MUTF-8 in the string at 0x{:x}", offset)));
+        }
+        DexEditor::from_dex(&dex, data)
+    }
+
+    fn from_dex(dex: &DexFile, data: &[u8]) -> Result<DexEditor, EditError> {
+        let unparsed = |item: &str, off: u32| EditError::Unsupported(format!("Unparsed {} at 0x{:x}", item, off));
+        let annotation_set = |off: u32| -> Result<Vec<AnnotationItem>, EditError> {
+            if off == 0 {
+                return Ok(Vec::new());
+            }
+            dex.annotation_sets.get(&off).ok_or_else(|| unparsed("annotation set", off))?.iter()
+                .map(|item_off| dex.annotation_items.get(item_off).cloned().ok_or_else(|| unparsed("annotation item", *item_off)))
+                .collect()
+        };
+        let optional = |idx: u32| if idx == NO_INDEX { None } else { Some(idx) };
+
+        let mut classes = Vec::with_capacity(dex.class_defs.len());
+        for (def, class_data) in dex.class_defs.iter().zip(&dex.class_data) {
+            let directory = match def.annotations_off {
+                0 => None,
+                off => Some(dex.annotations_directories.get(&off).ok_or_else(|| unparsed("annotations directory", off))?),
+            };
+            let static_values = match def.static_values_off {
+                0 => &[][..],
+                off => dex.static_values.get(&off).ok_or_else(|| unparsed("encoded array", off))?,
+            };
+            let mut class = ClassDefinition {
+                class_idx: def.class_idx,
+                access_flags: def.access_flags,
+                superclass_idx: optional(def.superclass_idx),
+                interfaces: dex.type_list(def.interfaces_off).iter().map(|it| *it as u32).collect(),
+                source_file_idx: optional(def.source_file_idx),
+                annotations: annotation_set(directory.map_or(0, |it| it.class_annotations_off))?,
+                static_fields: Vec::new(),
+                instance_fields: Vec::new(),
+                direct_methods: Vec::new(),
+                virtual_methods: Vec::new(),
+            };
+            let class_data = match class_data {
+                Some(class_data) => class_data,
+                None => {
+                    classes.push(class);
+                    continue;
+                }
+            };
+            for (encoded_fields, fields) in [(&class_data.static_fields, &mut class.static_fields), (&class_data.instance_fields, &mut class.instance_fields)] {
+                let mut field_idx = 0;
+                for (i, encoded) in encoded_fields.iter().enumerate() {
+                    field_idx += encoded.field_idx_diff as u32;
+                    let static_value = if core::ptr::eq(encoded_fields, &class_data.static_fields) { static_values.get(i).cloned() } else { None };
+                    fields.push(FieldDefinition {
+                        field_idx,
+                        access_flags: encoded.access_flags as u32,
+                        static_value,
+                        annotations: annotation_set(directory.and_then(|it| it.field_annotations_off(field_idx)).unwrap_or(0))?,
+                    });
+                }
+            }
+            for (encoded_methods, methods) in [(&class_data.direct_methods, &mut class.direct_methods), (&class_data.virtual_methods, &mut class.virtual_methods)] {
+                let mut method_idx = 0;
+                for encoded in encoded_methods {
+                    method_idx += encoded.method_idx_diff as u32;
+                    let code = match encoded.code_off {
+                        0 => None,
+                        off => {
+                            let code = dex.code_item(off).ok_or_else(|| unparsed("code item", off as u32))?;
+                            let mut tries = Vec::with_capacity(code.tries.len());
+                            for try_ in &code.tries {
+                                let handler = try_.resolve_handler(code).ok_or_else(|| unparsed("catch handler", off as u32))?;
+                                tries.push(Try {
+                                    start_addr: try_.start_addr,
+                                    insn_count: try_.insn_count,
+                                    handlers: handler.handlers.iter().map(|it| (it.type_idx as u32, it.addr as u32)).collect(),
+                                    catch_all_addr: handler.catch_all_addr.map(|it| it as u32),
+                                });
+                            }
+                            let debug_info = match code.debug_info_off {
+                                0 => None,
+                                off => Some(dex.debug_info.get(&off).cloned().ok_or_else(|| unparsed("debug info", off))?),
+                            };
+                            Some(Code {
+                                registers_size: code.registers_size,
+                                ins_size: code.ins_size,
+                                outs_size: code.outs_size,
+                                insns: code.insns.clone(),
+                                tries,
+                                debug_info,
+                            })
+                        }
+                    };
+                    let mut parameter_annotations = Vec::new();
+                    if let Some(off) = directory.and_then(|it| it.parameter_annotations_off(method_idx)) {
+                        for set_off in dex.annotation_set_ref_lists.get(&off).ok_or_else(|| unparsed("annotation set ref list", off))? {
+                            parameter_annotations.push(annotation_set(*set_off)?);
+                        }
+                    }
+                    methods.push(MethodDefinition {
+                        method_idx,
+                        access_flags: encoded.access_flags as u32,
+                        code,
+                        annotations: annotation_set(directory.and_then(|it| it.method_annotations_off(method_idx)).unwrap_or(0))?,
+                        parameter_annotations,
+                    });
+                }
+            }
+            classes.push(class);
+        }
+
+        let mut reader = Cursor::new(data);
+        let mut call_sites = Vec::new();
+        for off in raw_dex::parse_call_side_ids(&dex.map_list, &mut reader).map_err(EditError::Parse)? {
+            reader.seek(SeekFrom::Start(off.into())).map_err(EditError::Parse)?;
+            call_sites.push(raw_dex::parse_encoded_array(&mut reader).map_err(EditError::Parse)?);
+        }
+        let method_handles = raw_dex::parse_method_handles(&dex.map_list, &mut reader).map_err(EditError::Parse)?.iter()
+            .map(|it| MethodHandleRef { kind: it.method_handle_type, target: it.field_or_method_id as u32 })
+            .collect();
+
+        let editor = DexEditor {
+            version: dex.version(),
+            strings: dex.strings.clone(),
+            types: dex.type_ids.clone(),
+            protos: dex.proto_ids.iter().enumerate().map(|(i, it)| Proto {
+                shorty_idx: it.shorty_idx,
+                return_type_idx: it.return_type_idx,
+                parameters: dex.type_list(dex.proto_ids[i].parameters_off).iter().map(|it| *it as u32).collect(),
+            }).collect(),
+            fields: dex.field_ids.iter().map(|it| FieldRef { class_idx: it.class_idx.into(), type_idx: it.type_idx.into(), name_idx: it.name_idx }).collect(),
+            methods: dex.method_ids.iter().map(|it| MethodRef { class_idx: it.class_idx.into(), proto_idx: it.proto_idx.into(), name_idx: it.name_idx }).collect(),
+            classes,
+            call_sites,
+            method_handles,
+            unknown_sections: dex.unknown_sections.clone(),
+        };
+        let sorted = [
+            ("string_ids", is_sorted(&editor.strings, |a, b| cmp_utf16(a, b))),
+            ("type_ids", is_sorted(&editor.types, Ord::cmp)),
+            ("proto_ids", is_sorted(&editor.protos, cmp_proto)),
+            ("field_ids", is_sorted(&editor.fields, cmp_field)),
+            ("method_ids", is_sorted(&editor.methods, cmp_method)),
+        ];
+        if let Some((section, _)) = sorted.iter().find(|it| !it.1) {
+            return Err(EditError::Unsupported(format!("The {} are not sorted", section)));
+        }
+        Ok(editor)
+    }
+
+    pub fn string(&self, string_idx: u32) -> &str {
+        &self.strings[string_idx as usize]
+    }
+
+    pub fn type_descriptor(&self, type_idx: u32) -> &str {
+        self.string(self.types[type_idx as usize])
+    }
+
+    pub fn string_idx(&self, value: &str) -> Option<u32> {
+        self.strings.binary_search_by(|it| cmp_utf16(it, value)).ok().map(|it| it as u32)
+    }
+
+    pub fn type_idx(&self, descriptor: &str) -> Option<u32> {
+        let string_idx = self.string_idx(descriptor)?;
+        self.types.binary_search(&string_idx).ok().map(|it| it as u32)
+    }
+
+    /// Index into `classes` of the definition of the class
+    pub fn class_position(&self, descriptor: &str) -> Option<usize> {
+        let type_idx = self.type_idx(descriptor)?;
+        self.classes.iter().position(|it| it.class_idx == type_idx)
+    }
+
+    fn proto_symbol(&self, proto_idx: u32) -> (String, Vec<String>) {
+        let proto = &self.protos[proto_idx as usize];
+        let parameters = proto.parameters.iter().map(|it| self.type_descriptor(*it).to_owned()).collect();
+        (self.type_descriptor(proto.return_type_idx).to_owned(), parameters)
+    }
+
+    /// The id `idx` of the table `kind` by its contents, None for call sites and method handles
+    fn symbol(&self, kind: IdKind, idx: u32) -> Option<Symbol> {
+        Some(match kind {
+            IdKind::String => Symbol::String(self.string(idx).to_owned()),
+            IdKind::Type => Symbol::Type(self.type_descriptor(idx).to_owned()),
+            IdKind::Proto => {
+                let (return_type, parameters) = self.proto_symbol(idx);
+                Symbol::Proto(return_type, parameters)
+            }
+            IdKind::Field => {
+                let field = self.fields[idx as usize];
+                Symbol::Field(self.type_descriptor(field.class_idx).to_owned(), self.string(field.name_idx).to_owned(), self.type_descriptor(field.type_idx).to_owned())
+            }
+            IdKind::Method => {
+                let method = self.methods[idx as usize];
+                let (return_type, parameters) = self.proto_symbol(method.proto_idx);
+                Symbol::Method(self.type_descriptor(method.class_idx).to_owned(), self.string(method.name_idx).to_owned(), return_type, parameters)
+            }
+            IdKind::CallSite | IdKind::MethodHandle => return None,
+        })
+    }
+
+    fn find_proto(&self, return_type: &str, parameters: &[String]) -> Option<u32> {
+        let key = Proto {
+            shorty_idx: 0,
+            return_type_idx: self.type_idx(return_type)?,
+            parameters: parameters.iter().map(|it| self.type_idx(it)).collect::<Option<_>>()?,
+        };
+        self.protos.binary_search_by(|it| cmp_proto(it, &key)).ok().map(|it| it as u32)
+    }
+
+    /// Index of the id with the contents `symbol`
+    fn find(&self, symbol: &Symbol) -> Option<u32> {
+        match symbol {
+            Symbol::String(value) => self.string_idx(value),
+            Symbol::Type(descriptor) => self.type_idx(descriptor),
+            Symbol::Proto(return_type, parameters) => self.find_proto(return_type, parameters),
+            Symbol::Field(class, name, type_) => {
+                let key = FieldRef { class_idx: self.type_idx(class)?, type_idx: self.type_idx(type_)?, name_idx: self.string_idx(name)? };
+                self.fields.binary_search_by(|it| cmp_field(it, &key)).ok().map(|it| it as u32)
+            }
+            Symbol::Method(class, name, return_type, parameters) => {
+                let key = MethodRef { class_idx: self.type_idx(class)?, proto_idx: self.find_proto(return_type, parameters)?, name_idx: self.string_idx(name)? };
+                self.methods.binary_search_by(|it| cmp_method(it, &key)).ok().map(|it| it as u32)
+            }
+        }
+    }
+
+    /// Number of entries of each table
+    fn table_sizes(&self) -> impl Fn(IdKind) -> usize {
+        let sizes = [self.strings.len(), self.types.len(), self.protos.len(), self.fields.len(), self.methods.len(), self.call_sites.len(), self.method_handles.len()];
+        move |kind| match kind {
+            IdKind::String => sizes[0],
+            IdKind::Type => sizes[1],
+            IdKind::Proto => sizes[2],
+            IdKind::Field => sizes[3],
+            IdKind::Method => sizes[4],
+            IdKind::CallSite => sizes[5],
+            IdKind::MethodHandle => sizes[6],
+        }
+    }
+
+    /// Fails for the first of the `references` past the end of its table
+    fn check_indices(&self, references: impl IntoIterator<Item = (IdKind, u32)>) -> Result<(), EditError> {
+        let size = self.table_sizes();
+        match references.into_iter().find(|(kind, idx)| *idx as usize >= size(*kind)) {
+            Some((kind, idx)) => Err(EditError::IndexOutOfBounds(kind, idx)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails for the first reference of everything but the id tables (which the parser checks) past the
+    /// end of its table
+    fn check_references(&mut self) -> Result<(), EditError> {
+        let mut references = BTreeSet::new();
+        self.visit_references(&mut |kind, idx| {
+            references.insert((kind, *idx));
+        })?;
+        self.check_indices(references)
+    }
+
+    /// Visits the indices of everything but the id tables
+    fn visit_references(&mut self, f: &mut Visitor) -> Result<(), EditError> {
+        for class in &mut self.classes {
+            visit_class(class, f)?;
+        }
+        for values in &mut self.call_sites {
+            values.iter_mut().for_each(|it| visit_value(it, f));
+        }
+        for handle in &mut self.method_handles {
+            f(handle.target_kind(), &mut handle.target);
+        }
+        Ok(())
+    }
+
+    /// Runs `edit` on a copy of the editor, which replaces it only if the edit succeeds. Edits fail
+    /// midway (e.g. when a table overflows after others were extended), this leaves the editor as it was.
+    fn transaction<T>(&mut self, edit: impl FnOnce(&mut DexEditor) -> Result<T, EditError>) -> Result<T, EditError> {
+        let mut edited = self.clone();
+        let result = edit(&mut edited)?;
+        *self = edited;
+        Ok(result)
+    }
+
+    /// Updates the references to the ids moved by an edit of the id tables
+    fn apply(&mut self, remap: &Remap) -> Result<(), EditError> {
+        if remap.is_identity() {
+            return Ok(());
+        }
+        self.visit_references(&mut |kind, idx| *idx = remap.get(kind, *idx))
+    }
+
+    /// Adds the ids (and the ids they consist of) that are not in the tables yet at their sorted
+    /// positions and updates all references, returns the moves of the existing ids. Leaves the editor
+    /// inconsistent if it fails, callers run it in a transaction.
+    fn add_symbols(&mut self, symbols: &[Symbol]) -> Result<Remap, EditError> {
+        let mut strings = Vec::new();
+        let mut types = Vec::new();
+        let mut protos = Vec::new();
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        for symbol in symbols {
+            match symbol {
+                Symbol::String(value) => strings.push(value.clone()),
+                Symbol::Type(descriptor) => types.push(descriptor.clone()),
+                Symbol::Proto(return_type, parameters) => protos.push((return_type.clone(), parameters.clone())),
+                Symbol::Field(class, name, type_) => {
+                    types.extend([class.clone(), type_.clone()]);
+                    strings.push(name.clone());
+                    fields.push((class, name, type_));
+                }
+                Symbol::Method(class, name, return_type, parameters) => {
+                    types.push(class.clone());
+                    strings.push(name.clone());
+                    protos.push((return_type.clone(), parameters.clone()));
+                    methods.push((class, name, return_type, parameters));
+                }
+            }
+        }
+        for (return_type, parameters) in &protos {
+            types.push(return_type.clone());
+            types.extend(parameters.iter().cloned());
+            strings.push(shorty(return_type, parameters));
+        }
+        strings.extend(types.iter().cloned());
+
+        let mut remap = Remap { strings: merge_sorted(&mut self.strings, strings, |a, b| cmp_utf16(a, b)), ..Remap::default() };
+        for idx in &mut self.types {
+            *idx = remap.strings.get(*idx);
+        }
+        for proto in &mut self.protos {
+            proto.shorty_idx = remap.strings.get(proto.shorty_idx);
+        }
+        for field in &mut self.fields {
+            field.name_idx = remap.strings.get(field.name_idx);
+        }
+        for method in &mut self.methods {
+            method.name_idx = remap.strings.get(method.name_idx);
+        }
+
+        let added_types = types.iter().map(|it| self.string_idx(it).expect("Added before")).collect();
+        remap.types = merge_sorted(&mut self.types, added_types, Ord::cmp);
+        if self.types.len() > 0x10000 {
+            return Err(EditError::TooManyIds(IdKind::Type));
+        }
+        for proto in &mut self.protos {
+            proto.return_type_idx = remap.types.get(proto.return_type_idx);
+            proto.parameters.iter_mut().for_each(|it| *it = remap.types.get(*it));
+        }
+        for field in &mut self.fields {
+            field.class_idx = remap.types.get(field.class_idx);
+            field.type_idx = remap.types.get(field.type_idx);
+        }
+        for method in &mut self.methods {
+            method.class_idx = remap.types.get(method.class_idx);
+        }
+
+        let added_protos = protos.iter().map(|(return_type, parameters)| Proto {
+            shorty_idx: self.string_idx(&shorty(return_type, parameters)).expect("Added before"),
+            return_type_idx: self.type_idx(return_type).expect("Added before"),
+            parameters: parameters.iter().map(|it| self.type_idx(it).expect("Added before")).collect(),
+        }).collect();
+        remap.protos = merge_sorted(&mut self.protos, added_protos, cmp_proto);
+        if self.protos.len() > 0x10000 {
+            return Err(EditError::TooManyIds(IdKind::Proto));
+        }
+        for method in &mut self.methods {
+            method.proto_idx = remap.protos.get(method.proto_idx);
+        }
+
+        let added_fields = fields.iter().map(|(class, name, type_)| FieldRef {
+            class_idx: self.type_idx(class).expect("Added before"),
+            type_idx: self.type_idx(type_).expect("Added before"),
+            name_idx: self.string_idx(name).expect("Added before"),
+        }).collect();
+        remap.fields = merge_sorted(&mut self.fields, added_fields, cmp_field);
+        let added_methods = methods.iter().map(|(class, name, return_type, parameters)| MethodRef {
+            class_idx: self.type_idx(class).expect("Added before"),
+            proto_idx: self.find_proto(return_type, parameters).expect("Added before"),
+            name_idx: self.string_idx(name).expect("Added before"),
+        }).collect();
+        remap.methods = merge_sorted(&mut self.methods, added_methods, cmp_method);
+
+        self.apply(&remap)?;
+        Ok(remap)
+    }
+
+    /// Adds the id `symbol` unless the table has it, returns its index and the moves of the existing ids
+    fn add_symbol(&mut self, symbol: Symbol) -> Result<(u32, Remap), EditError> {
+        self.transaction(|editor| {
+            let remap = editor.add_symbols(core::slice::from_ref(&symbol))?;
+            Ok((editor.find(&symbol).expect("Added before"), remap))
+        })
+    }
+
+    /// Adds the string at its position in the string table (sorted by UTF-16 code units) unless the
+    /// table has it. Returns its index and the moves of the existing ids, which are applied to all
+    /// references in this editor.
+    pub fn add_string(&mut self, value: &str) -> Result<(u32, Remap), EditError> {
+        self.add_symbol(Symbol::String(value.to_owned()))
+    }
+
+    /// Adds the type (and its descriptor), see add_string
+    pub fn add_type(&mut self, descriptor: &str) -> Result<(u32, Remap), EditError> {
+        self.add_symbol(Symbol::Type(checked_descriptor(descriptor)?))
+    }
+
+    /// Adds the prototype (and its shorty and types), see add_string
+    pub fn add_proto(&mut self, return_type: &str, parameters: &[&str]) -> Result<(u32, Remap), EditError> {
+        let parameters = parameters.iter().map(|it| checked_descriptor(it)).collect::<Result<_, _>>()?;
+        self.add_symbol(Symbol::Proto(checked_descriptor(return_type)?, parameters))
+    }
+
+    /// Adds the field id `class->name:field_type` (and the ids it consists of), see add_string
+    pub fn add_field_id(&mut self, class: &str, name: &str, field_type: &str) -> Result<(u32, Remap), EditError> {
+        self.add_symbol(Symbol::Field(checked_descriptor(class)?, name.to_owned(), checked_descriptor(field_type)?))
+    }
+
+    /// Adds the method id `class->name(signature)`, e.g. `Lcom/example/Foo;`, `run`, `(I)V` (and the ids
+    /// it consists of), see add_string
+    pub fn add_method_id(&mut self, class: &str, name: &str, signature: &str) -> Result<(u32, Remap), EditError> {
+        checked_descriptor(class)?;
+        self.add_symbol(method_symbol(class, name, signature)?)
+    }
+
+    /// Adds the `referenced` ids of `source` with the call sites and method handles they reference, returns
+    /// the moves of the existing ids and the indices of the ids of the source in this file. Leaves the
+    /// editor inconsistent if it fails, see add_symbols.
+    fn import(&mut self, source: &DexEditor, mut referenced: BTreeSet<(IdKind, u32)>) -> Result<(Remap, Remap), EditError> {
+        source.check_indices(referenced.iter().copied())?;
+        let call_sites: Vec<u32> = referenced.iter().filter(|it| it.0 == IdKind::CallSite).map(|it| it.1).collect();
+        for call_site in &call_sites {
+            for value in &mut source.call_sites[*call_site as usize].clone() {
+                visit_value(value, &mut |kind, idx| {
+                    referenced.insert((kind, *idx));
+                });
+            }
+        }
+        source.check_indices(referenced.iter().copied())?;
+        let method_handles: Vec<u32> = referenced.iter().filter(|it| it.0 == IdKind::MethodHandle).map(|it| it.1).collect();
+        for method_handle in &method_handles {
+            let handle = source.method_handles[*method_handle as usize];
+            referenced.insert((handle.target_kind(), handle.target));
+        }
+        source.check_indices(referenced.iter().copied())?;
+
+        let symbols: Vec<(IdKind, u32, Symbol)> = referenced.iter()
+            .filter_map(|(kind, idx)| source.symbol(*kind, *idx).map(|it| (*kind, *idx, it)))
+            .collect();
+        let remap = self.add_symbols(&symbols.iter().map(|it| it.2.clone()).collect::<Vec<_>>())?;
+
+        let mut to_self = Remap::default();
+        let sizes = [
+            (IdKind::String, source.strings.len()), (IdKind::Type, source.types.len()), (IdKind::Proto, source.protos.len()),
+            (IdKind::Field, source.fields.len()), (IdKind::Method, source.methods.len()),
+            (IdKind::CallSite, source.call_sites.len()), (IdKind::MethodHandle, source.method_handles.len()),
+        ];
+        for (kind, size) in sizes {
+            *to_self.map_mut(kind) = IndexMap(vec![NO_INDEX; size]);
+        }
+        for (kind, idx, symbol) in &symbols {
+            to_self.map_mut(*kind).0[*idx as usize] = self.find(symbol).expect("Added before");
+        }
+        for method_handle in method_handles {
+            let mut handle = source.method_handles[method_handle as usize];
+            handle.target = to_self.get(handle.target_kind(), handle.target);
+            let idx = self.method_handles.iter().position(|it| *it == handle).unwrap_or_else(|| {
+                self.method_handles.push(handle);
+                self.method_handles.len() - 1
+            });
+            to_self.method_handles.0[method_handle as usize] = idx as u32;
+        }
+        for call_site in call_sites {
+            let mut values = source.call_sites[call_site as usize].clone();
+            values.iter_mut().for_each(|it| visit_value(it, &mut |kind, idx| *idx = to_self.get(kind, *idx)));
+            to_self.call_sites.0[call_site as usize] = self.call_sites.len() as u32;
+            self.call_sites.push(values);
+        }
+        Ok((remap, to_self))
+    }
+
+    /// Copies the classes `descriptors` of `source` (e.g. a dex file built by d8 or the CodeBuilder)
+    /// into this file with the ids, call sites and method handles they reference, returns the moves of
+    /// the existing ids
+    pub fn insert_classes(&mut self, source: &DexEditor, descriptors: &[&str]) -> Result<Remap, EditError> {
+        let mut classes = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors {
+            if self.class_position(descriptor).is_some() || classes.iter().any(|it: &ClassDefinition| source.type_descriptor(it.class_idx) == *descriptor) {
+                return Err(EditError::ClassExists((*descriptor).to_owned()));
+            }
+            let position = source.class_position(descriptor).ok_or_else(|| EditError::ClassNotFound((*descriptor).to_owned()))?;
+            classes.push(source.classes[position].clone());
+        }
+
+        let mut referenced = BTreeSet::new();
+        for class in &mut classes {
+            visit_class(class, &mut |kind, idx| {
+                referenced.insert((kind, *idx));
+            })?;
+        }
+        self.transaction(|editor| {
+            let (remap, to_self) = editor.import(source, referenced)?;
+            for mut class in classes {
+                visit_class(&mut class, &mut |kind, idx| *idx = to_self.get(kind, *idx))?;
+                editor.classes.push(class);
+            }
+            Ok(remap)
+        })
+    }
+
+    /// Copies the class `descriptor` of `source` into this file, see insert_classes
+    pub fn insert_class(&mut self, source: &DexEditor, descriptor: &str) -> Result<Remap, EditError> {
+        self.insert_classes(source, &[descriptor])
+    }
+
+    /// Index of the method `class->name(signature)`, e.g. `Lcom/example/Foo;`, `run`, `(I)V`
+    pub fn method_idx(&self, class: &str, name: &str, signature: &str) -> Option<u32> {
+        self.find(&method_symbol(class, name, signature).ok()?)
+    }
+
+    /// Adds the method `class->name(signature)` to the class, or replaces its definition if the class
+    /// defines it already. The indices of `method` (other than its method_idx, which is set) refer to
+    /// the ids before the edit. Returns the moves of the existing ids.
+    pub fn set_method(&mut self, class: &str, name: &str, signature: &str, method: MethodDefinition) -> Result<Remap, EditError> {
+        self.transaction(|editor| editor.place_method(class, name, signature, method))
+    }
+
+    /// set_method without the transaction
+    fn place_method(&mut self, class: &str, name: &str, signature: &str, mut method: MethodDefinition) -> Result<Remap, EditError> {
+        let symbol = method_symbol(class, name, signature)?;
+        self.class_position(class).ok_or_else(|| EditError::ClassNotFound(class.to_owned()))?;
+        let remap = self.add_symbols(core::slice::from_ref(&symbol))?;
+        if !remap.is_identity() {
+            visit_method(&mut method, &mut |kind, idx| *idx = remap.get(kind, *idx))?;
+        }
+        method.method_idx = self.find(&symbol).expect("Added before");
+        let position = self.class_position(class).expect("Found before");
+        let definition = &mut self.classes[position];
+        definition.direct_methods.retain(|it| it.method_idx != method.method_idx);
+        definition.virtual_methods.retain(|it| it.method_idx != method.method_idx);
+        if method.access_flags & (ACC_STATIC | ACC_PRIVATE | ACC_CONSTRUCTOR) != 0 {
+            definition.direct_methods.push(method);
+        } else {
+            definition.virtual_methods.push(method);
+        }
+        Ok(remap)
+    }
+
+    /// Adds the method `class->name(signature)` defined by `source` (e.g. a dex file built by d8 from
+    /// the patched class) to the class of the same name in this file with the ids it references, or
+    /// replaces its definition. Returns the moves of the existing ids.
+    pub fn copy_method(&mut self, source: &DexEditor, class: &str, name: &str, signature: &str) -> Result<Remap, EditError> {
+        let not_found = || EditError::MethodNotFound(format!("{}->{}{}", class, name, signature));
+        let method_idx = source.find(&method_symbol(class, name, signature)?).ok_or_else(not_found)?;
+        let mut method = source.class_position(class).into_iter()
+            .flat_map(|it| source.classes[it].methods())
+            .find(|it| it.method_idx == method_idx)
+            .ok_or_else(not_found)?
+            .clone();
+        self.class_position(class).ok_or_else(|| EditError::ClassNotFound(class.to_owned()))?;
+
+        let mut referenced = BTreeSet::new();
+        visit_method(&mut method, &mut |kind, idx| {
+            referenced.insert((kind, *idx));
+        })?;
+        self.transaction(|editor| {
+            let (remap, to_self) = editor.import(source, referenced)?;
+            visit_method(&mut method, &mut |kind, idx| *idx = to_self.get(kind, *idx))?;
+            // The method id was imported with the other ids, this only places the definition
+            editor.place_method(class, name, signature, method)?;
+            Ok(remap)
+        })
+    }
+
+    fn qualified_field_name(&self, field_idx: u32) -> String {
+        let field = self.fields[field_idx as usize];
+        format!("{}->{}:{}", self.type_descriptor(field.class_idx), self.string(field.name_idx), self.type_descriptor(field.type_idx))
+    }
+
+    /// Method as in the listings, e.g. `Lcom/example/Foo;->run()V`
+    pub fn qualified_method_name(&self, method_idx: u32) -> String {
+        let method = self.methods[method_idx as usize];
+        let (return_type, parameters) = self.proto_symbol(method.proto_idx);
+        format!("{}->{}({}){}", self.type_descriptor(method.class_idx), self.string(method.name_idx), parameters.concat(), return_type)
+    }
+
+    /// Name of an id in messages, e.g. `Lcom/example/Foo;->run()V` for a method
+    fn id_name(&self, kind: IdKind, idx: u32) -> String {
+        match kind {
+            IdKind::String => self.string(idx).to_owned(),
+            IdKind::Type => self.type_descriptor(idx).to_owned(),
+            IdKind::Proto => {
+                let (return_type, parameters) = self.proto_symbol(idx);
+                format!("({}){}", parameters.concat(), return_type)
+            }
+            IdKind::Field => self.qualified_field_name(idx),
+            IdKind::Method => self.qualified_method_name(idx),
+            IdKind::CallSite | IdKind::MethodHandle => format!("{} #{}", kind, idx),
+        }
+    }
+
+    /// Adds the class or member `target` defined by this file to the removal
+    fn add_removal(&self, target: &str, removal: &mut Removal) -> Result<(), EditError> {
+        let (class, member) = match target.split_once("->") {
+            Some((class, member)) => (class, Some(member)),
+            None => (target, None),
+        };
+        let defined = self.class_position(class).map(|it| &self.classes[it]);
+        let (name, signature) = match member {
+            None => {
+                let class = defined.ok_or_else(|| EditError::ClassNotFound(target.to_owned()))?;
+                removal.classes.insert(class.class_idx);
+                return Ok(());
+            }
+            Some(member) => match member.find(['(', ':']) {
+                Some(split) => member.split_at(split),
+                None => return Err(EditError::InvalidTarget(target.to_owned())),
+            },
+        };
+        if let Some(type_) = signature.strip_prefix(':') {
+            let field_idx = self.find(&Symbol::Field(class.to_owned(), name.to_owned(), type_.to_owned()))
+                .filter(|idx| defined.is_some_and(|it| it.fields().any(|field| field.field_idx == *idx)))
+                .ok_or_else(|| EditError::FieldNotFound(target.to_owned()))?;
+            removal.fields.insert(field_idx);
+        } else {
+            let method_idx = self.find(&method_symbol(class, name, signature)?)
+                .filter(|idx| defined.is_some_and(|it| it.methods().any(|method| method.method_idx == *idx)))
+                .ok_or_else(|| EditError::MethodNotFound(target.to_owned()))?;
+            removal.methods.insert(method_idx);
+        }
+        Ok(())
+    }
+
+    fn gone(&self, removal: &Removal) -> Gone {
+        let fields: Vec<bool> = self.fields.iter().enumerate()
+            .map(|(idx, it)| removal.fields.contains(&(idx as u32)) || removal.classes.contains(&it.class_idx))
+            .collect();
+        let methods: Vec<bool> = self.methods.iter().enumerate()
+            .map(|(idx, it)| removal.methods.contains(&(idx as u32)) || removal.classes.contains(&it.class_idx))
+            .collect();
+        let method_handles = self.method_handles.iter()
+            .map(|it| if it.target_kind() == IdKind::Field { fields[it.target as usize] } else { methods[it.target as usize] })
+            .collect();
+        let mut gone = Gone { types: removal.classes.clone(), fields, methods, method_handles, call_sites: Vec::new() };
+        gone.call_sites = self.call_sites.iter().map(|values| {
+            let mut hit = false;
+            for value in &mut values.clone() {
+                visit_value(value, &mut |kind, idx| hit |= gone.contains(kind, *idx));
+            }
+            hit
+        }).collect();
+        gone
+    }
+
+    /// Finds the references of the `classes` (taken out of this editor) to the removed classes and
+    /// members and handles them per `dangling`, returns whether more classes or members are removed
+    fn handle_dangling(&self, classes: &mut [ClassDefinition], removal: &mut Removal, stubbed: &mut BTreeSet<u32>, dangling: Dangling) -> Result<bool, EditError> {
+        let gone = self.gone(removal);
+        let hit = Cell::new(None);
+        let mut check = |kind: IdKind, idx: &mut u32| {
+            if hit.get().is_none() && gone.contains(kind, *idx) {
+                hit.set(Some((kind, *idx)));
+            }
+        };
+        let mut changed = false;
+        for class in classes.iter_mut() {
+            if removal.classes.contains(&class.class_idx) {
+                continue;
+            }
+            for type_idx in class.superclass_idx.iter_mut().chain(&mut class.interfaces) {
+                check(IdKind::Type, type_idx);
+            }
+            visit_annotations(&mut class.annotations, &mut check);
+            if let Some((kind, idx)) = hit.take() {
+                if dangling != Dangling::Cascade {
+                    return Err(EditError::Dangling(self.id_name(kind, idx), self.type_descriptor(class.class_idx).to_owned()));
+                }
+                removal.classes.insert(class.class_idx);
+                changed = true;
+                continue;
+            }
+            for field in class.static_fields.iter_mut().chain(&mut class.instance_fields) {
+                if removal.fields.contains(&field.field_idx) {
+                    continue;
+                }
+                if let Some(value) = &mut field.static_value {
+                    visit_value(value, &mut check);
+                }
+                visit_annotations(&mut field.annotations, &mut check);
+                if let Some((kind, idx)) = hit.take() {
+                    if dangling != Dangling::Cascade {
+                        return Err(EditError::Dangling(self.id_name(kind, idx), self.qualified_field_name(field.field_idx)));
+                    }
+                    removal.fields.insert(field.field_idx);
+                    changed = true;
+                }
+            }
+            for method in class.direct_methods.iter_mut().chain(&mut class.virtual_methods) {
+                if removal.methods.contains(&method.method_idx) {
+                    continue;
+                }
+                visit_annotations(&mut method.annotations, &mut check);
+                for annotations in &mut method.parameter_annotations {
+                    visit_annotations(annotations, &mut check);
+                }
+                let in_code = hit.get().is_none();
+                if let (Some(code), true) = (&mut method.code, in_code) {
+                    visit_code(code, method.method_idx, &mut check)?;
+                }
+                if let Some((kind, idx)) = hit.take() {
+                    match dangling {
+                        Dangling::Stub if in_code => {
+                            stubbed.insert(method.method_idx);
+                        }
+                        Dangling::Cascade => {
+                            removal.methods.insert(method.method_idx);
+                            changed = true;
+                        }
+                        _ => return Err(EditError::Dangling(self.id_name(kind, idx), self.qualified_method_name(method.method_idx))),
+                    }
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Removes the classes and members `targets` (e.g. `Lcom/example/Foo;`, `Lcom/example/Foo;->run()V`
+    /// or `Lcom/example/Foo;->name:I`) defined by this file. The references to them (by instructions,
+    /// catch clauses, supertypes, annotations, static values, method handles and call sites, not by
+    /// the signatures of other members) are handled per `dangling`. The ids only the removed items
+    /// referenced are dropped, see compact.
+    pub fn strip(&mut self, targets: &[&str], dangling: Dangling) -> Result<Stripped, EditError> {
+        self.transaction(|editor| editor.strip_unchecked(targets, dangling))
+    }
+
+    /// strip without the transaction
+    fn strip_unchecked(&mut self, targets: &[&str], dangling: Dangling) -> Result<Stripped, EditError> {
+        self.check_references()?;
+        let mut removal = Removal::default();
+        for target in targets {
+            self.add_removal(target, &mut removal)?;
+        }
+        let mut stubbed = BTreeSet::new();
+        let mut classes = core::mem::take(&mut self.classes);
+        let result = loop {
+            match self.handle_dangling(&mut classes, &mut removal, &mut stubbed, dangling) {
+                Ok(true) => continue,
+                result => break result,
+            }
+        };
+        self.classes = classes;
+        result?;
+
+        let mut items = Vec::new();
+        self.classes.retain(|class| !removal.classes.contains(&class.class_idx));
+        for class in &mut self.classes {
+            for fields in [&mut class.static_fields, &mut class.instance_fields] {
+                for field in fields.iter().filter(|it| removal.fields.contains(&it.field_idx)) {
+                    items.push((field.field_idx, IdKind::Field, StripAction::Removed));
+                }
+                fields.retain(|it| !removal.fields.contains(&it.field_idx));
+            }
+            for methods in [&mut class.direct_methods, &mut class.virtual_methods] {
+                for method in methods.iter_mut() {
+                    if removal.methods.contains(&method.method_idx) {
+                        items.push((method.method_idx, IdKind::Method, StripAction::Removed));
+                    } else if let (true, Some(code)) = (stubbed.contains(&method.method_idx), &mut method.code) {
+                        let method_idx = method.method_idx;
+                        let registers_size = code.ins_size.checked_add(1)
+                            .ok_or_else(|| EditError::Unsupported(format!("No register left for the stub of method #{}", method_idx)))?;
+                        // const/4 v0, 0; throw v0
+                        *code = Code { registers_size, ins_size: code.ins_size, outs_size: 0, insns: vec![0x0012, 0x0027], tries: Vec::new(), debug_info: None };
+                        items.push((method.method_idx, IdKind::Method, StripAction::Stubbed));
+                    }
+                }
+                methods.retain(|it| !removal.methods.contains(&it.method_idx));
+            }
+        }
+        let mut items: Vec<(String, StripAction)> = removal.classes.iter().map(|it| (self.type_descriptor(*it).to_owned(), StripAction::Removed))
+            .chain(items.into_iter().map(|(idx, kind, action)| (self.id_name(kind, idx), action)))
+            .collect();
+        items.sort();
+        let remap = self.compact()?;
+        Ok(Stripped { items, remap })
+    }
+
+    /// Drops the ids, call sites and method handles nothing references (anymore), returns the moves
+    /// of the remaining ids
+    pub fn compact(&mut self) -> Result<Remap, EditError> {
+        self.check_references()?;
+        let mut used = BTreeSet::new();
+        for class in &mut self.classes {
+            visit_class(class, &mut |kind, idx| {
+                used.insert((kind, *idx));
+            })?;
+        }
+        let call_sites: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::CallSite).map(|it| it.1).collect();
+        for call_site in call_sites {
+            for value in &mut self.call_sites[call_site as usize].clone() {
+                visit_value(value, &mut |kind, idx| {
+                    used.insert((kind, *idx));
+                });
+            }
+        }
+        let method_handles: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::MethodHandle).map(|it| it.1).collect();
+        for method_handle in method_handles {
+            let handle = self.method_handles[method_handle as usize];
+            used.insert((handle.target_kind(), handle.target));
+        }
+        // The ids the used ids consist of
+        let methods: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::Method).map(|it| it.1).collect();
+        for method in methods.iter().map(|it| self.methods[*it as usize]) {
+            used.extend([(IdKind::Type, method.class_idx), (IdKind::Proto, method.proto_idx), (IdKind::String, method.name_idx)]);
+        }
+        let fields: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::Field).map(|it| it.1).collect();
+        for field in fields.iter().map(|it| self.fields[*it as usize]) {
+            used.extend([(IdKind::Type, field.class_idx), (IdKind::Type, field.type_idx), (IdKind::String, field.name_idx)]);
+        }
+        let protos: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::Proto).map(|it| it.1).collect();
+        for proto in protos.iter().map(|it| &self.protos[*it as usize]) {
+            used.extend([(IdKind::String, proto.shorty_idx), (IdKind::Type, proto.return_type_idx)]);
+            used.extend(proto.parameters.iter().map(|it| (IdKind::Type, *it)));
+        }
+        let types: Vec<u32> = used.iter().filter(|it| it.0 == IdKind::Type).map(|it| it.1).collect();
+        for type_ in types {
+            used.insert((IdKind::String, self.types[type_ as usize]));
+        }
+
+        let remap = Remap {
+            strings: used_map(self.strings.len(), &used, IdKind::String),
+            types: used_map(self.types.len(), &used, IdKind::Type),
+            protos: used_map(self.protos.len(), &used, IdKind::Proto),
+            fields: used_map(self.fields.len(), &used, IdKind::Field),
+            methods: used_map(self.methods.len(), &used, IdKind::Method),
+            call_sites: used_map(self.call_sites.len(), &used, IdKind::CallSite),
+            method_handles: used_map(self.method_handles.len(), &used, IdKind::MethodHandle),
+        };
+        retain_mapped(&mut self.strings, &remap.strings);
+        retain_mapped(&mut self.types, &remap.types);
+        retain_mapped(&mut self.protos, &remap.protos);
+        retain_mapped(&mut self.fields, &remap.fields);
+        retain_mapped(&mut self.methods, &remap.methods);
+        retain_mapped(&mut self.call_sites, &remap.call_sites);
+        retain_mapped(&mut self.method_handles, &remap.method_handles);
+        for idx in &mut self.types {
+            *idx = remap.strings.get(*idx);
+        }
+        for proto in &mut self.protos {
+            proto.shorty_idx = remap.strings.get(proto.shorty_idx);
+            proto.return_type_idx = remap.types.get(proto.return_type_idx);
+            proto.parameters.iter_mut().for_each(|it| *it = remap.types.get(*it));
+        }
+        for field in &mut self.fields {
+            *field = FieldRef { class_idx: remap.types.get(field.class_idx), type_idx: remap.types.get(field.type_idx), name_idx: remap.strings.get(field.name_idx) };
+        }
+        for method in &mut self.methods {
+            *method = MethodRef { class_idx: remap.types.get(method.class_idx), proto_idx: remap.protos.get(method.proto_idx), name_idx: remap.strings.get(method.name_idx) };
+        }
+        self.apply(&remap)?;
+        Ok(remap)
+    }
+
+    /// Positions in `classes` with the superclass and interfaces of each class before it, as the
+    /// runtime requires
+    fn class_order(&self) -> Vec<usize> {
+        let mut positions = BTreeMap::new();
+        for (i, class) in self.classes.iter().enumerate() {
+            positions.entry(class.class_idx).or_insert(i);
+        }
+        fn visit(i: usize, classes: &[ClassDefinition], positions: &BTreeMap<u32, usize>, visited: &mut [bool], order: &mut Vec<usize>) {
+            if visited[i] {
+                return;
+            }
+            visited[i] = true;
+            let class = &classes[i];
+            for supertype in class.superclass_idx.iter().chain(&class.interfaces) {
+                if let Some(position) = positions.get(supertype) {
+                    visit(*position, classes, positions, visited, order);
+                }
+            }
+            order.push(i);
+        }
+        let mut visited = vec![false; self.classes.len()];
+        let mut order = Vec::with_capacity(self.classes.len());
+        for i in 0..self.classes.len() {
+            visit(i, &self.classes, &positions, &mut visited, &mut order);
+        }
+        order
+    }
+
+    /// Writes the dex file
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EditError> {
+        Writer::write(self)
+    }
+}
+
+/// Value of a static field without initial value, by the descriptor of its type
+fn default_value(descriptor: &str) -> EncodedValue {
+    match descriptor.as_bytes().first() {
+        Some(b'Z') => EncodedValue::Boolean(false),
+        Some(b'B') => EncodedValue::Byte(0),
+        Some(b'S') => EncodedValue::Short(0),
+        Some(b'C') => EncodedValue::Char(0),
+        Some(b'I') => EncodedValue::Int(0),
+        Some(b'J') => EncodedValue::Long(0),
+        Some(b'F') => EncodedValue::Float(0.0),
+        Some(b'D') => EncodedValue::Double(0.0),
+        _ => EncodedValue::Null,
+    }
+}
+
+fn write_sleb128(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_uleb128p1(out: &mut Vec<u8>, idx: OptionalIdx) {
+    write_uleb128(out, idx.get().map_or(0, |it| it + 1));
+}
+
+/// Bytes of an annotation_item, with the elements sorted by name as required
+fn annotation_item(item: &AnnotationItem) -> Vec<u8> {
+    let mut annotation = item.annotation.clone();
+    annotation.elements.sort_by_key(|it| it.name_idx);
+    let mut out = vec![match item.visibility {
+        Visibility::VisibilityBuild => 0x00,
+        Visibility::VisibilityRuntime => 0x01,
+        Visibility::VisibilitySystem => 0x02,
+    }];
+    annotation.write(&mut out);
+    out
+}
+
+fn debug_info_item(debug_info: &DebugInfoItem) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_uleb128(&mut out, debug_info.line_start as u32);
+    write_uleb128(&mut out, debug_info.parameter_names.len() as u32);
+    for name in &debug_info.parameter_names {
+        write_uleb128p1(&mut out, *name);
+    }
+    for insn in &debug_info.bytecode {
+        match insn {
+            DebugInstruction::AdvancePc(addr_diff) => {
+                out.push(0x01);
+                write_uleb128(&mut out, *addr_diff as u32);
+            }
+            DebugInstruction::AdvanceLine(line_diff) => {
+                out.push(0x02);
+                write_sleb128(&mut out, *line_diff as i32);
+            }
+            DebugInstruction::StartLocal { register_num, name_idx, type_idx } => {
+                out.push(0x03);
+                write_uleb128(&mut out, *register_num as u32);
+                write_uleb128p1(&mut out, *name_idx);
+                write_uleb128p1(&mut out, *type_idx);
+            }
+            DebugInstruction::StartLocalExtended { register_num, name_idx, type_idx, sig_idx } => {
+                out.push(0x04);
+                write_uleb128(&mut out, *register_num as u32);
+                write_uleb128p1(&mut out, *name_idx);
+                write_uleb128p1(&mut out, *type_idx);
+                write_uleb128p1(&mut out, *sig_idx);
+            }
+            DebugInstruction::EndLocal(register_num) => {
+                out.push(0x05);
+                write_uleb128(&mut out, *register_num as u32);
+            }
+            DebugInstruction::RestartLocal(register_num) => {
+                out.push(0x06);
+                write_uleb128(&mut out, *register_num as u32);
+            }
+            DebugInstruction::SetPrologueEnd => out.push(0x07),
+            DebugInstruction::SetEpilogueBegin => out.push(0x08),
+            DebugInstruction::SetFile(name_idx) => {
+                out.push(0x09);
+                write_uleb128p1(&mut out, *name_idx);
+            }
+            DebugInstruction::Special(opcode) => out.push(*opcode),
+        }
+    }
+    out.push(0x00);
+    out
+}
+
+/// Catch clauses and catch-all address of a try block
+type Handler<'a> = (&'a [(u32, u32)], Option<u32>);
+
+/// Layout of the written file
+struct Writer {
+    out: Vec<u8>,
+    /// (item type, size, offset) of the sections
+    map: Vec<(u16, u32, u32)>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.out.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.out.extend(value.to_le_bytes());
+    }
+
+    fn align(&mut self) {
+        while !self.out.len().is_multiple_of(4) {
+            self.out.push(0);
+        }
+    }
+
+    fn offset(&self) -> u32 {
+        self.out.len() as u32
+    }
+
+    /// Adds the section of `size` items starting at `start` to the map list, if not empty
+    fn section(&mut self, item_type: u16, size: usize, start: u32) {
+        if size > 0 {
+            self.map.push((item_type, size as u32, start));
+        }
+    }
+
+    /// Writes the items of the `kind` once each, returns the offset of each by its bytes
+    fn unique_items(&mut self, item_type: u16, items: impl IntoIterator<Item = Vec<u8>>, aligned: bool) -> BTreeMap<Vec<u8>, u32> {
+        let mut offsets = BTreeMap::new();
+        if aligned {
+            self.align();
+        }
+        let start = self.offset();
+        for item in items {
+            if offsets.contains_key(&item) {
+                continue;
+            }
+            if aligned {
+                self.align();
+            }
+            offsets.insert(item.clone(), self.offset());
+            self.out.extend(item);
+        }
+        self.section(item_type, offsets.len(), start);
+        offsets
+    }
+
+    fn write(editor: &DexEditor) -> Result<Vec<u8>, EditError> {
+        for (kind, size) in [(IdKind::Type, editor.types.len()), (IdKind::Proto, editor.protos.len())] {
+            if size > 0x10000 {
+                return Err(EditError::TooManyIds(kind));
+            }
+        }
+        if let Some(handle) = editor.method_handles.iter().find(|it| it.target > 0xffff) {
+            return Err(EditError::TooManyIds(handle.target_kind()));
+        }
+        let order = editor.class_order();
+        let classes: Vec<&ClassDefinition> = order.iter().map(|it| &editor.classes[*it]).collect();
+        let type_descriptor = |type_idx: u32| editor.type_descriptor(type_idx);
+
+        let string_ids_off = HEADER_SIZE;
+        let type_ids_off = string_ids_off + 4 * editor.strings.len() as u32;
+        let proto_ids_off = type_ids_off + 4 * editor.types.len() as u32;
+        let field_ids_off = proto_ids_off + 12 * editor.protos.len() as u32;
+        let method_ids_off = field_ids_off + 8 * editor.fields.len() as u32;
+        let class_defs_off = method_ids_off + 8 * editor.methods.len() as u32;
+        let call_site_ids_off = class_defs_off + 32 * classes.len() as u32;
+        let method_handles_off = call_site_ids_off + 4 * editor.call_sites.len() as u32;
+        let data_off = method_handles_off + 8 * editor.method_handles.len() as u32;
+        let mut w = Writer { out: vec![0; data_off as usize], map: Vec::new() };
+
+        let string_data = editor.strings.iter().map(|value| {
+            let (utf16_size, bytes) = m_utf8::encode(value);
+            let mut item = Vec::with_capacity(bytes.len() + 2);
+            write_uleb128(&mut item, utf16_size);
+            item.extend(bytes);
+            item.push(0);
+            item
+        });
+        let start = w.offset();
+        let mut string_data_offs = Vec::with_capacity(editor.strings.len());
+        for item in string_data {
+            string_data_offs.push(w.offset());
+            w.out.extend(item);
+        }
+        w.section(TYPE_STRING_DATA_ITEM, editor.strings.len(), start);
+
+        let type_list = |types: &[u32]| {
+            let mut item = Vec::with_capacity(4 + types.len() * 2);
+            item.extend((types.len() as u32).to_le_bytes());
+            types.iter().for_each(|it| item.extend((*it as u16).to_le_bytes()));
+            item
+        };
+        let type_lists = editor.protos.iter().map(|it| &it.parameters).chain(classes.iter().map(|it| &it.interfaces))
+            .filter(|it| !it.is_empty())
+            .map(|it| type_list(it));
+        let type_list_offs = w.unique_items(TYPE_TYPE_LIST, type_lists.collect::<Vec<_>>(), true);
+        let type_list_off = |types: &[u32]| if types.is_empty() { 0 } else { type_list_offs[&type_list(types)] };
+
+        // Annotations of the classes, their members and parameters
+        let mut annotation_lists: Vec<&[AnnotationItem]> = Vec::new();
+        for class in &classes {
+            annotation_lists.push(&class.annotations);
+            annotation_lists.extend(class.fields().map(|it| it.annotations.as_slice()));
+            for method in class.methods() {
+                annotation_lists.push(&method.annotations);
+                annotation_lists.extend(method.parameter_annotations.iter().map(Vec::as_slice));
+            }
+        }
+        let items = annotation_lists.iter().flat_map(|it| it.iter()).map(annotation_item).collect::<Vec<_>>();
+        let item_offs = w.unique_items(TYPE_ANNOTATION_ITEM, items, false);
+        let annotation_set = |annotations: &[AnnotationItem]| {
+            let mut sorted: Vec<&AnnotationItem> = annotations.iter().collect();
+            sorted.sort_by_key(|it| it.annotation.type_idx);
+            let mut item = Vec::with_capacity(4 + annotations.len() * 4);
+            item.extend((annotations.len() as u32).to_le_bytes());
+            sorted.iter().for_each(|it| item.extend(item_offs[&annotation_item(it)].to_le_bytes()));
+            item
+        };
+        let sets = annotation_lists.iter().filter(|it| !it.is_empty()).map(|it| annotation_set(it)).collect::<Vec<_>>();
+        let set_offs = w.unique_items(TYPE_ANNOTATION_SET_ITEM, sets, true);
+        let set_off = |annotations: &[AnnotationItem]| if annotations.is_empty() { 0 } else { set_offs[&annotation_set(annotations)] };
+
+        w.align();
+        let start = w.offset();
+        let mut ref_list_offs = BTreeMap::new();
+        for method in classes.iter().flat_map(|it| it.methods()).filter(|it| !it.parameter_annotations.is_empty()) {
+            w.align();
+            ref_list_offs.insert(method.method_idx, w.offset());
+            w.u32(method.parameter_annotations.len() as u32);
+            for annotations in &method.parameter_annotations {
+                w.u32(set_off(annotations));
+            }
+        }
+        w.section(TYPE_ANNOTATION_SET_REF_LIST, ref_list_offs.len(), start);
+
+        w.align();
+        let start = w.offset();
+        let mut directory_offs = vec![0; classes.len()];
+        for (i, class) in classes.iter().enumerate() {
+            let mut fields: Vec<(u32, u32)> = class.fields().filter(|it| !it.annotations.is_empty())
+                .map(|it| (it.field_idx, set_off(&it.annotations)))
+                .collect();
+            let mut methods: Vec<(u32, u32)> = class.methods().filter(|it| !it.annotations.is_empty())
+                .map(|it| (it.method_idx, set_off(&it.annotations)))
+                .collect();
+            let mut parameters: Vec<(u32, u32)> = class.methods().filter_map(|it| ref_list_offs.get(&it.method_idx).map(|off| (it.method_idx, *off))).collect();
+            if class.annotations.is_empty() && fields.is_empty() && methods.is_empty() && parameters.is_empty() {
+                continue;
+            }
+            fields.sort_unstable();
+            methods.sort_unstable();
+            parameters.sort_unstable();
+            directory_offs[i] = w.offset();
+            w.u32(set_off(&class.annotations));
+            for size in [fields.len(), methods.len(), parameters.len()] {
+                w.u32(size as u32);
+            }
+            for (idx, off) in fields.into_iter().chain(methods).chain(parameters) {
+                w.u32(idx);
+                w.u32(off);
+            }
+        }
+        w.section(TYPE_ANNOTATIONS_DIRECTORY_ITEM, directory_offs.iter().filter(|it| **it != 0).count(), start);
+
+        let start = w.offset();
+        let mut debug_info_offs = BTreeMap::new();
+        for method in classes.iter().flat_map(|it| it.methods()) {
+            if let Some(debug_info) = method.code.as_ref().and_then(|it| it.debug_info.as_ref()) {
+                debug_info_offs.insert(method.method_idx, w.offset());
+                w.out.extend(debug_info_item(debug_info));
+            }
+        }
+        w.section(TYPE_DEBUG_INFO_ITEM, debug_info_offs.len(), start);
+
+        w.align();
+        let start = w.offset();
+        let mut code_offs = BTreeMap::new();
+        for method in classes.iter().flat_map(|it| it.methods()) {
+            if let Some(code) = &method.code {
+                w.align();
+                code_offs.insert(method.method_idx, w.offset());
+                w.write_code(code, debug_info_offs.get(&method.method_idx).copied().unwrap_or(0));
+            }
+        }
+        w.section(TYPE_CODE_ITEM, code_offs.len(), start);
+
+        let start = w.offset();
+        let mut class_data_offs = vec![0; classes.len()];
+        for (i, class) in classes.iter().enumerate() {
+            if class.fields().next().is_none() && class.methods().next().is_none() {
+                continue;
+            }
+            class_data_offs[i] = w.offset();
+            let out = &mut w.out;
+            let lists = [class.static_fields.len(), class.instance_fields.len(), class.direct_methods.len(), class.virtual_methods.len()];
+            lists.iter().for_each(|it| write_uleb128(out, *it as u32));
+            for fields in [&class.static_fields, &class.instance_fields] {
+                let mut sorted: Vec<&FieldDefinition> = fields.iter().collect();
+                sorted.sort_by_key(|it| it.field_idx);
+                let mut previous = 0;
+                for field in sorted {
+                    write_uleb128(out, field.field_idx - previous);
+                    write_uleb128(out, field.access_flags);
+                    previous = field.field_idx;
+                }
+            }
+            for methods in [&class.direct_methods, &class.virtual_methods] {
+                let mut sorted: Vec<&MethodDefinition> = methods.iter().collect();
+                sorted.sort_by_key(|it| it.method_idx);
+                let mut previous = 0;
+                for method in sorted {
+                    write_uleb128(out, method.method_idx - previous);
+                    write_uleb128(out, method.access_flags);
+                    write_uleb128(out, code_offs.get(&method.method_idx).copied().unwrap_or(0));
+                    previous = method.method_idx;
+                }
+            }
+        }
+        w.section(TYPE_CLASS_DATA_ITEM, class_data_offs.iter().filter(|it| **it != 0).count(), start);
+
+        // Static values up to the last field with an initial value, in the order of the class data
+        let encoded_array = |values: &[EncodedValue]| {
+            let mut item = Vec::new();
+            write_uleb128(&mut item, values.len() as u32);
+            values.iter().for_each(|it| it.write(&mut item));
+            item
+        };
+        let static_values: Vec<Vec<EncodedValue>> = classes.iter().map(|class| {
+            let mut sorted: Vec<&FieldDefinition> = class.static_fields.iter().collect();
+            sorted.sort_by_key(|it| it.field_idx);
+            let count = sorted.iter().rposition(|it| it.static_value.is_some()).map_or(0, |it| it + 1);
+            sorted[..count].iter().map(|field| {
+                field.static_value.clone().unwrap_or_else(|| default_value(type_descriptor(editor.fields[field.field_idx as usize].type_idx)))
+            }).collect()
+        }).collect();
+        let arrays = static_values.iter().filter(|it| !it.is_empty()).chain(&editor.call_sites).map(|it| encoded_array(it));
+        let array_offs = w.unique_items(TYPE_ENCODED_ARRAY_ITEM, arrays.collect::<Vec<_>>(), false);
+
+        for section in &editor.unknown_sections {
+            w.align();
+            let start = w.offset();
+            w.map.push((section.item_type, section.size, start));
+            w.out.extend(&section.data);
+        }
+
+        w.align();
+        let map_off = w.offset();
+        let ids = [
+            (TYPE_HEADER_ITEM, 1, 0),
+            (TYPE_STRING_ID_ITEM, editor.strings.len(), string_ids_off),
+            (TYPE_TYPE_ID_ITEM, editor.types.len(), type_ids_off),
+            (TYPE_PROTO_ID_ITEM, editor.protos.len(), proto_ids_off),
+            (TYPE_FIELD_ID_ITEM, editor.fields.len(), field_ids_off),
+            (TYPE_METHOD_ID_ITEM, editor.methods.len(), method_ids_off),
+            (TYPE_CLASS_DEF_ITEM, classes.len(), class_defs_off),
+            (TYPE_CALL_SITE_ID_ITEM, editor.call_sites.len(), call_site_ids_off),
+            (TYPE_METHOD_HANDLE_ITEM, editor.method_handles.len(), method_handles_off),
+            (TYPE_MAP_LIST, 1, map_off),
+        ];
+        for (item_type, size, offset) in ids {
+            w.section(item_type, size, offset);
+        }
+        let mut map = core::mem::take(&mut w.map);
+        map.sort_by_key(|it| it.2);
+        w.u32(map.len() as u32);
+        for (item_type, size, offset) in map {
+            w.u16(item_type);
+            w.u16(0);
+            w.u32(size);
+            w.u32(offset);
+        }
+
+        let mut ids = Vec::with_capacity(data_off as usize);
+        let mut put = |value: u32| ids.extend(value.to_le_bytes());
+        string_data_offs.iter().for_each(|it| put(*it));
+        editor.types.iter().for_each(|it| put(*it));
+        for proto in &editor.protos {
+            put(proto.shorty_idx);
+            put(proto.return_type_idx);
+            put(type_list_off(&proto.parameters));
+        }
+        for field in &editor.fields {
+            put(field.class_idx | field.type_idx << 16);
+            put(field.name_idx);
+        }
+        for method in &editor.methods {
+            put(method.class_idx | method.proto_idx << 16);
+            put(method.name_idx);
+        }
+        for (i, class) in classes.iter().enumerate() {
+            put(class.class_idx);
+            put(class.access_flags);
+            put(class.superclass_idx.unwrap_or(NO_INDEX));
+            put(type_list_off(&class.interfaces));
+            put(class.source_file_idx.unwrap_or(NO_INDEX));
+            put(directory_offs[i]);
+            put(class_data_offs[i]);
+            put(if static_values[i].is_empty() { 0 } else { array_offs[&encoded_array(&static_values[i])] });
+        }
+        for values in &editor.call_sites {
+            put(array_offs[&encoded_array(values)]);
+        }
+        for handle in &editor.method_handles {
+            put(handle.kind as u32);
+            put(handle.target);
+        }
+        let mut out = w.out;
+        out[HEADER_SIZE as usize..data_off as usize].copy_from_slice(&ids);
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend(format!("dex\n{:03}\0", editor.version).as_bytes());
+        header.extend([0; 24]); // checksum and signature
+        let file_size = out.len() as u32;
+        let offset = |size: usize, offset: u32| if size == 0 { 0 } else { offset };
+        for value in [
+            file_size, HEADER_SIZE, ENDIAN_CONSTANT, 0, 0, map_off,
+            editor.strings.len() as u32, offset(editor.strings.len(), string_ids_off),
+            editor.types.len() as u32, offset(editor.types.len(), type_ids_off),
+            editor.protos.len() as u32, offset(editor.protos.len(), proto_ids_off),
+            editor.fields.len() as u32, offset(editor.fields.len(), field_ids_off),
+            editor.methods.len() as u32, offset(editor.methods.len(), method_ids_off),
+            classes.len() as u32, offset(classes.len(), class_defs_off),
+            file_size - data_off, data_off,
+        ] {
+            header.extend(value.to_le_bytes());
+        }
+        out[..HEADER_SIZE as usize].copy_from_slice(&header);
+        #[cfg(feature = "index")]
+        {
+            let signature = sha1_smol::Sha1::from(&out[DexHeader::SIGNATURE_END..]).digest().bytes();
+            out[DexHeader::CHECKSUM_END..DexHeader::SIGNATURE_END].copy_from_slice(&signature);
+        }
+        let checksum = DexHeader::compute_checksum(&out);
+        out[8..DexHeader::CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+        Ok(out)
+    }
+
+    fn write_code(&mut self, code: &Code, debug_info_off: u32) {
+        // The catch handlers of the tries, each distinct list once
+        let mut handlers: Vec<Handler> = Vec::new();
+        for try_ in &code.tries {
+            let handler = (try_.handlers.as_slice(), try_.catch_all_addr);
+            if !handlers.contains(&handler) {
+                handlers.push(handler);
+            }
+        }
+        let mut list = Vec::new();
+        let mut handler_offs = Vec::with_capacity(handlers.len());
+        write_uleb128(&mut list, handlers.len() as u32);
+        for (pairs, catch_all_addr) in &handlers {
+            handler_offs.push(list.len() as u16);
+            let size = pairs.len() as i32;
+            write_sleb128(&mut list, if catch_all_addr.is_some() { -size } else { size });
+            for (type_idx, addr) in pairs.iter() {
+                write_uleb128(&mut list, *type_idx);
+                write_uleb128(&mut list, *addr);
+            }
+            if let Some(addr) = catch_all_addr {
+                write_uleb128(&mut list, *addr);
+            }
+        }
+
+        self.u16(code.registers_size);
+        self.u16(code.ins_size);
+        self.u16(code.outs_size);
+        self.u16(code.tries.len() as u16);
+        self.u32(debug_info_off);
+        self.u32(code.insns.len() as u32);
+        code.insns.iter().for_each(|it| self.out.extend(it.to_le_bytes()));
+        if code.tries.is_empty() {
+            return;
+        }
+        if code.insns.len() % 2 == 1 {
+            self.u16(0);
+        }
+        for try_ in &code.tries {
+            let handler = (try_.handlers.as_slice(), try_.catch_all_addr);
+            self.u32(try_.start_addr);
+            self.u16(try_.insn_count);
+            self.u16(handler_offs[handlers.iter().position(|it| *it == handler).expect("Collected before")]);
+        }
+        self.out.extend(list);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::dex_file::DexFile;
+    use crate::fixture::{self, Fixture, FixtureMethod};
+    use crate::instructions::Instruction;
+
+    #[test]
+    fn inserted_class() {
+        let const_string = |fixture: Fixture, value: &str| {
+            let get = |insns| FixtureMethod::new("get", "Ljava/lang/String;", &[], insns).registers_size(1);
+            let mut code = CodeBuilder::new();
+            let string_idx = fixture.clone().method(get(Vec::new())).string_idx(value).unwrap();
+            code.emit("const-string", &[Reg(0), Idx(string_idx)]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap();
+            fixture.method(get(code.build().unwrap()))
+        };
+        let target = const_string(Fixture::new().string("hello"), "hello");
+        let source = const_string(Fixture::empty().class("Lcom/example/Added;").string("a"), "a").editor();
+
+        let mut editor = target.editor();
+        let remap = editor.insert_class(&source, "Lcom/example/Added;").unwrap();
+        let hello = target.string_idx("hello").unwrap();
+        assert_eq!(remap.strings.get(hello), hello + 2);
+        let dex = fixture::rewritten(&editor);
+        let strings: Vec<&str> = dex.classes().map(|class| {
+            let code = dex.code_item(class.methods()[0].encoded.code_off).unwrap();
+            dex.string(Instruction::decode(&code.insns, 0).unwrap().b)
+        }).collect();
+        assert_eq!(strings, ["hello", "a"]);
+    }
+
+    #[test]
+    fn inserted_duplicate_class() {
+        let source = Fixture::new().class("Lcom/example/Added;").editor();
+        let mut editor = Fixture::new().editor();
+        let before = editor.to_bytes().unwrap();
+        assert!(matches!(editor.insert_class(&editor.clone(), "Lcom/example/Fixture;"), Err(EditError::ClassExists(_))));
+        assert!(matches!(editor.insert_classes(&source, &["Lcom/example/Added;", "Lcom/example/Added;"]), Err(EditError::ClassExists(_))));
+        assert!(matches!(editor.insert_class(&source, "Lcom/example/Missing;"), Err(EditError::ClassNotFound(_))));
+        assert_eq!(editor.to_bytes().unwrap(), before);
+    }
+
+    #[test]
+    fn inserted_class_without_superclass() {
+        // The superclass stays a reference to a class outside the dex file
+        let source = Fixture::new().class("Lcom/example/Added;").superclass("Lcom/example/Base;").editor();
+        let mut editor = Fixture::new().editor();
+        editor.insert_class(&source, "Lcom/example/Added;").unwrap();
+        let dex = fixture::rewritten(&editor);
+        let added = dex.class_def("Lcom/example/Added;").unwrap();
+        assert_eq!(dex.type_descriptor(added.superclass_idx), "Lcom/example/Base;");
+        assert!(dex.class_def("Lcom/example/Base;").is_none());
+
+        // Written after its superclass once inserted
+        let base = Fixture::new().class("Lcom/example/Base;").editor();
+        editor.insert_class(&base, "Lcom/example/Base;").unwrap();
+        let dex = fixture::rewritten(&editor);
+        let classes: Vec<&str> = dex.classes().map(|it| it.descriptor()).collect();
+        assert_eq!(classes, ["Lcom/example/Fixture;", "Lcom/example/Base;", "Lcom/example/Added;"]);
+    }
+
+    fn method(access_flags: u32, insns: Vec<u16>, debug_info: Option<DebugInfoItem>) -> MethodDefinition {
+        MethodDefinition {
+            method_idx: 0,
+            access_flags,
+            code: Some(Code { registers_size: 1, ins_size: 0, outs_size: 0, insns, tries: Vec::new(), debug_info }),
+            annotations: Vec::new(),
+            parameter_annotations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn set_method() {
+        let fixture = Fixture::new().string("hello");
+        let mut editor = fixture.editor();
+        let mut code = CodeBuilder::new();
+        code.emit("const-string", &[Reg(0), Idx(fixture.string_idx("hello").unwrap())]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        let get = method(ACC_STATIC, code.build().unwrap(), None);
+        editor.set_method("Lcom/example/Fixture;", "get", "()Ljava/lang/String;", get).unwrap();
+        editor.set_method("Lcom/example/Fixture;", "run", "()V", method(0, vec![0x0000, 0x000e], None)).unwrap();
+        assert!(matches!(editor.set_method("Lcom/example/Fixture;", "run", "(", method(0, Vec::new(), None)), Err(EditError::InvalidSignature(_))));
+
+        let dex = fixture::rewritten(&editor);
+        let methods: Vec<(&str, Vec<u16>)> = dex.classes().next().unwrap().methods().iter()
+            .map(|it| (dex.method_name(it.method_idx), dex.code_item(it.encoded.code_off).unwrap().insns.clone()))
+            .collect();
+        let hello = dex.string_idx("hello").unwrap() as u16;
+        assert_eq!(methods, [("get", vec![0x001a, hello, 0x0011]), ("run", vec![0x0000, 0x000e])]);
+    }
+
+    #[test]
+    fn replaced_method() {
+        let debug_info = |line_start| Some(DebugInfoItem { line_start, parameter_names: Vec::new(), bytecode: vec![DebugInstruction::SetPrologueEnd] });
+        let mut editor = Fixture::new().editor();
+        editor.set_method("Lcom/example/Fixture;", "run", "()V", method(0, vec![0x000e], debug_info(3))).unwrap();
+        let run = |dex: &DexFile| {
+            let methods = dex.classes().next().unwrap().methods();
+            assert_eq!(methods.len(), 1);
+            let code = dex.code_item(methods[0].encoded.code_off).unwrap();
+            (code.insns.clone(), dex.debug_info.get(&code.debug_info_off).map(|it| it.line_start))
+        };
+        assert_eq!(run(&fixture::rewritten(&editor)), (vec![0x000e], Some(3)));
+
+        // Code and debug info are replaced, not merged
+        editor.set_method("Lcom/example/Fixture;", "run", "()V", method(0, vec![0x0000, 0x000e], debug_info(7))).unwrap();
+        assert_eq!(run(&fixture::rewritten(&editor)), (vec![0x0000, 0x000e], Some(7)));
+        editor.set_method("Lcom/example/Fixture;", "run", "()V", method(0, vec![0x000e], None)).unwrap();
+        let dex = fixture::rewritten(&editor);
+        assert_eq!(run(&dex), (vec![0x000e], None));
+        assert!(dex.debug_info.is_empty());
+    }
+
+    #[test]
+    fn added_ids() {
+        let fixture = Fixture::empty().string("hello");
+        let mut code = CodeBuilder::new();
+        let get = |insns| FixtureMethod::new("get", "Ljava/lang/String;", &[], insns).registers_size(1);
+        code.emit("const-string", &[Reg(0), Idx(fixture.clone().method(get(Vec::new())).string_idx("hello").unwrap())]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        let mut editor = fixture.method(get(code.build().unwrap())).editor();
+        let hello = editor.string_idx("hello").unwrap();
+
+        let (idx, remap) = editor.add_string("hello").unwrap();
+        assert_eq!((idx, remap.is_identity()), (hello, true));
+        let (a, remap) = editor.add_string("a").unwrap();
+        assert_eq!((editor.string(a), remap.get(IdKind::String, hello)), ("a", hello + 1));
+        let (method_idx, _) = editor.add_method_id("Ljava/lang/Object;", "toString", "()Ljava/lang/String;").unwrap();
+        assert!(matches!(editor.add_type("Q"), Err(EditError::InvalidDescriptor(_))));
+
+        let dex = fixture::rewritten(&editor);
+        assert_eq!(dex.method_idx("Ljava/lang/Object;", "toString", "()Ljava/lang/String;"), Some(method_idx));
+        assert_eq!(dex.string(Instruction::decode(&fixture::insns(&dex, "get"), 0).unwrap().b), "hello");
+    }
+
+    #[test]
+    fn failed_edit_keeps_editor() {
+        // const-string of the string 0xffff, which no longer fits once a string is inserted before it
+        let method = |insns| FixtureMethod::new("get", "Ljava/lang/String;", &[], insns).registers_size(1);
+        let mut fixture = Fixture::empty().string("~").method(method(Vec::new()));
+        let extra = 0x10000 - fixture.string_idx("~").unwrap();
+        fixture.strings = (0..extra).map(|it| format!("~{:05}", it)).collect();
+        let last = fixture.string_idx(&format!("~{:05}", extra - 1)).unwrap();
+        assert_eq!(last, 0xffff);
+        let mut code = CodeBuilder::new();
+        code.emit("const-string", &[Reg(0), Idx(last)]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        fixture.methods = vec![method(code.build().unwrap())];
+        let mut editor = DexEditor::from_bytes(&fixture.build()).unwrap();
+
+        let before = editor.clone().to_bytes().unwrap();
+        assert!(matches!(editor.add_string("a"), Err(EditError::Encode { .. })));
+        assert_eq!(editor.to_bytes().unwrap(), before);
+        // Appended after it
+        assert!(editor.add_string("~~").is_ok());
+    }
+
+    #[test]
+    fn stripped_method() {
+        let mut call = CodeBuilder::new();
+        call.emit("invoke-static", &[Regs(Vec::new()), Idx(0)]).unwrap().emit("return-void", &[]).unwrap();
+        let fixture = Fixture::empty()
+            .method(FixtureMethod::new("callee", "V", &[], vec![0x000e]).access_flags(ACC_STATIC))
+            .method(FixtureMethod::new("caller", "V", &[], call.build().unwrap()).access_flags(ACC_STATIC));
+        let callee = ["Lcom/example/Fixture;->callee()V"];
+        assert_eq!(fixture.editor().strip(&callee, Dangling::Cascade).unwrap().items.len(), 2);
+
+        let mut editor = fixture.editor();
+        let stripped = editor.strip(&callee, Dangling::Stub).unwrap();
+        assert_eq!(stripped.items, [(callee[0].to_owned(), StripAction::Removed), ("Lcom/example/Fixture;->caller()V".to_owned(), StripAction::Stubbed)]);
+        let dex = fixture::rewritten(&editor);
+        assert_eq!(dex.method_ids.len(), 1);
+        assert!(dex.string_idx("callee").is_none());
+        assert_eq!(fixture::insns(&dex, "caller"), vec![0x0012, 0x0027]);
+    }
+
+    #[test]
+    fn dangling_reference() {
+        let mut call = CodeBuilder::new();
+        call.emit("invoke-static", &[Regs(Vec::new()), Idx(0)]).unwrap().emit("return-void", &[]).unwrap();
+        let mut editor = Fixture::empty()
+            .method(FixtureMethod::new("callee", "V", &[], vec![0x000e]).access_flags(ACC_STATIC))
+            .method(FixtureMethod::new("caller", "V", &[], call.build().unwrap()).access_flags(ACC_STATIC))
+            .editor();
+        let before = editor.to_bytes().unwrap();
+        match editor.strip(&["Lcom/example/Fixture;->callee()V"], Dangling::Fail) {
+            Err(EditError::Dangling(removed, referrer)) => assert_eq!((removed.as_str(), referrer.as_str()), ("Lcom/example/Fixture;->callee()V", "Lcom/example/Fixture;->caller()V")),
+            other => panic!("{:?}", other.map(|it| it.items)),
+        }
+        assert_eq!(editor.to_bytes().unwrap(), before);
+        // Removing the referrer with it leaves nothing dangling
+        let both = ["Lcom/example/Fixture;->callee()V", "Lcom/example/Fixture;->caller()V"];
+        assert_eq!(editor.strip(&both, Dangling::Fail).unwrap().items.len(), 2);
+        assert!(fixture::rewritten(&editor).method_ids.is_empty());
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let mut code = CodeBuilder::new();
+        code.emit("const-string", &[Reg(0), Idx(999)]).unwrap()
+            .emit("return-void", &[]).unwrap();
+        let bad = Fixture::empty()
+            .method(FixtureMethod::new("run", "V", &[], code.build().unwrap()).registers_size(1))
+            .method(FixtureMethod::new("other", "V", &[], vec![0x000e]));
+        let mut editor = DexEditor::from_bytes(&bad.build()).unwrap();
+        assert!(matches!(editor.strip(&["Lcom/example/Fixture;->other()V"], Dangling::Fail), Err(EditError::IndexOutOfBounds(IdKind::String, 999))));
+        assert!(matches!(editor.compact(), Err(EditError::IndexOutOfBounds(IdKind::String, 999))));
+
+        let mut other = DexEditor::from_bytes(&Fixture::new().class("Lcom/example/Other;").build()).unwrap();
+        assert!(matches!(other.insert_class(&editor, "Lcom/example/Fixture;"), Err(EditError::IndexOutOfBounds(IdKind::String, 999))));
+        assert_eq!(other.classes.len(), 1);
+    }
+
+    #[test]
+    fn stub_without_register() {
+        let fixture = Fixture::empty().method(FixtureMethod::new("other", "V", &[], vec![0x000e]));
+        let other = fixture.clone().method(FixtureMethod::new("run", "V", &[], Vec::new())).build();
+        let other_idx = DexFile::from_bytes(&other).unwrap().method_idx("Lcom/example/Fixture;", "other", "()V").unwrap();
+        let mut code = CodeBuilder::new();
+        code.emit("invoke-virtual", &[Regs(vec![0]), Idx(other_idx)]).unwrap()
+            .emit("return-void", &[]).unwrap();
+        let data = fixture.method(FixtureMethod::new("run", "V", &[], code.build().unwrap())).build();
+        let mut editor = DexEditor::from_bytes(&data).unwrap();
+        let run = editor.method_idx("Lcom/example/Fixture;", "run", "()V").unwrap();
+        let method = editor.classes[0].virtual_methods.iter_mut().find(|it| it.method_idx == run).unwrap();
+        method.code.as_mut().unwrap().ins_size = u16::MAX;
+
+        let before = editor.to_bytes().unwrap();
+        assert!(matches!(editor.strip(&["Lcom/example/Fixture;->other()V"], Dangling::Stub), Err(EditError::Unsupported(_))));
+        assert_eq!(editor.to_bytes().unwrap(), before);
+    }
+
+    #[test]
+    fn unknown_section() {
+        let mut editor = Fixture::new().editor();
+        let data = vec![0xde, 0xad, 0xbe, 0xef, 1, 2, 3, 4];
+        editor.unknown_sections.push(UnknownSection { item_type: 0xf0f0, size: 2, offset: 0, data: data.clone() });
+        let written = editor.to_bytes().unwrap();
+        let dex = DexFile::from_bytes(&written).unwrap();
+        let section = &dex.unknown_sections[..];
+        assert!(matches!(section, [UnknownSection { item_type: 0xf0f0, size: 2, .. }]));
+        assert_eq!(section[0].data, data);
+
+        let editor = DexEditor::from_bytes(&written).unwrap();
+        assert_eq!(editor.unknown_sections.len(), 1);
+        assert_eq!(editor.to_bytes().unwrap(), written);
+    }
+}
@@ -0,0 +1,430 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(feature = "apk")]
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "apk")]
+use crate::apk;
+use crate::atomic;
+use crate::dex_file::DexFile;
+use crate::entropy::{self, RangeEntropy};
+use crate::raw_dex::DexHeader;
+use crate::tamper::{self, TamperReport};
+
+/*
+Dex files and zip archives embedded in a dex file, as droppers and packers hide their payload: in a
+string or an array of the data section, in bytes not covered by the map list or appended after the
+end declared by the header. Candidates are found by their magic, `dex\nVVV\0` followed by the endian
+tag of a little endian dex file, or the local file header `PK\x03\x04` of a zip archive. The size of an
+embedded dex file is its declared file size, the size of an archive extends to the end of its central
+directory, both limited to the end of the file. Payloads inside another payload are not listed, compressed
+payloads are only found through the archive holding them.
+
+analyze_nested runs the analyses of droppers on the payloads and on their payloads in turn: embedded dex
+files are parsed, checked for tampering and high entropy regions and scanned for payloads, archives are
+opened (with the apk feature) and their entries that are dex files or archives analyzed the same way.
+ */
+
+const ENDIAN_TAG: [u8; 4] = [0x78, 0x56, 0x34, 0x12];
+const LOCAL_FILE_HEADER: [u8; 4] = *b"PK\x03\x04";
+const END_OF_CENTRAL_DIRECTORY: [u8; 4] = *b"PK\x05\x06";
+/// Size of the end of central directory record without the comment
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+/// Deepest nesting analyzed by analyze_nested, against archives containing themselves
+pub const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PayloadKind {
+    Dex,
+    Zip,
+}
+
+impl PayloadKind {
+    /// Extension of the carved files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PayloadKind::Dex => "dex",
+            PayloadKind::Zip => "zip",
+        }
+    }
+}
+
+impl fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Where a payload starts in the dex file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// In an item of the data section, e.g. a string or an encoded array
+    Data,
+    /// In bytes of the data section not covered by the map list, see tamper::coverage_gaps
+    Slack,
+    /// After the end of the file declared by the header
+    Trailing,
+    /// In the header or the id tables
+    Other,
+    /// An entry of an archive, only for nested payloads
+    Entry,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Location::Data => "data",
+            Location::Slack => "slack",
+            Location::Trailing => "trailing",
+            Location::Other => "other",
+            Location::Entry => "entry",
+        })
+    }
+}
+
+/// A candidate payload embedded in a dex file
+#[derive(Debug, Clone)]
+pub struct Embedded {
+    pub kind: PayloadKind,
+    pub location: Location,
+    pub range: Range<usize>,
+    /// Version and declared size of a dex file, number of entries of an archive
+    pub detail: String,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// A payload found by analyze_nested and the results of the analyses of it
+#[derive(Debug, Clone)]
+pub struct NestedPayload {
+    /// Offsets of the payloads and names of the archive entries leading to the payload, joined by `/`
+    /// (e.g. `0x000009d4/assets/payload.dex/0x00001230`)
+    pub path: String,
+    /// 1 for the payloads of the analyzed dex file
+    pub depth: usize,
+    pub kind: PayloadKind,
+    pub location: Location,
+    pub data: Vec<u8>,
+    pub detail: String,
+    /// Tamper report of a dex file, None for archives and dex files that do not parse
+    pub tamper: Option<TamperReport>,
+    /// High entropy regions of a dex file
+    pub high_entropy: Vec<RangeEntropy>,
+}
+
+/// End and detail of the dex file starting at `start`, None if only the magic matches
+fn embedded_dex(data: &[u8], start: usize) -> Option<(usize, String)> {
+    let version = DexHeader::parse_version(data.get(start..start + 8)?.try_into().ok()?)?;
+    if data.get(start + 40..start + 44)? != ENDIAN_TAG {
+        return None;
+    }
+    let file_size = u32_at(data, start + 32)? as usize;
+    let end = start.saturating_add(file_size).min(data.len());
+    let mut detail = format!("version {:03}, file_size {}", version, file_size);
+    if end - start < file_size {
+        detail.push_str(&format!(", truncated to {} bytes", end - start));
+    }
+    Some((end, detail))
+}
+
+/// End and detail of the archive starting at `start`, at the end of the first end of central
+/// directory record after it (or the end of the file)
+fn embedded_zip(data: &[u8], start: usize) -> (usize, String) {
+    let record = data[start..].windows(4).position(|it| it == END_OF_CENTRAL_DIRECTORY).map(|it| start + it);
+    let eocd = match record {
+        Some(eocd) if eocd + END_OF_CENTRAL_DIRECTORY_SIZE <= data.len() => eocd,
+        _ => return (data.len(), "no end of central directory".to_owned()),
+    };
+    let comment_size = u16_at(data, eocd + 20).unwrap_or_default() as usize;
+    let entries = u16_at(data, eocd + 10).unwrap_or_default();
+    ((eocd + END_OF_CENTRAL_DIRECTORY_SIZE + comment_size).min(data.len()), format!("{} entries", entries))
+}
+
+fn location(dex: &DexFile, gaps: &[Range<usize>], offset: usize) -> Location {
+    let header = &dex.header;
+    let data_section = header.data_off as usize..header.data_off as usize + header.data_size as usize;
+    if offset >= header.file_size as usize {
+        Location::Trailing
+    } else if gaps.iter().any(|it| it.contains(&offset)) {
+        Location::Slack
+    } else if data_section.contains(&offset) {
+        Location::Data
+    } else {
+        Location::Other
+    }
+}
+
+/// Candidate dex files and zip archives embedded in the dex file `data`, in file order. The dex
+/// file itself at offset 0 is not listed.
+pub fn find_embedded(dex: &DexFile, data: &[u8]) -> Vec<Embedded> {
+    let gaps = tamper::coverage_gaps(dex, data);
+    let mut payloads = Vec::new();
+    let mut offset = 1;
+    while offset + 4 <= data.len() {
+        let found = match &data[offset..offset + 4] {
+            b"dex\n" => embedded_dex(data, offset).map(|(end, detail)| (PayloadKind::Dex, end, detail)),
+            magic if magic == LOCAL_FILE_HEADER => {
+                let (end, detail) = embedded_zip(data, offset);
+                Some((PayloadKind::Zip, end, detail))
+            }
+            _ => None,
+        };
+        match found {
+            Some((kind, end, detail)) => {
+                payloads.push(Embedded { kind, location: location(dex, &gaps, offset), range: offset..end, detail });
+                offset = end.max(offset + 1);
+            }
+            None => offset += 1,
+        }
+    }
+    payloads
+}
+
+/// The payloads embedded in the dex file `data` and, up to MAX_DEPTH, the payloads of these payloads,
+/// each followed by its own payloads
+pub fn analyze_nested(dex: &DexFile, data: &[u8]) -> Vec<NestedPayload> {
+    let mut nested = Vec::new();
+    analyze_dex(dex, data, "", 1, &mut nested);
+    nested
+}
+
+fn analyze_dex(dex: &DexFile, data: &[u8], parent: &str, depth: usize, nested: &mut Vec<NestedPayload>) {
+    for payload in find_embedded(dex, data) {
+        let path = format!("{}0x{:08x}", parent, payload.range.start);
+        let bytes = data[payload.range].to_vec();
+        analyze_payload(path, depth, payload.kind, payload.location, bytes, payload.detail, nested);
+    }
+}
+
+fn analyze_payload(path: String, depth: usize, kind: PayloadKind, location: Location, data: Vec<u8>,
+                   mut detail: String, nested: &mut Vec<NestedPayload>) {
+    let mut children = Vec::new();
+    let mut tamper = None;
+    let mut high_entropy = Vec::new();
+    match kind {
+        PayloadKind::Dex => match DexFile::from_bytes(&data) {
+            Ok(dex) => {
+                tamper = Some(tamper::tamper_report(&dex, &data));
+                high_entropy = entropy::high_entropy_regions(&dex, &data);
+                if depth < MAX_DEPTH {
+                    analyze_dex(&dex, &data, &format!("{}/", path), depth + 1, &mut children);
+                }
+            }
+            Err(err) => detail.push_str(&format!(", does not parse: {}", err)),
+        },
+        #[cfg(feature = "apk")]
+        PayloadKind::Zip if depth < MAX_DEPTH => match apk::payload_entries(Cursor::new(&data)) {
+            Ok(entries) => {
+                for (name, entry) in entries {
+                    let (kind, entry_detail) = match embedded_dex(&entry, 0) {
+                        Some((_, entry_detail)) => (PayloadKind::Dex, entry_detail),
+                        None if apk::is_zip(&entry) => (PayloadKind::Zip, embedded_zip(&entry, 0).1),
+                        None => (PayloadKind::Dex, "invalid header".to_owned()),
+                    };
+                    analyze_payload(format!("{}/{}", path, name), depth + 1, kind, Location::Entry, entry, entry_detail, &mut children);
+                }
+            }
+            Err(err) => detail.push_str(&format!(", does not open: {}", err)),
+        },
+        PayloadKind::Zip => {}
+    }
+    nested.push(NestedPayload { path, depth, kind, location, data, detail, tamper, high_entropy });
+    nested.append(&mut children);
+}
+
+/// Writes each payload to `dir` as `<offset>.dex` or `<offset>.zip`, returns the paths in the order
+/// of the payloads
+pub fn carve(payloads: &[Embedded], data: &[u8], dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let path = dir.join(format!("0x{:08x}.{}", payload.range.start, payload.kind.extension()));
+        atomic::write(&path, &data[payload.range.clone()])?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Writes each nested payload to `dir`, named by its path with `_` instead of `/` and the extension of
+/// its kind, returns the paths in the order of the payloads
+pub fn carve_nested(payloads: &[NestedPayload], dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let mut name = payload.path.replace('/', "_");
+        let extension = format!(".{}", payload.kind.extension());
+        if !name.ends_with(&extension) {
+            name.push_str(&extension);
+        }
+        let path = dir.join(name);
+        atomic::write(&path, &payload.data)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// An empty archive, the local file header magic directly followed by the end of central directory
+    fn empty_zip() -> Vec<u8> {
+        let mut zip = LOCAL_FILE_HEADER.to_vec();
+        zip.extend(END_OF_CENTRAL_DIRECTORY);
+        zip.extend([0; END_OF_CENTRAL_DIRECTORY_SIZE - 4]);
+        zip
+    }
+
+    fn found(payloads: &[Embedded]) -> Vec<(PayloadKind, Location, Range<usize>, &str)> {
+        payloads.iter().map(|it| (it.kind, it.location, it.range.clone(), it.detail.as_str())).collect()
+    }
+
+    #[test]
+    fn trailing_and_slack() {
+        let host = Fixture::new();
+        let dex = host.parse();
+        let mut data = host.build();
+        let payload = Fixture::empty().class("Lcom/example/Payload;").build();
+        let dex_start = data.len();
+        let zip_start = dex_start + payload.len();
+        data.extend(&payload);
+        data.extend(empty_zip());
+        // Only the magic of a dex file, without the endian tag
+        data.extend(b"dex\n035\0");
+        let dex_detail = format!("version 035, file_size {}", payload.len());
+        assert_eq!(found(&find_embedded(&dex, &data)), [
+            (PayloadKind::Dex, Location::Trailing, dex_start..zip_start, dex_detail.as_str()),
+            (PayloadKind::Zip, Location::Trailing, zip_start..zip_start + 26, "0 entries"),
+        ]);
+
+        // Within the declared file size, but not covered by the map list
+        let mut data = data[..zip_start].to_vec();
+        let file_size = data.len() as u32;
+        data[32..36].copy_from_slice(&file_size.to_le_bytes());
+        let dex = DexFile::from_bytes(&data).unwrap();
+        assert_eq!(found(&find_embedded(&dex, &data)), [(PayloadKind::Dex, Location::Slack, dex_start..zip_start, dex_detail.as_str())]);
+    }
+
+    #[test]
+    fn truncated() {
+        let host = Fixture::new();
+        let mut data = host.build();
+        let start = data.len();
+        let payload = Fixture::empty().build();
+        data.extend(&payload[..0x70]);
+        data.extend(LOCAL_FILE_HEADER);
+        let detail = format!("version 035, file_size {}, truncated to {} bytes", payload.len(), 0x74);
+        assert_eq!(found(&find_embedded(&host.parse(), &data)), [(PayloadKind::Dex, Location::Trailing, start..start + 0x74, detail.as_str())]);
+
+        let mut data = host.build();
+        data.extend(LOCAL_FILE_HEADER);
+        assert_eq!(found(&find_embedded(&host.parse(), &data))[0].3, "no end of central directory");
+    }
+
+    #[test]
+    fn carved() {
+        let host = Fixture::new();
+        let mut data = host.build();
+        let start = data.len();
+        data.extend(empty_zip());
+        let payloads = find_embedded(&host.parse(), &data);
+        let dir = std::env::temp_dir().join(format!("dex_tool-carve-{}", std::process::id()));
+        let paths = carve(&payloads, &data, &dir).unwrap();
+        assert_eq!(paths, [dir.join(format!("0x{:08x}.zip", start))]);
+        assert_eq!(fs::read(&paths[0]).unwrap(), empty_zip());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `outer` followed by `inner`, with the declared file size and checksum covering both
+    fn enclosing(outer: &Fixture, inner: &[u8]) -> Vec<u8> {
+        let mut data = outer.build();
+        data.extend(inner);
+        let file_size = data.len() as u32;
+        data[32..36].copy_from_slice(&file_size.to_le_bytes());
+        let checksum = DexHeader::compute_checksum(&data);
+        data[8..DexHeader::CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    fn analyzed(nested: &[NestedPayload]) -> Vec<(&str, usize, PayloadKind, Location, Option<u32>)> {
+        nested.iter().map(|it| (it.path.as_str(), it.depth, it.kind, it.location, it.tamper.as_ref().map(|it| it.score()))).collect()
+    }
+
+    #[test]
+    fn nested_dex() {
+        let inner = Fixture::empty().class("Lcom/example/Inner;").build();
+        let middle_fixture = Fixture::empty().class("Lcom/example/Middle;");
+        let middle = enclosing(&middle_fixture, &inner);
+        let host = Fixture::new();
+        let mut data = host.build();
+        data.extend(&middle);
+
+        let nested = analyze_nested(&host.parse(), &data);
+        let outer_path = format!("0x{:08x}", host.build().len());
+        let inner_path = format!("{}/0x{:08x}", outer_path, middle_fixture.build().len());
+        // The middle dex file has a valid checksum, but bytes not covered by its map list
+        let middle_score = if cfg!(feature = "index") { 40 } else { 20 };
+        assert_eq!(analyzed(&nested), [
+            (outer_path.as_str(), 1, PayloadKind::Dex, Location::Trailing, Some(middle_score)),
+            (inner_path.as_str(), 2, PayloadKind::Dex, Location::Slack, Some(0)),
+        ]);
+        assert_eq!(nested[0].data, middle);
+        assert_eq!(nested[1].data, inner);
+
+        let dir = std::env::temp_dir().join(format!("dex_tool-carve-nested-{}", std::process::id()));
+        let paths = carve_nested(&nested, &dir).unwrap();
+        assert_eq!(paths, [dir.join(format!("{}.dex", outer_path)), dir.join(format!("{}.dex", inner_path.replace('/', "_")))]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nested_depth() {
+        // Each dex file holds the next one, only MAX_DEPTH levels are analyzed
+        let mut data = Fixture::empty().build();
+        for _ in 0..MAX_DEPTH + 2 {
+            data = enclosing(&Fixture::empty(), &data);
+        }
+        let nested = analyze_nested(&DexFile::from_bytes(&data).unwrap(), &data);
+        assert_eq!(nested.iter().map(|it| it.depth).collect::<Vec<_>>(), (1..=MAX_DEPTH).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "apk")]
+    #[test]
+    fn nested_archive() {
+        use std::io::Write;
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let inner = Fixture::empty().class("Lcom/example/Inner;").build();
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("assets/readme.txt", options).unwrap();
+        writer.write_all(b"not a payload").unwrap();
+        writer.start_file("assets/payload.dex", options).unwrap();
+        writer.write_all(&inner).unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let host = Fixture::new();
+        let mut data = host.build();
+        data.extend(&archive);
+        let nested = analyze_nested(&host.parse(), &data);
+        let archive_path = format!("0x{:08x}", host.build().len());
+        let entry_path = format!("{}/assets/payload.dex", archive_path);
+        assert_eq!(analyzed(&nested), [
+            (archive_path.as_str(), 1, PayloadKind::Zip, Location::Trailing, None),
+            (entry_path.as_str(), 2, PayloadKind::Dex, Location::Entry, Some(0)),
+        ]);
+        assert_eq!(nested[0].detail, "2 entries");
+        assert_eq!(nested[1].data, inner);
+    }
+}
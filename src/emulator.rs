@@ -0,0 +1,683 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::cfg::ControlFlowGraph;
+use crate::dex_file::{self, DexFile};
+use crate::instructions::{Format, Instruction, Payload};
+use crate::raw_dex::CodeItem;
+
+/*
+Light emulation of string decryption helpers, i.e. static methods turning constant arguments into a string.
+Only integers, longs, arrays, strings and StringBuilders are modelled. Anything else (fields, floats,
+unknown framework methods, ...) aborts the emulation.
+ */
+
+/// Maximum number of executed instructions per emulated call (including nested calls)
+const MAX_STEPS: usize = 100_000;
+/// Maximum depth of nested calls to methods of the dex file
+const MAX_DEPTH: usize = 8;
+/// Maximum length of arrays, strings and StringBuilders, so a huge new-array or a string doubled in a
+/// loop fails the emulation instead of exhausting the memory
+const MAX_LENGTH: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i32),
+    Long(i64),
+    Null,
+    /// Strings as UTF-16 code units, like in Java
+    Str(Rc<Vec<u16>>),
+    Array(Rc<RefCell<Array>>),
+    Builder(Rc<RefCell<Vec<u16>>>),
+    /// Result of new-instance of the given type, before its constructor was invoked
+    Uninitialized(u32),
+    /// Contents of a register that was not written yet (or the second half of a wide value)
+    Undefined,
+}
+
+#[derive(Debug, Clone)]
+pub struct Array {
+    /// Descriptor of the element type
+    pub element: String,
+    pub values: Vec<Value>,
+}
+
+impl Value {
+    pub fn string(s: &str) -> Value {
+        Value::Str(Rc::new(s.encode_utf16().collect()))
+    }
+
+    fn array(element: &str, values: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(Array { element: element.to_string(), values })))
+    }
+
+    fn as_int(&self) -> Result<i32, EmulationError> {
+        match self {
+            Value::Int(value) => Ok(*value),
+            value => Err(EmulationError::TypeMismatch(format!("Expected int, got {:?}", value))),
+        }
+    }
+
+    fn as_long(&self) -> Result<i64, EmulationError> {
+        match self {
+            Value::Long(value) => Ok(*value),
+            value => Err(EmulationError::TypeMismatch(format!("Expected long, got {:?}", value))),
+        }
+    }
+
+    fn as_str(&self) -> Result<Rc<Vec<u16>>, EmulationError> {
+        match self {
+            Value::Str(value) => Ok(value.clone()),
+            value => Err(EmulationError::TypeMismatch(format!("Expected string, got {:?}", value))),
+        }
+    }
+
+    fn as_array(&self) -> Result<Rc<RefCell<Array>>, EmulationError> {
+        match self {
+            Value::Array(value) => Ok(value.clone()),
+            value => Err(EmulationError::TypeMismatch(format!("Expected array, got {:?}", value))),
+        }
+    }
+
+    /// Whether two references are the same object, None if either is no reference. Null is also the int
+    /// 0, as const/4 loads both.
+    fn same_reference(&self, other: &Value) -> Option<bool> {
+        let is_null = |value: &Value| matches!(value, Value::Null | Value::Int(0));
+        let is_object = |value: &Value| matches!(value, Value::Str(_) | Value::Array(_) | Value::Builder(_));
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => Some(Rc::ptr_eq(a, b)),
+            (Value::Array(a), Value::Array(b)) => Some(Rc::ptr_eq(a, b)),
+            (Value::Builder(a), Value::Builder(b)) => Some(Rc::ptr_eq(a, b)),
+            (a, b) if is_null(a) && is_null(b) => Some(true),
+            (a, b) if (is_object(a) || is_null(a)) && (is_object(b) || is_null(b)) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Fails if an array, string or StringBuilder would get longer than MAX_LENGTH
+fn check_length(len: usize) -> Result<(), EmulationError> {
+    if len > MAX_LENGTH { Err(EmulationError::LengthLimit) } else { Ok(()) }
+}
+
+#[derive(Debug)]
+pub enum EmulationError {
+    /// Instruction or invoked method that is not modelled
+    Unsupported(String),
+    TypeMismatch(String),
+    InvalidCode(String),
+    StepLimit,
+    DepthLimit,
+    LengthLimit,
+    ArithmeticException,
+    IndexOutOfBounds,
+}
+
+impl std::error::Error for EmulationError {}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulationError::Unsupported(what) => write!(f, "Unsupported {}", what),
+            EmulationError::TypeMismatch(msg) => write!(f, "Type mismatch: {}", msg),
+            EmulationError::InvalidCode(msg) => write!(f, "Invalid code: {}", msg),
+            EmulationError::StepLimit => write!(f, "Step limit of {} instructions exceeded", MAX_STEPS),
+            EmulationError::DepthLimit => write!(f, "Call depth limit of {} exceeded", MAX_DEPTH),
+            EmulationError::LengthLimit => write!(f, "Length limit of {} elements exceeded", MAX_LENGTH),
+            EmulationError::ArithmeticException => write!(f, "Division by zero"),
+            EmulationError::IndexOutOfBounds => write!(f, "Array index out of bounds"),
+        }
+    }
+}
+
+/// A string recovered by emulating the call of a decryptor method with constant arguments
+#[derive(Debug, Clone)]
+pub struct DecryptedString {
+    /// Method containing the call
+    pub caller: u32,
+    /// pc of the invoke instruction
+    pub pc: usize,
+    /// Invoked decryptor method
+    pub decryptor: u32,
+    pub value: String,
+}
+
+pub struct Emulator<'a> {
+    dex: &'a DexFile,
+    /// Code of the static methods defined in the dex file
+    static_methods: HashMap<u32, &'a CodeItem>,
+    /// Values of const-string by string index, interned like by the runtime
+    strings: HashMap<u32, Value>,
+    steps: usize,
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(dex: &'a DexFile) -> Emulator<'a> {
+        let mut static_methods = HashMap::new();
+        for class_data in dex.class_data.iter().flatten() {
+            let methods = &class_data.direct_methods;
+            for (method, method_idx) in methods.iter().zip(dex_file::method_indices(methods)) {
+                if method.access_flags & dex_file::ACC_STATIC != 0 {
+                    if let Some(code) = dex.code_item(method.code_off) {
+                        static_methods.insert(method_idx, code);
+                    }
+                }
+            }
+        }
+        Emulator { dex, static_methods, strings: HashMap::new(), steps: 0 }
+    }
+
+    /// Whether the method is a static method of the dex file returning a string
+    fn is_decryptor(&self, method_idx: u32) -> bool {
+        self.static_methods.contains_key(&method_idx) && self.proto_idx(method_idx)
+            .is_ok_and(|it| self.dex.type_descriptor(self.dex.proto_ids[it as usize].return_type_idx) == "Ljava/lang/String;")
+    }
+
+    /// Proto of a method operand, which the parser does not check
+    fn proto_idx(&self, method_idx: u32) -> Result<u32, EmulationError> {
+        self.dex.method_ids.get(method_idx as usize).map(|it| it.proto_idx as u32)
+            .ok_or_else(|| EmulationError::InvalidCode(format!("Method index {} out of bounds", method_idx)))
+    }
+
+    /// Emulates a call of the static method with the given arguments (wide values take a single argument)
+    pub fn call(&mut self, method_idx: u32, args: Vec<Value>) -> Result<Value, EmulationError> {
+        self.steps = 0;
+        self.invoke_static(method_idx, args, 0)
+    }
+
+    fn invoke_static(&mut self, method_idx: u32, args: Vec<Value>, depth: usize) -> Result<Value, EmulationError> {
+        if depth >= MAX_DEPTH {
+            return Err(EmulationError::DepthLimit);
+        }
+        let code = match self.static_methods.get(&method_idx) {
+            Some(code) => *code,
+            None => return self.invoke_framework(method_idx, None, args).map(|(result, _)| result),
+        };
+        let mut registers = vec![Value::Undefined; code.registers_size as usize];
+        let mut reg = code.registers_size.saturating_sub(code.ins_size) as usize;
+        for arg in args {
+            let wide = matches!(arg, Value::Long(_));
+            if reg >= registers.len() {
+                return Err(EmulationError::InvalidCode(String::from("Too many arguments")));
+            }
+            registers[reg] = arg;
+            reg += if wide { 2 } else { 1 };
+        }
+        self.execute(code, registers, depth)
+    }
+
+    fn execute(&mut self, code: &CodeItem, mut registers: Vec<Value>, depth: usize) -> Result<Value, EmulationError> {
+        let mut pc = 0;
+        let mut result = Value::Undefined;
+        loop {
+            self.steps += 1;
+            if self.steps > MAX_STEPS {
+                return Err(EmulationError::StepLimit);
+            }
+            let insn = Instruction::decode(&code.insns, pc).map_err(|err| EmulationError::InvalidCode(err.to_string()))?;
+            let get = |reg: u32| registers.get(reg as usize).cloned()
+                .ok_or_else(|| EmulationError::InvalidCode(format!("Register v{} out of range", reg)));
+            let target = |offset: u32| (pc as i64 + offset as i32 as i64) as usize;
+            let mut next = pc + insn.size;
+            let mut write: Option<(u32, Value)> = None;
+            match insn.opcode {
+                0x00 => {}
+                0x01..=0x09 => write = Some((insn.a, get(insn.b)?)),
+                0x0a..=0x0c => write = Some((insn.a, std::mem::replace(&mut result, Value::Undefined))),
+                0x0e => return Ok(Value::Undefined),
+                0x0f..=0x11 => return get(insn.a),
+                0x12..=0x14 => write = Some((insn.a, Value::Int(insn.b as i32))),
+                0x15 => write = Some((insn.a, Value::Int((insn.b << 16) as i32))),
+                0x16 | 0x17 => write = Some((insn.a, Value::Long(insn.b as i32 as i64))),
+                0x18 => write = Some((insn.a, Value::Long(insn.wide_b as i64))),
+                0x19 => write = Some((insn.a, Value::Long(((insn.b as u64) << 48) as i64))),
+                0x1a | 0x1b => {
+                    if !self.strings.contains_key(&insn.b) {
+                        self.strings.insert(insn.b, string_constant(self.dex, insn.b)?);
+                    }
+                    write = Some((insn.a, self.strings[&insn.b].clone()));
+                }
+                0x21 => write = Some((insn.a, Value::Int(get(insn.b)?.as_array()?.borrow().values.len() as i32))),
+                0x22 => write = Some((insn.a, Value::Uninitialized(insn.b))),
+                0x23 => {
+                    let size = get(insn.b)?.as_int()?;
+                    if size < 0 {
+                        return Err(EmulationError::IndexOutOfBounds);
+                    }
+                    check_length(size as usize)?;
+                    let element = self.dex.type_ids.get(insn.c as usize)
+                        .and_then(|_| self.dex.type_descriptor(insn.c).strip_prefix('['))
+                        .ok_or_else(|| EmulationError::InvalidCode(format!("Type index {} is no array type", insn.c)))?;
+                    let default = match element {
+                        "J" => Value::Long(0),
+                        "F" | "D" => return Err(EmulationError::Unsupported(String::from("floating point arrays"))),
+                        _ if element.len() == 1 => Value::Int(0),
+                        _ => Value::Null,
+                    };
+                    write = Some((insn.a, Value::array(element, vec![default; size as usize])));
+                }
+                0x26 => {
+                    let array = get(insn.a)?.as_array()?;
+                    let mut array = array.borrow_mut();
+                    let (element_width, data) = match Payload::decode(&code.insns, target(insn.b)) {
+                        Ok(Payload::FillArrayData { element_width, data }) => (element_width as usize, data),
+                        _ => return Err(EmulationError::InvalidCode(String::from("Missing array data"))),
+                    };
+                    for (i, element) in data.chunks(element_width.max(1)).enumerate() {
+                        let mut bytes = [0u8; 8];
+                        bytes[..element.len()].copy_from_slice(element);
+                        let raw = u64::from_le_bytes(bytes);
+                        let value = match array.element.as_str() {
+                            "J" => Value::Long(raw as i64),
+                            "C" => Value::Int(raw as u16 as i32),
+                            "B" => Value::Int(raw as i8 as i32),
+                            "S" => Value::Int(raw as i16 as i32),
+                            _ => Value::Int(raw as i32),
+                        };
+                        *array.values.get_mut(i).ok_or(EmulationError::IndexOutOfBounds)? = value;
+                    }
+                }
+                0x28..=0x2a => next = target(insn.a),
+                0x31 => write = Some((insn.a, Value::Int(get(insn.b)?.as_long()?.cmp(&get(insn.c)?.as_long()?) as i32))),
+                0x32..=0x3d => {
+                    let left = get(insn.a)?;
+                    let (right, offset) = if insn.format() == Format::F22t {
+                        (get(insn.b)?, insn.c)
+                    } else {
+                        (Value::Int(0), insn.b)
+                    };
+                    // References are only compared for (in)equality, to each other or to null
+                    let taken = match (&left, &right, (insn.opcode - 0x32) % 6) {
+                        (Value::Int(left), Value::Int(right), operation) => match operation {
+                            0 => left == right,
+                            1 => left != right,
+                            2 => left < right,
+                            3 => left >= right,
+                            4 => left > right,
+                            _ => left <= right,
+                        },
+                        (_, _, operation @ (0 | 1)) => match left.same_reference(&right) {
+                            Some(same) => same == (operation == 0),
+                            None => return Err(EmulationError::TypeMismatch(format!("Cannot compare {:?} and {:?}", left, right))),
+                        },
+                        _ => return Err(EmulationError::TypeMismatch(format!("Cannot order {:?} and {:?}", left, right))),
+                    };
+                    if taken {
+                        next = target(offset);
+                    }
+                }
+                0x44..=0x4a => {
+                    let array = get(insn.b)?.as_array()?;
+                    let index = get(insn.c)?.as_int()?;
+                    let value = array.borrow().values.get(index as usize).cloned().ok_or(EmulationError::IndexOutOfBounds)?;
+                    write = Some((insn.a, value));
+                }
+                0x4b..=0x51 => {
+                    let array = get(insn.b)?.as_array()?;
+                    let index = get(insn.c)?.as_int()?;
+                    let value = match (insn.opcode, get(insn.a)?) {
+                        (0x4e, value) => Value::Int(value.as_int()? & 1),
+                        (0x4f, value) => Value::Int(value.as_int()? as i8 as i32),
+                        (0x50, value) => Value::Int(value.as_int()? as u16 as i32),
+                        (0x51, value) => Value::Int(value.as_int()? as i16 as i32),
+                        (_, value) => value,
+                    };
+                    *array.borrow_mut().values.get_mut(index as usize).ok_or(EmulationError::IndexOutOfBounds)? = value;
+                }
+                0x71 | 0x77 => {
+                    let args = self.invoke_args(&insn, &registers, true)?;
+                    result = self.invoke_static(insn.b, args, depth + 1)?;
+                }
+                0x6e | 0x70 | 0x74 | 0x76 => {
+                    let mut args = self.invoke_args(&insn, &registers, false)?;
+                    let receiver = args.remove(0);
+                    let (value, receiver) = self.invoke_framework(insn.b, Some(receiver), args)?;
+                    // Constructors replace the uninitialized instance
+                    if let Some(receiver) = receiver {
+                        let reg = if insn.format() == Format::F35c { insn.args[0] as u32 } else { insn.c };
+                        write = Some((reg, receiver));
+                    }
+                    result = value;
+                }
+                0x7b => write = Some((insn.a, Value::Int(get(insn.b)?.as_int()?.wrapping_neg()))),
+                0x7c => write = Some((insn.a, Value::Int(!get(insn.b)?.as_int()?))),
+                0x7d => write = Some((insn.a, Value::Long(get(insn.b)?.as_long()?.wrapping_neg()))),
+                0x7e => write = Some((insn.a, Value::Long(!get(insn.b)?.as_long()?))),
+                0x81 => write = Some((insn.a, Value::Long(get(insn.b)?.as_int()? as i64))),
+                0x84 => write = Some((insn.a, Value::Int(get(insn.b)?.as_long()? as i32))),
+                0x8d => write = Some((insn.a, Value::Int(get(insn.b)?.as_int()? as i8 as i32))),
+                0x8e => write = Some((insn.a, Value::Int(get(insn.b)?.as_int()? as u16 as i32))),
+                0x8f => write = Some((insn.a, Value::Int(get(insn.b)?.as_int()? as i16 as i32))),
+                0x90..=0x9a => {
+                    let value = int_operation(insn.opcode - 0x90, get(insn.b)?.as_int()?, get(insn.c)?.as_int()?)?;
+                    write = Some((insn.a, Value::Int(value)));
+                }
+                0x9b..=0xa5 => write = Some((insn.a, Value::Long(long_operation(insn.opcode - 0x9b, &get(insn.b)?, &get(insn.c)?)?))),
+                0xb0..=0xba => {
+                    let value = int_operation(insn.opcode - 0xb0, get(insn.a)?.as_int()?, get(insn.b)?.as_int()?)?;
+                    write = Some((insn.a, Value::Int(value)));
+                }
+                0xbb..=0xc5 => write = Some((insn.a, Value::Long(long_operation(insn.opcode - 0xbb, &get(insn.a)?, &get(insn.b)?)?))),
+                // rsub-int and rsub-int/lit8
+                0xd1 | 0xd9 => write = Some((insn.a, Value::Int((insn.c as i32).wrapping_sub(get(insn.b)?.as_int()?)))),
+                0xd0..=0xd7 => {
+                    let value = int_operation(insn.opcode - 0xd0, get(insn.b)?.as_int()?, insn.c as i32)?;
+                    write = Some((insn.a, Value::Int(value)));
+                }
+                0xd8..=0xe2 => {
+                    let value = int_operation(insn.opcode - 0xd8, get(insn.b)?.as_int()?, insn.c as i32)?;
+                    write = Some((insn.a, Value::Int(value)));
+                }
+                _ => return Err(EmulationError::Unsupported(format!("instruction {}", insn.name()))),
+            }
+            if let Some((reg, value)) = write {
+                let wide = matches!(value, Value::Long(_));
+                let slot = registers.get_mut(reg as usize)
+                    .ok_or_else(|| EmulationError::InvalidCode(format!("Register v{} out of range", reg)))?;
+                *slot = value;
+                if wide {
+                    if let Some(high) = registers.get_mut(reg as usize + 1) {
+                        *high = Value::Undefined;
+                    }
+                }
+            }
+            pc = next;
+        }
+    }
+
+    /// Argument values of an invoke instruction, wide values are only taken from their first register
+    fn invoke_args(&self, insn: &Instruction, registers: &[Value], is_static: bool) -> Result<Vec<Value>, EmulationError> {
+        let regs: Vec<u32> = match insn.format() {
+            Format::F35c => insn.args.iter().take(insn.a as usize).map(|it| *it as u32).collect(),
+            _ => (insn.c..insn.c + insn.a).collect(),
+        };
+        let mut regs = regs.into_iter();
+        let mut args = Vec::new();
+        let mut take = |regs: &mut dyn Iterator<Item = u32>| -> Result<(), EmulationError> {
+            let reg = regs.next().ok_or_else(|| EmulationError::InvalidCode(String::from("Missing argument")))?;
+            args.push(registers.get(reg as usize).cloned()
+                .ok_or_else(|| EmulationError::InvalidCode(format!("Register v{} out of range", reg)))?);
+            Ok(())
+        };
+        if !is_static {
+            take(&mut regs)?;
+        }
+        let proto_idx = self.proto_idx(insn.b)?;
+        for descriptor in self.dex.proto_parameters(proto_idx) {
+            take(&mut regs)?;
+            if descriptor == "J" || descriptor == "D" {
+                regs.next();
+            }
+        }
+        Ok(args)
+    }
+
+    /// Models the framework methods used by typical decryptors. Returns the result and, for
+    /// constructors, the initialized receiver.
+    fn invoke_framework(&self, method_idx: u32, receiver: Option<Value>, args: Vec<Value>) -> Result<(Value, Option<Value>), EmulationError> {
+        self.proto_idx(method_idx)?;
+        let signature = format!("{}->{}{}", self.dex.method_class(method_idx), self.dex.method_name(method_idx),
+                                self.dex.method_signature(method_idx));
+        let receiver = receiver.unwrap_or(Value::Undefined);
+        let arg = |i: usize| args.get(i).cloned().ok_or_else(|| EmulationError::InvalidCode(String::from("Missing argument")));
+        let value = match signature.as_str() {
+            "Ljava/lang/String;->length()I" => Value::Int(receiver.as_str()?.len() as i32),
+            "Ljava/lang/String;->charAt(I)C" => {
+                let index = arg(0)?.as_int()?;
+                Value::Int(*receiver.as_str()?.get(index as usize).ok_or(EmulationError::IndexOutOfBounds)? as i32)
+            }
+            "Ljava/lang/String;->toCharArray()[C" => {
+                Value::array("C", receiver.as_str()?.iter().map(|it| Value::Int(*it as i32)).collect())
+            }
+            "Ljava/lang/String;->getBytes()[B" => {
+                let s = String::from_utf16_lossy(&receiver.as_str()?);
+                Value::array("B", s.bytes().map(|it| Value::Int(it as i8 as i32)).collect())
+            }
+            "Ljava/lang/String;->intern()Ljava/lang/String;" | "Ljava/lang/String;->toString()Ljava/lang/String;" => {
+                Value::Str(receiver.as_str()?)
+            }
+            "Ljava/lang/String;->valueOf([C)Ljava/lang/String;" => Value::Str(Rc::new(char_units(&arg(0)?)?)),
+            "Ljava/lang/String;->valueOf(C)Ljava/lang/String;" => Value::Str(Rc::new(vec![arg(0)?.as_int()? as u16])),
+            "Ljava/lang/String;-><init>([C)V" => {
+                return Ok((Value::Undefined, Some(Value::Str(Rc::new(char_units(&arg(0)?)?)))));
+            }
+            "Ljava/lang/String;-><init>([B)V" => {
+                let bytes: Vec<u8> = arg(0)?.as_array()?.borrow().values.iter()
+                    .map(|it| it.as_int().map(|it| it as u8))
+                    .collect::<Result<_, _>>()?;
+                let s = String::from_utf8_lossy(&bytes);
+                return Ok((Value::Undefined, Some(Value::string(&s))));
+            }
+            "Ljava/lang/StringBuilder;-><init>()V" => {
+                return Ok((Value::Undefined, Some(Value::Builder(Rc::new(RefCell::new(Vec::new()))))));
+            }
+            "Ljava/lang/StringBuilder;-><init>(Ljava/lang/String;)V" => {
+                let initial = arg(0)?.as_str()?.to_vec();
+                return Ok((Value::Undefined, Some(Value::Builder(Rc::new(RefCell::new(initial))))));
+            }
+            "Ljava/lang/StringBuilder;->append(C)Ljava/lang/StringBuilder;" => {
+                builder(&receiver)?.borrow_mut().push(arg(0)?.as_int()? as u16);
+                receiver.clone()
+            }
+            "Ljava/lang/StringBuilder;->append(I)Ljava/lang/StringBuilder;" => {
+                builder(&receiver)?.borrow_mut().extend(arg(0)?.as_int()?.to_string().encode_utf16());
+                receiver.clone()
+            }
+            "Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;" => {
+                match arg(0)? {
+                    Value::Null => builder(&receiver)?.borrow_mut().extend("null".encode_utf16()),
+                    value => builder(&receiver)?.borrow_mut().extend(value.as_str()?.iter()),
+                }
+                receiver.clone()
+            }
+            "Ljava/lang/StringBuilder;->toString()Ljava/lang/String;" => Value::Str(Rc::new(builder(&receiver)?.borrow().clone())),
+            _ => return Err(EmulationError::Unsupported(format!("method {}", signature))),
+        };
+        if let Value::Builder(builder) = &value {
+            check_length(builder.borrow().len())?;
+        }
+        Ok((value, None))
+    }
+}
+
+fn builder(value: &Value) -> Result<Rc<RefCell<Vec<u16>>>, EmulationError> {
+    match value {
+        Value::Builder(builder) => Ok(builder.clone()),
+        value => Err(EmulationError::TypeMismatch(format!("Expected StringBuilder, got {:?}", value))),
+    }
+}
+
+/// Value of const-string, the parser does not check the index
+fn string_constant(dex: &DexFile, string_idx: u32) -> Result<Value, EmulationError> {
+    match dex.strings.get(string_idx as usize) {
+        Some(string) => Ok(Value::string(string)),
+        None => Err(EmulationError::InvalidCode(format!("String index {} out of bounds", string_idx))),
+    }
+}
+
+fn char_units(array: &Value) -> Result<Vec<u16>, EmulationError> {
+    array.as_array()?.borrow().values.iter().map(|it| it.as_int().map(|it| it as u16)).collect()
+}
+
+/// Operations of the int binops in opcode order: add, sub, mul, div, rem, and, or, xor, shl, shr, ushr
+fn int_operation(operation: u8, a: i32, b: i32) -> Result<i32, EmulationError> {
+    Ok(match operation {
+        0 => a.wrapping_add(b),
+        1 => a.wrapping_sub(b),
+        2 => a.wrapping_mul(b),
+        3 => if b == 0 { return Err(EmulationError::ArithmeticException) } else { a.wrapping_div(b) },
+        4 => if b == 0 { return Err(EmulationError::ArithmeticException) } else { a.wrapping_rem(b) },
+        5 => a & b,
+        6 => a | b,
+        7 => a ^ b,
+        8 => a.wrapping_shl(b as u32 & 0x1f),
+        9 => a.wrapping_shr(b as u32 & 0x1f),
+        _ => ((a as u32) >> (b as u32 & 0x1f)) as i32,
+    })
+}
+
+/// Same as int_operation for longs, shift distances are ints
+fn long_operation(operation: u8, a: &Value, b: &Value) -> Result<i64, EmulationError> {
+    let a = a.as_long()?;
+    if operation >= 8 {
+        let shift = b.as_int()? as u32 & 0x3f;
+        return Ok(match operation {
+            8 => a.wrapping_shl(shift),
+            9 => a.wrapping_shr(shift),
+            _ => ((a as u64) >> shift) as i64,
+        });
+    }
+    let b = b.as_long()?;
+    Ok(match operation {
+        0 => a.wrapping_add(b),
+        1 => a.wrapping_sub(b),
+        2 => a.wrapping_mul(b),
+        3 => if b == 0 { return Err(EmulationError::ArithmeticException) } else { a.wrapping_div(b) },
+        4 => if b == 0 { return Err(EmulationError::ArithmeticException) } else { a.wrapping_rem(b) },
+        5 => a & b,
+        6 => a | b,
+        _ => a ^ b,
+    })
+}
+
+/// Finds calls of static methods returning a string whose arguments are all constants of the same
+/// basic block, and emulates them to recover the decrypted strings.
+pub fn decrypt_strings(dex: &DexFile) -> Vec<DecryptedString> {
+    let mut emulator = Emulator::new(dex);
+    let mut decrypted = Vec::new();
+    for class_data in dex.class_data.iter().flatten() {
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            for (method, caller) in methods.iter().zip(dex_file::method_indices(methods)) {
+                let code = match dex.code_item(method.code_off) {
+                    Some(code) => code,
+                    None => continue,
+                };
+                let cfg = match ControlFlowGraph::build(code) {
+                    Ok(cfg) => cfg,
+                    Err(err) => {
+                        tracing::warn!(method_idx = caller, %err, "Skipping method with invalid code");
+                        continue;
+                    }
+                };
+                for block in 0..cfg.blocks.len() {
+                    // Registers holding constants, reset at each block as other predecessors may differ
+                    let mut registers = vec![Value::Undefined; code.registers_size as usize];
+                    for (pc, insn) in cfg.block_instructions(block) {
+                        let constant = match insn.opcode {
+                            0x12..=0x14 => Value::Int(insn.b as i32),
+                            0x16 | 0x17 => Value::Long(insn.b as i32 as i64),
+                            0x18 => Value::Long(insn.wide_b as i64),
+                            0x1a | 0x1b => match string_constant(dex, insn.b) {
+                                Ok(value) => value,
+                                Err(_) => {
+                                    registers.fill(Value::Undefined);
+                                    continue;
+                                }
+                            },
+                            0x71 | 0x77 if emulator.is_decryptor(insn.b) => {
+                                let args = emulator.invoke_args(insn, &registers, true).ok()
+                                    .filter(|args| args.iter().all(|it| !matches!(it, Value::Undefined)));
+                                match args.map(|args| emulator.call(insn.b, args)) {
+                                    Some(Ok(Value::Str(value))) => decrypted.push(DecryptedString {
+                                        caller,
+                                        pc: *pc,
+                                        decryptor: insn.b,
+                                        value: String::from_utf16_lossy(&value),
+                                    }),
+                                    Some(Err(err)) => tracing::debug!(method_idx = caller, pc, decryptor = insn.b, %err, "Emulation failed"),
+                                    _ => {}
+                                }
+                                registers.fill(Value::Undefined);
+                                continue;
+                            }
+                            _ => {
+                                registers.fill(Value::Undefined);
+                                continue;
+                            }
+                        };
+                        let wide = matches!(constant, Value::Long(_));
+                        if let Some(reg) = registers.get_mut(insn.a as usize) {
+                            *reg = constant;
+                        }
+                        if wide {
+                            if let Some(high) = registers.get_mut(insn.a as usize + 1) {
+                                *high = Value::Undefined;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    decrypted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::fixture::{Fixture, FixtureMethod};
+
+    /// Emulates the static method `decrypt([I)Ljava/lang/String;` with the code emitted by `emit`, which
+    /// gets the fixture for the indices of its strings and types
+    fn emulate(strings: &[&str], emit: impl Fn(&Fixture, &mut CodeBuilder)) -> Result<Value, EmulationError> {
+        let fixture = strings.iter().fold(Fixture::empty(), |fixture, it| fixture.string(it));
+        let method = |insns| FixtureMethod::new("decrypt", "Ljava/lang/String;", &["[I"], insns).access_flags(0x8).registers_size(3);
+        let mut code = CodeBuilder::new();
+        emit(&fixture.clone().method(method(Vec::new())), &mut code);
+        let dex = DexFile::from_bytes(&fixture.method(method(code.build().unwrap())).build()).unwrap();
+        let method_idx = dex.method_idx("Lcom/example/Fixture;", "decrypt", "([I)Ljava/lang/String;").unwrap();
+        Emulator::new(&dex).call(method_idx, vec![Value::Null])
+    }
+
+    #[test]
+    fn huge_array() {
+        let result = emulate(&[], |fixture, code| {
+            code.emit("const", &[Reg(0), Lit(i32::MAX as i64)]).unwrap()
+                .emit("new-array", &[Reg(0), Reg(0), Idx(fixture.type_idx("[I").unwrap())]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap();
+        });
+        assert!(matches!(result, Err(EmulationError::LengthLimit)));
+    }
+
+    #[test]
+    fn reference_comparison() {
+        // Compares the string "a" to the string or int `other`
+        let compare = |other: Result<&str, i64>| emulate(&["a", "b", "same", "other"], |fixture, code| {
+            let same = code.label();
+            code.emit("const-string", &[Reg(0), Idx(fixture.string_idx("a").unwrap())]).unwrap();
+            match other {
+                Ok(string) => code.emit("const-string", &[Reg(1), Idx(fixture.string_idx(string).unwrap())]).unwrap(),
+                Err(int) => code.emit("const/4", &[Reg(1), Lit(int)]).unwrap(),
+            };
+            code.emit("if-eq", &[Reg(0), Reg(1), Target(same)]).unwrap()
+                .emit("const-string", &[Reg(0), Idx(fixture.string_idx("other").unwrap())]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap()
+                .bind(same).unwrap()
+                .emit("const-string", &[Reg(0), Idx(fixture.string_idx("same").unwrap())]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap();
+        });
+        let string = |result: Result<Value, EmulationError>| String::from_utf16_lossy(&result.unwrap().as_str().unwrap());
+        assert_eq!(string(compare(Ok("b"))), "other");
+        assert_eq!(string(compare(Ok("a"))), "same");
+        // Null
+        assert_eq!(string(compare(Err(0))), "other");
+        assert!(matches!(compare(Err(1)), Err(EmulationError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let invoke = emulate(&[], |_, code| {
+            code.emit("invoke-static", &[Regs(vec![]), Idx(999)]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap();
+        });
+        assert!(matches!(invoke, Err(EmulationError::InvalidCode(_))));
+        let string = emulate(&[], |_, code| {
+            code.emit("const-string", &[Reg(0), Idx(999)]).unwrap()
+                .emit("return-object", &[Reg(0)]).unwrap();
+        });
+        assert!(matches!(string, Err(EmulationError::InvalidCode(_))));
+    }
+}
@@ -0,0 +1,140 @@
+use std::ops::Range;
+
+use crate::dex_file::DexFile;
+
+/*
+Shannon entropy of the sections of a dex file, in bits per byte (0 to 8). Code, ids and strings stay
+well below 7, compressed or encrypted payloads hidden in a dex file (e.g. in a string, an array of
+static values or bytes not covered by the map list) are close to 8. Short ranges cannot reach a high
+entropy (n bytes have at most log2(n) bits per byte), so the data section is also scanned in windows.
+ */
+
+/// Entropy above which a range likely holds compressed or encrypted data
+pub const HIGH_ENTROPY: f64 = 7.5;
+/// Size of the windows of the data section, large enough to reach HIGH_ENTROPY
+pub const WINDOW_SIZE: usize = 1024;
+
+/// Entropy of a range of the file
+#[derive(Debug, Clone)]
+pub struct RangeEntropy {
+    /// Name of the section, `data` for the whole data section and `window` for high entropy regions
+    pub name: &'static str,
+    pub range: Range<usize>,
+    pub entropy: f64,
+}
+
+impl RangeEntropy {
+    pub fn is_high(&self) -> bool {
+        self.entropy >= HIGH_ENTROPY
+    }
+}
+
+/// Shannon entropy of the bytes in bits per byte, 0 for no bytes
+pub fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts.iter().filter(|it| **it > 0)
+        .map(|it| {
+            let p = *it as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn range_entropy(name: &'static str, range: Range<usize>, data: &[u8]) -> RangeEntropy {
+    let bytes = data.get(range.start.min(data.len())..range.end.min(data.len())).unwrap_or_default();
+    RangeEntropy { name, entropy: shannon_entropy(bytes), range }
+}
+
+/// Entropy of the data section and of each item of the map list of the dex file `data`. The range of
+/// a map item extends to the next one (or the end of the file), like in the map listing.
+pub fn section_entropy(dex: &DexFile, data: &[u8]) -> Vec<RangeEntropy> {
+    let header = &dex.header;
+    let data_section = header.data_off as usize..header.data_off as usize + header.data_size as usize;
+    let mut sections = vec![range_entropy("data", data_section, data)];
+    let mut offsets: Vec<usize> = dex.map_list.iter().map(|it| it.offset as usize).collect();
+    offsets.sort_unstable();
+    for item in &dex.map_list {
+        let start = item.offset as usize;
+        let end = offsets.iter().copied().find(|it| *it > start).unwrap_or(header.file_size as usize).max(start);
+        sections.push(range_entropy(item.type_name().unwrap_or("unknown"), start..end, data));
+    }
+    sections
+}
+
+/// Regions of the data section (and of the bytes after it, up to the end of `data`) whose windows
+/// of WINDOW_SIZE bytes have a high entropy, adjacent windows merged into one region
+pub fn high_entropy_regions(dex: &DexFile, data: &[u8]) -> Vec<RangeEntropy> {
+    let start = (dex.header.data_off as usize).min(data.len());
+    let mut regions: Vec<Range<usize>> = Vec::new();
+    for window_start in (start..data.len()).step_by(WINDOW_SIZE) {
+        let window = window_start..(window_start + WINDOW_SIZE).min(data.len());
+        if shannon_entropy(&data[window.clone()]) < HIGH_ENTROPY {
+            continue;
+        }
+        match regions.last_mut() {
+            Some(last) if last.end == window.start => last.end = window.end,
+            _ => regions.push(window),
+        }
+    }
+    regions.into_iter().map(|it| range_entropy("window", it, data)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// Bytes of xorshift32, close to 8 bits of entropy per byte
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f491u32;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect()
+    }
+
+    #[test]
+    fn entropy_of_bytes() {
+        assert_eq!(shannon_entropy(&[]), 0.0);
+        assert_eq!(shannon_entropy(&[7; 100]), 0.0);
+        assert_eq!(shannon_entropy(&[0, 1, 0, 1]), 1.0);
+        assert_eq!(shannon_entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+        assert!(shannon_entropy(&random_bytes(WINDOW_SIZE)) >= HIGH_ENTROPY);
+    }
+
+    #[test]
+    fn compiled_sections() {
+        let fixture = Fixture::new();
+        let dex = fixture.parse();
+        let data = fixture.build();
+        let sections = section_entropy(&dex, &data);
+        let names: Vec<&str> = sections.iter().map(|it| it.name).collect();
+        assert_eq!(names, ["data", "header_item", "string_id_item", "type_id_item", "proto_id_item", "method_id_item",
+                           "class_def_item", "code_item", "string_data_item", "class_data_item", "map_list"]);
+        assert_eq!(sections[0].range, dex.header.data_off as usize..(dex.header.data_off + dex.header.data_size) as usize);
+        assert_eq!(sections.last().unwrap().range.end, data.len());
+        assert!(sections.iter().all(|it| !it.is_high()));
+        assert!(high_entropy_regions(&dex, &data).is_empty());
+    }
+
+    #[test]
+    fn appended_payload() {
+        let dex = Fixture::new().parse();
+        let mut data = Fixture::new().build();
+        data.extend(random_bytes(4 * WINDOW_SIZE));
+        let regions: Vec<(&str, Range<usize>)> = high_entropy_regions(&dex, &data).into_iter().map(|it| (it.name, it.range)).collect();
+        // The first window still holds the items of the fixture, the full windows of the payload are merged
+        // into one region, and the last window is too short to reach a high entropy
+        let data_off = dex.header.data_off as usize;
+        assert!(data.len() - (data_off + 4 * WINDOW_SIZE) < WINDOW_SIZE / 4);
+        assert_eq!(regions, [("window", data_off + WINDOW_SIZE..data_off + 4 * WINDOW_SIZE)]);
+        // The map items end at the declared file size, the payload is not part of any section
+        assert!(section_entropy(&dex, &data).iter().skip(1).all(|it| !it.is_high()));
+    }
+}
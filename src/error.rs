@@ -0,0 +1,101 @@
+use core::fmt;
+
+use crate::prelude::*;
+
+use crate::io;
+
+/*
+Errors of malformed dex files that the parser detects itself, as opposed to failures of the reader.
+Parsing functions return io::Error, into which a DexError converts with the matching ErrorKind; with
+the std feature the DexError is its source (io::Error::get_ref), so the offset stays available.
+Errors of the items of a dex file are wrapped in DexError::Context, naming the item that was parsed.
+ */
+
+#[derive(Debug)]
+pub enum DexError {
+    /// A LEB128 value longer than the 5 bytes that can encode 32 bits, at the offset of its first byte
+    Leb128TooLong { offset: u64 },
+    /// The data ends within the LEB128 value starting at the offset
+    TruncatedLeb128 { offset: u64 },
+    /// An encoded_value of an unknown type or with a value_arg out of range for its type
+    InvalidEncodedValue { offset: u64, value_type: u8, value_arg: u8 },
+    /// An offset or size computed from the value at the offset that does not fit 32 bits (or usize)
+    Overflow { offset: u64 },
+    /// `len` bytes at the offset that extend past the `available` bytes of the input
+    OutOfBounds { offset: u64, len: u64, available: u64 },
+    /// An index into the `kind` table (e.g. `type`) at the offset that is not below its `size`
+    InvalidIndex { offset: u64, kind: &'static str, index: u64, size: u64 },
+    /// Failure to parse an item starting at the offset, e.g. `code_item #3 for method Lcom/a;->b()V`
+    Context { item: String, offset: u64, source: io::Error },
+    Io(io::Error),
+}
+
+impl DexError {
+    /// Offset of the malformed data, None for failures of the reader
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            DexError::Leb128TooLong { offset } | DexError::TruncatedLeb128 { offset } => Some(*offset),
+            DexError::InvalidEncodedValue { offset, .. } | DexError::Context { offset, .. } => Some(*offset),
+            DexError::Overflow { offset } | DexError::OutOfBounds { offset, .. } => Some(*offset),
+            DexError::InvalidIndex { offset, .. } => Some(*offset),
+            DexError::Io(_) => None,
+        }
+    }
+
+    /// Adds the item being parsed at `offset` to an error of parsing it
+    pub fn context(err: io::Error, item: String, offset: u64) -> io::Error {
+        DexError::Context { item, offset, source: err }.into()
+    }
+}
+
+impl fmt::Display for DexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DexError::Leb128TooLong { offset } => write!(f, "LEB128 value at offset 0x{:x} is longer than 5 bytes", offset),
+            DexError::TruncatedLeb128 { offset } => write!(f, "Truncated LEB128 value at offset 0x{:x}", offset),
+            DexError::InvalidEncodedValue { offset, value_type, value_arg } => {
+                write!(f, "Invalid encoded value at offset 0x{:x} (type 0x{:02x}, value_arg {})", offset, value_type, value_arg)
+            }
+            DexError::Overflow { offset } => write!(f, "Offset or size at offset 0x{:x} overflows", offset),
+            DexError::OutOfBounds { offset, len, available } => {
+                write!(f, "{} bytes at offset 0x{:x} extend past the end of the data ({} bytes)", len, offset, available)
+            }
+            DexError::InvalidIndex { offset, kind, index, size } => {
+                write!(f, "{} index {} at offset 0x{:x} is out of range ({} {}s)", kind, index, offset, size, kind)
+            }
+            DexError::Context { item, offset, source } => write!(f, "{} at offset 0x{:x}: {}", item, offset, source),
+            DexError::Io(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl core::error::Error for DexError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            DexError::Context { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DexError {
+    fn from(err: io::Error) -> DexError {
+        DexError::Io(err)
+    }
+}
+
+impl From<DexError> for io::Error {
+    fn from(err: DexError) -> io::Error {
+        let kind = match err {
+            DexError::Leb128TooLong { .. } | DexError::InvalidEncodedValue { .. } => io::ErrorKind::InvalidData,
+            DexError::Overflow { .. } | DexError::InvalidIndex { .. } => io::ErrorKind::InvalidData,
+            DexError::TruncatedLeb128 { .. } | DexError::OutOfBounds { .. } => io::ErrorKind::UnexpectedEof,
+            DexError::Context { ref source, .. } => source.kind(),
+            DexError::Io(err) => return err,
+        };
+        #[cfg(feature = "std")]
+        return io::Error::new(kind, err);
+        #[cfg(not(feature = "std"))]
+        return io::Error::new(kind, alloc::format!("{}", err));
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::dex_file::{self, DexFile};
+use crate::instructions::{IndexType, Instructions};
+
+/*
+Exports of the model into formats for external analysis
+ */
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+/// An instruction referencing a string, type, field, method, ... by index
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub method_idx: u32,
+    pub pc: usize,
+    pub kind: &'static str,
+    pub target: u32,
+}
+
+/// Name of the kind of index referenced by an instruction
+pub fn reference_kind(index_type: IndexType) -> &'static str {
+    match index_type {
+        IndexType::None => "none",
+        IndexType::StringRef => "string",
+        IndexType::TypeRef => "type",
+        IndexType::FieldRef => "field",
+        IndexType::MethodRef | IndexType::MethodAndProtoRef => "method",
+        IndexType::CallSiteRef => "call_site",
+        IndexType::MethodHandleRef => "method_handle",
+        IndexType::ProtoRef => "proto",
+    }
+}
+
+/// Name of the target of a reference, e.g. the string value or Lcls;->name(sig) for methods
+pub fn target_name(dex: &DexFile, reference: &Reference) -> String {
+    let target = reference.target;
+    match reference.kind {
+        "string" => dex.string(target).to_string(),
+        "type" => dex.type_descriptor(target).to_string(),
+        "field" => format!("{}->{}:{}", dex.field_class(target), dex.field_name(target), dex.field_type(target)),
+        "method" => format!("{}->{}{}", dex.method_class(target), dex.method_name(target), dex.method_signature(target)),
+        "proto" => dex.proto_signature(target),
+        _ => String::new(),
+    }
+}
+
+/// References of the instructions of all methods with code, ordered by method index and pc
+pub fn references(dex: &DexFile) -> Vec<Reference> {
+    let mut methods: Vec<(u32, u64)> = Vec::new();
+    for class_data in dex.class_data.iter().flatten() {
+        for list in [&class_data.direct_methods, &class_data.virtual_methods] {
+            methods.extend(dex_file::method_indices(list).into_iter().zip(list.iter().map(|it| it.code_off)));
+        }
+    }
+    methods.sort_unstable();
+
+    // Methods are decoded in parallel with the parallel feature, collecting keeps their order
+    #[cfg(feature = "parallel")]
+    return methods.into_par_iter().flat_map_iter(|(method_idx, code_off)| method_references(dex, method_idx, code_off)).collect();
+    #[cfg(not(feature = "parallel"))]
+    return methods.into_iter().flat_map(|(method_idx, code_off)| method_references(dex, method_idx, code_off)).collect();
+}
+
+fn method_references(dex: &DexFile, method_idx: u32, code_off: u64) -> Vec<Reference> {
+    let mut references = Vec::new();
+    let code = match dex.code_item(code_off) {
+        Some(code) => code,
+        None => return references,
+    };
+    for (pc, insn) in Instructions::new(&code.insns).map_while(Result::ok) {
+        if let Some(target) = insn.index() {
+            references.push(Reference { method_idx, pc, kind: reference_kind(insn.info().index_type), target });
+        }
+        if insn.info().index_type == IndexType::MethodAndProtoRef {
+            references.push(Reference { method_idx, pc, kind: "proto", target: insn.h });
+        }
+    }
+    references
+}
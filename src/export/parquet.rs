@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, BooleanArray, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+
+use crate::atomic::AtomicFile;
+
+use crate::dex_file::{self, DexFile};
+
+/*
+Columnar export as one Parquet file per dataset (strings, methods, references). Every row carries the
+name of the dex file, so the datasets of many files can be concatenated into a single table.
+ */
+
+fn write_batch(path: &Path, columns: Vec<(&str, DataType, bool, ArrayRef)>) -> Result<(), ParquetError> {
+    let schema = Arc::new(Schema::new(columns.iter()
+        .map(|(name, data_type, nullable, _)| Field::new(*name, data_type.clone(), *nullable))
+        .collect::<Vec<_>>()));
+    let batch = RecordBatch::try_new(schema.clone(), columns.into_iter().map(|it| it.3).collect())?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(AtomicFile::create(path)?, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.into_inner()?.commit()?;
+    Ok(())
+}
+
+fn file_column(file_name: &str, len: usize) -> ArrayRef {
+    Arc::new(StringArray::from(vec![file_name; len]))
+}
+
+/// Writes strings.parquet, methods.parquet and references.parquet into the directory `dir`
+pub fn export(dex: &DexFile, file_name: &str, dir: &Path) -> Result<(), ParquetError> {
+    std::fs::create_dir_all(dir)?;
+
+    // strings: file, index, value
+    write_batch(&dir.join("strings.parquet"), vec![
+        ("file", DataType::Utf8, false, file_column(file_name, dex.strings.len())),
+        ("index", DataType::UInt32, false, Arc::new(UInt32Array::from_iter_values(0..dex.strings.len() as u32))),
+        ("value", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(dex.strings.iter()))),
+    ])?;
+
+    // methods: file, index, class, name, signature, defined, access_flags, insns_size
+    let mut definitions = vec![None; dex.method_ids.len()];
+    for class_data in dex.class_data.iter().flatten() {
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            for (method, idx) in methods.iter().zip(dex_file::method_indices(methods)) {
+                if let Some(definition) = definitions.get_mut(idx as usize) {
+                    *definition = Some(method);
+                }
+            }
+        }
+    }
+    let indices = 0..dex.method_ids.len() as u32;
+    write_batch(&dir.join("methods.parquet"), vec![
+        ("file", DataType::Utf8, false, file_column(file_name, dex.method_ids.len())),
+        ("index", DataType::UInt32, false, Arc::new(UInt32Array::from_iter_values(indices.clone()))),
+        ("class", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(indices.clone().map(|it| dex.method_class(it))))),
+        ("name", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(indices.clone().map(|it| dex.method_name(it))))),
+        ("signature", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(indices.map(|it| dex.method_signature(it))))),
+        ("defined", DataType::Boolean, false, Arc::new(BooleanArray::from(definitions.iter().map(|it| it.is_some()).collect::<Vec<_>>()))),
+        ("access_flags", DataType::UInt32, true, Arc::new(definitions.iter().map(|it| it.map(|it| it.access_flags as u32)).collect::<UInt32Array>())),
+        ("insns_size", DataType::UInt32, true, Arc::new(definitions.iter()
+            .map(|it| it.and_then(|it| dex.code_item(it.code_off)).map(|it| it.insns.len() as u32))
+            .collect::<UInt32Array>())),
+    ])?;
+
+    // references: file, method_index, pc, kind, target, target_name
+    let references = super::references(dex);
+    write_batch(&dir.join("references.parquet"), vec![
+        ("file", DataType::Utf8, false, file_column(file_name, references.len())),
+        ("method_index", DataType::UInt32, false, Arc::new(UInt32Array::from_iter_values(references.iter().map(|it| it.method_idx)))),
+        ("pc", DataType::UInt32, false, Arc::new(UInt32Array::from_iter_values(references.iter().map(|it| it.pc as u32)))),
+        ("kind", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(references.iter().map(|it| it.kind)))),
+        ("target", DataType::UInt32, false, Arc::new(UInt32Array::from_iter_values(references.iter().map(|it| it.target)))),
+        ("target_name", DataType::Utf8, false, Arc::new(StringArray::from_iter_values(references.iter().map(|it| super::target_name(dex, it))))),
+    ])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow::util::display::{ArrayFormatter, FormatOptions};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::fixture::{self, Fixture};
+
+    /// Column names and rows of a Parquet file, with the columns joined by commas and null as empty
+    fn read(path: &Path) -> (Vec<String>, Vec<String>) {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let mut columns = Vec::new();
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            columns = batch.schema().fields().iter().map(|it| it.name().clone()).collect();
+            let formatters: Vec<_> = batch.columns().iter()
+                .map(|it| ArrayFormatter::try_new(it.as_ref(), &FormatOptions::default()).unwrap())
+                .collect();
+            for row in 0..batch.num_rows() {
+                rows.push(formatters.iter().map(|it| it.value(row).to_string()).collect::<Vec<_>>().join(","));
+            }
+        }
+        (columns, rows)
+    }
+
+    #[test]
+    fn datasets() {
+        let dir = std::env::temp_dir().join(format!("dex_tool-parquet-{}", std::process::id()));
+        export(&Fixture::greeting().parse(), "fixture.dex", &dir).unwrap();
+
+        let (columns, rows) = read(&dir.join("strings.parquet"));
+        assert_eq!(columns, ["file", "index", "value"]);
+        assert_eq!(rows, [
+            "fixture.dex,0,Fixture.java", "fixture.dex,1,Lcom/example/Fixture;", "fixture.dex,2,Ljava/lang/Object;",
+            "fixture.dex,3,V", "fixture.dex,4,greet", "fixture.dex,5,hello", "fixture.dex,6,run",
+        ]);
+        let (columns, rows) = read(&dir.join("methods.parquet"));
+        assert_eq!(columns, ["file", "index", "class", "name", "signature", "defined", "access_flags", "insns_size"]);
+        assert_eq!(rows, [
+            "fixture.dex,0,Lcom/example/Fixture;,greet,()V,true,9,3",
+            "fixture.dex,1,Lcom/example/Fixture;,run,()V,true,1,1",
+        ]);
+        let (columns, rows) = read(&dir.join("references.parquet"));
+        assert_eq!(columns, ["file", "method_index", "pc", "kind", "target", "target_name"]);
+        assert_eq!(rows, ["fixture.dex,0,0,string,5,hello"]);
+
+        // Methods that are only referenced have no flags and size
+        let mut editor = Fixture::new().editor();
+        editor.add_method_id("Ljava/lang/Object;", "<init>", "()V").unwrap();
+        export(&fixture::rewritten(&editor), "fixture.dex", &dir).unwrap();
+        assert_eq!(read(&dir.join("methods.parquet")).1, [
+            "fixture.dex,0,Lcom/example/Fixture;,run,()V,true,1,1",
+            "fixture.dex,1,Ljava/lang/Object;,<init>,()V,false,,",
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use prost::Message;
+
+use crate::atomic;
+use crate::dex_file::{self, DexFile, NO_INDEX};
+use crate::raw_dex::{CodeItem, EncodedField, EncodedMethod};
+
+/*
+Protobuf serialization of the model. The messages mirror proto/dex.proto (field numbers must be kept in
+sync), so consumers in other languages generate their bindings from that file.
+ */
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DexFileMessage {
+    #[prost(string, tag = "1")]
+    pub file: String,
+    #[prost(uint32, tag = "2")]
+    pub version: u32,
+    #[prost(uint32, tag = "3")]
+    pub checksum: u32,
+    #[prost(string, repeated, tag = "4")]
+    pub strings: Vec<String>,
+    #[prost(string, repeated, tag = "5")]
+    pub types: Vec<String>,
+    #[prost(message, repeated, tag = "6")]
+    pub protos: Vec<Proto>,
+    #[prost(message, repeated, tag = "7")]
+    pub fields: Vec<FieldId>,
+    #[prost(message, repeated, tag = "8")]
+    pub methods: Vec<MethodId>,
+    #[prost(message, repeated, tag = "9")]
+    pub classes: Vec<ClassDef>,
+    #[prost(message, repeated, tag = "10")]
+    pub references: Vec<Reference>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Proto {
+    #[prost(uint32, tag = "1")]
+    pub shorty_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub return_type_idx: u32,
+    #[prost(uint32, repeated, tag = "3")]
+    pub parameter_type_idxs: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FieldId {
+    #[prost(uint32, tag = "1")]
+    pub class_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub type_idx: u32,
+    #[prost(uint32, tag = "3")]
+    pub name_idx: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MethodId {
+    #[prost(uint32, tag = "1")]
+    pub class_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub proto_idx: u32,
+    #[prost(uint32, tag = "3")]
+    pub name_idx: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClassDef {
+    #[prost(uint32, tag = "1")]
+    pub class_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub access_flags: u32,
+    #[prost(uint32, optional, tag = "3")]
+    pub superclass_idx: Option<u32>,
+    #[prost(uint32, repeated, tag = "4")]
+    pub interface_idxs: Vec<u32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub source_file_idx: Option<u32>,
+    #[prost(message, repeated, tag = "6")]
+    pub static_fields: Vec<Field>,
+    #[prost(message, repeated, tag = "7")]
+    pub instance_fields: Vec<Field>,
+    #[prost(message, repeated, tag = "8")]
+    pub direct_methods: Vec<Method>,
+    #[prost(message, repeated, tag = "9")]
+    pub virtual_methods: Vec<Method>,
+}
+
+/// EncodedField in dex.proto
+#[derive(Clone, PartialEq, Message)]
+pub struct Field {
+    #[prost(uint32, tag = "1")]
+    pub field_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub access_flags: u32,
+}
+
+/// EncodedMethod in dex.proto
+#[derive(Clone, PartialEq, Message)]
+pub struct Method {
+    #[prost(uint32, tag = "1")]
+    pub method_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub access_flags: u32,
+    #[prost(message, optional, tag = "3")]
+    pub code: Option<Code>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Code {
+    #[prost(uint32, tag = "1")]
+    pub registers_size: u32,
+    #[prost(uint32, tag = "2")]
+    pub ins_size: u32,
+    #[prost(uint32, tag = "3")]
+    pub outs_size: u32,
+    #[prost(uint32, repeated, tag = "4")]
+    pub insns: Vec<u32>,
+    #[prost(message, repeated, tag = "5")]
+    pub tries: Vec<TryBlock>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TryBlock {
+    #[prost(uint32, tag = "1")]
+    pub start_addr: u32,
+    #[prost(uint32, tag = "2")]
+    pub insn_count: u32,
+    #[prost(message, repeated, tag = "3")]
+    pub handlers: Vec<CatchHandler>,
+    #[prost(uint32, optional, tag = "4")]
+    pub catch_all_addr: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CatchHandler {
+    #[prost(uint32, tag = "1")]
+    pub type_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub addr: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Reference {
+    #[prost(uint32, tag = "1")]
+    pub method_idx: u32,
+    #[prost(uint32, tag = "2")]
+    pub pc: u32,
+    #[prost(string, tag = "3")]
+    pub kind: String,
+    #[prost(uint32, tag = "4")]
+    pub target: u32,
+}
+
+fn optional_index(idx: u32) -> Option<u32> {
+    Some(idx).filter(|it| *it != NO_INDEX)
+}
+
+fn fields(fields: &[EncodedField]) -> Vec<Field> {
+    fields.iter().zip(dex_file::field_indices(fields))
+        .map(|(field, field_idx)| Field { field_idx, access_flags: field.access_flags as u32 })
+        .collect()
+}
+
+fn code(code: &CodeItem) -> Code {
+    let tries = code.tries.iter().map(|try_item| {
+        let handler = try_item.resolve_handler(code);
+        TryBlock {
+            start_addr: try_item.start_addr,
+            insn_count: try_item.insn_count as u32,
+            handlers: handler.map(|it| it.handlers.iter()
+                .map(|pair| CatchHandler { type_idx: pair.type_idx as u32, addr: pair.addr as u32 })
+                .collect()).unwrap_or_default(),
+            catch_all_addr: handler.and_then(|it| it.catch_all_addr).map(|it| it as u32),
+        }
+    }).collect();
+    Code {
+        registers_size: code.registers_size as u32,
+        ins_size: code.ins_size as u32,
+        outs_size: code.outs_size as u32,
+        insns: code.insns.iter().map(|it| *it as u32).collect(),
+        tries,
+    }
+}
+
+fn methods(dex: &DexFile, methods: &[EncodedMethod]) -> Vec<Method> {
+    methods.iter().zip(dex_file::method_indices(methods))
+        .map(|(method, method_idx)| Method {
+            method_idx,
+            access_flags: method.access_flags as u32,
+            code: dex.code_item(method.code_off).map(code),
+        })
+        .collect()
+}
+
+/// Builds the message of the whole dex file
+pub fn to_message(dex: &DexFile, file_name: &str) -> DexFileMessage {
+    let classes = dex.class_defs.iter().zip(&dex.class_data).map(|(class_def, class_data)| {
+        let mut class = ClassDef {
+            class_idx: class_def.class_idx,
+            access_flags: class_def.access_flags,
+            superclass_idx: optional_index(class_def.superclass_idx),
+            interface_idxs: dex.type_list(class_def.interfaces_off).iter().map(|it| *it as u32).collect(),
+            source_file_idx: optional_index(class_def.source_file_idx),
+            ..ClassDef::default()
+        };
+        if let Some(class_data) = class_data {
+            class.static_fields = fields(&class_data.static_fields);
+            class.instance_fields = fields(&class_data.instance_fields);
+            class.direct_methods = methods(dex, &class_data.direct_methods);
+            class.virtual_methods = methods(dex, &class_data.virtual_methods);
+        }
+        class
+    }).collect();
+
+    DexFileMessage {
+        file: file_name.to_string(),
+        version: dex.version() as u32,
+        checksum: dex.header.checksum,
+        strings: dex.strings.clone(),
+        types: (0..dex.type_ids.len() as u32).map(|it| dex.type_descriptor(it).to_string()).collect(),
+        protos: dex.proto_ids.iter().map(|it| Proto {
+            shorty_idx: it.shorty_idx,
+            return_type_idx: it.return_type_idx,
+            parameter_type_idxs: dex.type_list(it.parameters_off).iter().map(|it| *it as u32).collect(),
+        }).collect(),
+        fields: dex.field_ids.iter().map(|it| FieldId {
+            class_idx: it.class_idx as u32,
+            type_idx: it.type_idx as u32,
+            name_idx: it.name_idx,
+        }).collect(),
+        methods: dex.method_ids.iter().map(|it| MethodId {
+            class_idx: it.class_idx as u32,
+            proto_idx: it.proto_idx as u32,
+            name_idx: it.name_idx,
+        }).collect(),
+        classes,
+        references: super::references(dex).into_iter().map(|it| Reference {
+            method_idx: it.method_idx,
+            pc: it.pc as u32,
+            kind: it.kind.to_string(),
+            target: it.target,
+        }).collect(),
+    }
+}
+
+/// Writes the DexFile message (see proto/dex.proto) to `path`
+pub fn export(dex: &DexFile, file_name: &str, path: &Path) -> std::io::Result<()> {
+    atomic::write(path, &to_message(dex, file_name).encode_to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn message() {
+        let dex = Fixture::greeting().parse();
+        let path = std::env::temp_dir().join(format!("dex_tool-protobuf-{}.pb", std::process::id()));
+        export(&dex, "fixture.dex", &path).unwrap();
+        let message = DexFileMessage::decode(std::fs::read(&path).unwrap().as_slice()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let code = |registers_size, ins_size, insns: &[u32]| Some(Code { registers_size, ins_size, outs_size: 0, insns: insns.to_vec(), tries: Vec::new() });
+        assert_eq!(message, DexFileMessage {
+            file: "fixture.dex".to_owned(),
+            version: 35,
+            checksum: dex.header.checksum,
+            strings: ["Fixture.java", "Lcom/example/Fixture;", "Ljava/lang/Object;", "V", "greet", "hello", "run"].map(String::from).to_vec(),
+            types: ["Lcom/example/Fixture;", "Ljava/lang/Object;", "V"].map(String::from).to_vec(),
+            protos: vec![Proto { shorty_idx: 3, return_type_idx: 2, parameter_type_idxs: Vec::new() }],
+            fields: Vec::new(),
+            methods: vec![MethodId { class_idx: 0, proto_idx: 0, name_idx: 4 }, MethodId { class_idx: 0, proto_idx: 0, name_idx: 6 }],
+            classes: vec![ClassDef {
+                class_idx: 0,
+                access_flags: 0x1,
+                superclass_idx: Some(1),
+                interface_idxs: Vec::new(),
+                source_file_idx: Some(0),
+                static_fields: Vec::new(),
+                instance_fields: Vec::new(),
+                direct_methods: vec![Method { method_idx: 0, access_flags: 0x9, code: code(1, 0, &[0x001a, 5, 0x000e]) }],
+                virtual_methods: vec![Method { method_idx: 1, access_flags: 0x1, code: code(1, 1, &[0x000e]) }],
+            }],
+            references: vec![Reference { method_idx: 0, pc: 0, kind: "string".to_owned(), target: 5 }],
+        });
+    }
+}
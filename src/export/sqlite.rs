@@ -0,0 +1,250 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use rusqlite::{params, Connection, Transaction};
+
+use crate::atomic;
+use crate::dex_file::{self, DexFile, NO_INDEX};
+use crate::instructions::Instructions;
+
+const SCHEMA: &str = "
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT);
+CREATE TABLE strings (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE types (id INTEGER PRIMARY KEY, descriptor TEXT NOT NULL);
+CREATE TABLE classes (
+    id INTEGER PRIMARY KEY,
+    type_id INTEGER NOT NULL REFERENCES types(id),
+    descriptor TEXT NOT NULL,
+    access_flags INTEGER NOT NULL,
+    superclass TEXT,
+    source_file TEXT
+);
+CREATE TABLE fields (
+    id INTEGER PRIMARY KEY,
+    class TEXT NOT NULL,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    class_id INTEGER REFERENCES classes(id),
+    access_flags INTEGER
+);
+CREATE TABLE methods (
+    id INTEGER PRIMARY KEY,
+    class TEXT NOT NULL,
+    name TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    class_id INTEGER REFERENCES classes(id),
+    access_flags INTEGER,
+    code_off INTEGER,
+    registers INTEGER,
+    insns_size INTEGER
+);
+CREATE TABLE instructions (
+    method_id INTEGER NOT NULL REFERENCES methods(id),
+    pc INTEGER NOT NULL,
+    opcode INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    PRIMARY KEY (method_id, pc)
+);
+CREATE TABLE xrefs (
+    method_id INTEGER NOT NULL REFERENCES methods(id),
+    pc INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    target_id INTEGER NOT NULL
+);
+CREATE INDEX xrefs_target ON xrefs (kind, target_id);
+";
+
+#[derive(Debug)]
+pub enum ExportError {
+    /// Failure of SQLite while writing the database
+    Sqlite(rusqlite::Error),
+    /// Failure to remove a stale temporary file or to replace the file at the path
+    Io(io::Error),
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Sqlite(err) => Some(err),
+            ExportError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Sqlite(err) => fmt::Display::fmt(err, f),
+            ExportError::Io(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ExportError {
+    fn from(err: rusqlite::Error) -> Self {
+        ExportError::Sqlite(err)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+/// Creates a new database at `path` containing the strings, types, classes, fields, methods,
+/// instructions and cross references of the dex file. An existing file is replaced once the database
+/// is complete.
+pub fn export(dex: &DexFile, file_name: &str, path: &Path) -> Result<(), ExportError> {
+    let temp = atomic::temp_path(path);
+    if temp.exists() {
+        std::fs::remove_file(&temp)?;
+    }
+    let result = write_database(dex, file_name, &temp).map_err(ExportError::from)
+        .and_then(|()| Ok(std::fs::rename(&temp, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_database(dex: &DexFile, file_name: &str, path: &Path) -> rusqlite::Result<()> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let tx = connection.transaction()?;
+    insert_all(dex, file_name, &tx)?;
+    tx.commit()?;
+    connection.close().map_err(|(_, err)| err)
+}
+
+fn insert_all(dex: &DexFile, file_name: &str, tx: &Transaction) -> rusqlite::Result<()> {
+    let mut stmt = tx.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")?;
+    stmt.execute(params!["file", file_name])?;
+    stmt.execute(params!["version", dex.version().to_string()])?;
+    stmt.execute(params!["checksum", format!("{:08x}", dex.header.checksum)])?;
+
+    let mut stmt = tx.prepare("INSERT INTO strings (id, value) VALUES (?1, ?2)")?;
+    for (id, value) in dex.strings.iter().enumerate() {
+        stmt.execute(params![id, value])?;
+    }
+
+    let mut stmt = tx.prepare("INSERT INTO types (id, descriptor) VALUES (?1, ?2)")?;
+    for id in 0..dex.type_ids.len() {
+        stmt.execute(params![id, dex.type_descriptor(id as u32)])?;
+    }
+
+    let mut stmt = tx.prepare("INSERT INTO classes (id, type_id, descriptor, access_flags, superclass, source_file) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for (id, class_def) in dex.class_defs.iter().enumerate() {
+        let superclass = Some(class_def.superclass_idx).filter(|it| *it != NO_INDEX).map(|it| dex.type_descriptor(it));
+        let source_file = Some(class_def.source_file_idx).filter(|it| *it != NO_INDEX).map(|it| dex.string(it));
+        stmt.execute(params![id, class_def.class_idx, dex.type_descriptor(class_def.class_idx),
+                             class_def.access_flags, superclass, source_file])?;
+    }
+
+    // Definitions of fields and methods, keyed by their index
+    let mut field_defs = vec![None; dex.field_ids.len()];
+    let mut method_defs = vec![None; dex.method_ids.len()];
+    for (class_id, class_data) in dex.class_data.iter().enumerate() {
+        let class_data = match class_data {
+            Some(class_data) => class_data,
+            None => continue,
+        };
+        for fields in [&class_data.static_fields, &class_data.instance_fields] {
+            for (field, idx) in fields.iter().zip(dex_file::field_indices(fields)) {
+                if let Some(def) = field_defs.get_mut(idx as usize) {
+                    *def = Some((class_id, field.access_flags));
+                }
+            }
+        }
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            for (method, idx) in methods.iter().zip(dex_file::method_indices(methods)) {
+                if let Some(def) = method_defs.get_mut(idx as usize) {
+                    *def = Some((class_id, method.access_flags, method.code_off));
+                }
+            }
+        }
+    }
+
+    let mut stmt = tx.prepare("INSERT INTO fields (id, class, name, type, class_id, access_flags) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for (id, def) in field_defs.iter().enumerate() {
+        let idx = id as u32;
+        stmt.execute(params![id, dex.field_class(idx), dex.field_name(idx), dex.field_type(idx),
+                             def.map(|it| it.0), def.map(|it| it.1 as i64)])?;
+    }
+
+    let mut stmt = tx.prepare("INSERT INTO methods (id, class, name, signature, class_id, access_flags, code_off, registers, insns_size) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?;
+    let mut insn_stmt = tx.prepare("INSERT INTO instructions (method_id, pc, opcode, name, size) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for (id, def) in method_defs.iter().enumerate() {
+        let idx = id as u32;
+        let code = def.and_then(|it| dex.code_item(it.2));
+        stmt.execute(params![id, dex.method_class(idx), dex.method_name(idx), dex.method_signature(idx),
+                             def.map(|it| it.0), def.map(|it| it.1 as i64), code.and(def.map(|it| it.2 as i64)),
+                             code.map(|it| it.registers_size), code.map(|it| it.insns.len())])?;
+
+        let code = match code {
+            Some(code) => code,
+            None => continue,
+        };
+        for (pc, insn) in Instructions::new(&code.insns).map_while(Result::ok) {
+            if insn.payload.is_none() {
+                insn_stmt.execute(params![id, pc, insn.opcode, insn.name(), insn.size])?;
+            }
+        }
+    }
+
+    let mut stmt = tx.prepare("INSERT INTO xrefs (method_id, pc, kind, target_id) VALUES (?1, ?2, ?3, ?4)")?;
+    for reference in super::references(dex) {
+        stmt.execute(params![reference.method_idx, reference.pc, reference.kind, reference.target])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// Rows of a query with the columns joined by commas, NULL as an empty column
+    fn rows(connection: &Connection, query: &str) -> Vec<String> {
+        let mut stmt = connection.prepare(query).unwrap();
+        let columns = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..columns).map(|i| Ok(match row.get_ref(i)? {
+                rusqlite::types::ValueRef::Null => String::new(),
+                rusqlite::types::ValueRef::Integer(it) => it.to_string(),
+                rusqlite::types::ValueRef::Text(it) => String::from_utf8_lossy(it).into_owned(),
+                value => format!("{:?}", value),
+            })).collect::<rusqlite::Result<Vec<_>>>().map(|it| it.join(","))
+        }).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn tables() {
+        let dex = Fixture::greeting().parse();
+
+        let path = std::env::temp_dir().join(format!("dex_tool-sqlite-{}.db", std::process::id()));
+        std::fs::write(&path, b"replaced").unwrap();
+        export(&dex, "fixture.dex", &path).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        assert_eq!(rows(&connection, "SELECT * FROM meta"), [
+            "file,fixture.dex".to_owned(), "version,35".to_owned(), format!("checksum,{:08x}", dex.header.checksum),
+        ]);
+        assert_eq!(rows(&connection, "SELECT * FROM strings"), [
+            "0,Fixture.java", "1,Lcom/example/Fixture;", "2,Ljava/lang/Object;", "3,V", "4,greet", "5,hello", "6,run",
+        ]);
+        assert_eq!(rows(&connection, "SELECT * FROM types"), ["0,Lcom/example/Fixture;", "1,Ljava/lang/Object;", "2,V"]);
+        assert_eq!(rows(&connection, "SELECT * FROM classes"), ["0,0,Lcom/example/Fixture;,1,Ljava/lang/Object;,Fixture.java"]);
+        assert!(rows(&connection, "SELECT * FROM fields").is_empty());
+        let code_off = |name| dex.classes().flat_map(|it| it.methods()).find(|it| it.name() == name).unwrap().encoded.code_off;
+        assert_eq!(rows(&connection, "SELECT * FROM methods"), [
+            format!("0,Lcom/example/Fixture;,greet,()V,0,9,{},1,3", code_off("greet")),
+            format!("1,Lcom/example/Fixture;,run,()V,0,1,{},1,1", code_off("run")),
+        ]);
+        assert_eq!(rows(&connection, "SELECT * FROM instructions"), ["0,0,26,const-string,2", "0,2,14,return-void,1", "1,0,14,return-void,1"]);
+        assert_eq!(rows(&connection, "SELECT * FROM xrefs"), ["0,0,string,5"]);
+        connection.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use dex_tool::atomic;
+use dex_tool::dex_file::DexFile;
+use dex_tool::raw_dex::CodeItem;
+
+/*
+Extraction of the code item of a single method for external tools (emulators, differs). The
+instructions are written as raw little endian code units, the remaining fields of the code item are
+written to a JSON sidecar next to them.
+ */
+
+/// Path of the JSON sidecar of the instructions written to `out`, e.g. method.bin.json
+pub fn sidecar_path(out: &Path) -> PathBuf {
+    let mut path = out.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+fn sidecar(dex: &DexFile, method: &str, method_idx: u32, code_off: u64, code: &CodeItem) -> Value {
+    let tries: Vec<_> = code.tries.iter().zip(dex.catches(code)).map(|(try_item, catch)| {
+        let handlers: Vec<_> = catch.handlers.iter()
+            .map(|it| json!({ "exception": it.exception, "address": it.address }))
+            .collect();
+        json!({
+            "start_address": catch.start_address,
+            "end_address": catch.end_address,
+            "handler_off": try_item.handler_off,
+            "handlers": handlers,
+        })
+    }).collect();
+    json!({
+        "method": method,
+        "method_idx": method_idx,
+        "code_off": code_off,
+        "registers_size": code.registers_size,
+        "ins_size": code.ins_size,
+        "outs_size": code.outs_size,
+        "insns_size": code.insns.len(),
+        "debug_info_off": code.debug_info_off,
+        "tries": tries,
+    })
+}
+
+/// Writes the instructions of the method `Lcls;->name(sig)` to `out` and its sidecar next to it
+pub fn extract_method(dex: &DexFile, method: &str, out: &Path) -> io::Result<()> {
+    let found = method.split_once("->").and_then(|(class, rest)| {
+        let (name, signature) = rest.split_at(rest.find('(')?);
+        dex.find_method(class, name, signature)
+    });
+    let found = match found {
+        Some(found) => found,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("Method {} is not defined in the dex file", method))),
+    };
+    let code = match dex.code_item(found.encoded.code_off) {
+        Some(code) => code,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Method {} has no code", method))),
+    };
+
+    let insns: Vec<u8> = code.insns.iter().flat_map(|it| it.to_le_bytes()).collect();
+    atomic::write(out, &insns)?;
+    let sidecar = sidecar(dex, method, found.method_idx, found.encoded.code_off, code);
+    atomic::write(&sidecar_path(out), &serde_json::to_vec_pretty(&sidecar)?)
+}
@@ -0,0 +1,149 @@
+use std::fmt::Display;
+use std::panic::{self, PanicHookInfo};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde_json::json;
+
+/*
+Exit codes and error reports, so scripts can tell failures apart without parsing stderr. Failures the
+user can act on (unreadable input, malformed dex files, ...) are returned as Failure and end the command
+with their exit code, a panic is a bug and ends it with the exit code of panics. The codes are part of the
+interface like the columns of the listings, new ones are only ever added. With `--error-format json`
+each failure is reported as one line of JSON on stderr:
+{"error":{"kind":"parse","code":4,"message":"..."}}
+ */
+
+/// Exit codes of the dex_tool binary, 0 on success
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exit {
+    /// The verify command found problems in the bytecode
+    Verification = 1,
+    /// Invalid arguments, the exit code of clap
+    Usage = 2,
+    /// The input file could not be read
+    Input = 3,
+    /// The input is not a dex file or malformed
+    Parse = 4,
+    UnsupportedVersion = 5,
+    /// A query matched nothing, e.g. xrefs --target without references or extract-method of an
+    /// undefined method
+    Empty = 6,
+    /// The output could not be written
+    Output = 7,
+    /// A configuration file could not be read or is invalid
+    Config = 8,
+    /// A bug, the exit code of panics
+    Internal = 101,
+}
+
+impl Exit {
+    fn kind(self) -> &'static str {
+        match self {
+            Exit::Verification => "verification",
+            Exit::Usage => "usage",
+            Exit::Input => "input",
+            Exit::Parse => "parse",
+            Exit::UnsupportedVersion => "unsupported_version",
+            Exit::Empty => "empty",
+            Exit::Output => "output",
+            Exit::Config => "config",
+            Exit::Internal => "internal",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Messages for humans
+    Text,
+    /// One JSON object per failure with the kind, exit code and message
+    Json,
+}
+
+/// A failure the user can act on, returned by the commands and reported by main
+#[derive(Debug)]
+pub struct Failure {
+    pub exit: Exit,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(exit: Exit, message: impl Display) -> Failure {
+        Failure { exit, message: message.to_string() }
+    }
+
+    /// Reports the failure on stderr in the format selected by install
+    pub fn report(&self) {
+        match FORMAT.get() {
+            Some(ErrorFormat::Json) => eprintln!("{}", error_json(self.exit, &self.message)),
+            _ => eprintln!("error: {}", self.message),
+        }
+    }
+}
+
+static FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+fn error_json(exit: Exit, message: &str) -> String {
+    json!({ "error": { "kind": exit.kind(), "code": exit as i32, "message": message } }).to_string()
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    match info.location() {
+        Some(location) => format!("{} at {}", message, location),
+        None => message.to_string(),
+    }
+}
+
+/// Selects the format of the reports, panics are reported like the default hook does (or as internal
+/// error in JSON)
+pub fn install(format: ErrorFormat) {
+    FORMAT.get_or_init(|| format);
+    if format == ErrorFormat::Json {
+        panic::set_hook(Box::new(|info| eprintln!("{}", error_json(Exit::Internal, &panic_message(info)))));
+    }
+}
+
+pub trait OrFail<T> {
+    /// The value, or the failure with `exit` and the error after `context`
+    fn or_fail(self, exit: Exit, context: &str) -> Result<T, Failure>;
+}
+
+impl<T, E: Display> OrFail<T> for Result<T, E> {
+    fn or_fail(self, exit: Exit, context: &str) -> Result<T, Failure> {
+        self.map_err(|err| Failure::new(exit, format!("{}: {}", context, err)))
+    }
+}
+
+/// Whether the JSON error format was selected, also before (or if) the arguments are parsed
+fn json_format() -> bool {
+    if let Some(format) = FORMAT.get() {
+        return *format == ErrorFormat::Json;
+    }
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().any(|it| it == "--error-format=json") || args.windows(2).any(|it| it[0] == "--error-format" && it[1] == "json")
+}
+
+/// Reports the failure and exits right away, for failures before the arguments are parsed
+pub fn exit(exit: Exit, message: impl Display) -> ! {
+    if json_format() {
+        eprintln!("{}", error_json(exit, &message.to_string()));
+    } else {
+        eprintln!("error: {}", message);
+    }
+    std::process::exit(exit as i32)
+}
+
+/// Exits with the error of clap, in JSON if selected (but for the output of --help and --version)
+pub fn usage_error(error: clap::Error) -> ! {
+    if error.use_stderr() && json_format() {
+        let rendered = error.to_string();
+        let message = rendered.lines().next().unwrap_or_default();
+        exit(Exit::Usage, message.strip_prefix("error: ").unwrap_or(message));
+    }
+    error.exit()
+}
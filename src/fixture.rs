@@ -0,0 +1,448 @@
+use crate::prelude::*;
+
+use crate::dex_file::{DexFile, ParseOptions, Strictness};
+use crate::editor::DexEditor;
+use crate::instructions::Instructions;
+use crate::m_utf8;
+use crate::raw_dex::{write_uleb128, DexHeader};
+
+/*
+Builder of tiny valid dex files for tests, benchmarks and fuzzing seeds, so they do not depend on
+dex files outside the repository. A fixture has one class with a few methods and strings, the id
+tables are sorted like by dx/d8, and the checksum (and with the index feature the signature) is set.
+ */
+
+const ACC_PUBLIC: u32 = 0x1;
+const ACC_PRIVATE: u32 = 0x2;
+const ACC_STATIC: u32 = 0x8;
+const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+const HEADER_SIZE: u32 = 0x70;
+const NO_INDEX: u32 = 0xffffffff;
+
+/// A method of the fixture class
+#[derive(Debug, Clone)]
+pub struct FixtureMethod {
+    pub name: String,
+    pub return_type: String,
+    pub parameters: Vec<String>,
+    pub access_flags: u32,
+    pub registers_size: u16,
+    /// None for abstract and native methods
+    pub insns: Option<Vec<u16>>,
+}
+
+impl FixtureMethod {
+    /// A public method, with as many registers as its parameters need
+    pub fn new(name: &str, return_type: &str, parameters: &[&str], insns: Vec<u16>) -> FixtureMethod {
+        let mut method = FixtureMethod {
+            name: name.to_owned(),
+            return_type: return_type.to_owned(),
+            parameters: parameters.iter().map(|it| (*it).to_owned()).collect(),
+            access_flags: ACC_PUBLIC,
+            registers_size: 0,
+            insns: Some(insns),
+        };
+        method.registers_size = method.ins_size();
+        method
+    }
+
+    pub fn access_flags(mut self, access_flags: u32) -> Self {
+        self.access_flags = access_flags;
+        self.registers_size = self.registers_size.max(self.ins_size());
+        self
+    }
+
+    pub fn registers_size(mut self, registers_size: u16) -> Self {
+        self.registers_size = registers_size;
+        self
+    }
+
+    /// Registers of the parameters (and `this`), long and double take two
+    fn ins_size(&self) -> u16 {
+        let this = if self.access_flags & ACC_STATIC == 0 { 1 } else { 0 };
+        this + self.parameters.iter().map(|it| if it == "J" || it == "D" { 2 } else { 1 }).sum::<u16>()
+    }
+
+    /// Most argument registers of the invokes
+    fn outs_size(&self) -> u16 {
+        let insns = self.insns.as_deref().unwrap_or_default();
+        Instructions::new(insns).map_while(Result::ok)
+            .filter(|(_, insn)| insn.name().starts_with("invoke-"))
+            .map(|(_, insn)| insn.a as u16)
+            .max().unwrap_or(0)
+    }
+
+    fn is_direct(&self) -> bool {
+        self.access_flags & (ACC_STATIC | ACC_PRIVATE | ACC_CONSTRUCTOR) != 0
+    }
+
+    fn shorty(&self) -> String {
+        let shorty = |descriptor: &str| if descriptor.starts_with('L') || descriptor.starts_with('[') { 'L' } else { descriptor.chars().next().unwrap_or('V') };
+        core::iter::once(shorty(&self.return_type)).chain(self.parameters.iter().map(|it| shorty(it))).collect()
+    }
+}
+
+/// A dex file with a single class, e.g.
+/// `Fixture::new().string("hello").method(FixtureMethod::new("run", "V", &[], vec![0x000e])).build()`
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub class: String,
+    pub superclass: String,
+    pub source_file: Option<String>,
+    pub strings: Vec<String>,
+    pub methods: Vec<FixtureMethod>,
+}
+
+impl Default for Fixture {
+    /// The class `Lcom/example/Fixture;` with a `run()V` method that returns
+    fn default() -> Self {
+        Fixture {
+            class: "Lcom/example/Fixture;".to_owned(),
+            superclass: "Ljava/lang/Object;".to_owned(),
+            source_file: Some("Fixture.java".to_owned()),
+            strings: Vec::new(),
+            methods: vec![FixtureMethod::new("run", "V", &[], vec![0x000e])],
+        }
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend(value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_le_bytes());
+}
+
+fn align(out: &mut Vec<u8>, alignment: usize) {
+    while !out.len().is_multiple_of(alignment) {
+        out.push(0);
+    }
+}
+
+/// Position of `value` in a sorted list of it
+fn index_of<T: Ord>(sorted: &[T], value: &T) -> u32 {
+    sorted.binary_search(value).expect("Collected before") as u32
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        Fixture::default()
+    }
+
+    /// A fixture without methods
+    pub fn empty() -> Fixture {
+        Fixture { methods: Vec::new(), ..Fixture::default() }
+    }
+
+    pub fn class(mut self, descriptor: &str) -> Self {
+        self.class = descriptor.to_owned();
+        self
+    }
+
+    pub fn superclass(mut self, descriptor: &str) -> Self {
+        self.superclass = descriptor.to_owned();
+        self
+    }
+
+    pub fn source_file(mut self, source_file: Option<&str>) -> Self {
+        self.source_file = source_file.map(|it| it.to_owned());
+        self
+    }
+
+    /// Adds a string to the string table, e.g. for a const-string
+    pub fn string(mut self, value: &str) -> Self {
+        self.strings.push(value.to_owned());
+        self
+    }
+
+    pub fn method(mut self, method: FixtureMethod) -> Self {
+        self.methods.push(method);
+        self
+    }
+
+    fn all_types(&self) -> Vec<String> {
+        let mut types = vec![self.class.clone(), self.superclass.clone()];
+        for method in &self.methods {
+            types.push(method.return_type.clone());
+            types.extend(method.parameters.iter().cloned());
+        }
+        types
+    }
+
+    /// Strings of the string table, sorted by their UTF-16 code units
+    fn sorted_strings(&self) -> Vec<String> {
+        let mut strings = self.all_types();
+        strings.extend(self.strings.iter().cloned());
+        strings.extend(self.source_file.iter().cloned());
+        for method in &self.methods {
+            strings.push(method.name.clone());
+            strings.push(method.shorty());
+        }
+        strings.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+        strings.dedup();
+        strings
+    }
+
+    /// Index of a string in the built file, to reference it from instructions
+    pub fn string_idx(&self, value: &str) -> Option<u32> {
+        self.sorted_strings().iter().position(|it| it == value).map(|it| it as u32)
+    }
+
+    /// Index of a type in the built file
+    pub fn type_idx(&self, descriptor: &str) -> Option<u32> {
+        let strings = self.sorted_strings();
+        let mut types: Vec<u32> = self.all_types().iter().map(|it| index_of_str(&strings, it)).collect();
+        types.sort_unstable();
+        types.dedup();
+        let string_idx = self.string_idx(descriptor)?;
+        types.binary_search(&string_idx).ok().map(|it| it as u32)
+    }
+
+    /// Builds the dex file
+    pub fn build(&self) -> Vec<u8> {
+        let strings = self.sorted_strings();
+        let string_idx = |value: &str| index_of_str(&strings, value);
+        let mut types: Vec<u32> = self.all_types().iter().map(|it| string_idx(it)).collect();
+        types.sort_unstable();
+        types.dedup();
+        let type_idx = |descriptor: &str| index_of(&types, &string_idx(descriptor));
+        // (return type, parameter types, shorty)
+        let mut protos: Vec<(u32, Vec<u16>, u32)> = self.methods.iter()
+            .map(|it| (type_idx(&it.return_type), it.parameters.iter().map(|it| type_idx(it) as u16).collect(), string_idx(&it.shorty())))
+            .collect();
+        protos.sort_unstable();
+        protos.dedup();
+        let proto_idx = |method: &FixtureMethod| {
+            let parameters: Vec<u16> = method.parameters.iter().map(|it| type_idx(it) as u16).collect();
+            protos.iter().position(|it| it.0 == type_idx(&method.return_type) && it.1 == parameters).unwrap() as u32
+        };
+        // (name, proto) of the methods of the single class, with the index of the FixtureMethod
+        let mut methods: Vec<(u32, u32, usize)> = self.methods.iter().enumerate()
+            .map(|(i, it)| (string_idx(&it.name), proto_idx(it), i))
+            .collect();
+        methods.sort_unstable();
+
+        let string_ids_off = HEADER_SIZE;
+        let type_ids_off = string_ids_off + 4 * strings.len() as u32;
+        let proto_ids_off = type_ids_off + 4 * types.len() as u32;
+        let method_ids_off = proto_ids_off + 12 * protos.len() as u32;
+        let class_defs_off = method_ids_off + 8 * methods.len() as u32;
+        let data_off = class_defs_off + 32;
+
+        let mut out = vec![0; data_off as usize];
+        // (item type, size, offset) of the sections of the data section
+        let mut sections = Vec::new();
+        let mut section = |item_type: u16, size: usize, start: usize| {
+            if size > 0 {
+                sections.push((item_type, size as u32, start as u32));
+            }
+        };
+
+        // Code items by method
+        align(&mut out, 4);
+        let code_start = out.len();
+        let mut code_offs = vec![0u32; self.methods.len()];
+        for (i, method) in self.methods.iter().enumerate() {
+            if let Some(insns) = &method.insns {
+                align(&mut out, 4);
+                code_offs[i] = out.len() as u32;
+                put_u16(&mut out, method.registers_size);
+                put_u16(&mut out, method.ins_size());
+                put_u16(&mut out, method.outs_size());
+                put_u16(&mut out, 0); // tries_size
+                put_u32(&mut out, 0); // debug_info_off
+                put_u32(&mut out, insns.len() as u32);
+                insns.iter().for_each(|it| put_u16(&mut out, *it));
+            }
+        }
+        section(0x2001, code_offs.iter().filter(|it| **it != 0).count(), code_start);
+
+        // Type lists of the parameters
+        align(&mut out, 4);
+        let type_lists_start = out.len();
+        let mut parameters_offs = vec![0u32; protos.len()];
+        for (i, (_, parameters, _)) in protos.iter().enumerate() {
+            if !parameters.is_empty() {
+                align(&mut out, 4);
+                parameters_offs[i] = out.len() as u32;
+                put_u32(&mut out, parameters.len() as u32);
+                parameters.iter().for_each(|it| put_u16(&mut out, *it));
+            }
+        }
+        section(0x1001, parameters_offs.iter().filter(|it| **it != 0).count(), type_lists_start);
+
+        let string_data_start = out.len();
+        let mut string_data_offs = Vec::with_capacity(strings.len());
+        for value in &strings {
+            string_data_offs.push(out.len() as u32);
+            let (units, bytes) = m_utf8::encode(value);
+            write_uleb128(&mut out, units);
+            out.extend(bytes);
+            out.push(0);
+        }
+        section(0x2002, strings.len(), string_data_start);
+
+        let class_data_off = out.len() as u32;
+        let (direct, virtual_): (Vec<_>, Vec<_>) = methods.iter().enumerate().partition(|(_, (_, _, i))| self.methods[*i].is_direct());
+        for value in [0, 0, direct.len() as u32, virtual_.len() as u32] {
+            write_uleb128(&mut out, value);
+        }
+        for list in [&direct, &virtual_] {
+            let mut previous = 0;
+            for (method_idx, (_, _, i)) in list {
+                write_uleb128(&mut out, *method_idx as u32 - previous);
+                previous = *method_idx as u32;
+                write_uleb128(&mut out, self.methods[*i].access_flags);
+                write_uleb128(&mut out, code_offs[*i]);
+            }
+        }
+        section(0x2000, 1, class_data_off as usize);
+
+        align(&mut out, 4);
+        let map_off = out.len() as u32;
+        let mut map = vec![(0x0000, 1, 0), (0x0001, strings.len() as u32, string_ids_off), (0x0002, types.len() as u32, type_ids_off)];
+        map.extend([(0x0003, protos.len() as u32, proto_ids_off), (0x0005, methods.len() as u32, method_ids_off), (0x0006, 1, class_defs_off)]);
+        map.retain(|it| it.1 > 0);
+        map.extend(sections);
+        map.push((0x1000, 1, map_off));
+        map.sort_by_key(|it| it.2);
+        put_u32(&mut out, map.len() as u32);
+        for (item_type, size, offset) in map {
+            put_u16(&mut out, item_type);
+            put_u16(&mut out, 0);
+            put_u32(&mut out, size);
+            put_u32(&mut out, offset);
+        }
+
+        // Id tables
+        let mut ids = Vec::new();
+        string_data_offs.iter().for_each(|it| put_u32(&mut ids, *it));
+        types.iter().for_each(|it| put_u32(&mut ids, *it));
+        for (i, (return_type, _, shorty)) in protos.iter().enumerate() {
+            put_u32(&mut ids, *shorty);
+            put_u32(&mut ids, *return_type);
+            put_u32(&mut ids, parameters_offs[i]);
+        }
+        let class = type_idx(&self.class);
+        for (name, proto, _) in &methods {
+            put_u16(&mut ids, class as u16);
+            put_u16(&mut ids, *proto as u16);
+            put_u32(&mut ids, *name);
+        }
+        put_u32(&mut ids, class);
+        put_u32(&mut ids, ACC_PUBLIC);
+        put_u32(&mut ids, type_idx(&self.superclass));
+        put_u32(&mut ids, 0); // interfaces_off
+        put_u32(&mut ids, self.source_file.as_ref().map_or(NO_INDEX, |it| string_idx(it)));
+        put_u32(&mut ids, 0); // annotations_off
+        put_u32(&mut ids, class_data_off);
+        put_u32(&mut ids, 0); // static_values_off
+        out[HEADER_SIZE as usize..data_off as usize].copy_from_slice(&ids);
+
+        let mut header = Vec::new();
+        header.extend(b"dex\n035\0");
+        put_u32(&mut header, 0); // checksum
+        header.extend([0; 20]); // signature
+        let file_size = out.len() as u32;
+        for value in [file_size, HEADER_SIZE, 0x12345678, 0, 0, map_off,
+                      strings.len() as u32, string_ids_off, types.len() as u32, type_ids_off, protos.len() as u32, proto_ids_off,
+                      0, 0, methods.len() as u32, method_ids_off, 1, class_defs_off, file_size - data_off, data_off] {
+            put_u32(&mut header, value);
+        }
+        out[..HEADER_SIZE as usize].copy_from_slice(&header);
+        #[cfg(feature = "index")]
+        {
+            let signature = sha1_smol::Sha1::from(&out[DexHeader::SIGNATURE_END..]).digest().bytes();
+            out[DexHeader::CHECKSUM_END..DexHeader::SIGNATURE_END].copy_from_slice(&signature);
+        }
+        let checksum = DexHeader::compute_checksum(&out);
+        out[8..DexHeader::CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// The default fixture with a static `greet()V` that loads the string "hello", for listings and
+    /// exports of references
+    pub fn greeting() -> Fixture {
+        let greet = |insns| FixtureMethod::new("greet", "V", &[], insns).access_flags(ACC_PUBLIC | ACC_STATIC).registers_size(1);
+        let fixture = Fixture::new().string("hello");
+        let hello = fixture.clone().method(greet(Vec::new())).string_idx("hello").expect("Added before");
+        // const-string v0, "hello"; return-void
+        fixture.method(greet(vec![0x001a, hello as u16, 0x000e]))
+    }
+
+    /// Builds the dex file and parses it strictly
+    pub fn parse(&self) -> DexFile {
+        parse_strict(&self.build())
+    }
+
+    /// Builds the dex file and opens it for editing
+    pub fn editor(&self) -> DexEditor {
+        DexEditor::from_bytes(&self.build()).expect("Fixtures are valid")
+    }
+}
+
+fn parse_strict(data: &[u8]) -> DexFile {
+    ParseOptions::new().strictness(Strictness::Strict).parse_bytes(data).expect("Fixtures are valid")
+}
+
+/// Writes the edited dex file and parses it strictly, to check the result of an edit
+pub fn rewritten(editor: &DexEditor) -> DexFile {
+    parse_strict(&editor.to_bytes().expect("Edited fixtures are written"))
+}
+
+/// Instructions of the method `name` of the classes of the dex file
+pub fn insns(dex: &DexFile, name: &str) -> Vec<u16> {
+    dex.classes().flat_map(|it| it.methods()).find(|it| dex.method_name(it.method_idx) == name)
+        .and_then(|it| dex.code_item(it.encoded.code_off))
+        .map(|it| it.insns.clone())
+        .expect("Method with code")
+}
+
+fn index_of_str(sorted: &[String], value: &str) -> u32 {
+    sorted.iter().position(|it| it == value).expect("Collected before") as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_fixture_parses() {
+        let dex = Fixture::new().parse();
+        assert_eq!(dex.class_defs.len(), 1);
+        let class = dex.classes().next().unwrap();
+        assert_eq!(class.descriptor(), "Lcom/example/Fixture;");
+        let methods = class.methods();
+        assert_eq!(methods.len(), 1);
+        assert_eq!(dex.method_name(methods[0].method_idx), "run");
+        assert_eq!(dex.method_signature(methods[0].method_idx), "()V");
+        assert_eq!(dex.code_item(methods[0].encoded.code_off).unwrap().insns, vec![0x000e]);
+    }
+
+    #[test]
+    fn strings_and_methods() {
+        let fixture = Fixture::empty()
+            .string("hello")
+            .string("caf\u{e9} \u{1F600}")
+            .method(FixtureMethod::new("<init>", "V", &[], vec![0x000e]).access_flags(ACC_PUBLIC | ACC_CONSTRUCTOR))
+            .method(FixtureMethod::new("add", "J", &["I", "J"], vec![0x0010]).access_flags(ACC_STATIC).registers_size(4));
+        let hello = fixture.string_idx("hello").unwrap();
+        let dex = DexFile::from_bytes(&fixture.build()).unwrap();
+        assert_eq!(dex.string(hello), "hello");
+        assert!(dex.strings.iter().any(|it| it == "caf\u{e9} \u{1F600}"));
+        let mut sorted = dex.strings.clone();
+        sorted.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+        assert_eq!(sorted, dex.strings);
+
+        let class = dex.classes().next().unwrap();
+        let signatures: Vec<String> = class.methods().iter()
+            .map(|it| format!("{}{}", dex.method_name(it.method_idx), dex.method_signature(it.method_idx)))
+            .collect();
+        assert_eq!(signatures, ["<init>()V", "add(IJ)J"]);
+        let add = dex.code_item(class.methods()[1].encoded.code_off).unwrap();
+        assert_eq!((add.registers_size, add.ins_size), (4, 3));
+        assert_eq!(fixture.type_idx("J").map(|it| dex.type_descriptor(it)), Some("J"));
+    }
+}
@@ -0,0 +1,103 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+use crate::class::{Class, Field, Method};
+use crate::dex_file::DexFile;
+
+/*
+Classes and members generated by compilers instead of written in the source: bridge and synthetic
+members (accessors, lambda bodies, outer instance fields), the classes of lambdas desugared by D8
+(`-$$Lambda$Foo$...` by older versions, `Foo$$ExternalSyntheticLambda0` by newer ones) and the switch map
+classes of switches over enums (`Foo$1` with `$SwitchMap$...` fields by javac, `Foo$WhenMappings` by
+kotlinc). The classes are recognized by their names, which obfuscators may have changed.
+ */
+
+const ACC_BRIDGE: u64 = 0x40;
+const ACC_SYNTHETIC: u64 = 0x1000;
+
+/// Kind of a generated class or member
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Generated {
+    /// Marked synthetic, or another synthetic class of D8 (e.g. `$$ExternalSyntheticOutline0`)
+    Synthetic,
+    /// A bridge method, forwarding to the method it overrides with a more specific signature
+    Bridge,
+    Lambda,
+    SwitchMap,
+}
+
+impl fmt::Display for Generated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Generated::Synthetic => "synthetic",
+            Generated::Bridge => "bridge",
+            Generated::Lambda => "lambda",
+            Generated::SwitchMap => "switch_map",
+        })
+    }
+}
+
+impl Class<'_> {
+    /// Whether the compiler generated the class, None for classes of the source
+    pub fn generated(&self) -> Option<Generated> {
+        let descriptor = self.descriptor();
+        if descriptor.contains("$$Lambda$") || descriptor.contains("$$ExternalSyntheticLambda") {
+            Some(Generated::Lambda)
+        } else if descriptor.ends_with("$WhenMappings;")
+            || self.fields().iter().any(|it| it.name().starts_with("$SwitchMap$")) {
+            Some(Generated::SwitchMap)
+        } else if u64::from(self.def().access_flags) & ACC_SYNTHETIC != 0 || descriptor.contains("$$ExternalSynthetic") {
+            Some(Generated::Synthetic)
+        } else {
+            None
+        }
+    }
+}
+
+impl Method<'_> {
+    /// Whether the compiler generated the method, generated classes may declare methods that are not
+    pub fn generated(&self) -> Option<Generated> {
+        if self.encoded.access_flags & ACC_BRIDGE != 0 {
+            Some(Generated::Bridge)
+        } else if self.encoded.access_flags & ACC_SYNTHETIC != 0 {
+            Some(Generated::Synthetic)
+        } else {
+            None
+        }
+    }
+}
+
+impl Field<'_> {
+    /// Whether the compiler generated the field (e.g. `this$0`, `$SwitchMap$...`)
+    pub fn generated(&self) -> Option<Generated> {
+        if self.encoded.access_flags & ACC_SYNTHETIC != 0 {
+            Some(Generated::Synthetic)
+        } else {
+            None
+        }
+    }
+}
+
+/// Indices of the generated classes and members of a dex file, see DexFile::generated_items
+#[derive(Debug, Default)]
+pub struct GeneratedItems {
+    pub class_defs: BTreeSet<usize>,
+    pub fields: BTreeSet<u32>,
+    pub methods: BTreeSet<u32>,
+}
+
+impl DexFile {
+    /// The generated classes and members, including all members of the generated classes
+    pub fn generated_items(&self) -> GeneratedItems {
+        let mut items = GeneratedItems::default();
+        for class in self.classes() {
+            let generated = class.generated().is_some();
+            if generated {
+                items.class_defs.insert(class.class_def_idx());
+            }
+            items.fields.extend(class.fields().iter().filter(|it| generated || it.generated().is_some()).map(|it| it.field_idx));
+            items.methods.extend(class.methods().iter().filter(|it| generated || it.generated().is_some()).map(|it| it.method_idx));
+        }
+        items
+    }
+}
@@ -0,0 +1,82 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::class::{Class, Method};
+use crate::dex_file::{DexFile, ACC_STATIC, NO_INDEX};
+use crate::prelude::*;
+
+/*
+The class hierarchy of the classes defined in a dex file: the subtypes of a class or interface and the
+methods overriding or implementing a method. The supertypes of classes that are not defined in the
+file (e.g. of the framework) are unknown, so only the part of the hierarchy below the classes of the
+file is walked, a class extending a framework class that implements an interface is not found for it.
+ */
+
+const ACC_PRIVATE: u64 = 0x2;
+const ACC_INTERFACE: u32 = 0x200;
+
+impl DexFile {
+    /// Indices into class_defs of the classes extending each type and of the classes and interfaces
+    /// listing it as interface, by type index
+    fn direct_subtypes(&self) -> BTreeMap<u32, Vec<usize>> {
+        let mut subtypes: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for (class_def_idx, class_def) in self.class_defs.iter().enumerate() {
+            let supertypes = self.type_list(class_def.interfaces_off).iter().map(|it| u32::from(*it))
+                .chain(Some(class_def.superclass_idx).filter(|it| *it != NO_INDEX));
+            for supertype in supertypes {
+                subtypes.entry(supertype).or_default().push(class_def_idx);
+            }
+        }
+        subtypes
+    }
+
+    /// Classes and interfaces of this file that extend or implement the type (e.g. `Ljava/lang/Runnable;`),
+    /// directly or through their supertypes, in the order of the class definitions
+    pub fn subtypes(&self, descriptor: &str) -> Vec<Class<'_>> {
+        let type_idx = match self.type_idx(descriptor) {
+            Some(type_idx) => type_idx,
+            None => return Vec::new(),
+        };
+        let direct_subtypes = self.direct_subtypes();
+        let mut found = BTreeSet::new();
+        let mut pending = vec![type_idx];
+        while let Some(type_idx) = pending.pop() {
+            for &class_def_idx in direct_subtypes.get(&type_idx).into_iter().flatten() {
+                if found.insert(class_def_idx) {
+                    pending.push(self.class_defs[class_def_idx].class_idx);
+                }
+            }
+        }
+        found.into_iter().filter_map(|it| self.class(it)).collect()
+    }
+
+    /// Classes of this file implementing the interface, directly, through their superclasses or through
+    /// the interfaces extending it. Abstract classes are included, interfaces are not.
+    pub fn implementers(&self, interface: &str) -> Vec<Class<'_>> {
+        self.subtypes(interface).into_iter().filter(|it| it.def().access_flags & ACC_INTERFACE == 0).collect()
+    }
+
+    /// Methods of the subtypes of the class of the method that override or implement it, i.e. the
+    /// virtual methods with the same name and prototype. Static and private methods and constructors
+    /// are not overridden. Package-private methods are treated like public ones, whether a subtype of
+    /// another package overrides them is not checked.
+    pub fn overriders(&self, method_idx: u32) -> Vec<Method<'_>> {
+        let method_id = &self.method_ids[method_idx as usize];
+        let name = self.string(method_id.name_idx);
+        if name == "<init>" || name == "<clinit>" {
+            return Vec::new();
+        }
+        let class = self.type_descriptor(method_id.class_idx.into());
+        let defined = self.find_class(class).and_then(|it| it.methods().into_iter().find(|it| it.method_idx == method_idx));
+        if defined.is_some_and(|it| it.encoded.access_flags & (ACC_STATIC | ACC_PRIVATE) != 0) {
+            return Vec::new();
+        }
+        self.subtypes(class).into_iter()
+            .flat_map(|it| it.methods())
+            .filter(|it| {
+                let id = &self.method_ids[it.method_idx as usize];
+                id.name_idx == method_id.name_idx && id.proto_idx == method_id.proto_idx
+                    && it.encoded.access_flags & (ACC_STATIC | ACC_PRIVATE) == 0
+            })
+            .collect()
+    }
+}
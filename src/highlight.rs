@@ -0,0 +1,256 @@
+use std::io::Write;
+
+/*
+Syntax coloring of the textual outputs using ANSI escape sequences, or HTML spans with the class of the
+token (e.g. `<span class="opcode">`) for reports rendered in a browser
+ */
+
+const RESET: &str = "\x1b[0m";
+const OPCODE: &str = "\x1b[1;34m";
+const DIRECTIVE: &str = "\x1b[35m";
+const REGISTER: &str = "\x1b[36m";
+const STRING: &str = "\x1b[32m";
+const TYPE: &str = "\x1b[33m";
+const LABEL: &str = "\x1b[1;35m";
+const COMMENT: &str = "\x1b[90m";
+
+/// Output format to highlight
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Syntax {
+    /// Output of the disasm subcommand
+    Smali,
+    /// Output matching `dexdump -d`
+    Dexdump,
+    /// Pseudo-Java of the decompile subcommand
+    Java,
+    /// Listings, only strings and types are highlighted
+    Plain,
+}
+
+/// Markup of the highlighted lines
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Markup {
+    Ansi,
+    Html,
+}
+
+fn paint(out: &mut String, markup: Markup, color: &str, text: &str) {
+    match markup {
+        Markup::Ansi => {
+            out.push_str(color);
+            out.push_str(text);
+            out.push_str(RESET);
+        }
+        Markup::Html => {
+            out.push_str("<span class=\"");
+            out.push_str(css_class(color));
+            out.push_str("\">");
+            push_plain(out, markup, text);
+            out.push_str("</span>");
+        }
+    }
+}
+
+fn push_plain(out: &mut String, markup: Markup, text: &str) {
+    match markup {
+        Markup::Ansi => out.push_str(text),
+        Markup::Html => out.push_str(&escape_html(text)),
+    }
+}
+
+/// Class of the spans of the tokens of a color in HTML
+fn css_class(color: &str) -> &'static str {
+    match color {
+        OPCODE => "opcode",
+        DIRECTIVE => "directive",
+        REGISTER => "register",
+        STRING => "string",
+        TYPE => "type",
+        LABEL => "label",
+        _ => "comment",
+    }
+}
+
+/// Escapes the characters with a meaning in HTML text and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Highlights a single line (without line terminator)
+pub fn highlight_line(syntax: Syntax, line: &str) -> String {
+    highlight(syntax, line, Markup::Ansi)
+}
+
+/// Highlights a single line (without line terminator) as HTML, the tokens in spans with the classes
+/// opcode, directive, register, string, type, label and comment, all text escaped
+pub fn highlight_html(syntax: Syntax, line: &str) -> String {
+    highlight(syntax, line, Markup::Html)
+}
+
+fn highlight(syntax: Syntax, line: &str, markup: Markup) -> String {
+    let mut out = String::with_capacity(line.len() * 2);
+    let indent = line.len() - line.trim_start().len();
+    out.push_str(&line[..indent]);
+    let mut rest = &line[indent..];
+
+    // Leading opcode, directive or label
+    let word_end = |s: &str| s.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(s.len());
+    match syntax {
+        Syntax::Smali if rest.starts_with('.') || rest.starts_with(':') => {
+            let end = word_end(rest);
+            paint(&mut out, markup, if rest.starts_with('.') { DIRECTIVE } else { LABEL }, &rest[..end]);
+            rest = &rest[end..];
+        }
+        Syntax::Smali if indent > 0 && rest.starts_with(|c: char| c.is_ascii_lowercase()) => {
+            let end = word_end(rest);
+            paint(&mut out, markup, OPCODE, &rest[..end]);
+            rest = &rest[end..];
+        }
+        Syntax::Dexdump => {
+            // Bytecode lines look like "0001a4: 1a00 0000   |0000: const-string v0, ..."
+            if let Some(bar) = rest.find('|') {
+                if let Some(colon) = rest[bar..].find(": ").map(|it| bar + it + 2) {
+                    push_plain(&mut out, markup, &rest[..colon]);
+                    rest = &rest[colon..];
+                    let end = word_end(rest);
+                    paint(&mut out, markup, OPCODE, &rest[..end]);
+                    rest = &rest[end..];
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let comment_start = match syntax {
+        Syntax::Smali => "#",
+        Syntax::Dexdump | Syntax::Java => "//",
+        Syntax::Plain => "\0",
+    };
+    let mut chars = rest.char_indices().peekable();
+    let mut previous = ' ';
+    while let Some((i, c)) = chars.next() {
+        let remaining = &rest[i..];
+        if remaining.starts_with(comment_start) {
+            paint(&mut out, markup, COMMENT, remaining);
+            return out;
+        }
+        let is_boundary = !previous.is_alphanumeric() && previous != '_' && previous != '$';
+        let token_len = if c == '"' {
+            // String literal including escapes
+            let mut len = remaining.len();
+            let mut escaped = false;
+            for (j, c) in remaining.char_indices().skip(1) {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        len = j + 1;
+                        break;
+                    }
+                    _ => escaped = false,
+                }
+            }
+            paint(&mut out, markup, STRING, &remaining[..len]);
+            len
+        } else if is_boundary && (c == 'L' || c == '[') && type_len(remaining) > 0 {
+            let len = type_len(remaining);
+            paint(&mut out, markup, TYPE, &remaining[..len]);
+            len
+        } else if is_boundary && (c == 'v' || c == 'p') && syntax != Syntax::Plain && register_len(remaining) > 0 {
+            let len = register_len(remaining);
+            paint(&mut out, markup, REGISTER, &remaining[..len]);
+            len
+        } else if is_boundary && c == ':' && syntax == Syntax::Smali && remaining[1..].starts_with(|c: char| c.is_ascii_lowercase()) {
+            let len = 1 + remaining[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(remaining.len() - 1);
+            paint(&mut out, markup, LABEL, &remaining[..len]);
+            len
+        } else {
+            push_plain(&mut out, markup, c.encode_utf8(&mut [0; 4]));
+            previous = c;
+            continue;
+        };
+        previous = 'x';
+        // Skip the remaining characters of the token
+        while chars.peek().is_some_and(|(j, _)| *j < i + token_len) {
+            chars.next();
+        }
+    }
+    out
+}
+
+/// Length of a type descriptor of a class or array of classes (e.g. "[Ljava/lang/String;") at the start of `s`
+fn type_len(s: &str) -> usize {
+    let array_depth = s.len() - s.trim_start_matches('[').len();
+    let element = &s[array_depth..];
+    if !element.starts_with('L') {
+        return 0;
+    }
+    match element.find(|c: char| c == ';' || c.is_whitespace() || c == ',' || c == '"') {
+        Some(end) if element.as_bytes()[end] == b';' && end > 1 => array_depth + end + 1,
+        _ => 0,
+    }
+}
+
+/// Length of a register name (e.g. "v12" or "p0") at the start of `s`
+fn register_len(s: &str) -> usize {
+    let digits = s[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len() - 1);
+    let next = s[1 + digits..].chars().next();
+    if digits == 0 || next.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+        0
+    } else {
+        1 + digits
+    }
+}
+
+/// Writer highlighting complete lines before passing them on
+pub struct HighlightWriter<W: Write> {
+    inner: W,
+    syntax: Syntax,
+    line: Vec<u8>,
+}
+
+impl<W: Write> HighlightWriter<W> {
+    pub fn new(inner: W, syntax: Syntax) -> HighlightWriter<W> {
+        HighlightWriter { inner, syntax, line: Vec::new() }
+    }
+
+    fn write_line(&mut self, terminated: bool) -> std::io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        self.inner.write_all(highlight_line(self.syntax, line).as_bytes())?;
+        if terminated {
+            self.inner.write_all(b"\n")?;
+        }
+        self.line.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HighlightWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for chunk in buf.split_inclusive(|it| *it == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if chunk.ends_with(b"\n") {
+                self.write_line(true)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.line.is_empty() {
+            self.write_line(false)?;
+        }
+        self.inner.flush()
+    }
+}
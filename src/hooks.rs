@@ -0,0 +1,329 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use crate::class::Method;
+use crate::dex_file::{DexFile, ACC_STATIC};
+use crate::prelude::*;
+
+/*
+Migration of hook targets (the methods hooked by Xposed modules, Frida scripts, ...) from one version of
+an app to the next, where obfuscation renames classes and methods between builds. A target is matched by
+its signature first, then by the semantic hash of its code (see semantic_hash). Methods with the same
+code are told apart by the class the other methods of the target's class moved to and by the name. The
+shape of a signature (the signature with the classes defined by the app replaced, as obfuscation renames
+them) must always match. Abstract and native methods, which have no code, are only matched by the shape
+of their signature in the class their class moved to.
+ */
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Same signature and code
+    Unchanged,
+    /// Same signature, other code
+    Changed,
+    /// Other signature, e.g. renamed by obfuscation
+    Moved,
+    /// Several methods of the new version match equally well
+    Ambiguous,
+    /// No method of the new version matches
+    Lost,
+    /// The target is not defined by the old version
+    Unknown,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Unchanged => "unchanged",
+            Status::Changed => "changed",
+            Status::Moved => "moved",
+            Status::Ambiguous => "ambiguous",
+            Status::Lost => "lost",
+            Status::Unknown => "unknown",
+        })
+    }
+}
+
+/// What the proposed method of the new version was matched by
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Evidence {
+    Signature,
+    /// The semantic hash, no other method of the new version has the same code
+    Code,
+    /// The semantic hash and the class the class of the target moved to
+    CodeAndClass,
+    /// The semantic hash and the name
+    CodeAndName,
+    /// The shape of the signature in the class the class of the target moved to
+    ClassAndShape,
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Evidence::Signature => "signature",
+            Evidence::Code => "code",
+            Evidence::CodeAndClass => "code+class",
+            Evidence::CodeAndName => "code+name",
+            Evidence::ClassAndShape => "class+shape",
+        })
+    }
+}
+
+/// Outcome of migrating one target
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// The target as given, e.g. `Lcom/example/Foo;->run(I)V`
+    pub target: String,
+    pub status: Status,
+    /// Signature of the target in the new version, for unchanged, changed and moved targets
+    pub proposed: Option<String>,
+    pub evidence: Option<Evidence>,
+    /// Signatures of the methods matching an ambiguous target
+    pub candidates: Vec<String>,
+}
+
+impl Migration {
+    fn new(target: &str, status: Status) -> Migration {
+        Migration { target: target.to_owned(), status, proposed: None, evidence: None, candidates: Vec::new() }
+    }
+
+    fn found(target: &str, status: Status, method: &Method, evidence: Evidence) -> Migration {
+        Migration { proposed: Some(qualified_name(method)), evidence: Some(evidence), ..Migration::new(target, status) }
+    }
+}
+
+/// Signature of a method as in the listings, e.g. `Lcom/example/Foo;->run(I)V`
+fn qualified_name(method: &Method) -> String {
+    let dex = method.class.dex();
+    format!("{}->{}{}", method.class.descriptor(), method.name(), dex.method_signature(method.method_idx))
+}
+
+/// Signature of a method with the classes defined by its dex file replaced by `L;`, prefixed with static
+/// for static methods
+fn shape(method: &Method) -> String {
+    let dex = method.class.dex();
+    let mut shape = String::from(if method.encoded.access_flags & ACC_STATIC != 0 { "static " } else { "" });
+    for part in dex.method_signature(method.method_idx).split_inclusive([';', '(', ')']) {
+        match part.find('L') {
+            Some(start) if part.ends_with(';') && dex.find_class(&part[start..]).is_some() => {
+                shape.push_str(&part[..start]);
+                shape.push_str("L;");
+            }
+            _ => shape.push_str(part),
+        }
+    }
+    shape
+}
+
+/// Methods of a version of the app by semantic hash
+struct Methods<'a> {
+    by_hash: BTreeMap<u64, Vec<Method<'a>>>,
+}
+
+impl<'a> Methods<'a> {
+    fn new(dex: &'a DexFile) -> Methods<'a> {
+        let mut by_hash: BTreeMap<u64, Vec<Method<'a>>> = BTreeMap::new();
+        for method in dex.classes().flat_map(|it| it.methods()) {
+            if let Some(hash) = method.semantic_hash() {
+                by_hash.entry(hash).or_default().push(method);
+            }
+        }
+        Methods { by_hash }
+    }
+
+    /// The only method with the semantic hash
+    fn unique(&self, hash: u64) -> Option<&Method<'a>> {
+        match self.by_hash.get(&hash).map(Vec::as_slice) {
+            Some([method]) => Some(method),
+            _ => None,
+        }
+    }
+}
+
+/// Class of the new version that the class `descriptor` of the old version moved to: the class the most
+/// of its methods with unique code moved to, else the class of the same name
+fn moved_class<'a>(descriptor: &str, old: &DexFile, old_methods: &Methods, new: &'a DexFile, new_methods: &Methods<'a>) -> Option<&'a str> {
+    let mut votes: BTreeMap<&str, usize> = BTreeMap::new();
+    for method in old.find_class(descriptor).map(|it| it.methods()).unwrap_or_default() {
+        let moved = method.semantic_hash()
+            .filter(|hash| old_methods.unique(*hash).is_some())
+            .and_then(|hash| new_methods.unique(hash));
+        if let Some(moved) = moved {
+            *votes.entry(moved.class.descriptor()).or_default() += 1;
+        }
+    }
+    let most = votes.values().copied().max().unwrap_or_default();
+    let mut winners = votes.into_iter().filter(|(_, count)| *count == most).map(|(class, _)| class);
+    match (winners.next(), winners.next()) {
+        (Some(class), None) => Some(class),
+        _ => new.find_class(descriptor).map(|it| it.descriptor()),
+    }
+}
+
+/// Parses a target `Lcls;->name(sig)ret` into the class, name and signature
+fn parse_target(target: &str) -> Option<(&str, &str, &str)> {
+    let (class, rest) = target.split_once("->")?;
+    let (name, signature) = rest.split_at(rest.find('(')?);
+    Some((class, name, signature))
+}
+
+fn migrate_target(target: &str, old: &DexFile, old_methods: &Methods, new: &DexFile, new_methods: &Methods) -> Migration {
+    let (class, name, signature) = match parse_target(target) {
+        Some(parsed) => parsed,
+        None => return Migration::new(target, Status::Unknown),
+    };
+    let method = match old.find_method(class, name, signature) {
+        Some(method) => method,
+        None => return Migration::new(target, Status::Unknown),
+    };
+    let hash = method.semantic_hash();
+    let same = new.find_method(class, name, signature).filter(|it| shape(it) == shape(&method));
+    if let Some(same) = same.as_ref().filter(|it| it.semantic_hash() == hash) {
+        return Migration::found(target, Status::Unchanged, same, Evidence::Signature);
+    }
+
+    let target_shape = shape(&method);
+    let class_moved_to = moved_class(class, old, old_methods, new, new_methods);
+    let mut candidates: Vec<&Method> = hash.and_then(|it| new_methods.by_hash.get(&it)).into_iter().flatten()
+        .filter(|it| shape(it) == target_shape)
+        .collect();
+    let mut evidence = Evidence::Code;
+    if candidates.len() > 1 && candidates.iter().any(|it| Some(it.class.descriptor()) == class_moved_to) {
+        candidates.retain(|it| Some(it.class.descriptor()) == class_moved_to);
+        evidence = Evidence::CodeAndClass;
+    }
+    if candidates.len() > 1 && candidates.iter().any(|it| it.name() == name) {
+        candidates.retain(|it| it.name() == name);
+        evidence = Evidence::CodeAndName;
+    }
+    match candidates.as_slice() {
+        [moved] => return Migration::found(target, Status::Moved, moved, evidence),
+        [] => {}
+        _ => {
+            return Migration { candidates: candidates.iter().map(|it| qualified_name(it)).collect(), ..Migration::new(target, Status::Ambiguous) };
+        }
+    }
+
+    // The code changed too
+    if let Some(same) = same {
+        return Migration::found(target, Status::Changed, &same, Evidence::Signature);
+    }
+    let in_class: Vec<Method> = class_moved_to.and_then(|it| new.find_class(it)).map(|it| it.methods()).unwrap_or_default()
+        .into_iter()
+        .filter(|it| shape(it) == target_shape)
+        .collect();
+    match in_class.as_slice() {
+        [moved] => Migration::found(target, Status::Moved, moved, Evidence::ClassAndShape),
+        [] => Migration::new(target, Status::Lost),
+        _ => Migration { candidates: in_class.iter().map(|it| qualified_name(it)).collect(), ..Migration::new(target, Status::Ambiguous) },
+    }
+}
+
+/// Migrates the hook targets (signatures like `Lcom/example/Foo;->run(I)V`) from the old to the new
+/// version of an app
+pub fn migrate(old: &DexFile, new: &DexFile, targets: &[&str]) -> Vec<Migration> {
+    let old_methods = Methods::new(old);
+    let new_methods = Methods::new(new);
+    targets.iter().map(|target| migrate_target(target, old, &old_methods, new, &new_methods)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureMethod};
+
+    /// A method returning the literal `value`, the semantic hash tells the literals apart
+    fn literal(name: &str, parameters: &[&str], value: u16) -> FixtureMethod {
+        // const/4 v0, value; return v0
+        let method = FixtureMethod::new(name, "I", parameters, vec![value << 12 | 0x0012, 0x000f]);
+        let registers_size = method.registers_size + 1;
+        method.registers_size(registers_size)
+    }
+
+    /// Status, proposed method, evidence and candidates of a migration
+    type Outcome = (Status, Option<String>, Option<Evidence>, Vec<String>);
+
+    fn migrated(old: &Fixture, new: &Fixture, targets: &[&str]) -> Vec<Outcome> {
+        migrate(&old.parse(), &new.parse(), targets).into_iter()
+            .map(|it| (it.status, it.proposed, it.evidence, it.candidates))
+            .collect()
+    }
+
+    fn found(status: Status, proposed: &str, evidence: Evidence) -> Outcome {
+        (status, Some(proposed.to_owned()), Some(evidence), Vec::new())
+    }
+
+    #[test]
+    fn same_class() {
+        let old = Fixture::empty().class("Lcom/example/Foo;")
+            .method(literal("same", &[], 1))
+            .method(literal("edited", &[], 2))
+            .method(literal("twice", &[], 3))
+            .method(literal("gone", &["Z"], 4));
+        let new = Fixture::empty().class("Lcom/example/Foo;")
+            .method(literal("same", &[], 1))
+            .method(literal("edited", &[], 20))
+            .method(literal("copy1", &[], 3))
+            .method(literal("copy2", &[], 3));
+        assert_eq!(migrated(&old, &new, &[
+            "Lcom/example/Foo;->same()I",
+            "Lcom/example/Foo;->edited()I",
+            "Lcom/example/Foo;->twice()I",
+            "Lcom/example/Foo;->gone(Z)I",
+            "Lcom/example/Foo;->missing()I",
+            "nonsense",
+        ]), [
+            found(Status::Unchanged, "Lcom/example/Foo;->same()I", Evidence::Signature),
+            found(Status::Changed, "Lcom/example/Foo;->edited()I", Evidence::Signature),
+            (Status::Ambiguous, None, None, vec!["Lcom/example/Foo;->copy1()I".to_owned(), "Lcom/example/Foo;->copy2()I".to_owned()]),
+            (Status::Lost, None, None, Vec::new()),
+            (Status::Unknown, None, None, Vec::new()),
+            (Status::Unknown, None, None, Vec::new()),
+        ]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn listing() {
+        let old = Fixture::empty().class("Lcom/example/Foo;").method(literal("same", &[], 1)).method(literal("twice", &[], 3));
+        let new = Fixture::empty().class("Lcom/example/Foo;")
+            .method(literal("same", &[], 1))
+            .method(literal("copy1", &[], 3))
+            .method(literal("copy2", &[], 3));
+        let migrations = migrate(&old.parse(), &new.parse(), &["Lcom/example/Foo;->same()I", "Lcom/example/Foo;->twice()I"]);
+        let table = crate::listing::hook_targets(&migrations);
+        assert_eq!(table.columns, ["target", "status", "proposed", "evidence", "candidates"]);
+        assert_eq!(table.rows, [
+            ["Lcom/example/Foo;->same()I", "unchanged", "Lcom/example/Foo;->same()I", "signature", ""],
+            ["Lcom/example/Foo;->twice()I", "ambiguous", "", "", "Lcom/example/Foo;->copy1()I Lcom/example/Foo;->copy2()I"],
+        ]);
+    }
+
+    #[test]
+    fn renamed_class() {
+        // Obfuscation renamed the class and its methods
+        let old = Fixture::empty().class("Lcom/example/Foo;")
+            .method(literal("run", &[], 1))
+            .method(literal("load", &[], 2))
+            .method(literal("parse", &["J"], 3))
+            .method(literal("self", &["Lcom/example/Foo;"], 4));
+        let new = Fixture::empty().class("La/a;")
+            .method(literal("a", &[], 1))
+            .method(literal("b", &[], 2))
+            .method(literal("load", &[], 2))
+            .method(literal("c", &["J"], 30))
+            .method(literal("d", &["La/a;"], 4));
+        assert_eq!(migrated(&old, &new, &[
+            "Lcom/example/Foo;->run()I",
+            "Lcom/example/Foo;->load()I",
+            "Lcom/example/Foo;->parse(J)I",
+            "Lcom/example/Foo;->self(Lcom/example/Foo;)I",
+        ]), [
+            found(Status::Moved, "La/a;->a()I", Evidence::Code),
+            found(Status::Moved, "La/a;->load()I", Evidence::CodeAndName),
+            found(Status::Moved, "La/a;->c(J)I", Evidence::ClassAndShape),
+            found(Status::Moved, "La/a;->d(La/a;)I", Evidence::Code),
+        ]);
+    }
+}
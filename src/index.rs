@@ -0,0 +1,183 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+
+use crate::atomic::AtomicFile;
+use crate::dex_file::DexFile;
+use crate::listing;
+use crate::table::Table;
+
+/*
+Index of the listings of a dex file (strings, classes, methods, xrefs) that can be cached on disk, so
+queries on a file that was indexed before do not parse it again.
+
+Cache files are named by the SHA-1 of the dex file. They start with MAGIC and FORMAT_VERSION, followed
+by the tables in the order of Index: the column names, the number of rows and the cells, each string
+prefixed with its length (u32, little endian). Files with other columns are ignored and replaced, so
+columns can be added to the listings without invalidating the cache manually.
+ */
+
+const MAGIC: &[u8; 4] = b"DXIX";
+const FORMAT_VERSION: u32 = 1;
+
+/// Listings of a dex file for queries that do not need the full model
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub strings: Table,
+    pub classes: Table,
+    pub methods: Table,
+    pub xrefs: Table,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32<W: Write>(out: &mut W, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Value too large for index"))?;
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_str<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    write_u32(out, value.len())?;
+    out.write_all(value.as_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf) as usize)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_u32(reader)?;
+    let mut buf = Vec::new();
+    // Do not trust the length for the allocation, the file may be truncated
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|_| invalid_data("Invalid UTF-8 in index"))
+}
+
+fn write_table<W: Write>(out: &mut W, table: &Table) -> io::Result<()> {
+    write_u32(out, table.columns.len())?;
+    for column in &table.columns {
+        write_str(out, column)?;
+    }
+    write_u32(out, table.rows.len())?;
+    for cell in table.rows.iter().flatten() {
+        write_str(out, cell)?;
+    }
+    Ok(())
+}
+
+/// Reads a table written by write_table, fails if its columns are not `columns`
+fn read_table<R: Read>(reader: &mut R, columns: &[&'static str]) -> io::Result<Table> {
+    let column_count = read_u32(reader)?;
+    let mut found = Vec::new();
+    for _ in 0..column_count {
+        found.push(read_string(reader)?);
+    }
+    if found != columns {
+        return Err(invalid_data("Index has different columns"));
+    }
+    let mut table = Table::new(columns);
+    for _ in 0..read_u32(reader)? {
+        let row = (0..columns.len()).map(|_| read_string(reader)).collect::<io::Result<_>>()?;
+        table.push(row);
+    }
+    Ok(table)
+}
+
+impl Index {
+    pub fn new(dex: &DexFile) -> Index {
+        Index {
+            strings: listing::strings(dex),
+            classes: listing::classes(dex),
+            methods: listing::methods(dex),
+            xrefs: listing::xrefs(dex),
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        for table in [&self.strings, &self.classes, &self.methods, &self.xrefs] {
+            write_table(out, table)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Index> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4..] != FORMAT_VERSION.to_le_bytes() {
+            return Err(invalid_data("Not an index of this version"));
+        }
+        Ok(Index {
+            strings: read_table(reader, listing::STRINGS_COLUMNS)?,
+            classes: read_table(reader, listing::CLASSES_COLUMNS)?,
+            methods: read_table(reader, listing::METHODS_COLUMNS)?,
+            xrefs: read_table(reader, listing::XREFS_COLUMNS)?,
+        })
+    }
+}
+
+/// Hex encoded SHA-1 of the data
+pub fn sha1(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+/// Directory of cached indices, one file per dex file named by its SHA-1
+#[derive(Debug, Clone)]
+pub struct IndexCache {
+    dir: PathBuf,
+}
+
+impl IndexCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> IndexCache {
+        IndexCache { dir: dir.into() }
+    }
+
+    fn path(&self, data: &[u8]) -> PathBuf {
+        self.dir.join(format!("{}.idx", sha1(data)))
+    }
+
+    /// Cached index of the dex file `data`, None if it was not cached or the cache file is unusable
+    pub fn get(&self, data: &[u8]) -> Option<Index> {
+        let path = self.path(data);
+        let file = File::open(&path).ok()?;
+        match Index::read(&mut BufReader::new(file)) {
+            Ok(index) => Some(index),
+            Err(err) => {
+                tracing::debug!(path = %path.display(), %err, "Ignoring cached index");
+                None
+            }
+        }
+    }
+
+    /// Caches the index of the dex file `data`. The file is written under a temporary name first, so
+    /// concurrent readers never see a partial index.
+    pub fn put(&self, data: &[u8], index: &Index) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(data);
+        let mut out = AtomicFile::create(&path)?;
+        index.write(&mut out)?;
+        out.commit()
+    }
+
+    /// Cached index of the dex file `data`, or the index of the model returned by `parse`, which is
+    /// then cached. Failing to write the cache is not an error.
+    pub fn get_or_insert_with<E, F: FnOnce() -> Result<DexFile, E>>(&self, data: &[u8], parse: F) -> Result<Index, E> {
+        if let Some(index) = self.get(data) {
+            return Ok(index);
+        }
+        let index = Index::new(&parse()?);
+        if let Err(err) = self.put(data, &index) {
+            tracing::warn!(dir = %self.dir.display(), %err, "Could not cache index");
+        }
+        Ok(index)
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+#[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+use memmap::Mmap;
+
+/*
+Loading of input files. Files are mapped into memory if the mmap feature is enabled and mapping
+succeeds, otherwise (e.g. for pipes, empty files or file systems without mmap support) they are read
+into an owned buffer. With the compressed feature, gzip, xz and zstd compressed files (e.g. classes.dex.gz
+of a corpus archive) are recognized by their magic and decompressed, whatever their name.
+ */
+
+/// Compression formats of input files
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression format of `data`, by its magic
+    pub fn detect(data: &[u8]) -> Option<Compression> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+/// Contents of an input file
+pub enum InputData {
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for InputData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+            InputData::Mapped(mmap) => mmap,
+            InputData::Owned(data) => data,
+        }
+    }
+}
+
+/// Maps or reads the file at `path`. A mapped file must not be modified while the data is in use.
+pub fn read_file(path: &Path) -> io::Result<InputData> {
+    let file = File::open(path)?;
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    match unsafe { Mmap::map(&file) } {
+        Ok(mmap) => return decompress(InputData::Mapped(mmap)),
+        Err(err) => tracing::debug!(path = %path.display(), %err, "Could not map file, reading it instead"),
+    }
+    decompress(read_owned(file)?)
+}
+
+/// Reads all of stdin, e.g. for `unzip -p app.apk classes.dex | dex_tool dump -`
+pub fn read_stdin() -> io::Result<InputData> {
+    let mut data = Vec::new();
+    io::Read::read_to_end(&mut io::stdin().lock(), &mut data)?;
+    decompress(InputData::Owned(data))
+}
+
+fn read_owned(mut file: File) -> io::Result<InputData> {
+    let mut data = Vec::new();
+    io::Read::read_to_end(&mut file, &mut data)?;
+    Ok(InputData::Owned(data))
+}
+
+/// The decompressed data if `data` is compressed, else `data`
+#[cfg(feature = "compressed")]
+pub fn decompress(data: InputData) -> io::Result<InputData> {
+    let compression = match Compression::detect(&data) {
+        Some(compression) => compression,
+        None => return Ok(data),
+    };
+    let mut decompressed = Vec::new();
+    let invalid = |err: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid {} data: {}", compression, err));
+    match compression {
+        Compression::Gzip => {
+            io::Read::read_to_end(&mut flate2::read::MultiGzDecoder::new(&data[..]), &mut decompressed).map_err(|err| invalid(&err))?;
+        }
+        Compression::Xz => lzma_rs::xz_decompress(&mut &data[..], &mut decompressed).map_err(|err| invalid(&err))?,
+        Compression::Zstd => {
+            let mut decoder = ruzstd::decoding::StreamingDecoder::new(&data[..]).map_err(|err| invalid(&err))?;
+            io::Read::read_to_end(&mut decoder, &mut decompressed).map_err(|err| invalid(&err))?;
+        }
+    }
+    tracing::debug!(%compression, compressed_size = data.len(), size = decompressed.len(), "Decompressed input");
+    Ok(InputData::Owned(decompressed))
+}
+
+/// Without the compressed feature, the data is used as is
+#[cfg(not(feature = "compressed"))]
+pub fn decompress(data: InputData) -> io::Result<InputData> {
+    Ok(data)
+}
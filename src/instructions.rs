@@ -0,0 +1,1079 @@
+use core::convert::TryInto;
+use core::fmt;
+
+use crate::prelude::*;
+
+use crate::instructions::DecodeError::{NotAPayload, Truncated};
+use crate::instructions::EncodeError::{InvalidPayload, OutOfRange, UnusedOpcode};
+
+// Identifiers of the pseudo-instructions (payloads) that are placed in the instruction stream
+const PACKED_SWITCH_PAYLOAD: u16 = 0x0100;
+const SPARSE_SWITCH_PAYLOAD: u16 = 0x0200;
+const FILL_ARRAY_DATA_PAYLOAD: u16 = 0x0300;
+
+/// Instruction Formats as listed in the Dalvik Executable instruction formats document
+/// https://source.android.com/devices/tech/dalvik/instruction-formats
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    F10x,
+    F12x,
+    F11n,
+    F11x,
+    F10t,
+    F20t,
+    F22x,
+    F21t,
+    F21s,
+    F21h,
+    F21c,
+    F23x,
+    F22b,
+    F22t,
+    F22s,
+    F22c,
+    F30t,
+    F32x,
+    F31i,
+    F31t,
+    F31c,
+    F35c,
+    F3rc,
+    F45cc,
+    F4rcc,
+    F51l,
+}
+
+impl Format {
+    /// Size of an instruction in this format in 16-bit code units
+    pub fn size(self) -> usize {
+        use Format::*;
+        match self {
+            F10x | F12x | F11n | F11x | F10t => 1,
+            F20t | F22x | F21t | F21s | F21h | F21c | F23x | F22b | F22t | F22s | F22c => 2,
+            F30t | F32x | F31i | F31t | F31c | F35c | F3rc => 3,
+            F45cc | F4rcc => 4,
+            F51l => 5,
+        }
+    }
+}
+
+/// Kind of the index operand (if any) of an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndexType {
+    None,
+    StringRef,
+    TypeRef,
+    FieldRef,
+    MethodRef,
+    MethodAndProtoRef,
+    CallSiteRef,
+    MethodHandleRef,
+    ProtoRef,
+}
+
+#[derive(Debug)]
+pub struct OpcodeInfo {
+    pub name: &'static str,
+    pub format: Format,
+    pub index_type: IndexType,
+}
+
+macro_rules! op {
+    ($name:expr, $format:ident) => { op!($name, $format, None) };
+    ($name:expr, $format:ident, $index:ident) => {
+        OpcodeInfo { name: $name, format: Format::$format, index_type: IndexType::$index }
+    };
+}
+
+/// Opcode table indexed by the opcode byte. Unused opcodes are listed as "unused-XX" in format 10x
+pub static OPCODES: [OpcodeInfo; 256] = [
+    op!("nop", F10x),
+    op!("move", F12x),
+    op!("move/from16", F22x),
+    op!("move/16", F32x),
+    op!("move-wide", F12x),
+    op!("move-wide/from16", F22x),
+    op!("move-wide/16", F32x),
+    op!("move-object", F12x),
+    op!("move-object/from16", F22x),
+    op!("move-object/16", F32x),
+    op!("move-result", F11x),
+    op!("move-result-wide", F11x),
+    op!("move-result-object", F11x),
+    op!("move-exception", F11x),
+    op!("return-void", F10x),
+    op!("return", F11x),
+    // 0x10
+    op!("return-wide", F11x),
+    op!("return-object", F11x),
+    op!("const/4", F11n),
+    op!("const/16", F21s),
+    op!("const", F31i),
+    op!("const/high16", F21h),
+    op!("const-wide/16", F21s),
+    op!("const-wide/32", F31i),
+    op!("const-wide", F51l),
+    op!("const-wide/high16", F21h),
+    op!("const-string", F21c, StringRef),
+    op!("const-string/jumbo", F31c, StringRef),
+    op!("const-class", F21c, TypeRef),
+    op!("monitor-enter", F11x),
+    op!("monitor-exit", F11x),
+    op!("check-cast", F21c, TypeRef),
+    // 0x20
+    op!("instance-of", F22c, TypeRef),
+    op!("array-length", F12x),
+    op!("new-instance", F21c, TypeRef),
+    op!("new-array", F22c, TypeRef),
+    op!("filled-new-array", F35c, TypeRef),
+    op!("filled-new-array/range", F3rc, TypeRef),
+    op!("fill-array-data", F31t),
+    op!("throw", F11x),
+    op!("goto", F10t),
+    op!("goto/16", F20t),
+    op!("goto/32", F30t),
+    op!("packed-switch", F31t),
+    op!("sparse-switch", F31t),
+    op!("cmpl-float", F23x),
+    op!("cmpg-float", F23x),
+    op!("cmpl-double", F23x),
+    // 0x30
+    op!("cmpg-double", F23x),
+    op!("cmp-long", F23x),
+    op!("if-eq", F22t),
+    op!("if-ne", F22t),
+    op!("if-lt", F22t),
+    op!("if-ge", F22t),
+    op!("if-gt", F22t),
+    op!("if-le", F22t),
+    op!("if-eqz", F21t),
+    op!("if-nez", F21t),
+    op!("if-ltz", F21t),
+    op!("if-gez", F21t),
+    op!("if-gtz", F21t),
+    op!("if-lez", F21t),
+    op!("unused-3e", F10x),
+    op!("unused-3f", F10x),
+    // 0x40
+    op!("unused-40", F10x),
+    op!("unused-41", F10x),
+    op!("unused-42", F10x),
+    op!("unused-43", F10x),
+    op!("aget", F23x),
+    op!("aget-wide", F23x),
+    op!("aget-object", F23x),
+    op!("aget-boolean", F23x),
+    op!("aget-byte", F23x),
+    op!("aget-char", F23x),
+    op!("aget-short", F23x),
+    op!("aput", F23x),
+    op!("aput-wide", F23x),
+    op!("aput-object", F23x),
+    op!("aput-boolean", F23x),
+    op!("aput-byte", F23x),
+    // 0x50
+    op!("aput-char", F23x),
+    op!("aput-short", F23x),
+    op!("iget", F22c, FieldRef),
+    op!("iget-wide", F22c, FieldRef),
+    op!("iget-object", F22c, FieldRef),
+    op!("iget-boolean", F22c, FieldRef),
+    op!("iget-byte", F22c, FieldRef),
+    op!("iget-char", F22c, FieldRef),
+    op!("iget-short", F22c, FieldRef),
+    op!("iput", F22c, FieldRef),
+    op!("iput-wide", F22c, FieldRef),
+    op!("iput-object", F22c, FieldRef),
+    op!("iput-boolean", F22c, FieldRef),
+    op!("iput-byte", F22c, FieldRef),
+    op!("iput-char", F22c, FieldRef),
+    op!("iput-short", F22c, FieldRef),
+    // 0x60
+    op!("sget", F21c, FieldRef),
+    op!("sget-wide", F21c, FieldRef),
+    op!("sget-object", F21c, FieldRef),
+    op!("sget-boolean", F21c, FieldRef),
+    op!("sget-byte", F21c, FieldRef),
+    op!("sget-char", F21c, FieldRef),
+    op!("sget-short", F21c, FieldRef),
+    op!("sput", F21c, FieldRef),
+    op!("sput-wide", F21c, FieldRef),
+    op!("sput-object", F21c, FieldRef),
+    op!("sput-boolean", F21c, FieldRef),
+    op!("sput-byte", F21c, FieldRef),
+    op!("sput-char", F21c, FieldRef),
+    op!("sput-short", F21c, FieldRef),
+    op!("invoke-virtual", F35c, MethodRef),
+    op!("invoke-super", F35c, MethodRef),
+    // 0x70
+    op!("invoke-direct", F35c, MethodRef),
+    op!("invoke-static", F35c, MethodRef),
+    op!("invoke-interface", F35c, MethodRef),
+    op!("unused-73", F10x),
+    op!("invoke-virtual/range", F3rc, MethodRef),
+    op!("invoke-super/range", F3rc, MethodRef),
+    op!("invoke-direct/range", F3rc, MethodRef),
+    op!("invoke-static/range", F3rc, MethodRef),
+    op!("invoke-interface/range", F3rc, MethodRef),
+    op!("unused-79", F10x),
+    op!("unused-7a", F10x),
+    op!("neg-int", F12x),
+    op!("not-int", F12x),
+    op!("neg-long", F12x),
+    op!("not-long", F12x),
+    op!("neg-float", F12x),
+    // 0x80
+    op!("neg-double", F12x),
+    op!("int-to-long", F12x),
+    op!("int-to-float", F12x),
+    op!("int-to-double", F12x),
+    op!("long-to-int", F12x),
+    op!("long-to-float", F12x),
+    op!("long-to-double", F12x),
+    op!("float-to-int", F12x),
+    op!("float-to-long", F12x),
+    op!("float-to-double", F12x),
+    op!("double-to-int", F12x),
+    op!("double-to-long", F12x),
+    op!("double-to-float", F12x),
+    op!("int-to-byte", F12x),
+    op!("int-to-char", F12x),
+    op!("int-to-short", F12x),
+    // 0x90
+    op!("add-int", F23x),
+    op!("sub-int", F23x),
+    op!("mul-int", F23x),
+    op!("div-int", F23x),
+    op!("rem-int", F23x),
+    op!("and-int", F23x),
+    op!("or-int", F23x),
+    op!("xor-int", F23x),
+    op!("shl-int", F23x),
+    op!("shr-int", F23x),
+    op!("ushr-int", F23x),
+    op!("add-long", F23x),
+    op!("sub-long", F23x),
+    op!("mul-long", F23x),
+    op!("div-long", F23x),
+    op!("rem-long", F23x),
+    // 0xa0
+    op!("and-long", F23x),
+    op!("or-long", F23x),
+    op!("xor-long", F23x),
+    op!("shl-long", F23x),
+    op!("shr-long", F23x),
+    op!("ushr-long", F23x),
+    op!("add-float", F23x),
+    op!("sub-float", F23x),
+    op!("mul-float", F23x),
+    op!("div-float", F23x),
+    op!("rem-float", F23x),
+    op!("add-double", F23x),
+    op!("sub-double", F23x),
+    op!("mul-double", F23x),
+    op!("div-double", F23x),
+    op!("rem-double", F23x),
+    // 0xb0
+    op!("add-int/2addr", F12x),
+    op!("sub-int/2addr", F12x),
+    op!("mul-int/2addr", F12x),
+    op!("div-int/2addr", F12x),
+    op!("rem-int/2addr", F12x),
+    op!("and-int/2addr", F12x),
+    op!("or-int/2addr", F12x),
+    op!("xor-int/2addr", F12x),
+    op!("shl-int/2addr", F12x),
+    op!("shr-int/2addr", F12x),
+    op!("ushr-int/2addr", F12x),
+    op!("add-long/2addr", F12x),
+    op!("sub-long/2addr", F12x),
+    op!("mul-long/2addr", F12x),
+    op!("div-long/2addr", F12x),
+    op!("rem-long/2addr", F12x),
+    // 0xc0
+    op!("and-long/2addr", F12x),
+    op!("or-long/2addr", F12x),
+    op!("xor-long/2addr", F12x),
+    op!("shl-long/2addr", F12x),
+    op!("shr-long/2addr", F12x),
+    op!("ushr-long/2addr", F12x),
+    op!("add-float/2addr", F12x),
+    op!("sub-float/2addr", F12x),
+    op!("mul-float/2addr", F12x),
+    op!("div-float/2addr", F12x),
+    op!("rem-float/2addr", F12x),
+    op!("add-double/2addr", F12x),
+    op!("sub-double/2addr", F12x),
+    op!("mul-double/2addr", F12x),
+    op!("div-double/2addr", F12x),
+    op!("rem-double/2addr", F12x),
+    // 0xd0
+    op!("add-int/lit16", F22s),
+    op!("rsub-int", F22s),
+    op!("mul-int/lit16", F22s),
+    op!("div-int/lit16", F22s),
+    op!("rem-int/lit16", F22s),
+    op!("and-int/lit16", F22s),
+    op!("or-int/lit16", F22s),
+    op!("xor-int/lit16", F22s),
+    op!("add-int/lit8", F22b),
+    op!("rsub-int/lit8", F22b),
+    op!("mul-int/lit8", F22b),
+    op!("div-int/lit8", F22b),
+    op!("rem-int/lit8", F22b),
+    op!("and-int/lit8", F22b),
+    op!("or-int/lit8", F22b),
+    op!("xor-int/lit8", F22b),
+    // 0xe0
+    op!("shl-int/lit8", F22b),
+    op!("shr-int/lit8", F22b),
+    op!("ushr-int/lit8", F22b),
+    op!("unused-e3", F10x),
+    op!("unused-e4", F10x),
+    op!("unused-e5", F10x),
+    op!("unused-e6", F10x),
+    op!("unused-e7", F10x),
+    op!("unused-e8", F10x),
+    op!("unused-e9", F10x),
+    op!("unused-ea", F10x),
+    op!("unused-eb", F10x),
+    op!("unused-ec", F10x),
+    op!("unused-ed", F10x),
+    op!("unused-ee", F10x),
+    op!("unused-ef", F10x),
+    // 0xf0
+    op!("unused-f0", F10x),
+    op!("unused-f1", F10x),
+    op!("unused-f2", F10x),
+    op!("unused-f3", F10x),
+    op!("unused-f4", F10x),
+    op!("unused-f5", F10x),
+    op!("unused-f6", F10x),
+    op!("unused-f7", F10x),
+    op!("unused-f8", F10x),
+    op!("unused-f9", F10x),
+    op!("invoke-polymorphic", F45cc, MethodAndProtoRef),
+    op!("invoke-polymorphic/range", F4rcc, MethodAndProtoRef),
+    op!("invoke-custom", F35c, CallSiteRef),
+    op!("invoke-custom/range", F3rc, CallSiteRef),
+    op!("const-method-handle", F21c, MethodHandleRef),
+    op!("const-method-type", F21c, ProtoRef),
+];
+
+/// Whether the registers vA, vB and vC of an opcode are wide register pairs
+fn wide_registers(opcode: u8) -> (bool, bool, bool) {
+    match opcode {
+        // move-wide, move-wide/from16, move-wide/16
+        0x04..=0x06 => (true, true, false),
+        // move-result-wide, return-wide, const-wide*, aget-wide, aput-wide, iget-wide, iput-wide, sget-wide, sput-wide
+        0x0b | 0x10 | 0x16..=0x19 | 0x45 | 0x4c | 0x53 | 0x5a | 0x61 | 0x68 => (true, false, false),
+        // cmpl-double, cmpg-double, cmp-long
+        0x2f..=0x31 => (false, true, true),
+        // neg-long, not-long, neg-double, long-to-double, double-to-long
+        0x7d | 0x7e | 0x80 | 0x86 | 0x8b => (true, true, false),
+        // int-to-long, int-to-double, float-to-long, float-to-double
+        0x81 | 0x83 | 0x88 | 0x89 => (true, false, false),
+        // long-to-int, long-to-float, double-to-int, double-to-float
+        0x84 | 0x85 | 0x8a | 0x8c => (false, true, false),
+        // shl-long, shr-long, ushr-long (the shift distance is an int)
+        0xa3..=0xa5 => (true, true, false),
+        // add-long to xor-long, add-double to rem-double
+        0x9b..=0xa2 | 0xab..=0xaf => (true, true, true),
+        // shl-long/2addr, shr-long/2addr, ushr-long/2addr
+        0xc3..=0xc5 => (true, false, false),
+        // add-long/2addr to xor-long/2addr, add-double/2addr to rem-double/2addr
+        0xbb..=0xc2 | 0xcb..=0xcf => (true, true, false),
+        _ => (false, false, false),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PayloadKind {
+    PackedSwitch,
+    SparseSwitch,
+    FillArrayData,
+}
+
+/// A decoded instruction. Operands follow the naming of the instruction format documentation:
+/// `a`, `b`, `c` and `h` hold the values of vA, vB, vC and vH (registers, indices, literals or
+/// branch offsets depending on the format). Signed operands are sign extended into the u32.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub a: u32,
+    pub b: u32,
+    pub c: u32,
+    pub h: u32,
+    /// vB of format 51l (const-wide)
+    pub wide_b: u64,
+    /// Argument registers vC, vD, vE, vF, vG of formats 35c and 45cc (only the first `a` are used)
+    pub args: [u8; 5],
+    /// Set if this is a payload pseudo-instruction (encoded as nop)
+    pub payload: Option<PayloadKind>,
+    /// Size in 16-bit code units
+    pub size: usize,
+}
+
+impl Instruction {
+    /// Instruction of `opcode` with all operands zero, to be set for its format before encoding
+    pub fn new(opcode: u8) -> Instruction {
+        Instruction {
+            opcode,
+            a: 0,
+            b: 0,
+            c: 0,
+            h: 0,
+            wide_b: 0,
+            args: [0u8; 5],
+            payload: None,
+            size: OPCODES[opcode as usize].format.size(),
+        }
+    }
+
+    pub fn info(&self) -> &'static OpcodeInfo {
+        &OPCODES[self.opcode as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn format(&self) -> Format {
+        self.info().format
+    }
+
+    /// Index operand (string, type, field, method, ... index) if the instruction references one
+    pub fn index(&self) -> Option<u32> {
+        if self.payload.is_some() || self.info().index_type == IndexType::None {
+            return None;
+        }
+        match self.format() {
+            Format::F22c => Some(self.c),
+            _ => Some(self.b),
+        }
+    }
+
+    /// Register operands, each with whether it is the first register of a wide pair
+    pub fn registers(&self) -> Vec<(u32, bool)> {
+        let (wide_a, wide_b, wide_c) = wide_registers(self.opcode);
+        match self.format() {
+            Format::F10x | Format::F10t | Format::F20t | Format::F30t => Vec::new(),
+            Format::F11n | Format::F11x | Format::F21t | Format::F21s | Format::F21h | Format::F21c | Format::F31i |
+            Format::F31t | Format::F31c | Format::F51l => vec![(self.a, wide_a)],
+            Format::F12x | Format::F22x | Format::F22b | Format::F22t | Format::F22s | Format::F22c | Format::F32x =>
+                vec![(self.a, wide_a), (self.b, wide_b)],
+            Format::F23x => vec![(self.a, wide_a), (self.b, wide_b), (self.c, wide_c)],
+            Format::F35c | Format::F45cc => self.args.iter().take(self.a as usize).map(|it| (*it as u32, false)).collect(),
+            Format::F3rc | Format::F4rcc => (self.c..self.c + self.a).map(|it| (it, false)).collect(),
+        }
+    }
+
+    /// Whether vA is the destination register of the instruction
+    pub fn writes_a(&self) -> bool {
+        self.payload.is_none() && matches!(self.opcode, 0x01..=0x0d | 0x12..=0x1c | 0x1f..=0x23 | 0x2d..=0x31 |
+            0x44..=0x4a | 0x52..=0x58 | 0x60..=0x66 | 0x7b..=0xe2 | 0xfe | 0xff)
+    }
+
+    /// Registers written by the instruction, both registers of wide pairs
+    pub fn defined_registers(&self) -> Vec<u32> {
+        match self.registers().first() {
+            Some((register, wide)) if self.writes_a() => (*register..=*register + *wide as u32).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Registers read by the instruction, both registers of wide pairs. vA of check-cast and of the /2addr
+    /// operations is read and written.
+    pub fn used_registers(&self) -> Vec<u32> {
+        let read_and_written = self.opcode == 0x1f || (0xb0..=0xcf).contains(&self.opcode);
+        let skip = if self.writes_a() && !read_and_written { 1 } else { 0 };
+        self.registers().into_iter().skip(skip)
+            .flat_map(|(register, wide)| register..=register + wide as u32)
+            .collect()
+    }
+
+    /// Decode the instruction at `pc` (in code units) of the instruction array
+    pub fn decode(insns: &[u16], pc: usize) -> Result<Instruction, DecodeError> {
+        let unit = |i: usize| insns.get(pc + i).copied().ok_or(Truncated(pc));
+        let u0 = unit(0)?;
+
+        if let Some(payload) = Instruction::decode_payload(insns, pc)? {
+            return Ok(payload);
+        }
+
+        let mut insn = Instruction::new((u0 & 0xff) as u8);
+        let format = insn.format();
+        if pc + insn.size > insns.len() {
+            return Err(Truncated(pc));
+        }
+
+        let u0 = u0 as u32;
+        let high = u0 >> 8;
+        let nibble_a = (u0 >> 8) & 0xf;
+        let nibble_b = u0 >> 12;
+        let u32_at = |i: usize| insns[pc + i] as u32 | (insns[pc + i + 1] as u32) << 16;
+        let s16_at = |i: usize| insns[pc + i] as i16 as i32 as u32;
+
+        match format {
+            Format::F10x => {}
+            Format::F12x => {
+                insn.a = nibble_a;
+                insn.b = nibble_b;
+            }
+            Format::F11n => {
+                insn.a = nibble_a;
+                insn.b = ((u0 as u16 as i16) >> 12) as i32 as u32;
+            }
+            Format::F11x => insn.a = high,
+            Format::F10t => insn.a = high as u8 as i8 as i32 as u32,
+            Format::F20t => insn.a = s16_at(1),
+            Format::F22x => {
+                insn.a = high;
+                insn.b = insns[pc + 1] as u32;
+            }
+            Format::F21t | Format::F21s => {
+                insn.a = high;
+                insn.b = s16_at(1);
+            }
+            Format::F21h | Format::F21c => {
+                insn.a = high;
+                insn.b = insns[pc + 1] as u32;
+            }
+            Format::F23x => {
+                insn.a = high;
+                insn.b = insns[pc + 1] as u32 & 0xff;
+                insn.c = insns[pc + 1] as u32 >> 8;
+            }
+            Format::F22b => {
+                insn.a = high;
+                insn.b = insns[pc + 1] as u32 & 0xff;
+                insn.c = (insns[pc + 1] >> 8) as u8 as i8 as i32 as u32;
+            }
+            Format::F22t | Format::F22s => {
+                insn.a = nibble_a;
+                insn.b = nibble_b;
+                insn.c = s16_at(1);
+            }
+            Format::F22c => {
+                insn.a = nibble_a;
+                insn.b = nibble_b;
+                insn.c = insns[pc + 1] as u32;
+            }
+            Format::F30t => insn.a = u32_at(1),
+            Format::F32x => {
+                insn.a = insns[pc + 1] as u32;
+                insn.b = insns[pc + 2] as u32;
+            }
+            Format::F31i | Format::F31t | Format::F31c => {
+                insn.a = high;
+                insn.b = u32_at(1);
+            }
+            Format::F35c | Format::F45cc => {
+                insn.a = nibble_b;
+                insn.b = insns[pc + 1] as u32;
+                let regs = insns[pc + 2];
+                insn.args = [
+                    (regs & 0xf) as u8,
+                    (regs >> 4 & 0xf) as u8,
+                    (regs >> 8 & 0xf) as u8,
+                    (regs >> 12) as u8,
+                    nibble_a as u8,
+                ];
+                if format == Format::F45cc {
+                    insn.h = insns[pc + 3] as u32;
+                }
+            }
+            Format::F3rc | Format::F4rcc => {
+                insn.a = high;
+                insn.b = insns[pc + 1] as u32;
+                insn.c = insns[pc + 2] as u32;
+                if format == Format::F4rcc {
+                    insn.h = insns[pc + 3] as u32;
+                }
+            }
+            Format::F51l => {
+                insn.a = high;
+                insn.wide_b = u32_at(1) as u64 | (u32_at(3) as u64) << 32;
+            }
+        }
+        Ok(insn)
+    }
+
+    fn decode_payload(insns: &[u16], pc: usize) -> Result<Option<Instruction>, DecodeError> {
+        let unit = |i: usize| insns.get(pc + i).map(|it| *it as usize).ok_or(Truncated(pc));
+        let (kind, size) = match insns[pc] {
+            PACKED_SWITCH_PAYLOAD => (PayloadKind::PackedSwitch, 4 + unit(1)? * 2),
+            SPARSE_SWITCH_PAYLOAD => (PayloadKind::SparseSwitch, 2 + unit(1)? * 4),
+            FILL_ARRAY_DATA_PAYLOAD => {
+                let element_width = unit(1)?;
+                let size = unit(2)? | unit(3)? << 16;
+                (PayloadKind::FillArrayData, 4 + (size * element_width).div_ceil(2))
+            }
+            _ => return Ok(None),
+        };
+        if pc + size > insns.len() {
+            return Err(Truncated(pc));
+        }
+        Ok(Some(Instruction { payload: Some(kind), size, ..Instruction::new(0) }))
+    }
+
+    /// Encode the instruction into its code units, the inverse of decode. Fails if an operand does not
+    /// fit its field in the format of the opcode. The size is taken from the format, not from `size`.
+    /// Payload pseudo-instructions are encoded from their contents with Payload::encode.
+    pub fn encode(&self) -> Result<Vec<u16>, EncodeError> {
+        if self.payload.is_some() {
+            return Err(EncodeError::Payload);
+        }
+        if self.name().starts_with("unused-") {
+            return Err(UnusedOpcode(self.opcode));
+        }
+        let out_of_range = |operand: &'static str, value: i64| OutOfRange { opcode: self.opcode, operand, value };
+        // The operand if it fits in `bits` bits, unsigned or two's complement
+        let unsigned = |operand: &'static str, value: u32, bits: u32| -> Result<u32, EncodeError> {
+            if bits < 32 && value >> bits != 0 {
+                return Err(out_of_range(operand, value as i64));
+            }
+            Ok(value)
+        };
+        let signed = |operand: &'static str, value: u32, bits: u32| -> Result<u32, EncodeError> {
+            let value = value as i32 as i64;
+            if value < -(1 << (bits - 1)) || value >= 1 << (bits - 1) {
+                return Err(out_of_range(operand, value));
+            }
+            Ok(value as u32 & ((1 << bits) - 1))
+        };
+        let op = self.opcode as u32;
+        let units: Vec<u32> = match self.format() {
+            Format::F10x => vec![op],
+            Format::F12x => vec![op | unsigned("vA", self.a, 4)? << 8 | unsigned("vB", self.b, 4)? << 12],
+            Format::F11n => vec![op | unsigned("vA", self.a, 4)? << 8 | signed("vB", self.b, 4)? << 12],
+            Format::F11x => vec![op | unsigned("vA", self.a, 8)? << 8],
+            Format::F10t => vec![op | signed("vA", self.a, 8)? << 8],
+            Format::F20t => vec![op, signed("vA", self.a, 16)?],
+            Format::F22x | Format::F21h | Format::F21c => vec![op | unsigned("vA", self.a, 8)? << 8, unsigned("vB", self.b, 16)?],
+            Format::F21t | Format::F21s => vec![op | unsigned("vA", self.a, 8)? << 8, signed("vB", self.b, 16)?],
+            Format::F23x => vec![
+                op | unsigned("vA", self.a, 8)? << 8,
+                unsigned("vB", self.b, 8)? | unsigned("vC", self.c, 8)? << 8,
+            ],
+            Format::F22b => vec![
+                op | unsigned("vA", self.a, 8)? << 8,
+                unsigned("vB", self.b, 8)? | signed("vC", self.c, 8)? << 8,
+            ],
+            Format::F22t | Format::F22s => vec![
+                op | unsigned("vA", self.a, 4)? << 8 | unsigned("vB", self.b, 4)? << 12,
+                signed("vC", self.c, 16)?,
+            ],
+            Format::F22c => vec![
+                op | unsigned("vA", self.a, 4)? << 8 | unsigned("vB", self.b, 4)? << 12,
+                unsigned("vC", self.c, 16)?,
+            ],
+            Format::F30t => vec![op, self.a & 0xffff, self.a >> 16],
+            Format::F32x => vec![op, unsigned("vA", self.a, 16)?, unsigned("vB", self.b, 16)?],
+            Format::F31i | Format::F31t | Format::F31c => vec![op | unsigned("vA", self.a, 8)? << 8, self.b & 0xffff, self.b >> 16],
+            Format::F35c | Format::F45cc => {
+                if self.a > 5 {
+                    return Err(out_of_range("vA", self.a as i64));
+                }
+                let mut args = [0u32; 5];
+                for (i, operand) in ["vC", "vD", "vE", "vF", "vG"].iter().enumerate() {
+                    args[i] = unsigned(operand, self.args[i] as u32, 4)?;
+                }
+                let mut units = vec![
+                    op | args[4] << 8 | self.a << 12,
+                    unsigned("vB", self.b, 16)?,
+                    args[0] | args[1] << 4 | args[2] << 8 | args[3] << 12,
+                ];
+                if self.format() == Format::F45cc {
+                    units.push(unsigned("vH", self.h, 16)?);
+                }
+                units
+            }
+            Format::F3rc | Format::F4rcc => {
+                let count = unsigned("vA", self.a, 8)?;
+                let first = unsigned("vC", self.c, 16)?;
+                if count > 0 {
+                    unsigned("last register", first + count - 1, 16)?;
+                }
+                let mut units = vec![op | count << 8, unsigned("vB", self.b, 16)?, first];
+                if self.format() == Format::F4rcc {
+                    units.push(unsigned("vH", self.h, 16)?);
+                }
+                units
+            }
+            Format::F51l => {
+                let mut units = vec![op | unsigned("vA", self.a, 8)? << 8];
+                units.extend((0..4).map(|i| (self.wide_b >> (i * 16)) as u32 & 0xffff));
+                units
+            }
+        };
+        Ok(units.into_iter().map(|it| it as u16).collect())
+    }
+}
+
+/// Contents of a payload pseudo-instruction
+#[derive(Debug, Clone)]
+pub enum Payload {
+    /// Branch targets (relative to the switch instruction) of consecutive keys starting at first_key
+    PackedSwitch { first_key: i32, targets: Vec<i32> },
+    /// Sorted keys with their branch targets (relative to the switch instruction)
+    SparseSwitch { keys: Vec<i32>, targets: Vec<i32> },
+    /// Raw little endian array elements of element_width bytes each
+    FillArrayData { element_width: u16, data: Vec<u8> },
+}
+
+impl Payload {
+    /// Decode the payload at `pc`, i.e. the target of a switch or fill-array-data instruction
+    pub fn decode(insns: &[u16], pc: usize) -> Result<Payload, DecodeError> {
+        let insn = Instruction::decode(insns, pc)?;
+        let units = &insns[pc..pc + insn.size];
+        let u32_at = |i: usize| units[i] as u32 | (units[i + 1] as u32) << 16;
+        match insn.payload {
+            Some(PayloadKind::PackedSwitch) => {
+                let size = units[1] as usize;
+                Ok(Payload::PackedSwitch {
+                    first_key: u32_at(2) as i32,
+                    targets: (0..size).map(|i| u32_at(4 + i * 2) as i32).collect(),
+                })
+            }
+            Some(PayloadKind::SparseSwitch) => {
+                let size = units[1] as usize;
+                Ok(Payload::SparseSwitch {
+                    keys: (0..size).map(|i| u32_at(2 + i * 2) as i32).collect(),
+                    targets: (0..size).map(|i| u32_at(2 + size * 2 + i * 2) as i32).collect(),
+                })
+            }
+            Some(PayloadKind::FillArrayData) => {
+                let element_width = units[1];
+                let size = u32_at(2) as usize * element_width as usize;
+                let data = units[4..].iter().flat_map(|it| it.to_le_bytes()).take(size).collect();
+                Ok(Payload::FillArrayData { element_width, data })
+            }
+            None => Err(NotAPayload(pc)),
+        }
+    }
+
+    /// Encode the payload into its code units, the inverse of decode. Payloads have to start at an even
+    /// pc (4 byte aligned) in the instruction array.
+    pub fn encode(&self) -> Result<Vec<u16>, EncodeError> {
+        let push_u32 = |units: &mut Vec<u16>, value: u32| units.extend([value as u16, (value >> 16) as u16]);
+        let mut units = Vec::new();
+        match self {
+            Payload::PackedSwitch { first_key, targets } => {
+                let size: u16 = targets.len().try_into().map_err(|_| InvalidPayload("more than 65535 switch cases"))?;
+                units.extend([PACKED_SWITCH_PAYLOAD, size]);
+                push_u32(&mut units, *first_key as u32);
+                for target in targets {
+                    push_u32(&mut units, *target as u32);
+                }
+            }
+            Payload::SparseSwitch { keys, targets } => {
+                if keys.len() != targets.len() {
+                    return Err(InvalidPayload("not one target per key"));
+                }
+                if keys.windows(2).any(|it| it[0] >= it[1]) {
+                    return Err(InvalidPayload("keys not sorted"));
+                }
+                let size: u16 = keys.len().try_into().map_err(|_| InvalidPayload("more than 65535 switch cases"))?;
+                units.extend([SPARSE_SWITCH_PAYLOAD, size]);
+                for value in keys.iter().chain(targets) {
+                    push_u32(&mut units, *value as u32);
+                }
+            }
+            Payload::FillArrayData { element_width, data } => {
+                if *element_width == 0 || data.len() % *element_width as usize != 0 {
+                    return Err(InvalidPayload("data not a multiple of the element width"));
+                }
+                let size: u32 = (data.len() / *element_width as usize).try_into()
+                    .map_err(|_| InvalidPayload("more than 2^32 array elements"))?;
+                units.extend([FILL_ARRAY_DATA_PAYLOAD, *element_width]);
+                push_u32(&mut units, size);
+                units.extend(data.chunks(2).map(|it| u16::from_le_bytes([it[0], it.get(1).copied().unwrap_or(0)])));
+            }
+        }
+        Ok(units)
+    }
+}
+
+/// Elements of a fill-array-data payload, typed by their width (chars and booleans are read as shorts
+/// and bytes, floats and doubles as their raw bits)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArrayData {
+    Bytes(Vec<i8>),
+    Shorts(Vec<i16>),
+    Ints(Vec<i32>),
+    Longs(Vec<i64>),
+}
+
+impl ArrayData {
+    pub fn len(&self) -> usize {
+        match self {
+            ArrayData::Bytes(values) => values.len(),
+            ArrayData::Shorts(values) => values.len(),
+            ArrayData::Ints(values) => values.len(),
+            ArrayData::Longs(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Elements sign extended to i64
+    pub fn values(&self) -> Vec<i64> {
+        match self {
+            ArrayData::Bytes(values) => values.iter().map(|it| *it as i64).collect(),
+            ArrayData::Shorts(values) => values.iter().map(|it| *it as i64).collect(),
+            ArrayData::Ints(values) => values.iter().map(|it| *it as i64).collect(),
+            ArrayData::Longs(values) => values.clone(),
+        }
+    }
+
+    /// Java type of the elements by their width
+    pub fn element_type(&self) -> &'static str {
+        match self {
+            ArrayData::Bytes(_) => "byte",
+            ArrayData::Shorts(_) => "short",
+            ArrayData::Ints(_) => "int",
+            ArrayData::Longs(_) => "long",
+        }
+    }
+}
+
+/// Array initializer in Java syntax, e.g. `{1, 2, 3}` or `{1L, 2L}`
+impl fmt::Display for ArrayData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suffix = if let ArrayData::Longs(_) = self { "L" } else { "" };
+        f.write_str("{")?;
+        for (i, value) in self.values().iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}{}", value, suffix)?;
+        }
+        f.write_str("}")
+    }
+}
+
+impl Payload {
+    /// Typed elements of a fill-array-data payload, None for switches and element widths other than 1,
+    /// 2, 4 or 8
+    pub fn array_data(&self) -> Option<ArrayData> {
+        let (element_width, data) = match self {
+            Payload::FillArrayData { element_width, data } => (*element_width as usize, data),
+            _ => return None,
+        };
+        let elements = data.chunks_exact(element_width.max(1));
+        match element_width {
+            1 => Some(ArrayData::Bytes(data.iter().map(|it| *it as i8).collect())),
+            2 => Some(ArrayData::Shorts(elements.map(|it| i16::from_le_bytes([it[0], it[1]])).collect())),
+            4 => Some(ArrayData::Ints(elements.map(|it| i32::from_le_bytes([it[0], it[1], it[2], it[3]])).collect())),
+            8 => Some(ArrayData::Longs(elements.map(|it| {
+                i64::from_le_bytes([it[0], it[1], it[2], it[3], it[4], it[5], it[6], it[7]])
+            }).collect())),
+            _ => None,
+        }
+    }
+
+    /// Keys of a switch payload with their branch targets (relative to the switch instruction), in the
+    /// order of the payload. None for array data.
+    pub fn switch_cases(&self) -> Option<Vec<(i32, i32)>> {
+        match self {
+            Payload::PackedSwitch { first_key, targets } => Some(targets.iter().enumerate()
+                .map(|(i, target)| (first_key.wrapping_add(i as i32), *target))
+                .collect()),
+            Payload::SparseSwitch { keys, targets } => Some(keys.iter().copied().zip(targets.iter().copied()).collect()),
+            Payload::FillArrayData { .. } => None,
+        }
+    }
+}
+
+/// Iterator over the instructions of an instruction array, yielding (pc, Instruction) pairs.
+/// Stops after the first decoding error.
+pub struct Instructions<'a> {
+    insns: &'a [u16],
+    pc: usize,
+    failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+    pub fn new(insns: &'a [u16]) -> Instructions<'a> {
+        Instructions { insns, pc: 0, failed: false }
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<(usize, Instruction), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.pc >= self.insns.len() {
+            return None;
+        }
+        let pc = self.pc;
+        match Instruction::decode(self.insns, pc) {
+            Ok(insn) => {
+                self.pc += insn.size;
+                Some(Ok((pc, insn)))
+            }
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Instruction at the given pc extends past the end of the instruction array
+    Truncated(usize),
+    /// A switch or fill-array-data instruction references a pc without payload
+    NotAPayload(usize),
+}
+
+impl core::error::Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Truncated(pc) => write!(f, "Truncated instruction at 0x{:04x}", pc),
+            NotAPayload(pc) => write!(f, "No payload at 0x{:04x}", pc),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// An operand (vA, vB, ...) does not fit its field in the format of the opcode
+    OutOfRange { opcode: u8, operand: &'static str, value: i64 },
+    /// The opcode is not assigned to an instruction
+    UnusedOpcode(u8),
+    /// Payload pseudo-instructions are encoded from their Payload
+    Payload,
+    /// Contents that cannot be encoded as payload
+    InvalidPayload(&'static str),
+}
+
+impl core::error::Error for EncodeError {}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutOfRange { opcode, operand, value } => {
+                write!(f, "Operand {} of {} out of range: {}", operand, OPCODES[*opcode as usize].name, value)
+            }
+            UnusedOpcode(opcode) => write!(f, "Unused opcode 0x{:02x}", opcode),
+            EncodeError::Payload => f.write_str("Payload pseudo-instructions are encoded from their Payload"),
+            InvalidPayload(reason) => write!(f, "Invalid payload: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operands(insn: &Instruction) -> (u32, u32, u32, u32, u64, [u8; 5], usize) {
+        (insn.a, insn.b, insn.c, insn.h, insn.wide_b, insn.args, insn.size)
+    }
+
+    /// The instruction of `opcode` with the largest (or most negative) operands its format holds
+    fn extreme(opcode: u8) -> Instruction {
+        let mut insn = Instruction::new(opcode);
+        let (a, b, c) = match insn.format() {
+            Format::F10x => (0, 0, 0),
+            Format::F12x => (15, 15, 0),
+            Format::F11n => (15, -8i32 as u32, 0),
+            Format::F11x => (255, 0, 0),
+            Format::F10t => (-128i32 as u32, 0, 0),
+            Format::F20t => (-32768i32 as u32, 0, 0),
+            Format::F22x | Format::F21h | Format::F21c => (255, 0xffff, 0),
+            Format::F21t | Format::F21s => (255, -32768i32 as u32, 0),
+            Format::F23x => (255, 254, 253),
+            Format::F22b => (255, 254, -128i32 as u32),
+            Format::F22t | Format::F22s => (15, 14, -32768i32 as u32),
+            Format::F22c => (15, 14, 0xffff),
+            Format::F30t => (i32::MIN as u32, 0, 0),
+            Format::F32x => (0xffff, 0xfffe, 0),
+            Format::F31i | Format::F31t | Format::F31c => (255, 0x80000001, 0),
+            Format::F35c | Format::F45cc => {
+                insn.args = [1, 2, 3, 4, 15];
+                (5, 0xffff, 0)
+            }
+            Format::F3rc | Format::F4rcc => (255, 0xffff, 0xffff - 254),
+            Format::F51l => {
+                insn.wide_b = 0x8000_0000_0000_0001;
+                (255, 0, 0)
+            }
+        };
+        if matches!(insn.format(), Format::F45cc | Format::F4rcc) {
+            insn.h = 0xffff;
+        }
+        insn.a = a;
+        insn.b = b;
+        insn.c = c;
+        insn
+    }
+
+    #[test]
+    fn encode_decode_every_opcode() {
+        for opcode in 0..=255u8 {
+            let insn = extreme(opcode);
+            let units = match insn.encode() {
+                Ok(units) => units,
+                Err(err) => {
+                    assert!(insn.name().starts_with("unused-"), "{}: {}", insn.name(), err);
+                    assert!(matches!(err, UnusedOpcode(it) if it == opcode));
+                    continue;
+                }
+            };
+            assert_eq!(units.len(), insn.format().size(), "{}", insn.name());
+            let decoded = Instruction::decode(&units, 0).unwrap();
+            assert_eq!((decoded.opcode, operands(&decoded)), (opcode, operands(&insn)), "{}", insn.name());
+        }
+    }
+
+    #[test]
+    fn operands_out_of_range() {
+        let with = |opcode: u8, set: fn(&mut Instruction)| {
+            let mut insn = extreme(opcode);
+            set(&mut insn);
+            insn.encode()
+        };
+        // move vA (4 bits), const/4 #+B (4 bits signed), goto +AA (8 bits signed), add-int/lit8 #+CC
+        assert!(matches!(with(0x01, |it| it.a = 16), Err(OutOfRange { operand: "vA", value: 16, .. })));
+        assert!(matches!(with(0x12, |it| it.b = 8), Err(OutOfRange { operand: "vB", value: 8, .. })));
+        assert!(matches!(with(0x28, |it| it.a = -129i32 as u32), Err(OutOfRange { operand: "vA", value: -129, .. })));
+        assert!(matches!(with(0xd8, |it| it.c = 128), Err(OutOfRange { operand: "vC", value: 128, .. })));
+        // invoke-virtual with 6 arguments or a 5th above v15, invoke-virtual/range past v65535
+        assert!(matches!(with(0x6e, |it| it.a = 6), Err(OutOfRange { operand: "vA", .. })));
+        assert!(matches!(with(0x6e, |it| it.args[4] = 16), Err(OutOfRange { operand: "vG", .. })));
+        assert!(matches!(with(0x74, |it| it.c = 0xffff), Err(OutOfRange { operand: "last register", .. })));
+        let payload = Instruction { payload: Some(PayloadKind::PackedSwitch), ..Instruction::new(0) };
+        assert!(matches!(payload.encode(), Err(EncodeError::Payload)));
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let payloads = [
+            Payload::PackedSwitch { first_key: -1, targets: vec![3, -4, i32::MAX] },
+            Payload::SparseSwitch { keys: vec![i32::MIN, 0, 7], targets: vec![5, 6, -7] },
+            Payload::FillArrayData { element_width: 1, data: vec![1, 2, 3] },
+            Payload::FillArrayData { element_width: 8, data: (0..16).collect() },
+            Payload::PackedSwitch { first_key: 0, targets: Vec::new() },
+        ];
+        for payload in payloads {
+            let units = payload.encode().unwrap();
+            let insn = Instruction::decode(&units, 0).unwrap();
+            assert_eq!(insn.size, units.len());
+            assert_eq!(format!("{:?}", Payload::decode(&units, 0).unwrap()), format!("{:?}", payload));
+        }
+        assert!(matches!(Payload::SparseSwitch { keys: vec![1, 1], targets: vec![0, 0] }.encode(), Err(InvalidPayload(_))));
+        assert!(matches!(Payload::SparseSwitch { keys: vec![1], targets: Vec::new() }.encode(), Err(InvalidPayload(_))));
+        assert!(matches!(Payload::FillArrayData { element_width: 2, data: vec![1] }.encode(), Err(InvalidPayload(_))));
+        assert!(matches!(Payload::FillArrayData { element_width: 0, data: Vec::new() }.encode(), Err(InvalidPayload(_))));
+        // Size of the elements past the end of the instructions
+        assert!(matches!(Payload::decode(&[FILL_ARRAY_DATA_PAYLOAD, 4, 2, 0, 0], 0), Err(Truncated(0))));
+        assert!(matches!(Payload::decode(&[0x000e], 0), Err(NotAPayload(0))));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+Input abstraction of the parser. With the std feature these are the std::io types, so any std reader
+can be parsed. Without it, a minimal subset of the same API is provided (enough to parse from a byte
+slice through Cursor), so the parser itself does not depend on std.
+ */
+
+use crate::prelude::*;
+
+#[cfg(feature = "std")]
+pub use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+/// Readers that can seek, usable as trait object (e.g. `&mut dyn ReadSeek`) to choose the input at runtime
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Reads until the end of the reader
+pub fn read_to_end<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(data),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            #[cfg(feature = "std")]
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use core::fmt;
+
+    use crate::prelude::*;
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ErrorKind {
+        UnexpectedEof,
+        InvalidInput,
+        InvalidData,
+        Other,
+    }
+
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: String,
+    }
+
+    impl Error {
+        pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+            Error { kind, message: message.into() }
+        }
+
+        pub fn other(message: impl Into<String>) -> Error {
+            Error::new(ErrorKind::Other, message)
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.message)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum SeekFrom {
+        Start(u64),
+        End(i64),
+        Current(i64),
+    }
+
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                    n => buf = &mut buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub trait Seek {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+
+        fn stream_position(&mut self) -> Result<u64> {
+            self.seek(SeekFrom::Current(0))
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<S: Seek + ?Sized> Seek for &mut S {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            (**self).seek(pos)
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = buf.len().min(self.len());
+            let (head, tail) = self.split_at(n);
+            buf[..n].copy_from_slice(head);
+            *self = tail;
+            Ok(n)
+        }
+    }
+
+    /// In-memory reader over a byte buffer
+    #[derive(Debug, Clone)]
+    pub struct Cursor<T> {
+        inner: T,
+        pos: u64,
+    }
+
+    impl<T> Cursor<T> {
+        pub fn new(inner: T) -> Cursor<T> {
+            Cursor { inner, pos: 0 }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        pub fn position(&self) -> u64 {
+            self.pos
+        }
+
+        pub fn set_position(&mut self, pos: u64) {
+            self.pos = pos;
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = (self.pos as usize).min(data.len());
+            let n = buf.len().min(data.len() - start);
+            buf[..n].copy_from_slice(&data[start..start + n]);
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Seek for Cursor<T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            let (base, offset) = match pos {
+                SeekFrom::Start(offset) => {
+                    self.pos = offset;
+                    return Ok(offset);
+                }
+                SeekFrom::End(offset) => (self.inner.as_ref().len() as u64, offset),
+                SeekFrom::Current(offset) => (self.pos, offset),
+            };
+            match base.checked_add_signed(offset) {
+                Some(pos) => {
+                    self.pos = pos;
+                    Ok(pos)
+                }
+                None => Err(Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+            }
+        }
+    }
+}
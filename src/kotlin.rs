@@ -0,0 +1,153 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use crate::class::{Class, Method};
+use crate::dex_file::{DexFile, ACC_STATIC};
+use crate::prelude::*;
+
+/*
+Constructs that kotlinc generates for the declarations of a Kotlin class, attributed to the declaration
+they belong to:
+* the companion object `Foo$Companion`, the implementations of default methods of interfaces
+  `Foo$DefaultImpls` and the switch maps of `when` over enums `Foo$WhenMappings`, owned by the class Foo
+* `name$default`, the static method calling `name` with the default values of the omitted arguments
+  (and the constructor with a trailing DefaultConstructorMarker for constructors), owned by `name`
+* the getters and setters of properties, owned by the backing field, and `getName$annotations`, the
+  method holding the annotations of a property, owned by the property
+Accessors are only recognized in classes annotated with kotlin.Metadata (R8 removes it from classes that
+are not kept), accessors of properties without a backing field are not recognized.
+ */
+
+const KOTLIN_METADATA: &str = "Lkotlin/Metadata;";
+const DEFAULT_CONSTRUCTOR_MARKER: &str = "Lkotlin/jvm/internal/DefaultConstructorMarker;";
+
+/// Kind of a construct generated by kotlinc
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Construct {
+    Companion,
+    DefaultImpls,
+    WhenMappings,
+    DefaultArguments,
+    Getter,
+    Setter,
+    PropertyAnnotations,
+}
+
+/// A construct and its logical owner: the descriptor of the class owning a class, the name of the
+/// method or property owning a method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owned {
+    pub construct: Construct,
+    pub owner: String,
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Construct::Companion => "companion",
+            Construct::DefaultImpls => "default_impls",
+            Construct::WhenMappings => "when_mappings",
+            Construct::DefaultArguments => "default_arguments",
+            Construct::Getter => "getter",
+            Construct::Setter => "setter",
+            Construct::PropertyAnnotations => "property_annotations",
+        })
+    }
+}
+
+/// Candidates for the name of the property of an accessor without its prefix (`Name` of `getName`)
+fn property_names(capitalized: &str) -> Vec<String> {
+    let mut chars = capitalized.chars();
+    let decapitalized = match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => return Vec::new(),
+    };
+    vec![decapitalized, capitalized.to_owned()]
+}
+
+impl Class<'_> {
+    /// Whether kotlinc compiled the class, i.e. it is annotated with kotlin.Metadata
+    pub fn is_kotlin(&self) -> bool {
+        self.annotations().iter().any(|it| it.type_descriptor == KOTLIN_METADATA)
+    }
+
+    /// The construct and its owner class if the class is a companion object, DefaultImpls or
+    /// WhenMappings class
+    pub fn kotlin_owner(&self) -> Option<Owned> {
+        let name = self.descriptor().strip_suffix(';')?;
+        let (outer, inner) = name.rsplit_once('$')?;
+        let construct = match inner {
+            "Companion" => Construct::Companion,
+            "DefaultImpls" => Construct::DefaultImpls,
+            "WhenMappings" => Construct::WhenMappings,
+            _ => return None,
+        };
+        Some(Owned { construct, owner: format!("{};", outer) })
+    }
+}
+
+impl Method<'_> {
+    /// The construct and the name of its owning method or property if the method is generated for a
+    /// method or property
+    pub fn kotlin_owner(&self) -> Option<Owned> {
+        let dex = self.class.dex();
+        let name = self.name();
+        let proto_idx = dex.method_ids[self.method_idx as usize].proto_idx as u32;
+        let parameters = dex.proto_parameters(proto_idx);
+        let static_method = self.encoded.access_flags & ACC_STATIC != 0;
+        let owned = |construct, owner: &str| Some(Owned { construct, owner: owner.to_owned() });
+
+        if let Some(owner) = name.strip_suffix("$default").filter(|_| static_method) {
+            return owned(Construct::DefaultArguments, owner);
+        }
+        if name == "<init>" && parameters.last() == Some(&DEFAULT_CONSTRUCTOR_MARKER) {
+            return owned(Construct::DefaultArguments, name);
+        }
+        if let Some(property) = name.strip_prefix("get").and_then(|it| it.strip_suffix("$annotations")).filter(|_| static_method) {
+            return property_names(property).first().and_then(|it| owned(Construct::PropertyAnnotations, it));
+        }
+        if static_method || !self.class.is_kotlin() {
+            return None;
+        }
+
+        let return_type = dex.type_descriptor(dex.proto_ids[proto_idx as usize].return_type_idx);
+        let (construct, candidates, field_type) = match (name.strip_prefix("get"), name.strip_prefix("set"), &parameters[..]) {
+            (Some(property), _, []) if return_type != "V" => (Construct::Getter, property_names(property), return_type),
+            (_, Some(property), [field_type]) if return_type == "V" => {
+                let mut candidates = property_names(property);
+                candidates.push(format!("is{}", property));
+                (Construct::Setter, candidates, *field_type)
+            }
+            _ if name.starts_with("is") && parameters.is_empty() && return_type == "Z" => (Construct::Getter, vec![name.to_owned()], return_type),
+            _ => return None,
+        };
+        self.class.fields().into_iter()
+            .find(|field| candidates.iter().any(|it| it == field.name()) && dex.field_type(field.field_idx) == field_type)
+            .and_then(|field| owned(construct, field.name()))
+    }
+}
+
+impl DexFile {
+    /// Indices into class_defs of all classes, each class followed by the companion object, DefaultImpls
+    /// and WhenMappings classes it owns. Classes whose owner is not defined keep their position.
+    pub fn kotlin_grouped_classes(&self) -> Vec<usize> {
+        let mut owned: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut roots = Vec::new();
+        for class in self.classes() {
+            let owner = class.kotlin_owner()
+                .and_then(|it| self.find_class(&it.owner))
+                .filter(|it| it.class_def_idx() != class.class_def_idx());
+            match owner {
+                Some(owner) => owned.entry(owner.class_def_idx()).or_default().push(class.class_def_idx()),
+                None => roots.push(class.class_def_idx()),
+            }
+        }
+        let mut order = Vec::with_capacity(self.class_defs.len());
+        let mut pending: Vec<usize> = roots.into_iter().rev().collect();
+        while let Some(class_def_idx) = pending.pop() {
+            order.push(class_def_idx);
+            pending.extend(owned.remove(&class_def_idx).into_iter().flatten().rev());
+        }
+        order
+    }
+}
@@ -0,0 +1,88 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+/*
+Without the std feature the parser (raw_dex, m_utf8, dex_file, compact, instructions), the analyses of the
+parsed model (class, hierarchy, class_path, stubs, generated, kotlin, semantic_hash, hooks, verifier) and
+the editor (editor, code_builder, redirect, dex_version) are available. They then only need alloc and
+parse from a byte slice through io::Cursor.
+ */
+
+extern crate alloc;
+
+/// Items of the std prelude that are not in the core prelude
+mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::string::String;
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
+pub mod io;
+pub mod error;
+pub mod checked;
+pub mod raw_dex;
+pub mod m_utf8;
+pub mod dex_file;
+pub mod compact;
+pub mod class;
+pub mod hierarchy;
+pub mod class_path;
+pub mod stubs;
+pub mod generated;
+pub mod kotlin;
+pub mod instructions;
+pub mod code_builder;
+pub mod semantic_hash;
+pub mod hooks;
+pub mod verifier;
+pub mod editor;
+pub mod redirect;
+pub mod dex_version;
+#[cfg(feature = "std")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod atomic;
+#[cfg(feature = "std")]
+pub mod string_pool;
+#[cfg(feature = "apk")]
+pub mod apk;
+#[cfg(feature = "apk")]
+pub mod app;
+#[cfg(feature = "index")]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod dexdump;
+#[cfg(feature = "std")]
+pub mod dexdeps;
+#[cfg(feature = "std")]
+pub mod smali;
+#[cfg(feature = "std")]
+pub mod cfg;
+#[cfg(feature = "std")]
+pub mod types;
+#[cfg(feature = "std")]
+pub mod dataflow;
+#[cfg(feature = "std")]
+pub mod decompiler;
+#[cfg(feature = "std")]
+pub mod emulator;
+#[cfg(feature = "std")]
+pub mod highlight;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod table;
+#[cfg(feature = "std")]
+pub mod tamper;
+#[cfg(feature = "std")]
+pub mod entropy;
+#[cfg(feature = "std")]
+pub mod embedded;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod listing;
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixture;
@@ -0,0 +1,680 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "apk")]
+use std::collections::BTreeSet;
+
+#[cfg(feature = "apk")]
+use crate::app::AppDex;
+use crate::class::{Class, Method};
+use crate::dex_file::{self, DexFile, NO_INDEX};
+use crate::dex_version;
+use crate::editor::Stripped;
+use crate::embedded;
+use crate::entropy;
+use crate::export;
+use crate::hooks::Migration;
+use crate::raw_dex::DexHeader;
+use crate::redirect::Redirection;
+use crate::table::Table;
+use crate::tamper;
+use crate::verifier;
+
+/*
+Listings of the items of a dex file. Columns are stable, see Table. Rows are in index order of the
+items (or file order of the sections), so listings of the same file are byte-identical across runs.
+ */
+
+fn optional_string(dex: &DexFile, string_idx: u32) -> String {
+    if string_idx == NO_INDEX { String::new() } else { dex.string(string_idx).to_string() }
+}
+
+fn optional_type(dex: &DexFile, type_idx: u32) -> String {
+    if type_idx == NO_INDEX { String::new() } else { dex.type_descriptor(type_idx).to_string() }
+}
+
+/// Methods defined by the dex file, by method_idx
+fn defined_methods(dex: &DexFile) -> BTreeMap<u32, Method<'_>> {
+    dex.classes().flat_map(|it| it.methods()).map(|it| (it.method_idx, it)).collect()
+}
+
+/// Offset in the file of the code unit at `pc` of a method, see Method::file_offset_of
+fn file_offset(methods: &BTreeMap<u32, Method>, method_idx: u32, pc: usize) -> String {
+    methods.get(&method_idx).and_then(|it| it.file_offset_of(pc as u32)).map(|it| format!("0x{:08x}", it)).unwrap_or_default()
+}
+
+pub const STRINGS_COLUMNS: &[&str] = &["index", "value", "offset", "utf16_size", "code_references"];
+pub const CLASSES_COLUMNS: &[&str] = &["index", "class", "superclass", "access_flags", "source_file", "fields", "methods", "insns_size"];
+pub const METHODS_COLUMNS: &[&str] = &["index", "class", "name", "signature", "defined", "access_flags", "insns_size"];
+pub const VERIFY_COLUMNS: &[&str] = &["method_index", "method", "pc", "problem", "file_offset"];
+pub const XREFS_COLUMNS: &[&str] = &["method_index", "method", "pc", "kind", "target", "target_name", "file_offset"];
+
+/// Columns: index, value, offset, utf16_size, code_references.
+/// offset is the file offset of the string data item, code_references counts const-string instructions.
+pub fn strings(dex: &DexFile) -> Table {
+    let mut references = vec![0usize; dex.strings.len()];
+    for reference in export::references(dex).into_iter().filter(|it| it.kind == "string") {
+        if let Some(count) = references.get_mut(reference.target as usize) {
+            *count += 1;
+        }
+    }
+    let mut table = Table::new(STRINGS_COLUMNS);
+    for (idx, value) in dex.strings.iter().enumerate() {
+        table.push(vec![
+            idx.to_string(),
+            value.clone(),
+            format!("0x{:08x}", dex.string_data_offs[idx]),
+            value.encode_utf16().count().to_string(),
+            references[idx].to_string(),
+        ]);
+    }
+    table
+}
+
+/// Columns: index, class, superclass, access_flags, source_file, fields, methods, insns_size.
+/// insns_size is the number of code units of all methods of the class.
+pub fn classes(dex: &DexFile) -> Table {
+    let mut table = Table::new(CLASSES_COLUMNS);
+    for class in dex.classes() {
+        let class_def = class.def();
+        let insns_size: usize = class.methods().iter()
+            .filter_map(|it| dex.code_item(it.encoded.code_off))
+            .map(|it| it.insns.len())
+            .sum();
+        table.push(vec![
+            class.class_def_idx().to_string(),
+            class.descriptor().to_string(),
+            optional_type(dex, class_def.superclass_idx),
+            format!("0x{:04x}", class_def.access_flags),
+            optional_string(dex, class_def.source_file_idx),
+            class.fields().len().to_string(),
+            class.methods().len().to_string(),
+            insns_size.to_string(),
+        ]);
+    }
+    table
+}
+
+/// Columns: index, class, name, signature, defined, access_flags, insns_size.
+/// access_flags and insns_size are empty for methods that are not defined in the dex file.
+pub fn methods(dex: &DexFile) -> Table {
+    let mut definitions = vec![None; dex.method_ids.len()];
+    for class_data in dex.class_data.iter().flatten() {
+        for methods in [&class_data.direct_methods, &class_data.virtual_methods] {
+            for (method, idx) in methods.iter().zip(dex_file::method_indices(methods)) {
+                if let Some(definition) = definitions.get_mut(idx as usize) {
+                    *definition = Some(method);
+                }
+            }
+        }
+    }
+
+    let mut table = Table::new(METHODS_COLUMNS);
+    for (idx, definition) in definitions.iter().enumerate() {
+        let method_idx = idx as u32;
+        let insns_size = definition.and_then(|it| dex.code_item(it.code_off)).map(|it| it.insns.len().to_string());
+        table.push(vec![
+            idx.to_string(),
+            dex.method_class(method_idx).to_string(),
+            dex.method_name(method_idx).to_string(),
+            dex.method_signature(method_idx),
+            definition.is_some().to_string(),
+            definition.map(|it| format!("0x{:04x}", it.access_flags)).unwrap_or_default(),
+            insns_size.unwrap_or_default(),
+        ]);
+    }
+    table
+}
+
+/// Columns: method_index, method, pc, kind, target, target_name, file_offset.
+/// One row per instruction referencing a string, type, field, method or proto, ordered by method and pc.
+pub fn xrefs(dex: &DexFile) -> Table {
+    let mut table = Table::new(XREFS_COLUMNS);
+    let methods = defined_methods(dex);
+    for reference in export::references(dex) {
+        let method_idx = reference.method_idx;
+        table.push(vec![
+            method_idx.to_string(),
+            format!("{}->{}{}", dex.method_class(method_idx), dex.method_name(method_idx), dex.method_signature(method_idx)),
+            reference.pc.to_string(),
+            reference.kind.to_string(),
+            reference.target.to_string(),
+            export::target_name(dex, &reference),
+            file_offset(&methods, method_idx, reference.pc),
+        ]);
+    }
+    table
+}
+
+/// Columns: key, value
+pub fn stats(dex: &DexFile) -> Table {
+    let mut table = Table::new(&["key", "value"]);
+    let mut push = |key: &str, value: String| table.push(vec![key.to_string(), value]);
+    push("version", dex.version().to_string());
+    push("file_size", dex.header.file_size.to_string());
+    push("strings", dex.strings.len().to_string());
+    push("types", dex.type_ids.len().to_string());
+    push("protos", dex.proto_ids.len().to_string());
+    push("fields", dex.field_ids.len().to_string());
+    push("methods", dex.method_ids.len().to_string());
+    push("classes", dex.class_defs.len().to_string());
+    push("code_items", dex.code_items.len().to_string());
+    push("insns_size", dex.code_items.values().map(|it| it.insns.len()).sum::<usize>().to_string());
+    push("debug_info_items", dex.debug_info.len().to_string());
+    push("link_size", dex.link_data.len().to_string());
+    table
+}
+
+/// Size in bytes with the largest binary unit keeping it at least 1, e.g. `1.5 KiB`
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Whether a stored value matches the one computed from the file
+fn validity(valid: bool) -> &'static str {
+    if valid { "valid" } else { "INVALID" }
+}
+
+/// Columns: field, value. Fields of the header of the dex file `data` with the version, endianness,
+/// sizes and the validity of the checksum and signature decoded.
+pub fn header(dex: &DexFile, data: &[u8]) -> Table {
+    let header = &dex.header;
+    let mut table = Table::new(&["field", "value"]);
+    let mut push = |field: &str, value: String| table.push(vec![field.to_string(), value]);
+    push("magic", header.magic.iter().map(|it| format!("{:02x}", it)).collect::<Vec<_>>().join(" "));
+    let required = dex_version::required_version(&dex_version::feature_uses(dex));
+    push("version", if required <= dex.version() {
+        dex.version().to_string()
+    } else {
+        format!("{} (features used need {:03}, see the features command)", dex.version(), required)
+    });
+    push("endianness", match DexHeader::verify_endian(header.endian_tag) {
+        Ok(scroll::Endian::Little) => format!("little (0x{:08x})", header.endian_tag),
+        Ok(scroll::Endian::Big) => format!("big (0x{:08x})", header.endian_tag),
+        Err(_) => format!("invalid (0x{:08x})", header.endian_tag),
+    });
+    let checksum = DexHeader::compute_checksum(data);
+    push("checksum", if checksum == header.checksum {
+        format!("0x{:08x} ({})", header.checksum, validity(true))
+    } else {
+        format!("0x{:08x} ({}, computed 0x{:08x})", header.checksum, validity(false), checksum)
+    });
+    let signature: String = header.signature.iter().map(|it| format!("{:02x}", it)).collect();
+    #[cfg(feature = "index")]
+    let signature = {
+        let computed = sha1_smol::Sha1::from(data.get(DexHeader::SIGNATURE_END..).unwrap_or_default()).digest().bytes();
+        format!("{} ({})", signature, validity(computed == header.signature))
+    };
+    push("signature", signature);
+    push("file_size", if header.file_size as usize == data.len() {
+        format!("{} ({})", header.file_size, human_size(header.file_size.into()))
+    } else {
+        format!("{} ({}, file has {} bytes)", header.file_size, human_size(header.file_size.into()), data.len())
+    });
+    push("header_size", header.header_size.to_string());
+    push("link", if dex.link_data.is_empty() {
+        format!("{} at 0x{:08x}", header.link_size, header.link_off)
+    } else {
+        format!("{} at 0x{:08x} (present, unused by the runtime)", header.link_size, header.link_off)
+    });
+    let mut section = |name: &str, size: u32, off: u32| push(name, format!("{} at 0x{:08x}", size, off));
+    section("string_ids", header.string_ids_size, header.string_ids_off);
+    section("type_ids", header.type_ids_size, header.type_ids_off);
+    section("proto_ids", header.proto_ids_size, header.proto_ids_off);
+    section("field_ids", header.field_ids_size, header.field_ids_off);
+    section("method_ids", header.method_ids_size, header.method_ids_off);
+    section("class_defs", header.class_defs_size, header.class_defs_off);
+    push("data", format!("{} at 0x{:08x} ({})", header.data_size, header.data_off, human_size(header.data_size.into())));
+    push("map_off", format!("0x{:08x}", header.map_off));
+    push("map_items", dex.map_list.len().to_string());
+    push("defined_methods", dex.class_data.iter().flatten()
+        .map(|it| it.direct_methods.len() + it.virtual_methods.len())
+        .sum::<usize>().to_string());
+    push("defined_fields", dex.class_data.iter().flatten()
+        .map(|it| it.static_fields.len() + it.instance_fields.len())
+        .sum::<usize>().to_string());
+    push("code_items", dex.code_items.len().to_string());
+    table
+}
+
+/// Columns: check, result, weight, detail. One row per check of tamper::tamper_report on the dex file
+/// `data`, followed by a row with the verdict and the score (the weights of the failed checks).
+pub fn tamper(dex: &DexFile, data: &[u8]) -> Table {
+    let report = tamper::tamper_report(dex, data);
+    let mut table = Table::new(&["check", "result", "weight", "detail"]);
+    for check in &report.checks {
+        let result = match check.passed {
+            Some(true) => "passed",
+            Some(false) => "FAILED",
+            None => "unchecked",
+        };
+        table.push(vec![check.name.to_string(), result.to_string(), check.weight.to_string(), check.detail.clone()]);
+    }
+    table.push(vec![
+        "score".to_string(),
+        format!("modification {}", report.verdict()),
+        report.score().to_string(),
+        "sum of the weights of the failed checks, out of 100".to_string(),
+    ]);
+    table
+}
+
+/// Columns: section, offset, byte_size, entropy, high. The Shannon entropy (bits per byte) of the data
+/// section and of each map item of the dex file `data`, followed by the high entropy regions of the
+/// data section (section `window`), see entropy. high is `true` for likely compressed or encrypted data.
+pub fn entropy(dex: &DexFile, data: &[u8]) -> Table {
+    let mut table = Table::new(&["section", "offset", "byte_size", "entropy", "high"]);
+    for range in entropy::section_entropy(dex, data).into_iter().chain(entropy::high_entropy_regions(dex, data)) {
+        table.push(vec![
+            range.name.to_string(),
+            format!("0x{:08x}", range.range.start),
+            range.range.len().to_string(),
+            format!("{:.3}", range.entropy),
+            range.is_high().to_string(),
+        ]);
+    }
+    table
+}
+
+/// Columns: offset, kind, location, byte_size, detail. One row per candidate dex file or zip archive
+/// embedded in the dex file `data`, see embedded::find_embedded.
+pub fn embedded(dex: &DexFile, data: &[u8]) -> Table {
+    let mut table = Table::new(&["offset", "kind", "location", "byte_size", "detail"]);
+    for payload in embedded::find_embedded(dex, data) {
+        table.push(vec![
+            format!("0x{:08x}", payload.range.start),
+            payload.kind.to_string(),
+            payload.location.to_string(),
+            payload.range.len().to_string(),
+            payload.detail,
+        ]);
+    }
+    table
+}
+
+/// Columns: path, kind, location, byte_size, detail, tamper_score, tamper_verdict, high_entropy_bytes.
+/// One row per payload of embedded::analyze_nested, each followed by its own payloads. The tamper and
+/// entropy columns are empty for archives and dex files that do not parse.
+pub fn embedded_nested(dex: &DexFile, data: &[u8]) -> Table {
+    let mut table = Table::new(&["path", "kind", "location", "byte_size", "detail", "tamper_score", "tamper_verdict", "high_entropy_bytes"]);
+    for payload in embedded::analyze_nested(dex, data) {
+        let (score, verdict, high_entropy) = match &payload.tamper {
+            Some(report) => (
+                report.score().to_string(),
+                report.verdict().to_string(),
+                payload.high_entropy.iter().map(|it| it.range.len()).sum::<usize>().to_string(),
+            ),
+            None => (String::new(), String::new(), String::new()),
+        };
+        table.push(vec![
+            payload.path,
+            payload.kind.to_string(),
+            payload.location.to_string(),
+            payload.data.len().to_string(),
+            payload.detail,
+            score,
+            verdict,
+            high_entropy,
+        ]);
+    }
+    table
+}
+
+/// Columns: type, name, count, offset, byte_size, percent. One row per item of the map list, in its
+/// order. The byte size of a section extends to the next section (or the end of the file), including
+/// alignment padding.
+pub fn map(dex: &DexFile) -> Table {
+    let file_size = dex.header.file_size;
+    let mut offsets: Vec<u32> = dex.map_list.iter().map(|it| it.offset).collect();
+    offsets.sort_unstable();
+    let mut table = Table::new(&["type", "name", "count", "offset", "byte_size", "percent"]);
+    for item in &dex.map_list {
+        let end = offsets.iter().find(|it| **it > item.offset).copied().unwrap_or(file_size).max(item.offset);
+        let byte_size = end - item.offset;
+        table.push(vec![
+            format!("0x{:04x}", item.item_type),
+            item.type_name().unwrap_or("unknown").to_string(),
+            item.size.to_string(),
+            format!("0x{:08x}", item.offset),
+            byte_size.to_string(),
+            format!("{:.1}", if file_size == 0 { 0.0 } else { byte_size as f64 * 100.0 / file_size as f64 }),
+        ]);
+    }
+    table
+}
+
+pub const SOURCES_COLUMNS: &[&str] = &["index", "class", "source_file", "path", "status"];
+
+/// Columns: index, class, source_file, path, status. path is the source file in the directory of
+/// the package of the class. status is `missing` if the class has no source file, `differs` if the
+/// name of the source file is not the one of the outermost class (stripped or faked attribution,
+/// but also e.g. Kotlin file facades) and `matches` otherwise.
+pub fn sources(dex: &DexFile) -> Table {
+    let mut table = Table::new(SOURCES_COLUMNS);
+    for class in dex.classes() {
+        let descriptor = class.descriptor();
+        let name = descriptor.trim_start_matches('L').trim_end_matches(';');
+        let (package, simple_name) = name.rsplit_once('/').unwrap_or(("", name));
+        let outer = simple_name.split('$').next().unwrap_or_default();
+        let source_file_idx = class.def().source_file_idx;
+        let (source_file, path, status) = if source_file_idx == NO_INDEX {
+            (String::new(), String::new(), "missing")
+        } else {
+            let source_file = dex.string(source_file_idx);
+            let stem = source_file.rsplit_once('.').map_or(source_file, |it| it.0);
+            let path = if package.is_empty() { source_file.to_string() } else { format!("{}/{}", package, source_file) };
+            (source_file.to_string(), path, if stem == outer { "matches" } else { "differs" })
+        };
+        table.push(vec![class.class_def_idx().to_string(), descriptor.to_string(), source_file, path, status.to_string()]);
+    }
+    table
+}
+
+pub const MEMBERS_COLUMNS: &[&str] = &["kind", "index", "name", "descriptor", "access_flags", "value", "insns_size"];
+
+/// Columns: kind, index, name, descriptor, access_flags, value, insns_size. Fields of a class (field
+/// type and initial value of static fields) followed by its methods (signature and number of code
+/// units, empty for methods without code).
+pub fn members(class: Class) -> Table {
+    let dex = class.dex();
+    let mut table = Table::new(MEMBERS_COLUMNS);
+    for field in class.fields() {
+        table.push(vec![
+            "field".to_string(),
+            field.field_idx.to_string(),
+            field.name().to_string(),
+            dex.field_type(field.field_idx).to_string(),
+            format!("0x{:04x}", field.encoded.access_flags),
+            field.static_value().map(|it| it.to_string()).unwrap_or_default(),
+            String::new(),
+        ]);
+    }
+    for method in class.methods() {
+        table.push(vec![
+            "method".to_string(),
+            method.method_idx.to_string(),
+            method.name().to_string(),
+            dex.method_signature(method.method_idx),
+            format!("0x{:04x}", method.encoded.access_flags),
+            String::new(),
+            dex.code_item(method.encoded.code_off).map(|it| it.insns.len().to_string()).unwrap_or_default(),
+        ]);
+    }
+    table
+}
+
+pub const GENERATED_COLUMNS: &[&str] = &["kind", "index", "item", "generated"];
+
+/// Columns: kind, index, item, generated. The classes and members generated by compilers, see
+/// generated::Generated, each class followed by its generated members. index is the class_def index
+/// of classes and the field or method index of members.
+pub fn generated(dex: &DexFile) -> Table {
+    let mut table = Table::new(GENERATED_COLUMNS);
+    for class in dex.classes() {
+        if let Some(generated) = class.generated() {
+            table.push(vec!["class".to_string(), class.class_def_idx().to_string(), class.descriptor().to_string(), generated.to_string()]);
+        }
+        for field in class.fields() {
+            if let Some(generated) = field.generated() {
+                let item = format!("{}->{}:{}", class.descriptor(), field.name(), dex.field_type(field.field_idx));
+                table.push(vec!["field".to_string(), field.field_idx.to_string(), item, generated.to_string()]);
+            }
+        }
+        for method in class.methods() {
+            if let Some(generated) = method.generated() {
+                let item = format!("{}->{}{}", class.descriptor(), method.name(), dex.method_signature(method.method_idx));
+                table.push(vec!["method".to_string(), method.method_idx.to_string(), item, generated.to_string()]);
+            }
+        }
+    }
+    table
+}
+
+pub const KOTLIN_COLUMNS: &[&str] = &["owner", "kind", "item"];
+
+/// Columns: owner, kind, item. The constructs generated by kotlinc grouped by their owner, see
+/// kotlin::Construct. owner is the owning class or `Lcls;->name` of the owning method or property,
+/// item the class or method.
+pub fn kotlin(dex: &DexFile) -> Table {
+    let mut rows = Vec::new();
+    for class in dex.classes() {
+        if let Some(owned) = class.kotlin_owner() {
+            rows.push(vec![owned.owner, owned.construct.to_string(), class.descriptor().to_string()]);
+        }
+        for method in class.methods() {
+            if let Some(owned) = method.kotlin_owner() {
+                let item = format!("{}->{}{}", class.descriptor(), method.name(), dex.method_signature(method.method_idx));
+                rows.push(vec![format!("{}->{}", class.descriptor(), owned.owner), owned.construct.to_string(), item]);
+            }
+        }
+    }
+    // Stable, the constructs of an owner stay in the order of the classes and methods
+    rows.sort_by(|a, b| a[0].cmp(&b[0]));
+    let mut table = Table::new(KOTLIN_COLUMNS);
+    for row in rows {
+        table.push(row);
+    }
+    table
+}
+
+pub const HASHES_COLUMNS: &[&str] = &["method_index", "method", "semantic_hash"];
+pub const APP_COLUMNS: &[&str] = &["index", "apk", "dex", "version", "classes", "methods", "strings", "duplicate_classes", "module", "byte_size"];
+pub const HOOK_TARGETS_COLUMNS: &[&str] = &["target", "status", "proposed", "evidence", "candidates"];
+pub const STRIP_COLUMNS: &[&str] = &["item", "action"];
+pub const REDIRECT_COLUMNS: &[&str] = &["caller", "pc", "invoke"];
+pub const FEATURES_COLUMNS: &[&str] = &["feature", "version", "count", "first_use", "status"];
+pub const APP_CLASSES_COLUMNS: &[&str] = &["module", "apk", "dex", "class", "methods", "insns_size", "loaded"];
+
+/// Columns: method_index, method, semantic_hash. One row per method with code, in the order of the
+/// classes, see semantic_hash.
+pub fn hashes(dex: &DexFile) -> Table {
+    let mut table = Table::new(HASHES_COLUMNS);
+    for method in dex.classes().flat_map(|it| it.methods()) {
+        if let Some(hash) = method.semantic_hash() {
+            let method_idx = method.method_idx;
+            table.push(vec![
+                method_idx.to_string(),
+                format!("{}->{}{}", dex.method_class(method_idx), dex.method_name(method_idx), dex.method_signature(method_idx)),
+                format!("{:016x}", hash),
+            ]);
+        }
+    }
+    table
+}
+
+/// Columns: index, apk, dex, version, classes, methods, strings, duplicate_classes, module, byte_size.
+/// One row per dex file of an app in class loading order, see app. Duplicate classes are already
+/// defined by an earlier dex file, the runtime never loads them.
+#[cfg(feature = "apk")]
+pub fn app(dex_files: &[(&AppDex, &DexFile)]) -> Table {
+    let mut table = Table::new(APP_COLUMNS);
+    let mut defined = BTreeSet::new();
+    for (idx, (app_dex, dex)) in dex_files.iter().enumerate() {
+        let duplicates = dex.classes().filter(|class| !defined.insert(class.descriptor())).count();
+        table.push(vec![
+            idx.to_string(),
+            app_dex.apk.clone().unwrap_or_default(),
+            app_dex.name.clone(),
+            dex.version().to_string(),
+            dex.class_defs.len().to_string(),
+            dex.method_ids.len().to_string(),
+            dex.strings.len().to_string(),
+            duplicates.to_string(),
+            app_dex.module.clone().unwrap_or_default(),
+            app_dex.data.len().to_string(),
+        ]);
+    }
+    table
+}
+
+/// Columns: module, apk, dex, class, methods, insns_size, loaded. One row per class of each dex file of
+/// an app in class loading order, loaded is false for classes already defined by an earlier dex file.
+#[cfg(feature = "apk")]
+pub fn app_classes(dex_files: &[(&AppDex, &DexFile)]) -> Table {
+    let mut table = Table::new(APP_CLASSES_COLUMNS);
+    let mut defined = BTreeSet::new();
+    for (app_dex, dex) in dex_files {
+        for class in dex.classes() {
+            let methods = class.methods();
+            let insns_size: usize = methods.iter()
+                .filter_map(|it| dex.code_item(it.encoded.code_off))
+                .map(|it| it.insns.len())
+                .sum();
+            table.push(vec![
+                app_dex.module.clone().unwrap_or_default(),
+                app_dex.apk.clone().unwrap_or_default(),
+                app_dex.name.clone(),
+                class.descriptor().to_string(),
+                methods.len().to_string(),
+                insns_size.to_string(),
+                defined.insert(class.descriptor()).to_string(),
+            ]);
+        }
+    }
+    table
+}
+
+/// Columns: target, status, proposed, evidence, candidates. One row per migrated hook target, the
+/// candidates of ambiguous targets are separated by spaces, see hooks.
+pub fn hook_targets(migrations: &[Migration]) -> Table {
+    let mut table = Table::new(HOOK_TARGETS_COLUMNS);
+    for migration in migrations {
+        table.push(vec![
+            migration.target.clone(),
+            migration.status.to_string(),
+            migration.proposed.clone().unwrap_or_default(),
+            migration.evidence.map(|it| it.to_string()).unwrap_or_default(),
+            migration.candidates.join(" "),
+        ]);
+    }
+    table
+}
+
+/// Columns: item, action. One row per class or member removed or method stubbed by strip.
+pub fn strip(stripped: &Stripped) -> Table {
+    let mut table = Table::new(STRIP_COLUMNS);
+    for (item, action) in &stripped.items {
+        table.push(vec![item.clone(), action.to_string()]);
+    }
+    table
+}
+
+/// Columns: caller, pc, invoke. One row per invoke redirected to the trampoline.
+pub fn redirections(redirections: &[Redirection]) -> Table {
+    let mut table = Table::new(REDIRECT_COLUMNS);
+    for redirection in redirections {
+        table.push(vec![redirection.caller.clone(), format!("0x{:04x}", redirection.pc), redirection.invoke.to_owned()]);
+    }
+    table
+}
+
+/// Columns: feature, version, count, first_use, status. One row per feature of the dex format newer than
+/// 035 used by the dex file, see dex_version::feature_uses, UNSUPPORTED if the declared version is older.
+pub fn features(dex: &DexFile) -> Table {
+    let mut table = Table::new(FEATURES_COLUMNS);
+    for feature in dex_version::feature_uses(dex) {
+        table.push(vec![
+            feature.feature.to_owned(),
+            format!("{:03}", feature.version),
+            feature.count.to_string(),
+            feature.first,
+            if feature.version > dex.version() { "UNSUPPORTED" } else { "supported" }.to_owned(),
+        ]);
+    }
+    table
+}
+
+/// Columns: method_index, method, pc, problem, file_offset. One row per problem found by the verifier.
+pub fn verify(dex: &DexFile) -> Table {
+    let mut table = Table::new(VERIFY_COLUMNS);
+    let methods = defined_methods(dex);
+    for diagnostic in verifier::verify(dex) {
+        let method_idx = diagnostic.method_idx;
+        table.push(vec![
+            method_idx.to_string(),
+            format!("{}->{}{}", dex.method_class(method_idx), dex.method_name(method_idx), dex.method_signature(method_idx)),
+            format!("0x{:04x}", diagnostic.pc),
+            diagnostic.problem.to_string(),
+            file_offset(&methods, method_idx, diagnostic.pc),
+        ]);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Fixture};
+
+    fn rows(table: &Table) -> Vec<String> {
+        table.rows.iter().map(|it| it.join(",")).collect()
+    }
+
+    #[test]
+    fn strings_classes_methods() {
+        let dex = Fixture::greeting().parse();
+        let table = strings(&dex);
+        assert_eq!(table.columns, ["index", "value", "offset", "utf16_size", "code_references"]);
+        assert_eq!(rows(&table), [
+            "0,Fixture.java,0x00000100,12,0",
+            "1,Lcom/example/Fixture;,0x0000010e,21,0",
+            "2,Ljava/lang/Object;,0x00000125,18,0",
+            "3,V,0x00000139,1,0",
+            "4,greet,0x0000013c,5,0",
+            "5,hello,0x00000143,5,1",
+            "6,run,0x0000014a,3,0",
+        ]);
+
+        let table = classes(&dex);
+        assert_eq!(table.columns, ["index", "class", "superclass", "access_flags", "source_file", "fields", "methods", "insns_size"]);
+        assert_eq!(rows(&table), ["0,Lcom/example/Fixture;,Ljava/lang/Object;,0x0001,Fixture.java,0,2,4"]);
+
+        let table = methods(&dex);
+        assert_eq!(table.columns, ["index", "class", "name", "signature", "defined", "access_flags", "insns_size"]);
+        assert_eq!(rows(&table), [
+            "0,Lcom/example/Fixture;,greet,()V,true,0x0009,3",
+            "1,Lcom/example/Fixture;,run,()V,true,0x0001,1",
+        ]);
+    }
+
+    #[test]
+    fn undefined_methods() {
+        let mut editor = Fixture::new().editor();
+        editor.add_method_id("Ljava/lang/Object;", "<init>", "()V").unwrap();
+        let table = methods(&fixture::rewritten(&editor));
+        assert_eq!(rows(&table), [
+            "0,Lcom/example/Fixture;,run,()V,true,0x0001,1",
+            "1,Ljava/lang/Object;,<init>,()V,false,,",
+        ]);
+    }
+
+    #[test]
+    fn stats_of_fixture() {
+        let table = stats(&Fixture::greeting().parse());
+        assert_eq!(table.columns, ["key", "value"]);
+        assert_eq!(rows(&table), [
+            "version,35", "file_size,472", "strings,7", "types,3", "protos,1", "fields,0", "methods,2",
+            "classes,1", "code_items,2", "insns_size,4", "debug_info_items,0", "link_size,0",
+        ]);
+    }
+
+    #[test]
+    fn markdown() {
+        let mut out = Vec::new();
+        methods(&Fixture::greeting().parse()).write(crate::table::TableFormat::Markdown, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "| index | class | name | signature | defined | access_flags | insns_size |\n",
+            "| --- | --- | --- | --- | --- | --- | --- |\n",
+            "| 0 | Lcom/example/Fixture; | greet | ()V | true | 0x0009 | 3 |\n",
+            "| 1 | Lcom/example/Fixture; | run | ()V | true | 0x0001 | 1 |\n",
+        ));
+    }
+}
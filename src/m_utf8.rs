@@ -1,37 +1,56 @@
-use std::fmt;
-use std::fmt::Debug;
-use std::fs::File;
-use std::io::BufReader;
-use std::string::FromUtf16Error;
-use crate::m_utf8::LoadMUtf8StringError::{DecodeError, ReadError, Utf16ToStringError};
-
-use crate::m_utf8::MUtf8ParseError::{BadByte, BadSecondByte, BadSecondThirdByte};
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::io::Read;
+use crate::prelude::*;
+use crate::m_utf8::LoadMUtf8StringError::{DecodeError, ReadError};
+
+use crate::m_utf8::MUtf8ParseError::{BadByte, BadSecondByte, BadSecondThirdByte, LengthMismatch, MissingTerminator, UnpairedSurrogate};
 use crate::raw_dex::read_u8;
 
-#[derive(Debug)]
-// fixme Possible improvement: add position of seeker (absolute or relative?)
+/// Invalid MUTF-8, each variant holds the offset of the invalid sequence relative to the start of the
+/// decoded data
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MUtf8ParseError {
-    BadByte,
-    BadSecondByte,
-    BadSecondThirdByte,
+    BadByte(usize),
+    BadSecondByte(usize),
+    BadSecondThirdByte(usize),
+    /// A surrogate code unit that is not part of a high-low pair
+    UnpairedSurrogate { offset: usize, unit: u16 },
+    /// The data ends before the terminating NUL
+    MissingTerminator(usize),
+    /// The utf16_size before the data differs from the number of decoded UTF-16 code units
+    LengthMismatch { declared: u64, decoded: u64 },
 }
 
 #[derive(Debug)]
 pub enum LoadMUtf8StringError {
     DecodeError(MUtf8ParseError),
-    ReadError(std::io::Error),
-    Utf16ToStringError(FromUtf16Error),
+    ReadError(crate::io::Error),
+}
+
+impl MUtf8ParseError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            BadByte(offset) | BadSecondByte(offset) | BadSecondThirdByte(offset) | MissingTerminator(offset) => offset,
+            UnpairedSurrogate { offset, .. } => offset,
+            LengthMismatch { .. } => 0,
+        }
+    }
 }
 
-impl std::error::Error for MUtf8ParseError {}
-impl std::error::Error for LoadMUtf8StringError {}
+impl core::error::Error for MUtf8ParseError {}
+impl core::error::Error for LoadMUtf8StringError {}
 
 impl fmt::Display for MUtf8ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            BadByte => write!(f, "Bad byte"),
-            BadSecondByte => write!(f, "Bad second byte"),
-            BadSecondThirdByte => write!(f, "Bad second or third byte"),
+            BadByte(offset) => write!(f, "Bad byte at offset {}", offset),
+            BadSecondByte(offset) => write!(f, "Bad second byte at offset {}", offset),
+            BadSecondThirdByte(offset) => write!(f, "Bad second or third byte at offset {}", offset),
+            UnpairedSurrogate { offset, unit } => write!(f, "Unpaired surrogate 0x{:04x} at offset {}", unit, offset),
+            MissingTerminator(offset) => write!(f, "Missing terminating NUL at offset {}", offset),
+            LengthMismatch { declared, decoded } => write!(f, "Declared length {} does not match the decoded length {}", declared, decoded),
         }
     }
 }
@@ -39,47 +58,309 @@ impl fmt::Display for MUtf8ParseError {
 impl fmt::Display for LoadMUtf8StringError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DecodeError(d_err) => std::fmt::Display::fmt(&d_err, f),
-            ReadError(r_err) => std::fmt::Display::fmt(&r_err, f),
-            Utf16ToStringError(s_err) => std::fmt::Display::fmt(&s_err, f),
+            DecodeError(d_err) => fmt::Display::fmt(&d_err, f),
+            ReadError(r_err) => fmt::Display::fmt(&r_err, f),
         }
     }
 }
 
-pub fn to_string(reader: &mut BufReader<File>, size: u64) -> Result<String, LoadMUtf8StringError> {
-    // https://cs.android.com/android/platform/superproject/+/master:dalvik/dx/src/com/android/dex/Mutf8.java
-    let mut s = 0;
-    let mut out: Vec<u16> = vec![0u16; size as usize];
-    let mut buf = [0u8; 1];
-    loop {
-        let a = read_u8(reader, &mut buf).map_err(|r_err| ReadError(r_err))? as u16;
-        if a == 0 {
-            let string = String::from_utf16(&out.as_slice()[..s]).map_err(|s_err| Utf16ToStringError(s_err))?;
-            debug_assert!(s == size as usize,
-                          "Declared Length ({}) does not match decoded length ({})", size, s);
-            return Ok(string);
+/// A decoded string_data_item. Characters outside the Basic Multilingual Plane (e.g. emoji) are encoded
+/// as surrogate pairs, so the declared length in UTF-16 code units can differ from the number of chars.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MUtf8String {
+    pub value: String,
+    /// Length in UTF-16 code units as declared by the string_data_item
+    pub utf16_size: u64,
+}
+
+impl MUtf8String {
+    pub fn char_count(&self) -> usize {
+        self.value.chars().count()
+    }
+
+    /// Length of the decoded value in UTF-16 code units, equal to utf16_size in well-formed dex files
+    pub fn utf16_len(&self) -> usize {
+        self.value.encode_utf16().count()
+    }
+}
+
+/// A string decoded by to_string_lossy, with the invalid sequences that were replaced by U+FFFD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossyString {
+    pub string: MUtf8String,
+    pub errors: Vec<MUtf8ParseError>,
+}
+
+/// Decodes the NUL-terminated MUTF-8 `data` of a string_data_item (following its utf16_size). Bytes
+/// after the terminator are ignored, so `data` can extend to the end of the file.
+pub fn to_string(data: &[u8], utf16_size: u64) -> Result<String, MUtf8ParseError> {
+    decode(data, utf16_size).map(|it| it.value)
+}
+
+/// Decodes like `to_string`, keeping the declared length
+pub fn decode(data: &[u8], utf16_size: u64) -> Result<MUtf8String, MUtf8ParseError> {
+    decode_with(data, utf16_size, &mut Err)
+}
+
+/// Decodes like `to_string`, but replaces invalid sequences (e.g. deliberately emitted by obfuscators)
+/// with U+FFFD instead of failing
+pub fn to_string_lossy(data: &[u8], utf16_size: u64) -> LossyString {
+    let mut errors = Vec::new();
+    let string = decode_with(data, utf16_size, &mut |err| {
+        errors.push(err);
+        Ok(())
+    }).expect("Lossy decoding does not fail");
+    LossyString { string, errors }
+}
+
+/// Encodes `value` as MUTF-8 for a string_data_item, returns its length in UTF-16 code units (the
+/// utf16_size) and the bytes without the terminating NUL
+pub fn encode(value: &str) -> (u32, Vec<u8>) {
+    let mut out = Vec::new();
+    let mut units = 0;
+    for unit in value.encode_utf16() {
+        units += 1;
+        match unit {
+            0x01..=0x7f => out.push(unit as u8),
+            0x00 | 0x80..=0x7ff => out.extend([0xc0 | (unit >> 6) as u8, 0x80 | (unit & 0x3f) as u8]),
+            _ => out.extend([0xe0 | (unit >> 12) as u8, 0x80 | ((unit >> 6) & 0x3f) as u8, 0x80 | (unit & 0x3f) as u8]),
         }
-        out[s] = a as u16;
+    }
+    (units, out)
+}
 
-        if a < 0x80 {
-            s += 1;
+/// Whether the NUL-terminated MUTF-8 `raw` (string data after its utf16_size) decodes to `s`, without
+/// decoding into a String. Invalid MUTF-8 is never equal.
+pub fn eq_str(raw: &[u8], s: &str) -> bool {
+    let mut invalid = false;
+    let equal = Units::new(raw).map(|it| it.map(|(_, unit)| unit).unwrap_or_else(|_| {
+        invalid = true;
+        0xfffd
+    })).eq(s.encode_utf16());
+    equal && !invalid
+}
+
+/// Compares the NUL-terminated MUTF-8 `raw` with `s` by UTF-16 code units, the order of the string_ids
+/// section, e.g. to binary search for a name. Invalid sequences compare as U+FFFD.
+pub fn cmp_str(raw: &[u8], s: &str) -> Ordering {
+    Units::new(raw).map(|it| it.map_or(0xfffd, |(_, unit)| unit)).cmp(s.encode_utf16())
+}
+
+/// UTF-16 code units of MUTF-8 data with their offsets, up to the terminating NUL. Invalid sequences are
+/// skipped including their valid continuation bytes.
+struct Units<'a> {
+    data: &'a [u8],
+    idx: usize,
+    done: bool,
+}
+
+impl<'a> Units<'a> {
+    fn new(data: &'a [u8]) -> Units<'a> {
+        Units { data, idx: 0, done: false }
+    }
+}
+
+impl Iterator for Units<'_> {
+    type Item = Result<(usize, u16), MUtf8ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let start = self.idx;
+        let continuation = |idx: usize| self.data.get(idx).map(|it| *it as u16).filter(|it| (it & 0xc0) == 0x80);
+        let a = match self.data.get(start) {
+            Some(0) => {
+                self.done = true;
+                return None;
+            }
+            None => {
+                self.done = true;
+                return Some(Err(MissingTerminator(start)));
+            }
+            Some(a) => *a as u16,
+        };
+        let (unit, len) = if a < 0x80 {
+            (Ok(a), 1)
         } else if (a & 0xe0) == 0xc0 {
-            let b = read_u8(reader, &mut buf).map_err(|r_err| ReadError(r_err))? as u16;
-            if (b & 0xc0) != 0x80 {
-                return Err(DecodeError(BadSecondByte));
+            match continuation(start + 1) {
+                Some(b) => (Ok(((a & 0x1f) << 6) | (b & 0x3f)), 2),
+                None => (Err(BadSecondByte(start)), 1),
             }
-            out[s] = (((a & 0x1f) << 6) | (b & 0x3f)) as u16;
-            s += 1;
         } else if (a & 0xf0) == 0xe0 {
-            let b = read_u8(reader, &mut buf).map_err(|r_err| ReadError(r_err))? as u16;
-            let c = read_u8(reader, &mut buf).map_err(|r_err| ReadError(r_err))? as u16;
-            if ((b & 0xc0) != 0x80) || ((c & 0xc0) != 0x80) {
-                return Err(DecodeError(BadSecondThirdByte));
+            match (continuation(start + 1), continuation(start + 2)) {
+                (Some(b), Some(c)) => (Ok(((a & 0x0f) << 12) | ((b & 0x3f) << 6) | (c & 0x3f)), 3),
+                (Some(_), None) => (Err(BadSecondThirdByte(start)), 2),
+                (None, _) => (Err(BadSecondThirdByte(start)), 1),
             }
-            out[s] = (((a & 0x0f) << 12) | ((b & 0x3f) << 6) | (c & 0x3f)) as u16;
-            s += 1;
         } else {
-            return Err(DecodeError(BadByte));
+            (Err(BadByte(start)), 1)
+        };
+        self.idx += len;
+        Some(unit.map(|unit| (start, unit)))
+    }
+}
+
+/// Reads the data of a string_data_item (following its utf16_size) up to and including the terminating NUL
+pub fn read_data<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u8>, crate::io::Error> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1];
+    loop {
+        let byte = read_u8(reader, &mut buf)?;
+        data.push(byte);
+        if byte == 0 {
+            return Ok(data);
+        }
+    }
+}
+
+/// Reads and decodes the data of a string_data_item from a reader, see to_string
+pub fn read_string<R: Read + ?Sized>(reader: &mut R, utf16_size: u64) -> Result<String, LoadMUtf8StringError> {
+    to_string(&read_data(reader).map_err(ReadError)?, utf16_size).map_err(DecodeError)
+}
+
+/// Reports an invalid sequence, decoding continues with U+FFFD in its place if this does not fail
+type OnError<'a> = dyn FnMut(MUtf8ParseError) -> Result<(), MUtf8ParseError> + 'a;
+
+fn decode_with(data: &[u8], utf16_size: u64, on_error: &mut OnError) -> Result<MUtf8String, MUtf8ParseError> {
+    // https://cs.android.com/android/platform/superproject/+/master:dalvik/dx/src/com/android/dex/Mutf8.java
+    // The declared size is not trusted for the allocation
+    let mut value = String::with_capacity(utf16_size.min(data.len() as u64) as usize);
+    let mut units = 0;
+    let mut valid = true;
+    let mut invalid = |value: &mut String, err: MUtf8ParseError| {
+        valid = false;
+        on_error(err)?;
+        value.push(char::REPLACEMENT_CHARACTER);
+        Ok(())
+    };
+    // High surrogate (and its offset) waiting for the low surrogate of its pair
+    let mut high: Option<(usize, u16)> = None;
+    for unit in Units::new(data) {
+        let (start, unit) = match unit {
+            Ok(unit) => unit,
+            Err(err) => {
+                if let Some((offset, unit)) = high.take() {
+                    invalid(&mut value, UnpairedSurrogate { offset, unit })?;
+                }
+                invalid(&mut value, err)?;
+                continue;
+            }
+        };
+        units += 1;
+
+        if let Some((offset, pending)) = high.take() {
+            if (0xdc00..=0xdfff).contains(&unit) {
+                let code_point = 0x10000 + ((((pending - 0xd800) as u32) << 10) | (unit - 0xdc00) as u32);
+                value.push(char::from_u32(code_point).unwrap());
+                continue;
+            }
+            invalid(&mut value, UnpairedSurrogate { offset, unit: pending })?;
+        }
+        match unit {
+            0xd800..=0xdbff => high = Some((start, unit)),
+            0xdc00..=0xdfff => invalid(&mut value, UnpairedSurrogate { offset: start, unit })?,
+            _ => value.push(char::from_u32(unit as u32).unwrap()),
         }
     }
+    if let Some((offset, unit)) = high {
+        invalid(&mut value, UnpairedSurrogate { offset, unit })?;
+    }
+    // Only reported for otherwise valid data, the replaced sequences already explain the difference
+    if valid && units as u64 != utf16_size {
+        on_error(LengthMismatch { declared: utf16_size, decoded: units as u64 })?;
+    }
+    Ok(MUtf8String { value, utf16_size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MUTF-8 data of `value` with the terminating NUL
+    fn encoded(value: &str) -> (u64, Vec<u8>) {
+        let (units, mut data) = encode(value);
+        data.push(0);
+        (units as u64, data)
+    }
+
+    #[test]
+    fn round_trip() {
+        for value in ["", "hello", "\0", "caf\u{e9}", "\u{7ff}\u{800}\u{ffff}", "\u{1F600} and \u{10000}\u{10ffff}"] {
+            let (units, data) = encoded(value);
+            let decoded = decode(&data, units).unwrap();
+            assert_eq!(decoded.value, value);
+            assert_eq!((decoded.utf16_size, decoded.utf16_len()), (units, value.encode_utf16().count()));
+        }
+        // NUL is encoded in two bytes, supplementary characters as two 3-byte surrogates
+        assert_eq!(encode("\0"), (1, vec![0xc0, 0x80]));
+        assert_eq!(encode("\u{1F600}"), (2, vec![0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80]));
+        assert_eq!(decode(&[0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80, 0], 2).unwrap().char_count(), 1);
+    }
+
+    #[test]
+    fn unpaired_surrogates() {
+        // High surrogate at the end
+        let data = [b'a', 0xed, 0xa0, 0xbd, 0];
+        assert_eq!(decode(&data, 2), Err(UnpairedSurrogate { offset: 1, unit: 0xd83d }));
+        // High surrogate followed by another character, low surrogate alone
+        assert_eq!(decode(&[0xed, 0xa0, 0xbd, b'a', 0], 2), Err(UnpairedSurrogate { offset: 0, unit: 0xd83d }));
+        assert_eq!(decode(&[b'a', 0xed, 0xb8, 0x80, 0], 2), Err(UnpairedSurrogate { offset: 1, unit: 0xde00 }));
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let (units, data) = encoded("\u{1F600}");
+        assert_eq!(decode(&data, units - 1), Err(LengthMismatch { declared: 1, decoded: 2 }));
+        assert_eq!(decode(&data, units + 1).unwrap_err().offset(), 0);
+    }
+
+    #[test]
+    fn lossy() {
+        // A byte that starts no sequence, a 2-byte sequence without its second byte and an unpaired surrogate
+        let data = [b'a', 0xff, 0xc3, b'b', 0xed, 0xa0, 0xbd, 0];
+        let lossy = to_string_lossy(&data, 9);
+        assert_eq!(lossy.string.value, "a\u{fffd}\u{fffd}b\u{fffd}");
+        assert_eq!(lossy.errors, [BadByte(1), BadSecondByte(2), UnpairedSurrogate { offset: 4, unit: 0xd83d }]);
+        // The declared length is only checked for otherwise valid data
+        assert!(!lossy.errors.iter().any(|it| matches!(it, LengthMismatch { .. })));
+        assert_eq!(decode(&data, 3), Err(BadByte(1)));
+
+        let (units, data) = encoded("caf\u{e9}");
+        assert_eq!(to_string_lossy(&data, units), LossyString { string: decode(&data, units).unwrap(), errors: Vec::new() });
+        assert_eq!(to_string_lossy(&data, units + 1).errors, [LengthMismatch { declared: 5, decoded: 4 }]);
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(decode(&[0xe0, 0x80, b'a', 0], 1), Err(BadSecondThirdByte(0)));
+        assert_eq!(decode(&[b'a', 0xe0, b'b', 0], 2), Err(BadSecondThirdByte(1)));
+        assert_eq!(decode(&[b'a', b'b', 0x80, 0], 3), Err(BadByte(2)));
+        assert_eq!(decode(&[0xc3, 0xa9, 0xc3], 2), Err(BadSecondByte(2)));
+        assert_eq!(decode(b"ab", 2), Err(MissingTerminator(2)));
+        assert_eq!(MissingTerminator(2).offset(), 2);
+        // Data after the terminator belongs to the next item
+        assert_eq!(to_string(b"ab\0\xff", 2).unwrap(), "ab");
+        assert_eq!(read_string(&mut crate::io::Cursor::new(&b"ab\0c"[..]), 2).unwrap(), "ab");
+        assert!(matches!(read_string(&mut crate::io::Cursor::new(&b"ab"[..]), 2), Err(ReadError(_))));
+    }
+
+    #[test]
+    fn compare_raw() {
+        let (_, emoji) = encoded("\u{1F600}");
+        assert!(eq_str(&emoji, "\u{1F600}"));
+        assert!(!eq_str(&emoji, "\u{1F600}a"));
+        assert!(eq_str(&[0], ""));
+        // Invalid data is not equal to its replacement
+        assert!(!eq_str(&[0xff, 0], "\u{fffd}"));
+        assert!(!eq_str(b"ab", "ab"));
+
+        // By UTF-16 code units, surrogates sort before U+FFFF unlike in UTF-8
+        assert_eq!(cmp_str(&emoji, "\u{ffff}"), Ordering::Less);
+        assert!("\u{1F600}" > "\u{ffff}");
+        assert_eq!(cmp_str(&emoji, "\u{1F600}"), Ordering::Equal);
+        assert_eq!(cmp_str(b"ab\0", "a"), Ordering::Greater);
+        assert_eq!(cmp_str(b"a\0", "ab"), Ordering::Less);
+        assert_eq!(cmp_str(&[0xff, 0], "\u{fffd}"), Ordering::Equal);
+    }
 }
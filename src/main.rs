@@ -1,15 +1,44 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::HashMap;
+use std::io::{Cursor, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 
-use memmap::Mmap;
-use scroll::Pread;
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
 
-use crate::raw_dex::{DexHeader, MapItem, StringIds};
+use dex_tool::dex_file::{DexFile, ParseOptions, Progress, Strictness};
+use dex_tool::dex_version::VersionUpdate;
+use dex_tool::editor::{Dangling, DexEditor, EditError};
+use dex_tool::{app, atomic, decompiler, dex_version, dexdeps, dexdump, diff, embedded, emulator, hooks, input, listing, redirect, smali};
+use dex_tool::index::{Index, IndexCache};
+use dex_tool::input::InputData;
+use dex_tool::table::{Table, TableFormat};
+use dex_tool::highlight::Syntax;
+use failure::{ErrorFormat, Exit, Failure, OrFail};
+use pager::Output;
 
-mod raw_dex;
-mod m_utf8;
+mod completions;
+mod config;
+mod extract;
+mod failure;
+mod pager;
+mod scan;
+mod stats;
+#[cfg(unix)]
+mod serve;
 
 const SUPPORTED_DEX_VERSIONS: [u16; 4] = [35, 37, 38, 39];
+/// Interval of polling the input file in watch mode
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+/// Strictness of parsing the input files, set once the arguments are parsed
+static STRICTNESS: OnceLock<Strictness> = OnceLock::new();
+/// Whether progress bars are hidden, set once the arguments are parsed
+static QUIET: OnceLock<bool> = OnceLock::new();
 
 /*
 References:
@@ -18,66 +47,1121 @@ References:
 * https://android.googlesource.com/platform/dalvik/+/android-4.4.2_r2/libdex/DexFile.h
 * https://wiki.x10sec.org/android/basic_operating_mechanism/java_layer/dex/dex/
  */
+#[derive(Parser)]
+#[command(version, about = "Inspect Dex Files", arg_required_else_help = true,
+          after_help = "Dex files are read from stdin if FILE is -, e.g. unzip -p app.apk classes.dex | dex_tool -")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Dex file to print the header of if no command is given (same as the header command)
+    file: Option<PathBuf>,
+    /// Do not color the output (also disabled by NO_COLOR or if stdout is not a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Do not page the output through $PAGER
+    #[arg(long, global = true)]
+    no_pager: bool,
+    /// Cache the listings (strings, classes, methods, xrefs) of each dex file, so later queries do not parse it again
+    #[arg(long, global = true)]
+    cache: bool,
+    /// Directory of the cache, implies --cache [default: $XDG_CACHE_HOME/dex_tool or ~/.cache/dex_tool]
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+    /// Run the command again whenever one of its input files changes, re-parsing only the changed sections
+    #[arg(long, global = true)]
+    watch: bool,
+    /// Format of the error messages on stderr, the exit code tells the kind of failure
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+    /// How malformed input files are handled
+    #[arg(long, global = true, value_enum, default_value_t = ParseStrictness::Normal)]
+    strictness: ParseStrictness,
+    /// Write the output into FILE instead of stdout, it is only replaced once the command succeeded. Commands
+    /// writing into --out (e.g. strip) do not take it.
+    #[arg(short, long, global = true, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Report more on stderr: -v info messages, -vv also debug messages with the parsing time of each section
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Only report errors on stderr, without warnings (e.g. of lenient parsing) or progress bars
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Dump the contents of a dex file
+    Dump {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = DumpFormat::Dexdump)]
+        format: DumpFormat,
+    },
+    /// Disassemble all classes to smali (baksmali syntax)
+    Disasm {
+        file: PathBuf,
+        /// Write one .smali file per class into this directory instead of printing to stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Emulate calls of string decryption helpers and annotate the recovered strings
+        #[arg(long)]
+        decrypt_strings: bool,
+    },
+    /// List all strings with their offset, UTF-16 length and number of references from code
+    Strings {
+        file: PathBuf,
+        /// Only list strings of at least this many UTF-16 code units
+        #[arg(long, default_value_t = 0)]
+        min_length: usize,
+        /// Only list strings referenced by instructions
+        #[arg(long)]
+        only_code_referenced: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List all classes defined in the dex file
+    Classes {
+        file: PathBuf,
+        /// Only list classes of this package or its subpackages, e.g. com.example
+        #[arg(long)]
+        package: Option<String>,
+        /// Only list classes with all of these access flags, e.g. abstract,interface
+        #[arg(long, value_delimiter = ',')]
+        flags: Vec<String>,
+        #[arg(long, value_enum, default_value_t = ClassOrder::Index)]
+        sort: ClassOrder,
+        /// Do not list the classes and members generated by compilers, see the generated command
+        #[arg(long)]
+        hide_generated: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List all method ids, including methods of other dex files
+    Methods {
+        file: PathBuf,
+        /// Do not list the classes and members generated by compilers, see the generated command
+        #[arg(long)]
+        hide_generated: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the source file of each class and whether it matches the class name
+    Sources {
+        file: PathBuf,
+        /// Only list classes whose source file is missing or does not match
+        #[arg(long)]
+        suspicious: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Write the instructions of a method as raw code units, with the other fields of its code item
+    /// in a JSON sidecar (OUT.json)
+    ExtractMethod {
+        file: PathBuf,
+        /// Method as in the xrefs listing, e.g. 'Lcom/example/Foo;->run()V'
+        method: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Copy classes of another dex file (e.g. built by d8) into the dex file, with the ids they reference
+    InsertClass {
+        file: PathBuf,
+        /// Dex file defining the classes
+        #[arg(long)]
+        from: PathBuf,
+        /// Descriptor of a class to insert (e.g. Lcom/example/Foo;), all classes of --from if not given
+        #[arg(long = "class", value_name = "DESCRIPTOR")]
+        classes: Vec<String>,
+        /// Version declared by the written dex file, for the features its code uses
+        #[arg(long, value_enum, default_value_t = DexVersion::Keep)]
+        dex_version: DexVersion,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Add a method of another dex file (e.g. built by d8 from the patched class) to the class of the same
+    /// name in the dex file, or replace the method's definition if the class defines it already
+    SetMethod {
+        file: PathBuf,
+        /// Dex file defining the method
+        #[arg(long)]
+        from: PathBuf,
+        /// Method as in the xrefs listing, e.g. 'Lcom/example/Foo;->run()V'
+        method: String,
+        /// Version declared by the written dex file, for the features its code uses
+        #[arg(long, value_enum, default_value_t = DexVersion::Keep)]
+        dex_version: DexVersion,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Remove classes and members, e.g. 'Lcom/example/Foo;', 'Lcom/example/Foo;->run()V' or
+    /// 'Lcom/example/Foo;->name:I', with the ids only they referenced, and list what was removed
+    Strip {
+        file: PathBuf,
+        /// Class or member to remove
+        #[arg(long = "remove", value_name = "ITEM", required = true)]
+        items: Vec<String>,
+        /// What to do with the references of the remaining code to the removed items
+        #[arg(long, value_enum, default_value_t = DanglingReferences::Fail)]
+        dangling: DanglingReferences,
+        /// Version declared by the written dex file, for the features its code uses
+        #[arg(long, value_enum, default_value_t = DexVersion::Keep)]
+        dex_version: DexVersion,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Redirect the invokes of a method to a static trampoline method, which takes the receiver of an
+    /// instance method as first parameter, and list the redirected invokes
+    Redirect {
+        file: PathBuf,
+        /// Method whose invokes are redirected, e.g. 'Lcom/example/Foo;->get(I)Ljava/lang/String;'
+        #[arg(long)]
+        target: String,
+        /// Trampoline, e.g. 'Lcom/example/Hooks;->get(Lcom/example/Foo;I)Ljava/lang/String;'
+        #[arg(long)]
+        trampoline: String,
+        /// Dex file defining the trampoline (e.g. built by d8), inserted with its class if the dex file
+        /// does not define it already
+        #[arg(long)]
+        from: Option<PathBuf>,
+        /// Version declared by the written dex file, for the features its code uses
+        #[arg(long, value_enum, default_value_t = DexVersion::Keep)]
+        dex_version: DexVersion,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the fields and methods of a class
+    Members {
+        file: PathBuf,
+        /// Descriptor of the class, e.g. Lcom/example/Foo;
+        class: String,
+        /// Do not list the classes and members generated by compilers, see the generated command
+        #[arg(long)]
+        hide_generated: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the instructions referencing strings, types, fields, methods and protos
+    Xrefs {
+        file: PathBuf,
+        /// Only list references to this target (string value, type descriptor, Lcls;->name(sig) or Lcls;->name:type)
+        #[arg(long)]
+        target: Option<String>,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Check the instructions of all methods (register, branch, index and payload operands), exits with
+    /// status 1 if problems are found
+    Verify {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Print the header with the version, endianness, sizes and the validity of checksum and signature
+    Header {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the features of the dex format newer than 035 that the dex file uses (e.g. invoke-custom needs
+    /// 038) with the version each needs, exits with status 1 if the declared version is older
+    Features {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Check the checksum, signature, file size, header size and whether the map list covers the whole
+    /// file, and score how likely the file was modified after compilation
+    Tamper {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Print the entropy of the data section and of each section, and the high entropy regions of the
+    /// data section that likely hold compressed or encrypted payloads
+    Entropy {
+        file: PathBuf,
+        /// Only list the sections and regions of high entropy
+        #[arg(long)]
+        high: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the dex files and zip archives embedded in the data section, in bytes not covered by the map
+    /// list and after the end of the file, as droppers hide their payload
+    Embedded {
+        file: PathBuf,
+        /// Write each payload to this directory, named by its offset
+        #[arg(long)]
+        carve: Option<PathBuf>,
+        /// Analyze the payloads in turn: check dex files for tampering, high entropy and payloads, and
+        /// the dex files and archives in archives, listing the nested payloads by their path
+        #[arg(long)]
+        recurse: bool,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Print the map list with the size and share of the file of each section
+    Map {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Print summary statistics, with json the counts of all items, the sizes of the sections, the
+    /// distribution of the string lengths and the verification status in one document
+    Stats {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// List the classes, fields and methods referenced but not defined by the dex file, like the dexdeps
+    /// tool of the AOSP
+    Deps {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = DepsFormat::Brief)]
+        format: DepsFormat,
+        /// Only list the classes
+        #[arg(long)]
+        just_classes: bool,
+    },
+    /// List the classes and members generated by compilers: synthetic and bridge members, lambda
+    /// classes of D8 and switch map classes
+    Generated {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the constructs generated by kotlinc (companion objects, DefaultImpls and WhenMappings
+    /// classes, $default methods and property accessors), grouped by the class, method or property
+    /// owning them
+    Kotlin {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List a hash of the code of each method that is equal for the same code in other builds (registers,
+    /// references and instruction forms are normalized), for matching methods across versions
+    Hashes {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// List the dex files of an app in class loading order: an APK, a bundle of split APKs (.apks, .xapk),
+    /// a directory of split APKs or an Android App Bundle (.aab) with the module of each dex file
+    App {
+        path: PathBuf,
+        /// List the classes of all dex files with the module, APK and dex file defining them instead
+        #[arg(long)]
+        classes: bool,
+        /// Print the dex file defining the class with the descriptor (e.g. `Lcom/foo/Bar;`) instead, without
+        /// parsing the dex files
+        #[arg(long, value_name = "DESCRIPTOR", conflicts_with = "classes")]
+        find: Option<String>,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Show the classes and methods added, removed or changed from one version of a dex file to the next,
+    /// with the differences of the smali code of each changed method
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+    },
+    /// Find the hook targets (methods, e.g. 'Lcom/example/Foo;->run(I)V') of the old version of a dex file
+    /// in the new version, also if they were renamed, and propose their new signatures
+    HookTargets {
+        old: PathBuf,
+        new: PathBuf,
+        /// File with one target per line, lines starting with # are ignored
+        #[arg(long)]
+        targets: PathBuf,
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Export the model of a dex file for external analysis
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Decompile simple methods to pseudo-Java (experimental)
+    Decompile {
+        file: PathBuf,
+        /// Only decompile the class with this descriptor (e.g. Lcom/example/Foo;)
+        #[arg(long)]
+        class: Option<String>,
+    },
+    /// Summarize all dex files and APKs matching a glob pattern (e.g. 'apks/**/*.apk'), in parallel
+    Scan {
+        pattern: String,
+        /// Directory for one JSON report per file and summary.json with the totals
+        #[arg(long)]
+        report: PathBuf,
+    },
+    /// Print the completion script of a shell, e.g. `source <(dex_tool completions bash)`
+    Completions {
+        shell: completions::Shell,
+    },
+    /// Keep dex files parsed in memory and answer JSON-RPC queries on a Unix socket
+    #[cfg(unix)]
+    Serve {
+        /// Path of the socket, replaced if it is the socket of an earlier server
+        #[arg(long, default_value = "dex_tool.sock")]
+        socket: PathBuf,
+        /// Directory of the files that can be opened, relative paths are resolved against it
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+    },
+}
+
+impl Command {
+    /// The dex files read by the command, empty if it reads other input (e.g. an app)
+    fn inputs(&self) -> Vec<&Path> {
+        match self {
+            Command::Dump { file, .. } | Command::Disasm { file, .. } | Command::Strings { file, .. } |
+            Command::Classes { file, .. } | Command::Members { file, .. } | Command::Sources { file, .. } | Command::ExtractMethod { file, .. } | Command::InsertClass { file, .. } | Command::SetMethod { file, .. } | Command::Strip { file, .. } | Command::Redirect { file, .. } | Command::Methods { file, .. } | Command::Xrefs { file, .. } |
+            Command::Header { file, .. } | Command::Features { file, .. } | Command::Tamper { file, .. } | Command::Entropy { file, .. } | Command::Embedded { file, .. } | Command::Map { file, .. } | Command::Stats { file, .. } | Command::Verify { file, .. } | Command::Deps { file, .. } | Command::Generated { file, .. } | Command::Kotlin { file, .. } | Command::Hashes { file, .. } |
+            Command::Decompile { file, .. } => vec![file],
+            Command::Export { format } => match *format {
+                #[cfg(feature = "sqlite")]
+                ExportFormat::Sqlite { ref file, .. } => vec![file],
+                #[cfg(feature = "parquet")]
+                ExportFormat::Parquet { ref file, .. } => vec![file],
+                #[cfg(feature = "protobuf")]
+                ExportFormat::Protobuf { ref file, .. } => vec![file],
+            },
+            Command::Diff { old, new, .. } | Command::HookTargets { old, new, .. } => vec![old, new],
+            Command::App { .. } | Command::Scan { .. } | Command::Completions { .. } => Vec::new(),
+            #[cfg(unix)]
+            Command::Serve { .. } => Vec::new(),
+        }
+    }
+
+    /// The file or directory the command writes its output into instead of stdout, given by --out
+    /// (or OUT of export)
+    fn out(&self) -> Option<&Path> {
+        match self {
+            Command::Disasm { out, .. } => out.as_deref(),
+            Command::ExtractMethod { out, .. } | Command::InsertClass { out, .. } | Command::SetMethod { out, .. } |
+            Command::Strip { out, .. } | Command::Redirect { out, .. } => Some(out),
+            Command::Export { format } => match *format {
+                #[cfg(feature = "sqlite")]
+                ExportFormat::Sqlite { ref out, .. } => Some(out),
+                #[cfg(feature = "parquet")]
+                ExportFormat::Parquet { ref out, .. } => Some(out),
+                #[cfg(feature = "protobuf")]
+                ExportFormat::Protobuf { ref out, .. } => Some(out),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ExportFormat {
+    /// SQLite database with tables for strings, types, classes, fields, methods, instructions and xrefs
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        file: PathBuf,
+        out: PathBuf,
+    },
+    /// Parquet datasets (strings, methods, references) written into a directory
+    #[cfg(feature = "parquet")]
+    Parquet {
+        file: PathBuf,
+        out: PathBuf,
+    },
+    /// Protobuf encoded DexFile message, see proto/dex.proto
+    #[cfg(feature = "protobuf")]
+    Protobuf {
+        file: PathBuf,
+        out: PathBuf,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ParseStrictness {
+    /// Skip items that fail to parse with a warning
+    Lenient,
+    /// Fail on malformed items, replace invalid MUTF-8 in strings with a warning
+    Normal,
+    /// Fail on malformed items, invalid MUTF-8 and a wrong checksum
+    Strict,
+}
+
+impl From<ParseStrictness> for Strictness {
+    fn from(strictness: ParseStrictness) -> Strictness {
+        match strictness {
+            ParseStrictness::Lenient => Strictness::Lenient,
+            ParseStrictness::Normal => Strictness::Normal,
+            ParseStrictness::Strict => Strictness::Strict,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum DanglingReferences {
+    /// Fail if the remaining code references the removed items
+    Fail,
+    /// Replace the code of the methods referencing them with a stub that throws
+    Stub,
+    /// Remove the classes and members referencing them as well
+    Cascade,
+}
+
+impl From<DanglingReferences> for Dangling {
+    fn from(dangling: DanglingReferences) -> Dangling {
+        match dangling {
+            DanglingReferences::Fail => Dangling::Fail,
+            DanglingReferences::Stub => Dangling::Stub,
+            DanglingReferences::Cascade => Dangling::Cascade,
+        }
+    }
+}
+
+/// Version of the written dex file
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum DexVersion {
+    /// The version of the input file, with a warning if the features used need a newer one
+    Keep,
+    /// Raise the version to the one the features used need if the input file's is older
+    Raise,
+    /// The oldest version supporting the features used, also if the input file's is newer
+    Lowest,
+}
+
+impl From<DexVersion> for VersionUpdate {
+    fn from(version: DexVersion) -> VersionUpdate {
+        match version {
+            DexVersion::Keep => VersionUpdate::Keep,
+            DexVersion::Raise => VersionUpdate::Raise,
+            DexVersion::Lowest => VersionUpdate::Lowest,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ListFormat {
+    /// Aligned columns
+    Text,
+    /// Comma separated values with a header row
+    Csv,
+    /// Tab separated values with a header row
+    Tsv,
+    /// Markdown table, for pasting into issue trackers and wikis
+    Markdown,
+}
+
+impl From<ListFormat> for TableFormat {
+    fn from(format: ListFormat) -> TableFormat {
+        match format {
+            ListFormat::Text => TableFormat::Text,
+            ListFormat::Csv => TableFormat::Csv,
+            ListFormat::Tsv => TableFormat::Tsv,
+            ListFormat::Markdown => TableFormat::Markdown,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ClassOrder {
+    /// Order of the class definitions
+    Index,
+    /// By descriptor
+    Name,
+    /// By the size of the code of their methods, largest first
+    Size,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum DumpFormat {
+    /// Debug representation of the header and map list, see the header command for a readable one
+    Debug,
+    /// Output matching `dexdump -d` of the AOSP
+    Dexdump,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum StatsFormat {
+    Text,
+    Csv,
+    Tsv,
+    Markdown,
+    /// One JSON document with all statistics
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum DiffFormat {
+    /// Unified diff of the smali code
+    Text,
+    /// Standalone page with collapsible classes and methods and highlighted smali code
+    Html,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum DepsFormat {
+    /// Classes, fields and methods in sections, like `dexdeps --format=brief`
+    Brief,
+    /// Grouped by package and class, like `dexdeps --format=xml`
+    Xml,
+}
+
 fn main() {
-    let f = File::open("mx_files/classes.dex").expect("Could not open file");
-
-    use_mmap(&f);
-
-    // let mut reader = BufReader::new(f);
-    //
-    // let dex_header = DexHeader::from_reader(&mut reader);
-    //
-    // let version = DexHeader::verify_magic(&dex_header.magic);
-    // assert!(SUPPORTED_DEX_VERSIONS.contains(&version),
-    //         "Unsupported Dex Format Version ({})", version);
-    //
-    // let is_reverse_endian = DexHeader::verify_endian(dex_header.endian_tag);
-    // assert!(!is_reverse_endian, "Dex Files with reverse endian tag are not supported");
-    //
-    // println!("File Format Version: {}", version);
-    // println!("{:#X?}", dex_header);
-    //
-    // let map = raw_dex::MapItem::parse_map_list(&dex_header, &mut reader);
-    //
-    // let string_ids = raw_dex::parse_string_ids(&dex_header, &mut reader);
-    // let string_data = raw_dex::parse_string_data(string_ids, &mut reader);
-    // let type_ids = raw_dex::parse_type_ids(&dex_header, &mut reader);
-    // let proto_ids = raw_dex::parse_proto_ids(&dex_header, &mut reader);
-    // let field_ids = raw_dex::parse_field_ids(&dex_header, &mut reader);
-    // let method_ids = raw_dex::parse_method_ids(&dex_header, &mut reader);
-    // let class_defs = raw_dex::parse_class_defs(&dex_header, &mut reader);
-    // let call_side_ids = raw_dex::parse_call_side_ids(&map, &mut reader);
-    // let method_handles = raw_dex::parse_method_handles(&map, &mut reader);
-    // let class_data = raw_dex::parse_class_data(&map, &mut reader);
-    // let type_list = raw_dex::parse_type_lists(&map, &mut reader);
-    // let code_items = raw_dex::parse_code_items(&map, &mut reader);
-    // let debug_info = raw_dex::parse_debug_info(&map, &mut reader);
-    // let annotations_directories = raw_dex::parse_annotations_directories(&map, &mut reader);
-    // let annotation_set_ref_list = raw_dex::parse_annotation_set_ref_list(&map, &mut reader);
-    // let annotation_set_item = raw_dex::parse_annotation_set_item(&map, &mut reader);
-    // let hiddenapi_class_data = raw_dex::parse_hiddenapi_class_data(&map, &mut reader);
-}
-
-fn use_mmap(f: &File) {
-    let mmap = unsafe { Mmap::map(f).expect("Failed to use memmap on file") };
-
-    let ctx = raw_dex::EndianContext { 0: DexHeader::get_endian(&mmap) };
-    let dex_header: DexHeader = mmap.gread_with(&mut 0, ctx).unwrap();
-
-    let version = DexHeader::verify_magic(&dex_header.magic);
-
-    assert!(SUPPORTED_DEX_VERSIONS.contains(&version),
-            "Unsupported Dex Format Version ({})", version);
-
-    let map_list: Vec<MapItem> = mmap.pread_with(dex_header.map_off as usize, ctx).unwrap();
-
-    let ctx = raw_dex::TableContext {
-        endian: ctx.0,
-        header: &dex_header,
-        map: &map_list,
+    // Options of the command line override the defaults of the configuration files
+    let config = config::Config::load().unwrap_or_else(|err| failure::exit(Exit::Config, err));
+    let command = config.apply_defaults(Cli::command()).unwrap_or_else(|err| failure::exit(Exit::Config, err));
+    let cli = command.try_get_matches()
+        .and_then(|matches| Cli::from_arg_matches(&matches))
+        .unwrap_or_else(|err| failure::usage_error(err));
+    failure::install(cli.error_format);
+    STRICTNESS.get_or_init(|| cli.strictness.into());
+    QUIET.get_or_init(|| cli.quiet);
+    let (color, page) = (!cli.no_color && config.color != Some(false), !cli.no_pager && config.pager != Some(false));
+
+    // Sections are logged with their duration once they are parsed, e.g. with -vv. RUST_LOG (e.g.
+    // RUST_LOG=dex_tool::dex_file=debug) applies unless -v or -q are given.
+    let filter = match (cli.quiet, cli.verbose) {
+        (true, _) => EnvFilter::new("error"),
+        (false, 0) => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        (false, 1) => EnvFilter::new("warn,dex_tool=info"),
+        (false, 2) => EnvFilter::new("warn,dex_tool=debug"),
+        (false, _) => EnvFilter::new("warn,dex_tool=trace"),
     };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .with_ansi(color && std::io::stderr().is_terminal())
+        .init();
+    let cache = match cli.cache_dir {
+        Some(dir) => Some(IndexCache::new(dir)),
+        None if cli.cache => match default_cache_dir() {
+            Some(dir) => Some(IndexCache::new(dir)),
+            None => failure::usage_error(Cli::command().error(ErrorKind::MissingRequiredArgument, "Could not determine the cache directory, set --cache-dir")),
+        },
+        None => None,
+    };
+    let command = match (cli.command, cli.file) {
+        (Some(command), None) => command,
+        (None, Some(file)) => Command::Header { file, format: ListFormat::Text },
+        _ => failure::usage_error(Cli::command().error(ErrorKind::ArgumentConflict, "FILE can only be given without a command")),
+    };
+    if let (Some(_), Some(out)) = (&cli.output, command.out()) {
+        failure::usage_error(Cli::command().error(ErrorKind::ArgumentConflict, format!("--output can not be used with a command writing into {}", out.display())));
+    }
+    let out_file = cli.output.as_deref();
+    let mut loader = Loader { watch: cli.watch, loaded: HashMap::new() };
+    if !cli.watch {
+        if let Err(failure) = run(&command, &mut loader, cache.as_ref(), out_file, color, page) {
+            failure.report();
+            std::process::exit(failure.exit as i32);
+        }
+        return;
+    }
+
+    let paths = command.inputs();
+    if paths.is_empty() {
+        failure::usage_error(Cli::command().error(ErrorKind::ArgumentConflict, "--watch requires a command reading dex files"));
+    }
+    if paths.contains(&Path::new("-")) {
+        failure::usage_error(Cli::command().error(ErrorKind::ArgumentConflict, "--watch can not be used with stdin"));
+    }
+    loop {
+        let states: Vec<FileState> = paths.iter().map(|it| file_state(it)).collect();
+        // After a failure (e.g. of a partially written file) the next change is still handled
+        if let Err(failure) = run(&command, &mut loader, cache.as_ref(), out_file, color, false) {
+            failure.report();
+            loader.loaded.clear();
+        }
+        let changed = wait_for_change(&paths, &states);
+        eprintln!("{} changed, running again", paths[changed].display());
+    }
+}
+
+fn run(command: &Command, loader: &mut Loader, cache: Option<&IndexCache>, out_file: Option<&Path>, color: bool, page: bool) -> Result<(), Failure> {
+    let output_with = |syntax: Syntax, color: bool, page: bool| match out_file {
+        Some(path) => Output::file(path).or_fail(Exit::Output, "Could not create output file"),
+        None => Ok(Output::new(syntax, color, page)),
+    };
+    let output = |syntax: Syntax| output_with(syntax, color, page);
+    match command {
+        Command::Dump { file, format } => {
+            let dex = loader.load(file)?;
+            match format {
+                DumpFormat::Debug => {
+                    let mut out = output(Syntax::Plain)?;
+                    writeln!(out, "{:#X?}", dex.header).or_fail(Exit::Output, "Could not write output")?;
+                    writeln!(out, "{:#X?}", dex.map_list).or_fail(Exit::Output, "Could not write output")?;
+                    finish(out)?;
+                }
+                DumpFormat::Dexdump => {
+                    let mut out = output(Syntax::Dexdump)?;
+                    dexdump::dump(dex, &file.to_string_lossy(), &mut out).or_fail(Exit::Output, "Could not write output")?;
+                    finish(out)?;
+                }
+            }
+        }
+        Command::Disasm { file, out, decrypt_strings } => {
+            let dex = loader.load(file)?;
+            let mut comments = smali::Comments::new();
+            if *decrypt_strings {
+                for decrypted in emulator::decrypt_strings(dex) {
+                    comments.insert((decrypted.caller, decrypted.pc),
+                                    format!("Decrypted string: \"{}\"", decrypted.value.escape_default()));
+                }
+            }
+            match out {
+                Some(dir) => smali::write_all(dex, dir, &comments).or_fail(Exit::Output, "Could not write smali files")?,
+                None => {
+                    let mut out = output(Syntax::Smali)?;
+                    for idx in 0..dex.class_defs.len() {
+                        smali::write_class(dex, idx, &comments, &mut out).or_fail(Exit::Output, "Could not write output")?;
+                        writeln!(out).or_fail(Exit::Output, "Could not write output")?;
+                    }
+                    finish(out)?;
+                }
+            }
+        }
+        Command::Strings { file, min_length, only_code_referenced, format } => {
+            let mut table = list(file, loader, cache, listing::strings, |it| it.strings)?;
+            table.rows.retain(|row| {
+                row[3].parse::<usize>().map_or(true, |it| it >= *min_length) && (!only_code_referenced || row[4] != "0")
+            });
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Classes { file, package, flags, sort, hide_generated, format } => {
+            let mut table = list(file, loader, cache, listing::classes, |it| it.classes)?;
+            let mask = flags.iter().fold(0, |mask, name| {
+                match smali::CLASS_FLAGS.iter().find(|(_, it)| it == name) {
+                    Some((flag, _)) => mask | flag,
+                    None => failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, format!("Unknown class access flag {}", name))),
+                }
+            });
+            let prefix = package.as_ref().map(|it| format!("L{}/", it.replace('.', "/")));
+            table.rows.retain(|row| {
+                let access_flags = u32::from_str_radix(row[3].trim_start_matches("0x"), 16).unwrap_or_default();
+                access_flags & mask == mask && prefix.iter().all(|it| row[1].starts_with(it.as_str()))
+            });
+            if *hide_generated {
+                let generated = loader.load(file)?.generated_items();
+                table.rows.retain(|row| row[0].parse().map_or(true, |it| !generated.class_defs.contains(&it)));
+            }
+            match sort {
+                ClassOrder::Index => {}
+                ClassOrder::Name => table.rows.sort_by(|a, b| a[1].cmp(&b[1])),
+                ClassOrder::Size => table.rows.sort_by_key(|row| std::cmp::Reverse(row[7].parse::<usize>().unwrap_or_default())),
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Sources { file, suspicious, format } => {
+            let mut table = listing::sources(loader.load(file)?);
+            if *suspicious {
+                table.rows.retain(|row| row[4] != "matches");
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::ExtractMethod { file, method, out } => {
+            extract::extract_method(loader.load(file)?, method, out).map_err(|err| {
+                let exit = match err.kind() {
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::InvalidInput => Exit::Empty,
+                    _ => Exit::Output,
+                };
+                Failure::new(exit, format!("Could not extract method: {}", err))
+            })?;
+        }
+        Command::InsertClass { file, from, classes, dex_version, out } => {
+            let mut editor = edit(file)?;
+            let source = edit(from)?;
+            let classes: Vec<&str> = if classes.is_empty() {
+                source.classes.iter().map(|it| source.type_descriptor(it.class_idx)).collect()
+            } else {
+                classes.iter().map(String::as_str).collect()
+            };
+            match editor.insert_classes(&source, &classes) {
+                Ok(_) => write_dex(&mut editor, *dex_version, out)?,
+                Err(err @ (EditError::ClassExists(_) | EditError::ClassNotFound(_))) => failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, err.to_string())),
+                Err(err) => return Err(Failure::new(Exit::Output, format!("Could not insert classes: {}", err))),
+            }
+        }
+        Command::SetMethod { file, from, method, dex_version, out } => {
+            let mut editor = edit(file)?;
+            let source = edit(from)?;
+            let parsed = method.split_once("->").and_then(|(class, rest)| rest.find('(').map(|it| (class, &rest[..it], &rest[it..])));
+            let (class, name, signature) = match parsed {
+                Some(parsed) => parsed,
+                None => failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, format!("Invalid method {}, expected e.g. 'Lcom/example/Foo;->run()V'", method))),
+            };
+            match editor.copy_method(&source, class, name, signature) {
+                Ok(_) => write_dex(&mut editor, *dex_version, out)?,
+                Err(err @ (EditError::ClassNotFound(_) | EditError::MethodNotFound(_) | EditError::InvalidSignature(_))) => failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, err.to_string())),
+                Err(err) => return Err(Failure::new(Exit::Output, format!("Could not set method: {}", err))),
+            }
+        }
+        Command::Strip { file, items, dangling, dex_version, out, format } => {
+            let mut editor = edit(file)?;
+            let items: Vec<&str> = items.iter().map(String::as_str).collect();
+            let stripped = match editor.strip(&items, (*dangling).into()) {
+                Ok(stripped) => stripped,
+                Err(err @ (EditError::ClassNotFound(_) | EditError::MethodNotFound(_) | EditError::FieldNotFound(_) | EditError::InvalidTarget(_) | EditError::InvalidSignature(_))) => {
+                    failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, err.to_string()))
+                }
+                Err(err @ EditError::Dangling(..)) => return Err(Failure::new(Exit::Verification, format!("Could not strip: {}", err))),
+                Err(err) => return Err(Failure::new(Exit::Output, format!("Could not strip: {}", err))),
+            };
+            write_dex(&mut editor, *dex_version, out)?;
+            print_table(&listing::strip(&stripped), *format, output(Syntax::Plain)?)?
+        }
+        Command::Redirect { file, target, trampoline, from, dex_version, out, format } => {
+            let mut editor = edit(file)?;
+            let result = match from {
+                Some(from) => redirect::insert_trampoline(&mut editor, &edit(from)?, trampoline).map(|_| ()),
+                None => Ok(()),
+            };
+            let redirections = match result.and_then(|_| redirect::redirect_invokes(&mut editor, target, trampoline)) {
+                Ok(redirections) => redirections,
+                Err(err @ (EditError::ClassNotFound(_) | EditError::MethodNotFound(_) | EditError::InvalidTarget(_) | EditError::InvalidSignature(_) | EditError::IncompatibleTrampoline(_))) => {
+                    failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, err.to_string()))
+                }
+                Err(err) => return Err(Failure::new(Exit::Output, format!("Could not redirect invokes: {}", err))),
+            };
+            write_dex(&mut editor, *dex_version, out)?;
+            print_table(&listing::redirections(&redirections), *format, output(Syntax::Plain)?)?
+        }
+        Command::Members { file, class, hide_generated, format } => {
+            let dex = loader.load(file)?;
+            let class = match dex.find_class(class) {
+                Some(class) => class,
+                None => failure::usage_error(Cli::command().error(ErrorKind::InvalidValue, format!("Class {} is not defined in {}", class, file.display()))),
+            };
+            let mut table = listing::members(class);
+            if *hide_generated {
+                let generated = dex.generated_items();
+                table.rows.retain(|row| {
+                    let indices = if row[0] == "field" { &generated.fields } else { &generated.methods };
+                    row[1].parse().map_or(true, |it| !indices.contains(&it))
+                });
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Methods { file, hide_generated, format } => {
+            let mut table = list(file, loader, cache, listing::methods, |it| it.methods)?;
+            if *hide_generated {
+                let generated = loader.load(file)?.generated_items();
+                table.rows.retain(|row| row[0].parse().map_or(true, |it| !generated.methods.contains(&it)));
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Xrefs { file, target, format } => {
+            let mut table = list(file, loader, cache, listing::xrefs, |it| it.xrefs)?;
+            if let Some(target) = target {
+                table.rows.retain(|row| &row[5] == target);
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?;
+            if let Some(target) = target.as_ref().filter(|_| table.rows.is_empty() && !loader.watch) {
+                return Err(Failure::new(Exit::Empty, format!("No references to {}", target)));
+            }
+        }
+        Command::Verify { file, format } => {
+            let table = listing::verify(loader.load(file)?);
+            print_table(&table, *format, output(Syntax::Plain)?)?;
+            // Watch mode keeps running to verify the next version
+            if !table.rows.is_empty() && !loader.watch {
+                return Err(Failure::new(Exit::Verification, format!("{} problems found", table.rows.len())));
+            }
+        }
+        Command::Header { file, format } => {
+            let (data, dex) = loader.load_with_data(file)?;
+            print_table(&listing::header(dex, data), *format, output(Syntax::Plain)?)?
+        }
+        Command::Features { file, format } => {
+            let dex = loader.load(file)?;
+            let table = listing::features(dex);
+            print_table(&table, *format, output(Syntax::Plain)?)?;
+            let unsupported = table.rows.iter().filter(|row| row[4] == "UNSUPPORTED").count();
+            if unsupported > 0 {
+                return Err(Failure::new(Exit::Verification, format!("{} features need a newer version than {:03}", unsupported, dex.version())));
+            }
+        }
+        Command::Tamper { file, format } => {
+            let (data, dex) = loader.load_with_data(file)?;
+            print_table(&listing::tamper(dex, data), *format, output(Syntax::Plain)?)?
+        }
+        Command::Entropy { file, high, format } => {
+            let (data, dex) = loader.load_with_data(file)?;
+            let mut table = listing::entropy(dex, data);
+            if *high {
+                table.rows.retain(|row| row[4] == "true");
+            }
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Embedded { file, carve, recurse, format } => {
+            let (data, dex) = loader.load_with_data(file)?;
+            match carve {
+                Some(dir) if *recurse => {
+                    embedded::carve_nested(&embedded::analyze_nested(dex, data), dir).or_fail(Exit::Output, "Could not write payloads")?;
+                }
+                Some(dir) => {
+                    embedded::carve(&embedded::find_embedded(dex, data), data, dir).or_fail(Exit::Output, "Could not write payloads")?;
+                }
+                None => {}
+            }
+            let table = if *recurse { listing::embedded_nested(dex, data) } else { listing::embedded(dex, data) };
+            print_table(&table, *format, output(Syntax::Plain)?)?
+        }
+        Command::Map { file, format } => print_table(&listing::map(loader.load(file)?), *format, output(Syntax::Plain)?)?,
+        Command::Stats { file, format } => {
+            let format = match format {
+                StatsFormat::Text => ListFormat::Text,
+                StatsFormat::Csv => ListFormat::Csv,
+                StatsFormat::Tsv => ListFormat::Tsv,
+                StatsFormat::Markdown => ListFormat::Markdown,
+                StatsFormat::Json => {
+                    let (data, dex) = loader.load_with_data(file)?;
+                    let document = stats::stats_json(dex, data);
+                    let mut out = output(Syntax::Plain)?;
+                    writeln!(out, "{}", serde_json::to_string_pretty(&document).unwrap()).or_fail(Exit::Output, "Could not write output")?;
+                    return finish(out);
+                }
+            };
+            print_table(&listing::stats(loader.load(file)?), format, output(Syntax::Plain)?)?
+        }
+        Command::Deps { file, format, just_classes } => {
+            let dex = loader.load(file)?;
+            let mut out = output(Syntax::Plain)?;
+            match format {
+                DepsFormat::Brief => dexdeps::write_brief(dex, *just_classes, &mut out),
+                DepsFormat::Xml => dexdeps::write_xml(dex, &file.to_string_lossy(), *just_classes, &mut out),
+            }.or_fail(Exit::Output, "Could not write output")?;
+            finish(out)?;
+        }
+        Command::Generated { file, format } => {
+            print_table(&listing::generated(loader.load(file)?), *format, output(Syntax::Plain)?)?
+        }
+        Command::Kotlin { file, format } => {
+            print_table(&listing::kotlin(loader.load(file)?), *format, output(Syntax::Plain)?)?
+        }
+        Command::Hashes { file, format } => {
+            print_table(&listing::hashes(loader.load(file)?), *format, output(Syntax::Plain)?)?
+        }
+        Command::App { path, classes, find, format } => {
+            let dex_files = app::dex_files(path).or_fail(Exit::Input, "Could not read app")?;
+            if let Some(descriptor) = find {
+                let (dex, class_def_idx) = app::find_class(&dex_files, descriptor).or_fail(Exit::Parse, "Could not parse dex file")?
+                    .ok_or_else(|| Failure::new(Exit::Empty, format!("No dex file of the app defines {}", descriptor)))?;
+                let mut out = output(Syntax::Plain)?;
+                writeln!(out, "{} class_def #{}", dex.full_name(), class_def_idx).or_fail(Exit::Output, "Could not write output")?;
+                finish(out)?;
+            } else {
+                let parsed = dex_files.iter().map(|it| parse(&it.data, None)).collect::<Result<Vec<DexFile>, Failure>>()?;
+                let dex_files: Vec<_> = dex_files.iter().zip(&parsed).collect();
+                let table = if *classes { listing::app_classes(&dex_files) } else { listing::app(&dex_files) };
+                print_table(&table, *format, output(Syntax::Plain)?)?
+            }
+        }
+        Command::Diff { old, new, format } => {
+            let (old_dex, new_dex) = loader.load_both(old, new)?;
+            let classes = diff::diff(old_dex, new_dex);
+            // Colors would end up in the markup
+            let mut out = if *format == DiffFormat::Html { output_with(Syntax::Plain, false, page)? } else { output(Syntax::Plain)? };
+            match format {
+                DiffFormat::Text => diff::write_text(&classes, &mut out),
+                DiffFormat::Html => diff::write_html(&classes, &old.to_string_lossy(), &new.to_string_lossy(), &mut out),
+            }.or_fail(Exit::Output, "Could not write output")?;
+            finish(out)?;
+        }
+        Command::HookTargets { old, new, targets, format } => {
+            let targets = std::fs::read_to_string(targets).or_fail(Exit::Input, "Could not read targets")?;
+            let targets: Vec<&str> = targets.lines().map(str::trim).filter(|it| !it.is_empty() && !it.starts_with('#')).collect();
+            let (old_dex, new_dex) = loader.load_both(old, new)?;
+            let migrations = hooks::migrate(old_dex, new_dex, &targets);
+            print_table(&listing::hook_targets(&migrations), *format, output(Syntax::Plain)?)?
+        }
+        Command::Export { format } => match *format {
+            #[cfg(feature = "sqlite")]
+            ExportFormat::Sqlite { ref file, ref out } => {
+                let dex = loader.load(file)?;
+                dex_tool::export::sqlite::export(dex, &file.to_string_lossy(), out).or_fail(Exit::Output, "Could not export to SQLite")?;
+            }
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet { ref file, ref out } => {
+                let dex = loader.load(file)?;
+                dex_tool::export::parquet::export(dex, &file.to_string_lossy(), out).or_fail(Exit::Output, "Could not export to Parquet")?;
+            }
+            #[cfg(feature = "protobuf")]
+            ExportFormat::Protobuf { ref file, ref out } => {
+                let dex = loader.load(file)?;
+                dex_tool::export::protobuf::export(dex, &file.to_string_lossy(), out).or_fail(Exit::Output, "Could not export to Protobuf")?;
+            }
+        },
+        Command::Decompile { file, class } => {
+            let dex = loader.load(file)?;
+            let mut out = output(Syntax::Java)?;
+            // Companion objects and the other classes kotlinc generates for a class follow it
+            for idx in dex.kotlin_grouped_classes() {
+                if class.as_ref().is_some_and(|it| it != dex.type_descriptor(dex.class_defs[idx].class_idx)) {
+                    continue;
+                }
+                decompiler::write_class(dex, idx, &mut out).or_fail(Exit::Output, "Could not write output")?;
+                writeln!(out).or_fail(Exit::Output, "Could not write output")?;
+            }
+            finish(out)?;
+        }
+        Command::Scan { pattern, report } => {
+            let summary = scan::scan(pattern, report).or_fail(Exit::Output, "Could not scan files")?;
+            let mut out = output(Syntax::Plain)?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&summary).unwrap()).or_fail(Exit::Output, "Could not write output")?;
+            finish(out)?;
+        }
+        Command::Completions { shell } => {
+            let mut out = output_with(Syntax::Plain, false, false)?;
+            completions::write(*shell, Cli::command(), &mut out).or_fail(Exit::Output, "Could not write output")?;
+            finish(out)?;
+        }
+        #[cfg(unix)]
+        Command::Serve { socket, root } => serve::serve(socket, root).or_fail(Exit::Output, "Could not serve")?,
+    }
+    Ok(())
+}
+
+fn print_table(table: &Table, format: ListFormat, mut out: Output) -> Result<(), Failure> {
+    table.write(format.into(), &mut out).or_fail(Exit::Output, "Could not write output")?;
+    finish(out)
+}
 
-    let string_ids: StringIds = mmap.pread_with(dex_header.string_ids_off as usize, ctx).unwrap();
+fn finish(out: Output) -> Result<(), Failure> {
+    out.finish().or_fail(Exit::Output, "Could not write output")
+}
+
+/// Directory of the index cache in the cache directory of the user, None if neither XDG_CACHE_HOME nor HOME is set
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|it| PathBuf::from(it).join(".cache")))?;
+    Some(base.join("dex_tool"))
+}
+
+/// Loads the input files of a command. In watch mode the data of the last version of each file is kept, so
+/// the next version of it can be re-parsed incrementally.
+struct Loader {
+    watch: bool,
+    loaded: HashMap<PathBuf, (InputData, DexFile)>,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<&DexFile, Failure> {
+        Ok(self.load_with_data(path)?.1)
+    }
+
+    /// The data of the file with its model, for the commands looking at the bytes not covered by the model
+    fn load_with_data(&mut self, path: &Path) -> Result<(&[u8], &DexFile), Failure> {
+        let loaded = if self.watch {
+            let data = read(path)?.to_vec();
+            let dex = parse(&data, self.loaded.remove(path))?;
+            (InputData::Owned(data), dex)
+        } else {
+            let data = read(path)?;
+            let dex = parse(&data, None)?;
+            (data, dex)
+        };
+        self.loaded.insert(path.to_owned(), loaded);
+        let (data, dex) = &self.loaded[path];
+        Ok((data, dex))
+    }
+
+    /// Loads two files, e.g. the old and new version compared by diff
+    fn load_both(&mut self, first: &Path, second: &Path) -> Result<(&DexFile, &DexFile), Failure> {
+        self.load(first)?;
+        self.load(second)?;
+        Ok((&self.loaded[first].1, &self.loaded[second].1))
+    }
+}
 
-    println!("MapList: {:#X?}", string_ids);
-}
\ No newline at end of file
+/// Size and modification time of a file, None if it does not exist (e.g. while it is replaced)
+type FileState = Option<(u64, Option<SystemTime>)>;
+
+fn file_state(path: &Path) -> FileState {
+    std::fs::metadata(path).ok().map(|it| (it.len(), it.modified().ok()))
+}
+
+/// Blocks until the state of one of the files at `paths` differs from `states` and did not change for one
+/// interval, returns the index of that file
+fn wait_for_change(paths: &[&Path], states: &[FileState]) -> usize {
+    let mut last = states.to_vec();
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let current: Vec<FileState> = paths.iter().map(|it| file_state(it)).collect();
+        let changed = (0..paths.len()).find(|&idx| current[idx].is_some() && current[idx] != states[idx] && current[idx] == last[idx]);
+        if let Some(changed) = changed {
+            return changed;
+        }
+        last = current;
+    }
+}
+
+fn read(path: &Path) -> Result<InputData, Failure> {
+    if path == Path::new("-") {
+        input::read_stdin().or_fail(Exit::Input, "Could not read stdin")
+    } else {
+        input::read_file(path).or_fail(Exit::Input, "Could not open file")
+    }
+}
+
+/// Model of the dex file at `path` for editing
+fn edit(path: &Path) -> Result<DexEditor, Failure> {
+    let editor = DexEditor::from_bytes(&read(path)?).or_fail(Exit::Parse, "Could not parse dex file")?;
+    check_version(editor.version)?;
+    Ok(editor)
+}
+
+/// Writes the edited dex file with the version chosen by `version`
+fn write_dex(editor: &mut DexEditor, version: DexVersion, out: &Path) -> Result<(), Failure> {
+    for feature in dex_version::update_version(editor, version.into()) {
+        tracing::warn!(first_use = %feature.first, "{} needs dex version {:03}, the written file declares {:03}", feature.feature, feature.version, editor.version);
+    }
+    let data = editor.to_bytes().or_fail(Exit::Output, "Could not write dex file")?;
+    atomic::write(out, &data).or_fail(Exit::Output, "Could not write dex file")
+}
+
+/// Listing of the dex file at `path`, from the index cache if enabled
+fn list(path: &Path, loader: &mut Loader, cache: Option<&IndexCache>, listing: fn(&DexFile) -> Table, cached: fn(Index) -> Table) -> Result<Table, Failure> {
+    Ok(match cache {
+        Some(cache) => {
+            let data = read(path)?;
+            cached(cache.get_or_insert_with(&data, || parse(&data, None))?)
+        }
+        None => listing(loader.load(path)?),
+    })
+}
+
+/// Parses `data`, incrementally if the `previous` data it was changed from is given with its model
+fn parse(data: &[u8], previous: Option<(InputData, DexFile)>) -> Result<DexFile, Failure> {
+    // Only drawn if stderr is a terminal
+    let bar = if QUIET.get() == Some(&true) { ProgressBar::hidden() } else { ProgressBar::new(0) };
+    bar.set_style(ProgressStyle::with_template("{msg:>12} [{bar:40}] {bytes}/{total_bytes}")
+        .expect("Invalid progress template")
+        .progress_chars("=> "));
+    let mut progress = |progress: Progress| {
+        bar.set_length(progress.total_bytes);
+        bar.set_position(progress.bytes);
+        bar.set_message(progress.section);
+    };
+    let options = ParseOptions::new()
+        .strictness(STRICTNESS.get().copied().unwrap_or_default())
+        .progress(&mut progress);
+    let dex = match previous {
+        Some((old_data, old)) => options.reparse(old, &old_data, data),
+        None => options.parse(&mut Cursor::new(data)),
+    }.or_fail(Exit::Parse, "Could not parse dex file")?;
+    bar.finish_and_clear();
+    check_version(dex.version())?;
+    Ok(dex)
+}
+
+fn check_version(version: u16) -> Result<(), Failure> {
+    if !SUPPORTED_DEX_VERSIONS.contains(&version) {
+        return Err(Failure::new(Exit::UnsupportedVersion, format!("Unsupported Dex Format Version ({})", version)));
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+use dex_tool::atomic::AtomicFile;
+use dex_tool::highlight::{HighlightWriter, Syntax};
+
+/// Standard output of the commands, optionally highlighted and paged, or the file of --output
+pub struct Output {
+    writer: Option<Box<dyn Write>>,
+    pager: Option<Child>,
+    file: Option<AtomicFile>,
+}
+
+impl Output {
+    /// Colors and paging are only used if stdout is a terminal
+    pub fn new(syntax: Syntax, color: bool, page: bool) -> Output {
+        let terminal = std::io::stdout().is_terminal();
+        let color = color && terminal && std::env::var_os("NO_COLOR").is_none();
+        let mut pager = if page && terminal { spawn_pager(color) } else { None };
+
+        let writer: Box<dyn Write> = match pager.as_mut().and_then(|it| it.stdin.take()) {
+            Some(stdin) => Box::new(std::io::BufWriter::new(stdin)),
+            None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+        };
+        let writer = if color { Box::new(HighlightWriter::new(writer, syntax)) } else { writer };
+        Output { writer: Some(writer), pager, file: None }
+    }
+
+    /// Output into the file at `path`, which is only replaced once the output is finished
+    pub fn file(path: &Path) -> std::io::Result<Output> {
+        Ok(Output { writer: None, pager: None, file: Some(AtomicFile::create(path)?) })
+    }
+
+    /// Flushes the output, replaces the file of --output and waits for the user to quit the pager. Without
+    /// it (e.g. if the command failed) the file is left as it was.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            return file.commit();
+        }
+        match self.writer.take() {
+            Some(mut writer) => ignore_broken_pipe(writer.flush()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Spawns `$PAGER` (or `less`), which exits right away if the output fits on the screen
+fn spawn_pager(color: bool) -> Option<Child> {
+    let pager = std::env::var("PAGER").ok().filter(|it| !it.trim().is_empty()).unwrap_or_else(|| String::from("less"));
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", if color { "FRX" } else { "FX" });
+    }
+    command.spawn().ok()
+}
+
+/// Once the pager was closed, the remaining output is discarded
+fn ignore_broken_pipe<T: Default>(result: std::io::Result<T>) -> std::io::Result<T> {
+    match result {
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(T::default()),
+        result => result,
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(file) = self.file.as_mut() {
+            return file.write(buf);
+        }
+        match self.writer.as_mut() {
+            Some(writer) => match writer.write(buf) {
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+                result => result,
+            },
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            return file.flush();
+        }
+        match self.writer.as_mut() {
+            Some(writer) => ignore_broken_pipe(writer.flush()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            let _ = writer.flush();
+        }
+        // The writer closed the pipe, wait for the user to quit the pager
+        if let Some(mut pager) = self.pager.take() {
+            let _ = pager.wait();
+        }
+        // The temporary file of an unfinished output is removed by its drop
+    }
+}
@@ -1,14 +1,17 @@
-use std::convert::TryFrom;
-use std::fs::{File, read};
-use std::io::{BufReader, Read, Seek};
-use std::io::SeekFrom::{Current, Start};
 
-use memmap::Mmap;
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::io::{self, Read, Seek};
+use crate::io::SeekFrom::Start;
+use crate::prelude::*;
+
 use scroll::{ctx, Endian, Pread};
 use scroll::ctx::TryFromCtx;
 
+use crate::checked;
+use crate::error::DexError;
 use crate::m_utf8;
-use crate::raw_dex::EncodedValue::Boolean;
 use crate::raw_dex::Visibility::{VisibilityBuild, VisibilityRuntime, VisibilitySystem};
 
 // Bytes [4..7] specify Dex Format Version
@@ -17,67 +20,160 @@ const DEX_FILE_MAGIC: [u8; 8] = [0x64, 0x65, 0x78, 0x0a, 0x30, 0x33, 0x39, 0x00]
 const ENDIAN_CONSTANT: u32 = 0x12345678;
 const REVERSE_ENDIAN_CONSTANT: u32 = 0x78563412;
 
-pub fn read_u8(reader: &mut dyn Read, buf: &mut [u8; 1]) -> Result<u8, std::io::Error> {
+pub fn read_u8<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8; 1]) -> Result<u8, io::Error> {
     reader.read_exact(buf)?;
     Ok(buf[0])
 }
 
-pub fn read_u16(reader: &mut dyn Read) -> Result<u16, std::io::Error> {
+pub fn read_u16<R: Read + ?Sized>(reader: &mut R) -> Result<u16, io::Error> {
     let mut buf = [0u8; 2];
-    reader.read(&mut buf)?;
+    reader.read_exact(&mut buf)?;
     Ok(u16::from_le_bytes(buf))
 }
 
-pub fn read_u32(reader: &mut dyn Read) -> Result<u32, std::io::Error> {
+pub fn read_u32<R: Read + ?Sized>(reader: &mut R) -> Result<u32, io::Error> {
     let mut buf = [0u8; 4];
-    reader.read(&mut buf)?;
+    reader.read_exact(&mut buf)?;
     Ok(u32::from_le_bytes(buf))
 }
 
-pub fn parse_string_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<u32>, std::io::Error> {
+pub fn read_u64<R: Read + ?Sized>(reader: &mut R) -> Result<u64, io::Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn read_i8<R: Read + ?Sized>(reader: &mut R) -> Result<i8, io::Error> {
+    Ok(read_u8(reader, &mut [0u8])? as i8)
+}
+
+pub fn read_i16<R: Read + ?Sized>(reader: &mut R) -> Result<i16, io::Error> {
+    Ok(read_u16(reader)? as i16)
+}
+
+pub fn read_i32<R: Read + ?Sized>(reader: &mut R) -> Result<i32, io::Error> {
+    Ok(read_u32(reader)? as i32)
+}
+
+pub fn read_i64<R: Read + ?Sized>(reader: &mut R) -> Result<i64, io::Error> {
+    Ok(read_u64(reader)? as i64)
+}
+
+/// Reads the bytes of a LEB128 value (at most 5 in dex files), returns the value bits and their count
+fn read_leb128<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<(u32, u32), DexError> {
+    let offset = reader.stream_position()?;
+    let mut result = 0u32;
+    let mut buf = [0u8; 1];
+    for shift in (0..35).step_by(7) {
+        match reader.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Err(DexError::TruncatedLeb128 { offset }),
+            Err(err) => return Err(err.into()),
+        }
+        // The 5th byte holds the 4 highest bits, any other bits are ignored like by the runtime
+        result |= ((buf[0] & 0x7f) as u32) << shift;
+        if buf[0] & 0x80 == 0 {
+            return Ok((result, shift + 7));
+        }
+    }
+    Err(DexError::Leb128TooLong { offset })
+}
+
+/// Reads an unsigned LEB128 value
+pub fn read_uleb128<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<u32, DexError> {
+    read_leb128(reader).map(|(value, _)| value)
+}
+
+/// Appends an unsigned LEB128 value
+pub fn write_uleb128(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads an index encoded as uleb128p1, see OptionalIdx
+pub fn read_uleb128p1<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<OptionalIdx, DexError> {
+    read_uleb128(reader).map(|value| OptionalIdx(value.checked_sub(1)))
+}
+
+/// Reads a signed LEB128 value
+pub fn read_sleb128<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<i32, DexError> {
+    let (value, bits) = read_leb128(reader)?;
+    // Sign extension
+    Ok(if bits < 32 { ((value << (32 - bits)) as i32) >> (32 - bits) } else { value as i32 })
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.string_ids_off, size = dex_header.string_ids_size))]
+pub fn parse_string_ids<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<u32>, io::Error> {
     reader.seek(Start(dex_header.string_ids_off.into()))?;
 
-    let mut offsets = Vec::with_capacity(dex_header.string_ids_size as usize);
+    let mut offsets = Vec::with_capacity(checked::capacity(dex_header.string_ids_size));
     for _ in 0..dex_header.string_ids_size {
         offsets.push(read_u32(reader)?);
     }
     Ok(offsets)
 }
 
-pub fn parse_string_data(string_data_offs: Vec<u32>, reader: &mut BufReader<File>) -> Result<Vec<String>, std::io::Error> {
-    let mut strings = Vec::with_capacity(string_data_offs.len());
+pub fn parse_string_data<R: Read + Seek + ?Sized>(string_data_offs: Vec<u32>, reader: &mut R) -> Result<Vec<String>, io::Error> {
+    let items = parse_string_data_items(&string_data_offs, reader)?;
+    Ok(items.into_iter().zip(string_data_offs).enumerate().map(|(idx, (item, off))| {
+        if !item.errors.is_empty() {
+            tracing::warn!(string_idx = idx, offset = off, errors = ?item.errors, "Invalid MUTF-8 in string data");
+        }
+        item.value
+    }).collect())
+}
 
-    for off in string_data_offs {
-        reader.seek(Start(off.into()))?;
+/// A decoded string_data_item
+#[derive(Debug)]
+pub struct StringData {
+    /// Decoded value, invalid MUTF-8 sequences (e.g. in obfuscated names) are replaced
+    pub value: String,
+    /// Size of the item in bytes
+    pub size: u32,
+    /// The invalid sequences that were replaced
+    pub errors: Vec<m_utf8::MUtf8ParseError>,
+}
 
-        let size = leb128::read::unsigned(reader).unwrap();
+/// Reads the string_data_item at each offset
+#[tracing::instrument(level = "debug", skip_all, fields(size = string_data_offs.len()))]
+pub fn parse_string_data_items<R: Read + Seek + ?Sized>(string_data_offs: &[u32], reader: &mut R) -> Result<Vec<StringData>, io::Error> {
+    let mut items = Vec::with_capacity(string_data_offs.len());
 
-        // UTF-8 Encoding ("" if it fails)
-        // let mut v = vec![0u8; size as usize];
-        // reader.read_exact(&mut v).unwrap();
-        // let string = String::from_utf8(v).unwrap_or(String::new());
+    for (idx, &off) in string_data_offs.iter().enumerate() {
+        let mut read = || -> Result<StringData, io::Error> {
+            reader.seek(Start(off.into()))?;
 
-        // MUTF-8 Encoding
-        strings.push(m_utf8::to_string(reader, size).map_err(| it | std::io::Error::new(std::io::ErrorKind::Other, it.to_string()))?);
+            let size = read_uleb128(reader)?;
+
+            let decoded = m_utf8::to_string_lossy(&m_utf8::read_data(reader)?, size.into());
+            let size = (reader.stream_position()? - u64::from(off)) as u32;
+            Ok(StringData { value: decoded.string.value, size, errors: decoded.errors })
+        };
+        items.push(read().map_err(|err| DexError::context(err, format!("string_data_item #{}", idx), off.into()))?);
     }
 
-    Ok(strings)
+    Ok(items)
 }
 
-pub fn parse_type_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<u32>, std::io::Error> {
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.type_ids_off, size = dex_header.type_ids_size))]
+pub fn parse_type_ids<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<u32>, io::Error> {
     reader.seek(Start(dex_header.type_ids_off.into()))?;
 
-    let mut type_ids: Vec<u32> = Vec::with_capacity(dex_header.type_ids_size as usize);
+    let mut type_ids: Vec<u32> = Vec::with_capacity(checked::capacity(dex_header.type_ids_size));
     for _ in 0..dex_header.type_ids_size {
         type_ids.push(read_u32(reader)?);
     }
     Ok(type_ids)
 }
 
-pub fn parse_proto_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<ProtoIdItem>, std::io::Error> {
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.proto_ids_off, size = dex_header.proto_ids_size))]
+pub fn parse_proto_ids<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<ProtoIdItem>, io::Error> {
     reader.seek(Start(dex_header.proto_ids_off.into()))?;
 
-    let mut v = Vec::with_capacity(dex_header.proto_ids_size as usize);
+    let mut v = Vec::with_capacity(checked::capacity(dex_header.proto_ids_size));
     for _ in 0..dex_header.proto_ids_size {
         v.push(ProtoIdItem {
             shorty_idx: read_u32(reader)?,
@@ -88,10 +184,11 @@ pub fn parse_proto_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) ->
     Ok(v)
 }
 
-pub fn parse_field_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<FieldId>, std::io::Error> {
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.field_ids_off, size = dex_header.field_ids_size))]
+pub fn parse_field_ids<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<FieldId>, io::Error> {
     reader.seek(Start(dex_header.field_ids_off.into()))?;
 
-    let mut v = Vec::with_capacity(dex_header.field_ids_size as usize);
+    let mut v = Vec::with_capacity(checked::capacity(dex_header.field_ids_size));
     for _ in 0..dex_header.field_ids_size {
         v.push(FieldId {
             class_idx: read_u16(reader)?,
@@ -102,10 +199,11 @@ pub fn parse_field_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) ->
     Ok(v)
 }
 
-pub fn parse_method_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<MethodId>, std::io::Error> {
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.method_ids_off, size = dex_header.method_ids_size))]
+pub fn parse_method_ids<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<MethodId>, io::Error> {
     reader.seek(Start(dex_header.method_ids_off.into()))?;
 
-    let mut v = Vec::with_capacity(dex_header.method_ids_size as usize);
+    let mut v = Vec::with_capacity(checked::capacity(dex_header.method_ids_size));
     for _ in 0..dex_header.method_ids_size {
         v.push(MethodId {
             class_idx: read_u16(reader)?,
@@ -116,10 +214,11 @@ pub fn parse_method_ids(dex_header: &DexHeader, reader: &mut BufReader<File>) ->
     Ok(v)
 }
 
-pub fn parse_class_defs(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<ClassDef>, std::io::Error> {
+#[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.class_defs_off, size = dex_header.class_defs_size))]
+pub fn parse_class_defs<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<ClassDef>, io::Error> {
     reader.seek(Start(dex_header.class_defs_off.into()))?;
 
-    let mut v = Vec::with_capacity(dex_header.class_defs_size as usize);
+    let mut v = Vec::with_capacity(checked::capacity(dex_header.class_defs_size));
     for _ in 0..dex_header.class_defs_size {
         v.push(ClassDef {
             class_idx: read_u32(reader)?,
@@ -135,92 +234,32 @@ pub fn parse_class_defs(dex_header: &DexHeader, reader: &mut BufReader<File>) ->
     Ok(v)
 }
 
-// TODO Untested
-pub fn parse_call_side_ids(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<u32>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x07);
-    if item.is_none() { return Ok(Vec::new()); }
-    let item = item.unwrap();
+/// Parses the items of the section of `item_type` in the map list with `parse`, adding the `name` and
+/// index of an item to the error of parsing it. Files without the section have no items of it.
+fn parse_section<R: Read + Seek + ?Sized, T>(map_list: &[MapItem], item_type: u16, name: &str, reader: &mut R,
+                                            mut parse: impl FnMut(&mut R) -> Result<T, io::Error>) -> Result<Vec<T>, io::Error> {
+    let item = match find_type_in_map(map_list, item_type) {
+        Some(item) => item,
+        None => return Ok(Vec::new()),
+    };
     reader.seek(Start(item.offset.into()))?;
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        v.push(read_u32(reader)?);
+    let mut v = Vec::with_capacity(checked::capacity(item.size));
+    for i in 0..item.size {
+        let offset = reader.stream_position()?;
+        v.push(parse(reader).map_err(|err| DexError::context(err, format!("{} #{}", name, i), offset))?);
     }
     Ok(v)
 }
 
-// TODO Untested
-pub fn parse_call_side_item(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) {
-    let item = find_type_in_map(map_list, 0x07);
-
-    if item.is_some() {
-        panic!("Call Site Id Item was not null!");
-    }
-    // let item = item.unwrap();
-    //
-    // reader.seek(Start(item.offset.into())).unwrap();
-    //
-    // let mut offsets = Vec::with_capacity(item.size as usize);
-    // for _ in 0..item.size {
-    //     offsets.push(read_u32(reader));
-    // }
-    // let mut buf = [0u8; 1];
-    // reader.seek(Start(offset.into())).unwrap();
-    //
-    // let size = leb128::read::unsigned(reader).unwrap();
-    // let method_handle = raw_encoded_value_u32(reader, 0x16, &mut buf);
-    // let method_name = raw_encoded_value_u32(reader, 0x17, &mut buf);
-    // let method_type = raw_encoded_value_u32(reader, 0x15, &mut buf);
-    // for _ in 0..size - 3 {
-    //     parse_encoded_value(reader, &mut buf);
-    // }
-    // fn raw_encoded_value_u32(reader: &mut BufReader<File>, expected_type: u8, buf: &mut [u8; 1]) -> u32 {
-    //     let (value_arg, value_type) = raw_encoded_value_pre(reader, buf);
-    //
-    //     // debug_assert!(value_type == 0x15 || value_type == 0x16 || value_type == 0x17);
-    //     assert_eq!(value_type, expected_type);
-    //
-    //     let mut v = vec![0u8; value_arg as usize + 1];
-    //     reader.read_exact(v.as_mut_slice()).unwrap();
-    //     u32::from_le_bytes(v.as_slice().try_into().unwrap())
-    // }
-    //
-    // fn parse_encoded_value(reader: &mut BufReader<File>, buf: &mut [u8; 1]) -> EncodedValue {
-    //     let (value_type, value_arg) = raw_encoded_value_pre(reader, buf);
-    //     println!("Encoded Value: {:?}", (value_type, value_arg));
-    //     match value_type {
-    //         _ => panic!()
-    //     }
-    // }
-    //
-    // /// Returns the first byte of an encoded value (value_arg, value_type) as tuple
-    // fn raw_encoded_value_pre(reader: &mut BufReader<File>, buf: &mut [u8; 1]) -> (u8, u8) {
-    //     let byte = read_u8(reader, buf);
-    //     let value_arg = (byte & 0xe0) >> 5;
-    //     let value_type = byte & 0x1f;
-    //
-    //     debug_assert!(match value_type {
-    //         0x00 | 0x1c..=0x1e => value_arg == 0,
-    //         0x02 | 0x03 | 0x1f => value_arg == 0 || value_arg == 1,
-    //         0x04 | 0x10 | 0x15..=0x1b => value_arg <= 3,
-    //         0x06 | 0x11 => 0 <= value_arg && value_arg <= 7,
-    //         _ => panic!("Unmatched Value Type after exhaustively checking every listed format")
-    //     });
-    //
-    //     (value_arg, value_type)
-    // }
+/// Offsets of the encoded arrays of the call sites
+pub fn parse_call_side_ids<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<u32>, io::Error> {
+    parse_section(map_list, 0x07, "call_site_id_item", reader, read_u32)
 }
 
-// TODO Untested
-pub fn parse_method_handles(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<MethodHandle>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x08);
-    if item.is_none() { return Ok(Vec::new()); }
-    let item = item.unwrap();
-    reader.seek(Start(item.offset.into()))?;
-
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        v.push(MethodHandle {
+pub fn parse_method_handles<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<MethodHandle>, io::Error> {
+    parse_section(map_list, 0x08, "method_handle_item", reader, |reader| {
+        Ok(MethodHandle {
             method_handle_type: read_u16(reader)?,
             field_or_method_id: {
                 let mut buf = [0u8; 2];
@@ -229,87 +268,103 @@ pub fn parse_method_handles(map_list: &Vec<MapItem>, reader: &mut BufReader<File
                 reader.read_exact(&mut buf)?; // Unused
                 used
             },
-        });
-    }
-    Ok(v)
+        })
+    })
 }
 
-pub fn parse_class_data(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<ClassData>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x2000);
-    if item.is_none() { panic!("No Class Data Offset Found"); }
-    let item = item.unwrap();
-    reader.seek(Start(item.offset.into()))?;
+pub fn parse_class_data<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<ClassData>, io::Error> {
+    parse_section(map_list, 0x2000, "class_data_item", reader, ClassData::from_reader)
+}
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        let static_fields_size = leb128::read::unsigned(reader).unwrap();
-        let instance_fields_size = leb128::read::unsigned(reader).unwrap();
-        let direct_methods_size = leb128::read::unsigned(reader).unwrap();
-        let virtual_methods_size = leb128::read::unsigned(reader).unwrap();
-
-        let mut static_fields = Vec::with_capacity(static_fields_size as usize);
-        let mut instance_fields = Vec::with_capacity(instance_fields_size as usize);
-        let mut direct_methods = Vec::with_capacity(direct_methods_size as usize);
-        let mut virtual_methods = Vec::with_capacity(virtual_methods_size as usize);
-
-        fn read_encoded_field(reader: &mut BufReader<File>) -> EncodedField {
-            EncodedField {
-                field_idx_diff: leb128::read::unsigned(reader).unwrap(),
-                access_flags: leb128::read::unsigned(reader).unwrap(),
-            }
+impl ClassData {
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<ClassData, io::Error> {
+        let static_fields_size = read_uleb128(reader)?;
+        let instance_fields_size = read_uleb128(reader)?;
+        let direct_methods_size = read_uleb128(reader)?;
+        let virtual_methods_size = read_uleb128(reader)?;
+
+        let mut static_fields = Vec::with_capacity(checked::capacity(static_fields_size));
+        let mut instance_fields = Vec::with_capacity(checked::capacity(instance_fields_size));
+        let mut direct_methods = Vec::with_capacity(checked::capacity(direct_methods_size));
+        let mut virtual_methods = Vec::with_capacity(checked::capacity(virtual_methods_size));
+
+        fn read_encoded_field<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<EncodedField, DexError> {
+            Ok(EncodedField {
+                field_idx_diff: read_uleb128(reader)?.into(),
+                access_flags: read_uleb128(reader)?.into(),
+            })
         }
-        fn read_encoded_method(reader: &mut BufReader<File>) -> EncodedMethod {
-            EncodedMethod {
-                method_idx_diff: leb128::read::unsigned(reader).unwrap(),
-                access_flags: leb128::read::unsigned(reader).unwrap(),
-                code_off: leb128::read::unsigned(reader).unwrap(),
-            }
+        fn read_encoded_method<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<EncodedMethod, DexError> {
+            Ok(EncodedMethod {
+                method_idx_diff: read_uleb128(reader)?.into(),
+                access_flags: read_uleb128(reader)?.into(),
+                code_off: read_uleb128(reader)?.into(),
+            })
         }
         for _ in 0..static_fields_size {
-            static_fields.push(read_encoded_field(reader));
+            static_fields.push(read_encoded_field(reader)?);
         }
         for _ in 0..instance_fields_size {
-            instance_fields.push(read_encoded_field(reader));
+            instance_fields.push(read_encoded_field(reader)?);
         }
         for _ in 0..direct_methods_size {
-            direct_methods.push(read_encoded_method(reader));
+            direct_methods.push(read_encoded_method(reader)?);
         }
         for _ in 0..virtual_methods_size {
-            virtual_methods.push(read_encoded_method(reader));
+            virtual_methods.push(read_encoded_method(reader)?);
         }
-        v.push(ClassData { static_fields, instance_fields, direct_methods, virtual_methods });
+        Ok(ClassData { static_fields, instance_fields, direct_methods, virtual_methods })
     }
-    Ok(v)
 }
 
 /// Returns a Vec of TypeLists (Vector of u16 as indices into the type_ids list)
-pub fn parse_type_lists(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<Vec<u16>>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x1001).unwrap();
-    reader.seek(Start(item.offset.into()))?;
+pub fn parse_type_lists<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<Vec<u16>>, io::Error> {
+    parse_section(map_list, 0x1001, "type_list", reader, |reader| {
+        let type_list = parse_type_list(reader)?;
+        // alignment: 4 bytes --> ignore last 2 bytes if needed
+        if type_list.len() % 2 == 1 { reader.read_exact(&mut [0u8; 2])?; }
+        Ok(type_list)
+    })
+}
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    let mut buf = [0u8; 2];
+/// Reads a single TypeList at the current position of the reader
+pub fn parse_type_list<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<Vec<u16>, io::Error> {
+    let size = read_u32(reader)?;
+    let mut type_list = Vec::with_capacity(checked::capacity(size));
+    for _ in 0..size {
+        type_list.push(read_u16(reader)?);
+    }
+    Ok(type_list)
+}
 
-    for _ in 0..item.size {
-        let size = read_u32(reader)?;
-        let mut type_list = Vec::with_capacity(size as usize);
-        for _ in 0..size {
-            type_list.push(read_u16(reader)?);
-        }
-        // alignment: 4 bytes --> ignore last 2 bytes if needed
-        if size % 2 == 1 { reader.read_exact(&mut buf)?; }
-        v.push(type_list);
+/// Reads an encoded_array_item (e.g. the static values of a class) at the current position of the reader
+pub fn parse_encoded_array<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<Vec<EncodedValue>, io::Error> {
+    let size = read_uleb128(reader)?;
+    let mut values = Vec::with_capacity(checked::capacity(size));
+    for _ in 0..size {
+        values.push(EncodedValue::from_reader(reader)?);
     }
-    Ok(v)
+    Ok(values)
 }
 
-pub fn parse_code_items(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<CodeItem>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x2001).unwrap();
-    reader.seek(Start(item.offset.into()))?;
+pub fn parse_code_items<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<CodeItem>, io::Error> {
+    parse_section(map_list, 0x2001, "code_item", reader, |reader| {
+        let start_pos = reader.stream_position()?;
+        let code = CodeItem::from_reader(reader)?;
+        let item_size = reader.stream_position()? - start_pos;
+        if item_size % 4 != 0 {
+            let mut v = vec![0u8; (4 - item_size % 4) as usize];
+            reader.read_exact(v.as_mut_slice())?;
+        }
+        Ok(code)
+    })
+}
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    let mut buf = [0u8; 2];
-    for _ in 0..item.size {
+impl CodeItem {
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<CodeItem, io::Error> {
+        let mut buf = [0u8; 2];
         let registers_size = read_u16(reader)?;
         let ins_size = read_u16(reader)?;
         let outs_size = read_u16(reader)?;
@@ -317,14 +372,13 @@ pub fn parse_code_items(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -
         let debug_info_off = read_u32(reader)?;
         let insns_size = read_u32(reader)?;
 
-        let mut current_pos = reader.seek(Current(0))?;
-        v.push(CodeItem {
+        Ok(CodeItem {
             registers_size,
             ins_size,
             outs_size,
             debug_info_off,
             insns: {
-                let mut v = Vec::with_capacity(insns_size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(insns_size));
                 for _ in 0..insns_size {
                     v.push(read_u16(reader)?);
                 }
@@ -347,93 +401,164 @@ pub fn parse_code_items(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -
             },
             handlers: {
                 if tries_size == 0 { Vec::new() } else {
-                    let size = leb128::read::unsigned(reader).unwrap();
-                    let mut v = Vec::with_capacity(size as usize);
+                    let list_start = reader.stream_position()?;
+                    let size = read_uleb128(reader)?;
+                    let mut v = Vec::with_capacity(checked::capacity(size));
                     for _ in 0..size {
-                        let size = leb128::read::signed(reader).unwrap();
+                        let offset = (reader.stream_position()? - list_start) as u16;
+                        let size = read_sleb128(reader)?;
                         v.push(EncodedCatchHandler {
+                            offset,
                             handlers: {
-                                let abs_size = size.abs();
-                                let mut v = Vec::with_capacity(abs_size as usize);
+                                let abs_size = size.unsigned_abs();
+                                let mut v = Vec::with_capacity(checked::capacity(abs_size));
                                 for _ in 0..abs_size {
                                     v.push(
                                         EncodedTypeAddrPair {
-                                            type_idx: leb128::read::unsigned(reader).unwrap(),
-                                            addr: leb128::read::unsigned(reader).unwrap(),
+                                            type_idx: read_uleb128(reader)?.into(),
+                                            addr: read_uleb128(reader)?.into(),
                                         });
                                 }
                                 v
                             },
                             catch_all_addr: {
-                                if size > 0 { None } else { Some(leb128::read::unsigned(reader).unwrap()) }
+                                if size > 0 { None } else { Some(read_uleb128(reader)?.into()) }
                             },
                         })
                     }
                     v
                 }
             },
-        });
-        current_pos = reader.seek(Current(0))? - current_pos;
-        if current_pos % 4 != 0 {
-            let mut v = vec![0u8; (4 - current_pos % 4) as usize];
-            reader.read_exact(v.as_mut_slice())?;
-        }
+        })
     }
-    Ok(v)
 }
 
+pub fn parse_debug_info<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<DebugInfoItem>, io::Error> {
+    parse_section(map_list, 0x2003, "debug_info_item", reader, DebugInfoItem::from_reader)
+}
 
-pub fn parse_debug_info(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<DebugInfoItem>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x2003);
-    if item.is_none() { panic!("No Debug Info Found") }
-    let item = item.unwrap();
-
-    reader.seek(Start(item.offset.into()))?;
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        v.push(DebugInfoItem {
-            line_start: leb128::read::unsigned(reader).unwrap(),
+impl DebugInfoItem {
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<DebugInfoItem, io::Error> {
+        Ok(DebugInfoItem {
+            line_start: read_uleb128(reader)?.into(),
             parameter_names: {
-                let size = leb128::read::unsigned(reader).unwrap();
+                let size = read_uleb128(reader)?;
 
-                let mut v = Vec::with_capacity(size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(size));
                 for _ in 0..size {
-                    v.push(i64::try_from(leb128::read::unsigned(reader).unwrap()).unwrap() - 1);
+                    v.push(read_uleb128p1(reader)?);
                 }
                 v
             },
-            state_machine_bytes: {
+            bytecode: {
                 let mut buf = [0u8];
                 let mut v = Vec::new();
                 loop {
-                    reader.read_exact(&mut buf)?;
-                    if buf[0] == 0x00 {
-                        break;
-                    }
-                    v.push(buf[0]);
+                    v.push(match read_u8(reader, &mut buf)? {
+                        0x00 => break,
+                        0x01 => DebugInstruction::AdvancePc(read_uleb128(reader)?.into()),
+                        0x02 => DebugInstruction::AdvanceLine(read_sleb128(reader)?.into()),
+                        0x03 => DebugInstruction::StartLocal {
+                            register_num: read_uleb128(reader)?.into(),
+                            name_idx: read_uleb128p1(reader)?,
+                            type_idx: read_uleb128p1(reader)?,
+                        },
+                        0x04 => DebugInstruction::StartLocalExtended {
+                            register_num: read_uleb128(reader)?.into(),
+                            name_idx: read_uleb128p1(reader)?,
+                            type_idx: read_uleb128p1(reader)?,
+                            sig_idx: read_uleb128p1(reader)?,
+                        },
+                        0x05 => DebugInstruction::EndLocal(read_uleb128(reader)?.into()),
+                        0x06 => DebugInstruction::RestartLocal(read_uleb128(reader)?.into()),
+                        0x07 => DebugInstruction::SetPrologueEnd,
+                        0x08 => DebugInstruction::SetEpilogueBegin,
+                        0x09 => DebugInstruction::SetFile(read_uleb128p1(reader)?),
+                        special => DebugInstruction::Special(special),
+                    });
                 }
                 v
             },
-        });
+        })
     }
-    Ok(v)
 }
 
-pub fn parse_annotations_directories(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<AnnotationsDirectory>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x2006).unwrap();
-    reader.seek(Start(item.offset.into()))?;
+pub fn parse_annotations_directories<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<AnnotationsDirectory>, io::Error> {
+    parse_section(map_list, 0x2006, "annotations_directory_item", reader, AnnotationsDirectory::from_reader)
+}
+
+pub fn parse_annotation_set_ref_list<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<Vec<u32>>, io::Error> {
+    parse_section(map_list, 0x1002, "annotation_set_ref_list", reader, read_offset_list)
+}
+
+pub fn parse_annotation_set_item<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<Vec<u32>>, io::Error> {
+    parse_section(map_list, 0x1003, "annotation_set_item", reader, read_offset_list)
+}
+
+pub fn parse_annotation_item<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<AnnotationItem>, io::Error> {
+    parse_section(map_list, 0x2004, "annotation_item", reader, AnnotationItem::from_reader)
+}
+
+/// Reads an annotation_set_item or annotation_set_ref_list, a u32 size followed by as many offsets
+pub(crate) fn read_offset_list<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<Vec<u32>, io::Error> {
+    let size = read_u32(reader)?;
+    let mut list = Vec::with_capacity(checked::capacity(size));
+    for _ in 0..size {
+        list.push(read_u32(reader)?);
+    }
+    Ok(list)
+}
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
+/// Parses the annotations_directory_item at `off` (e.g. the annotations_off of a class definition),
+/// None for an offset of 0
+pub fn parse_annotations_directory_at<R: Read + Seek + ?Sized>(off: u32, reader: &mut R) -> Result<Option<AnnotationsDirectory>, io::Error> {
+    if off == 0 {
+        return Ok(None);
+    }
+    reader.seek(Start(off.into()))?;
+    AnnotationsDirectory::from_reader(reader).map(Some)
+}
+
+/// Parses the offsets of the annotation_set_item at `off`, an offset of 0 denoting an empty set
+pub fn parse_annotation_set_at<R: Read + Seek + ?Sized>(off: u32, reader: &mut R) -> Result<Vec<u32>, io::Error> {
+    if off == 0 {
+        return Ok(Vec::new());
+    }
+    reader.seek(Start(off.into()))?;
+    read_offset_list(reader)
+}
+
+/// Parses the annotation set offsets of the annotation_set_ref_list at `off` (one per parameter, 0 if
+/// the parameter has no annotations), an offset of 0 denoting an empty list
+pub fn parse_annotation_set_ref_list_at<R: Read + Seek + ?Sized>(off: u32, reader: &mut R) -> Result<Vec<u32>, io::Error> {
+    parse_annotation_set_at(off, reader)
+}
+
+/// Parses the annotation_item at `off`
+pub fn parse_annotation_item_at<R: Read + Seek + ?Sized>(off: u32, reader: &mut R) -> Result<AnnotationItem, io::Error> {
+    reader.seek(Start(off.into()))?;
+    AnnotationItem::from_reader(reader)
+}
+
+/// Parses the annotations of the annotation_set_item at `off`, following the offsets of the set
+pub fn parse_annotations_at<R: Read + Seek + ?Sized>(off: u32, reader: &mut R) -> Result<Vec<AnnotationItem>, io::Error> {
+    parse_annotation_set_at(off, reader)?.into_iter()
+        .map(|off| parse_annotation_item_at(off, reader))
+        .collect()
+}
+
+impl AnnotationsDirectory {
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<AnnotationsDirectory, io::Error> {
         let class_annotations_off = read_u32(reader)?;
         let fields_size = read_u32(reader)?;
         let annotated_methods_size = read_u32(reader)?;
         let annotated_parameters_size = read_u32(reader)?;
 
-        v.push(AnnotationsDirectory {
+        Ok(AnnotationsDirectory {
             class_annotations_off,
             field_annotations: {
-                let mut v = Vec::with_capacity(fields_size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(fields_size));
                 for _ in 0..fields_size {
                     v.push(FieldAnnotation {
                         field_idx: read_u32(reader)?,
@@ -443,7 +568,7 @@ pub fn parse_annotations_directories(map_list: &Vec<MapItem>, reader: &mut BufRe
                 v
             },
             method_annotations: {
-                let mut v = Vec::with_capacity(annotated_methods_size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(annotated_methods_size));
                 for _ in 0..annotated_methods_size {
                     v.push(MethodAnnotation {
                         method_idx: read_u32(reader)?,
@@ -453,7 +578,7 @@ pub fn parse_annotations_directories(map_list: &Vec<MapItem>, reader: &mut BufRe
                 v
             },
             parameter_annotations: {
-                let mut v = Vec::with_capacity(annotated_parameters_size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(annotated_parameters_size));
                 for _ in 0..annotated_parameters_size {
                     v.push(ParameterAnnotation {
                         method_idx: read_u32(reader)?,
@@ -464,71 +589,58 @@ pub fn parse_annotations_directories(map_list: &Vec<MapItem>, reader: &mut BufRe
             },
         })
     }
-    Ok(v)
-}
-
-pub fn parse_annotation_set_ref_list(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<Vec<u32>>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x1002).unwrap();
-    reader.seek(Start(item.offset.into()))?;
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        let size = read_u32(reader)?;
-        let mut list = Vec::with_capacity(size as usize);
-        for _ in 0..size {
-            list.push(read_u32(reader)?);
-        }
-        v.push(list);
+    /// Offset of the annotation set of a field, None if the field has no annotations
+    pub fn field_annotations_off(&self, field_idx: u32) -> Option<u32> {
+        self.field_annotations.iter().find(|it| it.field_idx == field_idx).map(|it| it.annotations_off)
     }
-    Ok(v)
-}
 
-pub fn parse_annotation_set_item(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<Vec<u32>>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x1003).unwrap();
-    reader.seek(Start(item.offset.into()))?;
+    /// Offset of the annotation set of a method, None if the method has no annotations
+    pub fn method_annotations_off(&self, method_idx: u32) -> Option<u32> {
+        self.method_annotations.iter().find(|it| it.method_idx == method_idx).map(|it| it.annotations_off)
+    }
 
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
-        let size = read_u32(reader)?;
-        let mut list = Vec::with_capacity(size as usize);
-        for _ in 0..size {
-            list.push(read_u32(reader)?);
-        }
-        v.push(list);
+    /// Offset of the annotation set ref list of the parameters of a method, None if none of them has
+    /// annotations
+    pub fn parameter_annotations_off(&self, method_idx: u32) -> Option<u32> {
+        self.parameter_annotations.iter().find(|it| it.method_idx == method_idx).map(|it| it.annotations_off)
     }
-    Ok(v)
 }
 
-pub fn parse_annotation_item(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<AnnotationItem>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0x2004).unwrap();
-    reader.seek(Start(item.offset.into()))?;
-
-    let mut v = Vec::with_capacity(item.size as usize);
-    let mut buf = [0u8];
-    for _ in 0..item.size {
-        v.push(AnnotationItem {
-            visibility: match read_u8(reader, &mut buf)? {
+impl AnnotationItem {
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<AnnotationItem, io::Error> {
+        Ok(AnnotationItem {
+            visibility: match read_u8(reader, &mut [0u8])? {
                 0x00 => VisibilityBuild,
                 0x01 => VisibilityRuntime,
                 0x02 => VisibilitySystem,
-                _ => panic!("Unknown visibility byte")
+                value => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown annotation visibility 0x{:02x}", value))),
             },
             annotation: EncodedAnnotation::from_reader(reader)?,
-        });
+        })
     }
-    Ok(v)
 }
 
 impl EncodedAnnotation {
-    fn from_reader(reader: &mut BufReader<File>) -> Result<EncodedAnnotation, std::io::Error> {
+    /// Appends the encoded_annotation
+    pub fn write(&self, out: &mut Vec<u8>) {
+        write_uleb128(out, self.type_idx as u32);
+        write_uleb128(out, self.elements.len() as u32);
+        for element in &self.elements {
+            write_uleb128(out, element.name_idx as u32);
+            element.value.write(out);
+        }
+    }
+
+    fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<EncodedAnnotation, io::Error> {
         Ok(EncodedAnnotation {
-            type_idx: leb128::read::unsigned(reader).unwrap(),
+            type_idx: read_uleb128(reader)?.into(),
             elements: {
-                let size = leb128::read::unsigned(reader).unwrap();
-                let mut v = Vec::with_capacity(size as usize);
+                let size = read_uleb128(reader)?;
+                let mut v = Vec::with_capacity(checked::capacity(size));
                 for _ in 0..size {
                     v.push(AnnotationElement {
-                        name_idx: leb128::read::unsigned(reader).unwrap(),
+                        name_idx: read_uleb128(reader)?.into(),
                         value: EncodedValue::from_reader(reader)?,
                     });
                 }
@@ -539,40 +651,53 @@ impl EncodedAnnotation {
 }
 
 // TODO Untested
-pub fn parse_hiddenapi_class_data(map_list: &Vec<MapItem>, reader: &mut BufReader<File>) -> Result<Vec<HiddenApiClassData>, std::io::Error> {
-    let item = find_type_in_map(map_list, 0xF000);
-    if item.is_none() { return Ok(Vec::new()); }
-    let item = item.unwrap();
-    reader.seek(Start(item.offset.into()))?;
-
-    let mut v = Vec::with_capacity(item.size as usize);
-    for _ in 0..item.size {
+pub fn parse_hiddenapi_class_data<R: Read + Seek + ?Sized>(map_list: &[MapItem], reader: &mut R) -> Result<Vec<HiddenApiClassData>, io::Error> {
+    parse_section(map_list, 0xF000, "hiddenapi_class_data_item", reader, |reader| {
         let size = read_u32(reader)?;
-        v.push(HiddenApiClassData {
+        Ok(HiddenApiClassData {
             size,
             offsets: {
-                let mut v = Vec::with_capacity(size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(size));
                 for _ in 0..size {
                     v.push(read_u32(reader)?);
                 }
                 v
             },
             flags: {
-                let mut v = Vec::with_capacity(size as usize);
+                let mut v = Vec::with_capacity(checked::capacity(size));
                 for _ in 0..size {
-                    v.push(leb128::read::unsigned(reader).unwrap());
+                    v.push(read_uleb128(reader)?.into());
                 }
                 v
             },
         })
-    }
-    Ok(v)
+    })
 }
 
 
-#[derive(Debug)]
+// Types of encoded values
+const VALUE_BYTE: u8 = 0x00;
+const VALUE_SHORT: u8 = 0x02;
+const VALUE_CHAR: u8 = 0x03;
+const VALUE_INT: u8 = 0x04;
+const VALUE_LONG: u8 = 0x06;
+const VALUE_FLOAT: u8 = 0x10;
+const VALUE_DOUBLE: u8 = 0x11;
+const VALUE_METHOD_TYPE: u8 = 0x15;
+const VALUE_METHOD_HANDLE: u8 = 0x16;
+const VALUE_STRING: u8 = 0x17;
+const VALUE_TYPE: u8 = 0x18;
+const VALUE_FIELD: u8 = 0x19;
+const VALUE_METHOD: u8 = 0x1a;
+const VALUE_ENUM: u8 = 0x1b;
+const VALUE_ARRAY: u8 = 0x1c;
+const VALUE_ANNOTATION: u8 = 0x1d;
+const VALUE_NULL: u8 = 0x1e;
+const VALUE_BOOLEAN: u8 = 0x1f;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EncodedValue {
-    Byte(u8),
+    Byte(i8),
     Short(i16),
     Char(u16),
     Int(i32),
@@ -592,60 +717,214 @@ pub enum EncodedValue {
     Boolean(bool),
 }
 
+/// Error of the conversion of an EncodedValue into a primitive of another type, holds the value
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnexpectedValue(pub EncodedValue);
+
+impl fmt::Display for UnexpectedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unexpected encoded value {}", self.0)
+    }
+}
+
+impl core::error::Error for UnexpectedValue {}
+
+// Conversions into primitives, integers are widened like in Java assignments
+macro_rules! try_from_value {
+    ($target:ty, $($variant:ident)|+) => {
+        impl TryFrom<EncodedValue> for $target {
+            type Error = UnexpectedValue;
+
+            fn try_from(value: EncodedValue) -> Result<$target, UnexpectedValue> {
+                match value {
+                    $(EncodedValue::$variant(value) => Ok(value.into()),)+
+                    value => Err(UnexpectedValue(value)),
+                }
+            }
+        }
+    };
+}
+
+try_from_value!(bool, Boolean);
+try_from_value!(i8, Byte);
+try_from_value!(i16, Byte | Short);
+try_from_value!(u16, Char);
+try_from_value!(i32, Byte | Short | Char | Int);
+try_from_value!(i64, Byte | Short | Char | Int | Long);
+try_from_value!(f32, Float);
+try_from_value!(f64, Float | Double);
+
+/// Values with their indices, e.g. `string@12`, like the index operands of dexdump
+impl fmt::Display for EncodedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodedValue::Byte(value) => write!(f, "{}", value),
+            EncodedValue::Short(value) => write!(f, "{}", value),
+            EncodedValue::Char(value) => match char::from_u32(*value as u32) {
+                Some(c) => write!(f, "{:?}", c),
+                None => write!(f, "'\\u{:04x}'", value),
+            },
+            EncodedValue::Int(value) => write!(f, "{}", value),
+            EncodedValue::Long(value) => write!(f, "{}L", value),
+            EncodedValue::Float(value) => write!(f, "{:?}f", value),
+            EncodedValue::Double(value) => write!(f, "{:?}", value),
+            EncodedValue::MethodType(idx) => write!(f, "proto@{}", idx),
+            EncodedValue::MethodHandle(idx) => write!(f, "method_handle@{}", idx),
+            EncodedValue::String(idx) => write!(f, "string@{}", idx),
+            EncodedValue::Type(idx) => write!(f, "type@{}", idx),
+            EncodedValue::Field(idx) => write!(f, "field@{}", idx),
+            EncodedValue::Method(idx) => write!(f, "method@{}", idx),
+            EncodedValue::Enum(idx) => write!(f, "enum field@{}", idx),
+            EncodedValue::Array(values) => {
+                f.write_str("{")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("}")
+            }
+            EncodedValue::Annotation(annotation) => {
+                write!(f, "@type@{}(", annotation.type_idx)?;
+                for (i, element) in annotation.elements.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "string@{}={}", element.name_idx, element.value)?;
+                }
+                f.write_str(")")
+            }
+            EncodedValue::Null => f.write_str("null"),
+            EncodedValue::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Reads the `size` little endian bytes of a value
+fn read_sized<R: Read + ?Sized>(reader: &mut R, size: u8) -> Result<u64, io::Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf[..size as usize])?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Sign extends the lowest `size` bytes of `value`
+fn sign_extend(value: u64, size: u8) -> i64 {
+    let shift = 64 - 8 * size as u32;
+    ((value << shift) as i64) >> shift
+}
+
+/// Smallest number of bytes (at least 1) from which sign_extend restores `value`
+fn signed_size(value: i64) -> u8 {
+    (1..8).find(|size| sign_extend(value as u64, *size) == value).unwrap_or(8)
+}
+
+/// Smallest number of bytes (at least 1) holding `value` zero extended
+fn unsigned_size(value: u64) -> u8 {
+    (1..8).find(|size| value >> (8 * size) == 0).unwrap_or(8)
+}
+
+/// Smallest number of high-order bytes (at least 1) of a `width` byte value, the dropped low-order
+/// bytes being zero (floating point values are zero extended to the right)
+fn right_size(value: u64, width: u8) -> u8 {
+    width - (value.trailing_zeros() / 8).min(width as u32 - 1) as u8
+}
+
 impl EncodedValue {
-    pub fn from_reader(reader: &mut BufReader<File>) -> Result<EncodedValue, std::io::Error> {
+    /// Reads an encoded_value, whose numeric values take value_arg + 1 bytes
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<EncodedValue, io::Error> {
+        let offset = reader.stream_position()?;
         let byte = read_u8(reader, &mut [0u8])?;
         let value_arg = (byte & 0xe0) >> 5;
         let value_type = byte & 0x1f;
+        let size = value_arg + 1;
+        let max_size = match value_type {
+            VALUE_BYTE | VALUE_NULL => 1,
+            VALUE_SHORT | VALUE_CHAR => 2,
+            VALUE_INT | VALUE_FLOAT | VALUE_METHOD_TYPE..=VALUE_ENUM => 4,
+            VALUE_LONG | VALUE_DOUBLE => 8,
+            VALUE_ARRAY | VALUE_ANNOTATION => 1,
+            VALUE_BOOLEAN => 2,
+            _ => 0,
+        };
+        if size > max_size {
+            return Err(DexError::InvalidEncodedValue { offset, value_type, value_arg }.into());
+        }
         Ok(match value_type {
-            0x00 => EncodedValue::Byte(read_u8(reader, &mut [0u8])?),
-            0x02 => {
-                let mut buf = [0u8; 2];
-                reader.read_exact(&mut buf)?;
-                EncodedValue::Short(i16::from_le_bytes(buf))
-            },
-            0x03 => EncodedValue::Char(read_u16(reader)?),
-            0x04 => {
-                let mut buf = [0u8; 4];
-                reader.read_exact(&mut buf)?;
-                EncodedValue::Int(i32::from_le_bytes(buf))
-            }
-            0x06 => {
-                let mut buf = [0u8; 8];
-                reader.read_exact(&mut buf)?;
-                EncodedValue::Long(i64::from_le_bytes(buf))
-            }
-            0x10 => {
-                let mut buf = [0u8; 4];
-                reader.read_exact(&mut buf)?;
-                EncodedValue::Float(f32::from_le_bytes(buf))
-            }
-            0x11 => {
-                let mut buf = [0u8; 8];
-                reader.read_exact(&mut buf)?;
-                EncodedValue::Double(f64::from_le_bytes(buf))
-            }
-            0x15 => EncodedValue::MethodType(read_u32(reader)?),
-            0x16 => EncodedValue::MethodHandle(read_u32(reader)?),
-            0x17 => EncodedValue::String(read_u32(reader)?),
-            0x18 => EncodedValue::Type(read_u32(reader)?),
-            0x19 => EncodedValue::Field(read_u32(reader)?),
-            0x1a => EncodedValue::Method(read_u32(reader)?),
-            0x1b => EncodedValue::Enum(read_u32(reader)?),
-            0x1c => EncodedValue::Array({
-                let size = leb128::read::unsigned(reader).unwrap();
-                let mut v = Vec::with_capacity(size as usize);
+            VALUE_BYTE => EncodedValue::Byte(read_i8(reader)?),
+            VALUE_SHORT => EncodedValue::Short(sign_extend(read_sized(reader, size)?, size) as i16),
+            VALUE_CHAR => EncodedValue::Char(read_sized(reader, size)? as u16),
+            VALUE_INT => EncodedValue::Int(sign_extend(read_sized(reader, size)?, size) as i32),
+            VALUE_LONG => EncodedValue::Long(sign_extend(read_sized(reader, size)?, size)),
+            VALUE_FLOAT => EncodedValue::Float(f32::from_bits((read_sized(reader, size)? << (8 * (4 - size))) as u32)),
+            VALUE_DOUBLE => EncodedValue::Double(f64::from_bits(read_sized(reader, size)? << (8 * (8 - size)))),
+            VALUE_METHOD_TYPE => EncodedValue::MethodType(read_sized(reader, size)? as u32),
+            VALUE_METHOD_HANDLE => EncodedValue::MethodHandle(read_sized(reader, size)? as u32),
+            VALUE_STRING => EncodedValue::String(read_sized(reader, size)? as u32),
+            VALUE_TYPE => EncodedValue::Type(read_sized(reader, size)? as u32),
+            VALUE_FIELD => EncodedValue::Field(read_sized(reader, size)? as u32),
+            VALUE_METHOD => EncodedValue::Method(read_sized(reader, size)? as u32),
+            VALUE_ENUM => EncodedValue::Enum(read_sized(reader, size)? as u32),
+            VALUE_ARRAY => EncodedValue::Array({
+                let size = read_uleb128(reader)?;
+                let mut v = Vec::with_capacity(checked::capacity(size));
                 for _ in 0..size {
                     v.push(EncodedValue::from_reader(reader)?)
                 }
                 v
             }),
-            0x1d => EncodedValue::Annotation(EncodedAnnotation::from_reader(reader)?),
-            0x1e => EncodedValue::Null,
-            0x1f => EncodedValue::Boolean(value_arg != 0),
-            _ => panic!("Unknown value bits for encoded value")
+            VALUE_ANNOTATION => EncodedValue::Annotation(EncodedAnnotation::from_reader(reader)?),
+            VALUE_NULL => EncodedValue::Null,
+            _ => EncodedValue::Boolean(value_arg != 0),
         })
     }
+
+    /// Appends the encoded_value, numeric values in as few bytes as possible
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let mut header = |value_type: u8, size: u8| out.push(((size - 1) << 5) | value_type);
+        let (value_type, size, value) = match *self {
+            EncodedValue::Byte(value) => (VALUE_BYTE, 1, value as u8 as u64),
+            EncodedValue::Short(value) => (VALUE_SHORT, signed_size(value.into()), value as u64),
+            EncodedValue::Char(value) => (VALUE_CHAR, unsigned_size(value.into()), value as u64),
+            EncodedValue::Int(value) => (VALUE_INT, signed_size(value.into()), value as u64),
+            EncodedValue::Long(value) => (VALUE_LONG, signed_size(value), value as u64),
+            EncodedValue::Float(value) => {
+                let bits = value.to_bits() as u64;
+                let size = right_size(bits, 4);
+                (VALUE_FLOAT, size, bits >> (8 * (4 - size)))
+            }
+            EncodedValue::Double(value) => {
+                let bits = value.to_bits();
+                let size = right_size(bits, 8);
+                (VALUE_DOUBLE, size, bits >> (8 * (8 - size)))
+            }
+            EncodedValue::MethodType(idx) => (VALUE_METHOD_TYPE, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::MethodHandle(idx) => (VALUE_METHOD_HANDLE, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::String(idx) => (VALUE_STRING, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::Type(idx) => (VALUE_TYPE, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::Field(idx) => (VALUE_FIELD, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::Method(idx) => (VALUE_METHOD, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::Enum(idx) => (VALUE_ENUM, unsigned_size(idx.into()), idx.into()),
+            EncodedValue::Array(ref values) => {
+                header(VALUE_ARRAY, 1);
+                write_uleb128(out, values.len() as u32);
+                for value in values {
+                    value.write(out);
+                }
+                return;
+            }
+            EncodedValue::Annotation(ref annotation) => {
+                header(VALUE_ANNOTATION, 1);
+                annotation.write(out);
+                return;
+            }
+            EncodedValue::Null => return header(VALUE_NULL, 1),
+            // value_arg is the value
+            EncodedValue::Boolean(value) => return header(VALUE_BOOLEAN, value as u8 + 1),
+        };
+        header(value_type, size);
+        out.extend_from_slice(&value.to_le_bytes()[..size as usize]);
+    }
 }
 
 
@@ -678,32 +957,34 @@ pub struct DexHeader {
 
 impl DexHeader {
     /// Verify Magic bytes of DexHeader and return parsed version
-    pub fn verify_magic(buf: &[u8; DEX_FILE_MAGIC.len()]) -> u16 {
+    pub fn verify_magic(buf: &[u8; DEX_FILE_MAGIC.len()]) -> Result<u16, io::Error> {
+        DexHeader::parse_version(buf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Not a dex file, magic {:02x?}", buf)))
+    }
+
+    /// Version of the magic bytes `dex\nVVV\0`, None if they are not the magic of a dex file
+    pub fn parse_version(buf: &[u8; DEX_FILE_MAGIC.len()]) -> Option<u16> {
         if !(buf.starts_with(&DEX_FILE_MAGIC[0..5]) && buf.ends_with(&DEX_FILE_MAGIC[7..8])) {
-            panic!("Given file does not contain correct file signature");
+            return None;
         }
-
-        let version = String::from_utf8_lossy(&buf[4..7]);
-        let version: u16 = version.parse().expect("Version number could not be parsed");
-
-        version
+        core::str::from_utf8(&buf[4..7]).ok()?.parse().ok()
     }
 
-    /// Check endian constant, returns true if it corresponds to the REVERSE_ENDIAN_CONSTANT
-    pub fn verify_endian(val: u32) -> scroll::Endian {
+    /// Check endian constant, returns the byte order of the ENDIAN_CONSTANT or REVERSE_ENDIAN_CONSTANT
+    pub fn verify_endian(val: u32) -> Result<scroll::Endian, io::Error> {
         match val {
-            ENDIAN_CONSTANT => scroll::LE,
-            REVERSE_ENDIAN_CONSTANT => scroll::BE,
-            _ => panic!("Bytes do not match valid constants")
+            ENDIAN_CONSTANT => Ok(scroll::LE),
+            REVERSE_ENDIAN_CONSTANT => Ok(scroll::BE),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid endian tag 0x{:08x}", val))),
         }
     }
 
-    pub fn from_reader(reader: &mut BufReader<File>) -> Result<DexHeader, std::io::Error> {
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<DexHeader, io::Error> {
         Ok(DexHeader {
             magic: {
                 let mut magic = [0u8; DEX_FILE_MAGIC.len()];
                 reader.read_exact(&mut magic)?;
-                DexHeader::verify_magic(&magic);
+                DexHeader::verify_magic(&magic)?;
                 magic
             },
             checksum: read_u32(reader)?,
@@ -716,7 +997,7 @@ impl DexHeader {
             header_size: read_u32(reader)?,
             endian_tag: {
                 let tag = read_u32(reader)?;
-                DexHeader::verify_endian(tag);
+                DexHeader::verify_endian(tag)?;
                 tag
             },
             link_size: read_u32(reader)?,
@@ -739,9 +1020,42 @@ impl DexHeader {
         })
     }
 
-    pub fn get_endian(mmap: &Mmap) -> Endian {
+    pub fn get_endian(data: &[u8]) -> Result<Endian, io::Error> {
         const ENDIAN_OFFSET: usize = 0x28;
-        DexHeader::verify_endian(mmap.pread_with(ENDIAN_OFFSET, scroll::LE).unwrap())
+        let tag = data.pread_with(ENDIAN_OFFSET, scroll::LE)
+            .map_err(|_| DexError::OutOfBounds { offset: ENDIAN_OFFSET as u64, len: 4, available: data.len() as u64 })?;
+        DexHeader::verify_endian(tag)
+    }
+
+    /// Name, offset and size in bytes of each field of the header, in file order
+    pub const FIELDS: [(&'static str, usize, usize); 23] = [
+        ("magic", 0, 8), ("checksum", 8, 4), ("signature", 12, 20), ("file_size", 32, 4),
+        ("header_size", 36, 4), ("endian_tag", 40, 4), ("link_size", 44, 4), ("link_off", 48, 4),
+        ("map_off", 52, 4), ("string_ids_size", 56, 4), ("string_ids_off", 60, 4), ("type_ids_size", 64, 4),
+        ("type_ids_off", 68, 4), ("proto_ids_size", 72, 4), ("proto_ids_off", 76, 4), ("field_ids_size", 80, 4),
+        ("field_ids_off", 84, 4), ("method_ids_size", 88, 4), ("method_ids_off", 92, 4), ("class_defs_size", 96, 4),
+        ("class_defs_off", 100, 4), ("data_size", 104, 4), ("data_off", 108, 4),
+    ];
+
+    /// End of the checksum, the data after it is covered by the checksum
+    pub const CHECKSUM_END: usize = 12;
+    /// End of the SHA-1 signature, the data after it is covered by the signature
+    pub const SIGNATURE_END: usize = 32;
+
+    /// Adler-32 checksum of a dex file as stored in its header, computed over everything after the checksum
+    pub fn compute_checksum(data: &[u8]) -> u32 {
+        const MOD_ADLER: u32 = 65521;
+        let (mut a, mut b) = (1u32, 0u32);
+        // Sums of 5552 bytes cannot overflow before the modulo
+        for chunk in data.get(DexHeader::CHECKSUM_END..).unwrap_or_default().chunks(5552) {
+            for byte in chunk {
+                a += *byte as u32;
+                b += a;
+            }
+            a %= MOD_ADLER;
+            b %= MOD_ADLER;
+        }
+        (b << 16) | a
     }
 }
 
@@ -764,24 +1078,22 @@ impl<'a> ctx::TryFromCtx<'a, EndianContext> for DexHeader {
             magic: {
                 const MAGIC_SIZE: usize = 8;
                 let mut magic = [0u8; MAGIC_SIZE];
-                magic.clone_from_slice(&src[*offset..*offset + MAGIC_SIZE]);
-                *offset += MAGIC_SIZE;
-                DexHeader::verify_magic(&magic);
+                src.gread_inout(offset, &mut magic)?;
+                DexHeader::parse_version(&magic).ok_or(scroll::Error::BadInput { size: MAGIC_SIZE, msg: "Not a dex file" })?;
                 magic
             },
             checksum: src.gread_with(offset, ctx.0)?,
             signature: {
                 const SIGNATURE_SIZE: usize = 20;
                 let mut signature = [0u8; SIGNATURE_SIZE];
-                signature.clone_from_slice(&src[*offset..*offset + SIGNATURE_SIZE]);
-                *offset += SIGNATURE_SIZE;
+                src.gread_inout(offset, &mut signature)?;
                 signature
             },
             file_size: src.gread_with(offset, ctx.0)?,
             header_size: src.gread_with(offset, ctx.0)?,
             endian_tag: {
                 let tag = src.gread_with(offset, ctx.0)?;
-                DexHeader::verify_endian(tag);
+                DexHeader::verify_endian(tag).map_err(|_| scroll::Error::BadInput { size: 4, msg: "Invalid endian tag" })?;
                 tag
             },
             link_size: src.gread_with(offset, ctx.0)?,
@@ -809,9 +1121,9 @@ impl<'a> ctx::TryFromCtx<'a, EndianContext> for Vec<MapItem> {
     type Error = scroll::Error;
 
     fn try_from_ctx(src: &'a [u8], ctx: EndianContext) -> Result<(Self, usize), Self::Error> {
-        let mut offset = &mut 0;
+        let offset = &mut 0;
         let size: u32 = src.gread_with(offset, ctx.0)?;
-        let mut v = Vec::with_capacity(size as usize);
+        let mut v = Vec::with_capacity(checked::capacity(size));
         for _ in 0..size {
             v.push(MapItem {
                 item_type: src.gread_with(offset, ctx.0)?,
@@ -834,22 +1146,7 @@ impl<'a> TryFromCtx<'a, TableContext<'_, '_>> for StringIds {
     fn try_from_ctx(src: &'a [u8], ctx: TableContext) -> Result<(Self, usize), Self::Error> {
         let size = ctx.header.string_ids_size as usize;
         let offset = &mut (ctx.header.string_ids_off.to_owned() as usize);
-        let mut v = Vec::with_capacity(size as usize);
-
-        for _ in 0..size {
-            v.push(src.gread_with(offset, ctx.endian)?)
-        }
-        Ok((v, 4 * size))
-    }
-}
-
-impl<'a> TryFromCtx<'a, TableContext<'_, '_>> for Vec<u32> {
-    type Error = scroll::Error;
-
-    fn try_from_ctx(src: &'a [u8], ctx: TableContext) -> Result<(Self, usize), Self::Error> {
-        let size = ctx.header.type_ids_size as usize;
-        let offset = &mut (ctx.header.type_ids_off.to_owned() as usize);
-        let mut v = Vec::with_capacity(size as usize);
+        let mut v = Vec::with_capacity(checked::capacity(ctx.header.string_ids_size));
 
         for _ in 0..size {
             v.push(src.gread_with(offset, ctx.endian)?)
@@ -872,11 +1169,6 @@ impl<'a> TryFromCtx<'a, TableContext<'_, '_>> for ProtoIdItem {
 }
 
 
-struct StringData {
-    utf16_size: u64,
-    data: Vec<u8>,
-}
-
 #[derive(Debug)]
 pub struct ProtoIdItem {
     pub shorty_idx: u32,
@@ -937,6 +1229,9 @@ pub struct EncodedMethod {
     pub code_off: u64,
 }
 
+/// Size of the fields of a code_item before the instructions
+pub const CODE_ITEM_HEADER_SIZE: u32 = 16;
+
 #[derive(Debug)]
 pub struct CodeItem {
     pub registers_size: u16,
@@ -955,8 +1250,18 @@ pub struct TryItem {
     pub handler_off: u16,
 }
 
+impl TryItem {
+    /// The handler referenced by handler_off, None if no handler of the code item starts at that offset
+    pub fn resolve_handler<'a>(&self, code: &'a CodeItem) -> Option<&'a EncodedCatchHandler> {
+        // Handlers are parsed in the order of the list, so they are sorted by their offset
+        code.handlers.binary_search_by_key(&self.handler_off, |it| it.offset).ok().map(|idx| &code.handlers[idx])
+    }
+}
+
 #[derive(Debug)]
 pub struct EncodedCatchHandler {
+    /// Offset in bytes from the start of the encoded_catch_handler_list (referenced by TryItem::handler_off)
+    pub offset: u16,
     pub handlers: Vec<EncodedTypeAddrPair>,
     pub catch_all_addr: Option<u64>,
 }
@@ -967,11 +1272,43 @@ pub struct EncodedTypeAddrPair {
     pub addr: u64,
 }
 
-#[derive(Debug)]
+/// Index encoded as uleb128p1 (the index plus one), so NO_INDEX is encoded in a single byte as 0
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct OptionalIdx(Option<u32>);
+
+impl OptionalIdx {
+    pub const NONE: OptionalIdx = OptionalIdx(None);
+
+    pub fn new(idx: u32) -> OptionalIdx {
+        OptionalIdx(Some(idx))
+    }
+
+    pub fn get(self) -> Option<u32> {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct DebugInfoItem {
     pub line_start: u64,
-    pub parameter_names: Vec<i64>,
-    pub state_machine_bytes: Vec<u8>,
+    pub parameter_names: Vec<OptionalIdx>,
+    pub bytecode: Vec<DebugInstruction>,
+}
+
+/// Instructions of the debug info state machine (excluding DBG_END_SEQUENCE)
+#[derive(Debug, Clone)]
+pub enum DebugInstruction {
+    AdvancePc(u64),
+    AdvanceLine(i64),
+    StartLocal { register_num: u64, name_idx: OptionalIdx, type_idx: OptionalIdx },
+    StartLocalExtended { register_num: u64, name_idx: OptionalIdx, type_idx: OptionalIdx, sig_idx: OptionalIdx },
+    EndLocal(u64),
+    RestartLocal(u64),
+    SetPrologueEnd,
+    SetEpilogueBegin,
+    SetFile(OptionalIdx),
+    /// Special opcodes (0x0a..=0xff) advancing both line and address and emitting a position entry
+    Special(u8),
 }
 
 #[derive(Debug)]
@@ -1000,26 +1337,26 @@ pub struct ParameterAnnotation {
     pub annotations_off: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AnnotationItem {
     pub visibility: Visibility,
     pub annotation: EncodedAnnotation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Visibility {
     VisibilityBuild,
     VisibilityRuntime,
     VisibilitySystem,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EncodedAnnotation {
     pub type_idx: u64,
     pub elements: Vec<AnnotationElement>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AnnotationElement {
     pub name_idx: u64,
     pub value: EncodedValue,
@@ -1042,11 +1379,40 @@ pub struct MapItem {
 }
 
 impl MapItem {
-    pub fn parse_map_list(dex_header: &DexHeader, reader: &mut BufReader<File>) -> Result<Vec<MapItem>, std::io::Error> {
+    /// Name of the item type as in the dex format documentation, e.g. `string_id_item`
+    pub fn type_name(&self) -> Option<&'static str> {
+        Some(match self.item_type {
+            0x0000 => "header_item",
+            0x0001 => "string_id_item",
+            0x0002 => "type_id_item",
+            0x0003 => "proto_id_item",
+            0x0004 => "field_id_item",
+            0x0005 => "method_id_item",
+            0x0006 => "class_def_item",
+            0x0007 => "call_site_id_item",
+            0x0008 => "method_handle_item",
+            0x1000 => "map_list",
+            0x1001 => "type_list",
+            0x1002 => "annotation_set_ref_list",
+            0x1003 => "annotation_set_item",
+            0x2000 => "class_data_item",
+            0x2001 => "code_item",
+            0x2002 => "string_data_item",
+            0x2003 => "debug_info_item",
+            0x2004 => "annotation_item",
+            0x2005 => "encoded_array_item",
+            0x2006 => "annotations_directory_item",
+            0xf000 => "hiddenapi_class_data_item",
+            _ => return None,
+        })
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(offset = dex_header.map_off))]
+    pub fn parse_map_list<R: Read + Seek + ?Sized>(dex_header: &DexHeader, reader: &mut R) -> Result<Vec<MapItem>, io::Error> {
         reader.seek(Start(dex_header.map_off.into()))?;
 
         let size = read_u32(reader)?;
-        let mut v = Vec::with_capacity(size as usize);
+        let mut v = Vec::with_capacity(checked::capacity(size));
         for _ in 0..size {
             let item_type = read_u16(reader)?;
             read_u16(reader)?; // unused
@@ -1058,7 +1424,7 @@ impl MapItem {
     }
 }
 
-pub fn find_type_in_map(map_list: &Vec<MapItem>, item_type: u16) -> Option<&MapItem> {
+pub fn find_type_in_map(map_list: &[MapItem], item_type: u16) -> Option<&MapItem> {
     let mut item = None;
     for it in map_list {
         if it.item_type == item_type {
@@ -1067,4 +1433,108 @@ pub fn find_type_in_map(map_list: &Vec<MapItem>, item_type: u16) -> Option<&MapI
         }
     }
     item
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Cursor;
+
+    #[test]
+    fn malformed_sections() {
+        // No code items, then one type list whose data ends early
+        let map_list = [MapItem { item_type: 0x1001, size: 2, offset: 0 }];
+        let data = [1, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, 1, 0];
+        assert!(parse_code_items(&map_list, &mut Cursor::new(&data[..])).unwrap().is_empty());
+        let err = parse_type_lists(&map_list, &mut Cursor::new(&data[..])).unwrap_err();
+        assert!(err.to_string().starts_with("type_list #1 at offset 0x8"), "{}", err);
+
+        assert!(DexHeader::verify_magic(b"dey\n035\0").is_err());
+        assert_eq!(DexHeader::verify_magic(b"dex\n039\0").unwrap(), 39);
+        assert!(DexHeader::verify_endian(0x1234).is_err());
+        assert!(DexHeader::get_endian(&[0; 0x2a]).is_err());
+        assert!(matches!(DexHeader::get_endian(&[0x78, 0x56, 0x34, 0x12].repeat(11)), Ok(scroll::LE)));
+        assert!(b"dex\n035\0".pread_with::<DexHeader>(0, EndianContext(scroll::LE)).is_err());
+    }
+
+    #[test]
+    fn string_data_items() {
+        // "hi", an invalid byte and a string without terminator
+        let data = [2, b'h', b'i', 0, 1, 0xff, 0, 1, b'a'];
+        let items = parse_string_data_items(&[0, 4], &mut Cursor::new(&data[..])).unwrap();
+        let items: Vec<(&str, u32, &[m_utf8::MUtf8ParseError])> = items.iter().map(|it| (it.value.as_str(), it.size, it.errors.as_slice())).collect();
+        assert_eq!(items, [("hi", 4, &[][..]), ("\u{fffd}", 3, &[m_utf8::MUtf8ParseError::BadByte(0)][..])]);
+        assert!(parse_string_data_items(&[7], &mut Cursor::new(&data[..])).is_err());
+    }
+
+    fn leb128<'a, T>(bytes: &'a [u8], read: impl FnOnce(&mut Cursor<&'a [u8]>) -> Result<T, DexError>) -> Result<T, DexError> {
+        read(&mut Cursor::new(bytes))
+    }
+
+    #[test]
+    fn uleb128() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 0x0fffffff, 0x10000000, u32::MAX] {
+            let mut out = Vec::new();
+            write_uleb128(&mut out, value);
+            assert_eq!(out.len(), (32 - value.leading_zeros()).max(1).div_ceil(7) as usize);
+            assert_eq!(leb128(&out, read_uleb128).unwrap(), value);
+        }
+        // Bits past the 32nd in the 5th byte are ignored, a 6th byte is invalid
+        assert_eq!(leb128(&[0xff, 0xff, 0xff, 0xff, 0x7f], read_uleb128).unwrap(), u32::MAX);
+        assert!(matches!(leb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00], read_uleb128), Err(DexError::Leb128TooLong { offset: 0 })));
+        assert!(matches!(leb128(&[0x01, 0x80], |it| { read_uleb128(it)?; read_uleb128(it) }), Err(DexError::TruncatedLeb128 { offset: 1 })));
+        assert!(matches!(leb128(&[], read_uleb128), Err(DexError::TruncatedLeb128 { offset: 0 })));
+    }
+
+    #[test]
+    fn sleb128() {
+        let cases: [(&[u8], i32); 7] = [(&[0x00], 0), (&[0x01], 1), (&[0x7f], -1), (&[0x40], -64), (&[0xbf, 0x7f], -65),
+                                        (&[0x80, 0x80, 0x80, 0x80, 0x78], i32::MIN), (&[0xff, 0xff, 0xff, 0xff, 0x07], i32::MAX)];
+        for (bytes, value) in cases {
+            assert_eq!(leb128(bytes, read_sleb128).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn uleb128p1() {
+        assert_eq!(leb128(&[0x00], read_uleb128p1).unwrap(), OptionalIdx::NONE);
+        assert_eq!(leb128(&[0x01], read_uleb128p1).unwrap(), OptionalIdx::new(0));
+        assert_eq!(leb128(&[0xff, 0xff, 0xff, 0xff, 0x0f], read_uleb128p1).unwrap(), OptionalIdx::new(u32::MAX - 1));
+    }
+
+    #[test]
+    fn encoded_value_sizes() {
+        use EncodedValue::*;
+        let annotation = EncodedAnnotation { type_idx: 1, elements: vec![AnnotationElement { name_idx: 2, value: Int(3) }] };
+        let cases = [
+            (Byte(-1), 2), (Short(-128), 2), (Short(-129), 3), (Char(0xff), 2), (Char(0x100), 3),
+            (Int(0), 2), (Int(127), 2), (Int(128), 3), (Int(-0x800000), 4), (Int(i32::MIN), 5),
+            (Long(-1), 2), (Long(i64::MAX), 9), (Long(0x80000000), 6),
+            // Floating point values drop their zero low-order bytes
+            (Float(0.0), 2), (Float(1.0), 3), (Float(f32::from_bits(0x3f800001)), 5), (Double(-2.0), 2), (Double(0.1), 9),
+            (MethodType(0xff), 2), (MethodHandle(0x100), 3), (String(0), 2), (Type(0x10000), 4), (Field(u32::MAX), 5),
+            (Method(1), 2), (Enum(0x1234), 3),
+            (Array(vec![Null, Boolean(true), Boolean(false)]), 5), (Annotation(annotation), 6), (Null, 1), (Boolean(true), 1),
+        ];
+        for (value, size) in cases {
+            let mut out = Vec::new();
+            value.write(&mut out);
+            assert_eq!(out.len(), size, "{:?}", value);
+            let mut reader = Cursor::new(&out[..]);
+            assert_eq!(EncodedValue::from_reader(&mut reader).unwrap(), value);
+            assert_eq!(reader.position(), size as u64);
+        }
+    }
+
+    #[test]
+    fn invalid_encoded_values() {
+        // An int of 5 bytes, a null of 2 bytes and an unknown type
+        for bytes in [&[0x84, 0, 0, 0, 0, 0][..], &[0x3e], &[0x05, 0]] {
+            let err = EncodedValue::from_reader(&mut Cursor::new(bytes)).unwrap_err();
+            assert!(err.to_string().starts_with("Invalid encoded value at offset 0x0"), "{}", err);
+        }
+        // A long of 8 bytes with 2
+        assert!(EncodedValue::from_reader(&mut Cursor::new(&[0xe6, 0, 0][..])).is_err());
+        assert_eq!(EncodedValue::from_reader(&mut Cursor::new(&[0x24, 0xff, 0x7f][..])).unwrap(), EncodedValue::Int(0x7fff));
+    }
+}
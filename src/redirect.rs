@@ -0,0 +1,147 @@
+use crate::editor::{DexEditor, EditError, Remap};
+use crate::instructions::Instructions;
+use crate::prelude::*;
+
+/*
+Static interception of calls (dex-level hooking): the invokes of a method are rewritten to call a
+static trampoline method of the dex file instead, which can run code before and after calling the
+original, without a hooking framework at runtime. The trampoline takes the receiver of an instance
+method as first parameter, e.g. `static String get(Foo foo, int i)` for `String Foo.get(int i)`, and
+its own invokes of the original are kept. invoke-super is not redirected, the trampoline could not
+make the super call. Constructors are rejected, a trampoline cannot initialize the instance.
+ */
+
+const ACC_STATIC: u32 = 0x8;
+
+const INVOKE_STATIC: u8 = 0x71;
+const INVOKE_STATIC_RANGE: u8 = 0x77;
+
+/// An invoke rewritten to call the trampoline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// Method containing the invoke, e.g. `Lcom/example/Foo;->run()V`
+    pub caller: String,
+    pub pc: usize,
+    /// Name of the original instruction, e.g. invoke-virtual
+    pub invoke: &'static str,
+}
+
+/// Splits a method `Lcom/example/Foo;->run(I)V` into the class, name and signature
+fn parse_method(method: &str) -> Result<(&str, &str, &str), EditError> {
+    method.split_once("->")
+        .and_then(|(class, rest)| rest.find('(').map(|it| (class, &rest[..it], &rest[it..])))
+        .ok_or_else(|| EditError::InvalidTarget(method.to_owned()))
+}
+
+/// Adds the trampoline (e.g. `Lcom/example/Hooks;->get(Lcom/example/Foo;I)Ljava/lang/String;`) defined
+/// by `source` to the dex file: its class if the dex file does not define it, else only the method.
+/// Returns the moves of the existing ids.
+pub fn insert_trampoline(editor: &mut DexEditor, source: &DexEditor, trampoline: &str) -> Result<Remap, EditError> {
+    let (class, name, signature) = parse_method(trampoline)?;
+    if editor.class_position(class).is_none() {
+        editor.insert_class(source, class)
+    } else {
+        editor.copy_method(source, class, name, signature)
+    }
+}
+
+/// Rewrites the invokes of `target` (e.g. `Lcom/example/Foo;->get(I)Ljava/lang/String;`) to invoke the
+/// static method `trampoline` defined by the dex file, returns the rewritten invokes
+pub fn redirect_invokes(editor: &mut DexEditor, target: &str, trampoline: &str) -> Result<Vec<Redirection>, EditError> {
+    let (class, name, signature) = parse_method(trampoline)?;
+    let trampoline_idx = editor.method_idx(class, name, signature)
+        .filter(|idx| editor.class_position(class).is_some_and(|it| editor.classes[it].methods().any(|method| method.method_idx == *idx)))
+        .ok_or_else(|| EditError::MethodNotFound(trampoline.to_owned()))?;
+    let incompatible = |reason: &str| EditError::IncompatibleTrampoline(format!("{} {}", trampoline, reason));
+    let is_static = editor.classes.iter().flat_map(|it| it.methods()).any(|it| it.method_idx == trampoline_idx && it.access_flags & ACC_STATIC != 0);
+    if !is_static {
+        return Err(incompatible("is not static"));
+    }
+    let (class, name, signature) = parse_method(target)?;
+    // Only invoke-direct can pass the uninitialized instance to a constructor
+    if name == "<init>" || name == "<clinit>" {
+        return Err(incompatible(&format!("cannot replace the constructor {}", target)));
+    }
+    let target_idx = match editor.method_idx(class, name, signature) {
+        Some(idx) => idx,
+        // Nothing invokes it
+        None => return Ok(Vec::new()),
+    };
+
+    // The parameters of the trampoline for invokes of a static method, and with the receiver first for
+    // the others
+    let target_proto = &editor.protos[editor.methods[target_idx as usize].proto_idx as usize];
+    let trampoline_proto = &editor.protos[editor.methods[trampoline_idx as usize].proto_idx as usize];
+    if trampoline_proto.return_type_idx != target_proto.return_type_idx {
+        return Err(incompatible("does not return the type of the target"));
+    }
+    let fits_static = trampoline_proto.parameters == target_proto.parameters;
+    let fits_instance = match trampoline_proto.parameters.split_first() {
+        Some((receiver, parameters)) => parameters == target_proto.parameters.as_slice()
+            && editor.type_descriptor(*receiver).starts_with(['L', '[']),
+        None => false,
+    };
+
+    let mut redirected = Vec::new();
+    for class in &mut editor.classes {
+        for method in class.direct_methods.iter_mut().chain(&mut class.virtual_methods) {
+            let code = match &mut method.code {
+                Some(code) if method.method_idx != trampoline_idx => code,
+                _ => continue,
+            };
+            let method_idx = method.method_idx;
+            let decoded = Instructions::new(&code.insns).collect::<Result<Vec<_>, _>>()
+                .map_err(|error| EditError::Decode { method_idx, error })?;
+            for (pc, mut insn) in decoded {
+                let (is_static, range) = match insn.name() {
+                    "invoke-virtual" | "invoke-direct" | "invoke-interface" => (false, false),
+                    "invoke-virtual/range" | "invoke-direct/range" | "invoke-interface/range" => (false, true),
+                    "invoke-static" => (true, false),
+                    "invoke-static/range" => (true, true),
+                    _ => continue,
+                };
+                if insn.b != target_idx {
+                    continue;
+                }
+                if !(if is_static { fits_static } else { fits_instance }) {
+                    return Err(incompatible("does not take the parameters of the target"));
+                }
+                let invoke = insn.name();
+                insn.opcode = if range { INVOKE_STATIC_RANGE } else { INVOKE_STATIC };
+                insn.b = trampoline_idx;
+                let units = insn.encode().map_err(|error| EditError::Encode { method_idx, error })?;
+                code.insns[pc..pc + units.len()].copy_from_slice(&units);
+                redirected.push((method_idx, pc, invoke));
+            }
+        }
+    }
+    Ok(redirected.into_iter()
+        .map(|(method_idx, pc, invoke)| Redirection { caller: editor.qualified_method_name(method_idx), pc, invoke })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::fixture::{self, Fixture, FixtureMethod};
+    use crate::instructions::Instruction;
+
+    #[test]
+    fn redirected_invoke() {
+        let mut call = CodeBuilder::new();
+        call.emit("invoke-static", &[Regs(Vec::new()), Idx(2)]).unwrap().emit("return-void", &[]).unwrap();
+        let mut editor = Fixture::empty()
+            .method(FixtureMethod::new("caller", "V", &[], call.build().unwrap()).access_flags(ACC_STATIC))
+            .method(FixtureMethod::new("hook", "V", &[], vec![0x000e]).access_flags(ACC_STATIC))
+            .method(FixtureMethod::new("target", "V", &[], vec![0x000e]).access_flags(ACC_STATIC))
+            .editor();
+        assert!(matches!(redirect_invokes(&mut editor, "Lcom/example/Fixture;->target()V", "Lcom/example/Fixture;->run()V"), Err(EditError::MethodNotFound(_))));
+        assert!(matches!(redirect_invokes(&mut editor, "Lcom/example/Fixture;-><init>()V", "Lcom/example/Fixture;->hook()V"), Err(EditError::IncompatibleTrampoline(_))));
+        assert!(matches!(redirect_invokes(&mut editor, "Lcom/example/Fixture;-><clinit>()V", "Lcom/example/Fixture;->hook()V"), Err(EditError::IncompatibleTrampoline(_))));
+        let redirections = redirect_invokes(&mut editor, "Lcom/example/Fixture;->target()V", "Lcom/example/Fixture;->hook()V").unwrap();
+        assert_eq!(redirections, [Redirection { caller: "Lcom/example/Fixture;->caller()V".to_owned(), pc: 0, invoke: "invoke-static" }]);
+        let insns = fixture::insns(&fixture::rewritten(&editor), "caller");
+        assert_eq!(Instruction::decode(&insns, 0).unwrap().b, 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use serde_json::{json, Map, Value};
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::{app, atomic, listing};
+
+use crate::stats;
+
+/*
+Batch mode for corpus studies. The files matching a glob pattern are processed in parallel, each dex
+file (or each dex file of an APK, bundle or directory of split APKs) is summarized with the statistics of the stats command. One report
+is written per input file, plus summary.json with the totals over all of them.
+ */
+
+/// Values of the stats listing, or the failure to parse the dex file
+type DexReport = Result<Map<String, Value>, String>;
+
+/// Report of one input file
+struct FileReport {
+    path: PathBuf,
+    /// Entry name (the file name for plain dex files) and statistics or failure of each dex file
+    dex: Vec<(String, DexReport)>,
+    /// Failure to read the file itself
+    error: Option<String>,
+}
+
+impl FileReport {
+    fn to_json(&self) -> Value {
+        let dex: Vec<_> = self.dex.iter().map(|(name, stats)| match stats {
+            Ok(stats) => json!({ "name": name, "stats": stats }),
+            Err(err) => json!({ "name": name, "error": err }),
+        }).collect();
+        json!({ "path": self.path.to_string_lossy(), "dex": dex, "error": self.error })
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.dex.iter().any(|(_, stats)| stats.is_err())
+    }
+}
+
+/// Values of the stats listing as numbers
+fn stats(dex: &DexFile) -> Map<String, Value> {
+    stats::values(listing::stats(dex).rows)
+}
+
+/// Parses a dex file, malformed input the parser does not handle gracefully must not end the scan
+fn parse(data: &[u8]) -> DexReport {
+    match panic::catch_unwind(|| DexFile::from_bytes(data)) {
+        Ok(Ok(dex)) => Ok(stats(&dex)),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("Parser panicked".to_string()),
+    }
+}
+
+fn scan_file(path: &Path) -> FileReport {
+    let mut report = FileReport { path: path.to_path_buf(), dex: Vec::new(), error: None };
+    match app::dex_files(path) {
+        Ok(dex_files) => report.dex = dex_files.iter().map(|it| (it.full_name(), parse(&it.data))).collect(),
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    report
+}
+
+/// File name of the report of `path`, unique for distinct relative paths
+fn report_name(path: &Path) -> String {
+    let name: String = path.to_string_lossy().chars()
+        .map(|c| if c == '/' || c == '\\' || c == ':' { '_' } else { c })
+        .collect();
+    format!("{}.json", name.trim_start_matches('_'))
+}
+
+/// Totals over all reports, versions are counted instead of summed
+fn summary(reports: &[FileReport]) -> Value {
+    let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+    let mut versions: BTreeMap<String, u64> = BTreeMap::new();
+    let mut dex_files = 0;
+    for (_, stats) in reports.iter().flat_map(|it| &it.dex) {
+        let Ok(stats) = stats else { continue };
+        dex_files += 1;
+        for (key, value) in stats {
+            if key == "version" {
+                *versions.entry(value.to_string()).or_default() += 1;
+            } else if let Some(value) = value.as_u64() {
+                *totals.entry(key.clone()).or_default() += value;
+            }
+        }
+    }
+    let failed: Vec<_> = reports.iter().filter(|it| it.failed()).map(|it| it.path.to_string_lossy()).collect();
+    json!({
+        "files": reports.len(),
+        "dex_files": dex_files,
+        "failed": failed,
+        "versions": versions,
+        "totals": totals,
+    })
+}
+
+/// Scans the files matching `pattern` and writes the reports into `report_dir`. Returns the summary.
+pub fn scan(pattern: &str, report_dir: &Path) -> io::Result<Value> {
+    let paths = glob::glob(pattern).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+        .filter_map(|entry| match entry {
+            Ok(path) if path.is_file() || app::is_split_dir(&path) => Some(path),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!(%err, "Could not read path");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    fs::create_dir_all(report_dir)?;
+    let reports = paths.par_iter()
+        .map(|path| {
+            let report = scan_file(path);
+            atomic::write(&report_dir.join(report_name(path)), &serde_json::to_vec_pretty(&report.to_json())?)?;
+            Ok(report)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let summary = summary(&reports);
+    atomic::write(&report_dir.join("summary.json"), &serde_json::to_vec_pretty(&summary)?)?;
+    Ok(summary)
+}
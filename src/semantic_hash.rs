@@ -0,0 +1,208 @@
+use alloc::collections::BTreeMap;
+
+use crate::class::Method;
+use crate::dex_file::ACC_STATIC;
+use crate::instructions::{Format, Instruction, Instructions, Payload};
+use crate::prelude::*;
+use crate::raw_dex::CodeItem;
+
+/*
+Hash of the code of a method that is equal for methods compiled from the same source by another build,
+for matching methods across versions of an app. Everything that the compiler chooses independently of
+the semantics is normalized:
+* registers are numbered by their first use, parameters by their position
+* the forms of an instruction selected by the sizes of its operands (move/16, const/4, goto/32,
+  const-string/jumbo, add-int/lit8, the /range invokes, the /2addr operations) are replaced by the
+  general form
+* string, type, field, method and proto indices are dropped, only the kind of the reference is kept
+* branch targets, try ranges and handlers are instruction ordinals instead of code unit offsets, nops
+  and payloads (hashed with their switch or fill-array-data instruction) are skipped
+Literals are kept, except for resource ids (0x7fXXXXXX, renumbered whenever a resource is added), which
+are replaced by a marker. Renamed or reordered references thus do not change the hash, but neither do
+calls of another method of the same shape.
+ */
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Markers hashed instead of an index, a resource id, a missing branch target and before an undecodable rest
+const REFERENCE: u64 = u64::MAX - 1;
+const RESOURCE_ID: u64 = u64::MAX - 2;
+const NO_TARGET: u64 = u64::MAX - 3;
+const INVALID: u64 = u64::MAX - 4;
+/// Flag of parameter registers, distinguishing them from locals
+const PARAMETER: u64 = 1 << 32;
+
+/// FNV-1a, stable across platforms and releases unlike the hasher of std
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// General form of an opcode whose forms only differ in the sizes of the operands
+fn normalized_opcode(opcode: u8) -> u8 {
+    match opcode {
+        0x02 | 0x03 => 0x01,
+        0x05 | 0x06 => 0x04,
+        0x08 | 0x09 => 0x07,
+        0x12 | 0x13 | 0x15 => 0x14,
+        0x16 | 0x17 | 0x19 => 0x18,
+        0x1b => 0x1a,
+        0x25 => 0x24,
+        0x29 | 0x2a => 0x28,
+        0x74..=0x78 => opcode - 0x06,
+        0xb0..=0xcf => opcode - 0x20,
+        0xd8..=0xdf => opcode - 0x08,
+        0xfb | 0xfd => opcode - 0x01,
+        _ => opcode,
+    }
+}
+
+/// Value of a literal operand, sign extended
+fn literal(insn: &Instruction) -> Option<i64> {
+    match (insn.opcode, insn.format()) {
+        (0x15, _) => Some((insn.b << 16) as i32 as i64),
+        (0x19, _) => Some(((insn.b as u64) << 48) as i64),
+        (_, Format::F11n) | (_, Format::F21s) | (_, Format::F31i) => Some(insn.b as i32 as i64),
+        (_, Format::F22b) | (_, Format::F22s) => Some(insn.c as i32 as i64),
+        (_, Format::F51l) => Some(insn.wide_b as i64),
+        _ => None,
+    }
+}
+
+/// Branch offset relative to the instruction, for switches and fill-array-data the offset of the payload
+fn branch_offset(insn: &Instruction) -> Option<i32> {
+    match insn.format() {
+        Format::F10t | Format::F20t | Format::F30t => Some(insn.a as i32),
+        Format::F21t | Format::F31t => Some(insn.b as i32),
+        Format::F22t => Some(insn.c as i32),
+        _ => None,
+    }
+}
+
+fn hash_literal(hasher: &mut Fnv, value: i64) {
+    if value >> 24 == 0x7f {
+        hasher.write(RESOURCE_ID);
+    } else {
+        hasher.write(value as u64);
+    }
+}
+
+/// Semantic hash of a method body, see the module documentation. `is_static` tells whether the first
+/// parameter register holds `this`.
+pub fn semantic_hash(code: &CodeItem, is_static: bool) -> u64 {
+    let mut decoded = Vec::new();
+    let mut invalid_from = None;
+    for result in Instructions::new(&code.insns) {
+        match result {
+            Ok(it) => decoded.push(it),
+            Err(_) => {
+                invalid_from = Some(decoded.last().map_or(0, |(pc, insn): &(usize, Instruction)| pc + insn.size));
+                break;
+            }
+        }
+    }
+    let kept: Vec<&(usize, Instruction)> = decoded.iter()
+        .filter(|(_, insn)| insn.payload.is_none() && insn.opcode != 0x00)
+        .collect();
+    // Branches to skipped nops fall through to the next kept instruction
+    let ordinal = |pc: i64| -> u64 {
+        let idx = kept.partition_point(|(it, _)| (*it as i64) < pc);
+        if pc < 0 || idx == kept.len() { NO_TARGET } else { idx as u64 }
+    };
+
+    let mut hasher = Fnv(FNV_OFFSET);
+    hasher.write(code.ins_size as u64);
+    hasher.write(is_static as u64);
+    let first_parameter = code.registers_size.saturating_sub(code.ins_size) as u32;
+    let mut locals: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut register = |hasher: &mut Fnv, register: u32| {
+        let normalized = if register >= first_parameter {
+            PARAMETER | (register - first_parameter) as u64
+        } else {
+            let next = locals.len() as u64;
+            *locals.entry(register).or_insert(next)
+        };
+        hasher.write(normalized);
+    };
+
+    for (pc, insn) in &kept {
+        let opcode = normalized_opcode(insn.opcode);
+        hasher.write(opcode as u64);
+        let registers = insn.registers();
+        if (0xb0..=0xcf).contains(&insn.opcode) {
+            // vA is read and written, hashed like the three register form with vA as first source
+            register(&mut hasher, insn.a);
+        }
+        for (reg, _) in registers {
+            register(&mut hasher, reg);
+        }
+        if insn.index().is_some() {
+            hasher.write(REFERENCE);
+        }
+        if let Some(value) = literal(insn) {
+            hash_literal(&mut hasher, value);
+        }
+        let target = match branch_offset(insn) {
+            Some(offset) => *pc as i64 + offset as i64,
+            None => continue,
+        };
+        if insn.format() != Format::F31t {
+            hasher.write(ordinal(target));
+            continue;
+        }
+        match Payload::decode(&code.insns, target as usize) {
+            Ok(Payload::FillArrayData { element_width, data }) => {
+                hasher.write(element_width as u64);
+                hasher.write(data.len() as u64);
+                data.iter().for_each(|it| hasher.write(*it as u64));
+            }
+            Ok(payload) => {
+                for (key, offset) in payload.switch_cases().unwrap_or_default() {
+                    hasher.write(key as u64);
+                    hasher.write(ordinal(*pc as i64 + offset as i64));
+                }
+            }
+            Err(_) => hasher.write(NO_TARGET),
+        }
+    }
+
+    for try_item in &code.tries {
+        let start = try_item.start_addr as i64;
+        hasher.write(ordinal(start));
+        hasher.write(ordinal(start + try_item.insn_count as i64));
+        let handler = match try_item.resolve_handler(code) {
+            Some(handler) => handler,
+            None => {
+                hasher.write(NO_TARGET);
+                continue;
+            }
+        };
+        for pair in &handler.handlers {
+            hasher.write(REFERENCE);
+            hasher.write(ordinal(pair.addr as i64));
+        }
+        if let Some(addr) = handler.catch_all_addr {
+            hasher.write(ordinal(addr as i64));
+        }
+    }
+
+    if let Some(pc) = invalid_from {
+        hasher.write(INVALID);
+        code.insns[pc..].iter().for_each(|it| hasher.write(*it as u64));
+    }
+    hasher.0
+}
+
+impl Method<'_> {
+    /// Semantic hash of the code of the method, None for abstract and native methods
+    pub fn semantic_hash(&self) -> Option<u64> {
+        let code = self.class.dex().code_item(self.encoded.code_off)?;
+        Some(semantic_hash(code, self.encoded.access_flags & ACC_STATIC != 0))
+    }
+}
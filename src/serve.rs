@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde_json::{json, Value};
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::export::{self, Reference};
+use dex_tool::{input, listing, smali};
+
+/*
+JSON-RPC 2.0 server on a Unix socket, one request or response object per line. Opened files stay parsed
+and indexed in memory until closed, so interactive queries do not parse the dex file again. Only the
+owner can connect to the socket, and only the files in the root directory of the server can be opened.
+
+Methods:
+* open {path}                      parses a dex file in the root directory (relative paths are resolved
+                                   against it), it is addressed by `path` in the other methods
+* close {file}
+* files                            paths of the open files
+* xrefs {file, kind, target}       instructions referencing an index, target is the index or the name
+                                   (string value, type descriptor, Lcls;->name(sig) or Lcls;->name:type)
+* search {file, query, kind?, limit?}  substring search in strings, classes or methods
+* disasm {file, class}             smali of the class with the given descriptor
+ */
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Server defined error for failures while processing a valid request
+const REQUEST_FAILED: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> RpcError {
+        RpcError { code: INVALID_PARAMS, message: message.into() }
+    }
+
+    fn failed(message: impl Into<String>) -> RpcError {
+        RpcError { code: REQUEST_FAILED, message: message.into() }
+    }
+}
+
+/// A parsed dex file with its references indexed by target
+struct Indexed {
+    dex: DexFile,
+    references: Vec<Reference>,
+    /// Indices into `references` by kind and target
+    by_target: HashMap<&'static str, HashMap<u32, Vec<usize>>>,
+}
+
+impl Indexed {
+    fn new(dex: DexFile) -> Indexed {
+        let references = export::references(&dex);
+        let mut by_target: HashMap<_, HashMap<_, Vec<usize>>> = HashMap::new();
+        for (idx, reference) in references.iter().enumerate() {
+            by_target.entry(reference.kind).or_default().entry(reference.target).or_default().push(idx);
+        }
+        Indexed { dex, references, by_target }
+    }
+
+    fn method_name(&self, method_idx: u32) -> String {
+        let dex = &self.dex;
+        format!("{}->{}{}", dex.method_class(method_idx), dex.method_name(method_idx), dex.method_signature(method_idx))
+    }
+
+    fn field_name(&self, field_idx: u32) -> String {
+        let dex = &self.dex;
+        format!("{}->{}:{}", dex.field_class(field_idx), dex.field_name(field_idx), dex.field_type(field_idx))
+    }
+
+    /// Index of the item of the given kind with the given name
+    fn resolve(&self, kind: &str, name: &str) -> Option<u32> {
+        let dex = &self.dex;
+        let position = match kind {
+            "string" => dex.string_idx(name).map(|it| it as usize),
+            "type" => (0..dex.type_ids.len()).position(|it| dex.type_descriptor(it as u32) == name),
+            "field" => (0..dex.field_ids.len()).position(|it| self.field_name(it as u32) == name),
+            "method" => (0..dex.method_ids.len()).position(|it| self.method_name(it as u32) == name),
+            "proto" => (0..dex.proto_ids.len()).position(|it| dex.proto_signature(it as u32) == name),
+            _ => None,
+        };
+        position.map(|it| it as u32)
+    }
+}
+
+/// The open files, shared by the connections
+struct Server {
+    /// Canonical path of the directory the opened files must be in
+    root: PathBuf,
+    files: RwLock<HashMap<String, Arc<Indexed>>>,
+}
+
+impl Server {
+    fn new(root: &Path) -> io::Result<Server> {
+        Ok(Server { root: root.canonicalize()?, files: RwLock::default() })
+    }
+
+    /// Canonical path of the file at `path`, relative paths are resolved against the root. Fails for
+    /// files outside of the root (e.g. through `..` or symbolic links).
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let resolved = self.root.join(path).canonicalize()?;
+        if !resolved.starts_with(&self.root) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Not in {}", self.root.display())));
+        }
+        Ok(resolved)
+    }
+}
+
+/// Accepts connections on the socket at `path` until the process is terminated. The files in `root`
+/// can be opened.
+pub fn serve(path: &Path, root: &Path) -> io::Result<()> {
+    remove_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    tracing::info!(socket = %path.display(), "Listening");
+    let server = Arc::new(Server::new(root)?);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, &server) {
+                tracing::warn!(%err, "Connection failed");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Removes the socket at `path` left behind by an earlier server, fails if there is anything else
+fn remove_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn handle_connection(stream: UnixStream, server: &Server) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(&line, server) {
+            writeln!(writer, "{}", response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Response to a request line, None for notifications
+fn handle_line(line: &str, server: &Server) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return Some(error_response(Value::Null, RpcError { code: PARSE_ERROR, message: err.to_string() })),
+    };
+    let id = request.get("id").cloned();
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => method,
+        _ => return Some(error_response(id.unwrap_or(Value::Null), RpcError {
+            code: INVALID_REQUEST,
+            message: "Expected a JSON-RPC 2.0 request".to_string(),
+        })),
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let _span = tracing::debug_span!("request", method).entered();
+    let result = dispatch(method, &params, server);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error_response(id, err),
+    })
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message } })
+}
+
+fn param<'a>(params: &'a Value, name: &str) -> Result<&'a Value, RpcError> {
+    params.get(name).ok_or_else(|| RpcError::invalid_params(format!("Missing parameter {}", name)))
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    param(params, name)?.as_str().ok_or_else(|| RpcError::invalid_params(format!("Parameter {} must be a string", name)))
+}
+
+fn file(params: &Value, server: &Server) -> Result<Arc<Indexed>, RpcError> {
+    let path = str_param(params, "file")?;
+    server.files.read().unwrap().get(path).cloned()
+        .ok_or_else(|| RpcError::invalid_params(format!("File {} is not open", path)))
+}
+
+fn dispatch(method: &str, params: &Value, server: &Server) -> Result<Value, RpcError> {
+    match method {
+        "open" => {
+            let path = str_param(params, "path")?;
+            let data = server.resolve(path).and_then(|it| input::read_file(&it))
+                .map_err(|err| RpcError::failed(format!("Could not open {}: {}", path, err)))?;
+            let dex = DexFile::from_bytes(&data)
+                .map_err(|err| RpcError::failed(format!("Could not parse {}: {}", path, err)))?;
+            let indexed = Indexed::new(dex);
+            let result = json!({
+                "file": path,
+                "version": indexed.dex.version(),
+                "strings": indexed.dex.strings.len(),
+                "classes": indexed.dex.class_defs.len(),
+                "methods": indexed.dex.method_ids.len(),
+                "references": indexed.references.len(),
+            });
+            server.files.write().unwrap().insert(path.to_string(), Arc::new(indexed));
+            Ok(result)
+        }
+        "close" => {
+            let path = str_param(params, "file")?;
+            Ok(json!(server.files.write().unwrap().remove(path).is_some()))
+        }
+        "files" => {
+            let mut paths: Vec<String> = server.files.read().unwrap().keys().cloned().collect();
+            paths.sort();
+            Ok(json!(paths))
+        }
+        "xrefs" => {
+            let indexed = file(params, server)?;
+            let kind = str_param(params, "kind")?;
+            let target = match param(params, "target")? {
+                Value::Number(number) => number.as_u64().map(|it| it as u32),
+                Value::String(name) => indexed.resolve(kind, name),
+                _ => return Err(RpcError::invalid_params("Parameter target must be an index or a name")),
+            };
+            let references = target
+                .and_then(|target| indexed.by_target.get(kind)?.get(&target))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            Ok(references.iter().map(|it| {
+                let reference = &indexed.references[*it];
+                json!({
+                    "method_idx": reference.method_idx,
+                    "method": indexed.method_name(reference.method_idx),
+                    "pc": reference.pc,
+                })
+            }).collect())
+        }
+        "search" => {
+            let indexed = file(params, server)?;
+            let query = str_param(params, "query")?;
+            let kind = params.get("kind").and_then(Value::as_str).unwrap_or("strings");
+            let limit = params.get("limit").and_then(Value::as_u64).unwrap_or(100) as usize;
+            let (table, column) = match kind {
+                "strings" => (listing::strings(&indexed.dex), "value"),
+                "classes" => (listing::classes(&indexed.dex), "class"),
+                "methods" => (listing::methods(&indexed.dex), "name"),
+                _ => return Err(RpcError::invalid_params(format!("Unknown kind {}", kind))),
+            };
+            let column = table.columns.iter().position(|it| *it == column).unwrap();
+            Ok(table.rows.iter()
+                .filter(|row| row[column].contains(query))
+                .take(limit)
+                .map(|row| Value::Object(table.columns.iter().map(|it| it.to_string()).zip(row.iter().map(|it| json!(it))).collect()))
+                .collect())
+        }
+        "disasm" => {
+            let indexed = file(params, server)?;
+            let class = str_param(params, "class")?;
+            let dex = &indexed.dex;
+            let idx = dex.type_idx(class).and_then(|it| dex.type_class_def_idx(it))
+                .ok_or_else(|| RpcError::invalid_params(format!("Class {} is not defined", class)))?;
+            let mut out = Vec::new();
+            smali::write_class(dex, idx, &smali::Comments::new(), &mut out)
+                .map_err(|err| RpcError::failed(err.to_string()))?;
+            Ok(json!(String::from_utf8_lossy(&out)))
+        }
+        _ => Err(RpcError { code: METHOD_NOT_FOUND, message: format!("Unknown method {}", method) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dex_tool::code_builder::{CodeBuilder, Operand::*};
+    use dex_tool::fixture::{Fixture, FixtureMethod};
+
+    /// Server with a root directory of its own, holding `fixture.dex`
+    fn server(name: &str) -> Server {
+        let root = std::env::temp_dir().join(format!("dex_tool-serve-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let get = |insns| FixtureMethod::new("get", "Ljava/lang/String;", &[], insns).registers_size(1);
+        let fixture = Fixture::new().string("hello");
+        // The string indices of the fixture with the method
+        let hello = fixture.clone().method(get(Vec::new())).string_idx("hello").unwrap();
+        let mut code = CodeBuilder::new();
+        code.emit("const-string", &[Reg(0), Idx(hello)]).unwrap()
+            .emit("return-object", &[Reg(0)]).unwrap();
+        let data = fixture.method(get(code.build().unwrap())).build();
+        fs::write(root.join("fixture.dex"), data).unwrap();
+        Server::new(&root).unwrap()
+    }
+
+    #[test]
+    fn socket_replaced() {
+        let server = server("socket");
+        let socket = server.root.join("dex_tool.sock");
+        drop(UnixListener::bind(&socket).unwrap());
+        remove_socket(&socket).unwrap();
+        assert!(!socket.exists());
+        remove_socket(&socket).unwrap();
+        // Anything else is kept
+        let dex = server.root.join("fixture.dex");
+        assert_eq!(remove_socket(&dex).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(dex.exists());
+    }
+
+    fn request(server: &Server, method: &str, params: Value) -> Value {
+        let line = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        handle_line(&line, server).unwrap()
+    }
+
+    fn error_code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn errors() {
+        let server = server("errors");
+        let response = handle_line("{\"jsonrpc\": \"2.0\",", &server).unwrap();
+        assert_eq!((error_code(&response), &response["id"]), (Some(PARSE_ERROR), &Value::Null));
+        let response = handle_line(r#"{"id": 7, "method": "files"}"#, &server).unwrap();
+        assert_eq!((error_code(&response), &response["id"]), (Some(INVALID_REQUEST), &json!(7)));
+        assert_eq!(error_code(&request(&server, "xrefs", json!({}))), Some(INVALID_PARAMS));
+        assert_eq!(error_code(&request(&server, "xrefs", json!({ "file": "fixture.dex", "kind": "string", "target": 0 }))), Some(INVALID_PARAMS));
+        assert_eq!(error_code(&request(&server, "open", json!({ "path": 1 }))), Some(INVALID_PARAMS));
+        assert_eq!(error_code(&request(&server, "unknown", json!({}))), Some(METHOD_NOT_FOUND));
+        // Files outside of the root
+        assert_eq!(error_code(&request(&server, "open", json!({ "path": "../fixture.dex" }))), Some(REQUEST_FAILED));
+        assert_eq!(error_code(&request(&server, "open", json!({ "path": "/etc/passwd" }))), Some(REQUEST_FAILED));
+        // Notifications have no response
+        assert!(handle_line(r#"{"jsonrpc": "2.0", "method": "files"}"#, &server).is_none());
+    }
+
+    #[test]
+    fn xrefs_and_search() {
+        let server = server("queries");
+        assert_eq!(request(&server, "open", json!({ "path": "fixture.dex" }))["result"]["file"], json!("fixture.dex"));
+        assert_eq!(request(&server, "files", Value::Null)["result"], json!(["fixture.dex"]));
+
+        let expected = json!([{ "method_idx": 0, "method": "Lcom/example/Fixture;->get()Ljava/lang/String;", "pc": 0 }]);
+        let xrefs = |target| request(&server, "xrefs", json!({ "file": "fixture.dex", "kind": "string", "target": target }));
+        assert_eq!(xrefs(json!("hello"))["result"], expected);
+        let hello = server.files.read().unwrap()["fixture.dex"].dex.string_idx("hello").unwrap();
+        assert_eq!(xrefs(json!(hello))["result"], expected);
+        assert_eq!(xrefs(json!("missing"))["result"], json!([]));
+
+        let search = |params| request(&server, "search", params)["result"].clone();
+        let strings = search(json!({ "file": "fixture.dex", "query": "hell" }));
+        assert_eq!(strings.as_array().map(|it| it.iter().map(|row| row["value"].clone()).collect()), Some(vec![json!("hello")]));
+        let methods = search(json!({ "file": "fixture.dex", "query": "get", "kind": "methods" }));
+        assert_eq!(methods.as_array().map(Vec::len), Some(1));
+        assert_eq!(search(json!({ "file": "fixture.dex", "query": "", "kind": "strings", "limit": 2 })).as_array().map(Vec::len), Some(2));
+
+        assert_eq!(request(&server, "close", json!({ "file": "fixture.dex" }))["result"], json!(true));
+        assert_eq!(error_code(&request(&server, "search", json!({ "file": "fixture.dex", "query": "" }))), Some(INVALID_PARAMS));
+    }
+}
@@ -0,0 +1,608 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::AtomicFile;
+use crate::dex_file::{self, CatchInfo, DexFile, ACC_STATIC, DBG_FIRST_SPECIAL, DBG_LINE_BASE, DBG_LINE_RANGE, NO_INDEX};
+use crate::instructions::{Format, IndexType, Instruction, Instructions, Payload};
+use crate::raw_dex::{CodeItem, DebugInstruction, EncodedField, EncodedMethod, OptionalIdx};
+
+/*
+Output in the smali syntax of baksmali, see
+* https://github.com/JesusFreke/smali/wiki
+ */
+
+/// Names of the access flags of classes, in the order of the smali syntax
+pub const CLASS_FLAGS: [(u32, &str); 10] = [
+    (0x1, "public"), (0x2, "private"), (0x4, "protected"), (0x8, "static"), (0x10, "final"),
+    (0x200, "interface"), (0x400, "abstract"), (0x1000, "synthetic"), (0x2000, "annotation"), (0x4000, "enum"),
+];
+const METHOD_FLAGS: [(u32, &str); 14] = [
+    (0x1, "public"), (0x2, "private"), (0x4, "protected"), (0x8, "static"), (0x10, "final"),
+    (0x20, "synchronized"), (0x40, "bridge"), (0x80, "varargs"), (0x100, "native"), (0x400, "abstract"),
+    (0x800, "strictfp"), (0x1000, "synthetic"), (0x10000, "constructor"), (0x20000, "declared-synchronized"),
+];
+const FIELD_FLAGS: [(u32, &str); 9] = [
+    (0x1, "public"), (0x2, "private"), (0x4, "protected"), (0x8, "static"), (0x10, "final"),
+    (0x40, "volatile"), (0x80, "transient"), (0x1000, "synthetic"), (0x4000, "enum"),
+];
+
+fn access_flags_str(flags: u32, names: &[(u32, &str)]) -> String {
+    names.iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| format!("{} ", name))
+        .collect()
+}
+
+/// Escapes a string constant, non-ASCII characters are written as \uXXXX escapes
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (' '..='~').contains(&c) => escaped.push(c),
+            c => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    escaped.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    escaped
+}
+
+/// Formats a literal as signed hex number, e.g. `-0x1`
+fn hex(value: i64) -> String {
+    if value < 0 { format!("-0x{:x}", (value as i128).unsigned_abs()) } else { format!("0x{:x}", value) }
+}
+
+/// Comments to add after instructions, keyed by method index and pc
+pub type Comments = HashMap<(u32, usize), String>;
+
+/// Path of the smali file of a class relative to the output directory, e.g. "com/example/Foo.smali"
+pub fn class_file_path(descriptor: &str) -> PathBuf {
+    let name = descriptor.strip_prefix('L').and_then(|it| it.strip_suffix(';')).unwrap_or(descriptor);
+    let mut path = PathBuf::new();
+    for part in name.split('/') {
+        path.push(part);
+    }
+    path.set_extension("smali");
+    path
+}
+
+/// Writes one smali file per class into `dir`, using the package directory layout
+pub fn write_all(dex: &DexFile, dir: &Path, comments: &Comments) -> std::io::Result<()> {
+    for idx in 0..dex.class_defs.len() {
+        let path = dir.join(class_file_path(dex.type_descriptor(dex.class_defs[idx].class_idx)));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = AtomicFile::create(&path)?;
+        write_class(dex, idx, comments, &mut out)?;
+        out.commit()?;
+    }
+    Ok(())
+}
+
+pub fn write_class(dex: &DexFile, idx: usize, comments: &Comments, out: &mut dyn Write) -> std::io::Result<()> {
+    let class_def = &dex.class_defs[idx];
+
+    writeln!(out, ".class {}{}", access_flags_str(class_def.access_flags, &CLASS_FLAGS),
+             dex.type_descriptor(class_def.class_idx))?;
+    if class_def.superclass_idx != NO_INDEX {
+        writeln!(out, ".super {}", dex.type_descriptor(class_def.superclass_idx))?;
+    }
+    if class_def.source_file_idx != NO_INDEX {
+        writeln!(out, ".source \"{}\"", escape_string(dex.string(class_def.source_file_idx)))?;
+    }
+
+    let interfaces = dex.type_list(class_def.interfaces_off);
+    if !interfaces.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "# interfaces")?;
+        for type_idx in interfaces {
+            writeln!(out, ".implements {}", dex.type_descriptor(*type_idx as u32))?;
+        }
+    }
+
+    if let Some(class_data) = &dex.class_data[idx] {
+        write_fields(dex, "static fields", &class_data.static_fields, out)?;
+        write_fields(dex, "instance fields", &class_data.instance_fields, out)?;
+        write_methods(dex, "direct methods", &class_data.direct_methods, comments, out)?;
+        write_methods(dex, "virtual methods", &class_data.virtual_methods, comments, out)?;
+    }
+    Ok(())
+}
+
+fn write_fields(dex: &DexFile, title: &str, fields: &[EncodedField], out: &mut dyn Write) -> std::io::Result<()> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+    writeln!(out)?;
+    writeln!(out)?;
+    writeln!(out, "# {}", title)?;
+    for (i, (field, field_idx)) in fields.iter().zip(dex_file::field_indices(fields)).enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, ".field {}{}:{}", access_flags_str(field.access_flags as u32, &FIELD_FLAGS),
+                 dex.field_name(field_idx), dex.field_type(field_idx))?;
+    }
+    Ok(())
+}
+
+fn write_methods(dex: &DexFile, title: &str, methods: &[EncodedMethod], comments: &Comments, out: &mut dyn Write) -> std::io::Result<()> {
+    if methods.is_empty() {
+        return Ok(());
+    }
+    writeln!(out)?;
+    writeln!(out)?;
+    writeln!(out, "# {}", title)?;
+    for (i, (method, method_idx)) in methods.iter().zip(dex_file::method_indices(methods)).enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        write_method(dex, method, method_idx, comments, out)?;
+    }
+    Ok(())
+}
+
+/// Writes a method from `.method` to `.end method`, `method_idx` is the index decoded from the
+/// differences of the class data
+pub fn write_method(dex: &DexFile, method: &EncodedMethod, method_idx: u32, comments: &Comments, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, ".method {}{}{}", access_flags_str(method.access_flags as u32, &METHOD_FLAGS),
+             dex.method_name(method_idx), dex.method_signature(method_idx))?;
+    if let Some(code) = dex.code_item(method.code_off) {
+        MethodWriter::new(dex, method_idx, method, code, comments).write(out)?;
+    }
+    writeln!(out, ".end method")
+}
+
+/// Kinds of labels, in the order in which they are written if several share an address
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Goto,
+    Cond,
+    PackedSwitch,
+    SparseSwitch,
+    TryStart,
+    Catch,
+    CatchAll,
+    PackedSwitchData,
+    SparseSwitchData,
+    Array,
+}
+
+impl LabelKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            LabelKind::Goto => "goto",
+            LabelKind::Cond => "cond",
+            LabelKind::PackedSwitch => "pswitch",
+            LabelKind::SparseSwitch => "sswitch",
+            LabelKind::TryStart => "try_start",
+            LabelKind::Catch => "catch",
+            LabelKind::CatchAll => "catchall",
+            LabelKind::PackedSwitchData => "pswitch_data",
+            LabelKind::SparseSwitchData => "sswitch_data",
+            LabelKind::Array => "array",
+        }
+    }
+}
+
+struct MethodWriter<'a> {
+    dex: &'a DexFile,
+    method_idx: u32,
+    method: &'a EncodedMethod,
+    comments: &'a Comments,
+    code: &'a CodeItem,
+    /// First register holding a parameter (p0)
+    first_param: u32,
+    insns: Vec<(usize, Instruction)>,
+    /// Labels (kind, address) with their number
+    labels: BTreeMap<(LabelKind, usize), usize>,
+    /// Switch instruction address for each switch payload, as case targets are relative to it
+    switch_addresses: BTreeMap<usize, usize>,
+    catches: Vec<CatchInfo<'a>>,
+}
+
+impl<'a> MethodWriter<'a> {
+    fn new(dex: &'a DexFile, method_idx: u32, method: &'a EncodedMethod, code: &'a CodeItem, comments: &'a Comments) -> MethodWriter<'a> {
+        let mut insns = Vec::new();
+        for insn in Instructions::new(&code.insns) {
+            match insn {
+                Ok(insn) => insns.push(insn),
+                Err(err) => tracing::warn!(method_idx, %err, "Skipping the remaining instructions"),
+            }
+        }
+        let mut writer = MethodWriter {
+            dex,
+            method_idx,
+            method,
+            comments,
+            code,
+            first_param: code.registers_size.saturating_sub(code.ins_size) as u32,
+            insns,
+            labels: BTreeMap::new(),
+            switch_addresses: BTreeMap::new(),
+            catches: dex.catches(code),
+        };
+        writer.collect_labels();
+        writer
+    }
+
+    fn collect_labels(&mut self) {
+        let mut labels = BTreeSet::new();
+        for (pc, insn) in &self.insns {
+            let target = |offset: u32| (*pc as i64 + offset as i32 as i64) as usize;
+            match insn.format() {
+                Format::F10t | Format::F20t | Format::F30t => { labels.insert((LabelKind::Goto, target(insn.a))); }
+                Format::F21t => { labels.insert((LabelKind::Cond, target(insn.b))); }
+                Format::F22t => { labels.insert((LabelKind::Cond, target(insn.c))); }
+                Format::F31t => {
+                    let payload_pc = target(insn.b);
+                    let kind = match insn.opcode {
+                        0x2b => LabelKind::PackedSwitchData,
+                        0x2c => LabelKind::SparseSwitchData,
+                        _ => LabelKind::Array,
+                    };
+                    labels.insert((kind, payload_pc));
+                    if kind != LabelKind::Array {
+                        self.switch_addresses.insert(payload_pc, *pc);
+                        let (case_kind, targets) = match Payload::decode(&self.code.insns, payload_pc) {
+                            Ok(Payload::PackedSwitch { targets, .. }) => (LabelKind::PackedSwitch, targets),
+                            Ok(Payload::SparseSwitch { targets, .. }) => (LabelKind::SparseSwitch, targets),
+                            _ => continue,
+                        };
+                        for offset in targets {
+                            labels.insert((case_kind, target(offset as u32)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for catch in &self.catches {
+            labels.insert((LabelKind::TryStart, catch.start_address as usize));
+            for handler in &catch.handlers {
+                let kind = if handler.exception.is_some() { LabelKind::Catch } else { LabelKind::CatchAll };
+                labels.insert((kind, handler.address as usize));
+            }
+        }
+
+        // Labels are numbered per kind in the order of their address
+        let mut counters: BTreeMap<LabelKind, usize> = BTreeMap::new();
+        for label in labels {
+            let counter = counters.entry(label.0).or_insert(0);
+            self.labels.insert(label, *counter);
+            *counter += 1;
+        }
+    }
+
+    fn label(&self, kind: LabelKind, address: usize) -> String {
+        match self.labels.get(&(kind, address)) {
+            Some(number) => format!(":{}_{}", kind.prefix(), number),
+            None => format!(":{}_{:x}", kind.prefix(), address),
+        }
+    }
+
+    fn reg(&self, reg: u32) -> String {
+        if reg >= self.first_param { format!("p{}", reg - self.first_param) } else { format!("v{}", reg) }
+    }
+
+    /// Debug directives (.line, .local, ...) grouped by address
+    fn debug_directives(&self) -> BTreeMap<usize, Vec<String>> {
+        let mut directives: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        let debug_info = match self.dex.debug_info.get(&self.code.debug_info_off) {
+            Some(debug_info) if self.code.debug_info_off != 0 => debug_info,
+            _ => return directives,
+        };
+        let dex = self.dex;
+        let local = |name_idx: OptionalIdx, type_idx: OptionalIdx, sig_idx: OptionalIdx| {
+            let mut local = String::new();
+            if let Some(name_idx) = name_idx.get() {
+                local.push_str(&format!("\"{}\"", escape_string(dex.string(name_idx))));
+            }
+            local.push(':');
+            if let Some(type_idx) = type_idx.get() {
+                local.push_str(dex.type_descriptor(type_idx));
+            }
+            if let Some(sig_idx) = sig_idx.get() {
+                local.push_str(&format!(", \"{}\"", escape_string(dex.string(sig_idx))));
+            }
+            local
+        };
+
+        // Description of the last local started in each register for .end local and .restart local
+        let mut started: BTreeMap<u64, String> = BTreeMap::new();
+        let mut address = 0usize;
+        let mut line = debug_info.line_start as i64;
+        for insn in &debug_info.bytecode {
+            let directive = match *insn {
+                DebugInstruction::AdvancePc(diff) => {
+                    address += diff as usize;
+                    continue;
+                }
+                DebugInstruction::AdvanceLine(diff) => {
+                    line += diff;
+                    continue;
+                }
+                DebugInstruction::StartLocal { register_num, name_idx, type_idx } => {
+                    let description = local(name_idx, type_idx, OptionalIdx::NONE);
+                    started.insert(register_num, description.clone());
+                    format!(".local {}, {}", self.reg(register_num as u32), description)
+                }
+                DebugInstruction::StartLocalExtended { register_num, name_idx, type_idx, sig_idx } => {
+                    let description = local(name_idx, type_idx, sig_idx);
+                    started.insert(register_num, description.clone());
+                    format!(".local {}, {}", self.reg(register_num as u32), description)
+                }
+                DebugInstruction::EndLocal(register_num) => match started.get(&register_num) {
+                    Some(description) => format!(".end local {}    # {}", self.reg(register_num as u32), description),
+                    None => format!(".end local {}", self.reg(register_num as u32)),
+                },
+                DebugInstruction::RestartLocal(register_num) => match started.get(&register_num) {
+                    Some(description) => format!(".restart local {}    # {}", self.reg(register_num as u32), description),
+                    None => format!(".restart local {}", self.reg(register_num as u32)),
+                },
+                DebugInstruction::SetPrologueEnd => String::from(".prologue"),
+                DebugInstruction::SetEpilogueBegin => String::from(".epilogue"),
+                DebugInstruction::SetFile(name_idx) => match name_idx.get() {
+                    Some(name_idx) => format!(".source \"{}\"", escape_string(dex.string(name_idx))),
+                    None => continue,
+                },
+                DebugInstruction::Special(opcode) => {
+                    let adjusted = opcode as i64 - DBG_FIRST_SPECIAL;
+                    address += (adjusted / DBG_LINE_RANGE) as usize;
+                    line += DBG_LINE_BASE + adjusted % DBG_LINE_RANGE;
+                    format!(".line {}", line)
+                }
+            };
+            directives.entry(address).or_default().push(directive);
+        }
+        directives
+    }
+
+    fn write_parameters(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let debug_info = match self.dex.debug_info.get(&self.code.debug_info_off) {
+            Some(debug_info) if self.code.debug_info_off != 0 => debug_info,
+            _ => return Ok(()),
+        };
+        let proto_idx = self.dex.method_ids[self.method_idx as usize].proto_idx as u32;
+        let mut reg = if self.method.access_flags & ACC_STATIC == 0 { 1 } else { 0 };
+        for (i, descriptor) in self.dex.proto_parameters(proto_idx).into_iter().enumerate() {
+            if let Some(name_idx) = debug_info.parameter_names.get(i).and_then(|it| it.get()) {
+                writeln!(out, "    .param p{}, \"{}\"    # {}", reg, escape_string(self.dex.string(name_idx)), descriptor)?;
+            }
+            reg += if descriptor == "J" || descriptor == "D" { 2 } else { 1 };
+        }
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(out, "    .registers {}", self.code.registers_size)?;
+        self.write_parameters(out)?;
+
+        let mut debug_directives = self.debug_directives();
+        let mut try_ends: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, try_item) in self.code.tries.iter().enumerate() {
+            try_ends.entry(try_item.start_addr as usize + try_item.insn_count as usize).or_default().push(i);
+        }
+
+        let end = self.code.insns.len();
+        for (i, (pc, insn)) in self.insns.iter().map(|(pc, insn)| (*pc, Some(insn))).chain(std::iter::once((end, None))).enumerate() {
+            if let Some(tries) = try_ends.remove(&pc) {
+                for i in tries {
+                    self.write_try_end(i, out)?;
+                }
+            }
+            let insn = match insn {
+                Some(insn) => insn,
+                None => break,
+            };
+            // Alignment of a following payload, omitted like in baksmali
+            let next_is_payload = self.insns.get(i + 1).is_some_and(|(_, next)| next.payload.is_some());
+            if insn.opcode == 0x00 && insn.payload.is_none() && pc % 2 == 1 && next_is_payload {
+                continue;
+            }
+            writeln!(out)?;
+            for directive in debug_directives.remove(&pc).unwrap_or_default() {
+                writeln!(out, "    {}", directive)?;
+            }
+            for (kind, _) in self.labels.keys().filter(|(_, address)| *address == pc) {
+                writeln!(out, "    {}", self.label(*kind, pc))?;
+            }
+            self.write_instruction(pc, insn, out)?;
+            self.write_payload_comment(pc, insn, out)?;
+            if let Some(comment) = self.comments.get(&(self.method_idx, pc)) {
+                writeln!(out, "    # {}", comment)?;
+            }
+        }
+        // Directives past the last instruction (e.g. the end of local variables)
+        for directive in debug_directives.into_values().flatten() {
+            writeln!(out, "    {}", directive)?;
+        }
+        Ok(())
+    }
+
+    /// Cases of a switch or elements of array data as comments at the instruction referencing the
+    /// payload, so they can be read without looking up the payload
+    fn write_payload_comment(&self, pc: usize, insn: &Instruction, out: &mut dyn Write) -> std::io::Result<()> {
+        if insn.payload.is_some() || !matches!(insn.opcode, 0x26 | 0x2b | 0x2c) {
+            return Ok(());
+        }
+        let payload_pc = (pc as i64 + insn.b as i32 as i64) as usize;
+        let payload = match Payload::decode(&self.code.insns, payload_pc) {
+            Ok(payload) => payload,
+            Err(_) => return Ok(()),
+        };
+        if let Some(array) = payload.array_data() {
+            return writeln!(out, "    # {}[{}] {}", array.element_type(), array.len(), array);
+        }
+        let kind = if insn.opcode == 0x2b { LabelKind::PackedSwitch } else { LabelKind::SparseSwitch };
+        for (key, offset) in payload.switch_cases().unwrap_or_default() {
+            let target = (pc as i64 + offset as i64) as usize;
+            writeln!(out, "    # case {}: {}", hex(key as i64), self.label(kind, target))?;
+        }
+        Ok(())
+    }
+
+    fn write_try_end(&self, try_idx: usize, out: &mut dyn Write) -> std::io::Result<()> {
+        let catch = &self.catches[try_idx];
+        writeln!(out, "    :try_end_{}", try_idx)?;
+        let range = format!("{{{} .. :try_end_{}}}", self.label(LabelKind::TryStart, catch.start_address as usize), try_idx);
+        for handler in &catch.handlers {
+            match handler.exception {
+                Some(exception) => writeln!(out, "    .catch {} {} {}", exception, range,
+                                            self.label(LabelKind::Catch, handler.address as usize))?,
+                None => writeln!(out, "    .catchall {} {}", range, self.label(LabelKind::CatchAll, handler.address as usize))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_instruction(&self, pc: usize, insn: &Instruction, out: &mut dyn Write) -> std::io::Result<()> {
+        if insn.payload.is_some() {
+            return self.write_payload(pc, out);
+        }
+        let target = |offset: u32| (pc as i64 + offset as i32 as i64) as usize;
+        let name = insn.name();
+        let operands = match insn.format() {
+            Format::F10x => String::new(),
+            Format::F12x | Format::F22x | Format::F32x => format!("{}, {}", self.reg(insn.a), self.reg(insn.b)),
+            Format::F11n | Format::F21s | Format::F31i => {
+                let wide = name.starts_with("const-wide");
+                format!("{}, {}{}", self.reg(insn.a), hex(insn.b as i32 as i64), if wide { "L" } else { "" })
+            }
+            Format::F21h => if insn.opcode == 0x15 {
+                format!("{}, {}", self.reg(insn.a), hex((insn.b << 16) as i32 as i64))
+            } else {
+                format!("{}, {}L", self.reg(insn.a), hex(((insn.b as u64) << 48) as i64))
+            },
+            Format::F51l => format!("{}, {}L", self.reg(insn.a), hex(insn.wide_b as i64)),
+            Format::F11x => self.reg(insn.a),
+            Format::F10t | Format::F20t | Format::F30t => self.label(LabelKind::Goto, target(insn.a)),
+            Format::F21t => format!("{}, {}", self.reg(insn.a), self.label(LabelKind::Cond, target(insn.b))),
+            Format::F22t => format!("{}, {}, {}", self.reg(insn.a), self.reg(insn.b), self.label(LabelKind::Cond, target(insn.c))),
+            Format::F21c | Format::F31c => format!("{}, {}", self.reg(insn.a), self.reference(insn, insn.b)),
+            Format::F23x => format!("{}, {}, {}", self.reg(insn.a), self.reg(insn.b), self.reg(insn.c)),
+            Format::F22b | Format::F22s => format!("{}, {}, {}", self.reg(insn.a), self.reg(insn.b), hex(insn.c as i32 as i64)),
+            Format::F22c => format!("{}, {}, {}", self.reg(insn.a), self.reg(insn.b), self.reference(insn, insn.c)),
+            Format::F31t => {
+                let kind = match insn.opcode {
+                    0x2b => LabelKind::PackedSwitchData,
+                    0x2c => LabelKind::SparseSwitchData,
+                    _ => LabelKind::Array,
+                };
+                format!("{}, {}", self.reg(insn.a), self.label(kind, target(insn.b)))
+            }
+            Format::F35c | Format::F45cc => {
+                let args: Vec<String> = insn.args.iter().take(insn.a as usize).map(|it| self.reg(*it as u32)).collect();
+                format!("{{{}}}, {}", args.join(", "), self.reference(insn, insn.b))
+            }
+            Format::F3rc | Format::F4rcc => {
+                let registers = match insn.a {
+                    0 => String::new(),
+                    count => format!("{} .. {}", self.reg(insn.c), self.reg(insn.c + count - 1)),
+                };
+                format!("{{{}}}, {}", registers, self.reference(insn, insn.b))
+            }
+        };
+        if operands.is_empty() {
+            writeln!(out, "    {}", name)
+        } else {
+            writeln!(out, "    {} {}", name, operands)
+        }
+    }
+
+    fn reference(&self, insn: &Instruction, index: u32) -> String {
+        let dex = self.dex;
+        let header = &dex.header;
+        // Operands are not checked by the parser, out of range ones are printed like invalid_string@12
+        let checked = |kind: &str, idx: u32, size: u32, name: &dyn Fn(u32) -> String| {
+            if idx < size { name(idx) } else { format!("invalid_{}@{}", kind, idx) }
+        };
+        let method = |idx: u32| checked("method", idx, header.method_ids_size,
+                                        &|idx| format!("{}->{}{}", dex.method_class(idx), dex.method_name(idx), dex.method_signature(idx)));
+        let proto = |idx: u32| checked("proto", idx, header.proto_ids_size, &|idx| dex.proto_signature(idx));
+        match insn.info().index_type {
+            IndexType::None => String::new(),
+            IndexType::StringRef => checked("string", index, header.string_ids_size, &|idx| format!("\"{}\"", escape_string(dex.string(idx)))),
+            IndexType::TypeRef => checked("type", index, header.type_ids_size, &|idx| dex.type_descriptor(idx).to_string()),
+            IndexType::FieldRef => checked("field", index, header.field_ids_size,
+                                           &|idx| format!("{}->{}:{}", dex.field_class(idx), dex.field_name(idx), dex.field_type(idx))),
+            IndexType::MethodRef => method(index),
+            IndexType::MethodAndProtoRef => format!("{}, {}", method(index), proto(insn.h)),
+            IndexType::ProtoRef => proto(index),
+            // Call sites and method handles are not resolved yet
+            IndexType::CallSiteRef => format!("call_site_{}", index),
+            IndexType::MethodHandleRef => format!("method_handle_{}", index),
+        }
+    }
+
+    fn write_payload(&self, pc: usize, out: &mut dyn Write) -> std::io::Result<()> {
+        let switch_pc = self.switch_addresses.get(&pc).copied().unwrap_or(pc);
+        let case_target = |offset: &i32| (switch_pc as i64 + *offset as i64) as usize;
+        match Payload::decode(&self.code.insns, pc) {
+            Ok(Payload::PackedSwitch { first_key, targets }) => {
+                writeln!(out, "    .packed-switch {}", hex(first_key as i64))?;
+                for offset in &targets {
+                    writeln!(out, "        {}", self.label(LabelKind::PackedSwitch, case_target(offset)))?;
+                }
+                writeln!(out, "    .end packed-switch")
+            }
+            Ok(Payload::SparseSwitch { keys, targets }) => {
+                writeln!(out, "    .sparse-switch")?;
+                for (key, offset) in keys.iter().zip(&targets) {
+                    writeln!(out, "        {} -> {}", hex(*key as i64), self.label(LabelKind::SparseSwitch, case_target(offset)))?;
+                }
+                writeln!(out, "    .end sparse-switch")
+            }
+            Ok(Payload::FillArrayData { element_width, data }) => {
+                writeln!(out, "    .array-data {}", element_width)?;
+                for element in data.chunks(element_width.max(1) as usize) {
+                    let mut bytes = [0u8; 8];
+                    bytes[..element.len()].copy_from_slice(element);
+                    let value = u64::from_le_bytes(bytes);
+                    let line = match element_width {
+                        1 => format!("{}t", hex(value as i8 as i64)),
+                        2 => format!("{}s", hex(value as i16 as i64)),
+                        4 => hex(value as i32 as i64),
+                        _ => format!("{}L", hex(value as i64)),
+                    };
+                    writeln!(out, "        {}", line)?;
+                }
+                writeln!(out, "    .end array-data")
+            }
+            Err(_) => writeln!(out, "    nop"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::fixture::{Fixture, FixtureMethod};
+
+    #[test]
+    fn malformed_code() {
+        // Fewer registers than ins and an index past the string ids, which the parser accepts
+        let mut code = CodeBuilder::new();
+        code.emit("const-string", &[Reg(0), Idx(999)]).unwrap()
+            .emit("return-void", &[]).unwrap();
+        let data = Fixture::empty().method(FixtureMethod::new("run", "V", &[], code.build().unwrap()).registers_size(0)).build();
+        let dex = DexFile::from_bytes(&data).unwrap();
+        let mut out = Vec::new();
+        write_class(&dex, 0, &Comments::new(), &mut out).unwrap();
+        let smali = String::from_utf8(out).unwrap();
+        assert!(smali.contains("const-string p0, invalid_string@999"), "{}", smali);
+    }
+}
@@ -0,0 +1,70 @@
+use serde_json::{json, Map, Value};
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::{listing, tamper, verifier};
+
+/*
+Statistics of one dex file as a single JSON document, for indexing large numbers of files into a search
+backend. The counts and sizes come from the stats and map listings, so the keys follow their rows: the
+ids and items of the stats listing, and the count and byte size of each section of the map list by the
+name of its item type.
+ */
+
+/// Values of the rows of a key-value listing, as numbers where they parse
+pub fn values(rows: Vec<Vec<String>>) -> Map<String, Value> {
+    rows.into_iter()
+        .map(|row| (row[0].clone(), row[1].parse::<u64>().map_or_else(|_| Value::from(row[1].clone()), Value::from)))
+        .collect()
+}
+
+/// Count, minimum, maximum, mean and median of the lengths of the strings (in characters), with a
+/// histogram of buckets of powers of two: 0, 1, 2-3, 4-7, ...
+fn string_lengths(dex: &DexFile) -> Value {
+    let mut lengths: Vec<usize> = dex.strings.iter().map(|it| it.chars().count()).collect();
+    lengths.sort_unstable();
+    let mut buckets: Vec<(usize, usize, usize)> = Vec::new();
+    for length in &lengths {
+        let min = if *length == 0 { 0 } else { 1 << (usize::BITS - 1 - length.leading_zeros()) };
+        match buckets.last_mut() {
+            Some((last_min, _, count)) if *last_min == min => *count += 1,
+            _ => buckets.push((min, if min == 0 { 0 } else { 2 * min - 1 }, 1)),
+        }
+    }
+    let histogram: Vec<Value> = buckets.into_iter()
+        .map(|(min, max, count)| json!({ "min": min, "max": max, "count": count }))
+        .collect();
+    let mean = if lengths.is_empty() { 0.0 } else { lengths.iter().sum::<usize>() as f64 / lengths.len() as f64 };
+    json!({
+        "count": lengths.len(),
+        "min": lengths.first().copied().unwrap_or_default(),
+        "max": lengths.last().copied().unwrap_or_default(),
+        "mean": mean,
+        "median": lengths.get(lengths.len() / 2).copied().unwrap_or_default(),
+        "histogram": histogram,
+    })
+}
+
+/// Statistics of the dex file `data`: version, counts of the ids and items, byte sizes of the sections,
+/// distribution of the string lengths and the results of the tamper checks and the bytecode verifier
+pub fn stats_json(dex: &DexFile, data: &[u8]) -> Value {
+    let map = listing::map(dex);
+    let counts: Map<String, Value> = map.rows.iter().map(|row| (row[1].clone(), Value::from(row[2].parse::<u64>().unwrap_or_default()))).collect();
+    let sizes: Map<String, Value> = map.rows.iter().map(|row| (row[1].clone(), Value::from(row[4].parse::<u64>().unwrap_or_default()))).collect();
+
+    let report = tamper::tamper_report(dex, data);
+    let mut verification: Map<String, Value> = report.checks.iter()
+        .map(|check| (check.name.to_string(), check.passed.map_or(Value::Null, Value::from)))
+        .collect();
+    verification.insert("tamper_score".to_string(), Value::from(report.score()));
+    verification.insert("bytecode_problems".to_string(), Value::from(verifier::verify(dex).len()));
+
+    json!({
+        "version": dex.version(),
+        "file_size": dex.header.file_size,
+        "stats": values(listing::stats(dex).rows),
+        "counts": counts,
+        "sizes": sizes,
+        "string_lengths": string_lengths(dex),
+        "verification": verification,
+    })
+}
@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io::{self, Cursor};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::m_utf8;
+use crate::raw_dex::{self, DexHeader};
+
+/*
+Strings of a dex file that are decoded on first access, for tools that resolve a few names without
+loading the whole file (DexFile decodes the entire string pool while parsing). Decoded strings are
+interned, so names shared by the dex files of an app (e.g. Ljava/lang/Object;) are allocated once
+when their pools share an Interner.
+ */
+
+/// Set of shared strings, cloning it shares the set
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    strings: Arc<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// The shared string equal to `value`, added to the set if there is none
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        let mut strings = self.strings.lock().unwrap();
+        if let Some(string) = strings.get(value) {
+            return string.clone();
+        }
+        let string: Arc<str> = Arc::from(value);
+        strings.insert(string.clone());
+        string
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Strings of a dex file in memory, decoded on first access
+#[derive(Debug)]
+pub struct StringPool<'a> {
+    data: &'a [u8],
+    header: DexHeader,
+    string_data_offs: Vec<u32>,
+    decoded: Vec<OnceLock<Arc<str>>>,
+    interner: Interner,
+}
+
+impl<'a> StringPool<'a> {
+    /// Reads the string ids of the dex file `data`, no string is decoded yet
+    pub fn new(data: &'a [u8]) -> io::Result<StringPool<'a>> {
+        StringPool::with_interner(data, Interner::new())
+    }
+
+    /// Like `new`, interning the strings in `interner` (e.g. shared with the pools of the other dex
+    /// files of an APK)
+    pub fn with_interner(data: &'a [u8], interner: Interner) -> io::Result<StringPool<'a>> {
+        let mut reader = Cursor::new(data);
+        let header = DexHeader::from_reader(&mut reader)?;
+        let string_data_offs = raw_dex::parse_string_ids(&header, &mut reader)?;
+        let decoded = string_data_offs.iter().map(|_| OnceLock::new()).collect();
+        Ok(StringPool { data, header, string_data_offs, decoded, interner })
+    }
+
+    pub fn len(&self) -> usize {
+        self.string_data_offs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.string_data_offs.is_empty()
+    }
+
+    /// Declared UTF-16 length and MUTF-8 data (up to the end of the file) of a string, None if the
+    /// index or its offset is out of bounds
+    fn string_data(&self, string_idx: u32) -> Option<(u64, &'a [u8])> {
+        let off = *self.string_data_offs.get(string_idx as usize)?;
+        let data = self.data.get(off as usize..)?;
+        let mut reader = Cursor::new(data);
+        let utf16_size = raw_dex::read_uleb128(&mut reader).ok()?;
+        Some((utf16_size.into(), &data[reader.position() as usize..]))
+    }
+
+    /// Undecoded MUTF-8 data of a string, to compare it with m_utf8::eq_str or m_utf8::cmp_str. The
+    /// data extends to the end of the file, the string ends at the first NUL.
+    pub fn raw(&self, string_idx: u32) -> Option<&'a [u8]> {
+        self.string_data(string_idx).map(|(_, data)| data)
+    }
+
+    /// Decoded string, invalid sequences are replaced like in DexFile. Decoded once, later calls
+    /// return the same string.
+    pub fn get(&self, string_idx: u32) -> Option<Arc<str>> {
+        let cell = self.decoded.get(string_idx as usize)?;
+        if let Some(string) = cell.get() {
+            return Some(string.clone());
+        }
+        let (utf16_size, data) = self.string_data(string_idx)?;
+        let decoded = m_utf8::to_string_lossy(data, utf16_size);
+        if !decoded.errors.is_empty() {
+            tracing::warn!(string_idx, errors = ?decoded.errors, "Invalid MUTF-8 in string data");
+        }
+        Some(cell.get_or_init(|| self.interner.intern(&decoded.string.value)).clone())
+    }
+
+    /// Index of the string equal to `value`. The string ids are sorted by their contents, so this
+    /// decodes no string and compares only O(log n) of them.
+    pub fn find(&self, value: &str) -> Option<u32> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let raw = self.raw(mid as u32)?;
+            match m_utf8::cmp_str(raw, value) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                // Invalid sequences compare equal to U+FFFD
+                Ordering::Equal => return Some(mid as u32).filter(|_| m_utf8::eq_str(raw, value)),
+            }
+        }
+        None
+    }
+
+    /// Index into the class_defs of the definition of the class with the descriptor (e.g. `Lcom/foo/Bar;`),
+    /// from the type ids and class definitions of the data without decoding any string
+    pub fn class_def_idx(&self, descriptor: &str) -> io::Result<Option<u32>> {
+        let string_idx = match self.find(descriptor) {
+            Some(string_idx) => string_idx,
+            None => return Ok(None),
+        };
+        let mut reader = Cursor::new(self.data);
+        let type_idx = match raw_dex::parse_type_ids(&self.header, &mut reader)?.binary_search(&string_idx) {
+            Ok(type_idx) => type_idx as u32,
+            Err(_) => return Ok(None),
+        };
+        let class_defs = raw_dex::parse_class_defs(&self.header, &mut reader)?;
+        Ok(class_defs.iter().position(|it| it.class_idx == type_idx).map(|it| it as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn decoded_on_access() {
+        let fixture = Fixture::new().string("hello").string("\u{1F600}").string("\u{ffff}");
+        let (data, dex) = (fixture.build(), fixture.parse());
+        let interner = Interner::new();
+        let pool = StringPool::with_interner(&data, interner.clone()).unwrap();
+        assert_eq!(pool.len(), dex.strings.len());
+        assert!(interner.is_empty());
+        for (idx, string) in dex.strings.iter().enumerate() {
+            assert_eq!(pool.get(idx as u32).as_deref(), Some(string.as_str()));
+            assert_eq!(pool.find(string), Some(idx as u32));
+        }
+        assert_eq!(pool.find("missing"), None);
+        assert_eq!(pool.get(pool.len() as u32), None);
+
+        // Shared by the pools of the same interner
+        let other = StringPool::with_interner(&data, interner.clone()).unwrap();
+        let hello = fixture.string_idx("hello").unwrap();
+        assert!(Arc::ptr_eq(&pool.get(hello).unwrap(), &other.get(hello).unwrap()));
+        assert_eq!(interner.len(), dex.strings.len());
+    }
+
+    #[test]
+    fn class_def_by_descriptor() {
+        let fixture = Fixture::new().class("Lcom/example/Other;").string("hello");
+        let (data, dex) = (fixture.build(), fixture.parse());
+        let pool = StringPool::new(&data).unwrap();
+        for (idx, class) in dex.classes().enumerate() {
+            assert_eq!(pool.class_def_idx(class.descriptor()).unwrap(), Some(idx as u32));
+        }
+        // Strings that are no type, types that are not defined
+        assert_eq!(pool.class_def_idx("hello").unwrap(), None);
+        assert_eq!(pool.class_def_idx("Ljava/lang/Object;").unwrap(), None);
+        assert_eq!(pool.class_def_idx("Lcom/example/Missing;").unwrap(), None);
+    }
+
+    #[test]
+    fn offset_out_of_bounds() {
+        let mut data = Fixture::new().build();
+        let string_ids_off = DexHeader::from_reader(&mut Cursor::new(&data)).unwrap().string_ids_off as usize;
+        data[string_ids_off..string_ids_off + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let pool = StringPool::new(&data).unwrap();
+        assert_eq!((pool.get(0), pool.raw(0)), (None, None));
+        assert_eq!(pool.get(1).as_deref(), Some(Fixture::new().parse().strings[1].as_str()));
+    }
+}
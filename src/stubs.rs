@@ -0,0 +1,227 @@
+use alloc::collections::BTreeMap;
+
+use crate::io::{self, Cursor, ErrorKind, Read};
+use crate::prelude::*;
+
+/*
+Stubs of the framework from the class files of android.jar (or any other jar), see
+* https://docs.oracle.com/javase/specs/jvms/se21/html/jvms-4.html
+Only the declarations are read: the names, flags and descriptors of the classes and their members, the
+attributes (code, generic signatures, annotations) are skipped. Class names are converted to type
+descriptors (`android/app/Activity` to `Landroid/app/Activity;`), member descriptors are the same in
+class files and dex files. The class files of stub jars are ASCII, the names are decoded as UTF-8 instead
+of the modified UTF-8 of class files.
+Framework dex files need no stubs, they are part of the ClassPath like the dex files of the app.
+ */
+
+const CLASS_MAGIC: u32 = 0xcafe_babe;
+
+/// A class declared by a class file
+#[derive(Debug, Clone)]
+pub struct StubClass {
+    pub descriptor: String,
+    pub access_flags: u32,
+    /// None for java.lang.Object (and module-info)
+    pub superclass: Option<String>,
+    pub interfaces: Vec<String>,
+    pub fields: Vec<StubMember>,
+    pub methods: Vec<StubMember>,
+}
+
+/// A field or method declared by a class file
+#[derive(Debug, Clone)]
+pub struct StubMember {
+    pub name: String,
+    /// Type descriptor of a field, signature of a method (e.g. `(ILjava/lang/String;)V`)
+    pub descriptor: String,
+    pub access_flags: u32,
+}
+
+/// Stub classes by descriptor
+#[derive(Debug, Default)]
+pub struct Stubs {
+    classes: BTreeMap<String, StubClass>,
+}
+
+enum Constant {
+    Utf8(String),
+    Class(u16),
+    Other,
+}
+
+fn read_u8<R: Read + ?Sized>(reader: &mut R) -> Result<u8, io::Error> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read + ?Sized>(reader: &mut R) -> Result<u16, io::Error> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<R: Read + ?Sized>(reader: &mut R) -> Result<u32, io::Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// Skips `len` bytes, reading past the end fails on the next read
+fn skip(reader: &mut Cursor<&[u8]>, len: usize) -> Result<(), io::Error> {
+    reader.set_position(reader.position() + len as u64);
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Type descriptor of a class name of the constant pool, array classes already are descriptors
+fn class_name_to_descriptor(name: &str) -> String {
+    if name.starts_with('[') {
+        name.to_owned()
+    } else {
+        format!("L{};", name)
+    }
+}
+
+struct ConstantPool(Vec<Constant>);
+
+impl ConstantPool {
+    fn read(reader: &mut Cursor<&[u8]>) -> Result<ConstantPool, io::Error> {
+        let count = read_u16(reader)? as usize;
+        // Index 0 is unused, longs and doubles take two entries
+        let mut constants = Vec::with_capacity(count);
+        constants.push(Constant::Other);
+        while constants.len() < count {
+            let tag = read_u8(reader)?;
+            let constant = match tag {
+                1 => {
+                    let len = read_u16(reader)? as usize;
+                    let mut bytes = vec![0u8; len];
+                    reader.read_exact(&mut bytes)?;
+                    Constant::Utf8(String::from_utf8_lossy(&bytes).into_owned())
+                }
+                7 => Constant::Class(read_u16(reader)?),
+                8 | 16 | 19 | 20 => { skip(reader, 2)?; Constant::Other }
+                15 => { skip(reader, 3)?; Constant::Other }
+                3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => { skip(reader, 4)?; Constant::Other }
+                5 | 6 => {
+                    skip(reader, 8)?;
+                    constants.push(Constant::Other);
+                    Constant::Other
+                }
+                _ => return Err(invalid(format!("Unknown constant pool tag {} at index {}", tag, constants.len()))),
+            };
+            constants.push(constant);
+        }
+        Ok(ConstantPool(constants))
+    }
+
+    fn utf8(&self, idx: u16) -> Result<&str, io::Error> {
+        match self.0.get(idx as usize) {
+            Some(Constant::Utf8(value)) => Ok(value),
+            _ => Err(invalid(format!("Constant pool index {} is not a Utf8 constant", idx))),
+        }
+    }
+
+    /// Descriptor of a Class constant, None for index 0
+    fn class(&self, idx: u16) -> Result<Option<String>, io::Error> {
+        if idx == 0 {
+            return Ok(None);
+        }
+        match self.0.get(idx as usize) {
+            Some(Constant::Class(name_idx)) => Ok(Some(class_name_to_descriptor(self.utf8(*name_idx)?))),
+            _ => Err(invalid(format!("Constant pool index {} is not a Class constant", idx))),
+        }
+    }
+}
+
+fn read_members(reader: &mut Cursor<&[u8]>, constants: &ConstantPool) -> Result<Vec<StubMember>, io::Error> {
+    let count = read_u16(reader)?;
+    let mut members = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let access_flags = read_u16(reader)? as u32;
+        let name = constants.utf8(read_u16(reader)?)?.to_owned();
+        let descriptor = constants.utf8(read_u16(reader)?)?.to_owned();
+        for _ in 0..read_u16(reader)? {
+            skip(reader, 2)?;
+            let len = read_u32(reader)? as usize;
+            skip(reader, len)?;
+        }
+        members.push(StubMember { name, descriptor, access_flags });
+    }
+    Ok(members)
+}
+
+impl StubClass {
+    /// Reads the declarations of a class file
+    pub fn parse(data: &[u8]) -> Result<StubClass, io::Error> {
+        let reader = &mut Cursor::new(data);
+        let magic = read_u32(reader)?;
+        if magic != CLASS_MAGIC {
+            return Err(invalid(format!("Not a class file, magic {:#x}", magic)));
+        }
+        skip(reader, 4)?;
+        let constants = ConstantPool::read(reader)?;
+        let access_flags = read_u16(reader)? as u32;
+        let descriptor = constants.class(read_u16(reader)?)?
+            .ok_or_else(|| invalid("Class file without this_class".to_owned()))?;
+        let superclass = constants.class(read_u16(reader)?)?;
+        let mut interfaces = Vec::new();
+        for _ in 0..read_u16(reader)? {
+            interfaces.extend(constants.class(read_u16(reader)?)?);
+        }
+        let fields = read_members(reader, &constants)?;
+        let methods = read_members(reader, &constants)?;
+        Ok(StubClass { descriptor, access_flags, superclass, interfaces, fields, methods })
+    }
+
+    pub fn find_method(&self, name: &str, signature: &str) -> Option<&StubMember> {
+        self.methods.iter().find(|it| it.name == name && it.descriptor == signature)
+    }
+
+    pub fn find_field(&self, name: &str, type_descriptor: &str) -> Option<&StubMember> {
+        self.fields.iter().find(|it| it.name == name && it.descriptor == type_descriptor)
+    }
+}
+
+impl Stubs {
+    pub fn new() -> Stubs {
+        Stubs::default()
+    }
+
+    /// Adds the class, a class with the same descriptor that was added before is kept
+    pub fn add(&mut self, class: StubClass) {
+        self.classes.entry(class.descriptor.clone()).or_insert(class);
+    }
+
+    /// Reads the class files of a jar (e.g. `platforms/android-34/android.jar` of the SDK)
+    #[cfg(feature = "apk")]
+    pub fn from_jar<R: std::io::Read + std::io::Seek>(reader: R) -> Result<Stubs, io::Error> {
+        let mut stubs = Stubs::new();
+        for (name, data) in crate::apk::class_entries(reader)? {
+            let class = StubClass::parse(&data)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+            stubs.add(class);
+        }
+        Ok(stubs)
+    }
+
+    pub fn get(&self, descriptor: &str) -> Option<&StubClass> {
+        self.classes.get(descriptor)
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item=&StubClass> {
+        self.classes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Write;
+
+/// Output formats of tables
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TableFormat {
+    /// Columns aligned with spaces, for reading in a terminal
+    Text,
+    /// RFC 4180 comma separated values with a header row
+    Csv,
+    /// Tab separated values with a header row, tabs and line breaks in values are escaped
+    Tsv,
+    /// GitHub flavored Markdown table, for pasting into issue trackers and wikis
+    Markdown,
+}
+
+/// Tabular listing with a fixed set of columns. The columns of each listing are part of its
+/// interface, new columns are only ever appended.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&'static str]) -> Table {
+        Table { columns: columns.to_vec(), rows: Vec::new() }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    pub fn write(&self, format: TableFormat, out: &mut dyn Write) -> std::io::Result<()> {
+        match format {
+            TableFormat::Text => self.write_text(out),
+            TableFormat::Csv => self.write_separated(out, ',', csv_field),
+            TableFormat::Tsv => self.write_separated(out, '\t', tsv_field),
+            TableFormat::Markdown => self.write_markdown(out),
+        }
+    }
+
+    fn write_text(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let header: Vec<String> = self.columns.iter().map(|it| it.to_string()).collect();
+        // Line breaks and tabs would break the alignment
+        let rows: Vec<Vec<String>> = self.rows.iter().map(|row| row.iter().map(|it| tsv_field(it)).collect()).collect();
+        let mut widths: Vec<usize> = header.iter().map(|it| it.chars().count()).collect();
+        for row in &rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        for row in std::iter::once(&header).chain(&rows) {
+            let mut line = String::new();
+            for (i, (value, width)) in row.iter().zip(&widths).enumerate() {
+                if i + 1 == row.len() {
+                    // No trailing spaces after the last column
+                    line.push_str(value);
+                } else {
+                    line.push_str(&format!("{:<width$}  ", value, width = width));
+                }
+            }
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn write_separated(&self, out: &mut dyn Write, separator: char, field: fn(&str) -> String) -> std::io::Result<()> {
+        let separator = separator.to_string();
+        let header: Vec<String> = self.columns.iter().map(|it| field(it)).collect();
+        writeln!(out, "{}", header.join(&separator))?;
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().map(|it| field(it)).collect();
+            writeln!(out, "{}", fields.join(&separator))?;
+        }
+        Ok(())
+    }
+
+    fn write_markdown(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        // Column names are snake case, underscores within words are not emphasis
+        writeln!(out, "| {} |", self.columns.join(" | "))?;
+        writeln!(out, "|{}", " --- |".repeat(self.columns.len()))?;
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().map(|it| markdown_field(it)).collect();
+            writeln!(out, "| {} |", fields.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn tsv_field(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Characters with a meaning in Markdown, escaped so values like `<init>` or `a_b_c` are shown as is
+const MARKDOWN_SPECIAL: &[char] = &['\\', '`', '*', '_', '<', '>', '|', '[', ']', '~', '&', '#'];
+
+fn markdown_field(value: &str) -> String {
+    let mut field = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' => field.push_str("<br>"),
+            '\r' => {}
+            _ if MARKDOWN_SPECIAL.contains(&c) => {
+                field.push('\\');
+                field.push(c);
+            }
+            _ => field.push(c),
+        }
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&["index", "value"]);
+        table.push(vec!["0".to_owned(), "plain".to_owned()]);
+        table.push(vec!["1".to_owned(), "a,\"b\"\tc\nd".to_owned()]);
+        table
+    }
+
+    fn written(format: TableFormat) -> String {
+        let mut out = Vec::new();
+        table().write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn separated() {
+        assert_eq!(written(TableFormat::Csv), "index,value\n0,plain\n1,\"a,\"\"b\"\"\tc\nd\"\n");
+        assert_eq!(written(TableFormat::Tsv), "index\tvalue\n0\tplain\n1\ta,\"b\"\\tc\\nd\n");
+        assert_eq!(written(TableFormat::Text), "index  value\n0      plain\n1      a,\"b\"\\tc\\nd\n");
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(written(TableFormat::Markdown), "| index | value |\n| --- | --- |\n| 0 | plain |\n| 1 | a,\"b\"\tc<br>d |\n");
+        let mut table = Table::new(&["method", "flags"]);
+        table.push(vec!["La/b_c;-><init>()V".to_owned(), "a|b *[x]* `~&#\\".to_owned()]);
+        let mut out = Vec::new();
+        table.write(TableFormat::Markdown, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "| method | flags |\n",
+            "| --- | --- |\n",
+            "| La/b\\_c;-\\>\\<init\\>()V | a\\|b \\*\\[x\\]\\* \\`\\~\\&\\#\\\\ |\n",
+        ));
+    }
+}
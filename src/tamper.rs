@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use crate::dex_file::{ByteSpan, DexFile};
+use crate::raw_dex::DexHeader;
+
+/*
+Evidence that a dex file was modified after it was compiled, e.g. by patching instructions or strings
+in place or by appending data. d8 writes a valid checksum and signature, the exact file size, a header
+of 0x70 bytes and no bytes outside of the items listed by the map list (but the alignment padding
+between them), so each deviation raises the score. Tools that repackage apps may fix the checksum and
+signature, a low score is no proof of an unmodified file.
+ */
+
+/// Size of the header of all dex versions
+const HEADER_SIZE: u32 = 0x70;
+/// Largest alignment padding between items, longer or non-zero gaps are not written by compilers
+const MAX_PADDING: usize = 3;
+
+/// Result of one check of the tamper report
+#[derive(Debug, Clone)]
+pub struct TamperCheck {
+    pub name: &'static str,
+    /// None if the check could not be performed (the signature without the index feature)
+    pub passed: Option<bool>,
+    /// Added to the score if the check failed
+    pub weight: u32,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TamperReport {
+    pub checks: Vec<TamperCheck>,
+}
+
+impl TamperReport {
+    /// Sum of the weights of the failed checks, from 0 to 100
+    pub fn score(&self) -> u32 {
+        self.checks.iter().filter(|it| it.passed == Some(false)).map(|it| it.weight).sum()
+    }
+
+    /// How likely the file was modified after compilation, by the score
+    pub fn verdict(&self) -> &'static str {
+        match self.score() {
+            0..=19 => "unlikely",
+            20..=49 => "possible",
+            _ => "likely",
+        }
+    }
+}
+
+/// Ranges of the file covered by the header, the id tables, the map list, the link data and the
+/// parsed items of the data section, and by the sections listed in the map list that are not parsed
+fn covered(dex: &DexFile, data: &[u8]) -> Vec<Range<usize>> {
+    let header = &dex.header;
+    let mut covered: Vec<Range<usize>> = vec![
+        dex.header_span().range(),
+        dex.map_list_span().range(),
+        ByteSpan { offset: header.link_off, len: header.link_size }.range(),
+    ];
+    let id_tables = [
+        (header.string_ids_off, header.string_ids_size, 4),
+        (header.type_ids_off, header.type_ids_size, 4),
+        (header.proto_ids_off, header.proto_ids_size, 12),
+        (header.field_ids_off, header.field_ids_size, 8),
+        (header.method_ids_off, header.method_ids_size, 8),
+        (header.class_defs_off, header.class_defs_size, 32),
+    ];
+    for (off, size, entry_size) in id_tables {
+        covered.push(off as usize..off as usize + size as usize * entry_size);
+    }
+    covered.extend(dex.item_sizes.iter().map(|(off, len)| ByteSpan { offset: *off, len: *len }.range()));
+
+    let mut offsets: Vec<usize> = dex.map_list.iter().map(|it| it.offset as usize).collect();
+    offsets.sort_unstable();
+    for item in &dex.map_list {
+        let start = item.offset as usize;
+        let next = offsets.iter().copied().find(|it| *it > start).unwrap_or(data.len());
+        match item.item_type {
+            // call_site_id_item and method_handle_item
+            0x0007 => covered.push(start..start + 4 * item.size as usize),
+            0x0008 => covered.push(start..start + 8 * item.size as usize),
+            // hiddenapi_class_data_item and unknown sections up to the next section
+            _ if item.item_type == 0xf000 || item.type_name().is_none() => covered.push(start..next),
+            _ => {}
+        }
+    }
+    covered
+}
+
+/// Ranges of the file not covered by any item that are longer than alignment padding or contain
+/// non-zero bytes, e.g. orphaned items or appended data, in file order
+pub fn coverage_gaps(dex: &DexFile, data: &[u8]) -> Vec<Range<usize>> {
+    let mut covered = covered(dex, data);
+    covered.sort_by_key(|it| it.start);
+    let mut gaps = Vec::new();
+    let mut position = 0;
+    for range in covered.into_iter().chain(Some(data.len()..data.len())) {
+        let start = range.start.min(data.len());
+        if start > position {
+            let gap = position..start;
+            if gap.len() > MAX_PADDING || data[gap.clone()].iter().any(|it| *it != 0) {
+                gaps.push(gap);
+            }
+        }
+        position = position.max(range.end.min(data.len()));
+    }
+    gaps
+}
+
+/// Checks the dex file `data` for signs of modification after compilation
+pub fn tamper_report(dex: &DexFile, data: &[u8]) -> TamperReport {
+    let header = &dex.header;
+    let mut checks = Vec::new();
+
+    let checksum = DexHeader::compute_checksum(data);
+    checks.push(TamperCheck {
+        name: "checksum",
+        passed: Some(checksum == header.checksum),
+        weight: 30,
+        detail: format!("declared 0x{:08x}, computed 0x{:08x}", header.checksum, checksum),
+    });
+
+    #[cfg(feature = "index")]
+    let signature = {
+        let computed = sha1_smol::Sha1::from(data.get(DexHeader::SIGNATURE_END..).unwrap_or_default()).digest().bytes();
+        let hex = |bytes: &[u8]| bytes.iter().map(|it| format!("{:02x}", it)).collect::<String>();
+        (Some(computed == header.signature), format!("declared {}, computed {}", hex(&header.signature), hex(&computed)))
+    };
+    #[cfg(not(feature = "index"))]
+    let signature = (None, "SHA-1 is only available with the index feature".to_owned());
+    checks.push(TamperCheck { name: "signature", passed: signature.0, weight: 20, detail: signature.1 });
+
+    checks.push(TamperCheck {
+        name: "file_size",
+        passed: Some(header.file_size as usize == data.len()),
+        weight: 20,
+        detail: format!("declared {}, file has {} bytes", header.file_size, data.len()),
+    });
+
+    let gaps = coverage_gaps(dex, data);
+    let detail = match gaps.first() {
+        Some(first) => format!("{} bytes not covered by the map list, first of {} ranges at 0x{:08x}",
+                               gaps.iter().map(|it| it.len()).sum::<usize>(), gaps.len(), first.start),
+        None => "all bytes are covered by the map list".to_owned(),
+    };
+    checks.push(TamperCheck { name: "map_coverage", passed: Some(gaps.is_empty()), weight: 20, detail });
+
+    checks.push(TamperCheck {
+        name: "header_size",
+        passed: Some(header.header_size == HEADER_SIZE),
+        weight: 10,
+        detail: format!("0x{:x}, compilers write 0x{:x}", header.header_size, HEADER_SIZE),
+    });
+    TamperReport { checks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// Result of each check, the signature is only checked with the index feature
+    fn results(dex: &DexFile, data: &[u8]) -> Vec<(&'static str, Option<bool>)> {
+        tamper_report(dex, data).checks.iter().map(|it| (it.name, it.passed)).collect()
+    }
+
+    fn signature(valid: bool) -> Option<bool> {
+        if cfg!(feature = "index") { Some(valid) } else { None }
+    }
+
+    fn fix_checksum(data: &mut [u8]) {
+        let checksum = DexHeader::compute_checksum(data);
+        data[8..DexHeader::CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn compiled() {
+        let fixture = Fixture::new();
+        let data = fixture.build();
+        let dex = fixture.parse();
+        let report = tamper_report(&dex, &data);
+        assert_eq!(results(&dex, &data), [
+            ("checksum", Some(true)), ("signature", signature(true)), ("file_size", Some(true)),
+            ("map_coverage", Some(true)), ("header_size", Some(true)),
+        ]);
+        assert_eq!((report.score(), report.verdict()), (0, "unlikely"));
+    }
+
+    #[test]
+    fn appended() {
+        let dex = Fixture::new().parse();
+        let mut data = Fixture::new().build();
+        let end = data.len();
+        data.extend([0xff; 16]);
+        assert_eq!(coverage_gaps(&dex, &data), vec![end..end + 16]);
+        assert_eq!(results(&dex, &data), [
+            ("checksum", Some(false)), ("signature", signature(false)), ("file_size", Some(false)),
+            ("map_coverage", Some(false)), ("header_size", Some(true)),
+        ]);
+        let report = tamper_report(&dex, &data);
+        assert_eq!(report.checks[3].detail, format!("16 bytes not covered by the map list, first of 1 ranges at 0x{:08x}", end));
+        assert_eq!(report.verdict(), "likely");
+
+        // Covering the appended data by the file size and checksum still leaves the gap
+        let file_size = data.len() as u32;
+        data[32..36].copy_from_slice(&file_size.to_le_bytes());
+        fix_checksum(&mut data);
+        let dex = DexFile::from_bytes(&data).unwrap();
+        assert_eq!(results(&dex, &data), [
+            ("checksum", Some(true)), ("signature", signature(false)), ("file_size", Some(true)),
+            ("map_coverage", Some(false)), ("header_size", Some(true)),
+        ]);
+        assert_eq!(tamper_report(&dex, &data).score(), if cfg!(feature = "index") { 40 } else { 20 });
+    }
+
+    #[test]
+    fn patched_in_place() {
+        let dex = Fixture::new().parse();
+        let mut data = Fixture::new().build();
+        let run = dex.string_data_offs[dex.strings.iter().position(|it| it == "run").unwrap()] as usize;
+        // The string data item is the uleb128 size followed by the MUTF-8 bytes
+        data[run + 3] = b'm';
+        assert_eq!(tamper_report(&dex, &data).checks[0].passed, Some(false));
+        fix_checksum(&mut data);
+        let dex = DexFile::from_bytes(&data).unwrap();
+        let report = tamper_report(&dex, &data);
+        assert_eq!(results(&dex, &data), [
+            ("checksum", Some(true)), ("signature", signature(false)), ("file_size", Some(true)),
+            ("map_coverage", Some(true)), ("header_size", Some(true)),
+        ]);
+        assert_eq!(report.verdict(), if cfg!(feature = "index") { "possible" } else { "unlikely" });
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cfg::ControlFlowGraph;
+use crate::dex_file::{DexFile, ACC_STATIC};
+use crate::instructions::{Format, Instruction};
+use crate::raw_dex::CodeItem;
+
+/*
+Simplified version of the register type inference of the ART verifier. Registers are tracked by
+category (constants, int, float, long, double, references and uninitialized references) over the
+control flow graph, without a class hierarchy: references of different classes merge to a reference
+of unknown class. Uses of registers that do not fit their instruction are reported, e.g. the int
+registers passed as objects or the halves of longs read as ints that protectors use to break tools.
+ */
+
+/// Type of a register before an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegType<'a> {
+    /// Not written on any path
+    Undefined,
+    /// Written with incompatible types on different paths
+    Conflict,
+    /// Type could not be determined (e.g. the result of invoke-custom), accepted by every use
+    Unknown,
+    /// Constant 0, usable as int, float or null
+    Zero,
+    /// 32-bit constant, usable as int or float
+    Constant,
+    /// int, boolean, byte, short or char
+    Integer,
+    Float,
+    /// First register of a 64-bit constant, usable as long or double
+    WideConstant,
+    /// First register of a long
+    Long,
+    /// First register of a double
+    Double,
+    /// Second register of a long, double or 64-bit constant
+    WideHigh,
+    /// Reference of the class with this descriptor (None if unknown, e.g. after merging classes)
+    Reference(Option<&'a str>),
+    /// Result of new-instance (with its pc) or `this` in a constructor (pc None) before the constructor
+    /// is invoked
+    Uninitialized { descriptor: &'a str, pc: Option<usize> },
+}
+
+impl<'a> RegType<'a> {
+    fn is_wide(self) -> bool {
+        matches!(self, RegType::WideConstant | RegType::Long | RegType::Double)
+    }
+
+    /// The type of a register that is `self` on one path and `other` on another
+    fn merge(self, other: RegType<'a>) -> RegType<'a> {
+        use RegType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Unknown, _) | (_, Unknown) => Unknown,
+            (Zero, Constant) | (Constant, Zero) => Constant,
+            (Zero | Constant, Integer) | (Integer, Zero | Constant) => Integer,
+            (Zero | Constant, Float) | (Float, Zero | Constant) => Float,
+            (Zero, Reference(class)) | (Reference(class), Zero) => Reference(class),
+            (Reference(_), Reference(_)) => Reference(None),
+            (WideConstant, Long) | (Long, WideConstant) => Long,
+            (WideConstant, Double) | (Double, WideConstant) => Double,
+            _ => Conflict,
+        }
+    }
+}
+
+impl fmt::Display for RegType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegType::Undefined => f.write_str("undefined"),
+            RegType::Conflict => f.write_str("conflict"),
+            RegType::Unknown => f.write_str("unknown"),
+            RegType::Zero => f.write_str("zero"),
+            RegType::Constant => f.write_str("constant"),
+            RegType::Integer => f.write_str("int"),
+            RegType::Float => f.write_str("float"),
+            RegType::WideConstant => f.write_str("wide constant"),
+            RegType::Long => f.write_str("long"),
+            RegType::Double => f.write_str("double"),
+            RegType::WideHigh => f.write_str("second half of a wide value"),
+            RegType::Reference(Some(descriptor)) => f.write_str(descriptor),
+            RegType::Reference(None) => f.write_str("reference"),
+            RegType::Uninitialized { descriptor, .. } => write!(f, "uninitialized {}", descriptor),
+        }
+    }
+}
+
+/// Category of the value an instruction expects in a register
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Int,
+    Float,
+    /// int or float (e.g. move, aput)
+    Narrow,
+    Long,
+    Double,
+    /// long or double (e.g. move-wide)
+    Wide,
+    /// Initialized reference or null
+    Object,
+    /// Reference or null, initialized or not (move-object and the receiver of constructors)
+    AnyObject,
+    /// int or reference (if-eq, if-eqz, ...)
+    IntOrObject,
+}
+
+impl Expected {
+    fn accepts(self, found: RegType) -> bool {
+        use RegType::*;
+        match found {
+            Unknown => true,
+            Undefined | Conflict | WideHigh => false,
+            Zero => !matches!(self, Expected::Long | Expected::Double | Expected::Wide),
+            Constant => matches!(self, Expected::Int | Expected::Float | Expected::Narrow | Expected::IntOrObject),
+            Integer => matches!(self, Expected::Int | Expected::Narrow | Expected::IntOrObject),
+            Float => matches!(self, Expected::Float | Expected::Narrow),
+            WideConstant => matches!(self, Expected::Long | Expected::Double | Expected::Wide),
+            Long => matches!(self, Expected::Long | Expected::Wide),
+            Double => matches!(self, Expected::Double | Expected::Wide),
+            Reference(_) => matches!(self, Expected::Object | Expected::AnyObject | Expected::IntOrObject),
+            Uninitialized { .. } => self == Expected::AnyObject,
+        }
+    }
+
+    fn is_wide(self) -> bool {
+        matches!(self, Expected::Long | Expected::Double | Expected::Wide)
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Expected::Int => "int",
+            Expected::Float => "float",
+            Expected::Narrow => "int or float",
+            Expected::Long => "long",
+            Expected::Double => "double",
+            Expected::Wide => "long or double",
+            Expected::Object => "reference",
+            Expected::AnyObject => "reference",
+            Expected::IntOrObject => "int or reference",
+        })
+    }
+}
+
+/// A register whose type does not fit its use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeProblem<'a> {
+    pub pc: usize,
+    pub register: u32,
+    pub expected: Expected,
+    pub found: RegType<'a>,
+}
+
+impl fmt::Display for TypeProblem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{} is {} but used as {}", self.register, self.found, self.expected)
+    }
+}
+
+/// Inferred types of the registers of a method
+#[derive(Debug, Clone)]
+pub struct RegisterTypes<'a> {
+    /// Types of all registers before each reachable instruction, by pc
+    pub before: BTreeMap<usize, Vec<RegType<'a>>>,
+    /// Uses of registers not fitting their instruction, in the order of their pc
+    pub problems: Vec<TypeProblem<'a>>,
+}
+
+impl<'a> RegisterTypes<'a> {
+    /// Type of `register` before the instruction at `pc`, None if the instruction is unreachable
+    pub fn at(&self, pc: usize, register: u32) -> Option<RegType<'a>> {
+        self.before.get(&pc).and_then(|it| it.get(register as usize)).copied()
+    }
+}
+
+/// Type of a register holding a value of the type `descriptor` (the first register for wide types)
+fn from_descriptor(descriptor: &str) -> RegType<'_> {
+    match descriptor.as_bytes().first() {
+        Some(b'Z' | b'B' | b'S' | b'C' | b'I') => RegType::Integer,
+        Some(b'F') => RegType::Float,
+        Some(b'J') => RegType::Long,
+        Some(b'D') => RegType::Double,
+        Some(b'L' | b'[') => RegType::Reference(Some(descriptor)),
+        _ => RegType::Unknown,
+    }
+}
+
+/// Expected category of a value of the type `descriptor`
+fn expected_for(descriptor: &str) -> Expected {
+    match descriptor.as_bytes().first() {
+        Some(b'F') => Expected::Float,
+        Some(b'J') => Expected::Long,
+        Some(b'D') => Expected::Double,
+        Some(b'L' | b'[') => Expected::Object,
+        _ => Expected::Int,
+    }
+}
+
+/// Category of the type name used in opcode names (e.g. the `long` of add-long or int-to-long)
+fn from_name(name: &str) -> (Expected, RegType<'static>) {
+    match name {
+        "long" => (Expected::Long, RegType::Long),
+        "float" => (Expected::Float, RegType::Float),
+        "double" => (Expected::Double, RegType::Double),
+        _ => (Expected::Int, RegType::Integer),
+    }
+}
+
+/// Registers of the current path through a block, and the result of the last invoke
+#[derive(Debug, Clone, PartialEq)]
+struct State<'a> {
+    regs: Vec<RegType<'a>>,
+    result: RegType<'a>,
+}
+
+impl<'a> State<'a> {
+    fn merge(&mut self, other: &State<'a>) -> bool {
+        let merged = State {
+            regs: self.regs.iter().zip(&other.regs).map(|(a, b)| a.merge(*b)).collect(),
+            result: self.result.merge(other.result),
+        };
+        let changed = merged != *self;
+        *self = merged;
+        changed
+    }
+
+    fn get(&self, register: u32) -> RegType<'a> {
+        self.regs.get(register as usize).copied().unwrap_or(RegType::Undefined)
+    }
+
+    /// Writes a register, invalidating wide values it overwrites half of
+    fn set(&mut self, register: u32, value: RegType<'a>) {
+        let register = register as usize;
+        if register >= self.regs.len() {
+            return;
+        }
+        if register > 0 && self.regs[register - 1].is_wide() {
+            self.regs[register - 1] = RegType::Conflict;
+        }
+        if self.regs[register].is_wide() && self.regs.get(register + 1) == Some(&RegType::WideHigh) {
+            self.regs[register + 1] = RegType::Conflict;
+        }
+        self.regs[register] = value;
+        if value.is_wide() {
+            if let Some(high) = self.regs.get_mut(register + 1) {
+                let overwritten = *high;
+                *high = RegType::WideHigh;
+                if overwritten.is_wide() {
+                    if let Some(next) = self.regs.get_mut(register + 2) {
+                        *next = RegType::Conflict;
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Inference<'a, 'p> {
+    dex: &'a DexFile,
+    return_type: &'a str,
+    problems: Option<&'p mut Vec<TypeProblem<'a>>>,
+}
+
+impl<'a> Inference<'a, '_> {
+    fn check(&mut self, state: &State<'a>, pc: usize, register: u32, expected: Expected) {
+        let found = state.get(register);
+        let fits = expected.accepts(found) && (!expected.is_wide() || found == RegType::Unknown ||
+            state.get(register + 1) == RegType::WideHigh);
+        if !fits {
+            if let Some(problems) = self.problems.as_mut() {
+                problems.push(TypeProblem { pc, register, expected, found });
+            }
+        }
+    }
+
+    /// Argument registers of an invoke or filled-new-array instruction
+    fn arguments(insn: &Instruction) -> Vec<u32> {
+        match insn.format() {
+            Format::F35c | Format::F45cc => insn.args.iter().take(insn.a as usize).map(|it| *it as u32).collect(),
+            _ => (insn.c..insn.c + insn.a).collect(),
+        }
+    }
+
+    fn invoke(&mut self, state: &mut State<'a>, pc: usize, insn: &Instruction) {
+        let dex = self.dex;
+        let method_idx = insn.b;
+        let method_id = match dex.method_ids.get(method_idx as usize) {
+            Some(method_id) => method_id,
+            None => {
+                state.result = RegType::Unknown;
+                return;
+            }
+        };
+        let mut registers = Inference::arguments(insn).into_iter();
+        let is_static = matches!(insn.opcode, 0x71 | 0x77);
+        let is_constructor = !is_static && dex.method_name(method_idx) == "<init>";
+        if !is_static {
+            if let Some(receiver) = registers.next() {
+                let expected = if is_constructor { Expected::AnyObject } else { Expected::Object };
+                self.check(state, pc, receiver, expected);
+                // The constructor initializes every copy of the reference
+                if let found @ RegType::Uninitialized { descriptor, .. } = state.get(receiver) {
+                    if is_constructor {
+                        for reg in state.regs.iter_mut().filter(|it| **it == found) {
+                            *reg = RegType::Reference(Some(descriptor));
+                        }
+                    }
+                }
+            }
+        }
+        for descriptor in dex.proto_parameters(method_id.proto_idx as u32) {
+            if let Some(register) = registers.next() {
+                self.check(state, pc, register, expected_for(descriptor));
+            }
+            if descriptor == "J" || descriptor == "D" {
+                registers.next();
+            }
+        }
+        let return_type = dex.type_descriptor(dex.proto_ids[method_id.proto_idx as usize].return_type_idx);
+        state.result = if return_type == "V" { RegType::Undefined } else { from_descriptor(return_type) };
+    }
+
+    /// Applies an instruction to the state before it, checking the registers it reads
+    fn transfer(&mut self, state: &mut State<'a>, pc: usize, insn: &Instruction) {
+        let dex = self.dex;
+        let (a, b, c) = (insn.a, insn.b, insn.c);
+        let name = insn.name();
+        let result = state.result;
+        state.result = RegType::Undefined;
+        match insn.opcode {
+            0x01..=0x03 => {
+                self.check(state, pc, b, Expected::Narrow);
+                state.set(a, state.get(b));
+            }
+            0x04..=0x06 => {
+                self.check(state, pc, b, Expected::Wide);
+                state.set(a, state.get(b));
+            }
+            0x07..=0x09 => {
+                self.check(state, pc, b, Expected::AnyObject);
+                state.set(a, state.get(b));
+            }
+            0x0a..=0x0c => state.set(a, result),
+            0x0d => state.set(a, RegType::Reference(None)),
+            0x0f..=0x11 => self.check(state, pc, a, expected_for(self.return_type)),
+            0x12..=0x15 => state.set(a, if b == 0 { RegType::Zero } else { RegType::Constant }),
+            0x16..=0x19 => state.set(a, RegType::WideConstant),
+            0x1a | 0x1b => state.set(a, RegType::Reference(Some("Ljava/lang/String;"))),
+            0x1c => state.set(a, RegType::Reference(Some("Ljava/lang/Class;"))),
+            0x1d | 0x1e | 0x26 | 0x27 => self.check(state, pc, a, Expected::Object),
+            0x1f => {
+                self.check(state, pc, a, Expected::Object);
+                state.set(a, RegType::Reference(Some(dex.type_descriptor(b))));
+            }
+            0x20 | 0x21 => {
+                self.check(state, pc, b, Expected::Object);
+                state.set(a, RegType::Integer);
+            }
+            0x22 => state.set(a, RegType::Uninitialized { descriptor: dex.type_descriptor(b), pc: Some(pc) }),
+            0x23 => {
+                self.check(state, pc, b, Expected::Int);
+                state.set(a, RegType::Reference(Some(dex.type_descriptor(c))));
+            }
+            0x24 | 0x25 => {
+                let descriptor = dex.type_descriptor(b);
+                let element = expected_for(descriptor.strip_prefix('[').unwrap_or("I"));
+                for register in Inference::arguments(insn) {
+                    self.check(state, pc, register, element);
+                }
+                state.result = RegType::Reference(Some(descriptor));
+            }
+            0x2b | 0x2c => self.check(state, pc, a, Expected::Int),
+            0x2d..=0x31 => {
+                let expected = match insn.opcode {
+                    0x2d | 0x2e => Expected::Float,
+                    0x2f | 0x30 => Expected::Double,
+                    _ => Expected::Long,
+                };
+                self.check(state, pc, b, expected);
+                self.check(state, pc, c, expected);
+                state.set(a, RegType::Integer);
+            }
+            // if-eq and if-ne compare ints or references, the other comparisons only ints
+            0x32 | 0x33 => {
+                let expected = if matches!(state.get(a), RegType::Reference(_)) { Expected::Object } else { Expected::IntOrObject };
+                self.check(state, pc, a, Expected::IntOrObject);
+                self.check(state, pc, b, expected);
+            }
+            0x34..=0x37 => {
+                self.check(state, pc, a, Expected::Int);
+                self.check(state, pc, b, Expected::Int);
+            }
+            0x38 | 0x39 => self.check(state, pc, a, Expected::IntOrObject),
+            0x3a..=0x3d => self.check(state, pc, a, Expected::Int),
+            0x44..=0x4a => {
+                let array = state.get(b);
+                self.check(state, pc, b, Expected::Object);
+                self.check(state, pc, c, Expected::Int);
+                let element = match array {
+                    RegType::Reference(Some(descriptor)) => descriptor.strip_prefix('['),
+                    _ => None,
+                };
+                let value = match (insn.opcode, element) {
+                    (0x44..=0x46, Some(element)) => from_descriptor(element),
+                    (0x44, None) => RegType::Constant,
+                    (0x45, None) => RegType::WideConstant,
+                    (0x46, None) => RegType::Reference(None),
+                    _ => RegType::Integer,
+                };
+                state.set(a, value);
+            }
+            0x4b..=0x51 => {
+                let expected = match insn.opcode {
+                    0x4b => Expected::Narrow,
+                    0x4c => Expected::Wide,
+                    0x4d => Expected::Object,
+                    _ => Expected::Int,
+                };
+                self.check(state, pc, a, expected);
+                self.check(state, pc, b, Expected::Object);
+                self.check(state, pc, c, Expected::Int);
+            }
+            0x52..=0x58 => {
+                self.check(state, pc, b, Expected::Object);
+                state.set(a, from_descriptor(dex.field_type(c)));
+            }
+            0x59..=0x5f => {
+                self.check(state, pc, a, expected_for(dex.field_type(c)));
+                self.check(state, pc, b, Expected::Object);
+            }
+            0x60..=0x66 => state.set(a, from_descriptor(dex.field_type(b))),
+            0x67..=0x6d => self.check(state, pc, a, expected_for(dex.field_type(b))),
+            0x6e..=0x72 | 0x74..=0x78 => self.invoke(state, pc, insn),
+            0x7b..=0x8f => {
+                // neg-<type>, not-<type> or <from>-to-<to>
+                let mut parts = name.split('-');
+                let (first, last) = (parts.next().unwrap_or_default(), parts.next_back().unwrap_or_default());
+                let from = if first == "neg" || first == "not" { last } else { first };
+                self.check(state, pc, b, from_name(from).0);
+                let to = match last {
+                    "byte" | "char" | "short" => RegType::Integer,
+                    _ => from_name(last).1,
+                };
+                state.set(a, to);
+            }
+            0x90..=0xcf => {
+                // <operation>-<type>[/2addr], shifts of longs take an int distance
+                let kind = name.split(['-', '/']).nth(1).unwrap_or_default();
+                let (expected, value) = from_name(kind);
+                let shift = name.starts_with("sh") || name.starts_with("ushr");
+                let distance = if shift { Expected::Int } else { expected };
+                if insn.opcode < 0xb0 {
+                    self.check(state, pc, b, expected);
+                    self.check(state, pc, c, distance);
+                } else {
+                    self.check(state, pc, a, expected);
+                    self.check(state, pc, b, distance);
+                }
+                state.set(a, value);
+            }
+            0xd0..=0xe2 => {
+                self.check(state, pc, b, Expected::Int);
+                state.set(a, RegType::Integer);
+            }
+            0xfa | 0xfb => {
+                // The return type is the one of the prototype operand
+                state.result = match dex.proto_ids.get(insn.h as usize) {
+                    Some(proto) => from_descriptor(dex.type_descriptor(proto.return_type_idx)),
+                    None => RegType::Unknown,
+                };
+            }
+            0xfc | 0xfd => state.result = RegType::Unknown,
+            0xfe => state.set(a, RegType::Reference(Some("Ljava/lang/invoke/MethodHandle;"))),
+            0xff => state.set(a, RegType::Reference(Some("Ljava/lang/invoke/MethodType;"))),
+            _ => {}
+        }
+    }
+}
+
+/// Registers at the entry of a method: parameters typed by the prototype, all others undefined
+fn entry_state<'a>(dex: &'a DexFile, method_idx: u32, access_flags: u64, code: &CodeItem) -> State<'a> {
+    let mut state = State { regs: vec![RegType::Undefined; code.registers_size as usize], result: RegType::Undefined };
+    let mut reg = code.registers_size.saturating_sub(code.ins_size) as u32;
+    if access_flags & ACC_STATIC == 0 {
+        let class = dex.method_class(method_idx);
+        let this = if dex.method_name(method_idx) == "<init>" && class != "Ljava/lang/Object;" {
+            RegType::Uninitialized { descriptor: class, pc: None }
+        } else {
+            RegType::Reference(Some(class))
+        };
+        state.set(reg, this);
+        reg += 1;
+    }
+    let proto_idx = dex.method_ids[method_idx as usize].proto_idx as u32;
+    for descriptor in dex.proto_parameters(proto_idx) {
+        state.set(reg, from_descriptor(descriptor));
+        reg += if descriptor == "J" || descriptor == "D" { 2 } else { 1 };
+    }
+    state
+}
+
+/// Infers the types of the registers of a method over its control flow graph
+pub fn infer<'a>(dex: &'a DexFile, method_idx: u32, access_flags: u64, code: &'a CodeItem, cfg: &ControlFlowGraph) -> RegisterTypes<'a> {
+    let proto_idx = dex.method_ids[method_idx as usize].proto_idx as usize;
+    let return_type = dex.type_descriptor(dex.proto_ids[proto_idx].return_type_idx);
+    let mut inference = Inference { dex, return_type, problems: None };
+    let mut entries: Vec<Option<State>> = vec![None; cfg.blocks.len()];
+    if let Some(entry) = entries.first_mut() {
+        *entry = Some(entry_state(dex, method_idx, access_flags, code));
+    }
+
+    // Iterate to a fixed point, then record the types and problems of the final states only
+    // Code without instructions has no blocks
+    let mut worklist = if entries.is_empty() { Vec::new() } else { vec![0] };
+    while let Some(block) = worklist.pop() {
+        let mut state = match entries[block].clone() {
+            Some(state) => state,
+            None => continue,
+        };
+        let mut handler_state: Option<State> = None;
+        for (pc, insn) in cfg.block_instructions(block) {
+            merge_into(&mut handler_state, &state);
+            inference.transfer(&mut state, *pc, insn);
+        }
+        let successors = &cfg.blocks[block].successors;
+        let handlers = &cfg.blocks[block].exception_successors;
+        for (successor, state) in successors.iter().map(|it| (*it, &state))
+            .chain(handlers.iter().filter_map(|it| handler_state.as_ref().map(|state| (*it, state)))) {
+            if merge_into(&mut entries[successor], state) && !worklist.contains(&successor) {
+                worklist.push(successor);
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    let mut before = BTreeMap::new();
+    inference.problems = Some(&mut problems);
+    for (block, entry) in entries.into_iter().enumerate() {
+        let mut state = match entry {
+            Some(state) => state,
+            None => continue,
+        };
+        for (pc, insn) in cfg.block_instructions(block) {
+            before.insert(*pc, state.regs.clone());
+            inference.transfer(&mut state, *pc, insn);
+        }
+    }
+    problems.sort_by_key(|it| it.pc);
+    RegisterTypes { before, problems }
+}
+
+/// Merges `state` into the state of a block entry, returning whether it changed
+fn merge_into<'a>(target: &mut Option<State<'a>>, state: &State<'a>) -> bool {
+    match target {
+        Some(target) => target.merge(state),
+        None => {
+            *target = Some(state.clone());
+            true
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+
+use crate::dex_file::DexFile;
+use crate::instructions::{DecodeError, Format, IndexType, Instruction, Instructions, Payload, PayloadKind};
+use crate::prelude::*;
+use crate::raw_dex::CodeItem;
+#[cfg(feature = "std")]
+use crate::types::{self, Expected};
+#[cfg(feature = "std")]
+use crate::cfg::ControlFlowGraph;
+
+/*
+Structural checks of the instructions of a method, similar to the first pass of the verifier of the
+runtime: operands have to stay within the method and the id tables. If these pass (with the std
+feature), unreachable blocks of the control flow graph are reported, and the registers are typed by
+types::infer to report uses not fitting their type.
+ */
+
+const TYPE_CALL_SITE_ID_ITEM: u16 = 0x0007;
+const TYPE_METHOD_HANDLE_ITEM: u16 = 0x0008;
+
+/// A problem found in the instructions of a method
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub method_idx: u32,
+    /// pc of the offending instruction
+    pub pc: usize,
+    pub problem: Problem,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+    /// The instructions could not be decoded from this pc on
+    Decode(DecodeError),
+    /// A register operand (or the second register of a wide pair) is not below registers_size
+    RegisterOutOfRange { register: u32, registers_size: u16 },
+    /// Branch offsets of 0 are only allowed for goto/32
+    ZeroBranchOffset,
+    /// A branch (or switch case) target is outside of the instructions
+    BranchOutOfBounds { target: i64 },
+    /// A branch (or switch case) target is not the start of an instruction, or is a payload
+    BranchNotOnInstruction { target: usize },
+    /// An index operand is not below the size of its id table
+    IndexOutOfBounds { index_type: IndexType, index: u32, size: usize },
+    /// A switch or fill-array-data instruction does not reference a payload of the expected kind
+    InvalidPayload { target: i64, expected: PayloadKind },
+    /// Payloads have to be aligned to 4 bytes
+    UnalignedPayload { target: usize },
+    /// More than 5 argument registers in format 35c or 45cc
+    TooManyArguments(u32),
+    /// The registers of the parameters (ins_size) are more than the registers of the method, reported at pc 0
+    InsOutOfRange { ins_size: u16, registers_size: u16 },
+    /// An invoke passes more argument registers than the outgoing registers of the method (outs_size)
+    OutsOutOfRange { arguments: u32, outs_size: u16 },
+    /// The inferred type of a register does not fit its use, see types::TypeProblem
+    #[cfg(feature = "std")]
+    TypeMismatch { register: u32, expected: Expected, found: String },
+    /// Instructions up to `end` (exclusive) that no path from the entry reaches, e.g. junk inserted by
+    /// obfuscators
+    #[cfg(feature = "std")]
+    UnreachableCode { end: usize },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Decode(err) => fmt::Display::fmt(err, f),
+            Problem::RegisterOutOfRange { register, registers_size } =>
+                write!(f, "Register v{} out of range, method has {} registers", register, registers_size),
+            Problem::ZeroBranchOffset => f.write_str("Branch offset of 0"),
+            Problem::BranchOutOfBounds { target } => write!(f, "Branch target {} outside of the method", target),
+            Problem::BranchNotOnInstruction { target } => write!(f, "Branch target 0x{:04x} is not an instruction", target),
+            Problem::IndexOutOfBounds { index_type, index, size } =>
+                write!(f, "{:?} index {} out of bounds, table has {} entries", index_type, index, size),
+            Problem::InvalidPayload { target, expected } => write!(f, "No {:?} payload at {}", expected, target),
+            Problem::UnalignedPayload { target } => write!(f, "Payload at 0x{:04x} is not aligned to 4 bytes", target),
+            Problem::TooManyArguments(count) => write!(f, "{} argument registers, at most 5 are allowed", count),
+            Problem::InsOutOfRange { ins_size, registers_size } =>
+                write!(f, "{} registers of parameters, method has {} registers", ins_size, registers_size),
+            Problem::OutsOutOfRange { arguments, outs_size } =>
+                write!(f, "{} argument registers, method has {} outgoing registers", arguments, outs_size),
+            #[cfg(feature = "std")]
+            Problem::TypeMismatch { register, expected, found } =>
+                write!(f, "v{} is {} but used as {}", register, found, expected),
+            #[cfg(feature = "std")]
+            Problem::UnreachableCode { end } => write!(f, "Unreachable code up to 0x{:04x}", end),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "method@{} 0x{:04x}: {}", self.method_idx, self.pc, self.problem)
+    }
+}
+
+/// Branch offset of an instruction of a branch format
+fn branch_offset(insn: &Instruction) -> Option<u32> {
+    match insn.format() {
+        Format::F10t | Format::F20t | Format::F30t => Some(insn.a),
+        Format::F21t => Some(insn.b),
+        Format::F22t => Some(insn.c),
+        _ => None,
+    }
+}
+
+/// Payload kind referenced by an instruction of format 31t
+fn payload_kind(opcode: u8) -> Option<PayloadKind> {
+    match opcode {
+        0x26 => Some(PayloadKind::FillArrayData),
+        0x2b => Some(PayloadKind::PackedSwitch),
+        0x2c => Some(PayloadKind::SparseSwitch),
+        _ => None,
+    }
+}
+
+/// Checks the instructions of the code item of a method, returning the problems in the order of their pc
+pub fn verify_method(dex: &DexFile, method_idx: u32, access_flags: u64, code: &CodeItem) -> Vec<Diagnostic> {
+    let mut problems = structural_problems(dex, code);
+    if code.ins_size > code.registers_size {
+        problems.insert(0, (0, Problem::InsOutOfRange { ins_size: code.ins_size, registers_size: code.registers_size }));
+    }
+    #[cfg(feature = "std")]
+    if problems.is_empty() {
+        if let Ok(cfg) = ControlFlowGraph::build(code) {
+            for block in cfg.unreachable_blocks() {
+                let block = &cfg.blocks[block];
+                problems.push((block.start, Problem::UnreachableCode { end: block.end }));
+            }
+            for problem in types::infer(dex, method_idx, access_flags, code, &cfg).problems {
+                problems.push((problem.pc, Problem::TypeMismatch {
+                    register: problem.register,
+                    expected: problem.expected,
+                    found: problem.found.to_string(),
+                }));
+            }
+        }
+    }
+    #[cfg(not(feature = "std"))]
+    let _ = access_flags;
+    problems.sort_by_key(|(pc, _)| *pc);
+    problems.into_iter().map(|(pc, problem)| Diagnostic { method_idx, pc, problem }).collect()
+}
+
+/// Problems of the operands of the instructions, in the order of the checks. Code without these
+/// problems stays within the method, its registers and the id tables.
+pub(crate) fn structural_problems(dex: &DexFile, code: &CodeItem) -> Vec<(usize, Problem)> {
+    let mut problems: Vec<(usize, Problem)> = Vec::new();
+    let mut instructions = Vec::new();
+    for insn in Instructions::new(&code.insns) {
+        match insn {
+            Ok(insn) => instructions.push(insn),
+            Err(err) => {
+                let pc = match err {
+                    DecodeError::Truncated(pc) | DecodeError::NotAPayload(pc) => pc,
+                };
+                problems.push((pc, Problem::Decode(err)));
+            }
+        }
+    }
+    // Targets of branches have to be instructions, payloads are only referenced by 31t instructions
+    let starts: BTreeSet<usize> = instructions.iter().filter(|(_, insn)| insn.payload.is_none()).map(|(pc, _)| *pc).collect();
+    let payloads: BTreeMap<usize, PayloadKind> = instructions.iter().filter_map(|(pc, insn)| insn.payload.map(|it| (*pc, it))).collect();
+    let check_target = |problems: &mut Vec<(usize, Problem)>, pc: usize, offset: u32| {
+        let target = pc as i64 + offset as i32 as i64;
+        if target < 0 || target >= code.insns.len() as i64 {
+            problems.push((pc, Problem::BranchOutOfBounds { target }));
+        } else if !starts.contains(&(target as usize)) {
+            problems.push((pc, Problem::BranchNotOnInstruction { target: target as usize }));
+        }
+    };
+
+    for (pc, insn) in instructions.iter().filter(|(_, insn)| insn.payload.is_none()) {
+        let pc = *pc;
+        for (register, wide) in insn.registers() {
+            let last = register + wide as u32;
+            if last >= code.registers_size as u32 {
+                problems.push((pc, Problem::RegisterOutOfRange { register: last, registers_size: code.registers_size }));
+            }
+        }
+        if matches!(insn.format(), Format::F35c | Format::F45cc) && insn.a > 5 {
+            problems.push((pc, Problem::TooManyArguments(insn.a)));
+        }
+        if insn.name().starts_with("invoke-") && insn.a > code.outs_size as u32 {
+            problems.push((pc, Problem::OutsOutOfRange { arguments: insn.a, outs_size: code.outs_size }));
+        }
+
+        if let Some(offset) = branch_offset(insn) {
+            if offset == 0 && insn.format() != Format::F30t {
+                problems.push((pc, Problem::ZeroBranchOffset));
+            } else {
+                check_target(&mut problems, pc, offset);
+            }
+        }
+        if let Some(expected) = payload_kind(insn.opcode) {
+            let target = pc as i64 + insn.b as i32 as i64;
+            let payload = payloads.get(&(target as usize)).filter(|_| target >= 0).copied();
+            if payload != Some(expected) {
+                problems.push((pc, Problem::InvalidPayload { target, expected }));
+            } else if target % 2 != 0 {
+                problems.push((pc, Problem::UnalignedPayload { target: target as usize }));
+            } else if let Ok(Payload::PackedSwitch { targets, .. }) | Ok(Payload::SparseSwitch { targets, .. }) =
+                Payload::decode(&code.insns, target as usize) {
+                for case in targets {
+                    check_target(&mut problems, pc, case as u32);
+                }
+            }
+        }
+
+        for (index_type, index) in indices(insn) {
+            let size = table_size(dex, index_type);
+            if index as usize >= size {
+                problems.push((pc, Problem::IndexOutOfBounds { index_type, index, size }));
+            }
+        }
+    }
+    problems
+}
+
+/// Index operands of an instruction with the table they index
+fn indices(insn: &Instruction) -> Vec<(IndexType, u32)> {
+    match (insn.info().index_type, insn.index()) {
+        (IndexType::MethodAndProtoRef, Some(index)) => vec![(IndexType::MethodRef, index), (IndexType::ProtoRef, insn.h)],
+        (index_type, Some(index)) => vec![(index_type, index)],
+        (_, None) => Vec::new(),
+    }
+}
+
+/// Number of entries of the table indexed by an index type
+fn table_size(dex: &DexFile, index_type: IndexType) -> usize {
+    let map_size = |item_type: u16| dex.map_list.iter().find(|it| it.item_type == item_type).map_or(0, |it| it.size as usize);
+    match index_type {
+        IndexType::None => 0,
+        IndexType::StringRef => dex.strings.len(),
+        IndexType::TypeRef => dex.type_ids.len(),
+        IndexType::FieldRef => dex.field_ids.len(),
+        IndexType::MethodRef | IndexType::MethodAndProtoRef => dex.method_ids.len(),
+        IndexType::ProtoRef => dex.proto_ids.len(),
+        IndexType::CallSiteRef => map_size(TYPE_CALL_SITE_ID_ITEM),
+        IndexType::MethodHandleRef => map_size(TYPE_METHOD_HANDLE_ITEM),
+    }
+}
+
+/// Checks the instructions of all methods defined in the dex file, in the order of the class definitions
+pub fn verify(dex: &DexFile) -> Vec<Diagnostic> {
+    dex.classes()
+        .flat_map(|class| class.methods())
+        .filter_map(|method| dex.code_item(method.encoded.code_off).map(|code| (method, code)))
+        .flat_map(|(method, code)| verify_method(dex, method.method_idx, method.encoded.access_flags, code))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_builder::{CodeBuilder, Operand::*};
+    use crate::fixture::Fixture;
+
+    fn problems(registers_size: u16, ins_size: u16, outs_size: u16, insns: Vec<u16>) -> Vec<String> {
+        let dex = DexFile::from_bytes(&Fixture::new().build()).unwrap();
+        let code = CodeItem { registers_size, ins_size, outs_size, debug_info_off: 0, insns, tries: Vec::new(), handlers: Vec::new() };
+        verify_method(&dex, 0, 0, &code).iter().map(|it| it.problem.to_string()).collect()
+    }
+
+    #[test]
+    fn register_counts() {
+        let mut code = CodeBuilder::new();
+        code.emit("invoke-virtual", &[Regs(vec![0]), Idx(0)]).unwrap()
+            .emit("return-void", &[]).unwrap();
+        let insns = code.build().unwrap();
+        assert_eq!(problems(1, 1, 1, insns.clone()), Vec::<String>::new());
+        assert_eq!(problems(1, 1, 0, insns.clone()), ["1 argument registers, method has 0 outgoing registers"]);
+        assert_eq!(problems(1, 2, 1, insns), ["2 registers of parameters, method has 1 registers"]);
+    }
+
+    #[test]
+    fn payload_of_switch() {
+        let mut code = CodeBuilder::new();
+        let (first, second) = (code.label(), code.label());
+        code.emit("const/4", &[Reg(0), Lit(1)]).unwrap()
+            .packed_switch(0, 0, &[first, second]).unwrap()
+            .bind(first).unwrap()
+            .emit("return-void", &[]).unwrap()
+            .bind(second).unwrap()
+            .emit("return-void", &[]).unwrap();
+        assert_eq!(problems(2, 1, 0, code.build().unwrap()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn structural_problems() {
+        let cases = [
+            (vec![0x0101, 0x000e], "Register v1 out of range, method has 1 registers"),
+            (vec![0x0028], "Branch offset of 0"),
+            (vec![0x0528, 0x000e], "Branch target 5 outside of the method"),
+            (vec![0x0029, 0x0001, 0x000e], "Branch target 0x0001 is not an instruction"),
+            (vec![0x001a, 999, 0x000e], "StringRef index 999 out of bounds, table has 5 entries"),
+            // packed-switch of a fill-array-data payload
+            (vec![0x002b, 0x0004, 0x0000, 0x000e, 0x0300, 0x0001, 0x0000, 0x0000, 0x0000], "No PackedSwitch payload at 4"),
+            (vec![0x000e, 0x001a], "Truncated instruction at 0x0001"),
+            (vec![0x000e, 0x000e], "Unreachable code up to 0x0002"),
+        ];
+        for (insns, problem) in cases {
+            assert_eq!(problems(1, 0, 0, insns), [problem]);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::sync::Arc;
+
+use dex_tool::dex_file::{DexFile, NO_INDEX};
+use dex_tool::smali;
+
+/*
+UniFFI bindings for Kotlin and Swift. The scaffolding is generated from the attributes in this file,
+the foreign bindings from the built library with the uniffi-bindgen binary (see Cargo.toml).
+ */
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum DexError {
+    /// The file could not be read or is not a valid dex file
+    Parse(String),
+    /// The class is not defined in the dex file
+    ClassNotFound(String),
+}
+
+impl fmt::Display for DexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DexError::Parse(message) => write!(f, "Could not parse dex file: {}", message),
+            DexError::ClassNotFound(class) => write!(f, "Class {} is not defined", class),
+        }
+    }
+}
+
+impl std::error::Error for DexError {}
+
+impl From<std::io::Error> for DexError {
+    fn from(err: std::io::Error) -> Self {
+        DexError::Parse(err.to_string())
+    }
+}
+
+/// A class defined in the dex file
+#[derive(uniffi::Record)]
+pub struct ClassInfo {
+    pub descriptor: String,
+    pub superclass: Option<String>,
+    pub access_flags: u32,
+    pub source_file: Option<String>,
+}
+
+/// A parsed dex file
+#[derive(uniffi::Object)]
+pub struct Dex {
+    dex: DexFile,
+}
+
+#[uniffi::export]
+impl Dex {
+    #[uniffi::constructor]
+    pub fn open(path: String) -> Result<Arc<Dex>, DexError> {
+        let dex = DexFile::open(path)?;
+        Ok(Arc::new(Dex { dex }))
+    }
+
+    #[uniffi::constructor]
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Arc<Dex>, DexError> {
+        let dex = DexFile::from_bytes(&bytes)?;
+        Ok(Arc::new(Dex { dex }))
+    }
+
+    /// Dex format version
+    pub fn version(&self) -> u16 {
+        self.dex.version()
+    }
+
+    pub fn strings(&self) -> Vec<String> {
+        self.dex.strings.clone()
+    }
+
+    pub fn classes(&self) -> Vec<ClassInfo> {
+        let dex = &self.dex;
+        dex.class_defs.iter().map(|it| ClassInfo {
+            descriptor: dex.type_descriptor(it.class_idx).to_string(),
+            superclass: Some(it.superclass_idx).filter(|it| *it != NO_INDEX).map(|it| dex.type_descriptor(it).to_string()),
+            access_flags: it.access_flags,
+            source_file: Some(it.source_file_idx).filter(|it| *it != NO_INDEX).map(|it| dex.string(it).to_string()),
+        }).collect()
+    }
+
+    /// All method ids in the form Lcom/example/Foo;->name(I)V
+    pub fn methods(&self) -> Vec<String> {
+        let dex = &self.dex;
+        (0..dex.method_ids.len() as u32)
+            .map(|it| format!("{}->{}{}", dex.method_class(it), dex.method_name(it), dex.method_signature(it)))
+            .collect()
+    }
+
+    /// Smali of the class with the given descriptor
+    pub fn disassemble(&self, class: String) -> Result<String, DexError> {
+        let dex = &self.dex;
+        let idx = dex.class_defs.iter().position(|it| dex.type_descriptor(it.class_idx) == class)
+            .ok_or(DexError::ClassNotFound(class))?;
+        let mut out = Vec::new();
+        smali::write_class(dex, idx, &smali::Comments::new(), &mut out)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+}
@@ -0,0 +1,61 @@
+use wasm_bindgen::prelude::*;
+
+use dex_tool::dex_file::DexFile;
+use dex_tool::{listing, smali};
+
+/*
+Thin wasm-bindgen wrapper for embedding the parser in a browser, the dex file is passed as bytes
+(e.g. from a FileReader or fetch).
+ */
+
+/// A parsed dex file
+#[wasm_bindgen]
+pub struct Dex {
+    dex: DexFile,
+}
+
+#[wasm_bindgen]
+impl Dex {
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<Dex, JsError> {
+        let dex = DexFile::from_bytes(bytes)?;
+        Ok(Dex { dex })
+    }
+
+    /// Dex format version
+    pub fn version(&self) -> u16 {
+        self.dex.version()
+    }
+
+    pub fn strings(&self) -> Vec<String> {
+        self.dex.strings.clone()
+    }
+
+    /// Descriptors of the classes defined in the dex file
+    pub fn classes(&self) -> Vec<String> {
+        self.dex.class_defs.iter().map(|it| self.dex.type_descriptor(it.class_idx).to_string()).collect()
+    }
+
+    /// All method ids in the form Lcom/example/Foo;->name(I)V
+    pub fn methods(&self) -> Vec<String> {
+        (0..self.dex.method_ids.len() as u32)
+            .map(|it| format!("{}->{}{}", self.dex.method_class(it), self.dex.method_name(it), self.dex.method_signature(it)))
+            .collect()
+    }
+
+    /// Summary statistics as CSV with the columns key and value
+    pub fn stats(&self) -> Result<String, JsError> {
+        let mut out = Vec::new();
+        listing::stats(&self.dex).write(dex_tool::table::TableFormat::Csv, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Smali of the class with the given descriptor
+    pub fn disassemble(&self, class: &str) -> Result<String, JsError> {
+        let idx = self.dex.class_defs.iter().position(|it| self.dex.type_descriptor(it.class_idx) == class)
+            .ok_or_else(|| JsError::new(&format!("Class {} is not defined", class)))?;
+        let mut out = Vec::new();
+        smali::write_class(&self.dex, idx, &smali::Comments::new(), &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+}